@@ -0,0 +1,336 @@
+//! Append-only log of authenticated git requests (`access.jsonl` under
+//! `git_root`), answering who fetched a project when. Requests hand their
+//! entry to a writer thread and never wait for the disk; the file is rotated
+//! once it reaches `ACCESS_LOG_MAX_BYTES`, keeping `ACCESS_LOG_FILES` old
+//! copies as `access.jsonl.1` (the newest), `access.jsonl.2`, ...
+
+use crate::config::Config;
+use crate::error::BridgeError;
+use crate::git_http::GitEndpoint;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use tracing::{error, warn};
+
+/// Entries waiting for the writer; beyond this, new ones are dropped.
+const QUEUE_LEN: usize = 10_000;
+
+/// What a logged request asked for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum AccessKind {
+    /// `info/refs`, the start of every clone and fetch
+    InfoRefs,
+    /// `git-upload-pack`, the transfer of a clone or fetch
+    UploadPack,
+    /// `git-upload-archive`
+    UploadArchive,
+}
+
+impl AccessKind {
+    /// The kind of requests to `endpoint`, `None` for those not logged.
+    pub(crate) fn of(endpoint: Option<GitEndpoint>) -> Option<Self> {
+        match endpoint? {
+            GitEndpoint::InfoRefs => Some(Self::InfoRefs),
+            GitEndpoint::UploadPack => Some(Self::UploadPack),
+            GitEndpoint::UploadArchive => Some(Self::UploadArchive),
+            _ => None,
+        }
+    }
+}
+
+/// An authenticated git request.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct AccessEntry {
+    pub(crate) at: DateTime<Utc>,
+    pub(crate) project_id: String,
+    /// Named source of the project; absent for the default one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) source: Option<String>,
+    /// Id of the managed token, or `project-token` or `deploy-token`
+    pub(crate) token: String,
+    /// Client address, behind trusted proxies the forwarded one
+    pub(crate) client: Option<IpAddr>,
+    pub(crate) kind: AccessKind,
+}
+
+enum Message {
+    Entry(AccessEntry),
+    /// Answered once everything sent before is written
+    #[cfg(feature = "admin-ui")]
+    Flush(SyncSender<()>),
+}
+
+/// The access log; without `ACCESS_LOG_MAX_BYTES` nothing is recorded.
+pub(crate) struct AccessLog {
+    #[cfg(feature = "admin-ui")]
+    path: PathBuf,
+    #[cfg(feature = "admin-ui")]
+    files: usize,
+    sender: Option<SyncSender<Message>>,
+}
+
+impl AccessLog {
+    /// The log of `cfg`, with its writer thread if enabled. The thread ends
+    /// once the log is dropped.
+    pub(crate) fn new(cfg: &Config) -> Self {
+        let path = cfg.access_log_file();
+        let files = cfg.access_log_files;
+        let sender = (cfg.access_log_max_bytes > 0).then(|| {
+            let (sender, receiver) = mpsc::sync_channel(QUEUE_LEN);
+            let writer = Writer {
+                path: path.clone(),
+                max_bytes: cfg.access_log_max_bytes,
+                files,
+                file: None,
+            };
+            std::thread::Builder::new()
+                .name("access-log".into())
+                .spawn(move || writer.run(receiver))
+                .expect("cannot start the access log writer");
+            sender
+        });
+        Self {
+            #[cfg(feature = "admin-ui")]
+            path,
+            #[cfg(feature = "admin-ui")]
+            files,
+            sender,
+        }
+    }
+
+    /// Queue `entry` for writing; dropped with a warning if the writer has
+    /// fallen too far behind.
+    pub(crate) fn record(&self, entry: AccessEntry) {
+        let Some(sender) = &self.sender else {
+            return;
+        };
+        match sender.try_send(Message::Entry(entry)) {
+            Ok(()) => {}
+            Err(TrySendError::Full(Message::Entry(entry))) => warn!(
+                project_id = %entry.project_id,
+                "access log writer is behind, entry dropped"
+            ),
+            Err(_) => error!("access log writer is gone"),
+        }
+    }
+
+    /// Wait until the queued entries are written. Blocking.
+    #[cfg(feature = "admin-ui")]
+    fn flush(&self) {
+        if let Some(sender) = &self.sender {
+            let (done, wait) = mpsc::sync_channel(1);
+            if sender.send(Message::Flush(done)).is_ok() {
+                let _ = wait.recv();
+            }
+        }
+    }
+
+    /// The newest `limit` entries, newest first, optionally only those of
+    /// `project_id` and those after `since`. Reads the rotated files too.
+    /// Blocking.
+    #[cfg(feature = "admin-ui")]
+    pub(crate) fn query(
+        &self,
+        project_id: Option<&str>,
+        since: Option<DateTime<Utc>>,
+        limit: usize,
+    ) -> Result<Vec<AccessEntry>, BridgeError> {
+        use std::collections::VecDeque;
+        use std::io::{BufRead, BufReader};
+
+        self.flush();
+        let mut found = VecDeque::with_capacity(limit);
+        for n in (0..=self.files).rev() {
+            let file = match File::open(rotated(&self.path, n)) {
+                Ok(file) => file,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            for line in BufReader::new(file).lines() {
+                // unparsable lines, e.g. cut off by a crash, are skipped
+                let Ok(entry) = serde_json::from_str::<AccessEntry>(&line?) else {
+                    continue;
+                };
+                if project_id.is_some_and(|id| entry.project_id != id)
+                    || since.is_some_and(|since| entry.at <= since)
+                {
+                    continue;
+                }
+                if found.len() == limit {
+                    found.pop_front();
+                }
+                found.push_back(entry);
+            }
+        }
+        Ok(found.into_iter().rev().collect())
+    }
+}
+
+/// `path` for `n` = 0, its `n`-th rotated copy otherwise.
+fn rotated(path: &Path, n: usize) -> PathBuf {
+    if n == 0 {
+        return path.to_path_buf();
+    }
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{n}"));
+    PathBuf::from(name)
+}
+
+/// Owner of the open log file, on the writer thread.
+struct Writer {
+    path: PathBuf,
+    max_bytes: u64,
+    files: usize,
+    /// The open file and its size
+    file: Option<(BufWriter<File>, u64)>,
+}
+
+impl Writer {
+    fn run(mut self, receiver: Receiver<Message>) {
+        while let Ok(message) = receiver.recv() {
+            // write whatever else is queued before flushing
+            for message in std::iter::once(message).chain(receiver.try_iter()) {
+                match message {
+                    Message::Entry(entry) => {
+                        if let Err(e) = self.write(&entry) {
+                            error!(path = %self.path.display(), "cannot write access log: {e}");
+                            self.file = None;
+                        }
+                    }
+                    #[cfg(feature = "admin-ui")]
+                    Message::Flush(done) => {
+                        self.flush();
+                        let _ = done.send(());
+                    }
+                }
+            }
+            self.flush();
+        }
+    }
+
+    fn flush(&mut self) {
+        if let Some((file, _)) = &mut self.file
+            && let Err(e) = file.flush()
+        {
+            error!(path = %self.path.display(), "cannot write access log: {e}");
+            self.file = None;
+        }
+    }
+
+    fn write(&mut self, entry: &AccessEntry) -> Result<(), BridgeError> {
+        let mut line = serde_json::to_string(entry)?;
+        line.push('\n');
+        let size = match &self.file {
+            Some((_, size)) => *size,
+            None => self.file.insert(self.open()?).1,
+        };
+        if size > 0 && size + line.len() as u64 > self.max_bytes {
+            self.rotate()?;
+            self.file = Some(self.open()?);
+        }
+        let (file, size) = self.file.as_mut().expect("opened above");
+        file.write_all(line.as_bytes())?;
+        *size += line.len() as u64;
+        Ok(())
+    }
+
+    fn open(&self) -> Result<(BufWriter<File>, u64), BridgeError> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        let size = file.metadata()?.len();
+        Ok((BufWriter::new(file), size))
+    }
+
+    /// Close the current file and shift it and its rotated copies by one,
+    /// dropping the oldest.
+    fn rotate(&mut self) -> Result<(), BridgeError> {
+        if let Some((mut file, _)) = self.file.take() {
+            file.flush()?;
+        }
+        if self.files == 0 {
+            fs::remove_file(&self.path)?;
+            return Ok(());
+        }
+        for n in (1..self.files).rev() {
+            match fs::rename(rotated(&self.path, n), rotated(&self.path, n + 1)) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        fs::rename(&self.path, rotated(&self.path, 1))?;
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "admin-ui"))]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+    use tempfile::TempDir;
+
+    fn entry(project_id: &str, at: DateTime<Utc>) -> AccessEntry {
+        AccessEntry {
+            at,
+            project_id: project_id.into(),
+            source: None,
+            token: "project-token".into(),
+            client: Some("192.0.2.7".parse().unwrap()),
+            kind: AccessKind::UploadPack,
+        }
+    }
+
+    #[test]
+    fn entries_are_rotated_by_size_and_queried_across_files() {
+        let root = TempDir::new().unwrap();
+        let mut cfg = Config::for_test(root.path());
+        let line = serde_json::to_string(&entry("p1", Utc::now()))
+            .unwrap()
+            .len()
+            + 1;
+        cfg.access_log_max_bytes = 10 * line as u64;
+        cfg.access_log_files = 2;
+        let log = AccessLog::new(&cfg);
+
+        let start = Utc::now();
+        for i in 0..35 {
+            let project = if i % 2 == 0 { "p1" } else { "p2" };
+            log.record(entry(project, start + Duration::seconds(i)));
+        }
+        let all = log.query(None, None, 100).unwrap();
+        // 10 per file and two rotated files: the first ten are gone
+        assert_eq!(all.len(), 25);
+        assert_eq!(all[0].at, start + Duration::seconds(34));
+        assert_eq!(all[24].at, start + Duration::seconds(10));
+        assert!(rotated(&cfg.access_log_file(), 2).is_file());
+        assert!(!rotated(&cfg.access_log_file(), 3).exists());
+
+        let p2 = log.query(Some("p2"), None, 3).unwrap();
+        let seconds: Vec<_> = p2.iter().map(|e| (e.at - start).num_seconds()).collect();
+        assert_eq!(seconds, [33, 31, 29]);
+        let since = log
+            .query(Some("p1"), Some(start + Duration::seconds(30)), 100)
+            .unwrap();
+        assert_eq!(since.len(), 2);
+
+        // a restarted bridge continues the file
+        drop(log);
+        let log = AccessLog::new(&cfg);
+        log.record(entry("p3", start + Duration::seconds(40)));
+        assert_eq!(log.query(None, None, 100).unwrap().len(), 26);
+
+        cfg.access_log_max_bytes = 0;
+        let off = AccessLog::new(&cfg);
+        off.record(entry("p4", start));
+        assert!(off.query(Some("p4"), None, 10).unwrap().is_empty());
+    }
+}
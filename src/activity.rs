@@ -0,0 +1,146 @@
+use crate::config::Config;
+use crate::error::BridgeError;
+use crate::metadata::SyncTrigger;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/// Events kept in the activity log. The file may grow to twice this before
+/// it is compacted.
+pub(crate) const MAX_ACTIVITY_EVENTS: usize = 1000;
+
+/// A sync that produced a commit.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ActivityEvent {
+    pub at: DateTime<Utc>,
+    pub project_id: String,
+    pub commit: String,
+    pub files_changed: usize,
+    pub trigger: SyncTrigger,
+}
+
+/// Instance-wide, bounded log of sync commits (`activity.jsonl` under
+/// `git_root`), so the feed does not need to scan every project.
+pub(crate) struct ActivityLog {
+    path: PathBuf,
+    /// Number of events in the file once known; held while appending or compacting
+    len: Mutex<Option<usize>>,
+}
+
+impl ActivityLog {
+    pub(crate) fn new(cfg: &Config) -> Self {
+        Self {
+            path: cfg.activity_log_file(),
+            len: Mutex::new(None),
+        }
+    }
+
+    /// Append an event, compacting the file once it holds twice the limit.
+    /// Blocking.
+    pub(crate) fn append(&self, event: &ActivityEvent) -> Result<(), BridgeError> {
+        let mut len = self.len.lock().expect("activity lock poisoned");
+        let mut count = match *len {
+            Some(count) => count,
+            None => self.read_all()?.len(),
+        };
+
+        let mut line = serde_json::to_string(event)?;
+        line.push('\n');
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?
+            .write_all(line.as_bytes())?;
+        count += 1;
+
+        if count > 2 * MAX_ACTIVITY_EVENTS {
+            let events = self.read_all()?;
+            let keep = &events[events.len().saturating_sub(MAX_ACTIVITY_EVENTS)..];
+            let mut data = String::new();
+            for event in keep {
+                data.push_str(&serde_json::to_string(event)?);
+                data.push('\n');
+            }
+            let tmp_path = self.path.with_extension("tmp");
+            fs::write(&tmp_path, data)?;
+            fs::rename(&tmp_path, &self.path)?;
+            count = keep.len();
+        }
+        *len = Some(count);
+        Ok(())
+    }
+
+    /// Events in file order; unparsable lines are skipped.
+    fn read_all(&self) -> Result<Vec<ActivityEvent>, BridgeError> {
+        match fs::read_to_string(&self.path) {
+            Ok(data) => Ok(data
+                .lines()
+                .filter_map(|line| serde_json::from_str(line).ok())
+                .collect()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(BridgeError::Io(e)),
+        }
+    }
+
+    /// Newest events first, optionally only those after `since`. Blocking.
+    #[cfg(feature = "admin-ui")]
+    pub(crate) fn recent(
+        &self,
+        limit: usize,
+        since: Option<DateTime<Utc>>,
+    ) -> Result<Vec<ActivityEvent>, BridgeError> {
+        let mut events = self.read_all()?;
+        events.retain(|e| since.is_none_or(|since| e.at > since));
+        events.sort_by_key(|e| std::cmp::Reverse(e.at));
+        events.truncate(limit);
+        Ok(events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+    use tempfile::TempDir;
+
+    fn event(project_id: &str, at: DateTime<Utc>) -> ActivityEvent {
+        ActivityEvent {
+            at,
+            project_id: project_id.into(),
+            commit: "abc".into(),
+            files_changed: 1,
+            trigger: SyncTrigger::Fetch,
+        }
+    }
+
+    #[test]
+    fn log_is_bounded_and_read_newest_first() {
+        let root = TempDir::new().unwrap();
+        let cfg = Config::for_test(root.path());
+        fs::create_dir_all(&cfg.git_root).unwrap();
+        let log = ActivityLog::new(&cfg);
+
+        let start = Utc::now();
+        for i in 0..(2 * MAX_ACTIVITY_EVENTS + 1) {
+            log.append(&event("p1", start + Duration::seconds(i as i64)))
+                .unwrap();
+        }
+        assert_eq!(log.read_all().unwrap().len(), MAX_ACTIVITY_EVENTS);
+
+        #[cfg(feature = "admin-ui")]
+        {
+            let recent = log.recent(3, None).unwrap();
+            assert_eq!(recent.len(), 3);
+            assert!(recent[0].at > recent[1].at);
+
+            let cutoff = start + Duration::seconds(2 * MAX_ACTIVITY_EVENTS as i64 - 2);
+            assert_eq!(log.recent(100, Some(cutoff)).unwrap().len(), 2);
+        }
+    }
+}
@@ -1,51 +1,376 @@
 use crate::AppState;
-use crate::auth::{load_tokens_file, save_tokens_file};
+use crate::archive;
+use crate::assets::{DASHBOARD_HTML, FAVICON_PNG, LOGO_WEBP, TAILWIND_JS};
+use crate::audit::AuditEvent;
+use crate::auth::{TokenQuota, TokensFile, constant_time_eq, token_hash};
+use crate::backup;
+use crate::browse::{self, BrowseError};
+use crate::config::AdminRole;
+use crate::deploy_tokens;
+use crate::error::BridgeError;
+use crate::git::SystemGit;
+use crate::ip_allowlist::client_ip;
+use crate::metadata::{MAX_CHANGE_LISTS, SyncTrigger, load_metadata};
+#[cfg(feature = "oidc")]
+use crate::oidc::{self, OidcError};
+use crate::project_id::ProjectId;
+use crate::project_settings::{load_project_settings, public_source, save_project_settings};
+use crate::projects::{ProjectSort, add_details, collect_projects, normalize_alias, sort_projects};
+use crate::prune;
+use crate::repo::{is_external_repo, list_mirrors};
+use crate::restore::{MAX_RESTORE_BUNDLE_BYTES, RestoreError, restore_bundle};
+use crate::secrets::verify_password_hash;
+use crate::server::{sync_project, with_retry_after};
+use crate::shared_objects;
+use crate::stats::{self, TopBy};
+use crate::token_store::ImportMode;
+use crate::totp;
+use crate::trash::{self, TrashError};
+use crate::webhook::{fingerprint, resolve_webhook_secret};
 use axum::{
-    Json,
-    body::Body,
-    extract::{Path, State},
+    Extension, Json,
+    body::{Body, Bytes, to_bytes},
+    extract::{ConnectInfo, Path, Query, State},
     http::{HeaderMap, Response, StatusCode, header},
-    response::IntoResponse,
 };
+use chrono::{DateTime, Utc};
 use hex::encode as hex_encode;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use sha2::{Digest, Sha256};
+use std::net::SocketAddr;
 use std::sync::Arc;
+use std::sync::atomic::Ordering;
 use std::time::{Duration, Instant};
-use tracing::error;
+use tracing::{error, info, warn};
 use uuid::Uuid;
 
-const ADMIN_APP_HTML: &str = include_str!("../templates/admin_dashboard.html");
-const TAILWIND_CSS: &str = include_str!("../templates/tailwind.js");
-const LOGO_WEBP: &[u8] = include_bytes!("../templates/gitbridge.webp");
-const FAVICON_PNG: &[u8] = include_bytes!("../templates/favicon-64.png");
+/// Account the admin password (`ADMIN_PASSWORD`, `ADMIN_PASSWORD_HASH`)
+/// logs in as; always a manager.
+const PASSWORD_ACCOUNT: &str = "admin";
+
+/// Account requests authorized by `ADMIN_API_TOKEN` act as, in logs and the
+/// audit log.
+const API_TOKEN_ACCOUNT: &str = "api-token";
+
+/// A logged-in admin, keyed by the hash of its session token.
+#[derive(Clone, Debug)]
+pub(crate) struct AdminSession {
+    pub(crate) username: String,
+    pub(crate) role: AdminRole,
+    expires: Instant,
+}
 
 #[derive(Deserialize)]
 pub struct LoginPayload {
+    /// Account of the admins file; the admin password's account when absent
+    #[serde(default)]
+    username: Option<String>,
     password: String,
+    /// One-time code, required when `ADMIN_TOTP_SECRET` is set
+    #[serde(default)]
+    otp: Option<String>,
 }
 
 #[derive(Deserialize)]
 pub struct CreateTokenRequest {
     description: String,
+    /// Source the token is limited to; every source when absent
+    #[serde(default)]
+    source: Option<String>,
+    /// Daily limits; absent fields are unlimited
+    #[serde(flatten)]
+    quota: TokenQuota,
+}
+
+#[derive(Deserialize)]
+pub struct UpdateTokenRequest {
+    /// Kept when omitted
+    #[serde(default)]
+    description: Option<String>,
+    /// Replaces the current limits; absent fields are unlimited
+    #[serde(flatten)]
+    quota: TokenQuota,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PatchTokenRequest {
+    /// Kept when omitted
+    #[serde(default)]
+    description: Option<String>,
+    /// Projects to limit the token to, `null` for every project; the
+    /// current scope is kept when omitted
+    #[serde(default, deserialize_with = "present")]
+    projects: Option<Option<Vec<String>>>,
+}
+
+/// Tells a field given as `null` (`Some(None)`) from a missing one (`None`).
+fn present<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+#[derive(Deserialize)]
+pub struct ProjectsQuery {
+    #[serde(default)]
+    q: Option<String>,
+    /// `id` (default) or `last_fetch`
+    #[serde(default)]
+    sort: Option<String>,
+    /// Projects skipped from the start of the sorted listing
+    #[serde(default)]
+    offset: Option<usize>,
+    /// Projects returned at most; all when absent
+    #[serde(default)]
+    limit: Option<usize>,
+}
+
+#[derive(Deserialize)]
+pub struct StatsQuery {
+    /// Number of recent syncs the growth figure covers
+    #[serde(default)]
+    n: Option<usize>,
+}
+
+#[derive(Deserialize)]
+pub struct TopStatsQuery {
+    #[serde(default)]
+    by: Option<String>,
+    #[serde(default)]
+    limit: Option<usize>,
+}
+
+#[derive(Deserialize)]
+pub struct ChangesQuery {
+    /// Number of recent sync commits to return
+    #[serde(default)]
+    limit: Option<usize>,
+}
+
+#[derive(Deserialize)]
+pub struct AccessQuery {
+    /// Only requests for this project
+    #[serde(default)]
+    project: Option<String>,
+    /// RFC 3339 timestamp; only later requests are returned
+    #[serde(default)]
+    since: Option<String>,
+    #[serde(default)]
+    limit: Option<usize>,
+}
+
+#[derive(Deserialize)]
+pub struct ActivityQuery {
+    #[serde(default)]
+    limit: Option<usize>,
+    /// RFC 3339 timestamp; only later events are returned
+    #[serde(default)]
+    since: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct StaleQuery {
+    /// Days without a fetch; defaults to [`DEFAULT_STALE_DAYS`]
+    #[serde(default)]
+    days: Option<u32>,
+}
+
+#[derive(Deserialize)]
+pub struct PruneRequest {
+    #[serde(default)]
+    days: Option<u32>,
+    /// Without it nothing is removed and the stale mirrors are only listed
+    #[serde(default)]
+    confirm: bool,
+    /// Delete the mirrors instead of moving them to the trash
+    #[serde(default)]
+    purge: bool,
+}
+
+#[derive(Deserialize)]
+pub struct DeployTokenRequest {
+    /// Lifetime of the token and of each rotated successor
+    #[serde(default)]
+    days: Option<u32>,
+    #[serde(default)]
+    auto_renew: Option<bool>,
+}
+
+/// Default lifetime of deploy tokens, in days.
+const DEFAULT_DEPLOY_TOKEN_DAYS: u32 = 90;
+
+/// Longest lifetime of a deploy token, in days.
+const MAX_DEPLOY_TOKEN_DAYS: u32 = 3650;
+
+/// Default for `days` on the stale and prune endpoints.
+const DEFAULT_STALE_DAYS: u32 = 180;
+
+#[derive(Deserialize)]
+pub struct RestoreQuery {
+    /// Restore even if the bundle lacks the read-only branch
+    #[serde(default)]
+    force: bool,
+}
+
+#[derive(Deserialize)]
+pub struct TokenImportQuery {
+    /// `merge` (the default) or `replace`
+    #[serde(default)]
+    mode: Option<String>,
+    /// On a merge, give stored tokens the imported description and scopes
+    #[serde(default)]
+    overwrite: bool,
+}
+
+/// Fields of `tokens.json`; an import with others is refused rather than
+/// losing them.
+const TOKENS_FILE_FIELDS: &[&str] = &[
+    "hashed",
+    "managed_tokens",
+    "quotas",
+    "scopes",
+    "source_scopes",
+    "last_used",
+];
+
+#[derive(Deserialize)]
+pub struct DeleteRepoQuery {
+    /// Delete the mirror instead of moving it to the trash
+    #[serde(default)]
+    purge: bool,
+}
+
+/// Upper bound for `limit` on listing endpoints.
+const MAX_LIST_LIMIT: usize = 500;
+
+/// Most access log entries returned at once.
+const MAX_ACCESS_LIMIT: usize = 10_000;
+
+#[derive(Deserialize)]
+pub struct MaintenanceRequest {
+    enabled: bool,
+    /// Shown to git clients; defaults to a generic notice
+    #[serde(default)]
+    message: Option<String>,
+    #[serde(default)]
+    retry_after_seconds: Option<u64>,
+}
+
+#[derive(Deserialize)]
+pub struct MaintenanceQuery {
+    /// Wait for running git requests, syncs and background runs to finish
+    #[serde(default)]
+    drain: bool,
+    #[serde(default)]
+    timeout_seconds: Option<u64>,
+}
+
+/// Default and upper bound of how long a drain waits, in seconds.
+const DEFAULT_DRAIN_TIMEOUT_SECONDS: u64 = 300;
+const MAX_DRAIN_TIMEOUT_SECONDS: u64 = 3600;
+
+/// How often a drain checks for running operations.
+const DRAIN_POLL: Duration = Duration::from_millis(100);
+
+#[derive(Deserialize)]
+pub struct SetAliasRequest {
+    alias: String,
+}
+
+#[derive(Deserialize)]
+pub struct SetWebhookSecretRequest {
+    /// Generated when omitted
+    #[serde(default)]
+    secret: Option<String>,
+}
+
+/// Shortest secret accepted through the admin API.
+const MIN_WEBHOOK_SECRET_LEN: usize = 16;
+
+#[derive(Deserialize)]
+pub struct BrowseQuery {
+    #[serde(default)]
+    path: String,
+    #[serde(rename = "ref")]
+    rev: Option<String>,
 }
 
 #[derive(Serialize)]
 struct TokenEntry {
-    token: String,
+    /// The token's hash, which names it in the admin API
+    id: String,
+    /// The token itself; only in the answer that created it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    token: Option<String>,
     description: String,
+    #[serde(flatten)]
+    quota: TokenQuota,
+    /// Projects the token is limited to; absent for global tokens
+    #[serde(skip_serializing_if = "Option::is_none")]
+    projects: Option<Vec<String>>,
+    /// Source the token is limited to; absent for tokens of every source
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source: Option<String>,
+    /// Clone URL of the first project the token may read, or with a
+    /// `<project_id>` placeholder for global tokens
+    example_clone_url: String,
+    /// Clone URLs of the projects the token is limited to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    clone_urls: Option<Vec<String>>,
+    /// Last git request the token authorized, to the minute; `null` if none
+    /// was recorded
+    last_used: Option<DateTime<Utc>>,
 }
 
-fn extract_admin_cookie(headers: &HeaderMap) -> Option<String> {
+impl TokenEntry {
+    /// Clone URLs point into the token's source, or the default one.
+    fn new(
+        app: &AppState,
+        id: String,
+        description: String,
+        quota: TokenQuota,
+        projects: Option<Vec<String>>,
+        source: Option<String>,
+    ) -> Self {
+        let cfg = source
+            .as_deref()
+            .and_then(|name| app.sources.get(name))
+            .map_or(&app.cfg, |source| &source.cfg);
+        let clone_urls: Option<Vec<String>> = projects
+            .as_ref()
+            .map(|ids| ids.iter().map(|id| cfg.clone_url(id)).collect());
+        let example_clone_url = clone_urls
+            .as_ref()
+            .and_then(|urls| urls.first().cloned())
+            .unwrap_or_else(|| cfg.clone_url("<project_id>"));
+        Self {
+            id,
+            token: None,
+            description,
+            quota,
+            projects,
+            source,
+            example_clone_url,
+            clone_urls,
+            last_used: None,
+        }
+    }
+}
+
+fn extract_cookie(headers: &HeaderMap, wanted: &str) -> Option<String> {
     for val in headers.get_all("cookie").iter() {
         if let Ok(cookie_line) = val.to_str() {
             for cookie in cookie_line.split(';') {
                 let c = cookie.trim();
-                if let Some((name, value)) = c.split_once('=') {
-                    if name == "gb_admin" && !value.is_empty() {
-                        return Some(value.to_string());
-                    }
+                if let Some((name, value)) = c.split_once('=')
+                    && name == wanted
+                    && !value.is_empty()
+                {
+                    return Some(value.to_string());
                 }
             }
         }
@@ -53,32 +378,99 @@ fn extract_admin_cookie(headers: &HeaderMap) -> Option<String> {
     None
 }
 
+fn extract_admin_cookie(headers: &HeaderMap) -> Option<String> {
+    extract_cookie(headers, "gb_admin")
+}
+
 fn hash_session_token(token: &str) -> String {
     let mut hasher = Sha256::new();
     hasher.update(token.as_bytes());
     hex_encode(hasher.finalize())
 }
 
-async fn has_admin_session(headers: &HeaderMap, app: &AppState) -> bool {
-    if app.cfg.admin_password.is_none() {
-        return false;
+/// The session of a request carrying `Authorization: Bearer <ADMIN_API_TOKEN>`.
+/// It acts as a manager and is not kept in `admin_sessions`.
+fn api_token_session(headers: &HeaderMap, app: &AppState) -> Option<AdminSession> {
+    let expected = app.cfg.admin_api_token.as_deref()?;
+    let presented = headers
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")?
+        .trim();
+    if !constant_time_eq(expected, presented) {
+        warn!("admin API request with a wrong bearer token");
+        return None;
     }
+    info!(
+        username = API_TOKEN_ACCOUNT,
+        "admin API request authorized by ADMIN_API_TOKEN"
+    );
+    Some(AdminSession {
+        username: API_TOKEN_ACCOUNT.to_string(),
+        role: AdminRole::Manager,
+        expires: Instant::now(),
+    })
+}
 
-    let token = match extract_admin_cookie(headers) {
-        Some(t) => t,
-        None => return false,
-    };
+async fn admin_session(headers: &HeaderMap, app: &AppState) -> Option<AdminSession> {
+    if !app.cfg.admin_enabled() {
+        return None;
+    }
+    if let Some(session) = api_token_session(headers, app) {
+        return Some(session);
+    }
 
+    let token = extract_admin_cookie(headers)?;
     let hashed = hash_session_token(&token);
     let now = Instant::now();
     let mut sessions = app.admin_sessions.lock().await;
-    if let Some(&expiry) = sessions.get(&hashed) {
-        if expiry > now {
-            return true;
+    if let Some(session) = sessions.get(&hashed) {
+        if session.expires > now {
+            return Some(session.clone());
         }
         sessions.remove(&hashed);
     }
-    false
+    None
+}
+
+/// Common guard for the JSON admin API: 503 when the UI is disabled, 401
+/// without a valid session.
+async fn require_admin(
+    headers: &HeaderMap,
+    app: &AppState,
+) -> Result<AdminSession, Response<Body>> {
+    if !app.cfg.admin_enabled() {
+        return Err(json_response(
+            StatusCode::SERVICE_UNAVAILABLE,
+            json!({"error": "admin ui disabled"}),
+            None,
+        ));
+    }
+
+    admin_session(headers, app).await.ok_or_else(|| {
+        json_response(
+            StatusCode::UNAUTHORIZED,
+            json!({"error": "unauthorized"}),
+            None,
+        )
+    })
+}
+
+/// [`require_admin`] for changes: 403 for a viewer.
+async fn require_manager(
+    headers: &HeaderMap,
+    app: &AppState,
+) -> Result<AdminSession, Response<Body>> {
+    let session = require_admin(headers, app).await?;
+    if session.role != AdminRole::Manager {
+        return Err(json_response(
+            StatusCode::FORBIDDEN,
+            json!({"error": "manager role required"}),
+            None,
+        ));
+    }
+    Ok(session)
 }
 
 fn json_response(
@@ -87,38 +479,38 @@ fn json_response(
     set_cookie: Option<String>,
 ) -> Response<Body> {
     let body = serde_json::to_vec(&value).expect("json serialization");
+    // answers carry tokens and project details; never keep them in a cache
     let mut builder = Response::builder()
         .status(status)
-        .header(header::CONTENT_TYPE, "application/json");
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::CACHE_CONTROL, "no-store");
     if let Some(cookie) = set_cookie {
         builder = builder.header(header::SET_COOKIE, cookie);
     }
     builder.body(Body::from(body)).expect("json response")
 }
 
-pub async fn admin_app(State(app): State<Arc<AppState>>) -> Response<Body> {
-    if app.cfg.admin_password.is_none() {
+pub async fn admin_app(State(app): State<Arc<AppState>>, headers: HeaderMap) -> Response<Body> {
+    if !app.cfg.admin_enabled() {
         return Response::builder()
             .status(StatusCode::SERVICE_UNAVAILABLE)
             .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
             .body(Body::from(
-                "<h1>Admin UI disabled</h1><p>Set ADMIN_PASSWORD env to enable.</p>".to_string(),
+                "<h1>Admin UI disabled</h1><p>Set ADMIN_PASSWORD, ADMIN_PASSWORD_HASH or ADMINS_FILE env to enable.</p>".to_string(),
             ))
             .expect("disabled admin response");
     }
 
-    Response::builder()
-        .status(StatusCode::OK)
-        .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
-        .body(Body::from(ADMIN_APP_HTML))
-        .expect("admin app html")
+    app.admin_assets.response(&DASHBOARD_HTML, &headers)
 }
 
 pub async fn admin_login_api(
     State(app): State<Arc<AppState>>,
+    connect_info: Option<Extension<ConnectInfo<SocketAddr>>>,
+    headers: HeaderMap,
     Json(payload): Json<LoginPayload>,
 ) -> Response<Body> {
-    if app.cfg.admin_password.is_none() {
+    if !app.cfg.admin_enabled() {
         return json_response(
             StatusCode::SERVICE_UNAVAILABLE,
             json!({"error": "admin ui disabled"}),
@@ -126,7 +518,12 @@ pub async fn admin_login_api(
         );
     }
 
-    if let Some(wait) = app.login_throttle_status().await {
+    // without the peer address (embedders not serving with connect info)
+    // every login shares one counter
+    let client = connect_info.map(|Extension(ConnectInfo(peer))| {
+        client_ip(peer.ip(), &headers, &app.cfg.trusted_proxies)
+    });
+    if let Some(wait) = app.login_throttle_status(client).await {
         let seconds = wait.as_secs().max(1);
         return json_response(
             StatusCode::TOO_MANY_REQUESTS,
@@ -142,39 +539,271 @@ pub async fn admin_login_api(
     }
 
     let cfg = &app.cfg;
-    if let Some(expected) = &cfg.admin_password {
-        if expected == &payload.password {
-            let raw_token = Uuid::new_v4().to_string();
-            let hashed = hash_session_token(&raw_token);
-            let ttl = cfg.admin_session_ttl_seconds;
-            let expiry = Instant::now() + Duration::from_secs(ttl);
-            {
-                let mut sessions = app.admin_sessions.lock().await;
-                sessions.insert(hashed, expiry);
-            }
-            app.reset_login_failures().await;
+    if !cfg.admin_password_login {
+        return json_response(
+            StatusCode::FORBIDDEN,
+            json!({"error": "password login is disabled, use single sign-on"}),
+            None,
+        );
+    }
+    // both are checked, so a failure does not tell which one was wrong
+    let account = authenticate(&app, payload.username.as_deref(), &payload.password).await;
+    let otp_ok = otp_matches(&app, payload.otp.as_deref());
+    if let Some((username, role)) = account
+        && otp_ok
+    {
+        app.reset_login_failures(client).await;
+        info!(username, role = ?role, "admin logged in");
+        let cookie = start_session(&app, &username, role).await;
+        let ttl = cfg.admin_session_ttl_seconds;
+        return json_response(
+            StatusCode::OK,
+            json!({ "success": true, "ttl": ttl, "username": username, "role": role }),
+            Some(cookie),
+        );
+    }
+
+    app.note_login_failure(client).await;
+    let error = match (
+        cfg.admin_accounts.is_empty(),
+        cfg.admin_totp_secret.is_some(),
+    ) {
+        (true, false) => "Invalid password",
+        (true, true) => "Invalid password or one-time code",
+        (false, false) => "Invalid username or password",
+        (false, true) => "Invalid username, password or one-time code",
+    };
+    json_response(StatusCode::UNAUTHORIZED, json!({ "error": error }), None)
+}
+
+/// Open a session for `username` and return the cookie carrying it.
+async fn start_session(app: &AppState, username: &str, role: AdminRole) -> String {
+    let raw_token = Uuid::new_v4().to_string();
+    let hashed = hash_session_token(&raw_token);
+    let ttl = app.cfg.admin_session_ttl_seconds;
+    let session = AdminSession {
+        username: username.to_string(),
+        role,
+        expires: Instant::now() + Duration::from_secs(ttl),
+    };
+    app.admin_sessions.lock().await.insert(hashed, session);
+
+    let mut cookie =
+        format!("gb_admin={raw_token}; HttpOnly; Path=/admin; SameSite=Strict; Max-Age={ttl}");
+    if app.cfg.admin_cookie_secure {
+        cookie.push_str("; Secure");
+    }
+    cookie
+}
+
+/// Which logins the dashboard offers: `password` and `oidc`.
+pub async fn admin_login_options_api(State(app): State<Arc<AppState>>) -> Response<Body> {
+    let cfg = &app.cfg;
+    let password = cfg.admin_password_login
+        && (cfg.admin_password.is_some()
+            || cfg.admin_password_hash.is_some()
+            || !cfg.admin_accounts.is_empty());
+    json_response(
+        StatusCode::OK,
+        json!({ "password": password, "oidc": cfg.oidc.is_some() }),
+        None,
+    )
+}
+
+/// Cookie tying an OIDC callback to the browser that started the login.
+#[cfg(feature = "oidc")]
+const OIDC_STATE_COOKIE: &str = "gb_oidc";
 
-            let mut cookie = format!(
-                "gb_admin={raw_token}; HttpOnly; Path=/admin; SameSite=Strict; Max-Age={ttl}"
+#[cfg(feature = "oidc")]
+fn oidc_error_response(e: &OidcError) -> Response<Body> {
+    let status = match e {
+        OidcError::Provider(_) => StatusCode::BAD_GATEWAY,
+        OidcError::UnknownLogin => StatusCode::BAD_REQUEST,
+        OidcError::Refused(_) | OidcError::InvalidToken(_) => StatusCode::UNAUTHORIZED,
+        OidcError::NotAllowed(_) => StatusCode::FORBIDDEN,
+    };
+    json_response(status, json!({ "error": e.to_string() }), None)
+}
+
+/// Send the browser to the OIDC provider to log in.
+#[cfg(feature = "oidc")]
+pub async fn admin_oidc_login_api(State(app): State<Arc<AppState>>) -> Response<Body> {
+    let Some(oidc) = app.oidc.clone() else {
+        return json_response(
+            StatusCode::NOT_FOUND,
+            json!({"error": "OIDC login is not set up"}),
+            None,
+        );
+    };
+    let started = tokio::task::spawn_blocking(move || oidc.start(Instant::now())).await;
+    let (state, url) = match started {
+        Ok(Ok(started)) => started,
+        Ok(Err(e)) => {
+            warn!("cannot start OIDC login: {e}");
+            return oidc_error_response(&e);
+        }
+        Err(e) => {
+            error!("OIDC login task failed: {e}");
+            return json_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"error": "internal error"}),
+                None,
             );
-            if cfg.admin_cookie_secure {
-                cookie.push_str("; Secure");
-            }
+        }
+    };
+    let mut cookie = format!(
+        "{OIDC_STATE_COOKIE}={state}; HttpOnly; Path=/admin/api/login/oidc; SameSite=Lax; Max-Age={}",
+        oidc::LOGIN_TIMEOUT.as_secs()
+    );
+    if app.cfg.admin_cookie_secure {
+        cookie.push_str("; Secure");
+    }
+    Response::builder()
+        .status(StatusCode::SEE_OTHER)
+        .header(header::LOCATION, url)
+        .header(header::SET_COOKIE, cookie)
+        .header(header::CACHE_CONTROL, "no-store")
+        .body(Body::empty())
+        .expect("oidc redirect")
+}
+
+#[cfg(feature = "oidc")]
+#[derive(Deserialize)]
+pub struct OidcCallbackQuery {
+    #[serde(default)]
+    code: Option<String>,
+    #[serde(default)]
+    state: Option<String>,
+    /// Set instead of `code` when the provider refused the login
+    #[serde(default)]
+    error: Option<String>,
+    #[serde(default)]
+    error_description: Option<String>,
+}
+
+/// Where the OIDC provider sends the browser back: check the login, open a
+/// session like a password login, and go on to the dashboard. The role is
+/// that of an admins file account named like the identity, else manager.
+#[cfg(feature = "oidc")]
+pub async fn admin_oidc_callback_api(
+    State(app): State<Arc<AppState>>,
+    connect_info: Option<Extension<ConnectInfo<SocketAddr>>>,
+    headers: HeaderMap,
+    Query(query): Query<OidcCallbackQuery>,
+) -> Response<Body> {
+    let Some(oidc) = app.oidc.clone() else {
+        return json_response(
+            StatusCode::NOT_FOUND,
+            json!({"error": "OIDC login is not set up"}),
+            None,
+        );
+    };
+    let client = connect_info.map(|Extension(ConnectInfo(peer))| {
+        client_ip(peer.ip(), &headers, &app.cfg.trusted_proxies)
+    });
+    if let Some(error) = query.error {
+        let detail = query.error_description.unwrap_or(error);
+        return oidc_error_response(&OidcError::Refused(detail));
+    }
+    // the state has to come back to the browser that started the login
+    let (Some(state), Some(code)) = (query.state, query.code) else {
+        return oidc_error_response(&OidcError::UnknownLogin);
+    };
+    if extract_cookie(&headers, OIDC_STATE_COOKIE).as_deref() != Some(state.as_str()) {
+        return oidc_error_response(&OidcError::UnknownLogin);
+    }
 
+    let unix_now = Utc::now().timestamp();
+    let finished =
+        tokio::task::spawn_blocking(move || oidc.finish(&state, &code, Instant::now(), unix_now))
+            .await;
+    let identity = match finished {
+        Ok(Ok(identity)) => identity,
+        Ok(Err(e)) => {
+            warn!("OIDC login failed: {e}");
+            app.note_login_failure(client).await;
+            return oidc_error_response(&e);
+        }
+        Err(e) => {
+            error!("OIDC login task failed: {e}");
             return json_response(
-                StatusCode::OK,
-                json!({ "success": true, "ttl": ttl }),
-                Some(cookie),
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"error": "internal error"}),
+                None,
             );
         }
+    };
+
+    let role = app
+        .cfg
+        .admin_accounts
+        .iter()
+        .find(|account| account.username == identity)
+        .map_or(AdminRole::Manager, |account| account.role);
+    info!(username = identity, role = ?role, "admin logged in via OIDC");
+    let cookie = start_session(&app, &identity, role).await;
+    let clear_state = format!(
+        "{OIDC_STATE_COOKIE}=; HttpOnly; Path=/admin/api/login/oidc; SameSite=Lax; Max-Age=0"
+    );
+    Response::builder()
+        .status(StatusCode::SEE_OTHER)
+        .header(header::LOCATION, format!("{}/admin", app.cfg.link_base()))
+        .header(header::SET_COOKIE, cookie)
+        .header(header::SET_COOKIE, clear_state)
+        .header(header::CACHE_CONTROL, "no-store")
+        .body(Body::empty())
+        .expect("oidc redirect")
+}
+
+/// The account and role a login names, if its password matches: an account
+/// of the admins file, or without a username (or as `admin`, unless the file
+/// has such an account) the admin password's.
+async fn authenticate(
+    app: &AppState,
+    username: Option<&str>,
+    password: &str,
+) -> Option<(String, AdminRole)> {
+    let username = username.map(str::trim).filter(|name| !name.is_empty());
+    let accounts = &app.cfg.admin_accounts;
+    let account = username.and_then(|name| accounts.iter().find(|a| a.username == name));
+    if account.is_none() && username.is_none_or(|name| name == PASSWORD_ACCOUNT) {
+        return password_matches(app, password)
+            .await
+            .then(|| (PASSWORD_ACCOUNT.to_string(), AdminRole::Manager));
     }
+    // an unknown name is checked against some hash too, so the time taken
+    // does not tell which names exist
+    let hash = account.or(accounts.first())?.password_hash.clone();
+    let password = password.to_string();
+    let matches = tokio::task::spawn_blocking(move || verify_password_hash(&hash, &password))
+        .await
+        .unwrap_or(false);
+    let account = account?;
+    matches.then(|| (account.username.clone(), account.role))
+}
 
-    app.note_login_failure().await;
-    json_response(
-        StatusCode::UNAUTHORIZED,
-        json!({"error": "Invalid password"}),
-        None,
-    )
+/// Check a login password against `ADMIN_PASSWORD_HASH`, or else the
+/// current plain admin password.
+async fn password_matches(app: &AppState, password: &str) -> bool {
+    if let Some(hash) = app.cfg.admin_password_hash.clone() {
+        let password = password.to_string();
+        return tokio::task::spawn_blocking(move || verify_password_hash(&hash, &password))
+            .await
+            .unwrap_or(false);
+    }
+    app.admin_password
+        .current()
+        .is_some_and(|expected| constant_time_eq(&expected, password))
+}
+
+/// Check a login's one-time code against `ADMIN_TOTP_SECRET`; without a
+/// secret no code is needed.
+fn otp_matches(app: &AppState, otp: Option<&str>) -> bool {
+    let Some(secret) = &app.cfg.admin_totp_secret else {
+        return true;
+    };
+    let now = Utc::now().timestamp().try_into().unwrap_or(0);
+    otp.is_some_and(|otp| totp::verify(secret, otp, now))
 }
 
 pub async fn admin_logout_api(
@@ -184,7 +813,9 @@ pub async fn admin_logout_api(
     if let Some(token) = extract_admin_cookie(&headers) {
         let hashed = hash_session_token(&token);
         let mut sessions = app.admin_sessions.lock().await;
-        sessions.remove(&hashed);
+        if let Some(session) = sessions.remove(&hashed) {
+            info!(username = session.username, "admin logged out");
+        }
     }
 
     let mut cookie = "gb_admin=; HttpOnly; Path=/admin; SameSite=Strict; Max-Age=0".to_string();
@@ -199,49 +830,44 @@ pub async fn admin_tokens_api(
     State(app): State<Arc<AppState>>,
     headers: HeaderMap,
 ) -> Response<Body> {
-    if app.cfg.admin_password.is_none() {
-        return json_response(
-            StatusCode::SERVICE_UNAVAILABLE,
-            json!({"error": "admin ui disabled"}),
-            None,
-        );
+    if let Err(resp) = require_admin(&headers, &app).await {
+        return resp;
     }
 
-    if !has_admin_session(&headers, &app).await {
-        return json_response(
-            StatusCode::UNAUTHORIZED,
-            json!({"error": "unauthorized"}),
-            None,
-        );
+    let listed = async {
+        Ok::<_, BridgeError>((
+            app.tokens.list().await?,
+            app.tokens.quotas().await?,
+            app.tokens.scopes().await?,
+            app.tokens.source_scopes().await?,
+            app.tokens.last_used().await?,
+        ))
     }
-
-    let entries = {
-        let _lock = app.tokens_lock.lock().await;
-        match load_tokens_file(&app.cfg) {
-            Ok(tf) => {
-                let mut items: Vec<TokenEntry> = tf
-                    .managed_tokens
-                    .iter()
-                    .map(|(token, desc)| TokenEntry {
-                        token: token.clone(),
-                        description: desc.clone(),
-                    })
-                    .collect();
-                items.sort_by(|a, b| a.token.cmp(&b.token));
-                items
-            }
-            Err(e) => {
-                error!("cannot load tokens.json: {e}");
-                return json_response(
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    json!({"error": "failed to load tokens"}),
-                    None,
-                );
-            }
+    .await;
+    let entries: Vec<TokenEntry> = match listed {
+        Ok((records, quotas, mut scopes, mut sources, last_used)) => records
+            .into_iter()
+            .map(|r| {
+                let quota = quotas.get(&r.id).copied().unwrap_or_default();
+                let projects = scopes.remove(&r.id);
+                let source = sources.remove(&r.id);
+                let used = last_used.get(&r.id).copied();
+                let mut entry = TokenEntry::new(&app, r.id, r.description, quota, projects, source);
+                entry.last_used = used;
+                entry
+            })
+            .collect(),
+        Err(e) => {
+            error!("cannot load tokens: {e}");
+            return json_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"error": "failed to load tokens"}),
+                None,
+            );
         }
     };
 
-    Json(entries).into_response()
+    json_response(StatusCode::OK, json!(entries), None)
 }
 
 pub async fn admin_create_token_api(
@@ -249,106 +875,467 @@ pub async fn admin_create_token_api(
     headers: HeaderMap,
     Json(payload): Json<CreateTokenRequest>,
 ) -> Response<Body> {
-    if app.cfg.admin_password.is_none() {
-        return json_response(
-            StatusCode::SERVICE_UNAVAILABLE,
-            json!({"error": "admin ui disabled"}),
-            None,
-        );
+    if let Err(resp) = require_manager(&headers, &app).await {
+        return resp;
     }
 
-    if !has_admin_session(&headers, &app).await {
+    if let Some(source) = &payload.source
+        && app.source_state(source).is_none()
+    {
         return json_response(
-            StatusCode::UNAUTHORIZED,
-            json!({"error": "unauthorized"}),
+            StatusCode::BAD_REQUEST,
+            json!({"error": format!("unknown source '{source}'")}),
             None,
         );
     }
 
     let description = payload.description.trim().to_string();
     let token = Uuid::new_v4().to_string();
+    let id = token_hash(&token);
 
-    {
-        let _lock = app.tokens_lock.lock().await;
-        let mut tf = match load_tokens_file(&app.cfg) {
-            Ok(tf) => tf,
-            Err(e) => {
-                error!("load_tokens_file failed in create: {e}");
-                return json_response(
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    json!({"error": "failed to load tokens"}),
-                    None,
-                );
-            }
-        };
+    let mut saved = app.tokens.insert(&token, &description).await;
+    if saved.is_ok() && !payload.quota.is_unlimited() {
+        saved = app.tokens.set_quota(&id, payload.quota).await.map(|_| ());
+    }
+    if saved.is_ok() && payload.source.is_some() {
+        saved = app
+            .tokens
+            .set_source_scope(&id, payload.source.clone())
+            .await
+            .map(|_| ());
+    }
+    if let Err(e) = saved {
+        error!("token insert failed in create: {e}");
+        return json_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            json!({"error": "failed to save tokens"}),
+            None,
+        );
+    }
 
-        tf.managed_tokens.insert(token.clone(), description.clone());
+    // the only time the token itself leaves the bridge
+    let mut entry = TokenEntry::new(&app, id, description, payload.quota, None, payload.source);
+    entry.token = Some(token);
+    json_response(StatusCode::CREATED, json!(entry), None)
+}
+
+/// Every managed token with its quotas, scopes and last uses, as the
+/// `tokens.json` to import into another bridge.
+pub async fn admin_export_tokens_api(
+    State(app): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Response<Body> {
+    if let Err(resp) = require_admin(&headers, &app).await {
+        return resp;
+    }
 
-        if let Err(e) = save_tokens_file(&app.cfg, &tf) {
-            error!("save_tokens_file failed in create: {e}");
+    let exported = async {
+        Ok::<_, BridgeError>(TokensFile {
+            hashed: true,
+            managed_tokens: app
+                .tokens
+                .list()
+                .await?
+                .into_iter()
+                .map(|r| (r.id, r.description))
+                .collect(),
+            quotas: app.tokens.quotas().await?,
+            scopes: app.tokens.scopes().await?,
+            source_scopes: app.tokens.source_scopes().await?,
+            last_used: app.tokens.last_used().await?,
+        })
+    }
+    .await;
+    let exported = match exported {
+        Ok(exported) => exported,
+        Err(e) => {
+            error!("cannot load tokens: {e}");
             return json_response(
                 StatusCode::INTERNAL_SERVER_ERROR,
-                json!({"error": "failed to save tokens"}),
+                json!({"error": "failed to load tokens"}),
                 None,
             );
         }
-    }
-
-    json_response(
-        StatusCode::CREATED,
-        json!({
-            "token": token,
-            "description": description,
-        }),
-        None,
-    )
+    };
+    let mut resp = json_response(StatusCode::OK, json!(exported), None);
+    resp.headers_mut().insert(
+        header::CONTENT_DISPOSITION,
+        header::HeaderValue::from_static("attachment; filename=\"tokens.json\""),
+    );
+    resp
 }
 
-pub async fn admin_delete_token_api(
+/// Store the tokens of an exported `tokens.json`: merged with the stored
+/// ones, or replacing them with `?mode=replace`.
+pub async fn admin_import_tokens_api(
     State(app): State<Arc<AppState>>,
     headers: HeaderMap,
-    Path(token): Path<String>,
+    Query(query): Query<TokenImportQuery>,
+    body: Bytes,
 ) -> Response<Body> {
-    if app.cfg.admin_password.is_none() {
-        return json_response(
-            StatusCode::SERVICE_UNAVAILABLE,
-            json!({"error": "admin ui disabled"}),
-            None,
-        );
-    }
+    let admin = match require_manager(&headers, &app).await {
+        Ok(session) => session.username,
+        Err(resp) => return resp,
+    };
 
-    if !has_admin_session(&headers, &app).await {
-        return json_response(
-            StatusCode::UNAUTHORIZED,
-            json!({"error": "unauthorized"}),
-            None,
+    let mode = match query.mode.as_deref() {
+        None | Some("merge") => ImportMode::Merge {
+            overwrite: query.overwrite,
+        },
+        Some("replace") => ImportMode::Replace,
+        Some(other) => {
+            return json_response(
+                StatusCode::BAD_REQUEST,
+                json!({"error": format!("unknown mode '{other}', expected merge or replace")}),
+                None,
+            );
+        }
+    };
+    let imported = match parse_tokens_import(&app, &body) {
+        Ok(imported) => imported,
+        Err(msg) => {
+            return json_response(StatusCode::BAD_REQUEST, json!({"error": msg}), None);
+        }
+    };
+
+    match app.tokens.import(imported, mode).await {
+        Ok(summary) => {
+            info!(
+                admin = %admin,
+                added = summary.added,
+                updated = summary.updated,
+                kept = summary.kept,
+                removed = summary.removed,
+                "tokens imported"
+            );
+            json_response(StatusCode::OK, json!(summary), None)
+        }
+        Err(e) => {
+            error!("token import failed: {e}");
+            json_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"error": "failed to save tokens"}),
+                None,
+            )
+        }
+    }
+}
+
+/// The checked tokens file of an import body, its tokens hashed if the file
+/// holds plain ones; the error says what is wrong with it.
+fn parse_tokens_import(app: &Arc<AppState>, body: &[u8]) -> Result<TokensFile, String> {
+    let value: serde_json::Value =
+        serde_json::from_slice(body).map_err(|e| format!("body is not JSON: {e}"))?;
+    let Some(fields) = value.as_object() else {
+        return Err("body must be a tokens.json object".into());
+    };
+    if let Some(field) = fields
+        .keys()
+        .find(|k| !TOKENS_FILE_FIELDS.contains(&k.as_str()))
+    {
+        return Err(format!("unknown field '{field}'"));
+    }
+    let mut imported: TokensFile =
+        serde_json::from_value(value).map_err(|e| format!("invalid tokens file: {e}"))?;
+    if !imported.hashed {
+        imported.hash_tokens();
+    }
+    imported.validate()?;
+    if let Some(source) = imported
+        .source_scopes
+        .values()
+        .find(|s| app.source_state(s).is_none())
+    {
+        return Err(format!("source_scopes: unknown source '{source}'"));
+    }
+    Ok(imported)
+}
+
+/// Change the description and replace the daily quotas of a token.
+pub async fn admin_update_token_api(
+    State(app): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+    Json(payload): Json<UpdateTokenRequest>,
+) -> Response<Body> {
+    if let Err(resp) = require_manager(&headers, &app).await {
+        return resp;
+    }
+
+    let existing = match app.tokens.list().await {
+        Ok(records) => records.into_iter().find(|r| r.id == id),
+        Err(e) => {
+            error!("cannot load tokens: {e}");
+            return json_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"error": "failed to load tokens"}),
+                None,
+            );
+        }
+    };
+    let Some(existing) = existing else {
+        return json_response(
+            StatusCode::NOT_FOUND,
+            json!({"error": "unknown token"}),
+            None,
+        );
+    };
+
+    let description = match payload.description {
+        Some(description) => description.trim().to_string(),
+        None => existing.description,
+    };
+    let mut saved = app
+        .tokens
+        .set_description(&id, &description)
+        .await
+        .map(|_| ());
+    if saved.is_ok() {
+        saved = app.tokens.set_quota(&id, payload.quota).await.map(|_| ());
+    }
+    if let Err(e) = saved {
+        error!("token update failed: {e}");
+        return json_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            json!({"error": "failed to save tokens"}),
+            None,
         );
     }
 
+    let scopes = async {
+        Ok::<_, BridgeError>((
+            app.tokens.scopes().await?.remove(&id),
+            app.tokens.source_scopes().await?.remove(&id),
+        ))
+    }
+    .await;
+    let (projects, source) = scopes.unwrap_or_else(|e| {
+        warn!("cannot load token scopes: {e}");
+        (None, None)
+    });
+    json_response(
+        StatusCode::OK,
+        json!(TokenEntry::new(
+            &app,
+            id,
+            description,
+            payload.quota,
+            projects,
+            source
+        )),
+        None,
+    )
+}
+
+/// Change the description and project scope of a token, leaving out what
+/// the request does not name; its quotas stay as they are.
+pub async fn admin_patch_token_api(
+    State(app): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+    Json(payload): Json<PatchTokenRequest>,
+) -> Response<Body> {
+    if let Err(resp) = require_manager(&headers, &app).await {
+        return resp;
+    }
+
+    let mut projects = payload.projects;
+    if let Some(Some(ids)) = &mut projects {
+        if ids.is_empty() {
+            return json_response(
+                StatusCode::BAD_REQUEST,
+                json!({"error": "projects must not be empty; use null for every project"}),
+                None,
+            );
+        }
+        if let Some(bad) = ids.iter().find(|id| app.cfg.parse_project_id(id).is_err()) {
+            return json_response(
+                StatusCode::BAD_REQUEST,
+                json!({"error": format!("invalid project id {bad:?}")}),
+                None,
+            );
+        }
+        ids.sort();
+        ids.dedup();
+    }
+
+    let existing = match app.tokens.list().await {
+        Ok(records) => records.into_iter().find(|r| r.id == id),
+        Err(e) => {
+            error!("cannot load tokens: {e}");
+            return json_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"error": "failed to load tokens"}),
+                None,
+            );
+        }
+    };
+    let Some(existing) = existing else {
+        return json_response(
+            StatusCode::NOT_FOUND,
+            json!({"error": "unknown token"}),
+            None,
+        );
+    };
+
+    let description = payload
+        .description
+        .map(|description| description.trim().to_string());
+    let mut saved = Ok(());
+    if let Some(description) = &description {
+        saved = app
+            .tokens
+            .set_description(&id, description)
+            .await
+            .map(|_| ());
+    }
+    if saved.is_ok()
+        && let Some(projects) = projects
     {
-        let _lock = app.tokens_lock.lock().await;
-        let mut tf = match load_tokens_file(&app.cfg) {
-            Ok(tf) => tf,
-            Err(e) => {
-                error!("load_tokens_file failed in delete: {e}");
-                return json_response(
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    json!({"error": "failed to load tokens"}),
-                    None,
-                );
-            }
-        };
+        saved = app.tokens.set_scope(&id, projects).await.map(|_| ());
+    }
+    if let Err(e) = saved {
+        error!("token update failed: {e}");
+        return json_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            json!({"error": "failed to save tokens"}),
+            None,
+        );
+    }
+
+    let current = async {
+        Ok::<_, BridgeError>((
+            app.tokens.quotas().await?.remove(&id),
+            app.tokens.scopes().await?.remove(&id),
+            app.tokens.source_scopes().await?.remove(&id),
+        ))
+    }
+    .await;
+    let (quota, projects, source) = match current {
+        Ok(current) => current,
+        Err(e) => {
+            error!("cannot load tokens: {e}");
+            return json_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"error": "failed to load tokens"}),
+                None,
+            );
+        }
+    };
+    json_response(
+        StatusCode::OK,
+        json!(TokenEntry::new(
+            &app,
+            id,
+            description.unwrap_or(existing.description),
+            quota.unwrap_or_default(),
+            projects,
+            source
+        )),
+        None,
+    )
+}
 
-        tf.managed_tokens.remove(&token);
+/// Daily git traffic of a token, newest day first, with its quota.
+pub async fn admin_token_usage_api(
+    State(app): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> Response<Body> {
+    if let Err(resp) = require_admin(&headers, &app).await {
+        return resp;
+    }
 
-        if let Err(e) = save_tokens_file(&app.cfg, &tf) {
-            error!("save_tokens_file failed in delete: {e}");
+    let known = match app.tokens.list().await {
+        Ok(records) => records.iter().any(|r| r.id == id),
+        Err(e) => {
+            error!("cannot load tokens: {e}");
             return json_response(
                 StatusCode::INTERNAL_SERVER_ERROR,
-                json!({"error": "failed to save tokens"}),
+                json!({"error": "failed to load tokens"}),
+                None,
+            );
+        }
+    };
+    if !known {
+        return json_response(
+            StatusCode::NOT_FOUND,
+            json!({"error": "unknown token"}),
+            None,
+        );
+    }
+
+    let quota = match app.tokens.quotas().await {
+        Ok(quotas) => quotas.get(&id).copied().unwrap_or_default(),
+        Err(e) => {
+            error!("cannot load token quotas: {e}");
+            return json_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"error": "failed to load tokens"}),
+                None,
+            );
+        }
+    };
+    let usage = app.usage.clone();
+    let lookup = id.clone();
+    let history = match tokio::task::spawn_blocking(move || usage.history(&lookup)).await {
+        Ok(Ok(history)) => history,
+        Ok(Err(e)) => {
+            error!("cannot read token usage: {e}");
+            return json_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"error": "failed to read usage"}),
+                None,
+            );
+        }
+        Err(e) => {
+            error!("token usage task failed: {e}");
+            return json_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"error": "failed to read usage"}),
                 None,
             );
         }
+    };
+
+    let days: Vec<_> = history
+        .into_iter()
+        .map(|(date, usage)| {
+            json!({
+                "date": date,
+                "requests": usage.requests,
+                "bytes_in": usage.bytes_in,
+                "bytes_out": usage.bytes_out,
+            })
+        })
+        .collect();
+    json_response(
+        StatusCode::OK,
+        json!({
+            "id": id,
+            "quota": quota,
+            "days": days,
+        }),
+        None,
+    )
+}
+
+pub async fn admin_delete_token_api(
+    State(app): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> Response<Body> {
+    if let Err(resp) = require_manager(&headers, &app).await {
+        return resp;
+    }
+
+    if let Err(e) = app.tokens.remove(&id).await {
+        error!("token remove failed in delete: {e}");
+        return json_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            json!({"error": "failed to save tokens"}),
+            None,
+        );
     }
 
     Response::builder()
@@ -357,32 +1344,3106 @@ pub async fn admin_delete_token_api(
         .expect("delete response")
 }
 
-pub async fn admin_tailwind_asset() -> Response<Body> {
-    Response::builder()
-        .status(StatusCode::OK)
-        .header(
-            header::CONTENT_TYPE,
-            "application/javascript; charset=utf-8",
-        )
-        .header(header::CACHE_CONTROL, "public, max-age=86400, immutable")
-        .body(Body::from(TAILWIND_CSS))
-        .expect("tailwind response")
+pub async fn admin_config_api(
+    State(app): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Response<Body> {
+    if let Err(resp) = require_admin(&headers, &app).await {
+        return resp;
+    }
+
+    json_response(StatusCode::OK, app.cfg.effective_json(), None)
 }
 
-pub async fn admin_logo_asset() -> Response<Body> {
-    Response::builder()
-        .status(StatusCode::OK)
-        .header(header::CONTENT_TYPE, "image/webp")
-        .header(header::CACHE_CONTROL, "public, max-age=86400, immutable")
-        .body(Body::from(LOGO_WEBP.to_vec()))
-        .expect("logo response")
+/// All projects known to the source, the mirrors, or the settings file;
+/// `?q=` filters by id or alias, `?sort=last_fetch` lists the most recently
+/// fetched first. `projects` are those of the default source, `sources`
+/// holds the projects of each named source.
+pub async fn admin_projects_api(
+    State(app): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(query): Query<ProjectsQuery>,
+) -> Response<Body> {
+    if let Err(resp) = require_admin(&headers, &app).await {
+        return resp;
+    }
+    let sort = match query.sort.as_deref().map(ProjectSort::parse) {
+        None => ProjectSort::default(),
+        Some(Some(sort)) => sort,
+        Some(None) => {
+            return json_response(
+                StatusCode::BAD_REQUEST,
+                json!({"error": "sort must be id or last_fetch"}),
+                None,
+            );
+        }
+    };
+
+    let offset = query.offset.unwrap_or(0);
+    let limit = query.limit.map(|limit| limit.clamp(1, MAX_LIST_LIMIT));
+
+    let listing = {
+        let app = app.clone();
+        tokio::task::spawn_blocking(move || {
+            // the page of the listing and the number of projects in all
+            let list = |state: &AppState| {
+                let settings = load_project_settings(&state.cfg)?;
+                let mut projects = collect_projects(
+                    &state.cfg,
+                    state.source.as_ref(),
+                    &settings,
+                    query.q.as_deref(),
+                )?;
+                sort_projects(&mut projects, sort);
+                let total = projects.len();
+                let mut page: Vec<_> = projects
+                    .into_iter()
+                    .skip(offset)
+                    .take(limit.unwrap_or(usize::MAX))
+                    .collect();
+                add_details(&state.cfg, state.source.as_ref(), &mut page);
+                Ok::<_, BridgeError>((page, total))
+            };
+            let (projects, total) = list(&app)?;
+            let mut sources = serde_json::Map::new();
+            for (name, state) in &app.sources {
+                sources.insert(name.clone(), json!(list(state)?.0));
+            }
+            Ok::<_, BridgeError>((projects, total, sources))
+        })
+        .await
+    };
+    match listing {
+        Ok(Ok((projects, total, sources))) => json_response(
+            StatusCode::OK,
+            json!({ "projects": projects, "total": total, "sources": sources }),
+            None,
+        ),
+        Ok(Err(e)) => {
+            error!("listing projects failed: {e}");
+            json_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"error": "failed to list projects"}),
+                None,
+            )
+        }
+        Err(e) => {
+            error!("listing projects task failed: {e}");
+            json_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"error": "failed to list projects"}),
+                None,
+            )
+        }
+    }
+}
+
+/// Attach a display alias to a project. The project does not need to exist.
+pub async fn admin_set_alias_api(
+    State(app): State<Arc<AppState>>,
+    headers: HeaderMap,
+    project_id: ProjectId,
+    Json(payload): Json<SetAliasRequest>,
+) -> Response<Body> {
+    if let Err(resp) = require_manager(&headers, &app).await {
+        return resp;
+    }
+    let Some(alias) = normalize_alias(&payload.alias) else {
+        return json_response(
+            StatusCode::BAD_REQUEST,
+            json!({"error": format!(
+                "alias must be 1-{} characters without control characters",
+                crate::projects::MAX_ALIAS_LEN
+            )}),
+            None,
+        );
+    };
+
+    if let Err(e) = update_project_settings(&app, &project_id, {
+        let alias = alias.clone();
+        move |s| s.alias = Some(alias)
+    })
+    .await
+    {
+        error!(%project_id, "saving alias failed: {e}");
+        return json_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            json!({"error": "failed to save project settings"}),
+            None,
+        );
+    }
+
+    json_response(
+        StatusCode::OK,
+        json!({ "id": project_id, "alias": alias }),
+        None,
+    )
 }
 
-pub async fn admin_favicon_asset() -> Response<Body> {
+pub async fn admin_delete_alias_api(
+    State(app): State<Arc<AppState>>,
+    headers: HeaderMap,
+    project_id: ProjectId,
+) -> Response<Body> {
+    if let Err(resp) = require_manager(&headers, &app).await {
+        return resp;
+    }
+    if let Err(e) = update_project_settings(&app, &project_id, |s| s.alias = None).await {
+        error!(%project_id, "removing alias failed: {e}");
+        return json_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            json!({"error": "failed to save project settings"}),
+            None,
+        );
+    }
+
     Response::builder()
-        .status(StatusCode::OK)
-        .header(header::CONTENT_TYPE, "image/png")
-        .header(header::CACHE_CONTROL, "public, max-age=86400, immutable")
-        .body(Body::from(FAVICON_PNG.to_vec()))
-        .expect("favicon response")
+        .status(StatusCode::NO_CONTENT)
+        .body(Body::empty())
+        .expect("delete response")
+}
+
+/// Mark a project public: fetchable without a token over HTTP and
+/// `git://`. The project does not need to exist.
+pub async fn admin_set_public_api(
+    State(app): State<Arc<AppState>>,
+    headers: HeaderMap,
+    project_id: ProjectId,
+) -> Response<Body> {
+    set_public(app, headers, project_id, true).await
+}
+
+pub async fn admin_delete_public_api(
+    State(app): State<Arc<AppState>>,
+    headers: HeaderMap,
+    project_id: ProjectId,
+) -> Response<Body> {
+    set_public(app, headers, project_id, false).await
+}
+
+async fn set_public(
+    app: Arc<AppState>,
+    headers: HeaderMap,
+    project_id: ProjectId,
+    public: bool,
+) -> Response<Body> {
+    if let Err(resp) = require_manager(&headers, &app).await {
+        return resp;
+    }
+    if let Err(e) = update_project_settings(&app, &project_id, move |s| s.public = public).await {
+        error!(%project_id, "saving public flag failed: {e}");
+        return json_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            json!({"error": "failed to save project settings"}),
+            None,
+        );
+    }
+
+    // the project's `.gitbridge.toml` may keep it public
+    let effective = {
+        let app = app.clone();
+        let id = project_id.clone();
+        tokio::task::spawn_blocking(move || {
+            let settings = load_project_settings(&app.cfg)?.get(&id);
+            Ok::<_, BridgeError>(public_source(&settings, app.source.as_ref(), &id))
+        })
+        .await
+    };
+    match effective {
+        Ok(Ok(source)) => json_response(
+            StatusCode::OK,
+            json!({ "id": project_id, "public": source.is_some(), "public_source": source }),
+            None,
+        ),
+        Ok(Err(e)) => {
+            error!(%project_id, "cannot load project settings: {e}");
+            json_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"error": "failed to load project settings"}),
+                None,
+            )
+        }
+        Err(e) => {
+            error!(%project_id, "public flag task failed: {e}");
+            json_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"error": "internal error"}),
+                None,
+            )
+        }
+    }
+}
+
+/// Overview of one project: source/mirror presence, last sync, and webhook
+/// secret status (fingerprint only).
+pub async fn admin_project_api(
+    State(app): State<Arc<AppState>>,
+    headers: HeaderMap,
+    project_id: ProjectId,
+) -> Response<Body> {
+    if let Err(resp) = require_admin(&headers, &app).await {
+        return resp;
+    }
+    let (source_exists, mirror_exists, sync, settings, public) = {
+        let app = app.clone();
+        let id = project_id.clone();
+        match tokio::task::spawn_blocking(move || {
+            let settings = load_project_settings(&app.cfg).map(|s| s.get(&id));
+            let public = settings
+                .as_ref()
+                .ok()
+                .and_then(|s| public_source(s, app.source.as_ref(), &id));
+            (
+                app.source.exists(&id),
+                app.cfg.bare_repo_dir(&id).is_ok_and(|repo| repo.is_dir()),
+                load_metadata(&app.cfg, &id),
+                settings,
+                public,
+            )
+        })
+        .await
+        {
+            Ok((source_exists, mirror_exists, sync, Ok(settings), public)) => {
+                (source_exists, mirror_exists, sync, settings, public)
+            }
+            Ok((_, _, _, Err(e), _)) => {
+                error!(%project_id, "cannot load project settings: {e}");
+                return json_response(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    json!({"error": "failed to load project settings"}),
+                    None,
+                );
+            }
+            Err(e) => {
+                error!(%project_id, "project detail task failed: {e}");
+                return json_response(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    json!({"error": "internal error"}),
+                    None,
+                );
+            }
+        }
+    };
+
+    let webhook = match resolve_webhook_secret(&app, &project_id).await {
+        Ok(Some(secret)) => json!({
+            "configured": true,
+            "source": secret.source,
+            "fingerprint": fingerprint(&secret.value),
+        }),
+        Ok(None) => json!({ "configured": false }),
+        Err(e) => {
+            error!(%project_id, "cannot resolve webhook secret: {e}");
+            return json_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"error": "failed to load project settings"}),
+                None,
+            );
+        }
+    };
+
+    json_response(
+        StatusCode::OK,
+        json!({
+            "id": project_id,
+            "alias": settings.alias,
+            "public": public.is_some(),
+            "public_source": public,
+            "source_exists": source_exists,
+            "mirror_exists": mirror_exists,
+            "last_fetch_at": sync.last_fetch(),
+            "sync": sync,
+            "webhook": webhook,
+            "clone_url": app.cfg.clone_url(&project_id),
+        }),
+        None,
+    )
+}
+
+/// Set (or generate) the project's own webhook secret. The secret is only
+/// returned in this response.
+pub async fn admin_set_webhook_secret_api(
+    State(app): State<Arc<AppState>>,
+    headers: HeaderMap,
+    project_id: ProjectId,
+    Json(payload): Json<SetWebhookSecretRequest>,
+) -> Response<Body> {
+    if let Err(resp) = require_manager(&headers, &app).await {
+        return resp;
+    }
+    let secret = match payload.secret.map(|s| s.trim().to_string()) {
+        Some(s) if s.len() < MIN_WEBHOOK_SECRET_LEN => {
+            return json_response(
+                StatusCode::BAD_REQUEST,
+                json!({
+                    "error": format!("secret must be at least {MIN_WEBHOOK_SECRET_LEN} characters")
+                }),
+                None,
+            );
+        }
+        Some(s) => s,
+        None => Uuid::new_v4().simple().to_string(),
+    };
+
+    if let Err(e) = update_project_settings(&app, &project_id, {
+        let secret = secret.clone();
+        move |s| s.webhook_secret = Some(secret)
+    })
+    .await
+    {
+        error!(%project_id, "saving webhook secret failed: {e}");
+        return json_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            json!({"error": "failed to save project settings"}),
+            None,
+        );
+    }
+
+    json_response(
+        StatusCode::OK,
+        json!({ "secret": secret, "fingerprint": fingerprint(&secret) }),
+        None,
+    )
+}
+
+/// Remove the admin-set secret; the project falls back to `.gitbridge.toml`
+/// or the global secret.
+pub async fn admin_delete_webhook_secret_api(
+    State(app): State<Arc<AppState>>,
+    headers: HeaderMap,
+    project_id: ProjectId,
+) -> Response<Body> {
+    if let Err(resp) = require_manager(&headers, &app).await {
+        return resp;
+    }
+    if let Err(e) = update_project_settings(&app, &project_id, |s| s.webhook_secret = None).await {
+        error!(%project_id, "removing webhook secret failed: {e}");
+        return json_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            json!({"error": "failed to save project settings"}),
+            None,
+        );
+    }
+
+    Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .body(Body::empty())
+        .expect("delete response")
+}
+
+/// Issue a deploy token for a project and write it to the project's
+/// `.gitbridge` file. A previous deploy token stays valid for the overlap
+/// window. The token is only shown in this response.
+pub async fn admin_create_deploy_token_api(
+    State(app): State<Arc<AppState>>,
+    headers: HeaderMap,
+    project_id: ProjectId,
+    Json(payload): Json<DeployTokenRequest>,
+) -> Response<Body> {
+    let admin = match require_manager(&headers, &app).await {
+        Ok(session) => session.username,
+        Err(resp) => return resp,
+    };
+    let days = payload.days.unwrap_or(DEFAULT_DEPLOY_TOKEN_DAYS);
+    let auto_renew = payload.auto_renew.unwrap_or(true);
+    if !(1..=MAX_DEPLOY_TOKEN_DAYS).contains(&days) {
+        return json_response(
+            StatusCode::BAD_REQUEST,
+            json!({"error": format!("days must be between 1 and {MAX_DEPLOY_TOKEN_DAYS}")}),
+            None,
+        );
+    }
+    if auto_renew && u64::from(days) * 24 <= app.cfg.deploy_token_overlap_hours {
+        return json_response(
+            StatusCode::BAD_REQUEST,
+            json!({"error": "auto-renewing tokens must live longer than DEPLOY_TOKEN_OVERLAP_HOURS"}),
+            None,
+        );
+    }
+
+    let result = {
+        let store = app.deploy_tokens.clone();
+        let source = app.source.clone();
+        let audit = app.audit.clone();
+        let project_id = project_id.clone();
+        tokio::task::spawn_blocking(move || {
+            if !source.exists(&project_id) {
+                return Ok(None);
+            }
+            let issued = store.issue(&project_id, days, auto_renew)?;
+            let replaced = issued.previous.as_ref().map_or("", |p| p.token.as_str());
+            deploy_tokens::update_token_file(
+                source.as_ref(),
+                &project_id,
+                Some(&issued.token),
+                replaced,
+            );
+            let detail = format!("{days} days, auto_renew {auto_renew}");
+            let event =
+                AuditEvent::new("deploy_token_create", &project_id, Some(detail)).by(&admin);
+            if let Err(e) = audit.append(&event) {
+                error!(%project_id, "cannot write audit log: {e}");
+            }
+            Ok(Some(issued))
+        })
+        .await
+        .map_err(|e| BridgeError::Other(format!("join error: {e}")))
+        .and_then(|r| r)
+    };
+    match result {
+        Ok(Some(issued)) => json_response(
+            StatusCode::CREATED,
+            json!({
+                "project_id": project_id,
+                "token": issued.token,
+                "created_at": issued.created_at,
+                "expires_at": issued.expires_at,
+                "auto_renew": issued.auto_renew,
+                "previous_valid_until": issued.previous.map(|p| p.valid_until),
+            }),
+            None,
+        ),
+        Ok(None) => json_response(
+            StatusCode::NOT_FOUND,
+            json!({"error": "project not found"}),
+            None,
+        ),
+        Err(e) => {
+            error!(%project_id, "issuing deploy token failed: {e}");
+            json_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"error": "failed to save deploy tokens"}),
+                None,
+            )
+        }
+    }
+}
+
+/// Deploy tokens of all projects, identified by fingerprint.
+pub async fn admin_deploy_tokens_api(
+    State(app): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Response<Body> {
+    if let Err(resp) = require_admin(&headers, &app).await {
+        return resp;
+    }
+    let store = app.deploy_tokens.clone();
+    let listed = tokio::task::spawn_blocking(move || store.list())
+        .await
+        .map_err(|e| BridgeError::Other(format!("join error: {e}")))
+        .and_then(|r| r);
+    match listed {
+        Ok(tokens) => {
+            let tokens: Vec<_> = tokens
+                .into_iter()
+                .map(|(project_id, t)| {
+                    json!({
+                        "project_id": project_id,
+                        "fingerprint": fingerprint(&t.token),
+                        "created_at": t.created_at,
+                        "expires_at": t.expires_at,
+                        "valid_days": t.valid_days,
+                        "auto_renew": t.auto_renew,
+                        "previous_valid_until": t.previous.map(|p| p.valid_until),
+                    })
+                })
+                .collect();
+            json_response(StatusCode::OK, json!({"tokens": tokens}), None)
+        }
+        Err(e) => {
+            error!("cannot list deploy tokens: {e}");
+            json_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"error": "failed to load deploy tokens"}),
+                None,
+            )
+        }
+    }
+}
+
+/// Revoke a project's deploy token, including a previous one still in its
+/// overlap window, and remove it from the `.gitbridge` file.
+pub async fn admin_revoke_deploy_token_api(
+    State(app): State<Arc<AppState>>,
+    headers: HeaderMap,
+    project_id: ProjectId,
+) -> Response<Body> {
+    let admin = match require_manager(&headers, &app).await {
+        Ok(session) => session.username,
+        Err(resp) => return resp,
+    };
+    let result = {
+        let store = app.deploy_tokens.clone();
+        let source = app.source.clone();
+        let audit = app.audit.clone();
+        let project_id = project_id.clone();
+        tokio::task::spawn_blocking(move || {
+            let Some(revoked) = store.revoke(&project_id)? else {
+                return Ok(false);
+            };
+            let previous = revoked.previous.map(|p| p.token);
+            for token in std::iter::once(revoked.token).chain(previous) {
+                deploy_tokens::update_token_file(source.as_ref(), &project_id, None, &token);
+            }
+            let event = AuditEvent::new("deploy_token_revoke", &project_id, None).by(&admin);
+            if let Err(e) = audit.append(&event) {
+                error!(%project_id, "cannot write audit log: {e}");
+            }
+            Ok(true)
+        })
+        .await
+        .map_err(|e| BridgeError::Other(format!("join error: {e}")))
+        .and_then(|r| r)
+    };
+    match result {
+        Ok(true) => Response::builder()
+            .status(StatusCode::NO_CONTENT)
+            .body(Body::empty())
+            .expect("delete response"),
+        Ok(false) => json_response(
+            StatusCode::NOT_FOUND,
+            json!({"error": "project has no deploy token"}),
+            None,
+        ),
+        Err(e) => {
+            error!(%project_id, "revoking deploy token failed: {e}");
+            json_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"error": "failed to save deploy tokens"}),
+                None,
+            )
+        }
+    }
+}
+
+/// Load, modify, and save project-settings.json under the settings lock.
+async fn update_project_settings(
+    app: &AppState,
+    project_id: &ProjectId,
+    f: impl FnOnce(&mut crate::project_settings::ProjectSettings) + Send + 'static,
+) -> Result<(), BridgeError> {
+    let _guard = app.settings_lock.lock().await;
+    let cfg = app.cfg.clone();
+    let project_id = project_id.clone();
+    tokio::task::spawn_blocking(move || {
+        let mut settings = load_project_settings(&cfg)?;
+        settings.update(&project_id, f);
+        save_project_settings(&cfg, &settings)?;
+        if cfg.export_gitweb_metadata
+            && let Err(e) = crate::gitweb::write_description(&cfg, &project_id, &settings)
+        {
+            warn!(%project_id, "cannot update gitweb description: {e}");
+        }
+        Ok(())
+    })
+    .await
+    .map_err(|e| BridgeError::Other(format!("join error: {e}")))?
+}
+
+/// Storage statistics of one mirror (cached for a few minutes).
+pub async fn admin_project_stats_api(
+    State(app): State<Arc<AppState>>,
+    headers: HeaderMap,
+    project_id: ProjectId,
+    Query(query): Query<StatsQuery>,
+) -> Response<Body> {
+    if let Err(resp) = require_admin(&headers, &app).await {
+        return resp;
+    }
+    let window = query
+        .n
+        .unwrap_or(stats::DEFAULT_GROWTH_WINDOW)
+        .clamp(2, crate::metadata::MAX_SYNC_HISTORY);
+
+    let result = {
+        let app = app.clone();
+        let project_id = project_id.clone();
+        tokio::task::spawn_blocking(move || {
+            app.stats_cache
+                .get_or_compute(&SystemGit, &app.cfg, &project_id, window)
+        })
+        .await
+        .map_err(|e| BridgeError::Other(format!("join error: {e}")))
+        .and_then(|r| r)
+    };
+    match result {
+        Ok(stats) => json_response(StatusCode::OK, json!(stats), None),
+        Err(BridgeError::ProjectNotFound(_)) => json_response(
+            StatusCode::NOT_FOUND,
+            json!({"error": "project has no mirror yet"}),
+            None,
+        ),
+        Err(BridgeError::OutsideRoot(_)) => json_response(
+            StatusCode::BAD_REQUEST,
+            json!({"error": "mirror path is outside GIT_ROOT"}),
+            None,
+        ),
+        Err(e) => {
+            error!(%project_id, "computing stats failed: {e}");
+            json_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"error": "failed to compute stats"}),
+                None,
+            )
+        }
+    }
+}
+
+/// Files changed by the latest sync commits of a project, newest first.
+pub async fn admin_project_changes_api(
+    State(app): State<Arc<AppState>>,
+    headers: HeaderMap,
+    project_id: ProjectId,
+    Query(query): Query<ChangesQuery>,
+) -> Response<Body> {
+    if let Err(resp) = require_admin(&headers, &app).await {
+        return resp;
+    }
+    let limit = query.limit.unwrap_or(5).clamp(1, MAX_CHANGE_LISTS);
+
+    let result = {
+        let app = app.clone();
+        let project_id = project_id.clone();
+        tokio::task::spawn_blocking(move || {
+            app.cfg
+                .bare_repo_dir(&project_id)
+                .is_ok_and(|repo| repo.is_dir())
+                .then(|| load_metadata(&app.cfg, &project_id))
+        })
+        .await
+    };
+    let meta = match result {
+        Ok(Some(meta)) => meta,
+        Ok(None) => {
+            return json_response(
+                StatusCode::NOT_FOUND,
+                json!({"error": "project has no mirror yet"}),
+                None,
+            );
+        }
+        Err(e) => {
+            error!(%project_id, "reading sync metadata failed: join error: {e}");
+            return json_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"error": "failed to read sync metadata"}),
+                None,
+            );
+        }
+    };
+    let syncs: Vec<_> = meta
+        .history
+        .iter()
+        .rev()
+        .filter(|record| record.commit.is_some())
+        .take(limit)
+        .map(|record| {
+            json!({
+                "at": record.at,
+                "trigger": record.trigger,
+                "commit": record.commit,
+                "files_changed": record.files_changed,
+                "changes": record.changes,
+            })
+        })
+        .collect();
+    json_response(
+        StatusCode::OK,
+        json!({"project_id": project_id, "syncs": syncs}),
+        None,
+    )
+}
+
+/// Largest mirrors across the instance; `by` is `size` (default), `commits`,
+/// or `growth`.
+pub async fn admin_stats_top_api(
+    State(app): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(query): Query<TopStatsQuery>,
+) -> Response<Body> {
+    if let Err(resp) = require_admin(&headers, &app).await {
+        return resp;
+    }
+    let Some(by) = TopBy::parse(query.by.as_deref().unwrap_or("size")) else {
+        return json_response(
+            StatusCode::BAD_REQUEST,
+            json!({"error": "by must be size, commits, or growth"}),
+            None,
+        );
+    };
+    let limit = query.limit.unwrap_or(20).clamp(1, MAX_LIST_LIMIT);
+
+    let result = {
+        let app = app.clone();
+        tokio::task::spawn_blocking(move || {
+            let mirrors = list_mirrors(&app.cfg)?;
+            Ok::<_, BridgeError>(stats::top(
+                &SystemGit,
+                &app.cfg,
+                &app.stats_cache,
+                &mirrors,
+                by,
+                limit,
+            ))
+        })
+        .await
+        .map_err(|e| BridgeError::Other(format!("join error: {e}")))
+        .and_then(|r| r)
+    };
+    match result {
+        Ok(top) => json_response(StatusCode::OK, json!({ "projects": top }), None),
+        Err(e) => {
+            error!("computing top stats failed: {e}");
+            json_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"error": "failed to compute stats"}),
+                None,
+            )
+        }
+    }
+}
+
+/// Authenticated git requests from the access log, newest first.
+pub async fn admin_audit_api(
+    State(app): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(query): Query<AccessQuery>,
+) -> Response<Body> {
+    if let Err(resp) = require_admin(&headers, &app).await {
+        return resp;
+    }
+    let since = match query.since.as_deref().map(DateTime::parse_from_rfc3339) {
+        None => None,
+        Some(Ok(since)) => Some(since.with_timezone(&Utc)),
+        Some(Err(_)) => {
+            return json_response(
+                StatusCode::BAD_REQUEST,
+                json!({"error": "since must be an RFC 3339 timestamp"}),
+                None,
+            );
+        }
+    };
+    let limit = query.limit.unwrap_or(100).clamp(1, MAX_ACCESS_LIMIT);
+
+    let log = app.access_log.clone();
+    let result =
+        tokio::task::spawn_blocking(move || log.query(query.project.as_deref(), since, limit))
+            .await
+            .map_err(|e| BridgeError::Other(format!("join error: {e}")))
+            .and_then(|r| r);
+    match result {
+        Ok(entries) => json_response(StatusCode::OK, json!({ "entries": entries }), None),
+        Err(e) => {
+            error!("reading access log failed: {e}");
+            json_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"error": "failed to read access log"}),
+                None,
+            )
+        }
+    }
+}
+
+/// Recent sync commits across all projects, newest first.
+pub async fn admin_activity_api(
+    State(app): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(query): Query<ActivityQuery>,
+) -> Response<Body> {
+    if let Err(resp) = require_admin(&headers, &app).await {
+        return resp;
+    }
+    let since = match query.since.as_deref().map(DateTime::parse_from_rfc3339) {
+        None => None,
+        Some(Ok(since)) => Some(since.with_timezone(&Utc)),
+        Some(Err(_)) => {
+            return json_response(
+                StatusCode::BAD_REQUEST,
+                json!({"error": "since must be an RFC 3339 timestamp"}),
+                None,
+            );
+        }
+    };
+    let limit = query.limit.unwrap_or(50).clamp(1, MAX_LIST_LIMIT);
+
+    let result = {
+        let app = app.clone();
+        tokio::task::spawn_blocking(move || {
+            let events = app.activity.recent(limit, since)?;
+            let settings = load_project_settings(&app.cfg)?;
+            Ok::<_, BridgeError>(
+                events
+                    .into_iter()
+                    .map(|event| {
+                        let alias = settings.get(&event.project_id).alias;
+                        json!({
+                            "project_id": event.project_id,
+                            "alias": alias,
+                            "commit": event.commit,
+                            "at": event.at,
+                            "files_changed": event.files_changed,
+                            "trigger": event.trigger,
+                        })
+                    })
+                    .collect::<Vec<_>>(),
+            )
+        })
+        .await
+        .map_err(|e| BridgeError::Other(format!("join error: {e}")))
+        .and_then(|r| r)
+    };
+    match result {
+        Ok(events) => json_response(StatusCode::OK, json!({ "events": events }), None),
+        Err(e) => {
+            error!("reading activity log failed: {e}");
+            json_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"error": "failed to read activity"}),
+                None,
+            )
+        }
+    }
+}
+
+/// Sync a project now, recorded as a manual sync. Answers with whether a
+/// commit was created, the commit the read-only branch points to after the
+/// sync and how long the sync took, lock wait included.
+pub async fn admin_sync_project_api(
+    State(app): State<Arc<AppState>>,
+    headers: HeaderMap,
+    project_id: ProjectId,
+) -> Response<Body> {
+    if let Err(resp) = require_manager(&headers, &app).await {
+        return resp;
+    }
+    let start = Instant::now();
+    let result = sync_project(&app, &project_id, SyncTrigger::Manual).await;
+    let duration_ms = start.elapsed().as_millis() as u64;
+    match result {
+        Ok(outcome) => {
+            let created = outcome.commit.is_some();
+            let commit = match outcome.commit {
+                Some(commit) => Some(commit),
+                None => {
+                    let cfg = app.cfg.clone();
+                    let id = project_id.clone();
+                    tokio::task::spawn_blocking(move || archive::resolve_snapshot(&cfg, &id, None))
+                        .await
+                        .ok()
+                        .and_then(Result::ok)
+                        .flatten()
+                }
+            };
+            json_response(
+                StatusCode::OK,
+                json!({
+                    "created": created,
+                    "commit": commit,
+                    "squashed": outcome.squashed,
+                    "files_changed": outcome.files_changed,
+                    "duration_ms": duration_ms,
+                }),
+                None,
+            )
+        }
+        Err(e @ BridgeError::ProjectNotFound(_)) => {
+            json_response(StatusCode::NOT_FOUND, json!({"error": e.to_string()}), None)
+        }
+        Err(BridgeError::OutsideRoot(_)) => json_response(
+            StatusCode::BAD_REQUEST,
+            json!({"error": "mirror path is outside GIT_ROOT"}),
+            None,
+        ),
+        Err(BridgeError::ProjectBusy(_)) => with_retry_after(
+            json_response(
+                StatusCode::SERVICE_UNAVAILABLE,
+                json!({"error": "project is busy"}),
+                None,
+            ),
+            &app.cfg,
+        ),
+        Err(e @ BridgeError::RepoTooLarge(_)) => {
+            json_response(StatusCode::CONFLICT, json!({"error": e.to_string()}), None)
+        }
+        Err(e) => {
+            error!(%project_id, "manual sync failed: {e}");
+            json_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"error": format!("sync failed: {e}")}),
+                None,
+            )
+        }
+    }
+}
+
+/// Days and the oldest last fetch a mirror may have to not be stale, or why
+/// `days` is rejected.
+fn stale_cutoff(days: Option<u32>) -> Result<(u32, DateTime<Utc>), &'static str> {
+    let days = days.unwrap_or(DEFAULT_STALE_DAYS);
+    if days == 0 {
+        return Err("days must be at least 1");
+    }
+    Ok((days, Utc::now() - chrono::Duration::days(days.into())))
+}
+
+/// Mirrors no git client fetched within the last `days`.
+pub async fn admin_stale_projects_api(
+    State(app): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(query): Query<StaleQuery>,
+) -> Response<Body> {
+    if let Err(resp) = require_admin(&headers, &app).await {
+        return resp;
+    }
+    let (days, cutoff) = match stale_cutoff(query.days) {
+        Ok(cutoff) => cutoff,
+        Err(msg) => return json_response(StatusCode::BAD_REQUEST, json!({"error": msg}), None),
+    };
+
+    let cfg = app.cfg.clone();
+    let stale = tokio::task::spawn_blocking(move || prune::find_stale(&cfg, cutoff))
+        .await
+        .map_err(|e| BridgeError::Other(format!("join error: {e}")))
+        .and_then(|r| r);
+    match stale {
+        Ok(repos) => json_response(
+            StatusCode::OK,
+            json!({
+                "days": days,
+                "cutoff": cutoff,
+                "total_bytes": repos.iter().map(|r| r.repo_bytes).sum::<u64>(),
+                "repos": repos,
+            }),
+            None,
+        ),
+        Err(e) => {
+            error!("cannot list stale mirrors: {e}");
+            json_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"error": "failed to list mirrors"}),
+                None,
+            )
+        }
+    }
+}
+
+/// Remove mirrors no git client fetched within the last `days`, archiving
+/// them to `PRUNE_ARCHIVE_DIR` if set. They go to the trash unless `purge`
+/// is true. A dry run unless `confirm` is true.
+pub async fn admin_prune_projects_api(
+    State(app): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Json(payload): Json<PruneRequest>,
+) -> Response<Body> {
+    let admin = match require_manager(&headers, &app).await {
+        Ok(session) => session.username,
+        Err(resp) => return resp,
+    };
+    let (days, cutoff) = match stale_cutoff(payload.days) {
+        Ok(cutoff) => cutoff,
+        Err(msg) => return json_response(StatusCode::BAD_REQUEST, json!({"error": msg}), None),
+    };
+
+    let cfg = app.cfg.clone();
+    let stale = match tokio::task::spawn_blocking(move || prune::find_stale(&cfg, cutoff))
+        .await
+        .map_err(|e| BridgeError::Other(format!("join error: {e}")))
+        .and_then(|r| r)
+    {
+        Ok(stale) => stale,
+        Err(e) => {
+            error!("cannot list stale mirrors: {e}");
+            return json_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"error": "failed to list mirrors"}),
+                None,
+            );
+        }
+    };
+    if !payload.confirm {
+        return json_response(
+            StatusCode::OK,
+            json!({
+                "dry_run": true,
+                "days": days,
+                "total_bytes": stale.iter().map(|r| r.repo_bytes).sum::<u64>(),
+                "repos": stale,
+            }),
+            None,
+        );
+    }
+
+    info!(days, count = stale.len(), "pruning stale mirrors");
+    let mut pruned = Vec::new();
+    let mut skipped = Vec::new();
+    for repo in stale {
+        let id = repo.id;
+        let Ok(_guard) = app.lock_project(&id, "prune").await else {
+            skipped.push(json!({"id": id, "reason": "busy"}));
+            continue;
+        };
+        let result = {
+            let cfg = app.cfg.clone();
+            let audit = app.audit.clone();
+            let id = id.clone();
+            let admin = admin.clone();
+            tokio::task::spawn_blocking(move || {
+                // fetched while waiting for the lock
+                if !prune::is_stale(&cfg, &id, cutoff) {
+                    return Ok(None);
+                }
+                let pruned = prune::prune_repo(&SystemGit, &cfg, &id, payload.purge)?;
+                let mut detail = match &pruned.trashed {
+                    Some(entry) => format!("moved to trash as {entry}"),
+                    None => "deleted".to_string(),
+                };
+                if let Some(bundle) = &pruned.archived {
+                    detail.push_str(&format!(", archived to {}", bundle.display()));
+                }
+                let event = AuditEvent::new("prune", &id, Some(detail)).by(&admin);
+                if let Err(e) = audit.append(&event) {
+                    error!(project_id = %id, "cannot write audit log: {e}");
+                }
+                Ok(Some(pruned))
+            })
+            .await
+            .map_err(|e| BridgeError::Other(format!("join error: {e}")))
+            .and_then(|r| r)
+        };
+        match result {
+            Ok(Some(result)) => pruned.push(json!({
+                "id": id,
+                "repo_bytes": repo.repo_bytes,
+                "archived_to": result.archived.map(|p| p.display().to_string()),
+                "trash_entry": result.trashed,
+            })),
+            Ok(None) => skipped.push(json!({"id": id, "reason": "fetched meanwhile"})),
+            Err(e) => {
+                error!(project_id = %id, "pruning mirror failed: {e}");
+                skipped.push(json!({"id": id, "reason": e.to_string()}));
+            }
+        }
+    }
+
+    json_response(
+        StatusCode::OK,
+        json!({
+            "dry_run": false,
+            "days": days,
+            "pruned": pruned,
+            "skipped": skipped,
+        }),
+        None,
+    )
+}
+
+/// Git transfers that did not complete since startup: clients that hung up
+/// and backend failures.
+pub async fn admin_transfers_api(
+    State(app): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Response<Body> {
+    if let Err(resp) = require_admin(&headers, &app).await {
+        return resp;
+    }
+
+    json_response(
+        StatusCode::OK,
+        json!({
+            "client_aborts": app.transfers.client_aborts.load(Ordering::Relaxed),
+            "backend_failures": app.transfers.backend_failures.load(Ordering::Relaxed),
+        }),
+        None,
+    )
+}
+
+/// Backlog signals for autoscaling: tasks waiting for a project lock and
+/// how long the oldest has been waiting. Also counts the git requests served
+/// without syncing because the source was unchanged, lists the mirrors
+/// being created for git requests, and holds histograms of where the time
+/// of git requests went.
+pub async fn admin_stats_api(
+    State(app): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Response<Body> {
+    if let Err(resp) = require_admin(&headers, &app).await {
+        return resp;
+    }
+    let (fetches_synced, fetches_without_sync) = app.fetches.sync_counts();
+    json_response(
+        StatusCode::OK,
+        json!({
+            "queues": {
+                "project_locks": app.locks.waiting(),
+            },
+            "coalesced_info_refs": app.advertisements.coalesced(),
+            "fetches_synced": fetches_synced,
+            "fetches_without_sync": fetches_without_sync,
+            "initializations": app.initializations.progress(),
+            "timings": app.timings.snapshot(),
+        }),
+        None,
+    )
+}
+
+/// How often git requests were let in or refused since startup, by
+/// decision (e.g. `managed`, `token_expired`, `no_credentials`).
+pub async fn admin_auth_stats_api(
+    State(app): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Response<Body> {
+    if let Err(resp) = require_admin(&headers, &app).await {
+        return resp;
+    }
+
+    json_response(
+        StatusCode::OK,
+        json!({"decisions": app.auth_stats.snapshot()}),
+        None,
+    )
+}
+
+/// Mirrors whose last integrity check failed, plus how many were checked.
+pub async fn admin_integrity_api(
+    State(app): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Response<Body> {
+    if let Err(resp) = require_admin(&headers, &app).await {
+        return resp;
+    }
+
+    let result = {
+        let app = app.clone();
+        tokio::task::spawn_blocking(move || {
+            let settings = load_project_settings(&app.cfg)?;
+            let mut checked = 0;
+            let mut unchecked = 0;
+            let mut failures = Vec::new();
+            for id in list_mirrors(&app.cfg)? {
+                let Some(check) = load_metadata(&app.cfg, &id).integrity else {
+                    unchecked += 1;
+                    continue;
+                };
+                checked += 1;
+                if !check.ok {
+                    failures.push(json!({
+                        "id": id,
+                        "alias": settings.get(&id).alias,
+                        "at": check.at,
+                        "error": check.error,
+                        "reset": check.reset,
+                    }));
+                }
+            }
+            Ok::<_, BridgeError>(json!({
+                "interval_hours": app.cfg.fsck_interval_hours,
+                "auto_reset": app.cfg.fsck_auto_reset,
+                "checked": checked,
+                "unchecked": unchecked,
+                "failures": failures,
+            }))
+        })
+        .await
+        .map_err(|e| BridgeError::Other(format!("join error: {e}")))
+        .and_then(|r| r)
+    };
+    match result {
+        Ok(report) => json_response(StatusCode::OK, report, None),
+        Err(e) => {
+            error!("building integrity report failed: {e}");
+            json_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"error": "failed to read integrity results"}),
+                None,
+            )
+        }
+    }
+}
+
+/// Backup settings, whether a backup is running, and the last run's report.
+pub async fn admin_backup_status_api(
+    State(app): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Response<Body> {
+    if let Err(resp) = require_admin(&headers, &app).await {
+        return resp;
+    }
+    json_response(
+        StatusCode::OK,
+        json!({
+            "dir": app.cfg.backup_dir.as_deref().map(|p| p.display().to_string()),
+            "interval_minutes": app.cfg.backup_interval_minutes,
+            "retention": app.cfg.backup_retention,
+            "bundles": app.cfg.backup_bundles,
+            "running": app.backups.is_running(),
+            "last_run": app.backups.last(),
+        }),
+        None,
+    )
+}
+
+/// Start a backup in the background.
+pub async fn admin_start_backup_api(
+    State(app): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Response<Body> {
+    if let Err(resp) = require_manager(&headers, &app).await {
+        return resp;
+    }
+    if app.cfg.backup_dir.is_none() {
+        return json_response(
+            StatusCode::NOT_FOUND,
+            json!({"error": "backups are not configured (BACKUP_DIR)"}),
+            None,
+        );
+    }
+    let Some(running) = app.backups.try_start() else {
+        return json_response(
+            StatusCode::CONFLICT,
+            json!({"error": "a backup is already running"}),
+            None,
+        );
+    };
+    tokio::spawn(async move {
+        backup::run_backup(&app, Arc::new(SystemGit), running).await;
+    });
+    json_response(StatusCode::ACCEPTED, json!({"started": true}), None)
+}
+
+/// Shared blobs listed by the shared objects endpoint, most saving first.
+const MAX_LISTED_SHARED_BLOBS: usize = 50;
+
+/// Settings and contents of the shared object store.
+pub async fn admin_shared_objects_api(
+    State(app): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Response<Body> {
+    if let Err(resp) = require_admin(&headers, &app).await {
+        return resp;
+    }
+    let cfg = app.cfg.clone();
+    let manifest = tokio::task::spawn_blocking(move || shared_objects::manifest(&cfg))
+        .await
+        .map_err(|e| BridgeError::Other(format!("join error: {e}")))
+        .and_then(|r| r);
+    match manifest {
+        Ok(manifest) => {
+            // bytes each blob would take up again without the store
+            let saving = |blob: &shared_objects::SharedBlob| {
+                blob.bytes * blob.projects.len().saturating_sub(1) as u64
+            };
+            let bytes: u64 = manifest.objects.values().map(|o| o.bytes).sum();
+            let saved: u64 = manifest.objects.values().map(saving).sum();
+            let mut listed: Vec<_> = manifest.objects.iter().collect();
+            listed.sort_by_key(|(_, blob)| std::cmp::Reverse(saving(blob)));
+            let listed: Vec<_> = listed
+                .into_iter()
+                .take(MAX_LISTED_SHARED_BLOBS)
+                .map(|(oid, blob)| {
+                    json!({"oid": oid, "bytes": blob.bytes, "projects": blob.projects})
+                })
+                .collect();
+            json_response(
+                StatusCode::OK,
+                json!({
+                    "enabled": app.cfg.shared_objects,
+                    "min_bytes": app.cfg.shared_objects_min_bytes,
+                    "blobs": manifest.objects.len(),
+                    "bytes": bytes,
+                    "saved_bytes": saved,
+                    "top_blobs": listed,
+                }),
+                None,
+            )
+        }
+        Err(e) => {
+            error!("failed to read shared objects manifest: {e}");
+            json_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"error": "failed to read shared objects manifest"}),
+                None,
+            )
+        }
+    }
+}
+
+/// Delete the shared blobs no mirror or trash entry uses any more.
+pub async fn admin_gc_shared_objects_api(
+    State(app): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Response<Body> {
+    if let Err(resp) = require_manager(&headers, &app).await {
+        return resp;
+    }
+    let cfg = app.cfg.clone();
+    let report =
+        tokio::task::spawn_blocking(move || shared_objects::collect_garbage(&SystemGit, &cfg))
+            .await
+            .map_err(|e| BridgeError::Other(format!("join error: {e}")))
+            .and_then(|r| r);
+    match report {
+        Ok(report) => json_response(StatusCode::OK, json!(report), None),
+        Err(e) => {
+            error!("shared object garbage collection failed: {e}");
+            json_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"error": format!("garbage collection failed: {e}")}),
+                None,
+            )
+        }
+    }
+}
+
+/// Whether a warm-up is running and the progress of the current or last one.
+pub async fn admin_warmup_status_api(
+    State(app): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Response<Body> {
+    if let Err(resp) = require_admin(&headers, &app).await {
+        return resp;
+    }
+    json_response(
+        StatusCode::OK,
+        json!({
+            "concurrency": app.cfg.warmup_concurrency,
+            "running": app.warmup.is_running(),
+            "aborting": app.warmup.is_aborting(),
+            "progress": app.warmup.progress(),
+        }),
+        None,
+    )
+}
+
+/// Start creating missing mirrors in the background.
+pub async fn admin_start_warmup_api(
+    State(app): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Response<Body> {
+    if let Err(resp) = require_manager(&headers, &app).await {
+        return resp;
+    }
+    if !crate::start_warmup(&app) {
+        return json_response(
+            StatusCode::CONFLICT,
+            json!({"error": "a warm-up is already running"}),
+            None,
+        );
+    }
+    json_response(StatusCode::ACCEPTED, json!({"started": true}), None)
+}
+
+/// Stop the running warm-up after the projects it is working on.
+pub async fn admin_abort_warmup_api(
+    State(app): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Response<Body> {
+    if let Err(resp) = require_manager(&headers, &app).await {
+        return resp;
+    }
+    if !app.warmup.abort() {
+        return json_response(
+            StatusCode::CONFLICT,
+            json!({"error": "no warm-up is running"}),
+            None,
+        );
+    }
+    json_response(StatusCode::ACCEPTED, json!({"aborting": true}), None)
+}
+
+fn maintenance_json(app: &AppState) -> serde_json::Value {
+    let current = app.maintenance.current();
+    json!({
+        "enabled": current.is_some(),
+        "message": current.as_ref().map(|m| m.message.as_str()),
+        "retry_after_seconds": current.as_ref().map(|m| m.retry_after_seconds),
+        "since": current.as_ref().map(|m| m.since),
+        "in_flight": app.maintenance.in_flight(),
+        "locks_held": app.locks.held(),
+    })
+}
+
+/// Whether maintenance mode is on, and what is still running.
+pub async fn admin_maintenance_api(
+    State(app): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Response<Body> {
+    if let Err(resp) = require_admin(&headers, &app).await {
+        return resp;
+    }
+    json_response(StatusCode::OK, maintenance_json(&app), None)
+}
+
+/// Switch maintenance mode on or off. With `drain=true`, switching it on
+/// returns once running operations have finished, or with `504` after
+/// `timeout_seconds`.
+pub async fn admin_set_maintenance_api(
+    State(app): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(query): Query<MaintenanceQuery>,
+    Json(payload): Json<MaintenanceRequest>,
+) -> Response<Body> {
+    if let Err(resp) = require_manager(&headers, &app).await {
+        return resp;
+    }
+    let bad_request =
+        |msg: &str| json_response(StatusCode::BAD_REQUEST, json!({"error": msg}), None);
+    let message = payload.message.map(|m| m.trim().to_string());
+    if let Some(message) = &message
+        && (message.is_empty() || message.chars().any(char::is_control))
+    {
+        return bad_request("message must be a non-empty single line");
+    }
+    if payload.retry_after_seconds == Some(0) {
+        return bad_request("retry_after_seconds must be positive");
+    }
+    let timeout = query
+        .timeout_seconds
+        .unwrap_or(DEFAULT_DRAIN_TIMEOUT_SECONDS);
+    if timeout == 0 || timeout > MAX_DRAIN_TIMEOUT_SECONDS {
+        return bad_request("timeout_seconds must be between 1 and 3600");
+    }
+
+    if !payload.enabled {
+        if let Err(e) = app.maintenance.disable() {
+            error!("cannot end maintenance mode: {e}");
+            return json_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"error": "failed to end maintenance mode"}),
+                None,
+            );
+        }
+        info!("maintenance mode ended");
+        return json_response(StatusCode::OK, maintenance_json(&app), None);
+    }
+
+    match app.maintenance.enable(message, payload.retry_after_seconds) {
+        Ok(state) => info!(message = %state.message, "maintenance mode on"),
+        Err(e) => {
+            error!("cannot start maintenance mode: {e}");
+            return json_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"error": "failed to start maintenance mode"}),
+                None,
+            );
+        }
+    }
+    if query.drain {
+        let idle = || app.maintenance.in_flight() == 0 && app.locks.held() == 0;
+        let drained = tokio::time::timeout(Duration::from_secs(timeout), async {
+            while !idle() {
+                tokio::time::sleep(DRAIN_POLL).await;
+            }
+        })
+        .await;
+        if drained.is_err() {
+            let mut body = maintenance_json(&app);
+            body["error"] = json!(format!("operations still running after {timeout} seconds"));
+            return json_response(StatusCode::GATEWAY_TIMEOUT, body, None);
+        }
+    }
+    json_response(StatusCode::OK, maintenance_json(&app), None)
+}
+
+/// Replace a project's mirror with an uploaded git bundle (raw request body).
+pub async fn admin_restore_project_api(
+    State(app): State<Arc<AppState>>,
+    headers: HeaderMap,
+    project_id: ProjectId,
+    Query(query): Query<RestoreQuery>,
+    body: Body,
+) -> Response<Body> {
+    if let Err(resp) = require_manager(&headers, &app).await {
+        return resp;
+    }
+    let bundle = match to_bytes(body, MAX_RESTORE_BUNDLE_BYTES).await {
+        Ok(bundle) if !bundle.is_empty() => bundle,
+        Ok(_) => {
+            return json_response(
+                StatusCode::BAD_REQUEST,
+                json!({"error": "request body must be a git bundle"}),
+                None,
+            );
+        }
+        Err(_) => {
+            return json_response(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                json!({"error": "bundle too large or upload incomplete"}),
+                None,
+            );
+        }
+    };
+
+    let _guard = match app.lock_project(&project_id, "restore").await {
+        Ok(guard) => guard,
+        Err(_) => {
+            return with_retry_after(
+                json_response(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    json!({"error": "project is busy"}),
+                    None,
+                ),
+                &app.cfg,
+            );
+        }
+    };
+    let result = {
+        let cfg = app.cfg.clone();
+        let project_id = project_id.clone();
+        tokio::task::spawn_blocking(move || {
+            restore_bundle(&SystemGit, &cfg, &project_id, &bundle, query.force)
+        })
+        .await
+        .map_err(|e| RestoreError::Git(BridgeError::Other(format!("join error: {e}"))))
+        .and_then(|r| r)
+    };
+    match result {
+        Ok(outcome) => json_response(
+            StatusCode::OK,
+            json!({
+                "restored": true,
+                "heads": outcome.heads,
+                "branch_present": outcome.branch_present,
+            }),
+            None,
+        ),
+        Err(e @ (RestoreError::InvalidBundle(_) | RestoreError::MissingBranch(_))) => {
+            json_response(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                json!({"error": e.to_string()}),
+                None,
+            )
+        }
+        Err(RestoreError::Git(e)) => {
+            error!(%project_id, "restoring mirror failed: {e}");
+            json_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"error": "restore failed"}),
+                None,
+            )
+        }
+    }
+}
+
+/// What [`admin_delete_repo_api`] found.
+enum RepoRemoval {
+    Missing,
+    External,
+    /// The mirror resolves to a place outside `git_root`
+    Outside,
+    Removed,
+}
+
+/// Remove a project's mirror, so the next fetch or sync builds it anew from
+/// the source, e.g. after an interrupted creation left it broken. It goes to
+/// the trash unless `?purge=true`.
+pub async fn admin_delete_repo_api(
+    State(app): State<Arc<AppState>>,
+    headers: HeaderMap,
+    project_id: ProjectId,
+    Query(query): Query<DeleteRepoQuery>,
+) -> Response<Body> {
+    let admin = match require_manager(&headers, &app).await {
+        Ok(session) => session.username,
+        Err(resp) => return resp,
+    };
+    let _guard = match app.lock_project(&project_id, "delete mirror").await {
+        Ok(guard) => guard,
+        Err(_) => {
+            return with_retry_after(
+                json_response(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    json!({"error": "project is busy"}),
+                    None,
+                ),
+                &app.cfg,
+            );
+        }
+    };
+    let result = {
+        let cfg = app.cfg.clone();
+        let audit = app.audit.clone();
+        let id = project_id.clone();
+        tokio::task::spawn_blocking(move || {
+            let mirror = match cfg.bare_repo_dir(&id) {
+                Ok(mirror) => mirror,
+                Err(BridgeError::OutsideRoot(_)) => return Ok(RepoRemoval::Outside),
+                Err(e) => return Err(e),
+            };
+            if !mirror.is_dir() {
+                return Ok(RepoRemoval::Missing);
+            }
+            if is_external_repo(&cfg, &id) {
+                return Ok(RepoRemoval::External);
+            }
+            let trashed = trash::remove_mirror(&cfg, &id, query.purge)?;
+            crate::gitweb::mirror_changed(&cfg, &id);
+            let detail = match &trashed {
+                Some(entry) => format!("moved to trash as {entry}"),
+                None => "deleted".to_string(),
+            };
+            info!(project_id = %id, %admin, "mirror removed by an admin, {detail}");
+            let event = AuditEvent::new("repo_delete", &id, Some(detail)).by(&admin);
+            if let Err(e) = audit.append(&event) {
+                error!(project_id = %id, "cannot write audit log: {e}");
+            }
+            Ok::<_, BridgeError>(RepoRemoval::Removed)
+        })
+        .await
+        .map_err(|e| BridgeError::Other(format!("join error: {e}")))
+        .and_then(|r| r)
+    };
+    match result {
+        Ok(RepoRemoval::Removed) => Response::builder()
+            .status(StatusCode::NO_CONTENT)
+            .body(Body::empty())
+            .expect("delete response"),
+        Ok(RepoRemoval::Missing) => json_response(
+            StatusCode::NOT_FOUND,
+            json!({"error": "no mirror for this project"}),
+            None,
+        ),
+        Ok(RepoRemoval::External) => json_response(
+            StatusCode::CONFLICT,
+            json!({"error": "external repos are not rebuilt from a source"}),
+            None,
+        ),
+        Ok(RepoRemoval::Outside) => {
+            error!(%project_id, "refusing to delete a mirror outside GIT_ROOT");
+            json_response(
+                StatusCode::BAD_REQUEST,
+                json!({"error": "mirror path is outside GIT_ROOT"}),
+                None,
+            )
+        }
+        Err(e) => {
+            error!(%project_id, "deleting mirror failed: {e}");
+            json_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"error": "failed to delete mirror"}),
+                None,
+            )
+        }
+    }
+}
+
+/// Mirrors waiting in the trash, most recently removed first.
+pub async fn admin_trash_api(
+    State(app): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Response<Body> {
+    if let Err(resp) = require_admin(&headers, &app).await {
+        return resp;
+    }
+    let cfg = app.cfg.clone();
+    let entries = tokio::task::spawn_blocking(move || trash::list_trash(&cfg))
+        .await
+        .map_err(|e| BridgeError::Other(format!("join error: {e}")))
+        .and_then(|r| r);
+    match entries {
+        Ok(entries) => json_response(
+            StatusCode::OK,
+            json!({
+                "retention_days": app.cfg.trash_retention_days,
+                "total_bytes": entries.iter().map(|e| e.repo_bytes).sum::<u64>(),
+                "entries": entries,
+            }),
+            None,
+        ),
+        Err(e) => {
+            error!("cannot list trash: {e}");
+            json_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"error": "failed to list trash"}),
+                None,
+            )
+        }
+    }
+}
+
+/// Move a trash entry back into place as its project's mirror.
+pub async fn admin_restore_trash_api(
+    State(app): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(entry): Path<String>,
+) -> Response<Body> {
+    let admin = match require_manager(&headers, &app).await {
+        Ok(session) => session.username,
+        Err(resp) => return resp,
+    };
+    let Some(project_id) = trash::entry_project_id(&entry) else {
+        return json_response(
+            StatusCode::BAD_REQUEST,
+            json!({"error": "invalid trash entry"}),
+            None,
+        );
+    };
+    let _guard = match app.lock_project(&project_id, "restore").await {
+        Ok(guard) => guard,
+        Err(_) => {
+            return with_retry_after(
+                json_response(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    json!({"error": "project is busy"}),
+                    None,
+                ),
+                &app.cfg,
+            );
+        }
+    };
+    let result = {
+        let cfg = app.cfg.clone();
+        let entry = entry.clone();
+        tokio::task::spawn_blocking(move || trash::restore_from_trash(&cfg, &entry))
+            .await
+            .map_err(|e| TrashError::Io(BridgeError::Other(format!("join error: {e}"))))
+            .and_then(|r| r)
+    };
+    match result {
+        Ok(_) => {
+            let event =
+                AuditEvent::new("trash_restore", &project_id, Some(entry.clone())).by(&admin);
+            if let Err(e) = app.audit.append(&event) {
+                error!(%project_id, "cannot write audit log: {e}");
+            }
+            json_response(
+                StatusCode::OK,
+                json!({"restored": true, "id": project_id, "entry": entry}),
+                None,
+            )
+        }
+        Err(e @ TrashError::NotFound(_)) => {
+            json_response(StatusCode::NOT_FOUND, json!({"error": e.to_string()}), None)
+        }
+        Err(e @ TrashError::MirrorExists(_)) => {
+            json_response(StatusCode::CONFLICT, json!({"error": e.to_string()}), None)
+        }
+        Err(TrashError::Io(e)) => {
+            error!(%project_id, %entry, "restoring from trash failed: {e}");
+            json_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"error": "restore failed"}),
+                None,
+            )
+        }
+    }
+}
+
+/// Webhook posts waiting for a retry.
+pub async fn admin_webhooks_api(
+    State(app): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Response<Body> {
+    if let Err(resp) = require_admin(&headers, &app).await {
+        return resp;
+    }
+    let webhooks = app.webhooks.clone();
+    let pending = tokio::task::spawn_blocking(move || webhooks.pending())
+        .await
+        .map_err(|e| BridgeError::Other(format!("join error: {e}")))
+        .and_then(|r| r);
+    match pending {
+        Ok(pending) => json_response(
+            StatusCode::OK,
+            json!({
+                "max_age_hours": app.cfg.webhook_queue_max_age_hours,
+                "max_entries": app.cfg.webhook_queue_max_entries,
+                "pending": pending.iter().map(|d| d.summary()).collect::<Vec<_>>(),
+            }),
+            None,
+        ),
+        Err(e) => {
+            error!("cannot read webhook queue: {e}");
+            json_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"error": "failed to read webhook queue"}),
+                None,
+            )
+        }
+    }
+}
+
+/// Try every queued webhook post now, whether due or not.
+pub async fn admin_flush_webhooks_api(
+    State(app): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Response<Body> {
+    if let Err(resp) = require_manager(&headers, &app).await {
+        return resp;
+    }
+    let webhooks = app.webhooks.clone();
+    let flushed = tokio::task::spawn_blocking(move || {
+        let delivered = webhooks.deliver(true, Utc::now())?;
+        Ok::<_, BridgeError>((delivered, webhooks.pending()?.len()))
+    })
+    .await
+    .map_err(|e| BridgeError::Other(format!("join error: {e}")))
+    .and_then(|r| r);
+    match flushed {
+        Ok((delivered, pending)) => json_response(
+            StatusCode::OK,
+            json!({"delivered": delivered, "pending": pending}),
+            None,
+        ),
+        Err(e) => {
+            error!("cannot flush webhook queue: {e}");
+            json_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"error": "failed to flush webhook queue"}),
+                None,
+            )
+        }
+    }
+}
+
+/// Drop every queued webhook post.
+pub async fn admin_discard_webhooks_api(
+    State(app): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Response<Body> {
+    discard_webhooks(app, headers, None).await
+}
+
+/// Drop one queued webhook post.
+pub async fn admin_discard_webhook_api(
+    State(app): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> Response<Body> {
+    discard_webhooks(app, headers, Some(id)).await
+}
+
+async fn discard_webhooks(
+    app: Arc<AppState>,
+    headers: HeaderMap,
+    id: Option<String>,
+) -> Response<Body> {
+    let admin = match require_manager(&headers, &app).await {
+        Ok(session) => session.username,
+        Err(resp) => return resp,
+    };
+    let webhooks = app.webhooks.clone();
+    let audit = app.audit.clone();
+    let only = id.clone();
+    let discarded = tokio::task::spawn_blocking(move || {
+        let discarded = webhooks.discard(only.as_deref())?;
+        for delivery in &discarded {
+            let project_id = delivery.payload["project_id"].as_str().unwrap_or_default();
+            let detail = format!("{} {:?}", delivery.id, delivery.kind);
+            let event = AuditEvent::new("webhook_discard", project_id, Some(detail)).by(&admin);
+            if let Err(e) = audit.append(&event) {
+                error!("cannot write audit log: {e}");
+            }
+        }
+        Ok::<_, BridgeError>(discarded.len())
+    })
+    .await
+    .map_err(|e| BridgeError::Other(format!("join error: {e}")))
+    .and_then(|r| r);
+    match discarded {
+        Ok(0) if id.is_some() => json_response(
+            StatusCode::NOT_FOUND,
+            json!({"error": "no such webhook post"}),
+            None,
+        ),
+        Ok(discarded) => json_response(StatusCode::OK, json!({"discarded": discarded}), None),
+        Err(e) => {
+            error!("cannot discard webhook posts: {e}");
+            json_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"error": "failed to discard webhook posts"}),
+                None,
+            )
+        }
+    }
+}
+
+/// Validate a browse request; returns the bare repo, ref, and in-repo path,
+/// or the status and message to reject it with.
+fn browse_target(
+    app: &AppState,
+    project_id: &ProjectId,
+    query: &BrowseQuery,
+) -> Result<(std::path::PathBuf, String, String), (StatusCode, &'static str)> {
+    let rev = query
+        .rev
+        .clone()
+        .filter(|r| !r.is_empty())
+        .unwrap_or_else(|| app.cfg.readonly_branch.clone());
+    if !browse::is_valid_ref(&rev) {
+        return Err((StatusCode::BAD_REQUEST, "invalid ref"));
+    }
+    let Some(path) = browse::sanitize_repo_path(&query.path) else {
+        return Err((StatusCode::BAD_REQUEST, "invalid path"));
+    };
+    let Ok(repo) = app.cfg.bare_repo_dir(project_id) else {
+        return Err((StatusCode::BAD_REQUEST, "mirror path is outside GIT_ROOT"));
+    };
+    if !repo.is_dir() {
+        return Err((StatusCode::NOT_FOUND, "project has no mirror yet"));
+    }
+    Ok((repo, rev, path))
+}
+
+fn browse_error_response(project_id: &str, e: BrowseError) -> Response<Body> {
+    match e {
+        BrowseError::NotFound => {
+            json_response(StatusCode::NOT_FOUND, json!({"error": e.to_string()}), None)
+        }
+        BrowseError::WrongKind(..) => json_response(
+            StatusCode::BAD_REQUEST,
+            json!({"error": e.to_string()}),
+            None,
+        ),
+        BrowseError::Git(e) => {
+            error!(%project_id, "browsing bare repo failed: {e}");
+            json_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"error": "failed to read repository"}),
+                None,
+            )
+        }
+    }
+}
+
+/// Directory listing of the bare mirror; never triggers a sync.
+pub async fn admin_project_tree_api(
+    State(app): State<Arc<AppState>>,
+    headers: HeaderMap,
+    project_id: ProjectId,
+    Query(query): Query<BrowseQuery>,
+) -> Response<Body> {
+    if let Err(resp) = require_admin(&headers, &app).await {
+        return resp;
+    }
+    let (repo, rev, path) = match browse_target(&app, &project_id, &query) {
+        Ok(target) => target,
+        Err((status, msg)) => return json_response(status, json!({"error": msg}), None),
+    };
+
+    let listing = {
+        let (rev, path) = (rev.clone(), path.clone());
+        tokio::task::spawn_blocking(move || browse::list_tree(&SystemGit, &repo, &rev, &path)).await
+    };
+    match listing {
+        Ok(Ok(entries)) => json_response(
+            StatusCode::OK,
+            json!({ "ref": rev, "path": path, "entries": entries }),
+            None,
+        ),
+        Ok(Err(e)) => browse_error_response(&project_id, e),
+        Err(e) => browse_error_response(
+            &project_id,
+            BrowseError::Git(BridgeError::Other(format!("join error: {e}"))),
+        ),
+    }
+}
+
+/// File content from the bare mirror, capped in size; binary files only
+/// report metadata. Never triggers a sync.
+pub async fn admin_project_blob_api(
+    State(app): State<Arc<AppState>>,
+    headers: HeaderMap,
+    project_id: ProjectId,
+    Query(query): Query<BrowseQuery>,
+) -> Response<Body> {
+    if let Err(resp) = require_admin(&headers, &app).await {
+        return resp;
+    }
+    let (repo, rev, path) = match browse_target(&app, &project_id, &query) {
+        Ok(target) => target,
+        Err((status, msg)) => return json_response(status, json!({"error": msg}), None),
+    };
+    if path.is_empty() {
+        return json_response(
+            StatusCode::BAD_REQUEST,
+            json!({"error": "path is required"}),
+            None,
+        );
+    }
+
+    let blob = {
+        let (rev, path) = (rev.clone(), path.clone());
+        tokio::task::spawn_blocking(move || browse::read_blob(&SystemGit, &repo, &rev, &path)).await
+    };
+    match blob {
+        Ok(Ok(blob)) => json_response(
+            StatusCode::OK,
+            json!({
+                "ref": rev,
+                "path": path,
+                "size": blob.size,
+                "binary": blob.binary,
+                "too_large": blob.too_large,
+                "max_size": browse::MAX_BLOB_PREVIEW_BYTES,
+                "content": blob.content,
+            }),
+            None,
+        ),
+        Ok(Err(e)) => browse_error_response(&project_id, e),
+        Err(e) => browse_error_response(
+            &project_id,
+            BrowseError::Git(BridgeError::Other(format!("join error: {e}"))),
+        ),
+    }
+}
+
+pub async fn admin_tailwind_asset(
+    State(app): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Response<Body> {
+    app.admin_assets.response(&TAILWIND_JS, &headers)
+}
+
+pub async fn admin_logo_asset(
+    State(app): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Response<Body> {
+    app.admin_assets.response(&LOGO_WEBP, &headers)
+}
+
+pub async fn admin_favicon_asset(
+    State(app): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Response<Body> {
+    app.admin_assets.response(&FAVICON_PNG, &headers)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::auth::token_hash;
+    use crate::config::{AdminAccount, AdminRole, Config};
+    use crate::token_store::{MemoryTokenStore, TokenStore};
+    use crate::{AppState, build_router};
+    use axum::body::{Body, to_bytes};
+    use axum::http::{Request, StatusCode, header};
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use tower::ServiceExt;
+
+    /// Log in with the password `pw` and return the session cookie.
+    async fn admin_cookie(app: &axum::Router) -> String {
+        let login = Request::post("/admin/api/login")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(r#"{"password":"pw"}"#))
+            .unwrap();
+        let resp = app.clone().oneshot(login).await.unwrap();
+        let cookie = resp.headers()[header::SET_COOKIE].to_str().unwrap();
+        cookie.split(';').next().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn login_checks_the_password_hash() {
+        use argon2::password_hash::{PasswordHasher, SaltString};
+        use argon2::{Algorithm, Argon2, Params, Version};
+
+        let root = tempfile::TempDir::new().unwrap();
+        let mut cfg = Config::for_test(root.path());
+        let argon2 = Argon2::new(
+            Algorithm::Argon2id,
+            Version::V0x13,
+            Params::new(1024, 1, 1, None).unwrap(),
+        );
+        let salt = SaltString::encode_b64(b"gitbridge-salt").unwrap();
+        cfg.admin_password_hash = Some(argon2.hash_password(b"pw", &salt).unwrap().to_string());
+        let app = build_router(Arc::new(AppState::new(cfg)));
+
+        let login = |password: &'static str| {
+            let app = app.clone();
+            async move {
+                let body = serde_json::json!({ "password": password }).to_string();
+                let req = Request::post("/admin/api/login")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(body))
+                    .unwrap();
+                app.oneshot(req).await.unwrap().status()
+            }
+        };
+        assert_eq!(login("wrong").await, StatusCode::UNAUTHORIZED);
+        assert_eq!(login("").await, StatusCode::UNAUTHORIZED);
+        let cookie = admin_cookie(&app).await;
+        let req = Request::get("/admin/api/tokens")
+            .header(header::COOKIE, cookie)
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(app.oneshot(req).await.unwrap().status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn viewers_can_look_but_only_managers_change_tokens() {
+        use argon2::password_hash::{PasswordHasher, SaltString};
+        use argon2::{Algorithm, Argon2, Params, Version};
+
+        let root = tempfile::TempDir::new().unwrap();
+        let mut cfg = Config::for_test(root.path());
+        let argon2 = Argon2::new(
+            Algorithm::Argon2id,
+            Version::V0x13,
+            Params::new(1024, 1, 1, None).unwrap(),
+        );
+        let salt = SaltString::encode_b64(b"gitbridge-salt").unwrap();
+        let account = |username: &str, password: &str, role| AdminAccount {
+            username: username.into(),
+            password_hash: argon2
+                .hash_password(password.as_bytes(), &salt)
+                .unwrap()
+                .to_string(),
+            role,
+        };
+        cfg.admin_accounts = vec![
+            account("ada", "ada-pw", AdminRole::Manager),
+            account("bob", "bob-pw", AdminRole::Viewer),
+        ];
+        cfg.admin_password = Some("pw".into());
+        let app = build_router(Arc::new(AppState::new(cfg)));
+
+        let login = |body: serde_json::Value| {
+            let app = app.clone();
+            async move {
+                let req = Request::post("/admin/api/login")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(body.to_string()))
+                    .unwrap();
+                let resp = app.oneshot(req).await.unwrap();
+                let status = resp.status();
+                let cookie = resp
+                    .headers()
+                    .get(header::SET_COOKIE)
+                    .map(|c| c.to_str().unwrap().split(';').next().unwrap().to_string());
+                let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+                let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+                (status, cookie, body)
+            }
+        };
+        let create = |cookie: String| {
+            let app = app.clone();
+            async move {
+                let req = Request::post("/admin/api/tokens")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .header(header::COOKIE, cookie)
+                    .body(Body::from(r#"{"description":"ci"}"#))
+                    .unwrap();
+                app.oneshot(req).await.unwrap().status()
+            }
+        };
+
+        for (username, password) in [("bob", "ada-pw"), ("carol", "bob-pw"), ("ada", "pw")] {
+            let body = serde_json::json!({ "username": username, "password": password });
+            let (status, _, body) = login(body).await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED, "{username}");
+            assert_eq!(body["error"], "Invalid username or password");
+        }
+
+        let (status, viewer, body) =
+            login(serde_json::json!({ "username": "bob", "password": "bob-pw" })).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["role"], "viewer");
+        let viewer = viewer.unwrap();
+        let req = Request::get("/admin/api/tokens")
+            .header(header::COOKIE, &viewer)
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(
+            app.clone().oneshot(req).await.unwrap().status(),
+            StatusCode::OK
+        );
+        assert_eq!(create(viewer.clone()).await, StatusCode::FORBIDDEN);
+        for req in [
+            Request::delete("/admin/api/tokens/some-id"),
+            Request::post("/admin/api/tokens/import"),
+        ] {
+            let req = req
+                .header(header::COOKIE, &viewer)
+                .body(Body::from("{}"))
+                .unwrap();
+            assert_eq!(
+                app.clone().oneshot(req).await.unwrap().status(),
+                StatusCode::FORBIDDEN
+            );
+        }
+
+        let (status, manager, body) =
+            login(serde_json::json!({ "username": "ada", "password": "ada-pw" })).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            (&body["username"], &body["role"]),
+            (&"ada".into(), &"manager".into())
+        );
+        assert_eq!(create(manager.unwrap()).await, StatusCode::CREATED);
+
+        // the admin password still logs in, as the manager "admin"
+        let (status, _, body) = login(serde_json::json!({ "password": "pw" })).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            (&body["username"], &body["role"]),
+            (&"admin".into(), &"manager".into())
+        );
+        assert_eq!(create(admin_cookie(&app).await).await, StatusCode::CREATED);
+    }
+
+    #[cfg(feature = "oidc")]
+    #[tokio::test]
+    async fn oidc_logins_open_sessions_for_allowed_users() {
+        use crate::config::OidcConfig;
+        use axum::extract::Form;
+        use axum::routing::{get, post};
+        use base64::Engine;
+        use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+        use std::sync::Mutex;
+
+        // a provider that hands out ID tokens for the code "good"
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let issuer = format!("http://{}", listener.local_addr().unwrap());
+        let nonce = Arc::new(Mutex::new(String::new()));
+        let provider = axum::Router::new()
+            .route(
+                "/.well-known/openid-configuration",
+                get({
+                    let issuer = issuer.clone();
+                    move || async move {
+                        axum::Json(serde_json::json!({
+                            "issuer": issuer,
+                            "authorization_endpoint": format!("{issuer}/auth"),
+                            "token_endpoint": format!("{issuer}/token"),
+                        }))
+                    }
+                }),
+            )
+            .route(
+                "/token",
+                post({
+                    let (issuer, nonce) = (issuer.clone(), nonce.clone());
+                    move |Form(form): Form<HashMap<String, String>>| async move {
+                        if form["code"] != "good" || form["client_secret"] != "s3cret" {
+                            return Err(StatusCode::BAD_REQUEST);
+                        }
+                        let claims = serde_json::json!({
+                            "iss": issuer, "aud": "gitbridge", "sub": "u-1",
+                            "exp": chrono::Utc::now().timestamp() + 300,
+                            "nonce": *nonce.lock().unwrap(), "email": "ada@example.org",
+                        });
+                        let id_token =
+                            format!("e30.{}.sig", URL_SAFE_NO_PAD.encode(claims.to_string()));
+                        Ok(axum::Json(serde_json::json!({ "id_token": id_token })))
+                    }
+                }),
+            );
+        tokio::spawn(async move { axum::serve(listener, provider).await });
+
+        let root = tempfile::TempDir::new().unwrap();
+        let mut cfg = Config::for_test(root.path());
+        cfg.admin_password = Some("pw".into());
+        cfg.admin_password_login = false;
+        cfg.oidc = Some(OidcConfig {
+            issuer_url: issuer.clone(),
+            client_id: "gitbridge".into(),
+            client_secret: "s3cret".into(),
+            redirect_url: "http://bridge/admin/api/login/oidc/callback".into(),
+            scopes: "openid email".into(),
+            allowed_groups: Vec::new(),
+            allowed_emails: vec!["ada@example.org".into()],
+        });
+        let app = build_router(Arc::new(AppState::new(cfg)));
+        let send = |req: Request<Body>| {
+            let app = app.clone();
+            async move { app.oneshot(req).await.unwrap() }
+        };
+
+        let resp = send(
+            Request::get("/admin/api/login")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        let options: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            options,
+            serde_json::json!({ "password": false, "oidc": true })
+        );
+        let login = Request::post("/admin/api/login")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(r#"{"password":"pw"}"#))
+            .unwrap();
+        assert_eq!(send(login).await.status(), StatusCode::FORBIDDEN);
+
+        let resp = send(
+            Request::get("/admin/api/login/oidc")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::SEE_OTHER);
+        let location = url::Url::parse(resp.headers()[header::LOCATION].to_str().unwrap()).unwrap();
+        assert_eq!(location.path(), "/auth");
+        let query: HashMap<_, _> = location.query_pairs().into_owned().collect();
+        *nonce.lock().unwrap() = query["nonce"].clone();
+        let state = &query["state"];
+        let state_cookie = resp.headers()[header::SET_COOKIE].to_str().unwrap();
+        assert_eq!(
+            state_cookie.split(';').next().unwrap(),
+            format!("gb_oidc={state}")
+        );
+
+        let callback = |code: &str, cookie: Option<String>| {
+            let mut req = Request::get(format!(
+                "/admin/api/login/oidc/callback?code={code}&state={state}"
+            ));
+            if let Some(cookie) = cookie {
+                req = req.header(header::COOKIE, cookie);
+            }
+            send(req.body(Body::empty()).unwrap())
+        };
+        // another browser cannot finish the login
+        assert_eq!(
+            callback("good", None).await.status(),
+            StatusCode::BAD_REQUEST
+        );
+        let resp = callback("good", Some(format!("gb_oidc={state}"))).await;
+        assert_eq!(resp.status(), StatusCode::SEE_OTHER);
+        assert_eq!(resp.headers()[header::LOCATION], "/admin");
+        let session = resp.headers()[header::SET_COOKIE].to_str().unwrap();
+        let session = session.split(';').next().unwrap().to_string();
+        assert!(session.starts_with("gb_admin="));
+        let req = Request::post("/admin/api/tokens")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(header::COOKIE, &session)
+            .body(Body::from(r#"{"description":"sso"}"#))
+            .unwrap();
+        assert_eq!(send(req).await.status(), StatusCode::CREATED);
+
+        // a state is used once
+        let resp = callback("good", Some(format!("gb_oidc={state}"))).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn the_api_token_works_without_a_session() {
+        let key = "k".repeat(32);
+        let root = tempfile::TempDir::new().unwrap();
+        let mut cfg = Config::for_test(root.path());
+        cfg.admin_password = Some("pw".into());
+        cfg.admin_api_token = Some(key.clone());
+        let state = Arc::new(AppState::new(cfg));
+        let app = build_router(state.clone());
+        let create = |bearer: Option<String>| {
+            let app = app.clone();
+            async move {
+                let mut req = Request::post("/admin/api/tokens")
+                    .header(header::CONTENT_TYPE, "application/json");
+                if let Some(bearer) = bearer {
+                    req = req.header(header::AUTHORIZATION, format!("Bearer {bearer}"));
+                }
+                let req = req
+                    .body(Body::from(r#"{"description":"ansible"}"#))
+                    .unwrap();
+                app.oneshot(req).await.unwrap().status()
+            }
+        };
+
+        assert_eq!(create(Some(key.clone())).await, StatusCode::CREATED);
+        assert!(state.admin_sessions.lock().await.is_empty());
+        assert_eq!(create(Some("k".repeat(31))).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(create(None).await, StatusCode::UNAUTHORIZED);
+        // the cookie login is unchanged
+        let req = Request::get("/admin/api/tokens")
+            .header(header::COOKIE, admin_cookie(&app).await)
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(
+            app.clone().oneshot(req).await.unwrap().status(),
+            StatusCode::OK
+        );
+
+        // without ADMIN_API_TOKEN a bearer token is no login
+        let mut cfg = Config::for_test(root.path());
+        cfg.admin_password = Some("pw".into());
+        let app = build_router(Arc::new(AppState::new(cfg)));
+        let req = Request::get("/admin/api/tokens")
+            .header(header::AUTHORIZATION, format!("Bearer {key}"))
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(
+            app.oneshot(req).await.unwrap().status(),
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[tokio::test]
+    async fn login_requires_the_totp_code_when_configured() {
+        let secret = "JBSWY3DPEHPK3PXPJBSWY3DP";
+        let root = tempfile::TempDir::new().unwrap();
+        let mut cfg = Config::for_test(root.path());
+        cfg.admin_password = Some("pw".into());
+        cfg.admin_totp_secret = Some(secret.into());
+        let state = Arc::new(AppState::new(cfg));
+        let app = build_router(state.clone());
+
+        let login = |body: serde_json::Value| {
+            let app = app.clone();
+            async move {
+                let req = Request::post("/admin/api/login")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(body.to_string()))
+                    .unwrap();
+                let resp = app.oneshot(req).await.unwrap();
+                let status = resp.status();
+                let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+                (
+                    status,
+                    serde_json::from_slice::<serde_json::Value>(&body).unwrap(),
+                )
+            }
+        };
+        let now = chrono::Utc::now().timestamp() as u64;
+        let code = crate::totp::code(secret, now);
+        let stale = crate::totp::code(secret, now - 120);
+
+        let (status, body) = login(serde_json::json!({ "password": "pw" })).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body["error"], "Invalid password or one-time code");
+        for (password, otp) in [("pw", stale.as_str()), ("pw", "abc"), ("wrong", &code)] {
+            let body = serde_json::json!({ "password": password, "otp": otp });
+            assert_eq!(login(body).await.0, StatusCode::UNAUTHORIZED, "{otp}");
+        }
+        // every miss counts against the login throttle
+        assert_eq!(state.admin_login_failures.lock().await.failures(None), 4);
+
+        let (status, _) = login(serde_json::json!({ "password": "pw", "otp": code })).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(state.admin_login_failures.lock().await.failures(None), 0);
+    }
+
+    #[tokio::test]
+    async fn login_throttling_is_per_client() {
+        use axum::extract::ConnectInfo;
+        use std::net::SocketAddr;
+
+        let root = tempfile::TempDir::new().unwrap();
+        let mut cfg = Config::for_test(root.path());
+        cfg.admin_password = Some("pw".into());
+        cfg.trusted_proxies = vec!["10.0.0.1".parse().unwrap()];
+        let app = build_router(Arc::new(AppState::new(cfg)));
+        let login = |peer: &'static str, forwarded: Option<&'static str>, password: &str| {
+            let body = serde_json::json!({ "password": password }).to_string();
+            let mut req =
+                Request::post("/admin/api/login").header(header::CONTENT_TYPE, "application/json");
+            if let Some(forwarded) = forwarded {
+                req = req.header("x-forwarded-for", forwarded);
+            }
+            let mut req = req.body(Body::from(body)).unwrap();
+            let peer: SocketAddr = format!("{peer}:40000").parse().unwrap();
+            req.extensions_mut().insert(ConnectInfo(peer));
+            let app = app.clone();
+            async move { app.oneshot(req).await.unwrap().status() }
+        };
+
+        for _ in 0..5 {
+            assert_eq!(
+                login("203.0.113.9", None, "guess").await,
+                StatusCode::UNAUTHORIZED
+            );
+        }
+        assert_eq!(
+            login("203.0.113.9", None, "pw").await,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        // the admin elsewhere is not locked out, also behind the proxy
+        assert_eq!(login("198.51.100.1", None, "pw").await, StatusCode::OK);
+        assert_eq!(
+            login("10.0.0.1", Some("198.51.100.2"), "pw").await,
+            StatusCode::OK
+        );
+        // a forwarded address only counts from a trusted proxy
+        assert_eq!(
+            login("203.0.113.9", Some("198.51.100.2"), "pw").await,
+            StatusCode::TOO_MANY_REQUESTS
+        );
+    }
+
+    #[tokio::test]
+    async fn only_static_assets_may_be_cached() {
+        let root = tempfile::TempDir::new().unwrap();
+        let mut cfg = Config::for_test(root.path());
+        cfg.admin_password = Some("pw".into());
+        let app = build_router(Arc::new(AppState::new(cfg)));
+        let cache_control = |path: &'static str| {
+            let app = app.clone();
+            async move {
+                let req = Request::get(path).body(Body::empty()).unwrap();
+                let resp = app.oneshot(req).await.unwrap();
+                let value = resp.headers()[header::CACHE_CONTROL].clone();
+                (resp.status(), value)
+            }
+        };
+
+        for asset in ["/assets/tailwind.js", "/assets/logo.webp", "/favicon.ico"] {
+            let (status, value) = cache_control(asset).await;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(value, "public, max-age=86400, immutable", "{asset}");
+        }
+        assert_eq!(cache_control("/admin").await.1, "no-cache");
+        for api in ["/admin/api/stats", "/admin/api/tokens"] {
+            let (status, value) = cache_control(api).await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED);
+            assert_eq!(value, "no-store", "{api}");
+        }
+    }
+
+    #[tokio::test]
+    async fn maintenance_mode_turns_git_clients_away_until_switched_off() {
+        let root = tempfile::TempDir::new().unwrap();
+        let mut cfg = Config::for_test(root.path());
+        cfg.admin_password = Some("pw".into());
+        let state = Arc::new(AppState::new(cfg.clone()));
+        let app = build_router(state.clone());
+        let send = |req: Request<Body>| {
+            let app = app.clone();
+            async move {
+                let resp = app.oneshot(req).await.unwrap();
+                let status = resp.status();
+                let headers = resp.headers().clone();
+                let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+                (status, headers, String::from_utf8(body.to_vec()).unwrap())
+            }
+        };
+        let cookie = admin_cookie(&app).await;
+        let set = |query: &str, body: &'static str| {
+            Request::post(format!("/admin/api/maintenance{query}"))
+                .header(header::COOKIE, &cookie)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body))
+                .unwrap()
+        };
+        let git = || {
+            Request::get("/git/abc123.git/info/refs?service=git-upload-pack")
+                .body(Body::empty())
+                .unwrap()
+        };
+        let health = || Request::get("/").body(Body::empty()).unwrap();
+
+        // a request still running keeps the drain waiting
+        let running = state.maintenance.begin();
+        let enable = r#"{"enabled":true,"message":"moving disks","retry_after_seconds":120}"#;
+        let (status, _, body) = send(set("?drain=true&timeout_seconds=1", enable)).await;
+        assert_eq!(status, StatusCode::GATEWAY_TIMEOUT, "{body}");
+        drop(running);
+        let (status, _, body) = send(set("?drain=true", enable)).await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["enabled"], true);
+        assert_eq!(body["in_flight"], 0);
+
+        let (status, headers, body) = send(git()).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(headers[header::RETRY_AFTER], "120");
+        assert_eq!(body, "moving disks\n");
+        let (status, _, body) = send(health()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("maintenance mode") && body.contains("moving disks"));
+
+        // restarts keep it on
+        let restarted = build_router(Arc::new(AppState::new(cfg)));
+        let resp = restarted.oneshot(git()).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let (status, _, _) = send(set("", r#"{"enabled":false}"#)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(send(git()).await.0, StatusCode::UNAUTHORIZED);
+        assert_eq!(send(health()).await.2, "sharelatex-gitbridge-ro up\n");
+
+        let (status, _, _) = send(set("", r#"{"enabled":true,"message":"a\nb"}"#)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn token_entries_carry_clone_urls() {
+        let root = tempfile::TempDir::new().unwrap();
+        let mut cfg = Config::for_test(root.path());
+        cfg.admin_password = Some("pw".into());
+        cfg.public_url = Some("https://tex.example.org/bridge".into());
+        let tokens = MemoryTokenStore::with_tokens(HashMap::from([
+            ("global".to_string(), "CI".to_string()),
+            ("scoped".to_string(), "thesis".to_string()),
+        ]));
+        tokens
+            .set_scope(&token_hash("scoped"), Some(vec!["p1".into(), "p2".into()]))
+            .await
+            .unwrap();
+        let app = build_router(Arc::new(AppState::with_token_store(cfg, Arc::new(tokens))));
+        let req = Request::get("/admin/api/tokens")
+            .header(header::COOKIE, admin_cookie(&app).await)
+            .body(Body::empty())
+            .unwrap();
+        let resp = app.oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()[header::CACHE_CONTROL], "no-store");
+        let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        let entries: Vec<serde_json::Value> = serde_json::from_slice(&body).unwrap();
+        let entry = |token: &str| {
+            let id = token_hash(token);
+            let entry = entries.iter().find(|e| e["id"] == id.as_str()).unwrap();
+            assert!(entry.get("token").is_none());
+            entry
+        };
+
+        let global = entry("global");
+        assert_eq!(
+            global["example_clone_url"],
+            "https://tex.example.org/bridge/git/<project_id>.git"
+        );
+        assert!(global.get("clone_urls").is_none());
+        let scoped = entry("scoped");
+        assert_eq!(
+            scoped["example_clone_url"],
+            "https://tex.example.org/bridge/git/p1.git"
+        );
+        assert_eq!(
+            scoped["clone_urls"],
+            serde_json::json!([
+                "https://tex.example.org/bridge/git/p1.git",
+                "https://tex.example.org/bridge/git/p2.git",
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn created_tokens_are_only_returned_once() {
+        let root = tempfile::TempDir::new().unwrap();
+        let mut cfg = Config::for_test(root.path());
+        cfg.admin_password = Some("pw".into());
+        std::fs::create_dir_all(&cfg.git_root).unwrap();
+        let state = Arc::new(AppState::new(cfg.clone()));
+        let app = build_router(state.clone());
+        let cookie = admin_cookie(&app).await;
+        let send = |req: axum::http::request::Builder, body: &'static str| {
+            let req = req
+                .header(header::COOKIE, &cookie)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body))
+                .unwrap();
+            let app = app.clone();
+            async move {
+                let resp = app.oneshot(req).await.unwrap();
+                let status = resp.status();
+                let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+                (status, serde_json::from_slice(&body).unwrap_or_default())
+            }
+        };
+
+        let (status, created): (_, serde_json::Value) = send(
+            Request::post("/admin/api/tokens"),
+            r#"{"description":"CI","daily_requests":5}"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+        let token = created["token"].as_str().unwrap().to_string();
+        let id = created["id"].as_str().unwrap().to_string();
+        assert_eq!(id, token_hash(&token));
+        let raw = std::fs::read_to_string(cfg.tokens_file()).unwrap();
+        assert!(raw.contains(&id) && !raw.contains(&token), "{raw}");
+        assert_eq!(
+            state.tokens.check(&token, "p1").await.unwrap(),
+            crate::token_store::Decision::Allowed
+        );
+
+        let (_, listed) = send(Request::get("/admin/api/tokens"), "").await;
+        assert_eq!(listed[0]["id"], id.as_str());
+        assert_eq!(listed[0]["daily_requests"], 5);
+        assert!(listed[0].get("token").is_none());
+
+        // PATCH changes only what it names
+        let (status, patched) = send(
+            Request::patch(format!("/admin/api/tokens/{id}")),
+            r#"{"description":" CI runner ","projects":["p2","p1","p2"]}"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(patched["description"], "CI runner");
+        assert_eq!(patched["daily_requests"], 5);
+        assert_eq!(patched["projects"], serde_json::json!(["p1", "p2"]));
+        assert_eq!(
+            state.tokens.check(&token, "p3").await.unwrap(),
+            crate::token_store::Decision::NotAllowedForProject
+        );
+        let (status, patched) = send(
+            Request::patch(format!("/admin/api/tokens/{id}")),
+            r#"{"projects":null}"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(patched["description"], "CI runner");
+        assert!(patched.get("projects").is_none());
+        for (body, expected) in [
+            (r#"{"projects":[]}"#, StatusCode::BAD_REQUEST),
+            (r#"{"projects":["../x"]}"#, StatusCode::BAD_REQUEST),
+            (
+                r#"{"expires_at":"2030-01-01T00:00:00Z"}"#,
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ),
+        ] {
+            let (status, _) = send(Request::patch(format!("/admin/api/tokens/{id}")), body).await;
+            assert_eq!(status, expected, "{body}");
+        }
+        let (status, _) = send(Request::patch(format!("/admin/api/tokens/{token}")), "{}").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (status, updated) = send(
+            Request::put(format!("/admin/api/tokens/{id}")),
+            r#"{"description":"CI mirror"}"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(updated["description"], "CI mirror");
+        assert!(updated.get("token").is_none());
+        let (status, _) = send(Request::put(format!("/admin/api/tokens/{token}")), "{}").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (status, _) = send(Request::delete(format!("/admin/api/tokens/{id}")), "").await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert!(state.tokens.list().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn tokens_can_be_exported_and_imported() {
+        let root = tempfile::TempDir::new().unwrap();
+        let mut cfg = Config::for_test(root.path());
+        cfg.admin_password = Some("pw".into());
+        std::fs::create_dir_all(&cfg.git_root).unwrap();
+        let state = Arc::new(AppState::new(cfg.clone()));
+        let app = build_router(state.clone());
+        let cookie = admin_cookie(&app).await;
+        let send = |req: axum::http::request::Builder, body: String| {
+            let req = req
+                .header(header::COOKIE, &cookie)
+                .body(Body::from(body))
+                .unwrap();
+            let app = app.clone();
+            async move {
+                let resp = app.oneshot(req).await.unwrap();
+                let status = resp.status();
+                let disposition = resp.headers().get(header::CONTENT_DISPOSITION).cloned();
+                let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+                let body: serde_json::Value = serde_json::from_slice(&body).unwrap_or_default();
+                (status, body, disposition)
+            }
+        };
+
+        state.tokens.insert("staging-ci", "CI").await.unwrap();
+        let ci = token_hash("staging-ci");
+        state
+            .tokens
+            .set_scope(&ci, Some(vec!["p1".into()]))
+            .await
+            .unwrap();
+        let (status, exported, disposition) =
+            send(Request::get("/admin/api/tokens/export"), String::new()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(disposition.unwrap(), "attachment; filename=\"tokens.json\"");
+        assert_eq!(exported["hashed"], true);
+        assert_eq!(exported["managed_tokens"][&ci], "CI");
+        assert_eq!(exported["scopes"][&ci], serde_json::json!(["p1"]));
+
+        // a second bridge takes the export as it is, plain tokens are hashed
+        let other = tempfile::TempDir::new().unwrap();
+        let mut other_cfg = Config::for_test(other.path());
+        other_cfg.admin_password = Some("pw".into());
+        std::fs::create_dir_all(&other_cfg.git_root).unwrap();
+        let other_state = Arc::new(AppState::new(other_cfg));
+        let other_app = build_router(other_state.clone());
+        let other_cookie = admin_cookie(&other_app).await;
+        let import = |query: &str, body: String| {
+            let req = Request::post(format!("/admin/api/tokens/import{query}"))
+                .header(header::COOKIE, &other_cookie)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body))
+                .unwrap();
+            let app = other_app.clone();
+            async move {
+                let resp = app.oneshot(req).await.unwrap();
+                let status = resp.status();
+                let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+                let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+                (status, body)
+            }
+        };
+        other_state
+            .tokens
+            .insert("prod-only", "prod")
+            .await
+            .unwrap();
+        let (status, summary) = import("", exported.to_string()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(summary["added"], 1);
+        assert_eq!(
+            other_state.tokens.check("staging-ci", "p2").await.unwrap(),
+            crate::token_store::Decision::NotAllowedForProject
+        );
+        let (status, summary) = import(
+            "?mode=replace",
+            r#"{"managed_tokens":{"plain":"from an old file"}}"#.into(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            (summary["added"].as_u64(), summary["removed"].as_u64()),
+            (Some(1), Some(2))
+        );
+        let ids: Vec<_> = other_state.tokens.list().await.unwrap();
+        assert_eq!(ids[0].id, token_hash("plain"));
+
+        for (query, body, error) in [
+            ("", "{", "body is not JSON"),
+            ("", "[]", "must be a tokens.json object"),
+            ("", r#"{"tokens":{}}"#, "unknown field 'tokens'"),
+            ("", r#"{"managed_tokens":[]}"#, "invalid tokens file"),
+            (
+                "",
+                r#"{"hashed":true,"managed_tokens":{"x":"y"}}"#,
+                "not a token hash",
+            ),
+            (
+                "",
+                r#"{"managed_tokens":{"t":""},"source_scopes":{"t":"nowhere"}}"#,
+                "unknown source 'nowhere'",
+            ),
+            ("?mode=wipe", "{}", "unknown mode 'wipe'"),
+        ] {
+            let (status, body) = import(query, body.into()).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{query} {body}");
+            let message = body["error"].as_str().unwrap();
+            assert!(message.contains(error), "{message}");
+        }
+        assert_eq!(other_state.tokens.list().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn projects_and_tokens_of_named_sources() {
+        use crate::config::{NamedSource, ProjectsLayout};
+        use crate::project_id::pid;
+
+        let root = tempfile::TempDir::new().unwrap();
+        let mut cfg = Config::for_test(root.path());
+        cfg.admin_password = Some("pw".into());
+        cfg.sources = vec![NamedSource {
+            name: "prod".into(),
+            sharelatex_data_path: root.path().join("prod"),
+            projects_dir: "projects".into(),
+            projects_layout: ProjectsLayout::Flat,
+        }];
+        std::fs::create_dir_all(&cfg.git_root).unwrap();
+        let state = Arc::new(AppState::new(cfg.clone()));
+        for (cfg, id) in [
+            (&cfg, "p1"),
+            (&cfg, "p3"),
+            (&state.sources["prod"].cfg, "p2"),
+        ] {
+            let dir = cfg.project_source_dir(&pid(id)).unwrap();
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(dir.join("main.tex"), id).unwrap();
+        }
+        let app = build_router(state.clone());
+        let cookie = admin_cookie(&app).await;
+        let send = |req: axum::http::request::Builder, body: &'static str| {
+            let req = req
+                .header(header::COOKIE, &cookie)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body))
+                .unwrap();
+            let app = app.clone();
+            async move {
+                let resp = app.oneshot(req).await.unwrap();
+                let status = resp.status();
+                let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+                let body: serde_json::Value = serde_json::from_slice(&body).unwrap_or_default();
+                (status, body)
+            }
+        };
+
+        let (status, listed) = send(Request::get("/admin/api/projects"), "").await;
+        assert_eq!(status, StatusCode::OK);
+        let ids = |projects: &serde_json::Value| -> Vec<String> {
+            projects
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|p| p["id"].as_str().unwrap().to_string())
+                .collect()
+        };
+        assert_eq!(ids(&listed["projects"]), ["p1", "p3"]);
+        assert_eq!(ids(&listed["sources"]["prod"]), ["p2"]);
+        assert!(listed["projects"][0]["source_modified_at"].is_string());
+        let (_, page) = send(Request::get("/admin/api/projects?offset=1&limit=1"), "").await;
+        assert_eq!(ids(&page["projects"]), ["p3"]);
+        assert_eq!(page["total"], 2);
+
+        let (status, created) = send(
+            Request::post("/admin/api/tokens"),
+            r#"{"description":"prod CI","source":"prod"}"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(created["source"], "prod");
+        assert_eq!(created["example_clone_url"], "/git/prod/<project_id>.git");
+        let (_, listed) = send(Request::get("/admin/api/tokens"), "").await;
+        assert_eq!(listed[0]["source"], "prod");
+
+        let (status, _) = send(
+            Request::post("/admin/api/tokens"),
+            r#"{"description":"x","source":"staging"}"#,
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(state.tokens.list().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn mirrors_can_be_synced_and_rebuilt_on_demand() {
+        use crate::project_id::pid;
+
+        let root = tempfile::TempDir::new().unwrap();
+        let mut cfg = Config::for_test(root.path());
+        cfg.admin_password = Some("pw".into());
+        let dir = cfg.project_source_dir(&pid("p1")).unwrap();
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("main.tex"), "hello").unwrap();
+        let app = build_router(Arc::new(AppState::new(cfg.clone())));
+        let cookie = admin_cookie(&app).await;
+        let send = |req: axum::http::request::Builder| {
+            let req = req
+                .header(header::COOKIE, &cookie)
+                .body(Body::empty())
+                .unwrap();
+            let app = app.clone();
+            async move {
+                let resp = app.oneshot(req).await.unwrap();
+                let status = resp.status();
+                let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+                let body: serde_json::Value = serde_json::from_slice(&body).unwrap_or_default();
+                (status, body)
+            }
+        };
+        let sync = |id: &str| send(Request::post(format!("/admin/api/projects/{id}/sync")));
+        let delete = |id: &str| send(Request::delete(format!("/admin/api/repos/{id}")));
+
+        let (status, first) = sync("p1").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(first["created"], true);
+        assert_eq!(first["commit"].as_str().unwrap().len(), 40);
+        assert!(first["duration_ms"].is_u64());
+
+        // nothing changed: no commit, but the one the branch is at
+        let (status, second) = sync("p1").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(second["created"], false);
+        assert_eq!(second["commit"], first["commit"]);
+
+        let (status, missing) = sync("p9").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert!(missing["error"].as_str().unwrap().contains("p9"));
+
+        // a removed mirror goes to the trash and is built anew
+        let (status, _) = delete("p1").await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert!(!cfg.bare_repo_dir(&pid("p1")).unwrap().exists());
+        assert_eq!(crate::trash::list_trash(&cfg).unwrap().len(), 1);
+        assert_eq!(delete("p1").await.0, StatusCode::NOT_FOUND);
+        assert_eq!(delete("..").await.0, StatusCode::BAD_REQUEST);
+
+        // a mirror that is a symlink out of GIT_ROOT is neither synced nor deleted
+        let outside = tempfile::TempDir::new().unwrap();
+        std::os::unix::fs::symlink(outside.path(), cfg.repos_dir().join("p2.git")).unwrap();
+        let (status, refused) = delete("p2").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(refused["error"], "mirror path is outside GIT_ROOT");
+        assert_eq!(sync("p2").await.0, StatusCode::BAD_REQUEST);
+        assert!(cfg.repos_dir().join("p2.git").is_symlink());
+
+        let (status, rebuilt) = sync("p1").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(rebuilt["created"], true);
+        let audit = std::fs::read_to_string(cfg.state_dir().join("audit.jsonl")).unwrap();
+        assert!(audit.contains("repo_delete"));
+    }
+
+    #[tokio::test]
+    async fn queued_webhooks_can_be_listed_flushed_and_discarded() {
+        use crate::webhook_queue::WebhookKind;
+
+        let root = tempfile::TempDir::new().unwrap();
+        let mut cfg = Config::for_test(root.path());
+        cfg.admin_password = Some("pw".into());
+        let state = Arc::new(AppState::new(cfg));
+        let app = build_router(state.clone());
+        let cookie = admin_cookie(&app).await;
+        let send = |method: &str, uri: &str| {
+            let req = Request::builder()
+                .method(method)
+                .uri(uri)
+                .header(header::COOKIE, &cookie)
+                .body(Body::empty())
+                .unwrap();
+            let app = app.clone();
+            async move {
+                let resp = app.oneshot(req).await.unwrap();
+                let status = resp.status();
+                let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+                (
+                    status,
+                    serde_json::from_slice::<serde_json::Value>(&body).unwrap(),
+                )
+            }
+        };
+
+        // nothing listens on port 1
+        let webhooks = state.webhooks.clone();
+        tokio::task::spawn_blocking(move || {
+            let event = serde_json::json!({"event": "sync", "project_id": "p1"});
+            for _ in 0..2 {
+                assert!(
+                    webhooks
+                        .send(
+                            WebhookKind::SyncEvent,
+                            "http://127.0.0.1:1/hook",
+                            event.clone()
+                        )
+                        .is_err()
+                );
+            }
+        })
+        .await
+        .unwrap();
+
+        let (status, body) = send("GET", "/admin/api/webhooks").await;
+        assert_eq!(status, StatusCode::OK);
+        let pending = body["pending"].as_array().unwrap();
+        assert_eq!(pending.len(), 2);
+        assert_eq!(pending[0]["kind"], "sync_event");
+        assert_eq!(pending[0]["target"], "127.0.0.1");
+        assert_eq!(pending[0]["project_id"], "p1");
+        assert_eq!(pending[0]["attempts"], 1);
+        assert!(pending[0].get("url").is_none());
+        let id = pending[0]["id"].as_str().unwrap().to_string();
+
+        let (status, body) = send("POST", "/admin/api/webhooks/flush").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            (body["delivered"].as_u64(), body["pending"].as_u64()),
+            (Some(0), Some(2))
+        );
+
+        let (status, body) = send("DELETE", &format!("/admin/api/webhooks/{id}")).await;
+        assert_eq!(
+            (status, body["discarded"].as_u64()),
+            (StatusCode::OK, Some(1))
+        );
+        let (status, _) = send("DELETE", &format!("/admin/api/webhooks/{id}")).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, body) = send("DELETE", "/admin/api/webhooks").await;
+        assert_eq!(
+            (status, body["discarded"].as_u64()),
+            (StatusCode::OK, Some(1))
+        );
+        let audit = std::fs::read_to_string(state.cfg.audit_log_file()).unwrap();
+        assert_eq!(audit.matches("webhook_discard").count(), 2);
+        assert_eq!(audit.matches(r#""actor":"admin""#).count(), 2);
+    }
 }
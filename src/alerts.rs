@@ -0,0 +1,349 @@
+use crate::config::{Config, SmtpConfig, SmtpTls};
+use crate::error::BridgeError;
+use crate::webhook_queue::{WebhookKind, WebhookQueue};
+use chrono::{DateTime, Utc};
+use lettre::message::header::ContentType;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Message, SmtpTransport, Transport};
+use serde::Serialize;
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// Timeout for delivering one notification.
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Longest error excerpt included in a notification.
+const MAX_ERROR_SUMMARY_CHARS: usize = 500;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum AlertKind {
+    /// The project reached the consecutive-failure threshold
+    Failing,
+    /// The first successful sync after a failure alert
+    Recovered,
+}
+
+/// A notification about one project.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct Alert {
+    pub(crate) kind: AlertKind,
+    pub(crate) project_id: String,
+    pub(crate) consecutive_failures: u32,
+    pub(crate) error: Option<String>,
+    /// Admin page of the project
+    pub(crate) link: String,
+    pub(crate) at: DateTime<Utc>,
+}
+
+impl Alert {
+    pub(crate) fn subject(&self) -> String {
+        match self.kind {
+            AlertKind::Failing => format!(
+                "[gitbridge] {} failed to sync {} times in a row",
+                self.project_id, self.consecutive_failures
+            ),
+            AlertKind::Recovered => format!("[gitbridge] {} syncs again", self.project_id),
+        }
+    }
+
+    /// Plain-text body shared by all channels.
+    pub(crate) fn text(&self) -> String {
+        let mut text = self.subject();
+        if let Some(error) = &self.error {
+            text.push_str(&format!("\nLast error: {error}"));
+        }
+        text.push_str(&format!("\n{}", self.link));
+        text
+    }
+}
+
+/// A channel alerts are delivered through. Blocking.
+pub(crate) trait AlertSender: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn send(&self, alert: &Alert) -> Result<(), BridgeError>;
+}
+
+/// Posts the alert as JSON through the [`WebhookQueue`]; `text` makes it
+/// usable with Slack-style incoming webhooks and Matrix bridges.
+struct WebhookSender {
+    url: String,
+    queue: Arc<WebhookQueue>,
+}
+
+impl AlertSender for WebhookSender {
+    fn name(&self) -> &'static str {
+        "webhook"
+    }
+
+    fn send(&self, alert: &Alert) -> Result<(), BridgeError> {
+        let mut body = serde_json::to_value(alert)?;
+        body["text"] = json!(alert.text());
+        self.queue.send(WebhookKind::Alert, &self.url, body)
+    }
+}
+
+struct SmtpSender {
+    smtp: SmtpConfig,
+}
+
+impl AlertSender for SmtpSender {
+    fn name(&self) -> &'static str {
+        "smtp"
+    }
+
+    fn send(&self, alert: &Alert) -> Result<(), BridgeError> {
+        let smtp_error = |e: &dyn std::fmt::Display| BridgeError::Other(format!("smtp: {e}"));
+        let mut message = Message::builder()
+            .from(self.smtp.from.parse().map_err(|e| smtp_error(&e))?)
+            .subject(alert.subject())
+            .header(ContentType::TEXT_PLAIN);
+        for to in &self.smtp.to {
+            message = message.to(to.parse().map_err(|e| smtp_error(&e))?);
+        }
+        let message = message.body(alert.text()).map_err(|e| smtp_error(&e))?;
+
+        let host = self.smtp.host.as_str();
+        let mut transport = match self.smtp.tls {
+            SmtpTls::StartTls => SmtpTransport::starttls_relay(host).map_err(|e| smtp_error(&e))?,
+            SmtpTls::Tls => SmtpTransport::relay(host).map_err(|e| smtp_error(&e))?,
+            SmtpTls::None => SmtpTransport::builder_dangerous(host),
+        }
+        .port(self.smtp.port)
+        .timeout(Some(SEND_TIMEOUT));
+        if let (Some(username), Some(password)) = (&self.smtp.username, &self.smtp.password) {
+            transport = transport.credentials(Credentials::new(username.clone(), password.clone()));
+        }
+        transport
+            .build()
+            .send(&message)
+            .map_err(|e| smtp_error(&e))?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+struct ProjectAlertState {
+    consecutive_failures: u32,
+    /// A failure alert went out and no recovery has been sent yet
+    alerted: bool,
+    last_failure_alert: Option<Instant>,
+}
+
+/// Tracks consecutive sync failures per project and notifies once a project
+/// crosses the threshold and again when it recovers. Failure alerts for the
+/// same project are at least the cooldown apart, so a flapping project does
+/// not flood the channels. Counts are kept in memory and start over on restart.
+pub(crate) struct Alerter {
+    threshold: u32,
+    cooldown: Duration,
+    /// `PUBLIC_URL`, or `BASE_PATH` for a relative link
+    link_base: String,
+    senders: Vec<Box<dyn AlertSender>>,
+    projects: Mutex<HashMap<String, ProjectAlertState>>,
+}
+
+impl Alerter {
+    /// Alerter with the channels configured in `cfg`, posting webhooks
+    /// through `queue`.
+    pub(crate) fn new(cfg: &Config, queue: &Arc<WebhookQueue>) -> Self {
+        let mut senders: Vec<Box<dyn AlertSender>> = Vec::new();
+        if let Some(url) = &cfg.alert_webhook_url {
+            senders.push(Box::new(WebhookSender {
+                url: url.clone(),
+                queue: queue.clone(),
+            }));
+        }
+        if let Some(smtp) = &cfg.smtp {
+            senders.push(Box::new(SmtpSender { smtp: smtp.clone() }));
+        }
+        Self::with_senders(cfg, senders)
+    }
+
+    pub(crate) fn with_senders(cfg: &Config, senders: Vec<Box<dyn AlertSender>>) -> Self {
+        Self {
+            threshold: cfg.alert_failure_threshold,
+            cooldown: Duration::from_secs(cfg.alert_cooldown_minutes * 60),
+            link_base: cfg.link_base().to_string(),
+            senders,
+            projects: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        !self.senders.is_empty()
+    }
+
+    /// Count a sync result (`error` is `None` on success) and return the
+    /// alert it triggers, if any.
+    pub(crate) fn evaluate(&self, project_id: &str, error: Option<&str>) -> Option<Alert> {
+        let mut projects = self.projects.lock().expect("alert state poisoned");
+        let state = projects.entry(project_id.to_string()).or_default();
+
+        let Some(error) = error else {
+            let failures = std::mem::take(&mut state.consecutive_failures);
+            let alerted = std::mem::take(&mut state.alerted);
+            return alerted.then(|| self.alert(AlertKind::Recovered, project_id, failures, None));
+        };
+
+        state.consecutive_failures += 1;
+        let cooling_down = state
+            .last_failure_alert
+            .is_some_and(|at| at.elapsed() < self.cooldown);
+        if state.alerted || state.consecutive_failures < self.threshold || cooling_down {
+            return None;
+        }
+        state.alerted = true;
+        state.last_failure_alert = Some(Instant::now());
+        Some(self.alert(
+            AlertKind::Failing,
+            project_id,
+            state.consecutive_failures,
+            Some(error),
+        ))
+    }
+
+    fn alert(
+        &self,
+        kind: AlertKind,
+        project_id: &str,
+        consecutive_failures: u32,
+        error: Option<&str>,
+    ) -> Alert {
+        Alert {
+            kind,
+            project_id: project_id.to_string(),
+            consecutive_failures,
+            error: error.map(summarize_error),
+            link: format!("{}/admin/api/projects/{project_id}", self.link_base),
+            at: Utc::now(),
+        }
+    }
+
+    /// Deliver an alert through every channel; failures are logged. Blocking.
+    pub(crate) fn dispatch(&self, alert: &Alert) {
+        for sender in &self.senders {
+            match sender.send(alert) {
+                Ok(()) => info!(
+                    project_id = %alert.project_id,
+                    kind = ?alert.kind,
+                    channel = sender.name(),
+                    "alert sent"
+                ),
+                Err(e) => warn!(
+                    project_id = %alert.project_id,
+                    channel = sender.name(),
+                    error = %e,
+                    "cannot send alert"
+                ),
+            }
+        }
+    }
+}
+
+/// First line of an error, shortened for notifications.
+fn summarize_error(error: &str) -> String {
+    let line = error.lines().next().unwrap_or_default().trim();
+    if line.chars().count() <= MAX_ERROR_SUMMARY_CHARS {
+        return line.to_string();
+    }
+    let cut: String = line.chars().take(MAX_ERROR_SUMMARY_CHARS).collect();
+    format!("{cut}…")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[derive(Default)]
+    struct RecordingSender {
+        sent: Mutex<Vec<Alert>>,
+    }
+
+    impl AlertSender for Arc<RecordingSender> {
+        fn name(&self) -> &'static str {
+            "recording"
+        }
+
+        fn send(&self, alert: &Alert) -> Result<(), BridgeError> {
+            self.sent.lock().unwrap().push(alert.clone());
+            Ok(())
+        }
+    }
+
+    fn setup(cooldown_minutes: u64) -> (Alerter, Arc<RecordingSender>) {
+        let dir = TempDir::new().unwrap();
+        let mut cfg = Config::for_test(dir.path());
+        cfg.alert_failure_threshold = 3;
+        cfg.alert_cooldown_minutes = cooldown_minutes;
+        cfg.public_url = Some("https://bridge.example.org".into());
+        let sender = Arc::new(RecordingSender::default());
+        let alerter = Alerter::with_senders(&cfg, vec![Box::new(sender.clone())]);
+        (alerter, sender)
+    }
+
+    fn observe(alerter: &Alerter, error: Option<&str>) {
+        if let Some(alert) = alerter.evaluate("p1", error) {
+            alerter.dispatch(&alert);
+        }
+    }
+
+    #[test]
+    fn alerts_once_at_the_threshold_and_on_recovery() {
+        let (alerter, sender) = setup(60);
+        for _ in 0..5 {
+            observe(&alerter, Some("git command failed\nmore detail"));
+        }
+        observe(&alerter, None);
+        observe(&alerter, None);
+
+        let sent = sender.sent.lock().unwrap();
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[0].kind, AlertKind::Failing);
+        assert_eq!(sent[0].consecutive_failures, 3);
+        assert_eq!(sent[0].error.as_deref(), Some("git command failed"));
+        assert_eq!(
+            sent[0].link,
+            "https://bridge.example.org/admin/api/projects/p1"
+        );
+        assert_eq!(sent[1].kind, AlertKind::Recovered);
+        assert_eq!(sent[1].consecutive_failures, 5);
+    }
+
+    #[test]
+    fn flapping_projects_are_held_back_by_the_cooldown() {
+        let (alerter, sender) = setup(60);
+        for _ in 0..2 {
+            for _ in 0..3 {
+                observe(&alerter, Some("boom"));
+            }
+            observe(&alerter, None);
+        }
+        // The second run of failures falls into the cooldown, so neither it
+        // nor its recovery is reported.
+        assert_eq!(sender.sent.lock().unwrap().len(), 2);
+
+        let (alerter, sender) = setup(0);
+        for _ in 0..2 {
+            for _ in 0..3 {
+                observe(&alerter, Some("boom"));
+            }
+            observe(&alerter, None);
+        }
+        assert_eq!(sender.sent.lock().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn successes_without_a_prior_alert_are_silent() {
+        let (alerter, sender) = setup(60);
+        observe(&alerter, Some("boom"));
+        observe(&alerter, None);
+        observe(&alerter, Some("boom"));
+        observe(&alerter, Some("boom"));
+        assert!(sender.sent.lock().unwrap().is_empty());
+    }
+}
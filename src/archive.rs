@@ -0,0 +1,169 @@
+//! Zip downloads of a project's snapshot, `/archive/<id>.zip`, optionally as
+//! of a point in time (`?at=2024-03-01T00:00:00Z`): the last commit of the
+//! read-only branch committed on or before it, which is what the mirror
+//! served then. The zip is streamed from `git archive` as it is written.
+
+use crate::config::Config;
+use crate::error::BridgeError;
+use crate::git::{GitRunner, SystemGit};
+use crate::project_id::ProjectId;
+use axum::body::{Body, Bytes, HttpBody};
+use chrono::{DateTime, Utc};
+use hyper::body::Frame;
+use std::pin::Pin;
+use std::process::Stdio;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, ReadBuf};
+use tokio::process::{Child, ChildStdout, Command};
+use url::form_urlencoded;
+
+/// Response header naming the commit an archive was made from.
+pub(crate) const COMMIT_HEADER: &str = "x-gitbridge-commit";
+
+/// Bytes read from `git archive` per body frame.
+const CHUNK_BYTES: usize = 64 * 1024;
+
+/// The `at` query parameter as a point in time, `None` without one. An
+/// unencoded `+` of a UTC offset arrives as a space and is taken back.
+pub(crate) fn requested_time(query: Option<&str>) -> Result<Option<DateTime<Utc>>, String> {
+    let Some((_, raw)) =
+        form_urlencoded::parse(query.unwrap_or_default().as_bytes()).find(|(k, _)| k == "at")
+    else {
+        return Ok(None);
+    };
+    let raw = raw.trim().replace(' ', "+");
+    DateTime::parse_from_rfc3339(&raw)
+        .map(|at| Some(at.with_timezone(&Utc)))
+        .map_err(|_| {
+            format!("invalid at={raw}, expected an RFC 3339 time such as 2024-03-01T00:00:00Z")
+        })
+}
+
+fn git_stdout(cfg: &Config, project_id: &ProjectId, args: &[&str]) -> Result<String, BridgeError> {
+    let out = SystemGit.run(args, &cfg.bare_repo_dir(project_id)?)?;
+    if !out.status.success() {
+        return Err(BridgeError::Other(format!(
+            "git {args:?} failed for {project_id}: {}",
+            String::from_utf8_lossy(&out.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).trim().to_string())
+}
+
+/// The commit of the read-only branch to archive: its tip, or the last
+/// commit committed on or before `at`. `None` if there is no such commit.
+pub(crate) fn resolve_snapshot(
+    cfg: &Config,
+    project_id: &ProjectId,
+    at: Option<DateTime<Utc>>,
+) -> Result<Option<String>, BridgeError> {
+    let branch = format!("refs/heads/{}", cfg.readonly_branch);
+    let commit = match at {
+        Some(at) => {
+            let before = format!("--before=@{}", at.timestamp());
+            git_stdout(cfg, project_id, &["rev-list", "-1", &before, &branch])?
+        }
+        None => {
+            let tip = format!("{branch}^{{commit}}");
+            let out = SystemGit.run(
+                &["rev-parse", "--verify", "--quiet", &tip],
+                &cfg.bare_repo_dir(project_id)?,
+            )?;
+            String::from_utf8_lossy(&out.stdout).trim().to_string()
+        }
+    };
+    Ok((!commit.is_empty()).then_some(commit))
+}
+
+/// Commit time of the first snapshot of the read-only branch, if any.
+pub(crate) fn first_snapshot_time(cfg: &Config, project_id: &ProjectId) -> Option<String> {
+    let branch = format!("refs/heads/{}", cfg.readonly_branch);
+    let dates = git_stdout(
+        cfg,
+        project_id,
+        &["log", "--max-parents=0", "--format=%cI", &branch],
+    )
+    .ok()?;
+    dates.lines().last().map(str::to_string)
+}
+
+/// The zip of `commit`, read from `git archive` while it is sent. `on_end`
+/// gets the number of bytes sent once the body is finished or dropped.
+pub(crate) fn archive_body(
+    cfg: &Config,
+    project_id: &ProjectId,
+    commit: &str,
+    on_end: impl FnOnce(u64) + Send + 'static,
+) -> Result<Body, BridgeError> {
+    let mut child = Command::new("git")
+        .args(["archive", "--format=zip", commit])
+        .current_dir(cfg.bare_repo_dir(project_id)?)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| BridgeError::Other(format!("failed to run git archive: {e}")))?;
+    let stdout = child.stdout.take().expect("stdout is piped");
+    Ok(Body::new(ArchiveBody {
+        _child: child,
+        stdout,
+        sent: 0,
+        finished: false,
+        on_end: Some(Box::new(on_end)),
+    }))
+}
+
+/// The output of a running `git archive`; dropping it kills the process.
+struct ArchiveBody {
+    _child: Child,
+    stdout: ChildStdout,
+    sent: u64,
+    finished: bool,
+    on_end: Option<Box<dyn FnOnce(u64) + Send>>,
+}
+
+impl HttpBody for ArchiveBody {
+    type Data = Bytes;
+    type Error = std::io::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, std::io::Error>>> {
+        if self.finished {
+            return Poll::Ready(None);
+        }
+        let mut chunk = vec![0; CHUNK_BYTES];
+        let mut buf = ReadBuf::new(&mut chunk);
+        match Pin::new(&mut self.stdout).poll_read(cx, &mut buf) {
+            Poll::Pending => Poll::Pending,
+            Poll::Ready(Err(e)) => {
+                self.finished = true;
+                Poll::Ready(Some(Err(e)))
+            }
+            Poll::Ready(Ok(())) if buf.filled().is_empty() => {
+                self.finished = true;
+                Poll::Ready(None)
+            }
+            Poll::Ready(Ok(())) => {
+                let read = buf.filled().len();
+                chunk.truncate(read);
+                self.sent += read as u64;
+                Poll::Ready(Some(Ok(Frame::data(Bytes::from(chunk)))))
+            }
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.finished
+    }
+}
+
+impl Drop for ArchiveBody {
+    fn drop(&mut self) {
+        if let Some(on_end) = self.on_end.take() {
+            on_end(self.sent);
+        }
+    }
+}
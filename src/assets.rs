@@ -0,0 +1,223 @@
+use axum::body::Body;
+use axum::http::{HeaderMap, Response, StatusCode, header};
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use tracing::{debug, warn};
+
+/// A file of the admin UI, compiled in and optionally overridden from `ADMIN_ASSETS_DIR`.
+pub(crate) struct AdminAsset {
+    /// File name looked up inside the override directory
+    pub(crate) file_name: &'static str,
+    pub(crate) content_type: &'static str,
+    /// Cache-Control for the embedded copy
+    pub(crate) embedded_cache_control: Option<&'static str>,
+    pub(crate) embedded: &'static [u8],
+}
+
+pub(crate) const DASHBOARD_HTML: AdminAsset = AdminAsset {
+    file_name: "admin_dashboard.html",
+    content_type: "text/html; charset=utf-8",
+    embedded_cache_control: Some("no-cache"),
+    embedded: include_bytes!("../templates/admin_dashboard.html"),
+};
+
+pub(crate) const TAILWIND_JS: AdminAsset = AdminAsset {
+    file_name: "tailwind.js",
+    content_type: "application/javascript; charset=utf-8",
+    embedded_cache_control: Some("public, max-age=86400, immutable"),
+    embedded: include_bytes!("../templates/tailwind.js"),
+};
+
+pub(crate) const LOGO_WEBP: AdminAsset = AdminAsset {
+    file_name: "logo.webp",
+    content_type: "image/webp",
+    embedded_cache_control: Some("public, max-age=86400, immutable"),
+    embedded: include_bytes!("../templates/gitbridge.webp"),
+};
+
+pub(crate) const FAVICON_PNG: AdminAsset = AdminAsset {
+    file_name: "favicon.png",
+    content_type: "image/png",
+    embedded_cache_control: Some("public, max-age=86400, immutable"),
+    embedded: include_bytes!("../templates/favicon-64.png"),
+};
+
+/// Overridden files may change at any time, so clients must revalidate.
+const OVERRIDE_CACHE_CONTROL: &str = "public, max-age=60, must-revalidate";
+
+/// Loaded override contents together with the mtime they were read at.
+type CachedAsset = (SystemTime, Arc<Vec<u8>>);
+
+/// Serves admin assets from the override directory when present, re-reading
+/// a file only when its mtime changes.
+pub(crate) struct AssetOverrides {
+    dir: Option<PathBuf>,
+    cache: Mutex<HashMap<&'static str, CachedAsset>>,
+}
+
+impl AssetOverrides {
+    pub(crate) fn new(dir: Option<PathBuf>) -> Self {
+        Self {
+            dir,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Contents and mtime of the override for `asset`, if there is a usable one.
+    fn resolve(&self, asset: &AdminAsset) -> Option<(Arc<Vec<u8>>, SystemTime)> {
+        let dir = self.dir.as_deref()?;
+        let path = contained_path(dir, asset.file_name)?;
+        let mtime = fs::metadata(&path).and_then(|m| m.modified()).ok()?;
+
+        let mut cache = self.cache.lock().expect("asset cache poisoned");
+        if let Some((cached_mtime, data)) = cache.get(asset.file_name)
+            && *cached_mtime == mtime
+        {
+            return Some((data.clone(), mtime));
+        }
+
+        match fs::read(&path) {
+            Ok(data) => {
+                debug!(path = %path.display(), "loaded admin asset override");
+                let data = Arc::new(data);
+                cache.insert(asset.file_name, (mtime, data.clone()));
+                Some((data, mtime))
+            }
+            Err(e) => {
+                warn!(path = %path.display(), error = %e, "failed to read admin asset override, using embedded copy");
+                None
+            }
+        }
+    }
+
+    /// Build the response for `asset`, honoring `If-Modified-Since` for overrides.
+    pub(crate) fn response(&self, asset: &AdminAsset, req_headers: &HeaderMap) -> Response<Body> {
+        let Some((data, mtime)) = self.resolve(asset) else {
+            let mut builder = Response::builder()
+                .status(StatusCode::OK)
+                .header(header::CONTENT_TYPE, asset.content_type);
+            if let Some(cache_control) = asset.embedded_cache_control {
+                builder = builder.header(header::CACHE_CONTROL, cache_control);
+            }
+            return builder
+                .body(Body::from(asset.embedded))
+                .expect("embedded asset response");
+        };
+
+        let last_modified = http_date(mtime);
+        let not_modified = req_headers
+            .get(header::IF_MODIFIED_SINCE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| DateTime::parse_from_rfc2822(v).ok())
+            .map(|since| DateTime::<Utc>::from(mtime).timestamp() <= since.timestamp())
+            .unwrap_or(false);
+
+        let builder = Response::builder()
+            .header(header::CACHE_CONTROL, OVERRIDE_CACHE_CONTROL)
+            .header(header::LAST_MODIFIED, last_modified);
+        if not_modified {
+            return builder
+                .status(StatusCode::NOT_MODIFIED)
+                .body(Body::empty())
+                .expect("not modified response");
+        }
+        builder
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, asset.content_type)
+            .body(Body::from(data.as_ref().clone()))
+            .expect("asset override response")
+    }
+}
+
+/// `dir/file_name`, but only if it exists and (after resolving symlinks)
+/// still lies inside `dir`.
+fn contained_path(dir: &Path, file_name: &str) -> Option<PathBuf> {
+    let root = dir.canonicalize().ok()?;
+    let candidate = root.join(file_name).canonicalize().ok()?;
+    if candidate.starts_with(&root) && candidate.is_file() {
+        Some(candidate)
+    } else {
+        warn!(path = %candidate.display(), "admin asset override points outside ADMIN_ASSETS_DIR, ignoring");
+        None
+    }
+}
+
+fn http_date(t: SystemTime) -> String {
+    DateTime::<Utc>::from(t)
+        .format("%a, %d %b %Y %H:%M:%S GMT")
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::to_bytes;
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    async fn body_of(resp: Response<Body>) -> Vec<u8> {
+        to_bytes(resp.into_body(), usize::MAX)
+            .await
+            .unwrap()
+            .to_vec()
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_embedded_copy() {
+        let dir = TempDir::new().unwrap();
+        let overrides = AssetOverrides::new(Some(dir.path().to_path_buf()));
+        let resp = overrides.response(&LOGO_WEBP, &HeaderMap::new());
+        assert_eq!(
+            resp.headers()[header::CACHE_CONTROL],
+            "public, max-age=86400, immutable"
+        );
+        assert_eq!(body_of(resp).await, LOGO_WEBP.embedded);
+    }
+
+    #[tokio::test]
+    async fn serves_override_and_reloads_on_mtime_change() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("tailwind.js");
+        fs::write(&path, "v1").unwrap();
+        let overrides = AssetOverrides::new(Some(dir.path().to_path_buf()));
+
+        let resp = overrides.response(&TAILWIND_JS, &HeaderMap::new());
+        assert_eq!(
+            resp.headers()[header::CACHE_CONTROL],
+            OVERRIDE_CACHE_CONTROL
+        );
+        assert_eq!(body_of(resp).await, b"v1");
+
+        fs::write(&path, "v2").unwrap();
+        let later = SystemTime::now() + Duration::from_secs(5);
+        fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+        let resp = overrides.response(&TAILWIND_JS, &HeaderMap::new());
+        assert_eq!(body_of(resp).await, b"v2");
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::IF_MODIFIED_SINCE, http_date(later).parse().unwrap());
+        let resp = overrides.response(&TAILWIND_JS, &headers);
+        assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+    }
+
+    #[test]
+    fn symlinks_out_of_the_directory_are_ignored() {
+        let outside = TempDir::new().unwrap();
+        fs::write(outside.path().join("secret"), "nope").unwrap();
+        let dir = TempDir::new().unwrap();
+        std::os::unix::fs::symlink(outside.path().join("secret"), dir.path().join("logo.webp"))
+            .unwrap();
+
+        let overrides = AssetOverrides::new(Some(dir.path().to_path_buf()));
+        assert!(overrides.resolve(&LOGO_WEBP).is_none());
+    }
+}
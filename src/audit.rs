@@ -0,0 +1,73 @@
+use crate::config::Config;
+use crate::error::BridgeError;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/// An admin action that changed or removed data, or a known token refused
+/// for a git request.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct AuditEvent {
+    pub(crate) at: DateTime<Utc>,
+    /// What was done, e.g. `prune`
+    pub(crate) action: String,
+    pub(crate) project_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) detail: Option<String>,
+    /// Admin who did it: an admins file account, `admin`, or an OIDC identity
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) actor: Option<String>,
+}
+
+impl AuditEvent {
+    pub(crate) fn new(action: &str, project_id: &str, detail: Option<String>) -> Self {
+        Self {
+            at: Utc::now(),
+            action: action.to_string(),
+            project_id: project_id.to_string(),
+            detail,
+            actor: None,
+        }
+    }
+
+    /// The event as done by the admin `actor`.
+    pub(crate) fn by(mut self, actor: &str) -> Self {
+        self.actor = Some(actor.to_string());
+        self
+    }
+}
+
+/// Append-only log of admin actions (`audit.jsonl` under `git_root`).
+pub(crate) struct AuditLog {
+    path: PathBuf,
+    /// Held while appending so lines never interleave
+    lock: Mutex<()>,
+}
+
+impl AuditLog {
+    pub(crate) fn new(cfg: &Config) -> Self {
+        Self {
+            path: cfg.audit_log_file(),
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Append an event. Blocking.
+    pub(crate) fn append(&self, event: &AuditEvent) -> Result<(), BridgeError> {
+        let _guard = self.lock.lock().expect("audit lock poisoned");
+        let mut line = serde_json::to_string(event)?;
+        line.push('\n');
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?
+            .write_all(line.as_bytes())?;
+        Ok(())
+    }
+}
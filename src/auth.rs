@@ -1,65 +1,210 @@
 use crate::config::Config;
+use crate::deploy_tokens::DeployTokenCheck;
 use crate::error::BridgeError;
+use crate::project_id::ProjectId;
+use crate::token_store::Decision;
+use crate::webhook::fingerprint;
+use async_trait::async_trait;
 use axum::body::Body;
 use axum::http::{Request, header};
 use base64::Engine;
 use base64::engine::general_purpose::STANDARD as BASE64_STD;
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use dashmap::mapref::entry::Entry;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::fs;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, OpenOptions, TryLockError};
 use std::io::Write;
-use tracing::{trace, warn};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{info, trace, warn};
 use url::form_urlencoded;
 
-#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+/// On-disk format of `tokens.json`. Managed tokens are only stored as their
+/// [`token_hash`], which also keys the quotas, scopes and last uses.
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct TokensFile {
+    // Whether the keys below are token hashes; files written before tokens
+    // were hashed hold the tokens themselves and lack the field.
+    #[serde(default)]
+    pub hashed: bool,
     // Managed tokens (global / super rights) with description.
-    // { "<token-uuid>": "Description text", ... }
+    // { "<sha256 of the token, hex>": "Description text", ... }
     #[serde(default)]
     pub managed_tokens: HashMap<String, String>,
+    // Daily quotas of managed tokens; tokens without an entry are unlimited.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub quotas: HashMap<String, TokenQuota>,
+    // Projects a managed token is limited to; tokens without an entry may
+    // read every project.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub scopes: HashMap<String, Vec<String>>,
+    // Source a managed token is limited to (`default` for the one at
+    // `/git/<id>.git`); tokens without an entry may read every source.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub source_scopes: HashMap<String, String>,
+    // When a managed token last authorized a git request, to the minute.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub last_used: HashMap<String, DateTime<Utc>>,
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use axum::http::Request;
-
-    #[test]
-    fn extract_basic_token_username() {
-        let req = Request::builder()
-            .uri("/git/foo.git/info/refs")
-            .header(
-                header::AUTHORIZATION,
-                "Basic MDc3YjJlMzktYjM0NS00OTVlLWE1YWQtMWU3N2I4NTU3NTcwOmJsYQ==",
-            )
-            .body(Body::empty())
-            .unwrap();
-        let token = extract_token(&req);
-        assert_eq!(
-            token,
-            Some("077b2e39-b345-495e-a5ad-1e77b8557570".to_string())
-        );
+impl Default for TokensFile {
+    fn default() -> Self {
+        Self {
+            hashed: true,
+            managed_tokens: HashMap::new(),
+            quotas: HashMap::new(),
+            scopes: HashMap::new(),
+            source_scopes: HashMap::new(),
+            last_used: HashMap::new(),
+        }
     }
 }
 
-pub fn load_tokens_file(cfg: &Config) -> Result<TokensFile, BridgeError> {
-    let path = cfg.tokens_file();
-    match fs::read_to_string(&path) {
-        Ok(data) => {
-            let parsed: TokensFile = serde_json::from_str(&data)?;
-            Ok(parsed)
+impl TokensFile {
+    /// The hash under which `token` is stored, if it is a managed token.
+    /// Every stored hash is compared, in constant time, so neither the time
+    /// taken nor the position of a match depends on the token.
+    pub fn find_token(&self, token: &str) -> Option<&str> {
+        let presented = Sha256::digest(token.as_bytes());
+        self.managed_tokens.keys().fold(None, |found, id| {
+            let matches = hex::decode(id).is_ok_and(|stored| digests_eq(&stored, &presented));
+            if matches { Some(id.as_str()) } else { found }
+        })
+    }
+
+    /// Why this file cannot be stored, if it cannot: every token must be a
+    /// hash, quotas, scopes and last uses must belong to one of its tokens,
+    /// and project scopes must list valid project ids.
+    #[cfg(feature = "admin-ui")]
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        let is_hash =
+            |id: &str| id.len() == 64 && id.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'));
+        if let Some(id) = self.managed_tokens.keys().find(|id| !is_hash(id)) {
+            return Err(format!("managed_tokens: '{id}' is not a token hash"));
         }
-        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
-            // No file yet? Return empty default
-            Ok(TokensFile::default())
+        let keyed: [(&str, Vec<&String>); 4] = [
+            ("quotas", self.quotas.keys().collect()),
+            ("scopes", self.scopes.keys().collect()),
+            ("source_scopes", self.source_scopes.keys().collect()),
+            ("last_used", self.last_used.keys().collect()),
+        ];
+        for (field, ids) in keyed {
+            if let Some(id) = ids
+                .into_iter()
+                .find(|id| !self.managed_tokens.contains_key(*id))
+            {
+                return Err(format!("{field}: '{id}' is not in managed_tokens"));
+            }
+        }
+        for (id, projects) in &self.scopes {
+            if projects.is_empty() {
+                return Err(format!("scopes: '{id}' lists no projects"));
+            }
+            if let Some(project) = projects
+                .iter()
+                .find(|p| ProjectId::new(p.as_str()).is_err())
+            {
+                return Err(format!("scopes: '{project}' is not a valid project id"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Replace the tokens of a file written before tokens were hashed by
+    /// their hashes, keeping descriptions, quotas, scopes and last uses.
+    pub(crate) fn hash_tokens(&mut self) {
+        fn rekey<V>(map: &mut HashMap<String, V>) {
+            *map = map
+                .drain()
+                .map(|(token, value)| (token_hash(&token), value))
+                .collect();
         }
+        rekey(&mut self.managed_tokens);
+        rekey(&mut self.quotas);
+        rekey(&mut self.scopes);
+        rekey(&mut self.source_scopes);
+        rekey(&mut self.last_used);
+        self.hashed = true;
+    }
+}
+
+/// What `tokens.json` and `token-usage.json` store instead of a managed
+/// token: its SHA-256 digest in hex. It also identifies the token in the
+/// admin API.
+pub fn token_hash(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Daily traffic limits of a managed token. `None` means unlimited; days
+/// start at 00:00 UTC.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TokenQuota {
+    /// Request plus response bytes per day
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub daily_bytes: Option<u64>,
+    /// Git requests per day
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub daily_requests: Option<u64>,
+}
+
+impl TokenQuota {
+    pub fn is_unlimited(&self) -> bool {
+        self.daily_bytes.is_none() && self.daily_requests.is_none()
+    }
+}
+
+/// Read `tokens.json`; a missing file yields an empty [`TokensFile`]. A
+/// file still holding plain tokens is refused until
+/// [`crate::init_storage`] has replaced them by their hashes.
+pub fn load_tokens_file(cfg: &Config) -> Result<TokensFile, BridgeError> {
+    match read_tokens_file(cfg)? {
+        Some(parsed) if !parsed.hashed => Err(BridgeError::Other(
+            "tokens.json still holds plain tokens; init_storage replaces them by their hashes"
+                .into(),
+        )),
+        parsed => Ok(parsed.unwrap_or_default()),
+    }
+}
+
+/// `tokens.json` as it is on disk; `None` if there is no file yet.
+fn read_tokens_file(cfg: &Config) -> Result<Option<TokensFile>, BridgeError> {
+    match fs::read_to_string(cfg.tokens_file()) {
+        Ok(data) => Ok(Some(serde_json::from_str(&data)?)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
         Err(e) => Err(BridgeError::Other(format!("cannot read tokens.json: {e}"))),
     }
 }
 
+/// Rewrite a `tokens.json` still holding plain tokens, as written by older
+/// versions, with their hashes, and `token-usage.json` along with it. The
+/// caller holds the [`TokensFileLock`]. Blocking.
+pub(crate) fn hash_plain_tokens(cfg: &Config) -> Result<(), BridgeError> {
+    let Some(mut parsed) = read_tokens_file(cfg)? else {
+        return Ok(());
+    };
+    if parsed.hashed {
+        return Ok(());
+    }
+    parsed.hash_tokens();
+    save_tokens_file(cfg, &parsed)?;
+    crate::usage::hash_usage_tokens(cfg)?;
+    info!(
+        tokens = parsed.managed_tokens.len(),
+        "replaced the plain tokens in tokens.json by their hashes"
+    );
+    Ok(())
+}
+
+/// Write `tokens.json` via a temp file and rename.
 pub fn save_tokens_file(cfg: &Config, tf: &TokensFile) -> Result<(), BridgeError> {
     let serialized = serde_json::to_string_pretty(tf)?;
     let path = cfg.tokens_file();
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
     // Write atomically-ish: write to temp then rename.
     let tmp_path = path.with_extension("tmp");
     {
@@ -71,53 +216,558 @@ pub fn save_tokens_file(cfg: &Config, tf: &TokensFile) -> Result<(), BridgeError
     Ok(())
 }
 
-pub fn token_allowed_for_project(tf: &TokensFile, token: &str, _project_id: &str) -> bool {
-    tf.managed_tokens.contains_key(token)
+/// How long a change of `tokens.json` waits for another process changing it.
+pub(crate) const TOKENS_LOCK_WAIT: Duration = Duration::from_secs(10);
+
+/// How often a waiter retries the lock file held by another process.
+const TOKENS_LOCK_POLL: Duration = Duration::from_millis(20);
+
+/// Advisory lock on `.tokens.lock` next to `tokens.json`, held for a whole
+/// read-modify-write cycle so that other processes using the same
+/// `git_root` (a second bridge, the CLI) cannot interleave with it.
+/// Released on drop.
+pub(crate) struct TokensFileLock {
+    _file: fs::File,
 }
 
-/// Extract token from Authorization header (Basic or Bearer) or from `?token=...`
-pub fn extract_token(req: &Request<Body>) -> Option<String> {
-    // 1. Authorization header
-    if let Some(auth_val) = req.headers().get(header::AUTHORIZATION) {
-        if let Ok(auth_str) = auth_val.to_str() {
-            // Basic <base64(TOKEN:anything)>
-            if let Some(rest) = auth_str.strip_prefix("Basic ") {
-                if let Ok(decoded) = BASE64_STD.decode(rest.trim()) {
-                    if let Ok(decoded_str) = String::from_utf8(decoded) {
-                        // Split by first ':'
-                        if let Some((token, _rest)) = decoded_str.split_once(':') {
-                            return Some(token.to_string());
-                        } else {
-                            // No colon, treat entire thing as token
-                            return Some(decoded_str);
-                        }
-                    }
-                }
+impl TokensFileLock {
+    /// The lock if no other process holds it.
+    fn try_acquire(cfg: &Config) -> Result<Option<Self>, BridgeError> {
+        let path = cfg.tokens_lock_file();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .map_err(|e| BridgeError::Other(format!("cannot open {}: {e}", path.display())))?;
+        match file.try_lock() {
+            Ok(()) => Ok(Some(Self { _file: file })),
+            Err(TryLockError::WouldBlock) => Ok(None),
+            Err(TryLockError::Error(e)) => Err(BridgeError::Other(format!(
+                "cannot lock {}: {e}",
+                path.display()
+            ))),
+        }
+    }
+
+    fn timed_out(cfg: &Config, wait: Duration) -> BridgeError {
+        BridgeError::Other(format!(
+            "tokens.json is being changed by another process: {} still locked after {}s",
+            cfg.tokens_lock_file().display(),
+            wait.as_secs_f32()
+        ))
+    }
+
+    /// Wait for the lock for at most `wait`.
+    pub(crate) async fn acquire(cfg: &Config, wait: Duration) -> Result<Self, BridgeError> {
+        let deadline = Instant::now() + wait;
+        loop {
+            if let Some(lock) = Self::try_acquire(cfg)? {
+                return Ok(lock);
+            }
+            if Instant::now() >= deadline {
+                return Err(Self::timed_out(cfg, wait));
             }
-            // Bearer TOKEN
-            if let Some(rest) = auth_str.strip_prefix("Bearer ") {
-                return Some(rest.trim().to_string());
+            tokio::time::sleep(TOKENS_LOCK_POLL).await;
+        }
+    }
+
+    /// [`TokensFileLock::acquire`] for callers outside the runtime.
+    pub(crate) fn acquire_blocking(cfg: &Config, wait: Duration) -> Result<Self, BridgeError> {
+        let deadline = Instant::now() + wait;
+        loop {
+            if let Some(lock) = Self::try_acquire(cfg)? {
+                return Ok(lock);
+            }
+            if Instant::now() >= deadline {
+                return Err(Self::timed_out(cfg, wait));
             }
+            std::thread::sleep(TOKENS_LOCK_POLL);
+        }
+    }
+}
+
+/// Whether `token` may read `project_id`. Managed tokens are global unless
+/// they have a scope.
+///
+/// ```
+/// use sharelatex_gitbridge_ro::{TokensFile, token_allowed_for_project, token_hash};
+///
+/// let mut tf = TokensFile::default();
+/// tf.managed_tokens.insert(token_hash("secret"), "CI mirror".into());
+/// assert!(token_allowed_for_project(&tf, "secret", "64a1f0c2e4b0a1b2c3d4e5f6"));
+/// assert!(!token_allowed_for_project(&tf, "other", "64a1f0c2e4b0a1b2c3d4e5f6"));
+///
+/// tf.scopes.insert(token_hash("secret"), vec!["64a1f0c2e4b0a1b2c3d4e5f6".into()]);
+/// assert!(token_allowed_for_project(&tf, "secret", "64a1f0c2e4b0a1b2c3d4e5f6"));
+/// assert!(!token_allowed_for_project(&tf, "secret", "64a1f0c2e4b0a1b2c3d4e5f7"));
+/// ```
+pub fn token_allowed_for_project(tf: &TokensFile, token: &str, project_id: &str) -> bool {
+    tf.find_token(token)
+        .is_some_and(|id| scope_allows(tf, id, project_id))
+}
+
+/// Whether the scope of the managed token stored as `id` includes
+/// `project_id`; tokens without a scope may read every project.
+pub(crate) fn scope_allows(tf: &TokensFile, id: &str, project_id: &str) -> bool {
+    tf.scopes
+        .get(id)
+        .is_none_or(|projects| projects.iter().any(|p| p == project_id))
+}
+
+/// Tokens a request may be carrying, in the order they should be tried:
+///
+/// 1. `Authorization: Basic`: the user name, then the password. Git clients
+///    put the token in either field (`<token>:x`, `x-token-auth:<token>`,
+///    `git:<token>`); a value without `:` is taken whole. Empty fields are
+///    skipped.
+/// 2. `Authorization: Bearer <token>`.
+/// 3. The `?token=...` query parameter.
+///
+/// Only the first candidate that authenticates counts; the other fields
+/// are never checked against anything.
+pub fn extract_tokens(req: &Request<Body>) -> Vec<String> {
+    let mut tokens = Vec::new();
+    // 1. Authorization header
+    if let Some(auth_val) = req.headers().get(header::AUTHORIZATION)
+        && let Ok(auth_str) = auth_val.to_str()
+    {
+        // Basic <base64(user:password)>
+        if let Some(rest) = auth_str.strip_prefix("Basic ")
+            && let Ok(decoded) = BASE64_STD.decode(rest.trim())
+            && let Ok(decoded_str) = String::from_utf8(decoded)
+        {
+            match decoded_str.split_once(':') {
+                Some((user, password)) => tokens.extend([user, password].map(str::to_string)),
+                // No colon, treat entire thing as token
+                None => tokens.push(decoded_str),
+            }
+        }
+        // Bearer TOKEN
+        if let Some(rest) = auth_str.strip_prefix("Bearer ") {
+            tokens.push(rest.trim().to_string());
         }
     }
 
     // 2. Query param ?token=...
-    if let Some(q) = req.uri().query() {
-        for (k, v) in form_urlencoded::parse(q.as_bytes()) {
-            if k == "token" {
-                return Some(v.into_owned());
+    if let Some(q) = req.uri().query()
+        && let Some((_, v)) = form_urlencoded::parse(q.as_bytes()).find(|(k, _)| k == "token")
+    {
+        tokens.push(v.into_owned());
+    }
+
+    tokens.retain(|t| !t.is_empty());
+    let mut seen = std::collections::HashSet::new();
+    tokens.retain(|t| seen.insert(t.clone()));
+    tokens
+}
+
+/// The first token a request carries; see [`extract_tokens`].
+pub fn extract_token(req: &Request<Body>) -> Option<String> {
+    extract_tokens(req).into_iter().next()
+}
+
+/// Compare two secrets without leaking where they differ. Both sides are
+/// hashed first so their lengths do not matter either.
+pub(crate) fn constant_time_eq(a: &str, b: &str) -> bool {
+    let (a, b) = (Sha256::digest(a.as_bytes()), Sha256::digest(b.as_bytes()));
+    digests_eq(&a, &b)
+}
+
+/// Compare two digests without leaking where they differ.
+fn digests_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Outcome of checking a git request's credentials against one project.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum AuthDecision {
+    /// A managed token from the token store
+    Managed(String),
+    /// The project's own `.gitbridge` token
+    ProjectToken(String),
+    /// The project's deploy token
+    DeployToken(String),
+    /// No valid token, but the project is public
+    Public,
+    /// No token at all, and the project is not public
+    NoCredentials,
+    /// None of the tokens is known
+    UnknownToken,
+    /// A deploy token of the project past its validity
+    TokenExpired(String),
+    /// A managed token scoped to other projects
+    NotAllowedForProject(String),
+    /// The project has a `.gitbridge` token and no token matches it
+    ProjectTokenMismatch,
+}
+
+impl AuthDecision {
+    pub(crate) fn is_granted(&self) -> bool {
+        matches!(
+            self,
+            Self::Managed(_) | Self::ProjectToken(_) | Self::DeployToken(_) | Self::Public
+        )
+    }
+
+    /// The managed token that granted access; only these have quotas.
+    pub(crate) fn managed_token(&self) -> Option<&str> {
+        match self {
+            Self::Managed(token) => Some(token),
+            _ => None,
+        }
+    }
+
+    /// Stable name for logs and counters.
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            Self::Managed(_) => "managed",
+            Self::ProjectToken(_) => "project_token",
+            Self::DeployToken(_) => "deploy_token",
+            Self::Public => "public",
+            Self::NoCredentials => "no_credentials",
+            Self::UnknownToken => "unknown_token",
+            Self::TokenExpired(_) => "token_expired",
+            Self::NotAllowedForProject(_) => "not_allowed_for_project",
+            Self::ProjectTokenMismatch => "project_token_mismatch",
+        }
+    }
+
+    /// What a refused client is told. A token that is simply wrong gets the
+    /// same answer whether or not the project has a `.gitbridge` token.
+    pub(crate) fn message(&self) -> &'static str {
+        match self {
+            Self::NoCredentials => "credentials required",
+            Self::TokenExpired(_) => "token expired",
+            Self::NotAllowedForProject(_) => "token not valid for this project",
+            Self::UnknownToken | Self::ProjectTokenMismatch => "invalid token",
+            _ => "access granted",
+        }
+    }
+
+    /// A known token used where it is not valid, worth an audit entry.
+    #[cfg(feature = "admin-ui")]
+    pub(crate) fn misused_token(&self) -> Option<&str> {
+        match self {
+            Self::TokenExpired(token) | Self::NotAllowedForProject(token) => Some(token),
+            _ => None,
+        }
+    }
+
+    /// How much a denial says about the caller; across several tokens the
+    /// most telling one is reported.
+    fn rank(&self) -> u8 {
+        match self {
+            Self::NotAllowedForProject(_) => 3,
+            Self::TokenExpired(_) => 2,
+            Self::ProjectTokenMismatch => 1,
+            _ => 0,
+        }
+    }
+}
+
+/// Where [`authorize`] looks tokens up. Each lookup happens only when the
+/// decision needs it.
+#[async_trait]
+pub(crate) trait AuthSources: Send + Sync {
+    async fn managed_token(
+        &self,
+        token: &str,
+        project_id: &ProjectId,
+    ) -> Result<Decision, BridgeError>;
+
+    /// The project's `.gitbridge` token, if it has one
+    async fn project_token(&self, project_id: &ProjectId) -> Option<String>;
+
+    async fn deploy_token(
+        &self,
+        project_id: &ProjectId,
+        token: &str,
+    ) -> Result<DeployTokenCheck, BridgeError>;
+
+    async fn is_public(&self, project_id: &ProjectId) -> Result<bool, BridgeError>;
+}
+
+/// Decide whether a request carrying `tokens` (see [`extract_tokens`]) may
+/// read `project_id`. Each token is tried, in order, as a managed token, the
+/// project's `.gitbridge` token and its deploy token; the first match
+/// grants access. Without a match, public projects are still readable.
+pub(crate) async fn authorize(
+    sources: &dyn AuthSources,
+    tokens: &[String],
+    project_id: &ProjectId,
+) -> Result<AuthDecision, BridgeError> {
+    let mut denial = if tokens.is_empty() {
+        AuthDecision::NoCredentials
+    } else {
+        AuthDecision::UnknownToken
+    };
+    let mut project_token = None;
+    for token in tokens {
+        let mut note = |decision: AuthDecision| {
+            if decision.rank() > denial.rank() {
+                denial = decision;
             }
+        };
+        match sources.managed_token(token, project_id).await? {
+            Decision::Allowed => return Ok(AuthDecision::Managed(token.clone())),
+            Decision::NotAllowedForProject => {
+                note(AuthDecision::NotAllowedForProject(token.clone()))
+            }
+            Decision::UnknownToken => {}
+        }
+        if project_token.is_none() {
+            project_token = Some(sources.project_token(project_id).await);
+        }
+        if let Some(Some(expected)) = &project_token {
+            if constant_time_eq(expected, token) {
+                return Ok(AuthDecision::ProjectToken(token.clone()));
+            }
+            note(AuthDecision::ProjectTokenMismatch);
+        }
+        match sources.deploy_token(project_id, token).await? {
+            DeployTokenCheck::Valid => return Ok(AuthDecision::DeployToken(token.clone())),
+            DeployTokenCheck::Expired => note(AuthDecision::TokenExpired(token.clone())),
+            DeployTokenCheck::Unknown => {}
         }
     }
+    if sources.is_public(project_id).await? {
+        return Ok(AuthDecision::Public);
+    }
+    Ok(denial)
+}
 
-    // nothing
-    None
+/// How often each [`AuthDecision`] was reached since startup.
+#[derive(Debug, Default)]
+pub(crate) struct AuthStats {
+    counts: Mutex<BTreeMap<&'static str, u64>>,
 }
 
-/// Quick helper for logging failed auth attempts
-pub fn log_auth_failure(token_opt: &Option<String>, project_id: &str) {
-    match token_opt {
-        Some(token) => warn!(token, project = project_id, "auth failed"),
-        None => trace!(project = project_id, "auth failed without credentials"),
+impl AuthStats {
+    pub(crate) fn record(&self, decision: &AuthDecision) {
+        let mut counts = self.counts.lock().expect("auth stats poisoned");
+        *counts.entry(decision.as_str()).or_default() += 1;
+    }
+
+    #[cfg(feature = "admin-ui")]
+    pub(crate) fn snapshot(&self) -> BTreeMap<&'static str, u64> {
+        self.counts.lock().expect("auth stats poisoned").clone()
+    }
+}
+
+/// Minimum time between two writes of a managed token's last use.
+pub(crate) const TOKEN_USE_RECORD_INTERVAL: Duration = Duration::from_secs(60);
+
+/// When the last use of each managed token was written, so busy tokens
+/// cause at most one write of `tokens.json` per [`TOKEN_USE_RECORD_INTERVAL`].
+#[derive(Default)]
+pub(crate) struct TokenUseTracker {
+    /// By token id
+    written: DashMap<String, Instant>,
+}
+
+impl TokenUseTracker {
+    /// Whether a use of `token` happening now should be written; if so, it
+    /// counts as written.
+    pub(crate) fn due(&self, token: &str) -> bool {
+        let now = Instant::now();
+        match self.written.entry(token.to_string()) {
+            Entry::Occupied(mut entry) => {
+                if now.duration_since(*entry.get()) < TOKEN_USE_RECORD_INTERVAL {
+                    return false;
+                }
+                entry.insert(now);
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(now);
+            }
+        }
+        true
+    }
+}
+
+/// Log a refused git request. The token may be a valid one used where it
+/// is not allowed, so only its fingerprint is logged, as in the audit log.
+pub(crate) fn log_auth_failure(decision: &AuthDecision, tokens: &[String], project_id: &str) {
+    // only the first candidate is logged; a later one may be a password
+    match tokens {
+        [] => trace!(project = project_id, "auth failed without credentials"),
+        [token, ..] => warn!(
+            token = %fingerprint(token),
+            candidates = tokens.len(),
+            project = project_id,
+            reason = decision.as_str(),
+            "auth failed"
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::Request;
+
+    #[test]
+    fn extract_basic_token_username() {
+        let req = Request::builder()
+            .uri("/git/foo.git/info/refs")
+            .header(
+                header::AUTHORIZATION,
+                "Basic MDc3YjJlMzktYjM0NS00OTVlLWE1YWQtMWU3N2I4NTU3NTcwOmJsYQ==",
+            )
+            .body(Body::empty())
+            .unwrap();
+        let token = extract_token(&req);
+        assert_eq!(
+            token,
+            Some("077b2e39-b345-495e-a5ad-1e77b8557570".to_string())
+        );
+    }
+
+    fn tokens_of(authorization: Option<&str>, uri: &str) -> Vec<String> {
+        let mut req = Request::builder().uri(uri);
+        if let Some(value) = authorization {
+            req = req.header(header::AUTHORIZATION, value);
+        }
+        extract_tokens(&req.body(Body::empty()).unwrap())
+    }
+
+    fn basic(credentials: &str) -> String {
+        format!("Basic {}", BASE64_STD.encode(credentials))
+    }
+
+    #[test]
+    fn basic_auth_yields_user_name_then_password() {
+        let uri = "/git/p1.git/info/refs";
+        // token as user name, as in https://<token>@host/...
+        assert_eq!(tokens_of(Some(&basic("tok:")), uri), ["tok"]);
+        assert_eq!(tokens_of(Some(&basic("tok:x")), uri), ["tok", "x"]);
+        // token as password, the Bitbucket and GitHub style conventions
+        assert_eq!(
+            tokens_of(Some(&basic("x-token-auth:tok")), uri),
+            ["x-token-auth", "tok"]
+        );
+        assert_eq!(tokens_of(Some(&basic("git:tok")), uri), ["git", "tok"]);
+        assert_eq!(tokens_of(Some(&basic(":tok")), uri), ["tok"]);
+        assert_eq!(tokens_of(Some(&basic("tok:tok")), uri), ["tok"]);
+        // only the first colon separates, passwords may contain more
+        assert_eq!(tokens_of(Some(&basic("u:a:b")), uri), ["u", "a:b"]);
+        assert_eq!(tokens_of(Some(&basic("tok")), uri), ["tok"]);
+    }
+
+    #[test]
+    fn header_tokens_come_before_the_query_token() {
+        assert_eq!(
+            tokens_of(Some("Bearer tok "), "/git/p1.git?token=q"),
+            ["tok", "q"]
+        );
+        assert_eq!(
+            tokens_of(Some(&basic("git:tok")), "/git/p1.git?token=q"),
+            ["git", "tok", "q"]
+        );
+        assert_eq!(tokens_of(None, "/git/p1.git?a=1&token=q"), ["q"]);
+        assert!(tokens_of(Some("Basic !!!"), "/git/p1.git").is_empty());
+        assert_eq!(extract_token(&Request::new(Body::empty())), None);
+    }
+
+    /// Fixed answers for [`authorize`]: managed token `m` may read `p1`
+    /// only, `p1` has the `.gitbridge` token `proj` and the deploy tokens
+    /// `dep` (valid) and `old` (expired), and `pub` is public.
+    struct FakeSources;
+
+    #[async_trait]
+    impl AuthSources for FakeSources {
+        async fn managed_token(
+            &self,
+            token: &str,
+            project_id: &ProjectId,
+        ) -> Result<Decision, BridgeError> {
+            Ok(match (token, project_id.as_str()) {
+                ("m", "p1") => Decision::Allowed,
+                ("m", _) => Decision::NotAllowedForProject,
+                _ => Decision::UnknownToken,
+            })
+        }
+
+        async fn project_token(&self, project_id: &ProjectId) -> Option<String> {
+            (project_id.as_str() == "p1").then(|| "proj".to_string())
+        }
+
+        async fn deploy_token(
+            &self,
+            project_id: &ProjectId,
+            token: &str,
+        ) -> Result<DeployTokenCheck, BridgeError> {
+            Ok(match (project_id.as_str(), token) {
+                ("p1", "dep") => DeployTokenCheck::Valid,
+                ("p1", "old") => DeployTokenCheck::Expired,
+                _ => DeployTokenCheck::Unknown,
+            })
+        }
+
+        async fn is_public(&self, project_id: &ProjectId) -> Result<bool, BridgeError> {
+            Ok(project_id.as_str() == "pub")
+        }
+    }
+
+    async fn decide(tokens: &[&str], project_id: &str) -> AuthDecision {
+        let tokens: Vec<String> = tokens.iter().map(|t| t.to_string()).collect();
+        let project_id = project_id.parse().unwrap();
+        authorize(&FakeSources, &tokens, &project_id).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn every_auth_decision_is_reachable() {
+        use AuthDecision::*;
+        assert_eq!(decide(&["m"], "p1").await, Managed("m".into()));
+        assert_eq!(decide(&["proj"], "p1").await, ProjectToken("proj".into()));
+        assert_eq!(decide(&["dep"], "p1").await, DeployToken("dep".into()));
+        assert_eq!(decide(&[], "pub").await, Public);
+        assert_eq!(decide(&["m"], "pub").await, Public);
+        assert_eq!(decide(&[], "p2").await, NoCredentials);
+        assert_eq!(decide(&["x"], "p2").await, UnknownToken);
+        assert_eq!(decide(&["old"], "p1").await, TokenExpired("old".into()));
+        assert_eq!(decide(&["m"], "p2").await, NotAllowedForProject("m".into()));
+        assert_eq!(decide(&["x"], "p1").await, ProjectTokenMismatch);
+
+        // any match wins; otherwise the most telling denial is reported
+        assert_eq!(decide(&["x", "dep"], "p1").await, DeployToken("dep".into()));
+        assert_eq!(
+            decide(&["x", "old", "y"], "p1").await,
+            TokenExpired("old".into())
+        );
+
+        for granted in [Managed("m".into()), Public] {
+            assert!(granted.is_granted());
+        }
+        for denied in [NoCredentials, UnknownToken, ProjectTokenMismatch] {
+            assert!(!denied.is_granted());
+        }
+        assert_eq!(UnknownToken.message(), ProjectTokenMismatch.message());
+        assert_eq!(Managed("m".into()).managed_token(), Some("m"));
+        assert_eq!(ProjectToken("proj".into()).managed_token(), None);
+
+        let stats = AuthStats::default();
+        stats.record(&UnknownToken);
+        stats.record(&UnknownToken);
+        stats.record(&Public);
+        let counts = stats.counts.lock().unwrap();
+        assert_eq!(counts["unknown_token"], 2);
+        assert_eq!(counts["public"], 1);
+    }
+
+    #[test]
+    fn token_uses_are_written_once_per_interval() {
+        let uses = TokenUseTracker::default();
+        assert!(uses.due("a"));
+        assert!(!uses.due("a"));
+        assert!(uses.due("b"));
+        uses.written
+            .insert("a".into(), Instant::now() - TOKEN_USE_RECORD_INTERVAL);
+        assert!(uses.due("a"));
+        assert!(!uses.due("a"));
     }
 }
@@ -0,0 +1,492 @@
+use crate::AppState;
+use crate::config::Config;
+use crate::error::BridgeError;
+use crate::git::GitRunner;
+use crate::metadata::load_metadata;
+use crate::project_id::ProjectId;
+use crate::repo::list_mirrors;
+use chrono::{DateTime, Utc};
+use hex::encode as hex_encode;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{error, info, warn};
+use walkdir::WalkDir;
+
+/// Prefix of backup directory names; the rest is the UTC start time.
+const BACKUP_PREFIX: &str = "gitbridge-backup-";
+
+/// Name of the manifest written into every backup.
+const MANIFEST_FILE: &str = "manifest.json";
+
+/// One file of a backup.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct ManifestEntry {
+    /// Relative to the backup directory, `/`-separated
+    pub(crate) path: String,
+    pub(crate) bytes: u64,
+    pub(crate) sha256: String,
+}
+
+/// Contents of `manifest.json`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Manifest {
+    pub(crate) started_at: DateTime<Utc>,
+    pub(crate) finished_at: DateTime<Utc>,
+    /// Mirrors backed up, of all sources
+    pub(crate) projects: usize,
+    /// Mirrors of each named source, backed up under `sources/<name>/`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub(crate) sources: BTreeMap<String, usize>,
+    pub(crate) files: Vec<ManifestEntry>,
+    /// Projects that could not be backed up completely
+    pub(crate) errors: Vec<String>,
+}
+
+/// Summary of a backup run as reported by the admin API.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct BackupReport {
+    pub(crate) started_at: DateTime<Utc>,
+    pub(crate) finished_at: DateTime<Utc>,
+    pub(crate) ok: bool,
+    /// Finished backup directory; `None` when the run failed before it was written
+    pub(crate) path: Option<String>,
+    pub(crate) projects: usize,
+    pub(crate) files: usize,
+    pub(crate) bytes: u64,
+    pub(crate) errors: Vec<String>,
+}
+
+/// Whether a backup is running and how the last one went.
+#[derive(Default)]
+pub(crate) struct BackupState {
+    running: AtomicBool,
+    last: Mutex<Option<BackupReport>>,
+}
+
+/// Marks a backup as running until dropped.
+pub(crate) struct RunningBackup(Arc<BackupState>);
+
+impl Drop for RunningBackup {
+    fn drop(&mut self) {
+        self.0.running.store(false, Ordering::SeqCst);
+    }
+}
+
+impl BackupState {
+    /// Claim the backup slot; `None` while another backup is running.
+    pub(crate) fn try_start(self: &Arc<Self>) -> Option<RunningBackup> {
+        (!self.running.swap(true, Ordering::SeqCst)).then(|| RunningBackup(self.clone()))
+    }
+
+    #[cfg(feature = "admin-ui")]
+    pub(crate) fn is_running(&self) -> bool {
+        self.running.load(Ordering::SeqCst)
+    }
+
+    #[cfg(feature = "admin-ui")]
+    pub(crate) fn last(&self) -> Option<BackupReport> {
+        self.last.lock().expect("backup state poisoned").clone()
+    }
+}
+
+fn blocking_error(e: tokio::task::JoinError) -> BridgeError {
+    BridgeError::Other(format!("join error: {e}"))
+}
+
+/// Copy `from` to `to` if it exists.
+fn copy_if_exists(from: &Path, to: &Path) -> Result<(), BridgeError> {
+    match fs::copy(from, to) {
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(BridgeError::Io(e)),
+    }
+}
+
+/// Copy the instance-wide state files into `dir`. Blocking.
+fn backup_state_files(cfg: &Config, dir: &Path) -> Result<(), BridgeError> {
+    fs::create_dir_all(dir.join("metadata"))?;
+    copy_if_exists(&cfg.tokens_file(), &dir.join("tokens.json"))?;
+    copy_if_exists(
+        &cfg.project_settings_file(),
+        &dir.join("project-settings.json"),
+    )?;
+    copy_if_exists(&cfg.activity_log_file(), &dir.join("activity.jsonl"))?;
+    copy_if_exists(&cfg.token_usage_file(), &dir.join("token-usage.json"))?;
+    copy_if_exists(&cfg.deploy_tokens_file(), &dir.join("deploy-tokens.json"))?;
+    copy_if_exists(&cfg.audit_log_file(), &dir.join("audit.jsonl"))?;
+    Ok(())
+}
+
+/// Save the sync metadata and, if enabled, a bundle of one mirror. Callers
+/// must hold the project lock. Blocking.
+fn backup_project(
+    git: &dyn GitRunner,
+    cfg: &Config,
+    project_id: &ProjectId,
+    dir: &Path,
+) -> Result<(), BridgeError> {
+    let meta = serde_json::to_string_pretty(&load_metadata(cfg, project_id))?;
+    fs::write(
+        dir.join("metadata").join(format!("{project_id}.json")),
+        meta,
+    )?;
+
+    if cfg.backup_bundles {
+        let bundles = dir.join("bundles");
+        fs::create_dir_all(&bundles)?;
+        let bundle = bundles.join(format!("{project_id}.bundle"));
+        let bundle = bundle.to_string_lossy();
+        let args = ["bundle", "create", bundle.as_ref(), "--all"];
+        let out = git.run(&args, &cfg.bare_repo_dir(project_id)?)?;
+        if !out.status.success() {
+            return Err(BridgeError::GitFailed(
+                "git bundle create".to_string(),
+                String::from_utf8_lossy(&out.stderr).trim().to_string(),
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn sha256_file(path: &Path) -> Result<String, BridgeError> {
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut fs::File::open(path)?, &mut hasher)?;
+    Ok(hex_encode(hasher.finalize()))
+}
+
+/// Checksums of every file below `dir`, sorted by path. Blocking.
+fn manifest_entries(dir: &Path) -> Result<Vec<ManifestEntry>, BridgeError> {
+    let mut entries = Vec::new();
+    for entry in WalkDir::new(dir).sort_by_file_name() {
+        let entry =
+            entry.map_err(|e| BridgeError::Other(format!("walk {}: {e}", dir.display())))?;
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let relative = entry.path().strip_prefix(dir).unwrap_or(entry.path());
+        entries.push(ManifestEntry {
+            path: relative
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/"),
+            bytes: entry.metadata().map(|m| m.len()).unwrap_or(0),
+            sha256: sha256_file(entry.path())?,
+        });
+    }
+    Ok(entries)
+}
+
+/// Remove all but the newest `keep` backups and leftovers of interrupted
+/// runs. Returns the removed directory names. Blocking.
+fn prune_backups(backup_dir: &Path, keep: usize) -> Result<Vec<String>, BridgeError> {
+    let mut backups = Vec::new();
+    let mut removed = Vec::new();
+    for entry in fs::read_dir(backup_dir)?.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if !entry.file_type().map(|ft| ft.is_dir()).unwrap_or(false) {
+            continue;
+        }
+        if name.starts_with(BACKUP_PREFIX) {
+            backups.push(name);
+        } else if name.starts_with(&format!(".{BACKUP_PREFIX}")) && name.ends_with(".partial") {
+            fs::remove_dir_all(entry.path())?;
+            removed.push(name);
+        }
+    }
+    backups.sort();
+    let excess = backups.len().saturating_sub(keep);
+    for name in backups.into_iter().take(excess) {
+        fs::remove_dir_all(backup_dir.join(&name))?;
+        removed.push(name);
+    }
+    Ok(removed)
+}
+
+/// Back up the state files and mirrors of the source served by `state`
+/// into `dir`. Returns the number of mirrors; projects that could not be
+/// backed up completely are added to `errors`.
+async fn backup_source(
+    state: &AppState,
+    git: &Arc<dyn GitRunner>,
+    dir: &Path,
+    errors: &mut Vec<String>,
+) -> Result<usize, BridgeError> {
+    let mirrors = {
+        let cfg = state.cfg.clone();
+        let dir = dir.to_path_buf();
+        tokio::task::spawn_blocking(move || {
+            backup_state_files(&cfg, &dir)?;
+            list_mirrors(&cfg)
+        })
+        .await
+        .map_err(blocking_error)??
+    };
+
+    for project_id in &mirrors {
+        let _guard = state.locks.lock(project_id, "backup").await;
+        let git = git.clone();
+        let cfg = state.cfg.clone();
+        let dir = dir.to_path_buf();
+        let id = project_id.clone();
+        let result =
+            tokio::task::spawn_blocking(move || backup_project(git.as_ref(), &cfg, &id, &dir))
+                .await
+                .map_err(blocking_error)
+                .and_then(|r| r);
+        if let Err(e) = result {
+            warn!(source = state.cfg.source_label(), %project_id, "backup of project incomplete: {e}");
+            errors.push(format!("{project_id}: {e}"));
+        }
+    }
+    Ok(mirrors.len())
+}
+
+/// Write one backup into a temporary directory, then move it into place
+/// and apply the retention.
+async fn write_backup(
+    state: &AppState,
+    git: Arc<dyn GitRunner>,
+    started_at: DateTime<Utc>,
+) -> Result<BackupReport, BridgeError> {
+    let backup_dir = state
+        .cfg
+        .backup_dir
+        .clone()
+        .ok_or_else(|| BridgeError::Other("BACKUP_DIR is not set".to_string()))?;
+    let name = format!("{BACKUP_PREFIX}{}", started_at.format("%Y%m%dT%H%M%S%.3fZ"));
+    let partial: PathBuf = backup_dir.join(format!(".{name}.partial"));
+
+    let mut errors = Vec::new();
+    let mut projects = backup_source(state, &git, &partial, &mut errors).await?;
+    let mut sources = BTreeMap::new();
+    for (name, source) in &state.sources {
+        let dir = partial.join("sources").join(name);
+        let mut source_errors = Vec::new();
+        let mirrors = backup_source(source, &git, &dir, &mut source_errors).await?;
+        errors.extend(source_errors.into_iter().map(|e| format!("{name}/{e}")));
+        sources.insert(name.clone(), mirrors);
+        projects += mirrors;
+    }
+
+    let retention = state.cfg.backup_retention;
+    tokio::task::spawn_blocking(move || {
+        let files = manifest_entries(&partial)?;
+        let manifest = Manifest {
+            started_at,
+            finished_at: Utc::now(),
+            projects,
+            sources,
+            files,
+            errors,
+        };
+        fs::write(
+            partial.join(MANIFEST_FILE),
+            serde_json::to_string_pretty(&manifest)?,
+        )?;
+        let target = backup_dir.join(&name);
+        fs::rename(&partial, &target)?;
+        for removed in prune_backups(&backup_dir, retention)? {
+            info!(backup = %removed, "removed old backup");
+        }
+        Ok(BackupReport {
+            started_at,
+            finished_at: manifest.finished_at,
+            ok: manifest.errors.is_empty(),
+            path: Some(target.display().to_string()),
+            projects: manifest.projects,
+            files: manifest.files.len() + 1,
+            bytes: manifest.files.iter().map(|f| f.bytes).sum(),
+            errors: manifest.errors,
+        })
+    })
+    .await
+    .map_err(blocking_error)?
+}
+
+/// Run a backup and remember its report as the last run.
+pub(crate) async fn run_backup(
+    state: &AppState,
+    git: Arc<dyn GitRunner>,
+    running: RunningBackup,
+) -> BackupReport {
+    let _in_flight = state.maintenance.begin();
+    let started_at = Utc::now();
+    let report = match write_backup(state, git, started_at).await {
+        Ok(report) => {
+            info!(
+                path = ?report.path,
+                projects = report.projects,
+                errors = report.errors.len(),
+                "backup finished"
+            );
+            report
+        }
+        Err(e) => {
+            error!("backup failed: {e}");
+            BackupReport {
+                started_at,
+                finished_at: Utc::now(),
+                ok: false,
+                path: None,
+                projects: 0,
+                files: 0,
+                bytes: 0,
+                errors: vec![e.to_string()],
+            }
+        }
+    };
+    *running.0.last.lock().expect("backup state poisoned") = Some(report.clone());
+    report
+}
+
+/// Background task running a backup every `interval`.
+pub(crate) async fn run_periodically(
+    state: Arc<AppState>,
+    git: Arc<dyn GitRunner>,
+    interval: Duration,
+) {
+    loop {
+        tokio::time::sleep(interval).await;
+        if state.maintenance.is_enabled() {
+            info!("skipping scheduled backup in maintenance mode");
+            continue;
+        }
+        match state.backups.try_start() {
+            Some(running) => {
+                run_backup(&state, git.clone(), running).await;
+            }
+            None => warn!("skipping scheduled backup, another backup is still running"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{NamedSource, ProjectsLayout};
+    use crate::git::mock::ScriptedGit;
+    use crate::project_id::pid;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn backup_contains_state_and_checksummed_manifest() {
+        let root = TempDir::new().unwrap();
+        let mut cfg = Config::for_test(root.path());
+        cfg.backup_dir = Some(root.path().join("backups"));
+        fs::create_dir_all(cfg.bare_repo_dir(&pid("p1")).unwrap()).unwrap();
+        fs::create_dir_all(cfg.state_dir()).unwrap();
+        fs::write(cfg.tokens_file(), "{\"tokens\":{}}").unwrap();
+        let state = AppState::new(cfg.clone());
+        let git = Arc::new(ScriptedGit::new());
+
+        let running = state.backups.try_start().unwrap();
+        assert!(state.backups.try_start().is_none());
+        let report = run_backup(&state, git.clone(), running).await;
+        assert!(report.ok, "{:?}", report.errors);
+        #[cfg(feature = "admin-ui")]
+        {
+            assert!(!state.backups.is_running());
+            assert_eq!(state.backups.last().unwrap().path, report.path);
+        }
+
+        let dir = PathBuf::from(report.path.unwrap());
+        let manifest: Manifest =
+            serde_json::from_str(&fs::read_to_string(dir.join(MANIFEST_FILE)).unwrap()).unwrap();
+        let paths: Vec<&str> = manifest.files.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(paths, ["metadata/p1.json", "tokens.json"]);
+        assert_eq!(
+            manifest.files[1].sha256,
+            hex_encode(Sha256::digest(b"{\"tokens\":{}}"))
+        );
+
+        let calls = git.calls();
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0][..2], ["bundle", "create"]);
+        assert_eq!(calls[0][3], "--all");
+    }
+
+    #[tokio::test]
+    async fn named_sources_are_backed_up_in_their_own_directory() {
+        let root = TempDir::new().unwrap();
+        let mut cfg = Config::for_test(root.path());
+        cfg.backup_dir = Some(root.path().join("backups"));
+        cfg.sources = vec![NamedSource {
+            name: "prod".into(),
+            sharelatex_data_path: root.path().join("prod"),
+            projects_dir: "projects".into(),
+            projects_layout: ProjectsLayout::Flat,
+        }];
+        fs::create_dir_all(cfg.bare_repo_dir(&pid("p1")).unwrap()).unwrap();
+        let state = AppState::new(cfg);
+        let prod = &state.sources["prod"].cfg;
+        fs::create_dir_all(prod.bare_repo_dir(&pid("p2")).unwrap()).unwrap();
+        fs::create_dir_all(prod.state_dir()).unwrap();
+        fs::write(prod.activity_log_file(), "{}\n").unwrap();
+        let git = Arc::new(ScriptedGit::new());
+
+        let running = state.backups.try_start().unwrap();
+        let report = run_backup(&state, git.clone(), running).await;
+        assert!(report.ok, "{:?}", report.errors);
+        assert_eq!(report.projects, 2);
+
+        let dir = PathBuf::from(report.path.unwrap());
+        let manifest: Manifest =
+            serde_json::from_str(&fs::read_to_string(dir.join(MANIFEST_FILE)).unwrap()).unwrap();
+        let paths: Vec<&str> = manifest.files.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            [
+                "metadata/p1.json",
+                "sources/prod/activity.jsonl",
+                "sources/prod/metadata/p2.json",
+            ]
+        );
+        assert_eq!(manifest.projects, 2);
+        assert_eq!(manifest.sources, BTreeMap::from([("prod".to_string(), 1)]));
+
+        let bundles: Vec<String> = git.calls().iter().map(|call| call[2].clone()).collect();
+        assert_eq!(bundles.len(), 2);
+        assert!(bundles[0].ends_with("/bundles/p1.bundle"), "{bundles:?}");
+        assert!(
+            bundles[1].ends_with("/sources/prod/bundles/p2.bundle"),
+            "{bundles:?}"
+        );
+    }
+
+    #[test]
+    fn retention_keeps_the_newest_backups() {
+        let root = TempDir::new().unwrap();
+        for name in [
+            "gitbridge-backup-20260101T000000.000Z",
+            "gitbridge-backup-20260102T000000.000Z",
+            "gitbridge-backup-20260103T000000.000Z",
+            ".gitbridge-backup-20260104T000000.000Z.partial",
+            "unrelated",
+        ] {
+            fs::create_dir_all(root.path().join(name)).unwrap();
+        }
+
+        let mut removed = prune_backups(root.path(), 2).unwrap();
+        removed.sort();
+        assert_eq!(
+            removed,
+            [
+                ".gitbridge-backup-20260104T000000.000Z.partial",
+                "gitbridge-backup-20260101T000000.000Z"
+            ]
+        );
+        assert!(root.path().join("unrelated").is_dir());
+        assert!(
+            root.path()
+                .join("gitbridge-backup-20260103T000000.000Z")
+                .is_dir()
+        );
+    }
+}
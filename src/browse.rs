@@ -0,0 +1,240 @@
+use crate::error::BridgeError;
+use crate::git::{GitRunner, git_stdout};
+use serde::Serialize;
+use std::path::Path;
+use thiserror::Error;
+
+/// Larger blobs are reported without content.
+pub(crate) const MAX_BLOB_PREVIEW_BYTES: u64 = 512 * 1024;
+
+/// Bytes inspected for NUL when deciding whether a blob is binary (same
+/// heuristic as git itself).
+const BINARY_SNIFF_BYTES: usize = 8000;
+
+#[derive(Debug, Error)]
+pub(crate) enum BrowseError {
+    #[error("ref or path not found")]
+    NotFound,
+    #[error("path is a {0}, not a {1}")]
+    WrongKind(String, &'static str),
+    #[error(transparent)]
+    Git(#[from] BridgeError),
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub(crate) struct TreeEntry {
+    pub(crate) name: String,
+    /// `blob`, `tree`, or `commit` (submodule)
+    #[serde(rename = "type")]
+    pub(crate) kind: String,
+    pub(crate) mode: String,
+    /// Only known for blobs
+    pub(crate) size: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct BlobView {
+    pub(crate) size: u64,
+    pub(crate) binary: bool,
+    pub(crate) too_large: bool,
+    /// UTF-8 content; `None` for binary or oversized blobs
+    pub(crate) content: Option<String>,
+}
+
+/// Normalize an in-repo path from a query string: no leading/trailing slashes,
+/// no empty, `.`, or `..` components. `None` if the path is unacceptable.
+pub(crate) fn sanitize_repo_path(raw: &str) -> Option<String> {
+    let trimmed = raw.trim_matches('/');
+    if trimmed.is_empty() {
+        return Some(String::new());
+    }
+    let ok = trimmed
+        .split('/')
+        .all(|c| !c.is_empty() && c != "." && c != ".." && !c.chars().any(|ch| ch.is_control()));
+    ok.then(|| trimmed.to_string())
+}
+
+/// Refs accepted by the browser: branch names and object ids. Colons would
+/// change the meaning of `<ref>:<path>`, and leading dashes look like options.
+pub(crate) fn is_valid_ref(rev: &str) -> bool {
+    !rev.is_empty()
+        && !rev.starts_with(['-', '/', '.'])
+        && !rev.contains("..")
+        && rev
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./".contains(c))
+}
+
+fn object_type(
+    git: &dyn GitRunner,
+    repo: &Path,
+    object: &str,
+) -> Result<Option<String>, BridgeError> {
+    let out = git.run(&["cat-file", "-t", object], repo)?;
+    if !out.status.success() {
+        return Ok(None);
+    }
+    Ok(Some(
+        String::from_utf8_lossy(&out.stdout).trim().to_string(),
+    ))
+}
+
+/// List the directory `path` (already sanitized) at `rev` in a bare repo.
+pub(crate) fn list_tree(
+    git: &dyn GitRunner,
+    repo: &Path,
+    rev: &str,
+    path: &str,
+) -> Result<Vec<TreeEntry>, BrowseError> {
+    let object = format!("{rev}:{path}");
+    match object_type(git, repo, &object)?.as_deref() {
+        None => return Err(BrowseError::NotFound),
+        Some("tree") => {}
+        Some(other) => return Err(BrowseError::WrongKind(other.to_string(), "directory")),
+    }
+
+    let raw = git_stdout(git, repo, &["ls-tree", "-l", "-z", &object])?;
+    let mut entries: Vec<TreeEntry> = raw
+        .split(|&b| b == 0)
+        .filter(|record| !record.is_empty())
+        .filter_map(|record| parse_ls_tree_record(&String::from_utf8_lossy(record)))
+        .collect();
+    entries.sort_by(|a, b| (a.kind != "tree", &a.name).cmp(&(b.kind != "tree", &b.name)));
+    Ok(entries)
+}
+
+/// `<mode> SP <type> SP <object> SP+ <size> TAB <name>`
+fn parse_ls_tree_record(record: &str) -> Option<TreeEntry> {
+    let (meta, name) = record.split_once('\t')?;
+    let mut fields = meta.split_whitespace();
+    let mode = fields.next()?.to_string();
+    let kind = fields.next()?.to_string();
+    let _object = fields.next()?;
+    let size = fields.next()?.parse().ok();
+    Some(TreeEntry {
+        name: name.to_string(),
+        kind,
+        mode,
+        size,
+    })
+}
+
+/// Read the file `path` (already sanitized) at `rev` in a bare repo.
+pub(crate) fn read_blob(
+    git: &dyn GitRunner,
+    repo: &Path,
+    rev: &str,
+    path: &str,
+) -> Result<BlobView, BrowseError> {
+    let object = format!("{rev}:{path}");
+    match object_type(git, repo, &object)?.as_deref() {
+        None => return Err(BrowseError::NotFound),
+        Some("blob") => {}
+        Some(other) => return Err(BrowseError::WrongKind(other.to_string(), "file")),
+    }
+
+    let size_out = git_stdout(git, repo, &["cat-file", "-s", &object])?;
+    let size: u64 = String::from_utf8_lossy(&size_out)
+        .trim()
+        .parse()
+        .map_err(|_| BridgeError::Other(format!("unexpected size for {object}")))?;
+    if size > MAX_BLOB_PREVIEW_BYTES {
+        return Ok(BlobView {
+            size,
+            binary: false,
+            too_large: true,
+            content: None,
+        });
+    }
+
+    let data = git_stdout(git, repo, &["cat-file", "blob", &object])?;
+    let sniff = &data[..data.len().min(BINARY_SNIFF_BYTES)];
+    let content = if sniff.contains(&0) {
+        None
+    } else {
+        String::from_utf8(data).ok()
+    };
+    Ok(BlobView {
+        size,
+        binary: content.is_none(),
+        too_large: false,
+        content,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git::mock::ScriptedGit;
+
+    #[test]
+    fn paths_and_refs_are_sanitized() {
+        assert_eq!(sanitize_repo_path("/").as_deref(), Some(""));
+        assert_eq!(
+            sanitize_repo_path("/chapters/intro.tex").as_deref(),
+            Some("chapters/intro.tex")
+        );
+        assert_eq!(sanitize_repo_path("chapters/../../etc"), None);
+        assert_eq!(sanitize_repo_path("a//b"), None);
+        assert_eq!(sanitize_repo_path("./a"), None);
+
+        assert!(is_valid_ref("master"));
+        assert!(is_valid_ref("feature/x"));
+        assert!(!is_valid_ref("--output=/tmp/x"));
+        assert!(!is_valid_ref("master:secret"));
+        assert!(!is_valid_ref("HEAD@{1}"));
+    }
+
+    #[test]
+    fn lists_directories_first() {
+        let repo = Path::new("/repo");
+        let listing = b"100644 blob e69de29bb2d1d6434b8b29ae775ad8c2e48c5391      12\tmain.tex\0\
+040000 tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904       -\tchapters\0";
+        let git = ScriptedGit::new()
+            .on_stdout(&["cat-file", "-t", "master:"], b"tree\n")
+            .on_stdout(&["ls-tree", "-l", "-z", "master:"], listing);
+
+        let entries = list_tree(&git, repo, "master", "").unwrap();
+        assert_eq!(entries[0].name, "chapters");
+        assert_eq!(entries[0].size, None);
+        assert_eq!(entries[1].name, "main.tex");
+        assert_eq!(entries[1].size, Some(12));
+    }
+
+    #[test]
+    fn binary_and_oversized_blobs_have_no_content() {
+        let repo = Path::new("/repo");
+        let git = ScriptedGit::new()
+            .on_stdout(&["cat-file", "-t", "master:fig.png"], b"blob\n")
+            .on_stdout(&["cat-file", "-s", "master:fig.png"], b"4\n")
+            .on_stdout(&["cat-file", "blob", "master:fig.png"], b"\x89P\0G")
+            .on_stdout(&["cat-file", "-t", "master:big.pdf"], b"blob\n")
+            .on_stdout(&["cat-file", "-s", "master:big.pdf"], b"99999999\n")
+            .on_stdout(&["cat-file", "-t", "master:chapters"], b"tree\n")
+            .on(
+                &["cat-file", "-t", "master:missing"],
+                128,
+                "fatal: not a valid object name",
+            );
+
+        let png = read_blob(&git, repo, "master", "fig.png").unwrap();
+        assert!(png.binary && png.content.is_none());
+
+        let pdf = read_blob(&git, repo, "master", "big.pdf").unwrap();
+        assert!(pdf.too_large && pdf.content.is_none());
+        assert!(
+            !git.calls()
+                .iter()
+                .any(|c| c == &["cat-file", "blob", "master:big.pdf"])
+        );
+
+        assert!(matches!(
+            read_blob(&git, repo, "master", "chapters"),
+            Err(BrowseError::WrongKind(..))
+        ));
+        assert!(matches!(
+            read_blob(&git, repo, "master", "missing"),
+            Err(BrowseError::NotFound)
+        ));
+    }
+}
@@ -0,0 +1,319 @@
+//! Command-line subcommands of the binary, run instead of the server.
+
+use crate::AppState;
+use crate::error::BridgeError;
+use crate::git::SystemGit;
+use crate::metadata::SyncTrigger;
+use crate::repo::{SyncOutcome, is_external_repo};
+use crate::verify::{all_projects, verify_projects};
+use serde_json::json;
+use std::sync::Arc;
+
+/// Exit code of a subcommand that did its job.
+pub const EXIT_OK: i32 = 0;
+/// Exit code of a subcommand that failed, or found problems.
+pub const EXIT_ERROR: i32 = 1;
+/// Exit code for invalid arguments or configuration.
+pub const EXIT_USAGE: i32 = 2;
+/// Exit code when the project has no source.
+pub const EXIT_NOT_FOUND: i32 = 3;
+/// Exit code when another sync held the project lock for `LOCK_WAIT_SECONDS`.
+pub const EXIT_BUSY: i32 = 4;
+
+pub const USAGE: &str = "\
+usage: sharelatex-gitbridge-ro [serve] [--warm-up]
+       sharelatex-gitbridge-ro sync <project-id>
+       sharelatex-gitbridge-ro verify [--json] [--jobs <n>] [<project-id>]
+       sharelatex-gitbridge-ro gc-shared-objects
+
+Without arguments, runs the server. With `--warm-up`, it also creates
+the mirrors of all projects that have none in the background,
+WARMUP_CONCURRENCY at a time.
+
+`sync` updates the mirror of one
+project once and exits: 0 when it is up to date, 3 when the project does
+not exist, 4 when it stayed locked, 1 on other errors.
+
+`verify` compares one or all mirrors with their sources without changing
+them, `--jobs` at a time (default FSCK_CONCURRENCY), and exits with 1 when
+any mirror is out of sync, corrupt, or has no source. `--json` prints a
+report for scripts on stdout and the progress on stderr.
+
+`gc-shared-objects` deletes the blobs in the SHARED_OBJECTS store that no
+mirror or trash entry uses any more. Syncs wait while it runs.
+";
+
+/// What the binary was asked to do.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// Run the server, creating missing mirrors first with `--warm-up`
+    Serve {
+        warm_up: bool,
+    },
+    Help,
+    /// Sync one project and exit
+    Sync(String),
+    /// Compare mirrors with their sources and exit
+    Verify(VerifyArgs),
+    /// Delete unused blobs from the shared object store and exit
+    GcSharedObjects,
+}
+
+/// Options of `verify`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VerifyArgs {
+    /// Only this project instead of all of them
+    pub project_id: Option<String>,
+    pub json: bool,
+    /// Projects verified at the same time; `FSCK_CONCURRENCY` if unset
+    pub jobs: Option<usize>,
+}
+
+fn parse_verify_args(args: &[&str]) -> Result<VerifyArgs, String> {
+    let mut parsed = VerifyArgs::default();
+    let mut args = args.iter();
+    while let Some(&arg) = args.next() {
+        match arg {
+            "--json" => parsed.json = true,
+            "--jobs" | "-j" => {
+                let jobs = args.next().and_then(|n| n.parse::<usize>().ok());
+                match jobs {
+                    Some(n) if n > 0 => parsed.jobs = Some(n),
+                    _ => return Err(format!("{arg} needs a positive number")),
+                }
+            }
+            _ if arg.starts_with('-') => return Err(format!("unknown option '{arg}'")),
+            _ if parsed.project_id.is_none() => parsed.project_id = Some(arg.to_string()),
+            _ => return Err("verify takes at most one project id".to_string()),
+        }
+    }
+    Ok(parsed)
+}
+
+fn parse_serve_args(args: &[&str]) -> Result<Command, String> {
+    let mut warm_up = false;
+    for &arg in args {
+        match arg {
+            "--warm-up" => warm_up = true,
+            _ if arg.starts_with('-') => return Err(format!("unknown option '{arg}'")),
+            _ => return Err(format!("unknown arguments starting at '{arg}'")),
+        }
+    }
+    Ok(Command::Serve { warm_up })
+}
+
+/// Parse the arguments after the program name.
+pub fn parse_args(args: &[String]) -> Result<Command, String> {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args.as_slice() {
+        ["help" | "-h" | "--help"] => Ok(Command::Help),
+        ["serve", rest @ ..] => parse_serve_args(rest),
+        [] => Ok(Command::Serve { warm_up: false }),
+        [first, ..] if first.starts_with('-') => parse_serve_args(&args),
+        ["sync", project_id] => Ok(Command::Sync(project_id.to_string())),
+        ["sync"] => Err("sync needs a project id".to_string()),
+        ["gc-shared-objects"] => Ok(Command::GcSharedObjects),
+        ["verify", rest @ ..] => parse_verify_args(rest).map(Command::Verify),
+        [other, ..] => Err(format!("unknown arguments starting at '{other}'")),
+    }
+}
+
+fn exit_code(result: &Result<SyncOutcome, BridgeError>) -> i32 {
+    match result {
+        Ok(_) => EXIT_OK,
+        Err(BridgeError::ProjectNotFound(_)) => EXIT_NOT_FOUND,
+        Err(BridgeError::ProjectBusy(_)) => EXIT_BUSY,
+        Err(_) => EXIT_ERROR,
+    }
+}
+
+/// Sync the mirror of `project_id` once, as a fetch would, and print what
+/// changed. Uses the lock files below `git_root`, so it can run next to a
+/// server using the same `git_root`. Returns the exit code.
+pub async fn sync(state: &AppState, project_id: &str) -> i32 {
+    let Ok(project_id) = state.cfg.parse_project_id(project_id) else {
+        eprintln!("invalid project id '{project_id}'");
+        return EXIT_USAGE;
+    };
+    if is_external_repo(&state.cfg, &project_id) {
+        println!("{project_id}: external repo, not synced");
+        return EXIT_OK;
+    }
+    let result = crate::server::sync_project(state, &project_id, SyncTrigger::Cli).await;
+    match &result {
+        Ok(SyncOutcome {
+            commit: Some(commit),
+            squashed: Some(previous),
+            files_changed,
+            ..
+        }) => println!(
+            "{project_id}: amended {previous} into {commit} ({files_changed} files changed)"
+        ),
+        Ok(SyncOutcome {
+            commit: Some(commit),
+            files_changed,
+            ..
+        }) => println!("{project_id}: committed {commit} ({files_changed} files changed)"),
+        Ok(_) => println!("{project_id}: unchanged"),
+        Err(e) => eprintln!("{project_id}: {e}"),
+    }
+    exit_code(&result)
+}
+
+/// Compare one or all mirrors with their sources and print a report.
+/// Returns the exit code.
+pub async fn verify(state: Arc<AppState>, args: &VerifyArgs) -> i32 {
+    let project_ids = match &args.project_id {
+        Some(id) => match state.cfg.parse_project_id(id) {
+            Ok(id) => vec![id],
+            Err(_) => {
+                eprintln!("invalid project id '{id}'");
+                return EXIT_USAGE;
+            }
+        },
+        None => {
+            let source = state.source.clone();
+            let cfg = state.cfg.clone();
+            let listed = tokio::task::spawn_blocking(move || all_projects(source.as_ref(), &cfg))
+                .await
+                .map_err(|e| BridgeError::Other(format!("join error: {e}")))
+                .and_then(|r| r);
+            match listed {
+                Ok(ids) => ids,
+                Err(e) => {
+                    eprintln!("cannot list projects: {e}");
+                    return EXIT_ERROR;
+                }
+            }
+        }
+    };
+
+    let total = project_ids.len();
+    let jobs = args.jobs.unwrap_or(state.cfg.fsck_concurrency);
+    let mut done = 0;
+    let reports = verify_projects(&state, Arc::new(SystemGit), project_ids, jobs, |report| {
+        done += 1;
+        let line = format!(
+            "[{done}/{total}] {}: {}",
+            report.project_id,
+            report.verdict.describe()
+        );
+        // with --json, stdout is reserved for the report
+        if args.json {
+            eprintln!("{line}");
+        } else {
+            println!("{line}");
+        }
+    })
+    .await;
+
+    let problems = reports.iter().filter(|r| r.verdict.is_problem()).count();
+    if args.json {
+        let report = json!({
+            "checked": reports.len(),
+            "problems": problems,
+            "projects": reports,
+        });
+        println!("{report:#}");
+    } else {
+        println!(
+            "{} projects verified, {problems} with problems",
+            reports.len()
+        );
+    }
+    if problems == 0 { EXIT_OK } else { EXIT_ERROR }
+}
+
+/// Collect the garbage of the shared object store and print what was
+/// removed. Returns the exit code.
+pub async fn gc_shared_objects(state: &AppState) -> i32 {
+    let cfg = state.cfg.clone();
+    let result = tokio::task::spawn_blocking(move || {
+        crate::shared_objects::collect_garbage(&SystemGit, &cfg)
+    })
+    .await
+    .map_err(|e| BridgeError::Other(format!("join error: {e}")))
+    .and_then(|r| r);
+    match result {
+        Ok(report) => {
+            println!(
+                "{} repositories scanned, {} shared blobs kept, {} removed ({} bytes freed)",
+                report.repos_scanned, report.kept, report.removed, report.freed_bytes
+            );
+            EXIT_OK
+        }
+        Err(e) => {
+            eprintln!("cannot collect shared objects: {e}");
+            EXIT_ERROR
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use tempfile::TempDir;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn arguments_select_the_command() {
+        assert_eq!(parse_args(&[]), Ok(Command::Serve { warm_up: false }));
+        assert_eq!(
+            parse_args(&args(&["serve", "--warm-up"])),
+            Ok(Command::Serve { warm_up: true })
+        );
+        assert_eq!(
+            parse_args(&args(&["--warm-up"])),
+            Ok(Command::Serve { warm_up: true })
+        );
+        assert!(parse_args(&args(&["--warm"])).is_err());
+        assert_eq!(parse_args(&args(&["--help"])), Ok(Command::Help));
+        assert_eq!(
+            parse_args(&args(&["sync", "abc123"])),
+            Ok(Command::Sync("abc123".into()))
+        );
+        assert!(parse_args(&args(&["sync"])).is_err());
+        assert!(parse_args(&args(&["sync", "a", "b"])).is_err());
+        assert_eq!(
+            parse_args(&args(&["verify"])),
+            Ok(Command::Verify(VerifyArgs::default()))
+        );
+        assert_eq!(
+            parse_args(&args(&["verify", "--jobs", "8", "abc123", "--json"])),
+            Ok(Command::Verify(VerifyArgs {
+                project_id: Some("abc123".into()),
+                json: true,
+                jobs: Some(8),
+            }))
+        );
+        assert!(parse_args(&args(&["verify", "--jobs", "0"])).is_err());
+        assert!(parse_args(&args(&["verify", "a", "b"])).is_err());
+        assert_eq!(
+            parse_args(&args(&["gc-shared-objects"])),
+            Ok(Command::GcSharedObjects)
+        );
+    }
+
+    #[tokio::test]
+    async fn sync_exit_codes_tell_failures_apart() {
+        let root = TempDir::new().unwrap();
+        let state = AppState::new(Config::for_test(root.path()));
+        assert_eq!(sync(&state, "../etc").await, EXIT_USAGE);
+        assert_eq!(sync(&state, "abc123").await, EXIT_NOT_FOUND);
+
+        let _held = state.locks.lock("abc123", "fetch sync").await;
+        let cli = AppState::new(Config {
+            lock_wait_seconds: 0,
+            ..Config::for_test(root.path())
+        });
+        assert_eq!(sync(&cli, "abc123").await, EXIT_BUSY);
+        assert_eq!(
+            exit_code(&Err(BridgeError::Other("boom".into()))),
+            EXIT_ERROR
+        );
+    }
+}
@@ -0,0 +1,165 @@
+//! Identical ref advertisements requested at the same time, e.g. by a
+//! classroom cloning the same project, share one `git http-backend` run.
+//! An advertisement is only shared while it is being computed, and only
+//! between requests for the same project, service and protocol version that
+//! were let in the same way.
+
+use crate::project_id::ProjectId;
+use axum::http::{HeaderMap, StatusCode};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::OnceCell;
+
+/// Backend response shared by the requests of one advertisement; errors are
+/// shared as their message.
+pub(crate) type Advertisement = Arc<Result<(StatusCode, HeaderMap, Vec<u8>), String>>;
+
+/// What makes two `info/refs` requests interchangeable.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct AdvertisementKey {
+    pub(crate) project_id: ProjectId,
+    /// Query string, which names the service
+    pub(crate) query: String,
+    /// `Git-Protocol` header, which selects protocol v2
+    pub(crate) git_protocol: String,
+    /// Kind of authorization decision that let the request in
+    pub(crate) auth: &'static str,
+}
+
+/// Advertisements being computed, by key.
+#[derive(Default)]
+pub(crate) struct Coalescer {
+    in_flight: Mutex<HashMap<AdvertisementKey, Arc<OnceCell<Advertisement>>>>,
+    /// Requests answered with another request's advertisement
+    coalesced: AtomicU64,
+}
+
+impl Coalescer {
+    /// The advertisement for `key`: the one being computed for an identical
+    /// request if there is one, else the result of `compute`. When the
+    /// request computing it goes away first, a waiting one takes over.
+    pub(crate) async fn advertise<F, Fut>(&self, key: AdvertisementKey, compute: F) -> Advertisement
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Advertisement>,
+    {
+        let cell = self
+            .in_flight
+            .lock()
+            .expect("coalescer poisoned")
+            .entry(key.clone())
+            .or_default()
+            .clone();
+        let computed = AtomicBool::new(false);
+        let advertisement = cell
+            .get_or_init(|| {
+                computed.store(true, Ordering::Relaxed);
+                compute()
+            })
+            .await
+            .clone();
+        if !computed.load(Ordering::Relaxed) {
+            self.coalesced.fetch_add(1, Ordering::Relaxed);
+        }
+
+        // done computing, so later requests start afresh
+        let mut in_flight = self.in_flight.lock().expect("coalescer poisoned");
+        if in_flight
+            .get(&key)
+            .is_some_and(|current| Arc::ptr_eq(current, &cell))
+        {
+            in_flight.remove(&key);
+        }
+        advertisement
+    }
+
+    /// Requests answered with another request's advertisement since startup.
+    #[cfg(feature = "admin-ui")]
+    pub(crate) fn coalesced(&self) -> u64 {
+        self.coalesced.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use std::time::Duration;
+
+    fn key(project_id: &str, auth: &'static str) -> AdvertisementKey {
+        AdvertisementKey {
+            project_id: project_id.parse().unwrap(),
+            query: "service=git-upload-pack".into(),
+            git_protocol: String::new(),
+            auth,
+        }
+    }
+
+    async fn slow_advertisement(runs: &AtomicUsize, body: &str) -> Advertisement {
+        runs.fetch_add(1, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        Arc::new(Ok((
+            StatusCode::OK,
+            HeaderMap::new(),
+            body.as_bytes().to_vec(),
+        )))
+    }
+
+    fn body(advertisement: &Advertisement) -> &[u8] {
+        &advertisement.as_ref().as_ref().unwrap().2
+    }
+
+    #[tokio::test]
+    async fn identical_requests_in_flight_share_one_run() {
+        let coalescer = Coalescer::default();
+        let runs = AtomicUsize::new(0);
+        let same = || coalescer.advertise(key("p1", "managed"), || slow_advertisement(&runs, "p1"));
+        let (a, b, c) = tokio::join!(same(), same(), same());
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        assert!(Arc::ptr_eq(&a, &b) && Arc::ptr_eq(&b, &c));
+        assert_eq!(coalescer.coalesced.load(Ordering::Relaxed), 2);
+        assert!(coalescer.in_flight.lock().unwrap().is_empty());
+
+        // finished advertisements are not reused
+        let later = same().await;
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+        assert!(!Arc::ptr_eq(&a, &later));
+    }
+
+    #[tokio::test]
+    async fn projects_and_authorizations_are_never_mixed() {
+        let coalescer = Coalescer::default();
+        let runs = AtomicUsize::new(0);
+        let (p1, p2, public) = tokio::join!(
+            coalescer.advertise(key("p1", "managed"), || slow_advertisement(&runs, "p1")),
+            coalescer.advertise(key("p2", "managed"), || slow_advertisement(&runs, "p2")),
+            coalescer.advertise(key("p1", "public"), || slow_advertisement(&runs, "public")),
+        );
+        assert_eq!(runs.load(Ordering::SeqCst), 3);
+        assert_eq!(body(&p1), b"p1");
+        assert_eq!(body(&p2), b"p2");
+        assert_eq!(body(&public), b"public");
+    }
+
+    #[tokio::test]
+    async fn a_waiter_takes_over_when_the_first_request_goes_away() {
+        let coalescer = Arc::new(Coalescer::default());
+        let runs = Arc::new(AtomicUsize::new(0));
+        let first = {
+            let (coalescer, runs) = (coalescer.clone(), runs.clone());
+            tokio::spawn(async move {
+                coalescer
+                    .advertise(key("p1", "managed"), || slow_advertisement(&runs, "first"))
+                    .await
+            })
+        };
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let second =
+            coalescer.advertise(key("p1", "managed"), || slow_advertisement(&runs, "second"));
+        first.abort();
+        assert_eq!(body(&second.await), b"second");
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+    }
+}
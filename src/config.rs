@@ -1,142 +1,2836 @@
-use std::{env, fs, path::PathBuf};
+use crate::error::BridgeError;
+use crate::ip_allowlist::Cidr;
+use crate::project_id::{InvalidProjectId, ProjectId};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::collections::{BTreeSet, HashSet};
+use std::path::{Path, PathBuf};
+use std::{env, fs, str::FromStr};
+use thiserror::Error;
 use tracing::{info, warn};
 
 pub const GIT_AUTHOR_NAME: &str = "ShareLatex Sync";
 pub const GIT_AUTHOR_EMAIL: &str = "sync@example.invalid";
 
+/// Default for `COMMIT_MESSAGE_TEMPLATE`.
+pub const DEFAULT_COMMIT_MESSAGE_TEMPLATE: &str =
+    "Sync {timestamp} from ShareLatex project {project_id}";
+
+/// Placeholders understood in `COMMIT_MESSAGE_TEMPLATE`.
+pub const COMMIT_MESSAGE_PLACEHOLDERS: &[&str] = &["project_id", "project_alias", "timestamp"];
+
+/// Transport security of SMTP connections (`SMTP_TLS`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SmtpTls {
+    /// Plain connection upgraded with STARTTLS (default, port 587)
+    StartTls,
+    /// Implicit TLS (port 465)
+    Tls,
+    /// Unencrypted, for relays on localhost (port 25)
+    None,
+}
+
+impl SmtpTls {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::StartTls => "starttls",
+            Self::Tls => "tls",
+            Self::None => "none",
+        }
+    }
+}
+
+/// How project directories are arranged below `PROJECTS_DIR`
+/// (`PROJECTS_LAYOUT`).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ProjectsLayout {
+    /// `<PROJECTS_DIR>/<project_id>` (default)
+    #[default]
+    Flat,
+    /// `<PROJECTS_DIR>/<user_id>/<project_id>`, as in the per-user compile
+    /// directories of Overleaf CE
+    Nested,
+    /// Flat where a project directory exists there, nested otherwise
+    Auto,
+}
+
+impl ProjectsLayout {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Flat => "flat",
+            Self::Nested => "nested",
+            Self::Auto => "auto",
+        }
+    }
+
+    fn parse(raw: &str) -> Option<Self> {
+        match raw.trim().to_ascii_lowercase().as_str() {
+            "flat" => Some(Self::Flat),
+            "nested" => Some(Self::Nested),
+            "auto" => Some(Self::Auto),
+            _ => None,
+        }
+    }
+}
+
+/// Where mirrors and the bridge's own files live below `GIT_ROOT`
+/// (`GIT_ROOT_LAYOUT`).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GitRootLayout {
+    /// Mirrors in `repos/`, the bridge's files in `state/` (default);
+    /// [`crate::init_storage`] moves entries of the flat layout there
+    #[default]
+    Split,
+    /// Mirrors and files side by side in `GIT_ROOT`, as before `split`
+    Flat,
+}
+
+impl GitRootLayout {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Split => "split",
+            Self::Flat => "flat",
+        }
+    }
+
+    fn parse(raw: &str) -> Option<Self> {
+        match raw.trim().to_ascii_lowercase().as_str() {
+            "split" => Some(Self::Split),
+            "flat" => Some(Self::Flat),
+            _ => None,
+        }
+    }
+
+    /// Directory of the bridge's own files below `git_root`.
+    fn state_dir(self, git_root: &Path) -> PathBuf {
+        match self {
+            Self::Split => git_root.join("state"),
+            Self::Flat => git_root.to_path_buf(),
+        }
+    }
+}
+
+/// What an admin account may do in the admin UI.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AdminRole {
+    /// Read everything, change nothing
+    Viewer,
+    /// Everything, including creating and deleting tokens
+    Manager,
+}
+
+/// An account of the admins file (`ADMINS_FILE`).
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+pub struct AdminAccount {
+    pub username: String,
+    /// Argon2 hash in PHC format, like `ADMIN_PASSWORD_HASH`
+    pub password_hash: String,
+    pub role: AdminRole,
+}
+
+/// On-disk format of the admins file.
+#[derive(Deserialize)]
+struct AdminsFile {
+    admins: Vec<AdminAccount>,
+}
+
+/// Read and check the admins file: unique, non-empty usernames and valid
+/// password hashes.
+fn read_admins_file(path: &Path) -> Result<Vec<AdminAccount>, String> {
+    let data = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let file: AdminsFile = serde_json::from_str(&data).map_err(|e| e.to_string())?;
+    let mut seen = HashSet::new();
+    for account in &file.admins {
+        if account.username.trim().is_empty() || account.username.trim() != account.username {
+            return Err(format!("invalid username {:?}", account.username));
+        }
+        if !seen.insert(account.username.as_str()) {
+            return Err(format!("duplicate username {:?}", account.username));
+        }
+        if !is_admin_password_hash(&account.password_hash) {
+            return Err(format!("invalid password_hash of {:?}", account.username));
+        }
+    }
+    Ok(file.admins)
+}
+
+/// Name under which the source configured by `SHARELATEX_DATA_PATH` is
+/// known in token scopes and the admin API; it is served at `/git/<id>.git`.
+pub const DEFAULT_SOURCE: &str = "default";
+
+/// A further Overleaf instance, served at `/git/<name>/<id>.git` with its
+/// mirrors in `git_root/<name>` (`SOURCES`).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NamedSource {
+    pub name: String,
+    /// Base path of the instance's data volume
+    pub sharelatex_data_path: PathBuf,
+    /// `SOURCE_<NAME>_PROJECTS_DIR`, defaulting to `PROJECTS_DIR`
+    pub projects_dir: PathBuf,
+    /// `SOURCE_<NAME>_PROJECTS_LAYOUT`, defaulting to `PROJECTS_LAYOUT`
+    pub projects_layout: ProjectsLayout,
+}
+
+/// Where project contents are read from (`SOURCE_BACKEND`).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SourceBackend {
+    /// Project directories below `PROJECTS_DIR` (default)
+    #[default]
+    Filesystem,
+    /// Overleaf's MongoDB, docstore and filestore; needs the `docstore`
+    /// feature
+    Docstore,
+}
+
+impl SourceBackend {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Filesystem => "filesystem",
+            Self::Docstore => "docstore",
+        }
+    }
+}
+
+/// SMTP delivery of alert e-mails; configured when `SMTP_HOST` is set.
+#[derive(Clone, Debug)]
+pub struct SmtpConfig {
+    /// `SMTP_HOST`
+    pub host: String,
+    /// `SMTP_PORT`, defaulting to the usual port of `tls`
+    pub port: u16,
+    /// `SMTP_TLS`
+    pub tls: SmtpTls,
+    /// `SMTP_USERNAME`
+    pub username: Option<String>,
+    /// `SMTP_PASSWORD`
+    pub password: Option<String>,
+    /// Sender mailbox (`SMTP_FROM`)
+    pub from: String,
+    /// Recipient mailboxes (`ALERT_EMAIL_TO`, comma-separated)
+    pub to: Vec<String>,
+}
+
+/// Single sign-on to the admin UI through an OpenID Connect provider.
+#[derive(Clone, Debug)]
+pub struct OidcConfig {
+    /// `OIDC_ISSUER_URL`, as the provider names itself in its tokens
+    pub issuer_url: String,
+    /// `OIDC_CLIENT_ID`
+    pub client_id: String,
+    /// `OIDC_CLIENT_SECRET`
+    pub client_secret: String,
+    /// Where the provider sends the browser back (`OIDC_REDIRECT_URL`,
+    /// default `PUBLIC_URL/admin/api/login/oidc/callback`)
+    pub redirect_url: String,
+    /// `OIDC_SCOPES`, space-separated
+    pub scopes: String,
+    /// Members of these groups (the `groups` claim) may log in
+    /// (`OIDC_ALLOWED_GROUPS`, comma-separated)
+    pub allowed_groups: Vec<String>,
+    /// These verified e-mail addresses may log in (`OIDC_ALLOWED_EMAILS`,
+    /// comma-separated)
+    pub allowed_emails: Vec<String>,
+}
+
+/// Runtime configuration, normally read from the environment via [`Config::from_env`].
 #[derive(Clone, Debug)]
 pub struct Config {
+    /// HTTP port to listen on (`PORT`).
     pub port: u16,
+    /// Base path of the ShareLatex data volume (`SHARELATEX_DATA_PATH`).
     pub sharelatex_data_path: PathBuf,
+    /// Projects directory relative to `sharelatex_data_path` (`PROJECTS_DIR`).
     pub projects_dir: PathBuf,
+    /// Whether projects sit directly in `projects_dir` or one level deeper
+    /// (`PROJECTS_LAYOUT`).
+    pub projects_layout: ProjectsLayout,
+    /// Only accept ShareLatex project ids: 24 hex digits, optionally
+    /// followed by `-<suffix>` (`STRICT_PROJECT_IDS`, on by default). See
+    /// [`Config::parse_project_id`].
+    pub strict_project_ids: bool,
+    /// Further Overleaf instances served next to this one (`SOURCES`)
+    pub sources: Vec<NamedSource>,
+    /// The [`NamedSource`] a configuration made by [`Config::for_source`]
+    /// serves; `None` for the default source.
+    pub source_name: Option<String>,
+    /// Where project contents are read from (`SOURCE_BACKEND`).
+    pub source_backend: SourceBackend,
+    /// Connection string of Overleaf's MongoDB including the database, for
+    /// the docstore backend (`MONGO_URL`).
+    pub mongo_url: Option<String>,
+    /// Overleaf's docstore, asked for documents archived out of MongoDB
+    /// (`DOCSTORE_URL`).
+    pub docstore_url: Option<String>,
+    /// Overleaf's filestore, asked for binary files missing from
+    /// `user_files_dir` (`FILESTORE_URL`).
+    pub filestore_url: Option<String>,
+    /// Directory of the filestore's disk backend (`USER_FILES_PATH`).
+    pub user_files_dir: PathBuf,
+    /// Directory holding the bare mirrors and `tokens.json` (`GIT_ROOT`).
     pub git_root: PathBuf,
+    /// Arrangement of `git_root` (`GIT_ROOT_LAYOUT`).
+    pub git_root_layout: GitRootLayout,
+    /// Branch the snapshots are committed to (`READONLY_BRANCH`).
     pub readonly_branch: String,
+    /// Admin UI password; `None` disables the admin UI (`ADMIN_PASSWORD`, or
+    /// the initial contents of `ADMIN_PASSWORD_FILE`).
     pub admin_password: Option<String>,
+    /// File the admin password is read from and re-read on change (`ADMIN_PASSWORD_FILE`).
+    pub admin_password_file: Option<PathBuf>,
+    /// Argon2 hash of the admin password in PHC format, used instead of a
+    /// plain password (`ADMIN_PASSWORD_HASH`).
+    pub admin_password_hash: Option<String>,
+    /// Key for scripts: `Authorization: Bearer <key>` on `/admin/api/*` acts
+    /// as a manager without logging in (`ADMIN_API_TOKEN`).
+    pub admin_api_token: Option<String>,
+    /// Whether the admin UI accepts passwords; can only be switched off
+    /// with OIDC login (`ADMIN_PASSWORD_LOGIN`).
+    pub admin_password_login: bool,
+    /// OIDC login to the admin UI, if `OIDC_ISSUER_URL` is set (needs the
+    /// `oidc` feature).
+    pub oidc: Option<OidcConfig>,
+    /// File with further admin accounts and their roles (`ADMINS_FILE`,
+    /// default `admins.json` next to `tokens.json`).
+    pub admins_file: PathBuf,
+    /// Accounts read from `admins_file` at startup
+    pub admin_accounts: Vec<AdminAccount>,
+    /// Base32 secret of a TOTP second factor the admin login asks for
+    /// (`ADMIN_TOTP_SECRET`).
+    pub admin_totp_secret: Option<String>,
+    /// Mark the admin cookie as `Secure` (`ADMIN_COOKIE_SECURE`).
     pub admin_cookie_secure: bool,
+    /// Admin session lifetime in seconds (`ADMIN_SESSION_TTL_SECONDS`).
     pub admin_session_ttl_seconds: u64,
+    /// Failed admin logins per minute, from all clients together, after
+    /// which every login waits; 0 for no such limit
+    /// (`ADMIN_LOGIN_GLOBAL_MAX_FAILURES`).
+    pub admin_login_global_max_failures: usize,
+    /// Directory with replacement admin UI files (`ADMIN_ASSETS_DIR`).
+    pub admin_assets_dir: Option<PathBuf>,
+    /// Message of sync commits; supports `{project_id}`, `{project_alias}`,
+    /// and `{timestamp}` (`COMMIT_MESSAGE_TEMPLATE`).
+    pub commit_message_template: String,
+    /// Date sync commits by the newest modification time of the files they
+    /// change instead of the time of the sync (`COMMIT_DATE_FROM_SOURCE`).
+    pub commit_date_from_source: bool,
+    /// Give new mirrors a root commit that only depends on the snapshot, so
+    /// re-creating a mirror of an unchanged project yields the same commit
+    /// (`DETERMINISTIC_INIT`).
+    pub deterministic_init: bool,
+    /// Fold a sync commit into the previous one if that is younger than this
+    /// many minutes, was made by a webhook, manual or CLI sync and was not
+    /// fetched since; 0 to always commit (`SQUASH_WINDOW_MINUTES`).
+    pub squash_window_minutes: u64,
+    /// Refuse to sync a mirror that takes more than this many bytes on disk;
+    /// 0 for no limit (`MAX_REPO_BYTES`).
+    pub max_repo_bytes: u64,
+    /// Turn CRLF into LF in text files of each snapshot and commit a
+    /// `.gitattributes` with `eol=lf` (`NORMALIZE_EOL=lf`).
+    pub normalize_eol: bool,
+    /// Send the same HTTP auth realm for every project instead of one per
+    /// project (`AUTH_GLOBAL_REALM`).
+    pub auth_global_realm: bool,
+    /// YAML or JSON file of managed tokens reconciled into the token store at
+    /// startup (`TOKENS_BOOTSTRAP_FILE`).
+    pub tokens_bootstrap_file: Option<PathBuf>,
+    /// Remove managed tokens missing from the bootstrap file, including ones
+    /// created through the admin UI (`TOKENS_BOOTSTRAP_PRUNE`).
+    pub tokens_bootstrap_prune: bool,
+    /// Serve git's dumb HTTP object files (loose objects, packs) to clients
+    /// that do not speak the smart protocol (`GIT_ALLOW_DUMB`).
+    pub git_allow_dumb: bool,
+    /// Keep `projects.list` and each mirror's `description` up to date for
+    /// cgit or gitweb browsing `git_root` (`EXPORT_GITWEB_METADATA`).
+    pub export_gitweb_metadata: bool,
+    /// Shared secret for `/hooks/sync/<id>`; projects may override it (`WEBHOOK_SECRET`).
+    pub webhook_secret: Option<String>,
+    /// Path prefix the bridge is reachable under behind a reverse proxy, used
+    /// for generated links; empty or `/prefix` without trailing slash (`BASE_PATH`).
+    pub base_path: String,
+    /// External URL of the bridge including `BASE_PATH`, used for clone URLs
+    /// and links in notifications (`PUBLIC_URL`).
+    pub public_url: Option<String>,
+    /// Generic JSON endpoint alerts are posted to (`ALERT_WEBHOOK_URL`).
+    pub alert_webhook_url: Option<String>,
+    /// Consecutive failed syncs of a project before an alert is sent
+    /// (`ALERT_FAILURE_THRESHOLD`).
+    pub alert_failure_threshold: u32,
+    /// Minimum time between two failure alerts for the same project
+    /// (`ALERT_COOLDOWN_MINUTES`).
+    pub alert_cooldown_minutes: u64,
+    /// E-mail alerts (`SMTP_*`, `ALERT_EMAIL_TO`).
+    pub smtp: Option<SmtpConfig>,
+    /// Hours between integrity checks of all mirrors; `None` disables them
+    /// (`FSCK_INTERVAL_HOURS`).
+    pub fsck_interval_hours: Option<u64>,
+    /// Mirrors checked at the same time (`FSCK_CONCURRENCY`).
+    pub fsck_concurrency: usize,
+    /// Mirrors created at the same time by a warm-up (`WARMUP_CONCURRENCY`).
+    pub warmup_concurrency: usize,
+    /// Mirrors created at the same time for git requests of projects that
+    /// have none yet (`INIT_CONCURRENCY`).
+    pub init_concurrency: usize,
+    /// Rebuild mirrors that fail the check from a fresh snapshot (`FSCK_AUTO_RESET`).
+    pub fsck_auto_reset: bool,
+    /// Directory backups are written to; unset disables backups (`BACKUP_DIR`).
+    pub backup_dir: Option<PathBuf>,
+    /// Minutes between scheduled backups, parsed from `BACKUP_SCHEDULE`
+    /// (`every 6 hours`, `every 30m`, `hourly`, `daily`); `None` means backups
+    /// only run when triggered through the admin API.
+    pub backup_interval_minutes: Option<u64>,
+    /// Backups kept in `backup_dir` (`BACKUP_RETENTION`).
+    pub backup_retention: usize,
+    /// Include a git bundle of every mirror in backups (`BACKUP_BUNDLES`).
+    pub backup_bundles: bool,
+    /// Directory pruned mirrors are saved to as git bundles; unset deletes
+    /// them outright (`PRUNE_ARCHIVE_DIR`).
+    pub prune_archive_dir: Option<PathBuf>,
+    /// Days removed mirrors stay in the trash before they are
+    /// deleted; 0 deletes them right away (`TRASH_RETENTION_DAYS`).
+    pub trash_retention_days: u64,
+    /// Store large blobs once in `GIT_ROOT/shared-objects` for all mirrors
+    /// (`SHARED_OBJECTS`).
+    pub shared_objects: bool,
+    /// Blobs of at least this size go to the shared store
+    /// (`SHARED_OBJECTS_MIN_BYTES`).
+    pub shared_objects_min_bytes: u64,
+    /// Size at which the access log of git requests is rotated; 0 keeps no
+    /// access log (`ACCESS_LOG_MAX_BYTES`).
+    pub access_log_max_bytes: u64,
+    /// Rotated access logs kept besides the current one (`ACCESS_LOG_FILES`).
+    pub access_log_files: usize,
+    /// Serve fetches from the existing mirror without syncing when the
+    /// source's fingerprint is the one recorded at the last sync
+    /// (`SYNC_SKIP_UNCHANGED`).
+    pub sync_skip_unchanged: bool,
+    /// Serve the existing mirror, marked stale, when the pre-fetch sync
+    /// fails (`SERVE_STALE_ON_SYNC_ERROR`).
+    pub serve_stale_on_sync_error: bool,
+    /// Log the git commands of syncs and their output at info instead of
+    /// debug level (`GIT_COMMAND_LOG`).
+    pub git_command_log: bool,
+    /// Hours a rotated deploy token stays valid next to its successor;
+    /// tokens are rotated this long before they expire
+    /// (`DEPLOY_TOKEN_OVERLAP_HOURS`).
+    pub deploy_token_overlap_hours: u64,
+    /// Endpoint rotated deploy tokens are posted to as JSON, including the
+    /// new token (`DEPLOY_TOKEN_WEBHOOK_URL`).
+    pub deploy_token_webhook_url: Option<String>,
+    /// Endpoint every sync commit is posted to as JSON with its change list
+    /// (`SYNC_EVENT_WEBHOOK_URL`).
+    pub sync_event_webhook_url: Option<String>,
+    /// Hours an undelivered webhook post is retried before it is dropped
+    /// (`WEBHOOK_QUEUE_MAX_AGE_HOURS`).
+    pub webhook_queue_max_age_hours: u64,
+    /// Undelivered webhook posts kept for retries; the oldest are dropped
+    /// beyond it (`WEBHOOK_QUEUE_MAX_ENTRIES`).
+    pub webhook_queue_max_entries: usize,
+    /// Requests handled at the same time; more are answered with 503
+    /// (`MAX_CONCURRENT_REQUESTS`).
+    pub max_concurrent_requests: usize,
+    /// Open client connections; more wait in the accept backlog (`MAX_CONNECTIONS`).
+    pub max_connections: usize,
+    /// Time a client has to send the request headers (`HEADER_READ_TIMEOUT_SECONDS`).
+    pub header_read_timeout_seconds: u64,
+    /// Port of the read-only `git://` listener for public projects; off when
+    /// unset (`GIT_DAEMON_PORT`).
+    pub git_daemon_port: Option<u16>,
+    /// Open `git://` connections; more wait in the accept backlog
+    /// (`GIT_DAEMON_MAX_CONNECTIONS`).
+    pub git_daemon_max_connections: usize,
+    /// Connections without traffic and without a request in progress are
+    /// closed after this long (`IDLE_TIMEOUT_SECONDS`).
+    pub idle_timeout_seconds: u64,
+    /// Longest a request waits for another operation on the same project
+    /// before it is answered with 503 (`LOCK_WAIT_SECONDS`).
+    pub lock_wait_seconds: u64,
+    /// How long `serve` waits on SIGTERM or SIGINT for running syncs and
+    /// requests before exiting anyway (`SHUTDOWN_GRACE_SECONDS`).
+    pub shutdown_grace_seconds: u64,
+    /// `http.maxRequestBuffer` of `git http-backend` in bytes; `None` keeps
+    /// git's default of 10 MiB (`GIT_BACKEND_MAX_REQUEST_BUFFER`).
+    pub git_backend_max_request_buffer: Option<u64>,
+    /// Log the pkt-line traffic of `git http-backend`; for debugging only
+    /// (`GIT_BACKEND_TRACE_PACKET`).
+    pub git_backend_trace_packet: bool,
+    /// Tell git clients where the time of their request went, in an
+    /// `X-GitBridge-Timing` header (`GIT_TIMING_HEADER`).
+    pub git_timing_header: bool,
+    /// Additional `key=value` git config passed to `git http-backend`
+    /// (`GIT_BACKEND_EXTRA_CONFIG`, comma-separated).
+    pub git_backend_extra_config: Vec<(String, String)>,
+    /// Ref prefixes left out of the ref advertisement, passed to git as
+    /// `transfer.hideRefs` (`GIT_HIDE_REFS`, comma-separated).
+    pub git_hide_refs: Vec<String>,
+    /// `key=value` git config set on every bridge-managed bare repo when it
+    /// is created and on each sync (`BARE_REPO_GIT_CONFIG`, comma- or
+    /// newline-separated). Projects can override keys in `.gitbridge.toml`.
+    pub bare_repo_git_config: Vec<(String, String)>,
+    /// Source addresses allowed to use the git endpoint; `None` allows all
+    /// (`GIT_ALLOWED_CIDRS`, comma-separated).
+    pub git_allowed_cidrs: Option<Vec<Cidr>>,
+    /// Reverse proxies whose `X-Forwarded-For` names the client
+    /// (`TRUSTED_PROXIES`, comma-separated addresses or CIDR ranges).
+    pub trusted_proxies: Vec<Cidr>,
+    /// Variables whose value was taken from the environment
+    pub(crate) provided_vars: BTreeSet<String>,
+}
+
+/// Whether `ADMIN_PASSWORD_HASH` holds a hash the admin login can check.
+#[cfg(feature = "admin-ui")]
+fn is_admin_password_hash(hash: &str) -> bool {
+    crate::secrets::is_password_hash(hash)
+}
+
+/// Without the admin UI the hash is never checked.
+#[cfg(not(feature = "admin-ui"))]
+fn is_admin_password_hash(_hash: &str) -> bool {
+    true
+}
+
+/// Shortest accepted `ADMIN_API_TOKEN`, e.g. `openssl rand -hex 16`.
+const MIN_ADMIN_API_TOKEN_LEN: usize = 32;
+
+/// Whether `ADMIN_TOTP_SECRET` holds a secret the admin login can check.
+#[cfg(feature = "admin-ui")]
+fn is_admin_totp_secret(secret: &str) -> bool {
+    crate::totp::decode_secret(secret).is_some()
+}
+
+/// Without the admin UI the secret is never checked.
+#[cfg(not(feature = "admin-ui"))]
+fn is_admin_totp_secret(_secret: &str) -> bool {
+    true
+}
+
+/// A variable that is set but holds an unusable value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigProblem {
+    pub var: String,
+    pub value: String,
+    pub expected: String,
+}
+
+impl std::fmt::Display for ConfigProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}={:?} (expected {})",
+            self.var, self.value, self.expected
+        )
+    }
+}
+
+/// All problems found while loading the configuration in strict mode, or
+/// when one of them is never ignored.
+#[derive(Debug, Error)]
+#[error("invalid configuration:{}", .problems.iter().map(|p| format!("\n  {p}")).collect::<String>())]
+pub struct ConfigError {
+    pub problems: Vec<ConfigProblem>,
+}
+
+/// Reads variables through a lookup function and records every present but
+/// invalid value instead of stopping at the first one.
+pub(crate) struct EnvReader<'a> {
+    lookup: &'a dyn Fn(&str) -> Option<String>,
+    pub(crate) problems: Vec<ConfigProblem>,
+    /// Whether a problem was recorded that fails loading even when not strict
+    pub(crate) fatal: bool,
+    /// Variables that were set and accepted
+    pub(crate) provided: BTreeSet<String>,
+}
+
+impl<'a> EnvReader<'a> {
+    pub(crate) fn new(lookup: &'a dyn Fn(&str) -> Option<String>) -> Self {
+        Self {
+            lookup,
+            problems: Vec::new(),
+            fatal: false,
+            provided: BTreeSet::new(),
+        }
+    }
+
+    /// Raw value, `None` when unset.
+    pub(crate) fn raw(&mut self, var: &str) -> Option<String> {
+        let value = (self.lookup)(var);
+        if value.is_some() {
+            self.provided.insert(var.to_string());
+        }
+        value
+    }
+
+    /// Raw value, `None` when unset or blank.
+    pub(crate) fn non_empty(&mut self, var: &str) -> Option<String> {
+        let value = (self.lookup)(var).filter(|v| !v.trim().is_empty());
+        if value.is_some() {
+            self.provided.insert(var.to_string());
+        }
+        value
+    }
+
+    pub(crate) fn problem(&mut self, var: &str, value: &str, expected: &str) {
+        self.provided.remove(var);
+        self.problems.push(ConfigProblem {
+            var: var.to_string(),
+            value: value.to_string(),
+            expected: expected.to_string(),
+        });
+    }
+
+    /// A problem that fails [`Config::try_from_env`] even without
+    /// `STRICT_CONFIG`, for settings that contradict each other.
+    pub(crate) fn conflict(&mut self, var: &str, value: &str, expected: &str) {
+        self.problem(var, value, expected);
+        self.fatal = true;
+    }
+
+    /// Parse `var` and check it with `valid`; invalid values are recorded and yield `None`.
+    pub(crate) fn parse<T: FromStr>(
+        &mut self,
+        var: &str,
+        expected: &str,
+        valid: impl Fn(&T) -> bool,
+    ) -> Option<T> {
+        let raw = self.non_empty(var)?;
+        match raw.trim().parse::<T>() {
+            Ok(v) if valid(&v) => Some(v),
+            _ => {
+                self.problem(var, &raw, expected);
+                None
+            }
+        }
+    }
+
+    /// Boolean flag accepting 1/0, true/false, yes/no, on/off.
+    pub(crate) fn flag(&mut self, var: &str) -> Option<bool> {
+        let raw = self.non_empty(var)?;
+        match raw.trim().to_ascii_lowercase().as_str() {
+            "1" | "true" | "yes" | "on" => Some(true),
+            "0" | "false" | "no" | "off" => Some(false),
+            _ => {
+                self.problem(var, &raw, "one of true/false, 1/0, yes/no, on/off");
+                None
+            }
+        }
+    }
 }
 
 impl Config {
+    /// Read the configuration from environment variables, falling back to
+    /// defaults (with a warning) for anything missing or unparsable.
     pub fn from_env() -> Self {
-        let port = env::var("PORT")
-            .ok()
-            .and_then(|v| v.parse::<u16>().ok())
+        let (cfg, problems, _strict) = Self::load(&|var| env::var(var).ok());
+        for problem in &problems {
+            warn!("ignoring invalid setting {problem}, using default");
+        }
+        cfg
+    }
+
+    /// Like [`Config::from_env`], but with `STRICT_CONFIG=true` every invalid
+    /// value is reported together in the returned error instead of being
+    /// replaced by its default. Conflicting settings are an error either way.
+    pub fn try_from_env() -> Result<Self, ConfigError> {
+        Self::try_from_lookup(&|var| env::var(var).ok())
+    }
+
+    pub(crate) fn try_from_lookup(
+        lookup: &dyn Fn(&str) -> Option<String>,
+    ) -> Result<Self, ConfigError> {
+        let (cfg, problems, fail) = Self::load(lookup);
+        if fail && !problems.is_empty() {
+            return Err(ConfigError { problems });
+        }
+        for problem in &problems {
+            warn!("ignoring invalid setting {problem}, using default");
+        }
+        Ok(cfg)
+    }
+
+    /// Build the configuration and collect problems; the flag tells whether
+    /// they are fatal, because strict mode was requested or settings conflict.
+    fn load(lookup: &dyn Fn(&str) -> Option<String>) -> (Self, Vec<ConfigProblem>, bool) {
+        let mut env = EnvReader::new(lookup);
+
+        // NOTE: strict mode is planned to become the default in the next major version
+        let strict = env.flag("STRICT_CONFIG").unwrap_or(false);
+
+        let port = env
+            .parse::<u16>("PORT", "a port number between 1 and 65535", |&p| p > 0)
             .unwrap_or(8022);
 
         let sharelatex_data_path = resolve_path(
-            env::var("SHARELATEX_DATA_PATH")
+            env.non_empty("SHARELATEX_DATA_PATH")
                 .map(PathBuf::from)
-                .unwrap_or_else(|_| PathBuf::from("/sharelatex-data")),
+                .unwrap_or_else(|| PathBuf::from("/sharelatex-data")),
         );
 
-        let projects_dir = env::var("PROJECTS_DIR")
+        let projects_dir = env
+            .non_empty("PROJECTS_DIR")
             .map(PathBuf::from)
-            .unwrap_or_else(|_| PathBuf::from("data/compiles"));
+            .unwrap_or_else(|| PathBuf::from("data/compiles"));
+        let projects_layout = parse_layout(&mut env, "PROJECTS_LAYOUT").unwrap_or_default();
+        let sources = parse_sources(&mut env, &projects_dir, projects_layout);
+        let strict_project_ids = env.flag("STRICT_PROJECT_IDS").unwrap_or(true);
+
+        let source_backend = match env.non_empty("SOURCE_BACKEND") {
+            Some(raw) => match raw.trim().to_ascii_lowercase().as_str() {
+                "filesystem" => SourceBackend::Filesystem,
+                "docstore" if cfg!(feature = "docstore") => SourceBackend::Docstore,
+                "docstore" => {
+                    env.problem(
+                        "SOURCE_BACKEND",
+                        &raw,
+                        "filesystem (built without the docstore feature)",
+                    );
+                    SourceBackend::Filesystem
+                }
+                _ => {
+                    env.problem("SOURCE_BACKEND", &raw, "one of filesystem, docstore");
+                    SourceBackend::Filesystem
+                }
+            },
+            None => SourceBackend::Filesystem,
+        };
+        let mongo_url = env.non_empty("MONGO_URL").map(|url| url.trim().to_string());
+        let source_backend = match (source_backend, &mongo_url) {
+            (SourceBackend::Docstore, None) => {
+                env.problem(
+                    "MONGO_URL",
+                    "",
+                    "a MongoDB connection string when SOURCE_BACKEND=docstore",
+                );
+                SourceBackend::Filesystem
+            }
+            (backend, _) => backend,
+        };
+        let docstore_url = http_url_setting(&mut env, "DOCSTORE_URL");
+        let filestore_url = http_url_setting(&mut env, "FILESTORE_URL");
+        let user_files_dir = env
+            .non_empty("USER_FILES_PATH")
+            .map(|dir| resolve_path(PathBuf::from(dir)))
+            .unwrap_or_else(|| sharelatex_data_path.join("data/user_files"));
 
         let git_root = resolve_path(
-            env::var("GIT_ROOT")
+            env.non_empty("GIT_ROOT")
                 .map(PathBuf::from)
-                .unwrap_or_else(|_| PathBuf::from("/data/git-bridge")),
+                .unwrap_or_else(|| PathBuf::from("/data/git-bridge")),
         );
+        // a typo must not start a migration nobody asked for, nor skip one
+        let git_root_layout = match env.non_empty("GIT_ROOT_LAYOUT") {
+            Some(raw) => GitRootLayout::parse(&raw).unwrap_or_else(|| {
+                env.conflict("GIT_ROOT_LAYOUT", &raw, "split or flat");
+                GitRootLayout::default()
+            }),
+            None => GitRootLayout::default(),
+        };
+
+        let readonly_branch = match env.non_empty("READONLY_BRANCH") {
+            Some(branch) if is_valid_branch_name(&branch) => branch,
+            Some(branch) => {
+                env.problem("READONLY_BRANCH", &branch, "a valid git branch name");
+                "master".to_string()
+            }
+            None => "master".to_string(),
+        };
+
+        let mut admin_password = env.raw("ADMIN_PASSWORD");
+
+        let mut admin_password_file = env
+            .non_empty("ADMIN_PASSWORD_FILE")
+            .map(|v| resolve_path(PathBuf::from(v)));
+        if let Some(path) = &admin_password_file {
+            if admin_password.is_some() {
+                env.problem(
+                    "ADMIN_PASSWORD_FILE",
+                    &path.display().to_string(),
+                    "to be unset when ADMIN_PASSWORD is set",
+                );
+            } else {
+                match fs::read_to_string(path) {
+                    Ok(v) if !v.trim().is_empty() => admin_password = Some(v.trim().to_string()),
+                    _ => env.problem(
+                        "ADMIN_PASSWORD_FILE",
+                        &path.display().to_string(),
+                        "a readable, non-empty file",
+                    ),
+                }
+            }
+        }
+
+        // the hash wins over a plain password; `try_from_env` refuses both
+        let admin_password_hash = match env.non_empty("ADMIN_PASSWORD_HASH") {
+            Some(hash) if is_admin_password_hash(hash.trim()) => Some(hash.trim().to_string()),
+            Some(_) => {
+                env.problem(
+                    "ADMIN_PASSWORD_HASH",
+                    "(not shown)",
+                    "an argon2 hash in PHC format, e.g. $argon2id$v=19$...",
+                );
+                None
+            }
+            None => None,
+        };
+        if admin_password_hash.is_some() {
+            if let Some(path) = admin_password_file.take() {
+                env.conflict(
+                    "ADMIN_PASSWORD_FILE",
+                    &path.display().to_string(),
+                    "to be unset when ADMIN_PASSWORD_HASH is set",
+                );
+            } else if admin_password.as_deref().is_some_and(|p| !p.is_empty()) {
+                env.conflict(
+                    "ADMIN_PASSWORD",
+                    "(not shown)",
+                    "to be unset when ADMIN_PASSWORD_HASH is set",
+                );
+            }
+            admin_password = None;
+        }
+
+        // unlike other bad values, a bad secret is never skipped: that would
+        // quietly drop the second factor
+        let admin_totp_secret = match env.non_empty("ADMIN_TOTP_SECRET") {
+            Some(secret) if is_admin_totp_secret(secret.trim()) => Some(secret.trim().to_string()),
+            Some(_) => {
+                env.conflict(
+                    "ADMIN_TOTP_SECRET",
+                    "(not shown)",
+                    "a base32 secret of at least 16 characters",
+                );
+                None
+            }
+            None => None,
+        };
 
-        let readonly_branch = env::var("READONLY_BRANCH").unwrap_or_else(|_| "master".to_string());
+        // a short key could be guessed; refusing to start beats ignoring it
+        let admin_api_token = match env.non_empty("ADMIN_API_TOKEN") {
+            Some(token) if token.trim().len() >= MIN_ADMIN_API_TOKEN_LEN => {
+                Some(token.trim().to_string())
+            }
+            Some(_) => {
+                env.conflict(
+                    "ADMIN_API_TOKEN",
+                    "(not shown)",
+                    "a key of at least 32 characters",
+                );
+                None
+            }
+            None => None,
+        };
 
-        let admin_password = env::var("ADMIN_PASSWORD").ok();
+        // a named file has to be there, the default one is optional
+        let admins_file_var = env.non_empty("ADMINS_FILE");
+        let admins_file = admins_file_var
+            .as_ref()
+            .map(|path| resolve_path(PathBuf::from(path)))
+            .unwrap_or_else(|| git_root_layout.state_dir(&git_root).join("admins.json"));
+        let admin_accounts = if admins_file_var.is_some() || admins_file.exists() {
+            match read_admins_file(&admins_file) {
+                Ok(accounts) => accounts,
+                Err(e) => {
+                    env.conflict(
+                        "ADMINS_FILE",
+                        &admins_file.display().to_string(),
+                        &format!("a readable admins file ({e})"),
+                    );
+                    Vec::new()
+                }
+            }
+        } else {
+            Vec::new()
+        };
 
-        let admin_cookie_secure = env::var("ADMIN_COOKIE_SECURE")
-            .ok()
-            .map(|v| v.trim().to_ascii_lowercase())
-            .map(|v| matches!(v.as_str(), "1" | "true" | "yes" | "on"))
-            .unwrap_or(false);
+        let admin_cookie_secure = env.flag("ADMIN_COOKIE_SECURE").unwrap_or(false);
 
-        let admin_session_ttl_seconds = env::var("ADMIN_SESSION_TTL_SECONDS")
-            .ok()
-            .and_then(|v| v.parse::<u64>().ok())
-            .filter(|&ttl| ttl > 0)
+        let admin_session_ttl_seconds = env
+            .parse::<u64>(
+                "ADMIN_SESSION_TTL_SECONDS",
+                "a positive number of seconds",
+                |&ttl| ttl > 0,
+            )
             .unwrap_or(3600);
 
-        Self {
+        let admin_login_global_max_failures = env
+            .parse::<usize>(
+                "ADMIN_LOGIN_GLOBAL_MAX_FAILURES",
+                "a number of failed logins, 0 for no limit",
+                |_| true,
+            )
+            .unwrap_or(50);
+
+        let admin_assets_dir = env
+            .non_empty("ADMIN_ASSETS_DIR")
+            .map(|v| resolve_path(PathBuf::from(v)));
+
+        let commit_message_template = match env.non_empty("COMMIT_MESSAGE_TEMPLATE") {
+            Some(template) if uses_known_placeholders(&template) => template,
+            Some(template) => {
+                env.problem(
+                    "COMMIT_MESSAGE_TEMPLATE",
+                    &template,
+                    "only {project_id}, {project_alias}, and {timestamp} placeholders",
+                );
+                DEFAULT_COMMIT_MESSAGE_TEMPLATE.to_string()
+            }
+            None => DEFAULT_COMMIT_MESSAGE_TEMPLATE.to_string(),
+        };
+
+        let commit_date_from_source = env.flag("COMMIT_DATE_FROM_SOURCE").unwrap_or(false);
+        let deterministic_init = env.flag("DETERMINISTIC_INIT").unwrap_or(false);
+        let squash_window_minutes = env
+            .parse::<u64>("SQUASH_WINDOW_MINUTES", "a number of minutes", |_| true)
+            .unwrap_or(0);
+        let max_repo_bytes = env
+            .parse::<u64>("MAX_REPO_BYTES", "a number of bytes", |_| true)
+            .unwrap_or(0);
+
+        let normalize_eol = match env.non_empty("NORMALIZE_EOL") {
+            Some(raw) => match raw.trim().to_ascii_lowercase().as_str() {
+                "lf" => true,
+                "off" => false,
+                _ => {
+                    env.problem("NORMALIZE_EOL", &raw, "lf or off");
+                    false
+                }
+            },
+            None => false,
+        };
+
+        let auth_global_realm = env.flag("AUTH_GLOBAL_REALM").unwrap_or(false);
+
+        let tokens_bootstrap_file = env
+            .non_empty("TOKENS_BOOTSTRAP_FILE")
+            .map(|v| resolve_path(PathBuf::from(v)));
+        let tokens_bootstrap_prune = match env.flag("TOKENS_BOOTSTRAP_PRUNE") {
+            Some(true) if tokens_bootstrap_file.is_none() => {
+                env.problem(
+                    "TOKENS_BOOTSTRAP_PRUNE",
+                    "true",
+                    "TOKENS_BOOTSTRAP_FILE to be set as well",
+                );
+                false
+            }
+            prune => prune.unwrap_or(false),
+        };
+        let git_allow_dumb = env.flag("GIT_ALLOW_DUMB").unwrap_or(false);
+        let export_gitweb_metadata = env.flag("EXPORT_GITWEB_METADATA").unwrap_or(false);
+
+        let webhook_secret = env.non_empty("WEBHOOK_SECRET");
+
+        let base_path = match env.non_empty("BASE_PATH") {
+            Some(raw) => normalize_base_path(&raw).unwrap_or_else(|| {
+                env.problem("BASE_PATH", &raw, "a URL path such as /gitbridge");
+                String::new()
+            }),
+            None => String::new(),
+        };
+
+        let public_url = match env.non_empty("PUBLIC_URL") {
+            Some(url) if is_http_url(&url) => Some(url.trim().trim_end_matches('/').to_string()),
+            Some(url) => {
+                env.problem("PUBLIC_URL", &url, "an http:// or https:// URL");
+                None
+            }
+            None => None,
+        };
+
+        let alert_webhook_url = match env.non_empty("ALERT_WEBHOOK_URL") {
+            Some(url) if is_http_url(&url) => Some(url.trim().to_string()),
+            Some(url) => {
+                env.problem("ALERT_WEBHOOK_URL", &url, "an http:// or https:// URL");
+                None
+            }
+            None => None,
+        };
+
+        let alert_failure_threshold = env
+            .parse::<u32>(
+                "ALERT_FAILURE_THRESHOLD",
+                "a positive number of failures",
+                |&n| n > 0,
+            )
+            .unwrap_or(3);
+
+        let alert_cooldown_minutes = env
+            .parse::<u64>("ALERT_COOLDOWN_MINUTES", "a number of minutes", |_| true)
+            .unwrap_or(60);
+
+        let smtp = smtp_from_env(&mut env);
+        let oidc = oidc_from_env(&mut env, public_url.as_deref());
+        let admin_password_login = match env.flag("ADMIN_PASSWORD_LOGIN") {
+            Some(false) if oidc.is_none() => {
+                env.problem(
+                    "ADMIN_PASSWORD_LOGIN",
+                    "false",
+                    "true unless OIDC login is set up",
+                );
+                true
+            }
+            flag => flag.unwrap_or(true),
+        };
+
+        let fsck_interval_hours =
+            env.parse::<u64>("FSCK_INTERVAL_HOURS", "a positive number of hours", |&h| {
+                h > 0
+            });
+
+        let fsck_concurrency = env
+            .parse::<usize>("FSCK_CONCURRENCY", "a positive number", |&n| n > 0)
+            .unwrap_or(2);
+
+        let warmup_concurrency = env
+            .parse::<usize>("WARMUP_CONCURRENCY", "a positive number", |&n| n > 0)
+            .unwrap_or(4);
+
+        let init_concurrency = env
+            .parse::<usize>("INIT_CONCURRENCY", "a positive number", |&n| n > 0)
+            .unwrap_or(2);
+
+        let fsck_auto_reset = env.flag("FSCK_AUTO_RESET").unwrap_or(false);
+
+        let backup_dir = env
+            .non_empty("BACKUP_DIR")
+            .map(|v| resolve_path(PathBuf::from(v)));
+
+        let backup_interval_minutes = match env.non_empty("BACKUP_SCHEDULE") {
+            Some(raw) if backup_dir.is_none() => {
+                env.problem("BACKUP_SCHEDULE", &raw, "BACKUP_DIR to be set as well");
+                None
+            }
+            Some(raw) => parse_schedule(&raw).or_else(|| {
+                env.problem(
+                    "BACKUP_SCHEDULE",
+                    &raw,
+                    "`every N minutes|hours|days`, `hourly`, or `daily`",
+                );
+                None
+            }),
+            None => None,
+        };
+
+        let backup_retention = env
+            .parse::<usize>("BACKUP_RETENTION", "a positive number of backups", |&n| {
+                n > 0
+            })
+            .unwrap_or(7);
+
+        let backup_bundles = env.flag("BACKUP_BUNDLES").unwrap_or(true);
+
+        let prune_archive_dir = env
+            .non_empty("PRUNE_ARCHIVE_DIR")
+            .map(|v| resolve_path(PathBuf::from(v)));
+
+        let trash_retention_days = env
+            .parse::<u64>("TRASH_RETENTION_DAYS", "a number of days", |_| true)
+            .unwrap_or(14);
+
+        let shared_objects = env.flag("SHARED_OBJECTS").unwrap_or(false);
+        let shared_objects_min_bytes = env
+            .parse::<u64>(
+                "SHARED_OBJECTS_MIN_BYTES",
+                "a positive number of bytes",
+                |&n| n > 0,
+            )
+            .unwrap_or(1024 * 1024);
+
+        let access_log_max_bytes = env
+            .parse::<u64>("ACCESS_LOG_MAX_BYTES", "a number of bytes", |_| true)
+            .unwrap_or(10 * 1024 * 1024);
+        let access_log_files = env
+            .parse::<usize>("ACCESS_LOG_FILES", "a number of files", |_| true)
+            .unwrap_or(5);
+
+        let sync_skip_unchanged = env.flag("SYNC_SKIP_UNCHANGED").unwrap_or(true);
+        let serve_stale_on_sync_error = env.flag("SERVE_STALE_ON_SYNC_ERROR").unwrap_or(false);
+        let git_command_log = env.flag("GIT_COMMAND_LOG").unwrap_or(false);
+
+        let deploy_token_overlap_hours = env
+            .parse::<u64>(
+                "DEPLOY_TOKEN_OVERLAP_HOURS",
+                "a positive number of hours",
+                |&n| n > 0,
+            )
+            .unwrap_or(24);
+
+        let deploy_token_webhook_url = match env.non_empty("DEPLOY_TOKEN_WEBHOOK_URL") {
+            Some(url) if is_http_url(&url) => Some(url.trim().to_string()),
+            Some(url) => {
+                env.problem(
+                    "DEPLOY_TOKEN_WEBHOOK_URL",
+                    &url,
+                    "an http:// or https:// URL",
+                );
+                None
+            }
+            None => None,
+        };
+
+        let sync_event_webhook_url = match env.non_empty("SYNC_EVENT_WEBHOOK_URL") {
+            Some(url) if is_http_url(&url) => Some(url.trim().to_string()),
+            Some(url) => {
+                env.problem("SYNC_EVENT_WEBHOOK_URL", &url, "an http:// or https:// URL");
+                None
+            }
+            None => None,
+        };
+
+        let webhook_queue_max_age_hours = env
+            .parse::<u64>(
+                "WEBHOOK_QUEUE_MAX_AGE_HOURS",
+                "a positive number of hours",
+                |&n| n > 0,
+            )
+            .unwrap_or(24);
+        let webhook_queue_max_entries = env
+            .parse::<usize>("WEBHOOK_QUEUE_MAX_ENTRIES", "a positive number", |&n| n > 0)
+            .unwrap_or(1000);
+
+        let max_concurrent_requests = env
+            .parse::<usize>("MAX_CONCURRENT_REQUESTS", "a positive number", |&n| n > 0)
+            .unwrap_or(256);
+
+        let max_connections = env
+            .parse::<usize>("MAX_CONNECTIONS", "a positive number", |&n| n > 0)
+            .unwrap_or(1024);
+
+        let header_read_timeout_seconds = env
+            .parse::<u64>(
+                "HEADER_READ_TIMEOUT_SECONDS",
+                "a positive number of seconds",
+                |&s| s > 0,
+            )
+            .unwrap_or(30);
+
+        let git_daemon_port = env.parse::<u16>(
+            "GIT_DAEMON_PORT",
+            "a port number between 1 and 65535",
+            |&p| p > 0,
+        );
+
+        let git_daemon_max_connections = env
+            .parse::<usize>("GIT_DAEMON_MAX_CONNECTIONS", "a positive number", |&n| {
+                n > 0
+            })
+            .unwrap_or(64);
+
+        let idle_timeout_seconds = env
+            .parse::<u64>(
+                "IDLE_TIMEOUT_SECONDS",
+                "a positive number of seconds",
+                |&s| s > 0,
+            )
+            .unwrap_or(120);
+
+        let lock_wait_seconds = env
+            .parse::<u64>("LOCK_WAIT_SECONDS", "a positive number of seconds", |&s| {
+                s > 0
+            })
+            .unwrap_or(60);
+
+        let shutdown_grace_seconds = env
+            .parse::<u64>("SHUTDOWN_GRACE_SECONDS", "a number of seconds", |_| true)
+            .unwrap_or(30);
+
+        let git_backend_max_request_buffer = env
+            .non_empty("GIT_BACKEND_MAX_REQUEST_BUFFER")
+            .and_then(|raw| {
+                parse_byte_size(&raw).or_else(|| {
+                    env.problem(
+                        "GIT_BACKEND_MAX_REQUEST_BUFFER",
+                        &raw,
+                        "a positive size in bytes, optionally with k, m or g suffix",
+                    );
+                    None
+                })
+            });
+
+        let git_backend_trace_packet = env.flag("GIT_BACKEND_TRACE_PACKET").unwrap_or(false);
+
+        let git_timing_header = env.flag("GIT_TIMING_HEADER").unwrap_or(false);
+
+        let git_backend_extra_config = match env.non_empty("GIT_BACKEND_EXTRA_CONFIG") {
+            Some(raw) => parse_extra_git_config(&raw).unwrap_or_else(|| {
+                env.problem(
+                    "GIT_BACKEND_EXTRA_CONFIG",
+                    &raw,
+                    "comma-separated `section.key=value` entries (http.receivepack is not allowed)",
+                );
+                Vec::new()
+            }),
+            None => Vec::new(),
+        };
+
+        let git_hide_refs = match env.non_empty("GIT_HIDE_REFS") {
+            Some(raw) => raw
+                .split(',')
+                .map(str::trim)
+                .filter(|entry| !entry.is_empty())
+                .filter_map(|entry| {
+                    if !is_valid_hide_refs_entry(entry) {
+                        env.problem(
+                            "GIT_HIDE_REFS",
+                            entry,
+                            "comma-separated ref prefixes starting with `refs/` (optionally `!` or `^`)",
+                        );
+                        return None;
+                    }
+                    Some(entry.to_string())
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+        let bare_repo_git_config = match env.non_empty("BARE_REPO_GIT_CONFIG") {
+            Some(raw) => raw
+                .split([',', '\n'])
+                .map(str::trim)
+                .filter(|entry| !entry.is_empty())
+                .filter_map(|entry| {
+                    let parsed = parse_git_config_entry(entry);
+                    if parsed.is_none() {
+                        env.problem(
+                            "BARE_REPO_GIT_CONFIG",
+                            entry,
+                            "`section.key=value` entries separated by commas or newlines",
+                        );
+                    }
+                    parsed
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+        // an allowlist whose entries are all invalid refuses everyone
+        let git_allowed_cidrs = parse_cidr_list(&mut env, "GIT_ALLOWED_CIDRS");
+        let trusted_proxies = parse_cidr_list(&mut env, "TRUSTED_PROXIES").unwrap_or_default();
+
+        let cfg = Self {
             port,
             sharelatex_data_path,
             projects_dir,
+            projects_layout,
+            strict_project_ids,
+            sources,
+            source_name: None,
+            source_backend,
+            mongo_url,
+            docstore_url,
+            filestore_url,
+            user_files_dir,
             git_root,
+            git_root_layout,
             readonly_branch,
             admin_password,
+            admin_password_file,
+            admin_password_hash,
+            admin_api_token,
+            admins_file,
+            admin_accounts,
+            admin_totp_secret,
             admin_cookie_secure,
             admin_session_ttl_seconds,
-        }
+            admin_login_global_max_failures,
+            admin_assets_dir,
+            commit_message_template,
+            commit_date_from_source,
+            deterministic_init,
+            squash_window_minutes,
+            max_repo_bytes,
+            normalize_eol,
+            auth_global_realm,
+            tokens_bootstrap_file,
+            tokens_bootstrap_prune,
+            git_allow_dumb,
+            export_gitweb_metadata,
+            webhook_secret,
+            base_path,
+            public_url,
+            alert_webhook_url,
+            alert_failure_threshold,
+            alert_cooldown_minutes,
+            smtp,
+            oidc,
+            admin_password_login,
+            fsck_interval_hours,
+            fsck_concurrency,
+            warmup_concurrency,
+            init_concurrency,
+            fsck_auto_reset,
+            backup_dir,
+            backup_interval_minutes,
+            backup_retention,
+            backup_bundles,
+            prune_archive_dir,
+            trash_retention_days,
+            shared_objects,
+            shared_objects_min_bytes,
+            access_log_max_bytes,
+            access_log_files,
+            sync_skip_unchanged,
+            serve_stale_on_sync_error,
+            git_command_log,
+            deploy_token_overlap_hours,
+            deploy_token_webhook_url,
+            sync_event_webhook_url,
+            webhook_queue_max_age_hours,
+            webhook_queue_max_entries,
+            max_concurrent_requests,
+            max_connections,
+            header_read_timeout_seconds,
+            git_daemon_port,
+            git_daemon_max_connections,
+            idle_timeout_seconds,
+            lock_wait_seconds,
+            shutdown_grace_seconds,
+            git_backend_max_request_buffer,
+            git_backend_trace_packet,
+            git_timing_header,
+            git_backend_extra_config,
+            git_hide_refs,
+            bare_repo_git_config,
+            git_allowed_cidrs,
+            trusted_proxies,
+            provided_vars: env.provided,
+        };
+        (cfg, env.problems, strict || env.fatal)
     }
 
-    pub fn project_source_dir(&self, project_id: &str) -> PathBuf {
-        let base = self.sharelatex_data_path.join(&self.projects_dir);
-        let direct = base.join(project_id);
-        if direct.is_dir() {
-            return direct;
+    /// The configuration serving `source`: its data path and layout, read
+    /// from the filesystem, with mirrors and all per-project state in
+    /// `git_root/<name>`. Everything else is shared.
+    pub fn for_source(&self, source: &NamedSource) -> Config {
+        Config {
+            sharelatex_data_path: source.sharelatex_data_path.clone(),
+            projects_dir: source.projects_dir.clone(),
+            projects_layout: source.projects_layout,
+            sources: Vec::new(),
+            source_name: Some(source.name.clone()),
+            source_backend: SourceBackend::Filesystem,
+            user_files_dir: source.sharelatex_data_path.join("data/user_files"),
+            git_root: self.git_root.join(&source.name),
+            ..self.clone()
         }
+    }
 
-        let mut matches: Vec<PathBuf> = Vec::new();
-        if let Ok(entries) = fs::read_dir(&base) {
-            for entry in entries.flatten() {
-                if !entry.file_type().map(|ft| ft.is_dir()).unwrap_or(false) {
-                    continue;
-                }
-                let name = entry.file_name();
-                let name = name.to_string_lossy();
-                if let Some(rest) = name.strip_prefix(project_id) {
-                    if rest.starts_with('-') {
-                        matches.push(base.join(name.as_ref()));
-                    }
-                }
-            }
-        }
+    /// Name of the source this configuration serves.
+    pub fn source_label(&self) -> &str {
+        self.source_name.as_deref().unwrap_or(DEFAULT_SOURCE)
+    }
 
-        if matches.is_empty() {
-            return direct;
-        }
+    /// Whether the admin UI can be used: OIDC, an API token, or an admin
+    /// password, password hash or admin account with password login on.
+    pub fn admin_enabled(&self) -> bool {
+        self.oidc.is_some()
+            || self.admin_api_token.is_some()
+            || self.admin_password_login
+                && (self.admin_password.is_some()
+                    || self.admin_password_hash.is_some()
+                    || !self.admin_accounts.is_empty())
+    }
 
-        matches.sort();
-        let chosen = matches[0].clone();
-        if matches.len() > 1 {
-            warn!(
-                %project_id,
-                count = matches.len(),
-                chosen = %chosen.display(),
-                "multiple directories share project prefix; using first match"
-            );
+    /// Root of the project directories (`sharelatex_data_path/projects_dir`).
+    pub fn projects_root(&self) -> PathBuf {
+        self.sharelatex_data_path.join(&self.projects_dir)
+    }
+
+    /// Parse a project id taken from a request, the CLI or a listing; with
+    /// `STRICT_PROJECT_IDS` it also has to be a ShareLatex id.
+    pub fn parse_project_id(&self, raw: &str) -> Result<ProjectId, InvalidProjectId> {
+        ProjectId::with_format(raw, self.strict_project_ids)
+    }
+
+    /// Whether `id` passes `STRICT_PROJECT_IDS`, see [`Config::parse_project_id`].
+    pub fn accepts_project_id(&self, id: &ProjectId) -> bool {
+        !self.strict_project_ids || id.is_sharelatex()
+    }
+
+    /// ShareLatex source directory of a project in the flat layout. Falls
+    /// back to the first `<project_id>-<suffix>` directory when no exact
+    /// match exists. [`crate::FsProjectSource`] also handles the nested layout.
+    /// [`BridgeError::OutsideRoot`] if the directory is a symlink leading out
+    /// of the projects root.
+    pub fn project_source_dir(&self, project_id: &ProjectId) -> Result<PathBuf, BridgeError> {
+        let base = self.projects_root();
+        let dir = find_project_dir(&base, project_id).unwrap_or_else(|| base.join(project_id));
+        contained(&base, dir)
+    }
+
+    /// Directory of the bare mirrors: `git_root/repos`, or `git_root` itself
+    /// in the flat layout.
+    pub fn repos_dir(&self) -> PathBuf {
+        match self.git_root_layout {
+            GitRootLayout::Split => self.git_root.join("repos"),
+            GitRootLayout::Flat => self.git_root.clone(),
         }
+    }
 
-        chosen
+    /// Directory of the bridge's own files (tokens, settings, logs, locks,
+    /// trash): `git_root/state`, or `git_root` itself in the flat layout.
+    pub fn state_dir(&self) -> PathBuf {
+        self.git_root_layout.state_dir(&self.git_root)
     }
 
-    pub fn bare_repo_dir(&self, project_id: &str) -> PathBuf {
-        self.git_root.join(format!("{project_id}.git"))
+    /// Location of the bare mirror for a project; [`BridgeError::OutsideRoot`]
+    /// if it is a symlink leading out of [`Config::repos_dir`].
+    pub fn bare_repo_dir(&self, project_id: &ProjectId) -> Result<PathBuf, BridgeError> {
+        let repos = self.repos_dir();
+        let dir = repos.join(format!("{project_id}.git"));
+        contained(&repos, dir)
     }
 
+    /// Location of `tokens.json`.
     pub fn tokens_file(&self) -> PathBuf {
-        self.git_root.join("tokens.json")
+        self.state_dir().join("tokens.json")
     }
-}
 
-fn resolve_path(p: PathBuf) -> PathBuf {
-    if p.is_absolute() {
-        p
-    } else {
-        env::current_dir().map(|base| base.join(&p)).unwrap_or(p)
+    /// Lock file guarding changes of `tokens.json` across processes.
+    pub fn tokens_lock_file(&self) -> PathBuf {
+        self.state_dir().join(".tokens.lock")
+    }
+
+    /// Location of the instance-wide sync activity log.
+    pub fn activity_log_file(&self) -> PathBuf {
+        self.state_dir().join("activity.jsonl")
+    }
+
+    /// Location of the access log of git requests; rotated copies get a
+    /// `.1`, `.2`, ... suffix.
+    pub fn access_log_file(&self) -> PathBuf {
+        self.state_dir().join("access.jsonl")
+    }
+
+    /// Location of the audit log of admin actions.
+    pub fn audit_log_file(&self) -> PathBuf {
+        self.state_dir().join("audit.jsonl")
+    }
+
+    /// Location of the per-project deploy tokens.
+    pub fn deploy_tokens_file(&self) -> PathBuf {
+        self.state_dir().join("deploy-tokens.json")
+    }
+
+    /// Location of the webhook posts waiting for a retry.
+    pub fn webhook_queue_file(&self) -> PathBuf {
+        self.state_dir().join("webhook-queue.json")
+    }
+
+    /// Location of the daily per-token traffic totals.
+    pub fn token_usage_file(&self) -> PathBuf {
+        self.state_dir().join("token-usage.json")
+    }
+
+    /// Location of the repository list read by cgit and gitweb.
+    pub fn gitweb_projects_list(&self) -> PathBuf {
+        self.state_dir().join("projects.list")
+    }
+
+    /// Directory of the per-project lock files shared by every process using
+    /// this `git_root`.
+    pub fn lock_dir(&self) -> PathBuf {
+        self.state_dir().join(".locks")
+    }
+
+    /// Store of large blobs shared by all mirrors (`SHARED_OBJECTS`).
+    pub fn shared_objects_dir(&self) -> PathBuf {
+        self.git_root.join("shared-objects")
+    }
+
+    /// Directory holding removed mirrors until `TRASH_RETENTION_DAYS` passed.
+    pub fn trash_dir(&self) -> PathBuf {
+        self.state_dir().join(".trash")
+    }
+
+    /// Location of `project-settings.json` (admin-managed per-project settings).
+    pub fn project_settings_file(&self) -> PathBuf {
+        self.state_dir().join("project-settings.json")
+    }
+
+    /// Present while the bridge is in maintenance mode.
+    pub fn maintenance_file(&self) -> PathBuf {
+        self.state_dir().join("maintenance.json")
+    }
+
+    /// Prefix of generated links: `PUBLIC_URL`, or `BASE_PATH` for links
+    /// relative to whatever host the reader reached the bridge on.
+    pub fn link_base(&self) -> &str {
+        self.public_url.as_deref().unwrap_or(&self.base_path)
+    }
+
+    /// Clone URL of a project, absolute when `PUBLIC_URL` is set.
+    pub fn clone_url(&self, project_id: &str) -> String {
+        match &self.source_name {
+            Some(source) => format!("{}/git/{source}/{project_id}.git", self.link_base()),
+            None => format!("{}/git/{project_id}.git", self.link_base()),
+        }
+    }
+
+    /// Absolute clone URL of a project, if `PUBLIC_URL` is set.
+    pub fn public_clone_url(&self, project_id: &str) -> Option<String> {
+        self.public_url
+            .is_some()
+            .then(|| self.clone_url(project_id))
     }
 }
 
+#[cfg(test)]
 impl Config {
-    pub fn log_summary(&self) {
-        info!("config initialized");
-        info!("  port          : {}", self.port);
-        info!("  git_root      : {}", self.git_root.display());
-        info!("  sharelatex_root : {}", self.sharelatex_data_path.display());
-        info!("  projects_dir  : {}", self.projects_dir.display());
-        info!("  tokens_file   : {}", self.tokens_file().display());
-        info!("  readonly_branch: {}", self.readonly_branch);
-        if self.admin_password.is_some() {
-            info!("  admin_ui      : enabled");
-            info!(
+    /// Defaults with all data paths below `root`.
+    pub(crate) fn for_test(root: &std::path::Path) -> Self {
+        Self {
+            port: 0,
+            sharelatex_data_path: root.join("sharelatex"),
+            projects_dir: PathBuf::from("projects"),
+            projects_layout: ProjectsLayout::Flat,
+            strict_project_ids: false,
+            sources: Vec::new(),
+            source_name: None,
+            source_backend: SourceBackend::Filesystem,
+            mongo_url: None,
+            docstore_url: None,
+            filestore_url: None,
+            user_files_dir: root.join("user_files"),
+            git_root: root.join("git"),
+            git_root_layout: GitRootLayout::Split,
+            readonly_branch: "master".to_string(),
+            admin_password: None,
+            admin_password_file: None,
+            admin_password_hash: None,
+            admin_api_token: None,
+            admins_file: root.join("git/state/admins.json"),
+            admin_accounts: Vec::new(),
+            admin_totp_secret: None,
+            admin_cookie_secure: false,
+            admin_session_ttl_seconds: 3600,
+            admin_login_global_max_failures: 50,
+            admin_assets_dir: None,
+            commit_message_template: DEFAULT_COMMIT_MESSAGE_TEMPLATE.to_string(),
+            commit_date_from_source: false,
+            deterministic_init: false,
+            squash_window_minutes: 0,
+            max_repo_bytes: 0,
+            normalize_eol: false,
+            auth_global_realm: false,
+            tokens_bootstrap_file: None,
+            tokens_bootstrap_prune: false,
+            git_allow_dumb: false,
+            export_gitweb_metadata: false,
+            webhook_secret: None,
+            base_path: String::new(),
+            public_url: None,
+            alert_webhook_url: None,
+            alert_failure_threshold: 3,
+            alert_cooldown_minutes: 60,
+            smtp: None,
+            oidc: None,
+            admin_password_login: true,
+            fsck_interval_hours: None,
+            fsck_concurrency: 2,
+            warmup_concurrency: 4,
+            init_concurrency: 2,
+            fsck_auto_reset: false,
+            backup_dir: None,
+            backup_interval_minutes: None,
+            backup_retention: 7,
+            backup_bundles: true,
+            prune_archive_dir: None,
+            trash_retention_days: 14,
+            shared_objects: false,
+            shared_objects_min_bytes: 1024 * 1024,
+            access_log_max_bytes: 10 * 1024 * 1024,
+            access_log_files: 5,
+            sync_skip_unchanged: true,
+            serve_stale_on_sync_error: false,
+            git_command_log: false,
+            deploy_token_overlap_hours: 24,
+            deploy_token_webhook_url: None,
+            sync_event_webhook_url: None,
+            webhook_queue_max_age_hours: 24,
+            webhook_queue_max_entries: 1000,
+            max_concurrent_requests: 256,
+            max_connections: 1024,
+            header_read_timeout_seconds: 30,
+            git_daemon_port: None,
+            git_daemon_max_connections: 64,
+            idle_timeout_seconds: 120,
+            lock_wait_seconds: 60,
+            shutdown_grace_seconds: 30,
+            git_backend_max_request_buffer: None,
+            git_backend_trace_packet: false,
+            git_timing_header: false,
+            git_backend_extra_config: Vec::new(),
+            git_hide_refs: Vec::new(),
+            bare_repo_git_config: Vec::new(),
+            git_allowed_cidrs: None,
+            trusted_proxies: Vec::new(),
+            provided_vars: BTreeSet::new(),
+        }
+    }
+}
+
+/// Conservative subset of `git check-ref-format --branch`.
+fn is_valid_branch_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with(['-', '/', '.'])
+        && !name.ends_with(['/', '.'])
+        && !name.ends_with(".lock")
+        && !name.contains("..")
+        && !name.contains("//")
+        && !name.contains("@{")
+        && !name
+            .chars()
+            .any(|c| c.is_ascii_control() || " ~^:?*[\\".contains(c))
+}
+
+/// Every `{...}` in `template` names a known placeholder.
+fn uses_known_placeholders(template: &str) -> bool {
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let Some(len) = rest[start..].find('}') else {
+            return false;
+        };
+        if !COMMIT_MESSAGE_PLACEHOLDERS.contains(&&rest[start + 1..start + len]) {
+            return false;
+        }
+        rest = &rest[start + len + 1..];
+    }
+    true
+}
+
+/// `/prefix` without trailing slash (`/` becomes empty), or `None` if the
+/// value contains characters that do not belong in a URL path.
+fn normalize_base_path(raw: &str) -> Option<String> {
+    let trimmed = raw.trim().trim_end_matches('/');
+    if trimmed.is_empty() {
+        return Some(String::new());
+    }
+    let valid = trimmed.starts_with('/')
+        && !trimmed.contains("//")
+        && trimmed
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "/-._~".contains(c));
+    valid.then(|| trimmed.to_string())
+}
+
+fn parse_layout(env: &mut EnvReader, var: &str) -> Option<ProjectsLayout> {
+    let raw = env.non_empty(var)?;
+    let layout = ProjectsLayout::parse(&raw);
+    if layout.is_none() {
+        env.problem(var, &raw, "one of flat, nested, auto");
+    }
+    layout
+}
+
+/// Whether `name` can name a [`NamedSource`]: lowercase letters, digits,
+/// `-` and `_`, and neither [`DEFAULT_SOURCE`] nor one of the bridge's own
+/// file names in `git_root`.
+pub(crate) fn is_valid_source_name(name: &str) -> bool {
+    (1..=32).contains(&name.len())
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-' || b == b'_')
+        && name != DEFAULT_SOURCE
+        && ProjectId::new(name).is_ok()
+}
+
+/// `SOURCES`: comma-separated `name:path` entries. Invalid and repeated
+/// names are skipped.
+fn parse_sources(
+    env: &mut EnvReader,
+    projects_dir: &Path,
+    projects_layout: ProjectsLayout,
+) -> Vec<NamedSource> {
+    let Some(raw) = env.non_empty("SOURCES") else {
+        return Vec::new();
+    };
+    let mut sources: Vec<NamedSource> = Vec::new();
+    for entry in raw.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        let parsed = entry
+            .split_once(':')
+            .map(|(name, path)| (name.trim(), path.trim()))
+            .filter(|(name, path)| {
+                is_valid_source_name(name)
+                    && !path.is_empty()
+                    && !sources.iter().any(|s| s.name == *name)
+            });
+        let Some((name, path)) = parsed else {
+            env.problem(
+                "SOURCES",
+                entry,
+                "comma-separated `name:path` entries with distinct names of lowercase letters, digits, `-` and `_` (not `default`)",
+            );
+            continue;
+        };
+        let var = |setting: &str| {
+            format!(
+                "SOURCE_{}_{setting}",
+                name.to_ascii_uppercase().replace('-', "_")
+            )
+        };
+        let projects_dir = env
+            .non_empty(&var("PROJECTS_DIR"))
+            .map_or_else(|| projects_dir.to_path_buf(), PathBuf::from);
+        let projects_layout = parse_layout(env, &var("PROJECTS_LAYOUT")).unwrap_or(projects_layout);
+        sources.push(NamedSource {
+            name: name.to_string(),
+            sharelatex_data_path: resolve_path(PathBuf::from(path)),
+            projects_dir,
+            projects_layout,
+        });
+    }
+    sources
+}
+
+/// Comma-separated CIDR ranges in `var`; invalid entries are skipped.
+fn parse_cidr_list(env: &mut EnvReader, var: &str) -> Option<Vec<Cidr>> {
+    let raw = env.non_empty(var)?;
+    let cidrs = raw
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .filter_map(|entry| match entry.parse() {
+            Ok(cidr) => Some(cidr),
+            Err(_) => {
+                env.problem(
+                    var,
+                    entry,
+                    "comma-separated IP addresses or CIDR ranges such as 10.0.0.0/8",
+                );
+                None
+            }
+        })
+        .collect();
+    Some(cidrs)
+}
+
+/// Minutes described by a `BACKUP_SCHEDULE` value.
+fn parse_schedule(raw: &str) -> Option<u64> {
+    let raw = raw.trim().to_ascii_lowercase();
+    match raw.as_str() {
+        "hourly" => return Some(60),
+        "daily" => return Some(24 * 60),
+        _ => {}
+    }
+    let spec = raw.strip_prefix("every")?.trim();
+    let split = spec
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(spec.len());
+    let (count, unit) = spec.split_at(split);
+    let count: u64 = if count.is_empty() {
+        1
+    } else {
+        count.parse().ok()?
+    };
+    let minutes = match unit.trim() {
+        "m" | "min" | "minute" | "minutes" => 1,
+        "h" | "hour" | "hours" => 60,
+        "d" | "day" | "days" => 24 * 60,
+        _ => return None,
+    };
+    (count > 0).then_some(count * minutes)
+}
+
+/// Bytes described by a git-style size (`512k`, `100M`, `1g`).
+fn parse_byte_size(raw: &str) -> Option<u64> {
+    let raw = raw.trim().to_ascii_lowercase();
+    let factor: u64 = match raw.chars().last()? {
+        'k' => 1 << 10,
+        'm' => 1 << 20,
+        'g' => 1 << 30,
+        _ => 1,
+    };
+    let count = if factor == 1 {
+        raw.as_str()
+    } else {
+        &raw[..raw.len() - 1]
+    };
+    let count: u64 = count.parse().ok()?;
+    count.checked_mul(factor).filter(|&bytes| bytes > 0)
+}
+
+/// Whether git accepts `key` as a config key: a section and a name, with an
+/// optional subsection in between.
+pub(crate) fn is_valid_git_config_key(key: &str) -> bool {
+    let Some((section, rest)) = key.split_once('.') else {
+        return false;
+    };
+    let Some(name) = rest.rsplit('.').next() else {
+        return false;
+    };
+    let valid_section = !section.is_empty()
+        && section
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.');
+    let valid_name = name.starts_with(|c: char| c.is_ascii_alphabetic())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+    valid_section && valid_name && !key.contains(['\0', '\n'])
+}
+
+/// One `key=value` git config entry with a valid key and a single-line value.
+fn parse_git_config_entry(entry: &str) -> Option<(String, String)> {
+    let (key, value) = entry.split_once('=')?;
+    let (key, value) = (key.trim(), value.trim());
+    (is_valid_git_config_key(key) && !value.contains(['\0', '\n']))
+        .then(|| (key.to_string(), value.to_string()))
+}
+
+/// `key=value` pairs of `GIT_BACKEND_EXTRA_CONFIG`. Keys need a section and
+/// a name; `http.receivepack` is refused so the bridge stays read-only.
+fn parse_extra_git_config(raw: &str) -> Option<Vec<(String, String)>> {
+    raw.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            parse_git_config_entry(entry)
+                .filter(|(key, _)| !key.eq_ignore_ascii_case("http.receivepack"))
+        })
+        .collect()
+}
+
+/// A `transfer.hideRefs` value: a ref prefix below `refs/`, optionally
+/// negated with `!` and matched against the unstripped name with `^`.
+fn is_valid_hide_refs_entry(entry: &str) -> bool {
+    let prefix = entry.strip_prefix('!').unwrap_or(entry);
+    let prefix = prefix.strip_prefix('^').unwrap_or(prefix);
+    prefix.starts_with("refs/")
+        && !prefix.contains("..")
+        && !prefix.contains(|c: char| c.is_whitespace() || c.is_control())
+}
+
+fn is_http_url(raw: &str) -> bool {
+    let url = raw.trim();
+    let rest = url
+        .strip_prefix("https://")
+        .or_else(|| url.strip_prefix("http://"));
+    rest.is_some_and(|rest| !rest.is_empty() && !rest.contains(char::is_whitespace))
+}
+
+/// Whether `path` lies inside `root` once symlinks are resolved. A path
+/// that does not exist yet does unless it is a dangling symlink.
+pub(crate) fn is_contained(root: &Path, path: &Path) -> bool {
+    match path.canonicalize() {
+        Ok(resolved) => root
+            .canonicalize()
+            .is_ok_and(|root| resolved.starts_with(root)),
+        Err(_) => !path.is_symlink(),
+    }
+}
+
+/// `path` if it is [contained](is_contained) in `root`. Ids are single path
+/// components, so only a symlink placed into `root` can lead elsewhere.
+fn contained(root: &Path, path: PathBuf) -> Result<PathBuf, BridgeError> {
+    if is_contained(root, &path) {
+        Ok(path)
+    } else {
+        Err(BridgeError::OutsideRoot(path.display().to_string()))
+    }
+}
+
+/// Directory of `project_id` directly below `base`: `<project_id>` itself,
+/// else the first `<project_id>-<suffix>`. Symlinks leading out of `base`
+/// are ignored.
+pub(crate) fn find_project_dir(base: &Path, project_id: &str) -> Option<PathBuf> {
+    let direct = base.join(project_id);
+    if direct.is_dir() {
+        if is_contained(base, &direct) {
+            return Some(direct);
+        }
+        warn!(
+            %project_id,
+            path = %direct.display(),
+            "project directory points outside the projects directory, ignoring"
+        );
+    }
+
+    let mut matches: Vec<PathBuf> = Vec::new();
+    if let Ok(entries) = fs::read_dir(base) {
+        for entry in entries.flatten() {
+            if !entry.file_type().map(|ft| ft.is_dir()).unwrap_or(false) {
+                continue;
+            }
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            if let Some(rest) = name.strip_prefix(project_id)
+                && rest.starts_with('-')
+            {
+                matches.push(base.join(name.as_ref()));
+            }
+        }
+    }
+
+    if matches.is_empty() {
+        return None;
+    }
+
+    matches.sort();
+    let chosen = matches[0].clone();
+    if matches.len() > 1 {
+        warn!(
+            %project_id,
+            count = matches.len(),
+            chosen = %chosen.display(),
+            "multiple directories share project prefix; using first match"
+        );
+    }
+
+    Some(chosen)
+}
+
+/// Optional `http(s)://` URL without trailing slash; other values are
+/// recorded as problems.
+fn http_url_setting(env: &mut EnvReader, var: &str) -> Option<String> {
+    let raw = env.non_empty(var)?;
+    if is_http_url(&raw) {
+        Some(raw.trim().trim_end_matches('/').to_string())
+    } else {
+        env.problem(var, &raw, "an http:// or https:// URL");
+        None
+    }
+}
+
+fn is_mailbox(raw: &str) -> bool {
+    raw.trim().parse::<lettre::message::Mailbox>().is_ok()
+}
+
+/// Comma-separated entries of `raw`, trimmed, without empty ones.
+fn comma_list(raw: Option<String>) -> Vec<String> {
+    raw.unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// OIDC settings, or `None` when `OIDC_ISSUER_URL` is unset, the build has no
+/// `oidc` feature, or the client or the allowed users are missing.
+fn oidc_from_env(env: &mut EnvReader, public_url: Option<&str>) -> Option<OidcConfig> {
+    let issuer = env.non_empty("OIDC_ISSUER_URL")?;
+    if !cfg!(feature = "oidc") {
+        env.problem(
+            "OIDC_ISSUER_URL",
+            &issuer,
+            "unset (built without the oidc feature)",
+        );
+        return None;
+    }
+    if !is_http_url(&issuer) {
+        env.problem("OIDC_ISSUER_URL", &issuer, "an http:// or https:// URL");
+        return None;
+    }
+    let Some(client_id) = env.non_empty("OIDC_CLIENT_ID") else {
+        env.problem(
+            "OIDC_CLIENT_ID",
+            "",
+            "a client id when OIDC_ISSUER_URL is set",
+        );
+        return None;
+    };
+    let Some(client_secret) = env.raw("OIDC_CLIENT_SECRET").filter(|s| !s.is_empty()) else {
+        env.problem(
+            "OIDC_CLIENT_SECRET",
+            "",
+            "a client secret when OIDC_ISSUER_URL is set",
+        );
+        return None;
+    };
+    let redirect_url = match http_url_setting(env, "OIDC_REDIRECT_URL") {
+        Some(url) => url,
+        None => match public_url {
+            Some(base) => format!("{base}/admin/api/login/oidc/callback"),
+            None => {
+                env.problem(
+                    "OIDC_REDIRECT_URL",
+                    "",
+                    "the callback URL (or PUBLIC_URL) when OIDC_ISSUER_URL is set",
+                );
+                return None;
+            }
+        },
+    };
+    let scopes = env
+        .non_empty("OIDC_SCOPES")
+        .map(|s| s.split_whitespace().collect::<Vec<_>>().join(" "))
+        .unwrap_or_else(|| "openid email profile".to_string());
+    if !scopes.split(' ').any(|scope| scope == "openid") {
+        env.problem(
+            "OIDC_SCOPES",
+            &scopes,
+            "space-separated scopes including openid",
+        );
+        return None;
+    }
+    let allowed_groups = comma_list(env.non_empty("OIDC_ALLOWED_GROUPS"));
+    let allowed_emails: Vec<String> = comma_list(env.non_empty("OIDC_ALLOWED_EMAILS"))
+        .into_iter()
+        .map(|email| email.to_ascii_lowercase())
+        .collect();
+    // everyone the provider knows is rarely meant
+    if allowed_groups.is_empty() && allowed_emails.is_empty() {
+        env.problem(
+            "OIDC_ALLOWED_GROUPS",
+            "",
+            "groups or OIDC_ALLOWED_EMAILS addresses allowed to log in",
+        );
+        return None;
+    }
+    Some(OidcConfig {
+        issuer_url: issuer.trim().to_string(),
+        client_id: client_id.trim().to_string(),
+        client_secret,
+        redirect_url,
+        scopes,
+        allowed_groups,
+        allowed_emails,
+    })
+}
+
+/// SMTP settings, or `None` when `SMTP_HOST` is unset or the sender or
+/// recipients are missing or invalid.
+fn smtp_from_env(env: &mut EnvReader) -> Option<SmtpConfig> {
+    let host = env.non_empty("SMTP_HOST")?.trim().to_string();
+
+    let tls = match env.non_empty("SMTP_TLS") {
+        Some(raw) => match raw.trim().to_ascii_lowercase().as_str() {
+            "starttls" => SmtpTls::StartTls,
+            "tls" => SmtpTls::Tls,
+            "none" => SmtpTls::None,
+            _ => {
+                env.problem("SMTP_TLS", &raw, "one of starttls, tls, none");
+                SmtpTls::StartTls
+            }
+        },
+        None => SmtpTls::StartTls,
+    };
+    let port = env
+        .parse::<u16>("SMTP_PORT", "a port number between 1 and 65535", |&p| p > 0)
+        .unwrap_or(match tls {
+            SmtpTls::StartTls => 587,
+            SmtpTls::Tls => 465,
+            SmtpTls::None => 25,
+        });
+    let username = env.non_empty("SMTP_USERNAME");
+    let password = env.raw("SMTP_PASSWORD");
+
+    let from = match env.non_empty("SMTP_FROM") {
+        Some(from) if is_mailbox(&from) => from.trim().to_string(),
+        Some(from) => {
+            env.problem("SMTP_FROM", &from, "an e-mail address");
+            return None;
+        }
+        None => {
+            env.problem("SMTP_FROM", "", "a sender address when SMTP_HOST is set");
+            return None;
+        }
+    };
+    let to = match env.non_empty("ALERT_EMAIL_TO") {
+        Some(raw) => {
+            let to: Vec<String> = raw
+                .split(',')
+                .map(str::trim)
+                .filter(|to| !to.is_empty())
+                .map(str::to_string)
+                .collect();
+            if to.is_empty() || !to.iter().all(|to| is_mailbox(to)) {
+                env.problem("ALERT_EMAIL_TO", &raw, "comma-separated e-mail addresses");
+                return None;
+            }
+            to
+        }
+        None => {
+            env.problem("ALERT_EMAIL_TO", "", "recipients when SMTP_HOST is set");
+            return None;
+        }
+    };
+
+    Some(SmtpConfig {
+        host,
+        port,
+        tls,
+        username,
+        password,
+        from,
+        to,
+    })
+}
+
+fn resolve_path(p: PathBuf) -> PathBuf {
+    if p.is_absolute() {
+        p
+    } else {
+        env::current_dir().map(|base| base.join(&p)).unwrap_or(p)
+    }
+}
+
+impl Config {
+    /// Effective configuration as JSON for diagnostics. Every setting reports
+    /// its env variable and whether the value came from the environment or
+    /// the built-in default; secrets are reduced to "set"/"unset".
+    pub fn effective_json(&self) -> Value {
+        let entry = |var: &str, value: Value| {
+            let source = if self.provided_vars.contains(var) {
+                "env"
+            } else {
+                "default"
+            };
+            json!({ "env": var, "value": value, "source": source })
+        };
+        let secret = |v: &Option<String>| json!(if v.is_some() { "set" } else { "unset" });
+        let path = |p: &std::path::Path| json!(p.display().to_string());
+        let smtp = self.smtp.as_ref();
+
+        // Built entry by entry; a single `json!` this size exceeds the macro
+        // recursion limit.
+        let mut settings = serde_json::Map::new();
+        let mut setting = |key: &str, var: &str, value: Value| {
+            settings.insert(key.to_string(), entry(var, value));
+        };
+        setting("port", "PORT", json!(self.port));
+        setting(
+            "sharelatex_data_path",
+            "SHARELATEX_DATA_PATH",
+            path(&self.sharelatex_data_path),
+        );
+        setting("projects_dir", "PROJECTS_DIR", path(&self.projects_dir));
+        setting(
+            "projects_layout",
+            "PROJECTS_LAYOUT",
+            json!(self.projects_layout.as_str()),
+        );
+        setting(
+            "strict_project_ids",
+            "STRICT_PROJECT_IDS",
+            json!(self.strict_project_ids),
+        );
+        setting(
+            "sources",
+            "SOURCES",
+            json!(
+                self.sources
+                    .iter()
+                    .map(|s| json!({
+                        "name": s.name,
+                        "sharelatex_data_path": path(&s.sharelatex_data_path),
+                        "projects_dir": path(&s.projects_dir),
+                        "projects_layout": s.projects_layout.as_str(),
+                    }))
+                    .collect::<Vec<_>>()
+            ),
+        );
+        setting(
+            "source_backend",
+            "SOURCE_BACKEND",
+            json!(self.source_backend.as_str()),
+        );
+        setting("mongo_url", "MONGO_URL", secret(&self.mongo_url));
+        setting("docstore_url", "DOCSTORE_URL", json!(self.docstore_url));
+        setting("filestore_url", "FILESTORE_URL", json!(self.filestore_url));
+        setting(
+            "user_files_dir",
+            "USER_FILES_PATH",
+            path(&self.user_files_dir),
+        );
+        setting("git_root", "GIT_ROOT", path(&self.git_root));
+        setting(
+            "git_root_layout",
+            "GIT_ROOT_LAYOUT",
+            json!(self.git_root_layout.as_str()),
+        );
+        setting(
+            "readonly_branch",
+            "READONLY_BRANCH",
+            json!(self.readonly_branch),
+        );
+        setting(
+            "admin_password",
+            "ADMIN_PASSWORD",
+            secret(&self.admin_password),
+        );
+        setting(
+            "admin_password_file",
+            "ADMIN_PASSWORD_FILE",
+            json!(
+                self.admin_password_file
+                    .as_deref()
+                    .map(|p| p.display().to_string())
+            ),
+        );
+        setting(
+            "admin_password_hash",
+            "ADMIN_PASSWORD_HASH",
+            secret(&self.admin_password_hash),
+        );
+        setting(
+            "admin_api_token",
+            "ADMIN_API_TOKEN",
+            secret(&self.admin_api_token),
+        );
+        setting(
+            "admin_password_login",
+            "ADMIN_PASSWORD_LOGIN",
+            json!(self.admin_password_login),
+        );
+        let oidc = self.oidc.as_ref();
+        setting(
+            "oidc_issuer_url",
+            "OIDC_ISSUER_URL",
+            json!(oidc.map(|o| &o.issuer_url)),
+        );
+        setting(
+            "oidc_client_id",
+            "OIDC_CLIENT_ID",
+            json!(oidc.map(|o| &o.client_id)),
+        );
+        setting(
+            "oidc_client_secret",
+            "OIDC_CLIENT_SECRET",
+            secret(&oidc.map(|o| o.client_secret.clone())),
+        );
+        setting(
+            "oidc_redirect_url",
+            "OIDC_REDIRECT_URL",
+            json!(oidc.map(|o| &o.redirect_url)),
+        );
+        setting("oidc_scopes", "OIDC_SCOPES", json!(oidc.map(|o| &o.scopes)));
+        setting(
+            "oidc_allowed_groups",
+            "OIDC_ALLOWED_GROUPS",
+            json!(oidc.map(|o| &o.allowed_groups)),
+        );
+        setting(
+            "oidc_allowed_emails",
+            "OIDC_ALLOWED_EMAILS",
+            json!(oidc.map(|o| &o.allowed_emails)),
+        );
+        setting(
+            "admins_file",
+            "ADMINS_FILE",
+            json!(self.admins_file.display().to_string()),
+        );
+        setting(
+            "admin_totp_secret",
+            "ADMIN_TOTP_SECRET",
+            secret(&self.admin_totp_secret),
+        );
+        setting(
+            "admin_cookie_secure",
+            "ADMIN_COOKIE_SECURE",
+            json!(self.admin_cookie_secure),
+        );
+        setting(
+            "admin_session_ttl_seconds",
+            "ADMIN_SESSION_TTL_SECONDS",
+            json!(self.admin_session_ttl_seconds),
+        );
+        setting(
+            "admin_login_global_max_failures",
+            "ADMIN_LOGIN_GLOBAL_MAX_FAILURES",
+            json!(self.admin_login_global_max_failures),
+        );
+        setting(
+            "admin_assets_dir",
+            "ADMIN_ASSETS_DIR",
+            json!(
+                self.admin_assets_dir
+                    .as_deref()
+                    .map(|p| p.display().to_string())
+            ),
+        );
+        setting(
+            "commit_message_template",
+            "COMMIT_MESSAGE_TEMPLATE",
+            json!(self.commit_message_template),
+        );
+        setting(
+            "commit_date_from_source",
+            "COMMIT_DATE_FROM_SOURCE",
+            json!(self.commit_date_from_source),
+        );
+        setting(
+            "deterministic_init",
+            "DETERMINISTIC_INIT",
+            json!(self.deterministic_init),
+        );
+        setting(
+            "squash_window_minutes",
+            "SQUASH_WINDOW_MINUTES",
+            json!(self.squash_window_minutes),
+        );
+        setting(
+            "max_repo_bytes",
+            "MAX_REPO_BYTES",
+            json!(self.max_repo_bytes),
+        );
+        setting(
+            "normalize_eol",
+            "NORMALIZE_EOL",
+            json!(if self.normalize_eol { "lf" } else { "off" }),
+        );
+        setting(
+            "auth_global_realm",
+            "AUTH_GLOBAL_REALM",
+            json!(self.auth_global_realm),
+        );
+        setting(
+            "tokens_bootstrap_file",
+            "TOKENS_BOOTSTRAP_FILE",
+            json!(
+                self.tokens_bootstrap_file
+                    .as_deref()
+                    .map(|p| p.display().to_string())
+            ),
+        );
+        setting(
+            "tokens_bootstrap_prune",
+            "TOKENS_BOOTSTRAP_PRUNE",
+            json!(self.tokens_bootstrap_prune),
+        );
+        setting(
+            "git_allow_dumb",
+            "GIT_ALLOW_DUMB",
+            json!(self.git_allow_dumb),
+        );
+        setting(
+            "export_gitweb_metadata",
+            "EXPORT_GITWEB_METADATA",
+            json!(self.export_gitweb_metadata),
+        );
+        setting(
+            "webhook_secret",
+            "WEBHOOK_SECRET",
+            secret(&self.webhook_secret),
+        );
+        setting("base_path", "BASE_PATH", json!(self.base_path));
+        setting("public_url", "PUBLIC_URL", json!(self.public_url));
+        setting(
+            "alert_webhook_url",
+            "ALERT_WEBHOOK_URL",
+            secret(&self.alert_webhook_url),
+        );
+        setting(
+            "alert_failure_threshold",
+            "ALERT_FAILURE_THRESHOLD",
+            json!(self.alert_failure_threshold),
+        );
+        setting(
+            "alert_cooldown_minutes",
+            "ALERT_COOLDOWN_MINUTES",
+            json!(self.alert_cooldown_minutes),
+        );
+        setting("smtp_host", "SMTP_HOST", json!(smtp.map(|s| &s.host)));
+        setting("smtp_port", "SMTP_PORT", json!(smtp.map(|s| s.port)));
+        setting("smtp_tls", "SMTP_TLS", json!(smtp.map(|s| s.tls.as_str())));
+        setting(
+            "smtp_username",
+            "SMTP_USERNAME",
+            json!(smtp.and_then(|s| s.username.as_ref())),
+        );
+        setting(
+            "smtp_password",
+            "SMTP_PASSWORD",
+            secret(&smtp.and_then(|s| s.password.clone())),
+        );
+        setting("smtp_from", "SMTP_FROM", json!(smtp.map(|s| &s.from)));
+        setting(
+            "alert_email_to",
+            "ALERT_EMAIL_TO",
+            json!(smtp.map(|s| &s.to)),
+        );
+        setting(
+            "fsck_interval_hours",
+            "FSCK_INTERVAL_HOURS",
+            json!(self.fsck_interval_hours),
+        );
+        setting(
+            "fsck_concurrency",
+            "FSCK_CONCURRENCY",
+            json!(self.fsck_concurrency),
+        );
+        setting(
+            "warmup_concurrency",
+            "WARMUP_CONCURRENCY",
+            json!(self.warmup_concurrency),
+        );
+        setting(
+            "init_concurrency",
+            "INIT_CONCURRENCY",
+            json!(self.init_concurrency),
+        );
+        setting(
+            "fsck_auto_reset",
+            "FSCK_AUTO_RESET",
+            json!(self.fsck_auto_reset),
+        );
+        setting(
+            "backup_dir",
+            "BACKUP_DIR",
+            json!(self.backup_dir.as_deref().map(|p| p.display().to_string())),
+        );
+        setting(
+            "backup_interval_minutes",
+            "BACKUP_SCHEDULE",
+            json!(self.backup_interval_minutes),
+        );
+        setting(
+            "backup_retention",
+            "BACKUP_RETENTION",
+            json!(self.backup_retention),
+        );
+        setting(
+            "backup_bundles",
+            "BACKUP_BUNDLES",
+            json!(self.backup_bundles),
+        );
+        setting(
+            "prune_archive_dir",
+            "PRUNE_ARCHIVE_DIR",
+            json!(
+                self.prune_archive_dir
+                    .as_deref()
+                    .map(|p| p.display().to_string())
+            ),
+        );
+        setting(
+            "trash_retention_days",
+            "TRASH_RETENTION_DAYS",
+            json!(self.trash_retention_days),
+        );
+        setting(
+            "shared_objects",
+            "SHARED_OBJECTS",
+            json!(self.shared_objects),
+        );
+        setting(
+            "shared_objects_min_bytes",
+            "SHARED_OBJECTS_MIN_BYTES",
+            json!(self.shared_objects_min_bytes),
+        );
+        setting(
+            "access_log_max_bytes",
+            "ACCESS_LOG_MAX_BYTES",
+            json!(self.access_log_max_bytes),
+        );
+        setting(
+            "access_log_files",
+            "ACCESS_LOG_FILES",
+            json!(self.access_log_files),
+        );
+        setting(
+            "sync_skip_unchanged",
+            "SYNC_SKIP_UNCHANGED",
+            json!(self.sync_skip_unchanged),
+        );
+        setting(
+            "serve_stale_on_sync_error",
+            "SERVE_STALE_ON_SYNC_ERROR",
+            json!(self.serve_stale_on_sync_error),
+        );
+        setting(
+            "git_command_log",
+            "GIT_COMMAND_LOG",
+            json!(self.git_command_log),
+        );
+        setting(
+            "deploy_token_overlap_hours",
+            "DEPLOY_TOKEN_OVERLAP_HOURS",
+            json!(self.deploy_token_overlap_hours),
+        );
+        setting(
+            "deploy_token_webhook_url",
+            "DEPLOY_TOKEN_WEBHOOK_URL",
+            secret(&self.deploy_token_webhook_url),
+        );
+        setting(
+            "sync_event_webhook_url",
+            "SYNC_EVENT_WEBHOOK_URL",
+            secret(&self.sync_event_webhook_url),
+        );
+        setting(
+            "webhook_queue_max_age_hours",
+            "WEBHOOK_QUEUE_MAX_AGE_HOURS",
+            json!(self.webhook_queue_max_age_hours),
+        );
+        setting(
+            "webhook_queue_max_entries",
+            "WEBHOOK_QUEUE_MAX_ENTRIES",
+            json!(self.webhook_queue_max_entries),
+        );
+        setting(
+            "max_concurrent_requests",
+            "MAX_CONCURRENT_REQUESTS",
+            json!(self.max_concurrent_requests),
+        );
+        setting(
+            "max_connections",
+            "MAX_CONNECTIONS",
+            json!(self.max_connections),
+        );
+        setting(
+            "header_read_timeout_seconds",
+            "HEADER_READ_TIMEOUT_SECONDS",
+            json!(self.header_read_timeout_seconds),
+        );
+        setting(
+            "git_daemon_port",
+            "GIT_DAEMON_PORT",
+            json!(self.git_daemon_port),
+        );
+        setting(
+            "git_daemon_max_connections",
+            "GIT_DAEMON_MAX_CONNECTIONS",
+            json!(self.git_daemon_max_connections),
+        );
+        setting(
+            "idle_timeout_seconds",
+            "IDLE_TIMEOUT_SECONDS",
+            json!(self.idle_timeout_seconds),
+        );
+        setting(
+            "lock_wait_seconds",
+            "LOCK_WAIT_SECONDS",
+            json!(self.lock_wait_seconds),
+        );
+        setting(
+            "shutdown_grace_seconds",
+            "SHUTDOWN_GRACE_SECONDS",
+            json!(self.shutdown_grace_seconds),
+        );
+        setting(
+            "git_backend_max_request_buffer",
+            "GIT_BACKEND_MAX_REQUEST_BUFFER",
+            json!(self.git_backend_max_request_buffer),
+        );
+        setting(
+            "git_backend_trace_packet",
+            "GIT_BACKEND_TRACE_PACKET",
+            json!(self.git_backend_trace_packet),
+        );
+        setting(
+            "git_timing_header",
+            "GIT_TIMING_HEADER",
+            json!(self.git_timing_header),
+        );
+        setting(
+            "git_backend_extra_config",
+            "GIT_BACKEND_EXTRA_CONFIG",
+            json!(
+                self.git_backend_extra_config
+                    .iter()
+                    .map(|(k, v)| format!("{k}={v}"))
+                    .collect::<Vec<_>>()
+            ),
+        );
+        setting("git_hide_refs", "GIT_HIDE_REFS", json!(self.git_hide_refs));
+        let cidrs = |list: &[Cidr]| list.iter().map(Cidr::to_string).collect::<Vec<_>>();
+        setting(
+            "git_allowed_cidrs",
+            "GIT_ALLOWED_CIDRS",
+            json!(self.git_allowed_cidrs.as_deref().map(cidrs)),
+        );
+        setting(
+            "trusted_proxies",
+            "TRUSTED_PROXIES",
+            json!(cidrs(&self.trusted_proxies)),
+        );
+        setting(
+            "bare_repo_git_config",
+            "BARE_REPO_GIT_CONFIG",
+            json!(
+                self.bare_repo_git_config
+                    .iter()
+                    .map(|(k, v)| format!("{k}={v}"))
+                    .collect::<Vec<_>>()
+            ),
+        );
+
+        json!({
+            "settings": settings,
+            "derived": {
+                "projects_root": path(&self.projects_root()),
+                "tokens_file": path(&self.tokens_file()),
+                "project_settings_file": path(&self.project_settings_file()),
+                "activity_log_file": path(&self.activity_log_file()),
+                "token_usage_file": path(&self.token_usage_file()),
+                "deploy_tokens_file": path(&self.deploy_tokens_file()),
+                "audit_log_file": path(&self.audit_log_file()),
+                "gitweb_projects_list": path(&self.gitweb_projects_list()),
+                "lock_dir": path(&self.lock_dir()),
+                "trash_dir": path(&self.trash_dir()),
+                "bare_repo_pattern": path(&self.repos_dir().join("<projectId>.git")),
+            },
+        })
+    }
+
+    /// Log the effective configuration (without secrets).
+    pub fn log_summary(&self) {
+        info!("config initialized");
+        info!("  port          : {}", self.port);
+        info!(
+            "  git_root      : {} ({} layout)",
+            self.git_root.display(),
+            self.git_root_layout.as_str()
+        );
+        info!(
+            "  sharelatex_root : {}",
+            self.sharelatex_data_path.display()
+        );
+        match self.source_backend {
+            SourceBackend::Filesystem => info!(
+                "  projects_dir  : {} ({} layout)",
+                self.projects_dir.display(),
+                self.projects_layout.as_str()
+            ),
+            SourceBackend::Docstore => info!(
+                "  source        : docstore (files from {})",
+                self.user_files_dir.display()
+            ),
+        }
+        for source in &self.sources {
+            info!(
+                "  source {}   : {} ({}, {} layout)",
+                source.name,
+                source.sharelatex_data_path.display(),
+                source.projects_dir.display(),
+                source.projects_layout.as_str()
+            );
+        }
+        info!("  tokens_file   : {}", self.tokens_file().display());
+        info!("  readonly_branch: {}", self.readonly_branch);
+        if let Some(file) = &self.tokens_bootstrap_file {
+            info!(
+                "  token bootstrap: {}{}",
+                file.display(),
+                if self.tokens_bootstrap_prune {
+                    ", pruning other tokens"
+                } else {
+                    ""
+                }
+            );
+        }
+        if self.auth_global_realm {
+            info!("  auth realm    : global");
+        }
+        if self.git_allow_dumb {
+            info!("  dumb http     : object files served");
+        }
+        if self.max_repo_bytes > 0 {
+            info!(
+                "  repo limit    : {} bytes, larger mirrors are not synced",
+                self.max_repo_bytes
+            );
+        }
+        if self.squash_window_minutes > 0 {
+            info!(
+                "  squash window : {} min, unfetched sync commits are amended",
+                self.squash_window_minutes
+            );
+        }
+        if let Some(port) = self.git_daemon_port {
+            info!(
+                "  git daemon    : port {port}, public projects only, {} connections",
+                self.git_daemon_max_connections
+            );
+        }
+        if self.export_gitweb_metadata {
+            info!(
+                "  gitweb export : {}",
+                self.gitweb_projects_list().display()
+            );
+        }
+        info!(
+            "  webhook secret: {}",
+            if self.webhook_secret.is_some() {
+                "set (projects may override)"
+            } else {
+                "unset (per-project secrets only)"
+            }
+        );
+        if !self.base_path.is_empty() {
+            info!("  base_path     : {}", self.base_path);
+        }
+        let mut alert_channels = Vec::new();
+        if self.alert_webhook_url.is_some() {
+            alert_channels.push("webhook".to_string());
+        }
+        if let Some(smtp) = &self.smtp {
+            alert_channels.push(format!("smtp via {}:{}", smtp.host, smtp.port));
+        }
+        if !alert_channels.is_empty() {
+            info!(
+                "  alerts        : {} after {} failed syncs",
+                alert_channels.join(", "),
+                self.alert_failure_threshold
+            );
+        }
+        if let Some(hours) = self.fsck_interval_hours {
+            info!(
+                "  fsck          : every {hours}h, {} at a time{}",
+                self.fsck_concurrency,
+                if self.fsck_auto_reset {
+                    ", auto-reset"
+                } else {
+                    ""
+                }
+            );
+        }
+        if let Some(dir) = &self.backup_dir {
+            let schedule = match self.backup_interval_minutes {
+                Some(minutes) => format!("every {minutes} min"),
+                None => "on demand".to_string(),
+            };
+            info!(
+                "  backups       : {} ({schedule}, keep {})",
+                dir.display(),
+                self.backup_retention
+            );
+        }
+        if let Some(dir) = &self.prune_archive_dir {
+            info!("  prune archive : {}", dir.display());
+        }
+        match self.trash_retention_days {
+            0 => info!("  trash         : off, removed mirrors are deleted"),
+            days => info!(
+                "  trash         : {} for {days} days",
+                self.trash_dir().display()
+            ),
+        }
+        if self.shared_objects {
+            info!(
+                "  shared objects: {}, blobs of {} bytes or more",
+                self.shared_objects_dir().display(),
+                self.shared_objects_min_bytes
+            );
+        }
+        if self.access_log_max_bytes > 0 {
+            info!(
+                "  access log    : {}, rotated at {} bytes, {} kept",
+                self.access_log_file().display(),
+                self.access_log_max_bytes,
+                self.access_log_files
+            );
+        }
+        info!(
+            "  deploy tokens : {}h overlap{}",
+            self.deploy_token_overlap_hours,
+            if self.deploy_token_webhook_url.is_some() {
+                ", rotations posted to webhook"
+            } else {
+                ""
+            }
+        );
+        if self.sync_event_webhook_url.is_some() {
+            info!("  sync events   : posted to webhook");
+        }
+        info!(
+            "  webhook queue : up to {} posts, retried for {}h",
+            self.webhook_queue_max_entries, self.webhook_queue_max_age_hours
+        );
+        if self.normalize_eol {
+            info!("  line endings  : CRLF normalized to LF in text files");
+        }
+        info!(
+            "  limits        : {} requests, {} connections",
+            self.max_concurrent_requests, self.max_connections
+        );
+        info!(
+            "  timeouts      : headers {}s, idle {}s, lock wait {}s, shutdown grace {}s",
+            self.header_read_timeout_seconds,
+            self.idle_timeout_seconds,
+            self.lock_wait_seconds,
+            self.shutdown_grace_seconds
+        );
+        if let Some(bytes) = self.git_backend_max_request_buffer {
+            info!("  backend buffer: {bytes} bytes");
+        }
+        if !self.git_backend_extra_config.is_empty() {
+            let keys: Vec<&str> = self
+                .git_backend_extra_config
+                .iter()
+                .map(|(key, _)| key.as_str())
+                .collect();
+            info!("  backend config: {}", keys.join(", "));
+        }
+        if !self.git_hide_refs.is_empty() {
+            info!("  hidden refs   : {}", self.git_hide_refs.join(", "));
+        }
+        if let Some(cidrs) = &self.git_allowed_cidrs {
+            let cidrs: Vec<String> = cidrs.iter().map(Cidr::to_string).collect();
+            info!("  git clients   : {}", cidrs.join(", "));
+        }
+        if !self.trusted_proxies.is_empty() {
+            let proxies: Vec<String> = self.trusted_proxies.iter().map(Cidr::to_string).collect();
+            info!("  proxies       : {}", proxies.join(", "));
+        }
+        if !self.bare_repo_git_config.is_empty() {
+            let keys: Vec<&str> = self
+                .bare_repo_git_config
+                .iter()
+                .map(|(key, _)| key.as_str())
+                .collect();
+            info!("  repo config   : {}", keys.join(", "));
+        }
+        if self.git_timing_header {
+            info!("  timing header : on (X-GitBridge-Timing)");
+        }
+        if self.git_backend_trace_packet {
+            warn!(
+                "  GIT_BACKEND_TRACE_PACKET is on: every fetch logs its full protocol exchange; \
+                 turn it off once debugging is done"
+            );
+        }
+        if !cfg!(feature = "admin-ui") {
+            info!("  admin_ui      : not compiled in (admin-ui feature disabled)");
+        } else if self.admin_enabled() {
+            info!("  admin_ui      : enabled");
+            info!(
+                "  password      : {}",
+                if !self.admin_password_login {
+                    "disabled (ADMIN_PASSWORD_LOGIN)"
+                } else if self.admin_password_hash.is_some() {
+                    "argon2 hash (ADMIN_PASSWORD_HASH)"
+                } else if self.admin_password_file.is_some() {
+                    "plain text (ADMIN_PASSWORD_FILE)"
+                } else if self.admin_password.is_some() {
+                    "plain text (ADMIN_PASSWORD)"
+                } else {
+                    "none, accounts only"
+                }
+            );
+            let managers = self
+                .admin_accounts
+                .iter()
+                .filter(|a| a.role == AdminRole::Manager)
+                .count();
+            info!(
+                "  accounts      : {} ({} managers) from {}",
+                self.admin_accounts.len(),
+                managers,
+                self.admins_file.display()
+            );
+            match &self.oidc {
+                Some(oidc) => info!(
+                    "  oidc          : {} ({} groups, {} e-mail addresses allowed)",
+                    oidc.issuer_url,
+                    oidc.allowed_groups.len(),
+                    oidc.allowed_emails.len()
+                ),
+                None => info!("  oidc          : off"),
+            }
+            info!(
+                "  api token     : {}",
+                if self.admin_api_token.is_some() {
+                    "set (ADMIN_API_TOKEN)"
+                } else {
+                    "none"
+                }
+            );
+            info!(
+                "  second factor : {}",
+                if self.admin_totp_secret.is_some() {
+                    "TOTP (ADMIN_TOTP_SECRET)"
+                } else {
+                    "none"
+                }
+            );
+            info!(
                 "  cookie secure : {}",
                 if self.admin_cookie_secure {
                     "on"
@@ -148,8 +2842,560 @@ impl Config {
                 "  session ttl   : {} seconds",
                 self.admin_session_ttl_seconds
             );
+            match self.admin_login_global_max_failures {
+                0 => info!("  login throttle: 5 failures per client and minute"),
+                max => info!("  login throttle: 5 failures per client, {max} in total per minute"),
+            }
+            if let Some(file) = &self.admin_password_file {
+                info!("  password file : {} (reloaded on change)", file.display());
+            }
+            if let Some(dir) = &self.admin_assets_dir {
+                info!("  assets dir    : {}", dir.display());
+            }
         } else {
-            info!("  admin_ui      : disabled (no ADMIN_PASSWORD)");
+            info!(
+                "  admin_ui      : disabled (no ADMIN_PASSWORD, ADMIN_PASSWORD_HASH or admin accounts)"
+            );
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::project_id::pid;
+    use std::collections::HashMap;
+
+    fn load_with(vars: &[(&str, &str)]) -> Result<Config, ConfigError> {
+        let map: HashMap<String, String> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        Config::try_from_lookup(&|var| map.get(var).cloned())
+    }
+
+    #[test]
+    fn lenient_mode_falls_back_to_defaults() {
+        let cfg = load_with(&[("PORT", "80x"), ("ADMIN_SESSION_TTL_SECONDS", "0")]).unwrap();
+        assert_eq!(cfg.port, 8022);
+        assert_eq!(cfg.admin_session_ttl_seconds, 3600);
+    }
+
+    #[test]
+    fn strict_mode_reports_all_problems() {
+        let err = load_with(&[
+            ("STRICT_CONFIG", "true"),
+            ("PORT", "80x"),
+            ("ADMIN_SESSION_TTL_SECONDS", "0"),
+            ("ADMIN_COOKIE_SECURE", "maybe"),
+            ("READONLY_BRANCH", "bad..name"),
+        ])
+        .unwrap_err();
+        let vars: Vec<&str> = err.problems.iter().map(|p| p.var.as_str()).collect();
+        assert_eq!(
+            vars,
+            [
+                "PORT",
+                "READONLY_BRANCH",
+                "ADMIN_COOKIE_SECURE",
+                "ADMIN_SESSION_TTL_SECONDS"
+            ]
+        );
+        let msg = err.to_string();
+        assert!(msg.contains(r#"PORT="80x" (expected a port number between 1 and 65535)"#));
+    }
+
+    #[test]
+    fn effective_json_redacts_secrets_and_reports_sources() {
+        let cfg = load_with(&[("PORT", "9000"), ("ADMIN_PASSWORD", "hunter2")]).unwrap();
+        let json = cfg.effective_json();
+        assert_eq!(json["settings"]["port"]["source"], "env");
+        assert_eq!(json["settings"]["git_root"]["source"], "default");
+        assert_eq!(json["settings"]["admin_password"]["value"], "set");
+        assert!(!json.to_string().contains("hunter2"));
+        assert!(
+            json["derived"]["tokens_file"]
+                .as_str()
+                .unwrap()
+                .ends_with("tokens.json")
+        );
+    }
+
+    #[test]
+    fn admin_password_file_is_read_and_conflicts_with_plain_password() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let file = dir.path().join("pw");
+        fs::write(&file, "from-file\n").unwrap();
+        let file = file.to_str().unwrap();
+
+        let cfg = load_with(&[("ADMIN_PASSWORD_FILE", file)]).unwrap();
+        assert_eq!(cfg.admin_password.as_deref(), Some("from-file"));
+
+        let err = load_with(&[
+            ("STRICT_CONFIG", "true"),
+            ("ADMIN_PASSWORD", "x"),
+            ("ADMIN_PASSWORD_FILE", file),
+        ])
+        .unwrap_err();
+        assert_eq!(err.problems[0].var, "ADMIN_PASSWORD_FILE");
+    }
+
+    #[test]
+    fn admin_password_hash_conflicts_with_plain_passwords_even_when_lenient() {
+        let hash = "$argon2id$v=19$m=19456,t=2,p=1$Z2l0YnJpZGdlLXNhbHQ$\
+                    3a+ZFXbNrrwpQ3cxfbn1ZOIdVJFU3hNlWsJ+7hTN4mU";
+        let cfg = load_with(&[("ADMIN_PASSWORD_HASH", hash)]).unwrap();
+        assert_eq!(cfg.admin_password_hash.as_deref(), Some(hash));
+        assert!(cfg.admin_enabled());
+        assert_eq!(
+            cfg.effective_json()["settings"]["admin_password_hash"]["value"],
+            "set"
+        );
+
+        let err = load_with(&[("ADMIN_PASSWORD_HASH", hash), ("ADMIN_PASSWORD", "x")]).unwrap_err();
+        assert_eq!(err.problems[0].var, "ADMIN_PASSWORD");
+        assert!(!err.to_string().contains("\"x\""));
+        // an empty ADMIN_PASSWORD is the documented way to leave it unset
+        let cfg = load_with(&[("ADMIN_PASSWORD_HASH", hash), ("ADMIN_PASSWORD", "")]).unwrap();
+        assert_eq!(cfg.admin_password, None);
+
+        // lenient loading skips a hash it cannot use
+        if !cfg!(feature = "admin-ui") {
+            return;
+        }
+        let cfg = load_with(&[("ADMIN_PASSWORD_HASH", "plain"), ("ADMIN_PASSWORD", "x")]).unwrap();
+        assert_eq!(cfg.admin_password_hash, None);
+        assert_eq!(cfg.admin_password.as_deref(), Some("x"));
+    }
+
+    #[test]
+    fn admin_accounts_come_from_the_admins_file() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let hash = "$argon2id$v=19$m=19456,t=2,p=1$Z2l0YnJpZGdlLXNhbHQ$\
+                    3a+ZFXbNrrwpQ3cxfbn1ZOIdVJFU3hNlWsJ+7hTN4mU";
+        let path = dir.path().join("admins.json");
+        let write = |admins: Value| fs::write(&path, json!({ "admins": admins }).to_string());
+        let file = path.to_str().unwrap();
+
+        write(json!([
+            {"username": "ada", "password_hash": hash, "role": "manager"},
+            {"username": "bob", "password_hash": hash, "role": "viewer"},
+        ]))
+        .unwrap();
+        let cfg = load_with(&[("ADMINS_FILE", file)]).unwrap();
+        assert!(cfg.admin_enabled());
+        assert_eq!(cfg.admin_accounts.len(), 2);
+        assert_eq!(cfg.admin_accounts[1].username, "bob");
+        assert_eq!(cfg.admin_accounts[1].role, AdminRole::Viewer);
+
+        // the default next to tokens.json is optional, a named file is not
+        let root = dir.path().to_str().unwrap();
+        let cfg = load_with(&[("GIT_ROOT", root)]).unwrap();
+        assert!(!cfg.admin_enabled());
+        assert_eq!(cfg.admins_file, cfg.state_dir().join("admins.json"));
+        let missing = dir.path().join("missing.json");
+        let err = load_with(&[("ADMINS_FILE", missing.to_str().unwrap())]).unwrap_err();
+        assert_eq!(err.problems[0].var, "ADMINS_FILE");
+
+        write(json!([
+            {"username": "ada", "password_hash": hash, "role": "manager"},
+            {"username": "ada", "password_hash": hash, "role": "viewer"},
+        ]))
+        .unwrap();
+        let err = load_with(&[("ADMINS_FILE", file)]).unwrap_err();
+        assert!(err.to_string().contains("duplicate username"), "{err}");
+        write(json!([{"username": "ada", "password_hash": hash, "role": "owner"}])).unwrap();
+        assert!(load_with(&[("ADMINS_FILE", file)]).is_err());
+
+        if !cfg!(feature = "admin-ui") {
+            return;
+        }
+        write(json!([{"username": "ada", "password_hash": "plain", "role": "viewer"}])).unwrap();
+        let err = load_with(&[("ADMINS_FILE", file)]).unwrap_err();
+        assert!(err.to_string().contains("invalid password_hash"), "{err}");
+    }
+
+    #[test]
+    fn oidc_login_needs_the_feature_a_client_and_allowed_users() {
+        let oidc = [
+            ("OIDC_ISSUER_URL", "https://sso.example.org/realms/main"),
+            ("OIDC_CLIENT_ID", "gitbridge"),
+            ("OIDC_CLIENT_SECRET", "s3cret"),
+            ("OIDC_ALLOWED_GROUPS", "latex-admins, ops"),
+            ("OIDC_ALLOWED_EMAILS", "Ada@Example.org"),
+            ("PUBLIC_URL", "https://git.example.org/bridge"),
+            ("ADMIN_PASSWORD_LOGIN", "false"),
+        ];
+        let mut strict = vec![("STRICT_CONFIG", "1")];
+        strict.extend(oidc);
+        if !cfg!(feature = "oidc") {
+            let err = load_with(&strict).unwrap_err();
+            assert_eq!(err.problems[0].var, "OIDC_ISSUER_URL");
+            // without OIDC the password login stays on
+            let cfg = load_with(&oidc).unwrap();
+            assert!(cfg.oidc.is_none() && cfg.admin_password_login);
+            return;
+        }
+
+        let cfg = load_with(&strict).unwrap();
+        let settings = cfg.oidc.as_ref().unwrap();
+        assert_eq!(
+            settings.redirect_url,
+            "https://git.example.org/bridge/admin/api/login/oidc/callback"
+        );
+        assert_eq!(settings.scopes, "openid email profile");
+        assert_eq!(settings.allowed_groups, ["latex-admins", "ops"]);
+        assert_eq!(settings.allowed_emails, ["ada@example.org"]);
+        assert!(!cfg.admin_password_login && cfg.admin_enabled());
+        assert_eq!(
+            cfg.effective_json()["settings"]["oidc_client_secret"]["value"],
+            "set"
+        );
+
+        for (var, missing) in [
+            ("OIDC_CLIENT_SECRET", "OIDC_CLIENT_SECRET"),
+            ("OIDC_ALLOWED_GROUPS", "OIDC_ALLOWED_GROUPS"),
+            ("PUBLIC_URL", "OIDC_REDIRECT_URL"),
+        ] {
+            let mut vars: Vec<_> = oidc.iter().copied().filter(|(v, _)| *v != var).collect();
+            if var == "OIDC_ALLOWED_GROUPS" {
+                vars.retain(|(v, _)| *v != "OIDC_ALLOWED_EMAILS");
+            }
+            let cfg = load_with(&vars).unwrap();
+            assert!(cfg.oidc.is_none(), "{var}");
+            assert!(cfg.admin_password_login, "{var}");
+            vars.push(("STRICT_CONFIG", "1"));
+            assert_eq!(load_with(&vars).unwrap_err().problems[0].var, missing);
+        }
+    }
+
+    #[test]
+    fn a_short_admin_api_token_fails_even_when_lenient() {
+        let key = "0123456789abcdef0123456789abcdef";
+        let cfg = load_with(&[("ADMIN_API_TOKEN", key)]).unwrap();
+        assert_eq!(cfg.admin_api_token.as_deref(), Some(key));
+        assert!(cfg.admin_enabled());
+        assert_eq!(
+            cfg.effective_json()["settings"]["admin_api_token"]["value"],
+            "set"
+        );
+        let err = load_with(&[("ADMIN_API_TOKEN", "secret")]).unwrap_err();
+        assert_eq!(err.problems[0].var, "ADMIN_API_TOKEN");
+        assert!(!err.to_string().contains("secret"));
+    }
+
+    #[test]
+    fn a_bad_totp_secret_fails_even_when_lenient() {
+        let secret = "JBSW Y3DP EHPK 3PXP JBSW Y3DP";
+        let cfg = load_with(&[("ADMIN_PASSWORD", "pw"), ("ADMIN_TOTP_SECRET", secret)]).unwrap();
+        assert_eq!(cfg.admin_totp_secret.as_deref(), Some(secret));
+        assert_eq!(
+            cfg.effective_json()["settings"]["admin_totp_secret"]["value"],
+            "set"
+        );
+
+        if !cfg!(feature = "admin-ui") {
+            return;
+        }
+        for bad in ["JBSWY3DP", "not-base32-at-all!"] {
+            let err =
+                load_with(&[("ADMIN_PASSWORD", "pw"), ("ADMIN_TOTP_SECRET", bad)]).unwrap_err();
+            assert_eq!(err.problems[0].var, "ADMIN_TOTP_SECRET");
+            assert!(!err.to_string().contains(bad));
+        }
+    }
+
+    #[test]
+    fn strict_mode_accepts_valid_values() {
+        let cfg = load_with(&[
+            ("STRICT_CONFIG", "1"),
+            ("PORT", "9000"),
+            ("ADMIN_COOKIE_SECURE", "off"),
+            ("READONLY_BRANCH", "main"),
+            ("NORMALIZE_EOL", "LF"),
+        ])
+        .unwrap();
+        assert_eq!(cfg.port, 9000);
+        assert!(!cfg.admin_cookie_secure);
+        assert_eq!(cfg.readonly_branch, "main");
+        assert!(cfg.normalize_eol);
+
+        let err = load_with(&[("STRICT_CONFIG", "1"), ("NORMALIZE_EOL", "crlf")]).unwrap_err();
+        assert_eq!(err.problems[0].var, "NORMALIZE_EOL");
+    }
+
+    #[test]
+    fn base_path_is_normalized() {
+        assert_eq!(
+            load_with(&[("BASE_PATH", "/git-bridge/")])
+                .unwrap()
+                .base_path,
+            "/git-bridge"
+        );
+        assert_eq!(load_with(&[("BASE_PATH", "/")]).unwrap().base_path, "");
+        let err = load_with(&[("STRICT_CONFIG", "1"), ("BASE_PATH", "bridge\"><x")]).unwrap_err();
+        assert_eq!(err.problems[0].var, "BASE_PATH");
+    }
+
+    #[test]
+    fn commit_message_template_placeholders_are_checked() {
+        let cfg = load_with(&[(
+            "COMMIT_MESSAGE_TEMPLATE",
+            "{project_alias}: sync at {timestamp}",
+        )])
+        .unwrap();
+        assert_eq!(
+            cfg.commit_message_template,
+            "{project_alias}: sync at {timestamp}"
+        );
+
+        let err = load_with(&[
+            ("STRICT_CONFIG", "1"),
+            ("COMMIT_MESSAGE_TEMPLATE", "sync {project_name}"),
+        ])
+        .unwrap_err();
+        assert_eq!(err.problems[0].var, "COMMIT_MESSAGE_TEMPLATE");
+    }
+
+    #[test]
+    fn smtp_requires_sender_and_recipients() {
+        let cfg = load_with(&[
+            ("SMTP_HOST", "mail.example.org"),
+            ("SMTP_TLS", "tls"),
+            ("SMTP_FROM", "Git Bridge <bridge@example.org>"),
+            ("ALERT_EMAIL_TO", "ops@example.org, admin@example.org"),
+        ])
+        .unwrap();
+        let smtp = cfg.smtp.unwrap();
+        assert_eq!((smtp.port, smtp.tls), (465, SmtpTls::Tls));
+        assert_eq!(smtp.to.len(), 2);
+
+        let err = load_with(&[
+            ("STRICT_CONFIG", "1"),
+            ("SMTP_HOST", "mail.example.org"),
+            ("SMTP_FROM", "bridge@example.org"),
+            ("ALERT_EMAIL_TO", "not an address"),
+        ])
+        .unwrap_err();
+        assert_eq!(err.problems[0].var, "ALERT_EMAIL_TO");
+    }
+
+    #[test]
+    fn docstore_backend_needs_the_feature_and_mongodb() {
+        let err = load_with(&[("STRICT_CONFIG", "1"), ("SOURCE_BACKEND", "docstore")]).unwrap_err();
+        let expected = if cfg!(feature = "docstore") {
+            "MONGO_URL"
+        } else {
+            "SOURCE_BACKEND"
+        };
+        assert_eq!(err.problems[0].var, expected);
+
+        let cfg = load_with(&[
+            ("SOURCE_BACKEND", "docstore"),
+            ("MONGO_URL", "mongodb://mongo/sharelatex"),
+            ("FILESTORE_URL", "http://filestore:3009/"),
+        ])
+        .unwrap();
+        assert_eq!(cfg.filestore_url.as_deref(), Some("http://filestore:3009"));
+        assert_eq!(
+            cfg.source_backend == SourceBackend::Docstore,
+            cfg!(feature = "docstore")
+        );
+    }
+
+    #[test]
+    fn backup_schedules_are_parsed() {
+        assert_eq!(parse_schedule("every 6 hours"), Some(360));
+        assert_eq!(parse_schedule("every 30m"), Some(30));
+        assert_eq!(parse_schedule("Every day"), Some(1440));
+        assert_eq!(parse_schedule("daily"), Some(1440));
+        assert_eq!(parse_schedule("every 0 hours"), None);
+        assert_eq!(parse_schedule("0 3 * * *"), None);
+
+        let err = load_with(&[("STRICT_CONFIG", "1"), ("BACKUP_SCHEDULE", "hourly")]).unwrap_err();
+        assert_eq!(err.problems[0].var, "BACKUP_SCHEDULE");
+    }
+
+    #[test]
+    fn git_backend_tuning_is_validated() {
+        let cfg = load_with(&[
+            ("GIT_BACKEND_MAX_REQUEST_BUFFER", "100M"),
+            ("GIT_BACKEND_TRACE_PACKET", "on"),
+            (
+                "GIT_BACKEND_EXTRA_CONFIG",
+                "pack.threads=2, uploadpack.allowFilter=true,",
+            ),
+        ])
+        .unwrap();
+        assert_eq!(cfg.git_backend_max_request_buffer, Some(100 << 20));
+        assert!(cfg.git_backend_trace_packet);
+        assert_eq!(
+            cfg.git_backend_extra_config,
+            [
+                ("pack.threads".to_string(), "2".to_string()),
+                ("uploadpack.allowFilter".to_string(), "true".to_string()),
+            ]
+        );
+
+        assert_eq!(parse_byte_size("512k"), Some(512 << 10));
+        assert_eq!(parse_byte_size("4096"), Some(4096));
+        for bad in ["0", "10x", "m", "-1", "99999999999g"] {
+            assert_eq!(parse_byte_size(bad), None, "{bad}");
+        }
+        for bad in ["threads=2", "pack.threads", "http.receivepack=true", ".x=1"] {
+            assert_eq!(parse_extra_git_config(bad), None, "{bad}");
+        }
+        assert_eq!(
+            parse_extra_git_config("http.https://example.org.sslVerify=false").map(|c| c.len()),
+            Some(1)
+        );
+
+        let cfg = load_with(&[(
+            "GIT_HIDE_REFS",
+            "refs/gitbridge/, !refs/gitbridge/keep, tags/v1, ^refs/x",
+        )])
+        .unwrap();
+        assert_eq!(
+            cfg.git_hide_refs,
+            ["refs/gitbridge/", "!refs/gitbridge/keep", "^refs/x"]
+        );
+
+        let cfg = load_with(&[
+            ("GIT_ALLOWED_CIDRS", "10.0.0.0/8, 192.0.2.7,10.0.0.0/40"),
+            ("TRUSTED_PROXIES", "::1"),
+        ])
+        .unwrap();
+        let cidrs: Vec<String> = cfg
+            .git_allowed_cidrs
+            .unwrap()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(cidrs, ["10.0.0.0/8", "192.0.2.7/32"]);
+        assert_eq!(cfg.trusted_proxies[0].to_string(), "::1/128");
+        assert_eq!(load_with(&[]).unwrap().git_allowed_cidrs, None);
+        // nothing valid left allows nobody rather than everybody
+        let cfg = load_with(&[("GIT_ALLOWED_CIDRS", "office")]).unwrap();
+        assert_eq!(cfg.git_allowed_cidrs, Some(Vec::new()));
+    }
+
+    #[test]
+    fn named_sources_get_their_own_layout_and_subtree() {
+        let cfg = load_with(&[
+            ("GIT_ROOT", "/srv/git"),
+            ("PROJECTS_LAYOUT", "nested"),
+            (
+                "SOURCES",
+                "prod:/data/prod, staging:/data/staging,Bad:/x,default:/y,prod:/z,shared-objects:/w",
+            ),
+            ("SOURCE_STAGING_PROJECTS_LAYOUT", "flat"),
+            ("SOURCE_STAGING_PROJECTS_DIR", "compiles"),
+        ])
+        .unwrap();
+        let names: Vec<&str> = cfg.sources.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["prod", "staging"]);
+        assert_eq!(cfg.sources[0].projects_layout, ProjectsLayout::Nested);
+        assert_eq!(cfg.sources[1].projects_layout, ProjectsLayout::Flat);
+        assert_eq!(cfg.sources[1].projects_dir, PathBuf::from("compiles"));
+
+        let staging = cfg.for_source(&cfg.sources[1]);
+        assert_eq!(
+            staging.projects_root(),
+            PathBuf::from("/data/staging/compiles")
+        );
+        assert_eq!(staging.git_root, PathBuf::from("/srv/git/staging"));
+        assert_eq!(staging.source_label(), "staging");
+        assert_eq!(staging.clone_url("p1"), "/git/staging/p1.git");
+        assert!(staging.sources.is_empty());
+        assert_eq!(cfg.source_label(), DEFAULT_SOURCE);
+        assert_eq!(cfg.clone_url("p1"), "/git/p1.git");
+
+        let err = load_with(&[("STRICT_CONFIG", "true"), ("SOURCES", "prod")]).unwrap_err();
+        assert_eq!(err.problems[0].var, "SOURCES");
+    }
+
+    #[test]
+    fn invalid_bare_repo_config_entries_are_skipped_or_fatal_when_strict() {
+        let raw = "core.ignoreCase=false, receive.denyNonFastForwards=true\nnosection=1\n";
+        let cfg = load_with(&[("BARE_REPO_GIT_CONFIG", raw)]).unwrap();
+        assert_eq!(
+            cfg.bare_repo_git_config,
+            [
+                ("core.ignoreCase".to_string(), "false".to_string()),
+                (
+                    "receive.denyNonFastForwards".to_string(),
+                    "true".to_string()
+                ),
+            ]
+        );
+
+        let err = load_with(&[("STRICT_CONFIG", "1"), ("BARE_REPO_GIT_CONFIG", raw)]).unwrap_err();
+        assert_eq!(err.problems.len(), 1);
+        assert_eq!(err.problems[0].value, "nosection=1");
+    }
+
+    #[test]
+    fn strict_project_ids_are_opt_out() {
+        let cfg = load_with(&[]).unwrap();
+        assert!(cfg.strict_project_ids);
+        assert!(cfg.parse_project_id("p1").is_err());
+        assert!(
+            cfg.parse_project_id("64a1f0c2e4b0a1b2c3d4e5f6-thesis")
+                .is_ok()
+        );
+
+        let cfg = load_with(&[("STRICT_PROJECT_IDS", "false")]).unwrap();
+        assert!(!cfg.strict_project_ids);
+        assert!(cfg.accepts_project_id(&cfg.parse_project_id("p1").unwrap()));
+    }
+
+    #[test]
+    fn project_and_mirror_dirs_may_not_lead_out_of_their_roots() {
+        use std::os::unix::fs::symlink;
+
+        let root = tempfile::TempDir::new().unwrap();
+        let outside = tempfile::TempDir::new().unwrap();
+        let cfg = Config::for_test(root.path());
+        let projects = cfg.projects_root();
+        fs::create_dir_all(&projects).unwrap();
+        fs::create_dir_all(cfg.repos_dir()).unwrap();
+
+        // links that stay inside are followed
+        fs::create_dir(projects.join("inside")).unwrap();
+        symlink(projects.join("inside"), projects.join("p1")).unwrap();
+        assert_eq!(
+            cfg.project_source_dir(&pid("p1")).unwrap(),
+            projects.join("p1")
+        );
+        assert_eq!(
+            cfg.project_source_dir(&pid("p2")).unwrap(),
+            projects.join("p2"),
+            "not there yet"
+        );
+
+        symlink(outside.path(), projects.join("p3")).unwrap();
+        symlink(outside.path(), projects.join("p4-thesis")).unwrap();
+        assert_eq!(find_project_dir(&projects, "p3"), None);
+        assert_eq!(find_project_dir(&projects, "p4"), None);
+        assert!(matches!(
+            cfg.project_source_dir(&pid("p3")),
+            Err(BridgeError::OutsideRoot(_))
+        ));
+
+        symlink(outside.path(), cfg.repos_dir().join("p3.git")).unwrap();
+        symlink(outside.path().join("gone"), cfg.repos_dir().join("p4.git")).unwrap();
+        for id in ["p3", "p4"] {
+            assert!(
+                matches!(
+                    cfg.bare_repo_dir(&pid(id)),
+                    Err(BridgeError::OutsideRoot(_))
+                ),
+                "{id}"
+            );
+        }
+        assert_eq!(
+            cfg.bare_repo_dir(&pid("p5")).unwrap(),
+            cfg.repos_dir().join("p5.git")
+        );
+    }
+}
@@ -0,0 +1,471 @@
+use crate::config::Config;
+use crate::shutdown::Shutdown;
+use axum::Router;
+use axum::body::Body;
+use axum::error_handling::HandleErrorLayer;
+use axum::extract::ConnectInfo;
+use axum::http::{Request, StatusCode, header};
+use axum::response::IntoResponse;
+use hyper::body::Incoming;
+use hyper_util::rt::{TokioIo, TokioTimer};
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Semaphore;
+use tower::limit::GlobalConcurrencyLimitLayer;
+use tower::load_shed::LoadShedLayer;
+use tower::{BoxError, ServiceBuilder, ServiceExt};
+use tracing::{debug, error, info};
+
+/// Answer requests beyond `max` concurrent ones with 503 right away instead
+/// of queueing them. Applies to the routes already added to `router`, which
+/// share one limit.
+pub(crate) fn limit_requests<S>(router: Router<S>, max: usize) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    router.layer(
+        ServiceBuilder::new()
+            .layer(HandleErrorLayer::new(|_: BoxError| async {
+                (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    [(header::RETRY_AFTER, "1")],
+                    "server busy, try again later\n",
+                )
+                    .into_response()
+            }))
+            .layer(LoadShedLayer::new())
+            .layer(GlobalConcurrencyLimitLayer::new(max)),
+    )
+}
+
+/// Counts of transfers that did not complete, by whose fault.
+#[derive(Debug, Default)]
+pub(crate) struct TransferStats {
+    /// Clients that disconnected before their response was sent
+    pub(crate) client_aborts: AtomicU64,
+    /// Requests that failed because `git http-backend` did
+    pub(crate) backend_failures: AtomicU64,
+}
+
+impl TransferStats {
+    /// Log a client that went away mid-transfer. This is routine (think of
+    /// an interrupted clone), so it is counted but not logged as an error.
+    pub(crate) fn client_aborted(&self, detail: &dyn std::fmt::Display) {
+        self.client_aborts.fetch_add(1, Ordering::Relaxed);
+        info!(
+            event = "client_abort",
+            "client aborted the transfer: {detail}"
+        );
+    }
+
+    pub(crate) fn backend_failed(&self) {
+        self.backend_failures.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Whether a connection failed because the client hung up: it closed the
+/// socket while a request or response was still in transit.
+fn is_client_abort(err: &hyper::Error) -> bool {
+    if err.is_incomplete_message() || err.is_canceled() {
+        return true;
+    }
+    let mut source = std::error::Error::source(err);
+    while let Some(cause) = source {
+        if let Some(io) = cause.downcast_ref::<io::Error>() {
+            return matches!(
+                io.kind(),
+                io::ErrorKind::BrokenPipe
+                    | io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionAborted
+                    | io::ErrorKind::UnexpectedEof
+            );
+        }
+        source = cause.source();
+    }
+    false
+}
+
+/// When a connection last moved bytes and how many of its requests are
+/// being handled.
+struct Activity {
+    start: Instant,
+    /// Milliseconds after `start`
+    last_io: AtomicU64,
+    requests: AtomicUsize,
+}
+
+impl Activity {
+    fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            last_io: AtomicU64::new(0),
+            requests: AtomicUsize::new(0),
+        }
+    }
+
+    fn touch(&self) {
+        let now = self.start.elapsed().as_millis() as u64;
+        self.last_io.store(now, Ordering::Relaxed);
+    }
+
+    fn idle_for(&self) -> Duration {
+        let last = Duration::from_millis(self.last_io.load(Ordering::Relaxed));
+        self.start.elapsed().saturating_sub(last)
+    }
+}
+
+/// Counts a request as in flight until dropped.
+struct RequestGuard(Arc<Activity>);
+
+impl RequestGuard {
+    fn new(activity: &Arc<Activity>) -> Self {
+        activity.requests.fetch_add(1, Ordering::Relaxed);
+        Self(activity.clone())
+    }
+}
+
+impl Drop for RequestGuard {
+    fn drop(&mut self) {
+        self.0.touch();
+        self.0.requests.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// TCP stream recording every read and write that moved bytes.
+struct TrackedStream {
+    inner: TcpStream,
+    activity: Arc<Activity>,
+}
+
+impl AsyncRead for TrackedStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if buf.filled().len() > before {
+            self.activity.touch();
+        }
+        poll
+    }
+}
+
+impl AsyncWrite for TrackedStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = &poll
+            && *n > 0
+        {
+            self.activity.touch();
+        }
+        poll
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// Resolves once the connection moved no bytes for `timeout` while no
+/// request was being handled. Transfers that keep flowing never expire.
+async fn idle_expired(activity: &Activity, timeout: Duration) {
+    loop {
+        let idle = activity.idle_for();
+        if idle >= timeout && activity.requests.load(Ordering::Relaxed) == 0 {
+            return;
+        }
+        tokio::time::sleep(timeout.saturating_sub(idle).max(Duration::from_millis(100))).await;
+    }
+}
+
+/// Serve `router` on `listener` with at most `MAX_CONNECTIONS` open
+/// connections (more wait in the accept backlog), a deadline for reading
+/// request headers, and an idle timeout per connection. Requests carry the
+/// peer address as [`ConnectInfo`]. Connections the client drops
+/// mid-transfer are counted in `stats`. Once `shutdown` begins, open
+/// connections are closed after their current request; accepting stops
+/// when the returned future is dropped.
+pub(crate) async fn serve_connections(
+    listener: TcpListener,
+    router: Router,
+    cfg: &Config,
+    stats: Arc<TransferStats>,
+    shutdown: Arc<Shutdown>,
+) -> io::Result<()> {
+    let connections = Arc::new(Semaphore::new(cfg.max_connections));
+    let header_timeout = Duration::from_secs(cfg.header_read_timeout_seconds);
+    let idle_timeout = Duration::from_secs(cfg.idle_timeout_seconds);
+    loop {
+        let permit = connections
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("semaphore is never closed");
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                // Usually out of file descriptors; give open connections a
+                // moment to finish instead of spinning.
+                error!("accept failed: {e}");
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                continue;
+            }
+        };
+
+        let activity = Arc::new(Activity::new());
+        let io = TokioIo::new(TrackedStream {
+            inner: stream,
+            activity: activity.clone(),
+        });
+        let router = router.clone();
+        let stats = stats.clone();
+        let shutdown = shutdown.clone();
+        let service_activity = activity.clone();
+        let service = hyper::service::service_fn(move |req: Request<Incoming>| {
+            let guard = RequestGuard::new(&service_activity);
+            let router = router.clone();
+            async move {
+                let mut req = req.map(Body::new);
+                req.extensions_mut().insert(ConnectInfo(peer));
+                let response = router.oneshot(req).await;
+                drop(guard);
+                response
+            }
+        });
+
+        tokio::spawn(async move {
+            let _permit = permit;
+            let conn = hyper::server::conn::http1::Builder::new()
+                .timer(TokioTimer::new())
+                .header_read_timeout(header_timeout)
+                .serve_connection(io, service);
+            tokio::pin!(conn);
+            let mut closing = false;
+            loop {
+                tokio::select! {
+                    result = conn.as_mut() => {
+                        match result {
+                            Ok(()) => {}
+                            Err(e) if is_client_abort(&e) => {
+                                stats.client_aborted(&format_args!("{peer}: {e}"));
+                            }
+                            Err(e) => debug!(%peer, "connection closed with error: {e}"),
+                        }
+                        break;
+                    }
+                    _ = idle_expired(&activity, idle_timeout) => {
+                        debug!(%peer, "closing idle connection");
+                        break;
+                    }
+                    _ = shutdown.stopped(), if !closing => {
+                        // answers the request in progress, if any, then closes
+                        conn.as_mut().graceful_shutdown();
+                        closing = true;
+                    }
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::get;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::sync::Notify;
+
+    #[tokio::test]
+    async fn requests_over_the_limit_are_shed() {
+        let release = Arc::new(Notify::new());
+        let waiting = release.clone();
+        let router = Router::new()
+            .route(
+                "/slow",
+                get(move || {
+                    let waiting = waiting.clone();
+                    async move {
+                        waiting.notified().await;
+                        "done"
+                    }
+                }),
+            )
+            .route("/fast", get(|| async { "done" }));
+        let router = limit_requests(router, 1);
+
+        let first = tokio::spawn(
+            router
+                .clone()
+                .oneshot(Request::get("/slow").body(Body::empty()).unwrap()),
+        );
+        tokio::task::yield_now().await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let shed = router
+            .clone()
+            .oneshot(Request::get("/fast").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(shed.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(shed.headers().contains_key(header::RETRY_AFTER));
+
+        release.notify_one();
+        assert_eq!(first.await.unwrap().unwrap().status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn idle_connections_are_closed_but_slow_requests_are_not() {
+        let root = tempfile::TempDir::new().unwrap();
+        let mut cfg = Config::for_test(root.path());
+        cfg.idle_timeout_seconds = 1;
+        let router = Router::new().route(
+            "/slow",
+            get(|| async {
+                tokio::time::sleep(Duration::from_millis(1500)).await;
+                "done"
+            }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let stats = Arc::new(TransferStats::default());
+        tokio::spawn(async move {
+            serve_connections(listener, router, &cfg, stats, Arc::default()).await
+        });
+
+        // A request taking longer than the idle timeout still completes.
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET /slow HTTP/1.1\r\nHost: x\r\n\r\n")
+            .await
+            .unwrap();
+        let mut buf = vec![0; 1024];
+        let n = stream.read(&mut buf).await.unwrap();
+        assert!(buf[..n].starts_with(b"HTTP/1.1 200"));
+
+        // The kept-alive connection is then closed once idle.
+        let closed = tokio::time::timeout(Duration::from_secs(5), stream.read(&mut buf))
+            .await
+            .expect("idle connection was not closed")
+            .unwrap();
+        assert_eq!(closed, 0);
+    }
+
+    #[tokio::test]
+    async fn connections_close_after_their_request_on_shutdown() {
+        let root = tempfile::TempDir::new().unwrap();
+        let cfg = Config::for_test(root.path());
+        let router = Router::new().route(
+            "/slow",
+            get(|| async {
+                tokio::time::sleep(Duration::from_millis(300)).await;
+                "done"
+            }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let shutdown = Arc::new(Shutdown::default());
+        let server_shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            let stats = Arc::new(TransferStats::default());
+            serve_connections(listener, router, &cfg, stats, server_shutdown).await
+        });
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET /slow HTTP/1.1\r\nHost: x\r\n\r\n")
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        shutdown.begin();
+
+        // the request in progress is answered, then the kept-alive
+        // connection is closed instead of waiting for the idle timeout
+        let mut response = Vec::new();
+        tokio::time::timeout(Duration::from_secs(5), stream.read_to_end(&mut response))
+            .await
+            .expect("connection was not closed")
+            .unwrap();
+        let response = String::from_utf8(response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+        assert!(response.ends_with("done"), "{response}");
+    }
+
+    /// Log lines written while a test runs.
+    #[derive(Clone, Default)]
+    struct CapturedLogs(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl io::Write for CapturedLogs {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn aborted_downloads_are_counted_but_not_errors() {
+        let logs = CapturedLogs::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::DEBUG)
+            .with_ansi(false)
+            .with_writer(move || writer.clone())
+            .finish();
+        // current-thread runtime: the connection task logs on this thread
+        let _default = tracing::subscriber::set_default(subscriber);
+
+        let root = tempfile::TempDir::new().unwrap();
+        let cfg = Config::for_test(root.path());
+        let router = Router::new().route("/pack", get(|| async { vec![b'x'; 32 << 20] }));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let stats = Arc::new(TransferStats::default());
+        let server_stats = stats.clone();
+        tokio::spawn(async move {
+            serve_connections(listener, router, &cfg, server_stats, Arc::default()).await
+        });
+
+        // Read the start of the response, then hang up like an interrupted clone.
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET /pack HTTP/1.1\r\nHost: x\r\n\r\n")
+            .await
+            .unwrap();
+        let mut buf = vec![0; 1024];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert!(buf.starts_with(b"HTTP/1.1 200"));
+        drop(stream);
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while stats.client_aborts.load(Ordering::Relaxed) == 0 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("abort was not counted");
+
+        let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        assert!(logs.contains("client_abort"), "{logs}");
+        assert!(!logs.contains("ERROR"), "{logs}");
+        assert_eq!(stats.backend_failures.load(Ordering::Relaxed), 0);
+    }
+}
@@ -0,0 +1,445 @@
+//! Per-project deploy tokens: read-only credentials for one project that
+//! expire and, if asked to, rotate themselves.
+//!
+//! The current token of a project is also written to its `.gitbridge` file.
+//! A rotated token stays valid for `DEPLOY_TOKEN_OVERLAP_HOURS` next to its
+//! successor, and rotations are posted to `DEPLOY_TOKEN_WEBHOOK_URL` so CI
+//! secrets can follow.
+
+use crate::AppState;
+use crate::auth::constant_time_eq;
+use crate::config::Config;
+use crate::error::BridgeError;
+use crate::project_id::ProjectId;
+use crate::source::ProjectSource;
+use crate::webhook_queue::{WebhookKind, WebhookQueue};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+/// Time between checks for tokens due for rotation or expired.
+const MAINTENANCE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(15 * 60);
+
+/// A token replaced by a rotation, still accepted for a while.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct PreviousToken {
+    pub(crate) token: String,
+    pub(crate) valid_until: DateTime<Utc>,
+}
+
+/// The deploy token of one project.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct DeployToken {
+    pub(crate) token: String,
+    pub(crate) created_at: DateTime<Utc>,
+    pub(crate) expires_at: DateTime<Utc>,
+    /// Lifetime of every token issued for the project
+    pub(crate) valid_days: u32,
+    /// Replace the token before it expires instead of letting it lapse
+    pub(crate) auto_renew: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) previous: Option<PreviousToken>,
+}
+
+/// How a presented token relates to a project's deploy token.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum DeployTokenCheck {
+    /// The current or still overlapping previous token
+    Valid,
+    /// One of the project's tokens, past its validity
+    Expired,
+    Unknown,
+}
+
+impl DeployToken {
+    fn check(&self, token: &str, now: DateTime<Utc>) -> DeployTokenCheck {
+        let previous = self.previous.as_ref().map(|p| (&p.token, p.valid_until));
+        for (known, valid_until) in [(&self.token, self.expires_at)].into_iter().chain(previous) {
+            if constant_time_eq(known, token) {
+                return if now < valid_until {
+                    DeployTokenCheck::Valid
+                } else {
+                    DeployTokenCheck::Expired
+                };
+            }
+        }
+        DeployTokenCheck::Unknown
+    }
+}
+
+/// What [`DeployTokenStore::maintain`] did to a project's token.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Maintenance {
+    Rotated {
+        project_id: ProjectId,
+        token: DeployToken,
+    },
+    /// A token without auto-renewal lapsed and was removed
+    Expired {
+        project_id: ProjectId,
+        token: String,
+    },
+}
+
+/// On-disk format of `deploy-tokens.json`, by project id.
+type DeployTokens = BTreeMap<ProjectId, DeployToken>;
+
+/// Deploy tokens persisted under `git_root`.
+pub(crate) struct DeployTokenStore {
+    path: PathBuf,
+    overlap: Duration,
+    /// Contents of the file once read; held while updating it
+    tokens: Mutex<Option<DeployTokens>>,
+}
+
+fn new_token() -> String {
+    format!("deploy-{}", Uuid::new_v4().simple())
+}
+
+impl DeployTokenStore {
+    pub(crate) fn new(cfg: &Config) -> Self {
+        let hours = i64::try_from(cfg.deploy_token_overlap_hours).unwrap_or(i64::MAX / 3600);
+        Self {
+            path: cfg.deploy_tokens_file(),
+            overlap: Duration::hours(hours),
+            tokens: Mutex::new(None),
+        }
+    }
+
+    fn read(&self) -> Result<DeployTokens, BridgeError> {
+        match fs::read_to_string(&self.path) {
+            Ok(data) => Ok(serde_json::from_str(&data)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(DeployTokens::new()),
+            Err(e) => Err(BridgeError::Io(e)),
+        }
+    }
+
+    fn write(&self, tokens: &DeployTokens) -> Result<(), BridgeError> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let tmp_path = self.path.with_extension("tmp");
+        fs::write(&tmp_path, serde_json::to_string_pretty(tokens)?)?;
+        fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+
+    /// Run `f` on the tokens; they are written back if it returns `true`.
+    fn with_tokens<R>(
+        &self,
+        f: impl FnOnce(&mut DeployTokens) -> (bool, R),
+    ) -> Result<R, BridgeError> {
+        let mut guard = self.tokens.lock().expect("deploy token lock poisoned");
+        if guard.is_none() {
+            *guard = Some(self.read()?);
+        }
+        let tokens = guard.as_mut().expect("loaded above");
+        let (changed, result) = f(tokens);
+        if changed {
+            self.write(tokens)?;
+        }
+        Ok(result)
+    }
+
+    /// Whether `token` is the current or still overlapping previous deploy
+    /// token of `project_id`. Blocking.
+    pub(crate) fn check(
+        &self,
+        project_id: &ProjectId,
+        token: &str,
+    ) -> Result<DeployTokenCheck, BridgeError> {
+        let now = Utc::now();
+        self.with_tokens(|tokens| {
+            let check = tokens
+                .get(project_id)
+                .map_or(DeployTokenCheck::Unknown, |t| t.check(token, now));
+            (false, check)
+        })
+    }
+
+    /// Issue a new token for `project_id`, valid for `days`. A token the
+    /// project already had stays valid for the overlap window. Blocking.
+    #[cfg(feature = "admin-ui")]
+    pub(crate) fn issue(
+        &self,
+        project_id: &ProjectId,
+        days: u32,
+        auto_renew: bool,
+    ) -> Result<DeployToken, BridgeError> {
+        let now = Utc::now();
+        let overlap = self.overlap;
+        self.with_tokens(|tokens| {
+            let previous = tokens
+                .get(project_id)
+                .filter(|old| now < old.expires_at)
+                .map(|old| PreviousToken {
+                    token: old.token.clone(),
+                    valid_until: (now + overlap).min(old.expires_at),
+                });
+            let issued = DeployToken {
+                token: new_token(),
+                created_at: now,
+                expires_at: now + Duration::days(days.into()),
+                valid_days: days,
+                auto_renew,
+                previous,
+            };
+            tokens.insert(project_id.clone(), issued.clone());
+            (true, issued)
+        })
+    }
+
+    /// Remove the deploy token of `project_id`; neither it nor a previous
+    /// token is accepted afterwards. Blocking.
+    #[cfg(feature = "admin-ui")]
+    pub(crate) fn revoke(
+        &self,
+        project_id: &ProjectId,
+    ) -> Result<Option<DeployToken>, BridgeError> {
+        self.with_tokens(|tokens| {
+            let removed = tokens.remove(project_id);
+            (removed.is_some(), removed)
+        })
+    }
+
+    /// Every project's deploy token. Blocking.
+    #[cfg(feature = "admin-ui")]
+    pub(crate) fn list(&self) -> Result<Vec<(ProjectId, DeployToken)>, BridgeError> {
+        self.with_tokens(|tokens| (false, tokens.clone().into_iter().collect()))
+    }
+
+    /// Rotate auto-renewing tokens that expire within the overlap window,
+    /// remove lapsed ones, and forget previous tokens past their overlap.
+    /// Blocking.
+    pub(crate) fn maintain(&self, now: DateTime<Utc>) -> Result<Vec<Maintenance>, BridgeError> {
+        let overlap = self.overlap;
+        self.with_tokens(|tokens| {
+            let mut changed = false;
+            let mut done = Vec::new();
+            tokens.retain(|project_id, entry| {
+                if entry.auto_renew && entry.expires_at - overlap <= now {
+                    let previous = (now < entry.expires_at).then(|| PreviousToken {
+                        token: entry.token.clone(),
+                        valid_until: (now + overlap).min(entry.expires_at),
+                    });
+                    entry.token = new_token();
+                    entry.created_at = now;
+                    entry.expires_at = now + Duration::days(entry.valid_days.into());
+                    entry.previous = previous;
+                    changed = true;
+                    done.push(Maintenance::Rotated {
+                        project_id: project_id.clone(),
+                        token: entry.clone(),
+                    });
+                    return true;
+                }
+                if entry.expires_at <= now {
+                    changed = true;
+                    done.push(Maintenance::Expired {
+                        project_id: project_id.clone(),
+                        token: entry.token.clone(),
+                    });
+                    return false;
+                }
+                if entry
+                    .previous
+                    .as_ref()
+                    .is_some_and(|p| p.valid_until <= now)
+                {
+                    entry.previous = None;
+                    changed = true;
+                }
+                true
+            });
+            (changed, done)
+        })
+    }
+}
+
+/// Point the project's `.gitbridge` file at `token`, or remove it when
+/// `token` is `None` and the file still holds `replaced`. Failures are only
+/// logged; the deploy token store authorizes fetches either way. Blocking.
+pub(crate) fn update_token_file(
+    source: &dyn ProjectSource,
+    project_id: &ProjectId,
+    token: Option<&str>,
+    replaced: &str,
+) {
+    if token.is_none() {
+        let mut current = String::new();
+        let holds_replaced = source
+            .read(project_id, Path::new(".gitbridge"))
+            .and_then(|mut file| Ok(file.read_to_string(&mut current)?))
+            .is_ok_and(|_| current.trim() == replaced);
+        if !holds_replaced {
+            return;
+        }
+    }
+    if let Err(e) = source.write_project_token(project_id, token) {
+        warn!(%project_id, "cannot update .gitbridge file: {e}");
+    }
+}
+
+/// Post a rotated token to `DEPLOY_TOKEN_WEBHOOK_URL`. Blocking.
+fn notify_rotation(
+    webhooks: &WebhookQueue,
+    url: &str,
+    project_id: &ProjectId,
+    token: &DeployToken,
+) -> Result<(), BridgeError> {
+    let body = json!({
+        "event": "deploy_token_rotated",
+        "project_id": project_id,
+        "token": token.token,
+        "expires_at": token.expires_at,
+        "previous_valid_until": token.previous.as_ref().map(|p| p.valid_until),
+    });
+    webhooks.send(WebhookKind::DeployToken, url, body)
+}
+
+/// Rotate and expire deploy tokens, then update `.gitbridge` files and post
+/// rotations. Blocking.
+fn maintain_blocking(
+    cfg: &Config,
+    store: &DeployTokenStore,
+    source: &dyn ProjectSource,
+    webhooks: &WebhookQueue,
+    now: DateTime<Utc>,
+) -> Result<(), BridgeError> {
+    for change in store.maintain(now)? {
+        match change {
+            Maintenance::Rotated { project_id, token } => {
+                let replaced = token.previous.as_ref().map_or("", |p| p.token.as_str());
+                update_token_file(source, &project_id, Some(&token.token), replaced);
+                info!(%project_id, expires_at = %token.expires_at, "rotated deploy token");
+                if let Some(url) = &cfg.deploy_token_webhook_url
+                    && let Err(e) = notify_rotation(webhooks, url, &project_id, &token)
+                {
+                    warn!(%project_id, "cannot post deploy token rotation, queued for a retry: {e}");
+                }
+            }
+            Maintenance::Expired { project_id, token } => {
+                update_token_file(source, &project_id, None, &token);
+                info!(%project_id, "deploy token expired");
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Background task rotating and expiring deploy tokens at startup and
+/// every [`MAINTENANCE_INTERVAL`].
+pub(crate) async fn run_periodically(state: Arc<AppState>) {
+    loop {
+        if !state.maintenance.is_enabled() {
+            let _in_flight = state.maintenance.begin();
+            let cfg = state.cfg.clone();
+            let store = state.deploy_tokens.clone();
+            let source = state.source.clone();
+            let webhooks = state.webhooks.clone();
+            let result = tokio::task::spawn_blocking(move || {
+                maintain_blocking(&cfg, &store, source.as_ref(), &webhooks, Utc::now())
+            })
+            .await;
+            match result {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => error!("deploy token maintenance failed: {e}"),
+                Err(e) => error!("deploy token maintenance failed: join error: {e}"),
+            }
+        }
+        tokio::time::sleep(MAINTENANCE_INTERVAL).await;
+    }
+}
+
+#[cfg(all(test, feature = "admin-ui"))]
+mod tests {
+    use super::*;
+    use crate::project_id::pid;
+    use crate::source::FsProjectSource;
+    use tempfile::TempDir;
+
+    #[test]
+    fn rotation_keeps_the_previous_token_for_the_overlap() {
+        let root = TempDir::new().unwrap();
+        let cfg = Config::for_test(root.path());
+        fs::create_dir_all(&cfg.git_root).unwrap();
+        let store = DeployTokenStore::new(&cfg);
+        let first = store.issue(&pid("p1"), 30, true).unwrap();
+        assert_eq!(
+            store.check(&pid("p1"), &first.token).unwrap(),
+            DeployTokenCheck::Valid
+        );
+        assert_eq!(
+            store.check(&pid("p2"), &first.token).unwrap(),
+            DeployTokenCheck::Unknown
+        );
+        assert!(store.maintain(Utc::now()).unwrap().is_empty());
+
+        // 24h overlap: due one day before expiry
+        let due = first.expires_at - Duration::hours(23);
+        let changes = store.maintain(due).unwrap();
+        let [Maintenance::Rotated { project_id, token }] = &changes[..] else {
+            panic!("{changes:?}");
+        };
+        assert_eq!(project_id, "p1");
+        assert_ne!(token.token, first.token);
+        assert_eq!(token.expires_at, due + Duration::days(30));
+        let previous = token.previous.as_ref().unwrap();
+        assert_eq!(previous.token, first.token);
+        assert_eq!(previous.valid_until, first.expires_at);
+        assert_eq!(token.check(&first.token, due), DeployTokenCheck::Valid);
+        assert_eq!(
+            token.check(&first.token, first.expires_at),
+            DeployTokenCheck::Expired
+        );
+
+        // a fresh store reads the rotation back from disk
+        let reloaded = DeployTokenStore::new(&cfg);
+        assert_eq!(reloaded.list().unwrap(), [(pid("p1"), token.clone())]);
+        assert!(reloaded.maintain(first.expires_at).unwrap().is_empty());
+        assert_eq!(reloaded.list().unwrap()[0].1.previous, None);
+
+        assert!(reloaded.revoke(&pid("p1")).unwrap().is_some());
+        assert_eq!(
+            reloaded.check(&pid("p1"), &token.token).unwrap(),
+            DeployTokenCheck::Unknown
+        );
+    }
+
+    #[test]
+    fn tokens_without_renewal_lapse_and_leave_the_token_file() {
+        let root = TempDir::new().unwrap();
+        let cfg = Config::for_test(root.path());
+        fs::create_dir_all(&cfg.git_root).unwrap();
+        let project = cfg.project_source_dir(&pid("p1")).unwrap();
+        fs::create_dir_all(&project).unwrap();
+        let source = FsProjectSource::new(cfg.clone());
+        let store = DeployTokenStore::new(&cfg);
+
+        let issued = store.issue(&pid("p1"), 1, false).unwrap();
+        update_token_file(&source, &pid("p1"), Some(&issued.token), "");
+        assert_eq!(
+            fs::read_to_string(project.join(".gitbridge")).unwrap(),
+            format!("{}\n", issued.token)
+        );
+        // not rotated inside the overlap window
+        assert!(store.maintain(Utc::now()).unwrap().is_empty());
+
+        let webhooks = WebhookQueue::new(&cfg);
+        maintain_blocking(&cfg, &store, &source, &webhooks, issued.expires_at).unwrap();
+        assert!(store.list().unwrap().is_empty());
+        assert!(!project.join(".gitbridge").exists());
+
+        // a token file someone replaced by hand is left alone
+        fs::write(project.join(".gitbridge"), "mine").unwrap();
+        update_token_file(&source, &pid("p1"), None, &issued.token);
+        assert!(project.join(".gitbridge").exists());
+    }
+}
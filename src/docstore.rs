@@ -0,0 +1,492 @@
+use crate::config::Config;
+use crate::error::BridgeError;
+use crate::project_id::ProjectId;
+use crate::source::{ProjectSource, SourceEntry};
+use mongodb::bson::{self, Bson, Document, doc, oid::ObjectId};
+use mongodb::options::{FindOneOptions, FindOptions};
+use mongodb::sync::{Client, Database};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Cursor, Read};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+use tracing::warn;
+
+/// Timeout of requests to the docstore and filestore.
+const HTTP_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Database used when `MONGO_URL` names none.
+const DEFAULT_DATABASE: &str = "sharelatex";
+
+/// One entry of a project's file tree as stored in the `projects` collection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Node {
+    Folder,
+    /// Text document; its lines live in the `docs` collection
+    Doc(ObjectId),
+    /// Binary file kept by the filestore
+    File {
+        id: ObjectId,
+        created: Option<SystemTime>,
+    },
+}
+
+/// Projects of an Overleaf instance read from its MongoDB: the file tree
+/// from `projects`, text documents from `docs` (or the docstore for archived
+/// ones), binary files from the filestore's disk backend or HTTP API.
+pub struct DocstoreProjectSource {
+    cfg: Config,
+    /// Connected on first use, so a MongoDB that is down at startup does not
+    /// stop the bridge
+    database: Mutex<Option<Database>>,
+    http: ureq::Agent,
+}
+
+impl DocstoreProjectSource {
+    pub fn new(cfg: Config) -> Self {
+        let http = ureq::Agent::config_builder()
+            .timeout_global(Some(HTTP_TIMEOUT))
+            .build()
+            .into();
+        Self {
+            cfg,
+            database: Mutex::new(None),
+            http,
+        }
+    }
+
+    fn database(&self) -> Result<Database, BridgeError> {
+        let mut database = self.database.lock().expect("database lock poisoned");
+        if let Some(db) = database.as_ref() {
+            return Ok(db.clone());
+        }
+        let url = self
+            .cfg
+            .mongo_url
+            .as_deref()
+            .ok_or_else(|| BridgeError::Other("MONGO_URL is not set".to_string()))?;
+        let client = Client::with_uri_str(url).map_err(mongo_error)?;
+        let db = client
+            .default_database()
+            .unwrap_or_else(|| client.database(DEFAULT_DATABASE));
+        *database = Some(db.clone());
+        Ok(db)
+    }
+
+    /// The project's file tree, `None` when there is no such project.
+    fn tree(&self, project_id: &str) -> Result<Option<Vec<(PathBuf, Node)>>, BridgeError> {
+        let Ok(oid) = ObjectId::parse_str(project_id) else {
+            return Ok(None);
+        };
+        let options = FindOneOptions::builder()
+            .projection(doc! { "rootFolder": 1 })
+            .build();
+        let project = self
+            .database()?
+            .collection::<Document>("projects")
+            .find_one(doc! { "_id": oid }, options)
+            .map_err(mongo_error)?;
+        let Some(project) = project else {
+            return Ok(None);
+        };
+        let root = project
+            .get_array("rootFolder")
+            .ok()
+            .and_then(|folders| folders.first())
+            .and_then(Bson::as_document)
+            .ok_or_else(|| BridgeError::Other(format!("project {project_id} has no rootFolder")))?;
+        let mut entries = Vec::new();
+        collect_tree(project_id, root, Path::new(""), &mut entries);
+        Ok(Some(entries))
+    }
+
+    fn existing_tree(&self, project_id: &str) -> Result<Vec<(PathBuf, Node)>, BridgeError> {
+        self.tree(project_id)?
+            .ok_or_else(|| BridgeError::ProjectNotFound(project_id.to_string()))
+    }
+
+    /// Contents of all text documents of a project, by document id.
+    fn docs(&self, project_id: &str) -> Result<HashMap<ObjectId, String>, BridgeError> {
+        let oid = ObjectId::parse_str(project_id)
+            .map_err(|_| BridgeError::ProjectNotFound(project_id.to_string()))?;
+        let options = FindOptions::builder()
+            .projection(doc! { "lines": 1, "inS3": 1 })
+            .build();
+        let cursor = self
+            .database()?
+            .collection::<Document>("docs")
+            .find(
+                doc! { "project_id": oid, "deleted": { "$ne": true } },
+                options,
+            )
+            .map_err(mongo_error)?;
+        let mut docs = HashMap::new();
+        for doc in cursor {
+            let doc = doc.map_err(mongo_error)?;
+            let Ok(id) = doc.get_object_id("_id") else {
+                continue;
+            };
+            let text = match doc.get_array("lines") {
+                Ok(lines) => join_lines(lines),
+                // archived to object storage; the docstore brings it back
+                Err(_) => self.archived_doc(project_id, &id)?,
+            };
+            docs.insert(id, text);
+        }
+        Ok(docs)
+    }
+
+    fn archived_doc(&self, project_id: &str, doc_id: &ObjectId) -> Result<String, BridgeError> {
+        let base = self.cfg.docstore_url.as_deref().ok_or_else(|| {
+            BridgeError::Other(format!(
+                "document {doc_id} of {project_id} is archived and DOCSTORE_URL is not set"
+            ))
+        })?;
+        let url = format!("{base}/project/{project_id}/doc/{doc_id}");
+        let body = self
+            .http
+            .get(&url)
+            .call()
+            .map_err(|e| BridgeError::Other(format!("docstore: {e}")))?
+            .into_body()
+            .read_to_string()
+            .map_err(|e| BridgeError::Other(format!("docstore: {e}")))?;
+        let doc: serde_json::Value = serde_json::from_str(&body)?;
+        let lines = doc["lines"]
+            .as_array()
+            .ok_or_else(|| BridgeError::Other(format!("docstore: no lines in {url}")))?;
+        Ok(lines
+            .iter()
+            .map(|line| line.as_str().unwrap_or_default())
+            .collect::<Vec<_>>()
+            .join("\n"))
+    }
+
+    /// Open a binary file: from the filestore's directory if it is there,
+    /// otherwise through its HTTP API.
+    fn open_file(
+        &self,
+        project_id: &str,
+        file_id: &ObjectId,
+    ) -> Result<Box<dyn Read + Send>, BridgeError> {
+        let path = self
+            .cfg
+            .user_files_dir
+            .join(format!("{project_id}_{file_id}"));
+        match fs::File::open(&path) {
+            Ok(file) => return Ok(Box::new(file)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(BridgeError::Io(e)),
+        }
+        let Some(base) = &self.cfg.filestore_url else {
+            return Err(BridgeError::Io(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} not found and FILESTORE_URL is not set", path.display()),
+            )));
+        };
+        let response = self
+            .http
+            .get(format!("{base}/project/{project_id}/file/{file_id}"))
+            .call()
+            .map_err(|e| BridgeError::Other(format!("filestore: {e}")))?;
+        Ok(Box::new(response.into_body().into_reader()))
+    }
+
+    fn file_len(&self, project_id: &str, file_id: &ObjectId) -> u64 {
+        fs::metadata(
+            self.cfg
+                .user_files_dir
+                .join(format!("{project_id}_{file_id}")),
+        )
+        .map(|m| m.len())
+        .unwrap_or(0)
+    }
+}
+
+impl ProjectSource for DocstoreProjectSource {
+    fn exists(&self, project_id: &ProjectId) -> bool {
+        match self.tree(project_id) {
+            Ok(tree) => tree.is_some(),
+            Err(e) => {
+                warn!(%project_id, "cannot look up project in MongoDB: {e}");
+                false
+            }
+        }
+    }
+
+    fn list_projects(&self) -> Result<Vec<ProjectId>, BridgeError> {
+        let options = FindOptions::builder().projection(doc! { "_id": 1 }).build();
+        let cursor = self
+            .database()?
+            .collection::<Document>("projects")
+            .find(None, options)
+            .map_err(mongo_error)?;
+        let mut ids = Vec::new();
+        for project in cursor {
+            if let Ok(id) = project.map_err(mongo_error)?.get_object_id("_id") {
+                ids.extend(ProjectId::new(id.to_hex()).ok());
+            }
+        }
+        ids.sort();
+        Ok(ids)
+    }
+
+    /// Paths, the text of every document, and the id of every binary file,
+    /// which the filestore replaces rather than changes.
+    fn fingerprint(&self, project_id: &ProjectId) -> Result<Option<String>, BridgeError> {
+        let mut tree = self.existing_tree(project_id)?;
+        let docs = self.docs(project_id)?;
+        tree.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut hasher = Sha256::new();
+        for (path, node) in &tree {
+            hasher.update(path.as_os_str().as_encoded_bytes());
+            match node {
+                Node::Folder => hasher.update(b"\0/\n"),
+                Node::Doc(id) => {
+                    let text = docs.get(id).map_or("", String::as_str);
+                    hasher.update(format!("\0d{}\0", text.len()));
+                    hasher.update(text);
+                }
+                Node::File { id, .. } => hasher.update(format!("\0f{id}\n")),
+            }
+        }
+        Ok(Some(hex::encode(hasher.finalize())))
+    }
+
+    fn enumerate(
+        &self,
+        project_id: &ProjectId,
+    ) -> Result<Box<dyn Iterator<Item = SourceEntry> + Send>, BridgeError> {
+        let tree = self.existing_tree(project_id)?;
+        let docs = self.docs(project_id)?;
+        let entries: Vec<SourceEntry> = tree
+            .into_iter()
+            .map(|(path, node)| {
+                let (is_dir, len, modified) = match &node {
+                    Node::Folder => (true, 0, None),
+                    Node::Doc(id) => (false, docs.get(id).map_or(0, |d| d.len() as u64), None),
+                    Node::File { id, created } => (false, self.file_len(project_id, id), *created),
+                };
+                SourceEntry {
+                    path,
+                    is_dir,
+                    len,
+                    modified,
+                }
+            })
+            .collect();
+        Ok(Box::new(entries.into_iter()))
+    }
+
+    fn read(
+        &self,
+        project_id: &ProjectId,
+        path: &Path,
+    ) -> Result<Box<dyn Read + Send>, BridgeError> {
+        let tree = self.existing_tree(project_id)?;
+        let not_found = || BridgeError::Io(io::Error::from(io::ErrorKind::NotFound));
+        let node = tree
+            .into_iter()
+            .find_map(|(p, node)| (p == path).then_some(node))
+            .ok_or_else(not_found)?;
+        match node {
+            Node::Folder => Err(not_found()),
+            Node::Doc(id) => {
+                let text = self.docs(project_id)?.remove(&id).ok_or_else(not_found)?;
+                Ok(Box::new(Cursor::new(text.into_bytes())))
+            }
+            Node::File { id, .. } => self.open_file(project_id, &id),
+        }
+    }
+
+    /// Load the tree and all documents once rather than per file.
+    fn copy_to_dir(&self, project_id: &ProjectId, dst: &Path) -> Result<(), BridgeError> {
+        let tree = self.existing_tree(project_id)?;
+        let docs = self.docs(project_id)?;
+        for (path, node) in tree {
+            let target = dst.join(&path);
+            if node == Node::Folder {
+                fs::create_dir_all(&target)?;
+                continue;
+            }
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            match node {
+                Node::Doc(id) => match docs.get(&id) {
+                    Some(text) => fs::write(&target, text)?,
+                    None => {
+                        warn!(%project_id, path = %path.display(), "document missing from docs")
+                    }
+                },
+                Node::File { id, .. } => {
+                    let mut reader = self.open_file(project_id, &id)?;
+                    let mut file = fs::File::create(&target)?;
+                    io::copy(&mut reader, &mut file)?;
+                }
+                Node::Folder => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+fn mongo_error(e: mongodb::error::Error) -> BridgeError {
+    BridgeError::Other(format!("mongodb: {e}"))
+}
+
+/// Document text as Overleaf's compiles write it: lines joined by `\n`.
+fn join_lines(lines: &bson::Array) -> String {
+    lines
+        .iter()
+        .map(|line| line.as_str().unwrap_or_default())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Whether an entry name from MongoDB is usable as a single path component.
+fn is_safe_name(name: &str) -> bool {
+    let mut components = Path::new(name).components();
+    matches!(components.next(), Some(Component::Normal(_)))
+        && components.next().is_none()
+        && !name.contains(['/', '\\', '\0'])
+}
+
+/// Flatten a folder of the `rootFolder` tree into paths, folders before
+/// their contents. Entries with unusable names are skipped.
+fn collect_tree(project_id: &str, folder: &Document, dir: &Path, out: &mut Vec<(PathBuf, Node)>) {
+    let children = |key: &str| {
+        folder
+            .get_array(key)
+            .map(|items| {
+                items
+                    .iter()
+                    .filter_map(Bson::as_document)
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default()
+    };
+    let named = |entry: &Document| {
+        let name = entry.get_str("name").unwrap_or_default();
+        if is_safe_name(name) {
+            Some(dir.join(name))
+        } else {
+            warn!(%project_id, name, "skipping entry with unusable name");
+            None
+        }
+    };
+    for doc in children("docs") {
+        if let (Some(path), Ok(id)) = (named(doc), doc.get_object_id("_id")) {
+            out.push((path, Node::Doc(id)));
+        }
+    }
+    for file in children("fileRefs") {
+        if let (Some(path), Ok(id)) = (named(file), file.get_object_id("_id")) {
+            let created = file
+                .get_datetime("created")
+                .ok()
+                .map(|at| at.to_system_time());
+            out.push((path, Node::File { id, created }));
+        }
+    }
+    for sub in children("folders") {
+        if let Some(path) = named(sub) {
+            out.push((path.clone(), Node::Folder));
+            collect_tree(project_id, sub, &path, out);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::project_id::pid;
+    use tempfile::TempDir;
+
+    #[test]
+    fn root_folder_is_flattened_into_paths() {
+        let (main, logo, chapter, bad) = (
+            ObjectId::new(),
+            ObjectId::new(),
+            ObjectId::new(),
+            ObjectId::new(),
+        );
+        let root = doc! {
+            "name": "rootFolder",
+            "docs": [{ "_id": main, "name": "main.tex" }],
+            "fileRefs": [{ "_id": logo, "name": "logo.png" }],
+            "folders": [{
+                "name": "chapters",
+                "docs": [
+                    { "_id": chapter, "name": "intro.tex" },
+                    { "_id": bad, "name": "../escape.tex" },
+                ],
+            }, {
+                "name": "..",
+                "docs": [{ "_id": bad, "name": "up.tex" }],
+            }],
+        };
+        let mut tree = Vec::new();
+        collect_tree("p1", &root, Path::new(""), &mut tree);
+        assert_eq!(
+            tree,
+            [
+                (PathBuf::from("main.tex"), Node::Doc(main)),
+                (
+                    PathBuf::from("logo.png"),
+                    Node::File {
+                        id: logo,
+                        created: None
+                    }
+                ),
+                (PathBuf::from("chapters"), Node::Folder),
+                (PathBuf::from("chapters/intro.tex"), Node::Doc(chapter)),
+            ]
+        );
+        assert_eq!(
+            join_lines(&vec![Bson::from("a"), Bson::from(""), Bson::from("b")]),
+            "a\n\nb"
+        );
+    }
+
+    #[test]
+    fn files_come_from_user_files_before_the_filestore() {
+        let root = TempDir::new().unwrap();
+        let mut cfg = Config::for_test(root.path());
+        cfg.mongo_url = Some("mongodb://127.0.0.1:1/sharelatex".to_string());
+        fs::create_dir_all(&cfg.user_files_dir).unwrap();
+        let project = ObjectId::new().to_hex();
+        let file = ObjectId::new();
+        fs::write(cfg.user_files_dir.join(format!("{project}_{file}")), "png").unwrap();
+        let source = DocstoreProjectSource::new(cfg);
+
+        let mut content = String::new();
+        source
+            .open_file(&project, &file)
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content, "png");
+        assert_eq!(source.file_len(&project, &file), 3);
+
+        let missing = source.open_file(&project, &ObjectId::new());
+        assert!(matches!(missing, Err(BridgeError::Io(e)) if e.kind() == io::ErrorKind::NotFound));
+        // not an object id, so no database round trip
+        assert!(!source.exists(&pid("not-a-project")));
+    }
+
+    #[tokio::test]
+    async fn unreachable_mongodb_is_an_error() {
+        let root = TempDir::new().unwrap();
+        let mut cfg = Config::for_test(root.path());
+        cfg.mongo_url =
+            Some("mongodb://127.0.0.1:1/sharelatex?serverSelectionTimeoutMS=100".to_string());
+        let source = DocstoreProjectSource::new(cfg);
+        let listed = tokio::task::spawn_blocking(move || source.list_projects())
+            .await
+            .unwrap();
+        assert!(matches!(listed, Err(BridgeError::Other(e)) if e.starts_with("mongodb")));
+    }
+}
@@ -0,0 +1,143 @@
+//! Line ending normalization of snapshots (`NORMALIZE_EOL=lf`). Overleaf
+//! keeps files with whatever line endings they were uploaded with, so a file
+//! re-uploaded from another OS shows up as a whole-file diff. With the
+//! option on, CRLF in text files is turned into LF in the worktree before
+//! `git add`, and a `.gitattributes` asking git for the same is committed.
+
+use crate::error::BridgeError;
+use std::fs;
+use std::path::Path;
+use walkdir::WalkDir;
+
+/// Committed at the root of every snapshot unless the project has its own.
+pub(crate) const GITATTRIBUTES: &str = "* text=auto eol=lf\n";
+
+/// Extensions of files that are always text (unless they contain a NUL).
+const TEXT_EXTENSIONS: &[&str] = &[
+    "bbx", "bib", "bst", "cbx", "cfg", "cls", "clo", "csv", "def", "dtx", "fd", "html", "ins",
+    "ist", "json", "lbx", "ltx", "md", "py", "r", "sty", "svg", "tex", "tikz", "tsv", "txt", "xml",
+    "yaml", "yml",
+];
+
+/// Extensions of files that are never touched, whatever they contain.
+const BINARY_EXTENSIONS: &[&str] = &[
+    "bmp", "docx", "eps", "gif", "gz", "ico", "jpeg", "jpg", "mp4", "odt", "otf", "pdf", "pfb",
+    "png", "ps", "pptx", "tif", "tiff", "ttf", "webp", "woff", "woff2", "xlsx", "zip",
+];
+
+/// Whether `content` of the file at `path` is text whose line endings may
+/// be changed: never with a NUL byte or a binary extension; with other
+/// unknown extensions only if it is UTF-8.
+fn is_text(path: &Path, content: &[u8]) -> bool {
+    if content.contains(&0) {
+        return false;
+    }
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(str::to_ascii_lowercase);
+    match extension.as_deref() {
+        Some(ext) if BINARY_EXTENSIONS.contains(&ext) => false,
+        Some(ext) if TEXT_EXTENSIONS.contains(&ext) => true,
+        _ => std::str::from_utf8(content).is_ok(),
+    }
+}
+
+/// `content` with every CRLF turned into LF; `None` if it has none.
+fn crlf_to_lf(content: &[u8]) -> Option<Vec<u8>> {
+    if !content.windows(2).any(|pair| pair == b"\r\n") {
+        return None;
+    }
+    let mut normalized = Vec::with_capacity(content.len());
+    let mut bytes = content.iter().peekable();
+    while let Some(&byte) = bytes.next() {
+        if byte == b'\r' && bytes.peek() == Some(&&b'\n') {
+            continue;
+        }
+        normalized.push(byte);
+    }
+    Some(normalized)
+}
+
+/// Turn CRLF into LF in every text file of the worktree `dst`, leaving
+/// `.git` and symlinks alone. Returns the number of files changed.
+pub(crate) fn normalize_worktree(dst: &Path) -> Result<usize, BridgeError> {
+    let mut changed = 0;
+    let files = WalkDir::new(dst)
+        .into_iter()
+        .filter_entry(|entry| entry.depth() != 1 || entry.file_name() != ".git")
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file());
+    for entry in files {
+        let content = fs::read(entry.path())?;
+        if !is_text(entry.path(), &content) {
+            continue;
+        }
+        if let Some(normalized) = crlf_to_lf(&content) {
+            fs::write(entry.path(), normalized)?;
+            changed += 1;
+        }
+    }
+    Ok(changed)
+}
+
+/// Write [`GITATTRIBUTES`] to `dst` unless the project brings its own.
+pub(crate) fn ensure_gitattributes(dst: &Path) -> Result<(), BridgeError> {
+    let path = dst.join(".gitattributes");
+    if !path.exists() {
+        fs::write(path, GITATTRIBUTES)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn only_crlf_in_text_files_is_normalized() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path();
+        let files: &[(&str, &[u8])] = &[
+            ("main.tex", b"a\r\nb\nc\r\n"),
+            ("chapters/intro.TEX", b"x\r\n"),
+            ("latexmkrc", b"$pdf_mode = 1;\r\n"),
+            ("lone.txt", b"old\rmac\n"),
+            ("figure.png", b"\x89PNG\r\n\x1a\n"),
+            ("plot.pdf", b"%PDF-1.5\r\n"),
+            ("data.bin", b"\xff\xfe\r\n"),
+            ("nul.tex", b"a\0\r\n"),
+            (".git/config", b"[core]\r\n"),
+        ];
+        for (path, content) in files {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+
+        assert_eq!(normalize_worktree(root).unwrap(), 3);
+        let read = |path: &str| fs::read(root.join(path)).unwrap();
+        assert_eq!(read("main.tex"), b"a\nb\nc\n");
+        assert_eq!(read("chapters/intro.TEX"), b"x\n");
+        assert_eq!(read("latexmkrc"), b"$pdf_mode = 1;\n");
+        assert_eq!(read("lone.txt"), b"old\rmac\n");
+        for (path, content) in &files[4..] {
+            assert_eq!(read(path), *content, "{path}");
+        }
+        // a second pass finds nothing to do
+        assert_eq!(normalize_worktree(root).unwrap(), 0);
+
+        ensure_gitattributes(root).unwrap();
+        assert_eq!(
+            fs::read_to_string(root.join(".gitattributes")).unwrap(),
+            GITATTRIBUTES
+        );
+        fs::write(root.join(".gitattributes"), "*.tex text\n").unwrap();
+        ensure_gitattributes(root).unwrap();
+        assert_eq!(
+            fs::read_to_string(root.join(".gitattributes")).unwrap(),
+            "*.tex text\n"
+        );
+    }
+}
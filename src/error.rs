@@ -1,5 +1,6 @@
 use thiserror::Error;
 
+/// Errors produced while syncing repositories or talking to git.
 #[derive(Debug, Error)]
 pub enum BridgeError {
     #[error("io error: {0}")]
@@ -14,6 +15,21 @@ pub enum BridgeError {
     #[error("project not found: {0}")]
     ProjectNotFound(String),
 
+    #[error("project busy: {0}")]
+    ProjectBusy(String),
+
+    #[error("repository too large: {0}")]
+    RepoTooLarge(String),
+
+    #[error("mirror is being prepared: {0}")]
+    MirrorPreparing(String),
+
+    #[error("path leads out of its root directory: {0}")]
+    OutsideRoot(String),
+
+    #[error("client aborted: {0}")]
+    ClientAborted(String),
+
     #[error("git command failed: {0} - {1}")]
     GitFailed(String, String),
 
@@ -0,0 +1,352 @@
+//! Read-only `git://` listener (`GIT_DAEMON_PORT`).
+//!
+//! Speaks the request line of `git daemon` and hands the connection to
+//! `git upload-pack`. Only public projects are exported; pushes and every
+//! other service are refused.
+
+use crate::AppState;
+use crate::config::Config;
+use crate::error::BridgeError;
+use crate::git_http::backend_tuning_env;
+use crate::project_id::ProjectId;
+use crate::server::{
+    is_public_project, note_fetch, note_fetch_before_serving, prepare_mirror, serves_stale,
+    sync_for_fetch,
+};
+use std::io;
+use std::net::SocketAddr;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::process::Command;
+use tokio::sync::Semaphore;
+use tracing::{debug, error, warn};
+
+/// Longest request line accepted; real ones are a path and a host name.
+const MAX_REQUEST_LEN: usize = 4096;
+
+/// The first pkt-line of a `git://` connection.
+#[derive(Debug, PartialEq, Eq)]
+struct DaemonRequest {
+    service: String,
+    path: String,
+    /// Extra parameters after the host, like `version=2`
+    extra: Vec<String>,
+}
+
+/// Parse `<service> <path>\0host=<host>\0[\0<param>\0...]`.
+fn parse_request(line: &[u8]) -> Option<DaemonRequest> {
+    let line = std::str::from_utf8(line).ok()?;
+    let line = line.strip_suffix('\n').unwrap_or(line);
+    let mut parts = line.split('\0');
+    let (service, path) = parts.next()?.split_once(' ')?;
+    let mut extra = Vec::new();
+    let mut after_host = false;
+    for part in parts {
+        if part.is_empty() {
+            after_host = true;
+        } else if after_host {
+            extra.push(part.to_string());
+        }
+    }
+    Some(DaemonRequest {
+        service: service.to_string(),
+        path: path.to_string(),
+        extra,
+    })
+}
+
+/// Project id of `/<id>.git` or `/<id>`, as accepted by `cfg`.
+fn project_id_of(cfg: &Config, path: &str) -> Option<ProjectId> {
+    let path = path.strip_prefix('/')?;
+    let path = path.strip_suffix('/').unwrap_or(path);
+    cfg.parse_project_id(path.strip_suffix(".git").unwrap_or(path))
+        .ok()
+}
+
+/// A pkt-line carrying an error for the client, shown as `remote error`.
+fn error_pkt(message: &str) -> Vec<u8> {
+    let payload = format!("ERR {message}\n");
+    format!("{:04x}{payload}", payload.len() + 4).into_bytes()
+}
+
+async fn read_request(stream: &mut TcpStream) -> io::Result<Vec<u8>> {
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len).await?;
+    let len = std::str::from_utf8(&len)
+        .ok()
+        .and_then(|l| usize::from_str_radix(l, 16).ok())
+        .filter(|&l| l > 4 && l <= MAX_REQUEST_LEN)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "bad pkt-line length"))?;
+    let mut line = vec![0u8; len - 4];
+    stream.read_exact(&mut line).await?;
+    Ok(line)
+}
+
+/// Accept `git://` connections on `listener`, at most
+/// `GIT_DAEMON_MAX_CONNECTIONS` at a time.
+pub(crate) async fn serve_git_daemon(state: Arc<AppState>, listener: TcpListener) {
+    let connections = Arc::new(Semaphore::new(state.cfg.git_daemon_max_connections));
+    loop {
+        let permit = connections
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("semaphore is never closed");
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                error!("git daemon accept failed: {e}");
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                continue;
+            }
+        };
+        let state = state.clone();
+        tokio::spawn(async move {
+            let _permit = permit;
+            if let Err(e) = handle_connection(&state, stream, peer).await {
+                debug!(%peer, "git daemon connection failed: {e}");
+            }
+        });
+    }
+}
+
+async fn handle_connection(
+    state: &Arc<AppState>,
+    mut stream: TcpStream,
+    peer: SocketAddr,
+) -> io::Result<()> {
+    let header_timeout = Duration::from_secs(state.cfg.header_read_timeout_seconds);
+    let line = tokio::time::timeout(header_timeout, read_request(&mut stream))
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "no request line"))??;
+    let Some(request) = parse_request(&line) else {
+        return stream.write_all(&error_pkt("invalid request")).await;
+    };
+    let _in_flight = state.maintenance.begin();
+    if let Some(maintenance) = state.maintenance.current() {
+        return stream.write_all(&error_pkt(&maintenance.message)).await;
+    }
+    match request.service.as_str() {
+        "git-upload-pack" => {}
+        "git-receive-pack" => {
+            return stream
+                .write_all(&error_pkt("push disabled (read-only bridge)"))
+                .await;
+        }
+        _ => {
+            return stream.write_all(&error_pkt("service not enabled")).await;
+        }
+    }
+
+    let denied = format!("access denied or repository not exported: {}", request.path);
+    let Some(project_id) = project_id_of(&state.cfg, &request.path) else {
+        return stream.write_all(&error_pkt(&denied)).await;
+    };
+    match is_public_project(state, &project_id).await {
+        Ok(true) => {}
+        Ok(false) => return stream.write_all(&error_pkt(&denied)).await,
+        Err(e) => {
+            error!(%project_id, "cannot load project settings: {e}");
+            return stream.write_all(&error_pkt("internal error")).await;
+        }
+    }
+
+    let repo = match state.cfg.bare_repo_dir(&project_id) {
+        Ok(repo) => repo,
+        Err(e) => {
+            warn!(%project_id, "not serving the mirror: {e}");
+            return stream.write_all(&error_pkt(&denied)).await;
+        }
+    };
+    let mirror_exists = repo.is_dir();
+    let synced = if mirror_exists {
+        sync_for_fetch(state, &project_id).await
+    } else {
+        prepare_mirror(state, &project_id).await
+    };
+    let message = match synced {
+        Ok(()) => None,
+        Err(BridgeError::ProjectNotFound(_)) => Some(denied.as_str()),
+        Err(BridgeError::ProjectBusy(_)) => Some("project is busy, try again later"),
+        Err(BridgeError::MirrorPreparing(_)) => Some("repository is being prepared, retry shortly"),
+        Err(e @ BridgeError::RepoTooLarge(_)) => {
+            warn!(%project_id, "not synced, serving the existing mirror: {e}");
+            None
+        }
+        // git:// has no headers to mark the mirror stale with
+        Err(e) if mirror_exists && serves_stale(state, &project_id).await => {
+            warn!(%project_id, "sync failed, serving the existing mirror: {e}");
+            None
+        }
+        Err(e) => {
+            error!("ensure_repo error: {e}");
+            Some("repo sync error")
+        }
+    };
+    if let Some(message) = message {
+        return stream.write_all(&error_pkt(message)).await;
+    }
+    if !repo.is_dir() {
+        return stream.write_all(&error_pkt(&denied)).await;
+    }
+    note_fetch_before_serving(state, &project_id).await;
+
+    debug!(%peer, %project_id, "serving git:// upload-pack");
+    let mut cmd = Command::new("git");
+    cmd.arg("upload-pack")
+        .arg("--strict")
+        .arg(format!("--timeout={}", state.cfg.idle_timeout_seconds))
+        .arg(&repo)
+        .envs(backend_tuning_env(&state.cfg))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    if !request.extra.is_empty() {
+        cmd.env("GIT_PROTOCOL", request.extra.join(":"));
+    }
+    let mut child = cmd.spawn()?;
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let mut stdout = child.stdout.take().expect("stdout is piped");
+    let mut stderr = child.stderr.take().expect("stderr is piped");
+    let stderr = tokio::spawn(async move {
+        let mut buf = Vec::new();
+        let _ = stderr.read_to_end(&mut buf).await;
+        buf
+    });
+
+    let (mut client_rd, mut client_wr) = stream.into_split();
+    let upload = tokio::spawn(async move {
+        let _ = tokio::io::copy(&mut client_rd, &mut stdin).await;
+    });
+    let sent = tokio::io::copy(&mut stdout, &mut client_wr).await;
+    let _ = client_wr.shutdown().await;
+    // upload-pack is done talking; closing its stdin lets it exit
+    upload.abort();
+    let status = child.wait().await?;
+    let stderr = stderr.await.unwrap_or_default();
+
+    if status.success() && sent.is_ok() {
+        note_fetch(state, &project_id);
+    } else {
+        warn!(
+            %peer,
+            %project_id,
+            "git upload-pack failed ({status}): {}",
+            String::from_utf8_lossy(&stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::project_id::pid;
+    use crate::project_settings::{load_project_settings, save_project_settings};
+    use std::fs;
+    use std::path::Path;
+    use tempfile::TempDir;
+
+    #[test]
+    fn request_lines_are_parsed() {
+        assert_eq!(
+            parse_request(b"git-upload-pack /abc123.git\0host=localhost:9418\0\0version=2\0"),
+            Some(DaemonRequest {
+                service: "git-upload-pack".into(),
+                path: "/abc123.git".into(),
+                extra: vec!["version=2".into()],
+            })
+        );
+        assert_eq!(
+            parse_request(b"git-receive-pack /abc123\0host=h\0")
+                .unwrap()
+                .extra,
+            Vec::<String>::new()
+        );
+        assert_eq!(parse_request(b"no-path"), None);
+        let mut cfg = Config::for_test(Path::new("/nonexistent"));
+        assert_eq!(
+            project_id_of(&cfg, "/abc123.git").as_deref(),
+            Some("abc123")
+        );
+        assert_eq!(project_id_of(&cfg, "/abc123/").as_deref(), Some("abc123"));
+        assert_eq!(project_id_of(&cfg, "/../etc.git"), None);
+        assert_eq!(project_id_of(&cfg, "abc123.git"), None);
+        cfg.strict_project_ids = true;
+        assert_eq!(project_id_of(&cfg, "/abc123.git"), None);
+        let hex = "/64a1f0c2e4b0a1b2c3d4e5f6.git";
+        assert!(project_id_of(&cfg, hex).is_some());
+        assert_eq!(error_pkt("no"), b"000bERR no\n");
+    }
+
+    async fn git(dir: &Path, args: &[&str]) -> std::process::Output {
+        Command::new("git")
+            .args(args)
+            .current_dir(dir)
+            .output()
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn only_public_projects_are_cloned_and_pushes_are_refused() {
+        let root = TempDir::new().unwrap();
+        let cfg = Config::for_test(root.path());
+        fs::create_dir_all(&cfg.git_root).unwrap();
+        for id in ["open", "closed"] {
+            let dir = cfg.project_source_dir(&pid(id)).unwrap();
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join("main.tex"), id).unwrap();
+        }
+        let mut settings = load_project_settings(&cfg).unwrap();
+        settings.update("open", |s| s.public = true);
+        save_project_settings(&cfg, &settings).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let state = Arc::new(AppState::new(cfg));
+        tokio::spawn(serve_git_daemon(state, listener));
+
+        let work = root.path().join("work");
+        fs::create_dir_all(&work).unwrap();
+        let url = |id: &str| format!("git://127.0.0.1:{port}/{id}.git");
+
+        let cloned = git(&work, &["clone", "-q", &url("open"), "open"]).await;
+        assert!(
+            cloned.status.success(),
+            "{}",
+            String::from_utf8_lossy(&cloned.stderr)
+        );
+        assert_eq!(
+            fs::read_to_string(work.join("open/main.tex")).unwrap(),
+            "open"
+        );
+
+        let refused = git(&work, &["clone", "-q", &url("closed"), "closed"]).await;
+        assert!(!refused.status.success());
+        assert!(String::from_utf8_lossy(&refused.stderr).contains("not exported"));
+
+        let clone = work.join("open");
+        fs::write(clone.join("main.tex"), "changed").unwrap();
+        git(
+            &clone,
+            &[
+                "-c",
+                "user.name=t",
+                "-c",
+                "user.email=t@t",
+                "commit",
+                "-qam",
+                "x",
+            ],
+        )
+        .await;
+        let pushed = git(&clone, &["push", "-q", "origin", "HEAD"]).await;
+        assert!(!pushed.status.success());
+        assert!(String::from_utf8_lossy(&pushed.stderr).contains("push disabled"));
+    }
+}
@@ -1,149 +1,1463 @@
 use crate::config::Config;
 use crate::error::BridgeError;
-use axum::http::{HeaderMap, HeaderValue, Method, StatusCode};
-use std::io::{Read, Write};
-use std::process::{Command, Stdio};
-use tracing::{error, warn};
+use crate::project_id::ProjectId;
+use axum::body::{Body, Bytes, HttpBody};
+use axum::http::{HeaderMap, HeaderValue, Method, StatusCode, header};
+use hyper::body::Frame;
+use std::env;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::process::{Child, ChildStdout, Command, ExitStatus, Stdio};
+use std::sync::{Arc, Mutex, MutexGuard, Once, PoisonError};
+use std::task::{Context, Poll};
+use std::thread::JoinHandle;
+use tempfile::NamedTempFile;
+use tokio::runtime::Handle;
+use tokio::sync::mpsc;
+use tracing::{Span, debug, error, warn};
+
+/// The parts of a repository served to git clients. Everything else,
+/// including git's dumb HTTP object files unless `GIT_ALLOW_DUMB` is set, is
+/// answered with 404.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum GitEndpoint {
+    /// `info/refs`, the ref advertisement
+    InfoRefs,
+    /// `HEAD`, read directly from the mirror
+    Head,
+    /// `git-upload-pack`, fetches and clones
+    UploadPack,
+    /// `git-upload-archive`
+    UploadArchive,
+    /// `git-receive-pack`; always refused
+    ReceivePack,
+    /// `info/gitbridge`, the bridge's JSON status of the project
+    Status,
+}
+
+impl GitEndpoint {
+    /// Endpoint named by a canonical path below `<id>.git/`.
+    pub(crate) fn from_path(path: &str) -> Option<Self> {
+        match path {
+            "info/refs" => Some(Self::InfoRefs),
+            "HEAD" => Some(Self::Head),
+            "git-upload-pack" => Some(Self::UploadPack),
+            "git-upload-archive" => Some(Self::UploadArchive),
+            "git-receive-pack" => Some(Self::ReceivePack),
+            "info/gitbridge" => Some(Self::Status),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn path(self) -> &'static str {
+        match self {
+            Self::InfoRefs => "info/refs",
+            Self::Head => "HEAD",
+            Self::UploadPack => "git-upload-pack",
+            Self::UploadArchive => "git-upload-archive",
+            Self::ReceivePack => "git-receive-pack",
+            Self::Status => "info/gitbridge",
+        }
+    }
+
+    /// Whether git clients use `method` on this endpoint.
+    pub(crate) fn allows(self, method: &Method) -> bool {
+        match self {
+            Self::InfoRefs | Self::Head | Self::Status => {
+                matches!(*method, Method::GET | Method::HEAD)
+            }
+            Self::UploadPack | Self::UploadArchive | Self::ReceivePack => *method == Method::POST,
+        }
+    }
+}
+
+/// Whether `path` is one of the files git's dumb HTTP protocol fetches
+/// objects through: `objects/info/packs`, a loose object, or a pack and its
+/// index.
+pub(crate) fn is_dumb_object_file(path: &str) -> bool {
+    let is_hex = |s: &str, len: usize| s.len() == len && s.bytes().all(|b| b.is_ascii_hexdigit());
+    match path.split('/').collect::<Vec<_>>().as_slice() {
+        ["objects", "info", "packs"] => true,
+        ["objects", dir, file] if is_hex(dir, 2) && is_hex(file, 38) => true,
+        ["objects", "pack", file] => ["pack", "idx"].iter().any(|ext| {
+            file.strip_prefix("pack-")
+                .and_then(|rest| rest.strip_suffix(&format!(".{ext}")))
+                .is_some_and(|hash| is_hex(hash, 40) || is_hex(hash, 64))
+        }),
+        _ => false,
+    }
+}
+
+/// Whether a request comes from a git client rather than a browser or
+/// script: it asks for a protocol version or has git's `git/<version>` user
+/// agent (libgit2 and JGit based clients send one too).
+pub(crate) fn is_git_client(headers: &HeaderMap) -> bool {
+    headers.contains_key("git-protocol")
+        || headers
+            .get(header::USER_AGENT)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|agent| agent.starts_with("git/"))
+}
+
+/// `data` as a pkt-line: its length, with the four length digits, in hex,
+/// then `data`.
+fn pkt_line(data: &str) -> String {
+    format!("{:04x}{data}", data.len() + 4)
+}
+
+/// A refusal git shows its user, for an `info/refs` request of `service`: an
+/// advertisement holding a single `ERR` packet, which git reports as
+/// `remote error: <message>`. It is sent as `200`, since git reads the body
+/// of no error status.
+pub(crate) fn git_error_response(service: &str, message: &str) -> (StatusCode, HeaderMap, String) {
+    let mut headers = no_cache_headers();
+    let content_type = format!("application/x-{service}-advertisement");
+    if let Ok(value) = HeaderValue::from_str(&content_type) {
+        headers.insert(header::CONTENT_TYPE, value);
+    }
+    (StatusCode::OK, headers, pkt_line(&format!("ERR {message}")))
+}
+
+/// Headers telling clients and proxies not to cache a response, as
+/// `git http-backend` sends them for files that change with every sync.
+pub(crate) fn no_cache_headers() -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::EXPIRES,
+        HeaderValue::from_static("Fri, 01 Jan 1980 00:00:00 GMT"),
+    );
+    headers.insert(header::PRAGMA, HeaderValue::from_static("no-cache"));
+    headers.insert(
+        header::CACHE_CONTROL,
+        HeaderValue::from_static("no-cache, max-age=0, must-revalidate"),
+    );
+    headers
+}
+
+/// The `HEAD` file of a mirror, naming its default branch. Blocking.
+pub(crate) fn read_head_file(
+    cfg: &Config,
+    project_id: &ProjectId,
+) -> Result<(StatusCode, HeaderMap, Vec<u8>), BridgeError> {
+    let head = fs::read(cfg.bare_repo_dir(project_id)?.join("HEAD"))?;
+    let mut headers = no_cache_headers();
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("text/plain"));
+    Ok((StatusCode::OK, headers, head))
+}
+
+/// The `Content-Encoding` of a request body as `git http-backend` spells
+/// it: `gzip` for `gzip` and `x-gzip` in any case, `None` for no encoding or
+/// `identity`. The backend inflates gzip itself and fails on anything else,
+/// which is refused here with the encoding found.
+pub(crate) fn body_encoding(headers: &HeaderMap) -> Result<Option<&'static str>, String> {
+    let Some(value) = headers.get(header::CONTENT_ENCODING) else {
+        return Ok(None);
+    };
+    let encoding = value.to_str().map(str::trim).unwrap_or_default();
+    if encoding.eq_ignore_ascii_case("gzip") || encoding.eq_ignore_ascii_case("x-gzip") {
+        Ok(Some("gzip"))
+    } else if encoding.is_empty() || encoding.eq_ignore_ascii_case("identity") {
+        Ok(None)
+    } else {
+        Err(String::from_utf8_lossy(value.as_bytes()).into_owned())
+    }
+}
+
+/// Bytes of a streamed request body kept for the `safe.directory` retry.
+const MAX_REPLAYED_BODY: usize = 1 << 20;
+
+/// The body of a request to `git http-backend`. A client's body is not
+/// received in advance but streamed into the backend as it reads it, so
+/// fetch negotiations of mirrors with thousands of refs take neither memory
+/// nor disk. Its first [`MAX_REPLAYED_BODY`] bytes are kept to be sent again
+/// for the `safe.directory` retry, which git asks for before reading any.
+pub(crate) enum RequestBody {
+    Memory(Vec<u8>),
+    Streamed(Mutex<StreamedBody>),
+}
+
+/// A body still being received from the client.
+pub(crate) struct StreamedBody {
+    body: Body,
+    /// Runtime of the connection, driven by the backend's writer thread
+    runtime: Handle,
+    /// Everything received so far, `None` once that is more than
+    /// [`MAX_REPLAYED_BODY`]
+    received: Option<Vec<u8>>,
+    /// Received but not yet read, once `received` is gone
+    pending: Bytes,
+    len: u64,
+}
+
+impl RequestBody {
+    /// Stream `body` from the client. Must be called within the runtime.
+    pub(crate) fn stream(body: Body) -> Self {
+        RequestBody::Streamed(Mutex::new(StreamedBody {
+            body,
+            runtime: Handle::current(),
+            received: Some(Vec::new()),
+            pending: Bytes::new(),
+            len: 0,
+        }))
+    }
+
+    /// Bytes received from the client so far.
+    pub(crate) fn len(&self) -> u64 {
+        match self {
+            RequestBody::Memory(bytes) => bytes.len() as u64,
+            RequestBody::Streamed(body) => lock(body).len,
+        }
+    }
+
+    /// Read the body from its start. Blocking, and not to be called from
+    /// the runtime. Fails for a streamed body if more than
+    /// [`MAX_REPLAYED_BODY`] of it has been read before.
+    fn reader(&self) -> io::Result<Box<dyn Read + Send + '_>> {
+        Ok(match self {
+            RequestBody::Memory(bytes) => Box::new(bytes.as_slice()),
+            RequestBody::Streamed(body) => {
+                if lock(body).received.is_none() {
+                    return Err(io::Error::other(
+                        "the request body has been sent to git http-backend and cannot be sent again",
+                    ));
+                }
+                Box::new(StreamReader { body, pos: 0 })
+            }
+        })
+    }
+}
+
+fn lock(body: &Mutex<StreamedBody>) -> MutexGuard<'_, StreamedBody> {
+    body.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// A reader of a [`StreamedBody`], replaying what has been received before
+/// reading on.
+struct StreamReader<'a> {
+    body: &'a Mutex<StreamedBody>,
+    /// Position within `received`
+    pos: usize,
+}
+
+impl Read for StreamReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut body = lock(self.body);
+        loop {
+            if let Some(received) = &body.received
+                && self.pos < received.len()
+            {
+                let n = (&received[self.pos..]).read(buf)?;
+                self.pos += n;
+                return Ok(n);
+            }
+            if !body.pending.is_empty() {
+                let n = buf.len().min(body.pending.len());
+                buf[..n].copy_from_slice(&body.pending.split_to(n));
+                return Ok(n);
+            }
+            let Some(data) = body.next_chunk()? else {
+                return Ok(0);
+            };
+            body.len += data.len() as u64;
+            match &mut body.received {
+                Some(received) if received.len() + data.len() <= MAX_REPLAYED_BODY => {
+                    received.extend_from_slice(&data);
+                }
+                _ => {
+                    body.received = None;
+                    body.pending = data;
+                }
+            }
+        }
+    }
+}
+
+impl StreamedBody {
+    /// The next data received from the client, `None` at the end of the
+    /// body. An incomplete body fails with `ConnectionAborted`.
+    fn next_chunk(&mut self) -> io::Result<Option<Bytes>> {
+        loop {
+            let body = &mut self.body;
+            let frame = self.runtime.block_on(std::future::poll_fn(|cx| {
+                Pin::new(&mut *body).poll_frame(cx)
+            }));
+            match frame {
+                None => return Ok(None),
+                Some(Err(e)) => return Err(io::Error::new(io::ErrorKind::ConnectionAborted, e)),
+                // trailers carry no body data
+                Some(Ok(frame)) => {
+                    if let Ok(data) = frame.into_data()
+                        && !data.is_empty()
+                    {
+                        return Ok(Some(data));
+                    }
+                }
+            }
+        }
+    }
+}
 
 /// Call `git http-backend` like a CGI and translate its output into (StatusCode, headers, body).
-pub fn run_git_http_backend(
+/// `path` is the canonical path below `<id>.git/`. Returns once the backend
+/// has written its headers; the body is streamed from it as it is written.
+/// Blocking.
+pub(crate) fn run_git_http_backend(
     cfg: &Config,
-    path_tail: &str,
+    project_id: &ProjectId,
+    path: &str,
     method: &Method,
     query: Option<&str>,
-    content_type: Option<&HeaderValue>,
-    content_length: Option<&HeaderValue>,
-    request_body: &[u8],
-) -> Result<(StatusCode, HeaderMap, Vec<u8>), BridgeError> {
+    headers: &HeaderMap,
+    request_body: &Arc<RequestBody>,
+) -> Result<(StatusCode, HeaderMap, BackendBody), BridgeError> {
+    let request = BackendRequest {
+        project_id,
+        path,
+        method,
+        query,
+        headers,
+        body: request_body,
+    };
+    run_backend(OsStr::new("git"), cfg, &request)
+}
+
+/// A request forwarded to `git http-backend`.
+struct BackendRequest<'a> {
+    project_id: &'a str,
+    path: &'a str,
+    method: &'a Method,
+    query: Option<&'a str>,
+    headers: &'a HeaderMap,
+    body: &'a Arc<RequestBody>,
+}
+
+/// Run the backend through the `git` binary at `git`, retrying once with
+/// `safe.directory` set if git refuses the mirror for belonging to another
+/// user.
+fn run_backend(
+    git: &OsStr,
+    cfg: &Config,
+    request: &BackendRequest,
+) -> Result<(StatusCode, HeaderMap, BackendBody), BridgeError> {
+    let mut backend = spawn_backend(git, cfg, request, None)?;
+    if !backend.read_headers()? {
+        let (status, stdout_buf, stderr_buf) = backend.finish()?;
+        if !is_dubious_ownership(&stderr_buf) {
+            return buffered_response(status, stdout_buf, stderr_buf);
+        }
+        warn_about_ownership(cfg);
+        backend = spawn_backend(git, cfg, request, Some(safe_directory_config()?))?;
+        if !backend.read_headers()? {
+            let (status, stdout_buf, stderr_buf) = backend.finish()?;
+            return buffered_response(status, stdout_buf, stderr_buf);
+        }
+    }
+
+    let (status, headers, rest) = parse_cgi_response(std::mem::take(&mut backend.head))?;
+    let writer = backend.writer.take();
+    let body = backend.stream(rest);
+    // git writes its headers before reading the request; the response only
+    // starts once the request is in, or hyper would send it instead of the
+    // `100 Continue` a client may be waiting for
+    if let Some(writer) = writer {
+        check_written(writer.join().expect("request body writer panicked"))?;
+    }
+    Ok((status, forwarded_headers(headers), body))
+}
+
+/// The response of a backend that exited before finishing its headers
+/// (or right after them): errors, and short answers like `HEAD` requests.
+fn buffered_response(
+    status: ExitStatus,
+    stdout_buf: Vec<u8>,
+    stderr_buf: Vec<u8>,
+) -> Result<(StatusCode, HeaderMap, BackendBody), BridgeError> {
+    if killed_by_sigpipe(&status) {
+        // The client went away while the backend was still writing; its
+        // complaints about the hung-up remote end are expected.
+        if !stderr_buf.is_empty() {
+            debug!(
+                "git http-backend stderr: {}",
+                String::from_utf8_lossy(&stderr_buf)
+            );
+        }
+        return Err(BridgeError::ClientAborted(format!(
+            "git http-backend stopped writing to a closed connection ({status:?})"
+        )));
+    }
+    if !stderr_buf.is_empty() {
+        warn!(
+            "git http-backend stderr: {}",
+            String::from_utf8_lossy(&stderr_buf)
+        );
+    }
+    if !status.success() {
+        error!("git http-backend exited with {status:?}");
+        return Err(BridgeError::Other(format!(
+            "git http-backend failed with {status:?}"
+        )));
+    }
+
+    // Parse CGI-style output: headers \r\n\r\n body
+    let (status, headers, body) = parse_cgi_response(stdout_buf)?;
+    Ok((status, forwarded_headers(headers), BackendBody::from(body)))
+}
+
+/// Headers of `git http-backend` passed on to clients: what git sends for
+/// the smart and dumb protocols. None of them is hop-by-hop (RFC 7230,
+/// section 6.1), so `Connection`, `Transfer-Encoding` and the like are
+/// never forwarded, nor is anything else a newer git or a hook might add,
+/// such as `Set-Cookie`.
+const FORWARDED_HEADERS: &[header::HeaderName] = &[
+    header::CONTENT_TYPE,
+    header::CONTENT_LENGTH,
+    header::CACHE_CONTROL,
+    header::EXPIRES,
+    header::PRAGMA,
+    header::LAST_MODIFIED,
+];
+
+/// `headers` without the ones not in [`FORWARDED_HEADERS`], which are
+/// dropped with a debug log.
+fn forwarded_headers(headers: HeaderMap) -> HeaderMap {
+    let mut forwarded = HeaderMap::with_capacity(headers.len());
+    let mut name = None;
+    for (next, value) in headers {
+        // `None` continues the values of the previous name
+        if next.is_some() {
+            name = next;
+        }
+        let Some(name) = &name else { continue };
+        if FORWARDED_HEADERS.contains(name) {
+            forwarded.append(name.clone(), value);
+        } else {
+            debug!(header = %name, "not forwarding header from git http-backend");
+        }
+    }
+    forwarded
+}
+
+/// Longest CGI header block read from the backend.
+const MAX_CGI_HEAD: usize = 64 * 1024;
+
+/// Bytes read from the backend per body frame.
+const CHUNK_BYTES: usize = 64 * 1024;
+
+/// Body frames read ahead of the client; with [`CHUNK_BYTES`], what a
+/// transfer holds in memory however large the pack.
+const CHUNKS_AHEAD: usize = 4;
+
+/// A running `git http-backend`, fed its request body by a writer thread
+/// while its stderr is collected by another.
+struct Backend {
+    child: Child,
+    stdout: ChildStdout,
+    /// Output read so far, while looking for the end of the headers
+    head: Vec<u8>,
+    writer: Option<JoinHandle<io::Result<()>>>,
+    stderr: Option<JoinHandle<io::Result<Vec<u8>>>>,
+    /// Global config of the run, kept until it ends
+    global_config: Option<NamedTempFile>,
+}
+
+/// Start `git http-backend` for `request`. `global_config` replaces git's
+/// global config file.
+fn spawn_backend(
+    git: &OsStr,
+    cfg: &Config,
+    request: &BackendRequest,
+    global_config: Option<NamedTempFile>,
+) -> Result<Backend, BridgeError> {
     // Prepare env for git http-backend
-    let mut cmd = Command::new("git");
+    let git_protocol = header_str(
+        request.headers,
+        header::HeaderName::from_static("git-protocol"),
+    );
+    let mut cmd = Command::new(git);
     cmd.arg("http-backend")
-        .env("GIT_PROJECT_ROOT", &cfg.git_root)
+        .env("GIT_PROJECT_ROOT", cfg.repos_dir())
         .env("GIT_HTTP_EXPORT_ALL", "1")
-        .env("PATH_INFO", format!("/{}", path_tail))
-        .env("REQUEST_METHOD", method.as_str())
-        .env("QUERY_STRING", query.unwrap_or(""))
+        .env(
+            "PATH_INFO",
+            format!("/{}.git/{}", request.project_id, request.path),
+        )
+        .env("REQUEST_METHOD", request.method.as_str())
+        .env("QUERY_STRING", request.query.unwrap_or(""))
         .env(
             "CONTENT_TYPE",
-            content_type.and_then(|v| v.to_str().ok()).unwrap_or(""),
+            header_str(request.headers, header::CONTENT_TYPE),
         )
         .env(
             "CONTENT_LENGTH",
-            content_length.and_then(|v| v.to_str().ok()).unwrap_or(""),
+            header_str(request.headers, header::CONTENT_LENGTH),
         )
+        // git compresses large negotiations and asks for protocol v2
+        // through these headers
+        .env(
+            "HTTP_CONTENT_ENCODING",
+            header_str(request.headers, header::CONTENT_ENCODING),
+        )
+        .env("HTTP_GIT_PROTOCOL", git_protocol)
+        // the backend copies the header into `GIT_PROTOCOL` only if that is
+        // unset, so one inherited from the bridge would win; set it directly
+        .env("GIT_PROTOCOL", git_protocol)
         .env("REMOTE_USER", "gitbridge-ro")
+        .envs(backend_tuning_env(cfg))
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
         .stderr(Stdio::piped());
+    if let Some(global_config) = &global_config {
+        cmd.env("GIT_CONFIG_GLOBAL", global_config.path());
+    }
 
     let mut child = cmd.spawn().map_err(BridgeError::Io)?;
+    let stdin = child.stdin.take();
+    let stdout = child.stdout.take().expect("stdout is piped");
+    let stderr = child.stderr.take();
+    // Feed the request body while the output is read, so neither side
+    // stalls on a full pipe however large the negotiation gets.
+    let body = request.body.clone();
+    let writer = std::thread::spawn(move || match stdin {
+        Some(mut stdin) => io::copy(&mut body.reader()?, &mut stdin).map(drop),
+        None => Ok(()),
+    });
+    let stderr = std::thread::spawn(move || {
+        let mut stderr_buf = Vec::new();
+        if let Some(mut stderr) = stderr {
+            stderr.read_to_end(&mut stderr_buf)?;
+        }
+        Ok(stderr_buf)
+    });
+    Ok(Backend {
+        child,
+        stdout,
+        head: Vec::new(),
+        writer: Some(writer),
+        stderr: Some(stderr),
+        global_config,
+    })
+}
 
-    // write request body to stdin of child
-    if let Some(stdin) = child.stdin.as_mut() {
-        stdin.write_all(request_body).map_err(BridgeError::Io)?;
+impl Backend {
+    /// Read the output until the end of the CGI headers. `false` if the
+    /// backend closed its output first, which is then all in `head`.
+    fn read_headers(&mut self) -> Result<bool, BridgeError> {
+        let mut chunk = [0; 8192];
+        loop {
+            if head_end(&self.head).is_some() {
+                return Ok(true);
+            }
+            if self.head.len() > MAX_CGI_HEAD {
+                let _ = self.child.kill();
+                return Err(BridgeError::Other(format!(
+                    "git http-backend wrote more than {MAX_CGI_HEAD} bytes of headers"
+                )));
+            }
+            match self.stdout.read(&mut chunk) {
+                Ok(0) => return Ok(false),
+                Ok(n) => self.head.extend_from_slice(&chunk[..n]),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(BridgeError::Io(e)),
+            }
+        }
     }
-    drop(child.stdin.take());
 
-    // Read all stdout
-    let mut stdout_buf: Vec<u8> = Vec::new();
-    if let Some(mut stdout) = child.stdout.take() {
-        stdout
-            .read_to_end(&mut stdout_buf)
-            .map_err(BridgeError::Io)?;
+    /// Wait for a backend whose output has ended, returning its exit status,
+    /// stdout and stderr.
+    fn finish(mut self) -> Result<(ExitStatus, Vec<u8>, Vec<u8>), BridgeError> {
+        let (status, stderr_buf) = self.wait()?;
+        Ok((status, self.head, stderr_buf))
     }
 
-    // Capture stderr for logging
-    let mut stderr_buf: Vec<u8> = Vec::new();
-    if let Some(mut stderr) = child.stderr.take() {
-        stderr
-            .read_to_end(&mut stderr_buf)
-            .map_err(BridgeError::Io)?;
+    /// Wait for the backend to exit and its helper threads to end.
+    fn wait(&mut self) -> Result<(ExitStatus, Vec<u8>), BridgeError> {
+        let status = self.child.wait().map_err(BridgeError::Io)?;
+        let written = self.writer.take().map_or(Ok(()), |writer| {
+            writer.join().expect("request body writer panicked")
+        });
+        let stderr_buf = match self.stderr.take() {
+            Some(stderr) => stderr
+                .join()
+                .expect("stderr reader panicked")
+                .map_err(BridgeError::Io)?,
+            None => Vec::new(),
+        };
+        self.global_config = None;
+        check_written(written)?;
+        Ok((status, stderr_buf))
+    }
+
+    /// The body after the headers, starting with `rest`, read from the
+    /// backend by a thread that waits for it to exit afterwards. The request
+    /// body writer is left to the caller. A backend
+    /// that fails ends the body with an error instead of its end, so the
+    /// client sees a broken transfer rather than a short one.
+    fn stream(mut self, rest: Vec<u8>) -> BackendBody {
+        let (sender, chunks) = mpsc::channel(CHUNKS_AHEAD);
+        let span = Span::current();
+        std::thread::spawn(move || {
+            let _entered = span.enter();
+            let delivered = self.send_output(rest, &sender);
+            let failure = match self.wait() {
+                Ok((status, stderr_buf)) => backend_exit(status, &stderr_buf, delivered),
+                Err(e) => {
+                    error!("git http-backend error: {e}");
+                    Some(e.to_string())
+                }
+            };
+            if let Some(failure) = failure {
+                let _ = sender.blocking_send(Err(io::Error::other(failure)));
+            }
+        });
+        BackendBody {
+            buffered: None,
+            chunks: Some(chunks),
+            sent: 0,
+            on_end: None,
+            on_failure: None,
+        }
+    }
+
+    /// Send `rest` and the remaining output through `sender`. `false` if
+    /// the body was dropped first, i.e. the client went away.
+    fn send_output(&mut self, rest: Vec<u8>, sender: &mpsc::Sender<io::Result<Bytes>>) -> bool {
+        if !rest.is_empty() && sender.blocking_send(Ok(Bytes::from(rest))).is_err() {
+            return false;
+        }
+        loop {
+            let mut chunk = vec![0; CHUNK_BYTES];
+            match self.stdout.read(&mut chunk) {
+                Ok(0) => return true,
+                Ok(n) => {
+                    chunk.truncate(n);
+                    if sender.blocking_send(Ok(Bytes::from(chunk))).is_err() {
+                        // closing stdout makes the backend stop
+                        let _ = self.child.kill();
+                        return false;
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => {
+                    error!("cannot read the output of git http-backend: {e}");
+                    let _ = self.child.kill();
+                    let _ = sender.blocking_send(Err(e));
+                    return false;
+                }
+            }
+        }
+    }
+}
+
+/// The outcome of feeding the request body to the backend.
+fn check_written(written: io::Result<()>) -> Result<(), BridgeError> {
+    match written {
+        // the backend may answer without reading everything, e.g. on errors
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {
+            debug!("git http-backend did not read the whole request body");
+            Ok(())
+        }
+        Err(e) if e.kind() == io::ErrorKind::ConnectionAborted => Err(BridgeError::ClientAborted(
+            format!("request body not received: {e}"),
+        )),
+        Err(e) => Err(BridgeError::Io(e)),
+        Ok(()) => Ok(()),
+    }
+}
+
+/// Log how a backend streaming its body exited; the failure to end the body
+/// with, if any. `delivered` is whether its whole output reached the body.
+fn backend_exit(status: ExitStatus, stderr_buf: &[u8], delivered: bool) -> Option<String> {
+    if !delivered || killed_by_sigpipe(&status) {
+        // the client went away; the backend was stopped
+        if !stderr_buf.is_empty() {
+            debug!(
+                "git http-backend stderr: {}",
+                String::from_utf8_lossy(stderr_buf)
+            );
+        }
+        return None;
     }
     if !stderr_buf.is_empty() {
         warn!(
             "git http-backend stderr: {}",
-            String::from_utf8_lossy(&stderr_buf)
+            String::from_utf8_lossy(stderr_buf)
         );
     }
+    if status.success() {
+        return None;
+    }
+    error!("git http-backend exited with {status:?} while sending its response");
+    Some(format!("git http-backend failed with {status:?}"))
+}
 
-    // make sure process exited "successfully"
-    let status = child.wait().map_err(BridgeError::Io)?;
-    if !status.success() {
-        error!("git http-backend exited with {status:?}");
-        return Err(BridgeError::Other(format!(
-            "git http-backend failed with {status:?}"
-        )));
+/// Where the CGI header block of `out` ends, if it does: after its first
+/// empty line.
+fn head_end(out: &[u8]) -> Option<usize> {
+    let mut pos = 0;
+    while let Some(len) = out[pos..].iter().position(|&b| b == b'\n') {
+        let line = &out[pos..pos + len];
+        pos += len + 1;
+        if line.is_empty() || line == b"\r" {
+            return Some(pos);
+        }
     }
+    None
+}
 
-    // Parse CGI-style output: headers \r\n\r\n body
-    parse_cgi_response(stdout_buf)
+/// The body of a response of `git http-backend`, either read in full or
+/// streamed from the running backend.
+pub(crate) struct BackendBody {
+    buffered: Option<Bytes>,
+    chunks: Option<mpsc::Receiver<io::Result<Bytes>>>,
+    sent: u64,
+    on_end: Option<Box<dyn FnOnce(u64) + Send>>,
+    on_failure: Option<Box<dyn FnOnce() + Send>>,
 }
 
-fn parse_cgi_response(mut all: Vec<u8>) -> Result<(StatusCode, HeaderMap, Vec<u8>), BridgeError> {
-    // find header/body split
-    let split_seq = b"\r\n\r\n";
-    let split_alt = b"\n\n";
-
-    let (header_bytes, body_start_idx) = if let Some(idx) = find_subslice(&all, split_seq) {
-        let (h, _rest) = all.split_at(idx);
-        (h.to_vec(), idx + split_seq.len())
-    } else if let Some(idx) = find_subslice(&all, split_alt) {
-        let (h, _rest) = all.split_at(idx);
-        (h.to_vec(), idx + split_alt.len())
-    } else {
-        return Err(BridgeError::Other(
-            "git http-backend output missing header delimiter".into(),
+impl From<Vec<u8>> for BackendBody {
+    fn from(body: Vec<u8>) -> Self {
+        Self {
+            buffered: Some(Bytes::from(body)).filter(|body| !body.is_empty()),
+            chunks: None,
+            sent: 0,
+            on_end: None,
+            on_failure: None,
+        }
+    }
+}
+
+impl BackendBody {
+    /// Call `on_end` with the number of bytes sent once the body is
+    /// finished or dropped.
+    pub(crate) fn on_end(mut self, on_end: impl FnOnce(u64) + Send + 'static) -> Self {
+        self.on_end = Some(Box::new(on_end));
+        self
+    }
+
+    /// The size of a body read in full, `None` for a streamed one.
+    pub(crate) fn len(&self) -> Option<u64> {
+        match (&self.buffered, &self.chunks) {
+            (_, Some(_)) => None,
+            (Some(data), None) => Some(data.len() as u64),
+            (None, None) => Some(0),
+        }
+    }
+
+    /// Call `on_failure` if the backend fails while the body is sent.
+    pub(crate) fn on_failure(mut self, on_failure: impl FnOnce() + Send + 'static) -> Self {
+        self.on_failure = Some(Box::new(on_failure));
+        self
+    }
+
+    /// The whole body. Blocking, and not to be called from the runtime.
+    pub(crate) fn into_vec(mut self) -> Result<Vec<u8>, BridgeError> {
+        let mut body = self.buffered.take().map(Vec::from).unwrap_or_default();
+        if let Some(mut chunks) = self.chunks.take() {
+            while let Some(chunk) = chunks.blocking_recv() {
+                body.extend_from_slice(&chunk?);
+            }
+        }
+        Ok(body)
+    }
+}
+
+impl HttpBody for BackendBody {
+    type Data = Bytes;
+    type Error = io::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, io::Error>>> {
+        let polled = if let Some(data) = self.buffered.take() {
+            Poll::Ready(Some(Ok(data)))
+        } else if let Some(chunks) = &mut self.chunks {
+            chunks.poll_recv(cx)
+        } else {
+            Poll::Ready(None)
+        };
+        match polled {
+            Poll::Pending => Poll::Pending,
+            Poll::Ready(Some(Ok(data))) => {
+                self.sent += data.len() as u64;
+                Poll::Ready(Some(Ok(Frame::data(data))))
+            }
+            Poll::Ready(Some(Err(e))) => {
+                self.chunks = None;
+                if let Some(on_failure) = self.on_failure.take() {
+                    on_failure();
+                }
+                Poll::Ready(Some(Err(e)))
+            }
+            Poll::Ready(None) => {
+                self.chunks = None;
+                Poll::Ready(None)
+            }
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.buffered.is_none() && self.chunks.is_none()
+    }
+}
+
+impl Drop for BackendBody {
+    fn drop(&mut self) {
+        if let Some(on_end) = self.on_end.take() {
+            on_end(self.sent);
+        }
+    }
+}
+
+/// Whether git refused a repository for being owned by another user
+/// (CVE-2022-24765), in the wording of git 2.35.2 or later releases.
+fn is_dubious_ownership(stderr: &[u8]) -> bool {
+    let stderr = String::from_utf8_lossy(stderr);
+    stderr.contains("detected dubious ownership") || stderr.contains("unsafe repository")
+}
+
+/// Tell the operator once per process that `GIT_ROOT` has the wrong owner.
+fn warn_about_ownership(cfg: &Config) {
+    static WARNED: Once = Once::new();
+    WARNED.call_once(|| {
+        warn!(
+            git_root = %cfg.git_root.display(),
+            "git refuses mirrors owned by another user; serving them with safe.directory \
+             set for each request. Change the owner of GIT_ROOT to the user running the \
+             bridge (or match the UID of the bind mount) to fix this"
+        );
+    });
+}
+
+/// A generated global git config trusting any repository. It only reaches a
+/// backend run for one mirror, and `git upload-pack` checks the mirror as
+/// `.`, which a path would not match. The global config it replaces is
+/// included, so other settings still apply.
+fn safe_directory_config() -> Result<NamedTempFile, BridgeError> {
+    let mut config = String::new();
+    let global = env::var_os("GIT_CONFIG_GLOBAL")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".gitconfig")));
+    if let Some(global) = global.filter(|path| path.is_file()) {
+        config.push_str(&format!(
+            "[include]\n\tpath = {}\n",
+            quote_config_value(&global)
         ));
-    };
+    }
+    config.push_str("[safe]\n\tdirectory = *\n");
+    let mut file = NamedTempFile::new()?;
+    file.write_all(config.as_bytes())?;
+    Ok(file)
+}
 
-    let body_bytes = all.split_off(body_start_idx);
+/// `path` as a double-quoted git config value.
+fn quote_config_value(path: &Path) -> String {
+    let escaped = path
+        .to_string_lossy()
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"");
+    format!("\"{escaped}\"")
+}
+
+/// Whether the backend died of SIGPIPE, i.e. the reading end of its output
+/// was closed because the client disconnected.
+fn killed_by_sigpipe(status: &ExitStatus) -> bool {
+    use std::os::unix::process::ExitStatusExt;
+    const SIGPIPE: i32 = 13;
+    status.signal() == Some(SIGPIPE)
+}
+
+fn header_str(headers: &HeaderMap, name: header::HeaderName) -> &str {
+    headers
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("")
+}
+
+/// Environment applying the `GIT_BACKEND_*` settings and `GIT_HIDE_REFS`
+/// to `git http-backend` and the `git://` upload-pack.
+/// Config uses `GIT_CONFIG_COUNT` so values need no quoting.
+pub(crate) fn backend_tuning_env(cfg: &Config) -> Vec<(String, String)> {
+    let mut vars = Vec::new();
+    if let Some(bytes) = cfg.git_backend_max_request_buffer {
+        vars.push(("GIT_HTTP_MAX_REQUEST_BUFFER".to_string(), bytes.to_string()));
+    }
+    if cfg.git_backend_trace_packet {
+        vars.push(("GIT_TRACE_PACKET".to_string(), "1".to_string()));
+    }
+    // Clients that already know a tip may still ask for it after it was
+    // hidden; `GIT_BACKEND_EXTRA_CONFIG` comes last and can turn this off.
+    let mut config = vec![("uploadpack.allowTipSHA1InWant", "true")];
+    config.extend(
+        cfg.git_hide_refs
+            .iter()
+            .map(|prefix| ("transfer.hideRefs", prefix.as_str())),
+    );
+    config.extend(
+        cfg.git_backend_extra_config
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str())),
+    );
+    vars.push(("GIT_CONFIG_COUNT".to_string(), config.len().to_string()));
+    for (i, (key, value)) in config.into_iter().enumerate() {
+        vars.push((format!("GIT_CONFIG_KEY_{i}"), key.to_string()));
+        vars.push((format!("GIT_CONFIG_VALUE_{i}"), value.to_string()));
+    }
+    vars
+}
+
+/// Split the output of `git http-backend` into status, headers and body.
+/// The CGI header block ends at the first empty line; lines may end in
+/// `\r\n` or a bare `\n`. A missing `Status` header means `200`, and its
+/// reason phrase is optional. Repeated headers are all kept, and headers
+/// that are not valid HTTP are dropped with a warning rather than failing
+/// the response. Output without the empty line is only accepted as a
+/// response without body: complete lines, each of them a header.
+fn parse_cgi_response(mut all: Vec<u8>) -> Result<(StatusCode, HeaderMap, Vec<u8>), BridgeError> {
+    let mut lines = Vec::new();
+    let mut pos = 0;
+    let mut body_start = None;
+    while let Some(len) = all[pos..].iter().position(|&b| b == b'\n') {
+        let next = pos + len + 1;
+        let mut end = pos + len;
+        if end > pos && all[end - 1] == b'\r' {
+            end -= 1;
+        }
+        let line = pos..end;
+        if line.is_empty() {
+            body_start = Some(next);
+            break;
+        }
+        lines.push(line);
+        pos = next;
+    }
+    let body_bytes = match body_start {
+        Some(start) => all.split_off(start),
+        None => {
+            let complete = !lines.is_empty() && pos == all.len();
+            if !complete || lines.iter().any(|line| !all[line.clone()].contains(&b':')) {
+                return Err(BridgeError::Other(
+                    "git http-backend output missing header delimiter".into(),
+                ));
+            }
+            Vec::new()
+        }
+    };
 
-    // parse headers line by line
-    let header_text = String::from_utf8(header_bytes)?;
     let mut status_code = StatusCode::OK;
     let mut headers = HeaderMap::new();
+    for line in lines {
+        let line = &all[line];
+        // lines without ':' are ignored quietly
+        let Some(colon) = line.iter().position(|&b| b == b':') else {
+            continue;
+        };
+        let name = line[..colon].trim_ascii();
+        let value = line[colon + 1..].trim_ascii();
 
-    for line in header_text.lines() {
-        let line = line.trim();
-        if line.is_empty() {
+        if name.eq_ignore_ascii_case(b"Status") {
+            // "Status: 200 OK", or just "Status: 200"
+            if let Some(code) = value.split(|&b| b == b' ').next()
+                && let Ok(code) = std::str::from_utf8(code)
+                && let Ok(code) = code.parse::<u16>()
+                && let Ok(sc) = StatusCode::from_u16(code)
+            {
+                status_code = sc;
+            }
             continue;
         }
-        if let Some((name, value)) = line.split_once(':') {
-            let name = name.trim();
-            let value = value.trim();
-
-            if name.eq_ignore_ascii_case("Status") {
-                // "Status: 200 OK"
-                let mut parts = value.split_whitespace();
-                if let Some(code_str) = parts.next() {
-                    if let Ok(code_u16) = code_str.parse::<u16>() {
-                        if let Ok(sc) = StatusCode::from_u16(code_u16) {
-                            status_code = sc;
-                        }
-                    }
-                }
-                continue;
-            }
 
-            let header_name = axum::http::HeaderName::from_bytes(name.as_bytes())
-                .map_err(|e| BridgeError::HeaderName(e.to_string()))?;
-            let header_val = axum::http::HeaderValue::from_str(value)
-                .map_err(|e| BridgeError::HeaderValue(e.to_string()))?;
-            headers.append(header_name, header_val);
-        } else {
-            // line without ':', ignore quietly
+        match (
+            axum::http::HeaderName::from_bytes(name),
+            HeaderValue::from_bytes(value),
+        ) {
+            (Ok(name), Ok(value)) => {
+                headers.append(name, value);
+            }
+            _ => warn!(
+                header = %String::from_utf8_lossy(line),
+                "skipping invalid header from git http-backend"
+            ),
         }
     }
 
     Ok((status_code, headers, body_bytes))
 }
 
-/// Find first occurrence of needle in haystack, return start index.
-fn find_subslice(haystack: &[u8], needle: &[u8]) -> Option<usize> {
-    if needle.is_empty() {
-        return Some(0);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::project_id::pid;
+
+    #[test]
+    fn only_smart_http_endpoints_are_known() {
+        for endpoint in [
+            GitEndpoint::InfoRefs,
+            GitEndpoint::Head,
+            GitEndpoint::UploadPack,
+            GitEndpoint::UploadArchive,
+            GitEndpoint::ReceivePack,
+        ] {
+            assert_eq!(GitEndpoint::from_path(endpoint.path()), Some(endpoint));
+        }
+        for path in [
+            "",
+            "objects/info/packs",
+            "info/refs/x",
+            "config",
+            "hooks/pre-receive",
+        ] {
+            assert_eq!(GitEndpoint::from_path(path), None, "{path}");
+        }
+        assert!(GitEndpoint::InfoRefs.allows(&Method::GET));
+        assert!(!GitEndpoint::UploadPack.allows(&Method::GET));
+        assert!(!GitEndpoint::Head.allows(&Method::POST));
+    }
+
+    #[test]
+    fn dubious_ownership_is_retried_as_a_safe_directory() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = tempfile::TempDir::new().unwrap();
+        let cfg = Config::for_test(root.path());
+        fs::create_dir_all(cfg.bare_repo_dir(&pid("p1")).unwrap()).unwrap();
+        // stands in for git: refuses the repo unless a global config trusts it
+        let shim = root.path().join("bin/git");
+        fs::create_dir_all(root.path().join("bin")).unwrap();
+        let calls = root.path().join("calls");
+        fs::write(
+            &shim,
+            format!(
+                "#!/bin/sh\n\
+                 echo call >> '{}'\n\
+                 if grep -q 'directory = \\*' \"${{GIT_CONFIG_GLOBAL:-/nonexistent}}\" 2>/dev/null; then\n\
+                 printf 'Content-Type: text/plain\\r\\n\\r\\nserved'\n\
+                 else\n\
+                 echo \"fatal: detected dubious ownership in repository at '$GIT_PROJECT_ROOT$PATH_INFO'\" >&2\n\
+                 exit 128\n\
+                 fi\n",
+                calls.display(),
+            ),
+        )
+        .unwrap();
+        fs::set_permissions(&shim, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let headers = HeaderMap::new();
+        let request = BackendRequest {
+            project_id: "p1",
+            path: "info/refs",
+            method: &Method::GET,
+            query: None,
+            headers: &headers,
+            body: &Arc::new(RequestBody::Memory(Vec::new())),
+        };
+        let (status, _, body) = run_backend(shim.as_os_str(), &cfg, &request).unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body.into_vec().unwrap(), b"served");
+        assert_eq!(fs::read_to_string(&calls).unwrap().lines().count(), 2);
+
+        assert!(!is_dubious_ownership(b"fatal: repository not found"));
+        assert_eq!(
+            quote_config_value(Path::new("/srv/a \"b\"")),
+            r#""/srv/a \"b\"""#
+        );
+    }
+
+    #[test]
+    fn only_known_backend_headers_reach_the_client() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = tempfile::TempDir::new().unwrap();
+        let cfg = Config::for_test(root.path());
+        let shim = root.path().join("git");
+        fs::write(
+            &shim,
+            "#!/bin/sh\n\
+             printf 'Status: 200 OK\\r\\n\\
+             Content-Type: application/x-git-upload-pack-advertisement\\r\\n\\
+             Expires: Fri, 01 Jan 1980 00:00:00 GMT\\r\\n\\
+             Cache-Control: no-cache, max-age=0, must-revalidate\\r\\n\\
+             Cache-Control: no-store\\r\\n\\
+             Set-Cookie: session=abc\\r\\n\\
+             Connection: close\\r\\n\\
+             Keep-Alive: timeout=5\\r\\n\\
+             Transfer-Encoding: chunked\\r\\n\\
+             Upgrade: h2c\\r\\n\\
+             X-Powered-By: hook\\r\\n\\r\\nrefs'\n",
+        )
+        .unwrap();
+        fs::set_permissions(&shim, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let headers = HeaderMap::new();
+        let request = BackendRequest {
+            project_id: "p1",
+            path: "info/refs",
+            method: &Method::GET,
+            query: Some("service=git-upload-pack"),
+            headers: &headers,
+            body: &Arc::new(RequestBody::Memory(Vec::new())),
+        };
+        let (status, headers, body) = run_backend(shim.as_os_str(), &cfg, &request).unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body.into_vec().unwrap(), b"refs");
+        let mut names: Vec<_> = headers.keys().map(|name| name.as_str()).collect();
+        names.sort();
+        assert_eq!(names, ["cache-control", "content-type", "expires"]);
+        assert_eq!(headers.get_all(header::CACHE_CONTROL).iter().count(), 2);
+    }
+
+    /// A client sending `chunks` chunks of 64 KiB, made as they are polled.
+    struct SlowUpload {
+        chunks: usize,
+    }
+
+    impl HttpBody for SlowUpload {
+        type Data = Bytes;
+        type Error = io::Error;
+
+        fn poll_frame(
+            mut self: Pin<&mut Self>,
+            _cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<Option<Result<hyper::body::Frame<Bytes>, io::Error>>> {
+            if self.chunks == 0 {
+                return std::task::Poll::Ready(None);
+            }
+            self.chunks -= 1;
+            let chunk = Bytes::from(vec![b'0' + (self.chunks % 10) as u8; 64 << 10]);
+            std::task::Poll::Ready(Some(Ok(hyper::body::Frame::data(chunk))))
+        }
+    }
+
+    #[tokio::test]
+    async fn request_bodies_are_streamed_into_the_backend() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = tempfile::TempDir::new().unwrap();
+        let cfg = Config::for_test(root.path());
+        // stands in for git: refuses the first call without reading the
+        // body, then answers with the size of the body it read
+        let shim = root.path().join("git");
+        let calls = root.path().join("calls");
+        fs::write(
+            &shim,
+            format!(
+                "#!/bin/sh\n\
+                 echo call >> '{calls}'\n\
+                 if [ \"$(wc -l < '{calls}')\" -eq 1 ]; then\n\
+                 echo 'fatal: detected dubious ownership in repository' >&2\n\
+                 exit 128\n\
+                 fi\n\
+                 printf 'Content-Type: text/plain\\r\\n\\r\\n'\n\
+                 wc -c | tr -d ' '\n",
+                calls = calls.display(),
+            ),
+        )
+        .unwrap();
+        fs::set_permissions(&shim, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let upload = |chunks: usize, refuse_once: bool| {
+            let (cfg, shim, calls) = (cfg.clone(), shim.clone(), calls.clone());
+            let body = Arc::new(RequestBody::stream(Body::new(SlowUpload { chunks })));
+            tokio::task::spawn_blocking(move || {
+                fs::write(&calls, if refuse_once { "" } else { "call\n" }).unwrap();
+                let headers = HeaderMap::new();
+                let request = BackendRequest {
+                    project_id: "p1",
+                    path: "git-upload-pack",
+                    method: &Method::POST,
+                    query: None,
+                    headers: &headers,
+                    body: &body,
+                };
+                let out = run_backend(shim.as_os_str(), &cfg, &request)
+                    .and_then(|(_, _, out)| out.into_vec());
+                let RequestBody::Streamed(streamed) = &*body else {
+                    unreachable!()
+                };
+                let kept = lock(streamed).received.as_ref().map(Vec::len);
+                (out, body.len(), kept)
+            })
+        };
+
+        // a small body is sent again for the safe.directory retry
+        let (out, len, kept) = upload(4, true).await.unwrap();
+        assert_eq!(out.unwrap(), b"262144\n");
+        assert_eq!((len, kept), (4 << 16, Some(4 << 16)));
+
+        // 8 MiB pass through without being kept
+        let (out, len, kept) = upload(128, false).await.unwrap();
+        assert_eq!(out.unwrap(), b"8388608\n");
+        assert_eq!((len, kept), (8 << 20, None));
+
+        // which leaves nothing to retry with once more than the replay limit
+        // has been read
+        let body = RequestBody::stream(Body::new(SlowUpload { chunks: 32 }));
+        tokio::task::spawn_blocking(move || {
+            io::copy(&mut body.reader().unwrap(), &mut io::sink()).unwrap();
+            assert!(body.reader().is_err());
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn responses_are_streamed_from_the_backend() {
+        use std::os::unix::fs::PermissionsExt;
+        use std::sync::atomic::{AtomicU64, Ordering};
+
+        let root = tempfile::TempDir::new().unwrap();
+        let cfg = Config::for_test(root.path());
+        // stands in for git: answers with 16 MiB once `go` exists, then
+        // exits with the code in it
+        let shim = root.path().join("git");
+        let go = root.path().join("go");
+        fs::write(
+            &shim,
+            format!(
+                "#!/bin/sh\n\
+                 printf 'Content-Type: application/x-git-upload-pack-result\\r\\n\\r\\n'\n\
+                 while [ ! -f '{go}' ]; do sleep 0.01; done\n\
+                 head -c 16777216 /dev/zero\n\
+                 exit \"$(cat '{go}')\"\n",
+                go = go.display(),
+            ),
+        )
+        .unwrap();
+        fs::set_permissions(&shim, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let respond = |code: &'static str| {
+            let (cfg, shim, go) = (cfg.clone(), shim.clone(), go.clone());
+            let _ = fs::remove_file(&go);
+            tokio::task::spawn_blocking(move || {
+                let headers = HeaderMap::new();
+                let request = BackendRequest {
+                    project_id: "p1",
+                    path: "git-upload-pack",
+                    method: &Method::POST,
+                    query: None,
+                    headers: &headers,
+                    body: &Arc::new(RequestBody::Memory(Vec::new())),
+                };
+                // the headers are back while the backend is still waiting
+                let response = run_backend(shim.as_os_str(), &cfg, &request).unwrap();
+                fs::write(&go, code).unwrap();
+                response
+            })
+        };
+
+        let (status, headers, body) = respond("0").await.unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            headers[header::CONTENT_TYPE],
+            "application/x-git-upload-pack-result"
+        );
+        let sent = Arc::new(AtomicU64::new(0));
+        let counted = sent.clone();
+        let body = body.on_end(move |n| counted.store(n, Ordering::Relaxed));
+        let body = axum::body::to_bytes(Body::new(body), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(body.len(), 16 << 20);
+        assert_eq!(sent.load(Ordering::Relaxed), 16 << 20);
+
+        // a backend failing after its headers breaks the body off
+        let (status, _, body) = respond("3").await.unwrap();
+        assert_eq!(status, StatusCode::OK);
+        let failures = Arc::new(AtomicU64::new(0));
+        let counted = failures.clone();
+        let body = body.on_failure(move || {
+            counted.fetch_add(1, Ordering::Relaxed);
+        });
+        let failed = axum::body::to_bytes(Body::new(body), usize::MAX).await;
+        assert!(failed.is_err());
+        assert_eq!(failures.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn refs_are_hidden_through_git_config() {
+        let root = tempfile::TempDir::new().unwrap();
+        let mut cfg = Config::for_test(root.path());
+        cfg.git_hide_refs = vec!["refs/gitbridge/".into()];
+        cfg.git_backend_extra_config =
+            vec![("uploadpack.allowTipSHA1InWant".into(), "false".into())];
+        let env: Vec<String> = backend_tuning_env(&cfg)
+            .into_iter()
+            .map(|(k, v)| format!("{k}={v}"))
+            .collect();
+        assert_eq!(
+            env,
+            [
+                "GIT_CONFIG_COUNT=3",
+                "GIT_CONFIG_KEY_0=uploadpack.allowTipSHA1InWant",
+                "GIT_CONFIG_VALUE_0=true",
+                "GIT_CONFIG_KEY_1=transfer.hideRefs",
+                "GIT_CONFIG_VALUE_1=refs/gitbridge/",
+                "GIT_CONFIG_KEY_2=uploadpack.allowTipSHA1InWant",
+                "GIT_CONFIG_VALUE_2=false",
+            ]
+        );
+    }
+
+    #[test]
+    fn refusals_are_framed_as_err_packets() {
+        let (status, headers, body) = git_error_response(
+            "git-receive-pack",
+            "push is disabled on this read-only mirror",
+        );
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            headers[header::CONTENT_TYPE],
+            "application/x-git-receive-pack-advertisement"
+        );
+        assert_eq!(headers[header::PRAGMA], "no-cache");
+        assert_eq!(body, "0031ERR push is disabled on this read-only mirror");
+        assert_eq!(pkt_line(""), "0004");
+
+        let client = |name: &str, value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(
+                header::HeaderName::from_bytes(name.as_bytes()).unwrap(),
+                HeaderValue::from_str(value).unwrap(),
+            );
+            is_git_client(&headers)
+        };
+        assert!(client("user-agent", "git/2.43.0"));
+        assert!(client("user-agent", "git/2.0 (libgit2 1.7.1)"));
+        assert!(client("git-protocol", "version=2"));
+        assert!(!client("user-agent", "curl/8.5.0"));
+        assert!(!client("user-agent", "Mozilla/5.0 (X11; Linux x86_64)"));
+        assert!(!is_git_client(&HeaderMap::new()));
+    }
+
+    #[test]
+    fn dumb_object_files_are_recognized() {
+        for path in [
+            "objects/info/packs",
+            "objects/ab/cdef0123456789abcdef0123456789abcdef01",
+            "objects/pack/pack-0123456789abcdef0123456789abcdef01234567.pack",
+            "objects/pack/pack-0123456789abcdef0123456789abcdef01234567.idx",
+        ] {
+            assert!(is_dumb_object_file(path), "{path}");
+        }
+        for path in [
+            "objects/info/alternates",
+            "objects/ab/cd",
+            "objects/pack/pack-0123.pack",
+            "objects/pack/pack-0123456789abcdef0123456789abcdef01234567.keep",
+            "config",
+            "info/refs",
+        ] {
+            assert!(!is_dumb_object_file(path), "{path}");
+        }
+    }
+
+    /// Output of `git http-backend` (git 2.39) captured below
+    /// `tests/fixtures/http-backend`.
+    fn fixture(name: &str) -> Vec<u8> {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/http-backend");
+        fs::read(dir.join(name)).unwrap()
+    }
+
+    /// The bytes of `out` after its CRLF header block.
+    fn body_of(out: &[u8]) -> &[u8] {
+        let end = out.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        &out[end + 4..]
+    }
+
+    #[test]
+    fn captured_backend_outputs_are_parsed() {
+        let out = fixture("info-refs.out");
+        let (status, headers, body) = parse_cgi_response(out.clone()).unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            headers[header::CONTENT_TYPE],
+            "application/x-git-upload-pack-advertisement"
+        );
+        assert_eq!(headers[header::PRAGMA], "no-cache");
+        assert_eq!(headers.len(), 4);
+        assert_eq!(body, body_of(&out));
+        assert!(body.starts_with(b"001e# service=git-upload-pack\n0000"));
+        assert!(body.ends_with(b"0000"));
+
+        let out = fixture("upload-pack.out");
+        let (status, headers, body) = parse_cgi_response(out.clone()).unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            headers[header::CONTENT_TYPE],
+            "application/x-git-upload-pack-result"
+        );
+        assert_eq!(body, body_of(&out));
+        assert!(body.starts_with(b"0008NAK\nPACK"));
+
+        let (status, headers, body) = parse_cgi_response(fixture("missing-repo.out")).unwrap();
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert!(!headers.contains_key("status"));
+        assert_eq!(headers.len(), 3);
+        assert!(body.is_empty());
+
+        // sent with its no-cache headers twice, all of which are kept
+        let (status, headers, body) =
+            parse_cgi_response(fixture("receive-pack-disabled.out")).unwrap();
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(headers.get_all(header::EXPIRES).iter().count(), 2);
+        assert_eq!(headers.get_all(header::CACHE_CONTROL).iter().count(), 2);
+        assert!(body.is_empty());
+    }
+
+    #[test]
+    fn bare_line_feeds_and_status_without_reason_are_accepted() {
+        // the body may contain what looks like a CRLF header delimiter
+        let out = b"Status: 200\nContent-Type: text/plain\n\nbody\r\n\r\nmore".to_vec();
+        let (status, headers, body) = parse_cgi_response(out).unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers[header::CONTENT_TYPE], "text/plain");
+        assert_eq!(body, b"body\r\n\r\nmore");
+
+        let (status, headers, body) = parse_cgi_response(b"Status: 304\r\n\n".to_vec()).unwrap();
+        assert_eq!(status, StatusCode::NOT_MODIFIED);
+        assert!(headers.is_empty());
+        assert!(body.is_empty());
+
+        // an unknown status leaves the default
+        let (status, _, _) = parse_cgi_response(b"Status: teapot\n\n".to_vec()).unwrap();
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[test]
+    fn invalid_headers_are_skipped() {
+        let out = b"Content-Type: text/plain\r\nBad Name: x\r\nX-Control: a\x01b\r\n\
+            X-Latin: caf\xe9\r\nno colon here\r\n\r\nhi"
+            .to_vec();
+        let (status, headers, body) = parse_cgi_response(out).unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers.len(), 2);
+        assert_eq!(headers[header::CONTENT_TYPE], "text/plain");
+        assert_eq!(headers["x-latin"].as_bytes(), b"caf\xe9");
+        assert_eq!(body, b"hi");
+    }
+
+    #[test]
+    fn only_complete_header_blocks_may_lack_the_delimiter() {
+        let out = b"Status: 404 Not Found\r\nContent-Type: text/plain\r\n".to_vec();
+        let (status, headers, body) = parse_cgi_response(out).unwrap();
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(headers[header::CONTENT_TYPE], "text/plain");
+        assert!(body.is_empty());
+
+        for truncated in [
+            &b""[..],
+            b"Status: 200 OK\r\nContent-Ty",
+            b"Status: 200 OK\r\nContent-Type: text/plain",
+            b"not a cgi response\n",
+        ] {
+            assert!(
+                parse_cgi_response(truncated.to_vec()).is_err(),
+                "{}",
+                String::from_utf8_lossy(truncated)
+            );
+        }
     }
-    haystack
-        .windows(needle.len())
-        .position(|window| window == needle)
 }
@@ -0,0 +1,182 @@
+use crate::AppState;
+use crate::config::Config;
+use crate::error::BridgeError;
+use crate::project_id::ProjectId;
+use crate::project_settings::{ProjectSettingsFile, load_project_settings};
+use crate::repo::{is_external_repo, list_mirrors};
+use std::fs;
+use std::io::Write;
+use std::sync::Arc;
+use std::time::Duration;
+use tempfile::NamedTempFile;
+use tracing::{debug, error, warn};
+
+/// How often the whole export is rewritten, catching mirrors created or
+/// removed outside a sync (restores, integrity resets, manual changes).
+pub(crate) const EXPORT_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+/// Text of the `description` file of a mirror: its alias, or its id.
+fn description_text(project_id: &ProjectId, settings: &ProjectSettingsFile) -> String {
+    let name = settings
+        .get(project_id)
+        .alias
+        .unwrap_or_else(|| format!("ShareLatex project {project_id}"));
+    format!("{name}\n")
+}
+
+/// Write the `description` file of a mirror if it changed. External repos
+/// keep their own. Blocking.
+pub(crate) fn write_description(
+    cfg: &Config,
+    project_id: &ProjectId,
+    settings: &ProjectSettingsFile,
+) -> Result<(), BridgeError> {
+    let repo = cfg.bare_repo_dir(project_id)?;
+    if !repo.is_dir() || is_external_repo(cfg, project_id) {
+        return Ok(());
+    }
+    let path = repo.join("description");
+    let text = description_text(project_id, settings);
+    if fs::read_to_string(&path).is_ok_and(|current| current == text) {
+        return Ok(());
+    }
+    fs::write(&path, text)?;
+    Ok(())
+}
+
+/// Rewrite `projects.list` with every mirror, one `<id>.git` per line,
+/// relative to [`Config::repos_dir`]. Blocking.
+pub(crate) fn write_projects_list(cfg: &Config) -> Result<(), BridgeError> {
+    let mut mirrors = list_mirrors(cfg)?;
+    mirrors.sort();
+    let list: String = mirrors.iter().map(|id| format!("{id}.git\n")).collect();
+    // concurrent syncs may rewrite the list at the same time, so each
+    // writes its own temp file
+    fs::create_dir_all(cfg.state_dir())?;
+    let mut tmp = NamedTempFile::new_in(cfg.state_dir())?;
+    tmp.write_all(list.as_bytes())?;
+    tmp.persist(cfg.gitweb_projects_list())
+        .map_err(|e| BridgeError::Io(e.error))?;
+    Ok(())
+}
+
+/// Update the export after the mirror of `project_id` was created, removed,
+/// or renamed, if `EXPORT_GITWEB_METADATA` is on. Failures are only logged.
+/// Callers must hold the project lock. Blocking.
+pub(crate) fn mirror_changed(cfg: &Config, project_id: &ProjectId) {
+    if !cfg.export_gitweb_metadata {
+        return;
+    }
+    let exported = load_project_settings(cfg)
+        .and_then(|settings| write_description(cfg, project_id, &settings))
+        .and_then(|()| write_projects_list(cfg));
+    if let Err(e) = exported {
+        warn!(%project_id, "cannot update gitweb export: {e}");
+    }
+}
+
+/// Rewrite the whole export. Mirrors that are busy keep their description
+/// until the next run.
+async fn export_all(state: &AppState) -> Result<(), BridgeError> {
+    let cfg = state.cfg.clone();
+    let (mirrors, settings) = tokio::task::spawn_blocking(move || {
+        Ok::<_, BridgeError>((list_mirrors(&cfg)?, load_project_settings(&cfg)?))
+    })
+    .await
+    .map_err(|e| BridgeError::Other(format!("join error: {e}")))??;
+
+    let settings = Arc::new(settings);
+    for project_id in mirrors {
+        let Some(guard) = state.locks.try_lock(&project_id, "gitweb export") else {
+            debug!(%project_id, "skipping description of busy mirror");
+            continue;
+        };
+        let cfg = state.cfg.clone();
+        let settings = settings.clone();
+        let written = tokio::task::spawn_blocking(move || {
+            let _guard = guard;
+            write_description(&cfg, &project_id, &settings)
+                .map_err(|e| format!("{project_id}: {e}"))
+        })
+        .await
+        .map_err(|e| BridgeError::Other(format!("join error: {e}")))?;
+        if let Err(e) = written {
+            warn!("cannot write gitweb description: {e}");
+        }
+    }
+
+    let cfg = state.cfg.clone();
+    tokio::task::spawn_blocking(move || write_projects_list(&cfg))
+        .await
+        .map_err(|e| BridgeError::Other(format!("join error: {e}")))?
+}
+
+/// Background task rewriting the export at startup and every
+/// [`EXPORT_INTERVAL`].
+pub(crate) async fn run_periodically(state: Arc<AppState>) {
+    loop {
+        if !state.maintenance.is_enabled() {
+            let _in_flight = state.maintenance.begin();
+            if let Err(e) = export_all(&state).await {
+                error!("gitweb export failed: {e}");
+            }
+        }
+        tokio::time::sleep(EXPORT_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::project_id::pid;
+    use crate::repo::EXTERNAL_MARKER;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn export_lists_mirrors_and_names_them_by_alias() {
+        let root = TempDir::new().unwrap();
+        let cfg = Config::for_test(root.path());
+        for id in ["bbb", "aaa", "style"] {
+            fs::create_dir_all(cfg.bare_repo_dir(&pid(id)).unwrap()).unwrap();
+        }
+        fs::write(
+            cfg.bare_repo_dir(&pid("style"))
+                .unwrap()
+                .join(EXTERNAL_MARKER),
+            "",
+        )
+        .unwrap();
+        fs::write(
+            cfg.bare_repo_dir(&pid("style"))
+                .unwrap()
+                .join("description"),
+            "Style files\n",
+        )
+        .unwrap();
+        let mut settings = ProjectSettingsFile::default();
+        settings.update("bbb", |s| s.alias = Some("Lab Report".into()));
+        crate::project_settings::save_project_settings(&cfg, &settings).unwrap();
+
+        let state = AppState::new(cfg.clone());
+        let _busy = state.locks.lock("aaa", "test").await;
+        export_all(&state).await.unwrap();
+
+        let list = fs::read_to_string(cfg.gitweb_projects_list()).unwrap();
+        assert_eq!(list, "aaa.git\nbbb.git\nstyle.git\n");
+        let description =
+            |id: &str| fs::read_to_string(cfg.bare_repo_dir(&pid(id)).unwrap().join("description"));
+        assert_eq!(description("bbb").unwrap(), "Lab Report\n");
+        assert_eq!(description("style").unwrap(), "Style files\n");
+        assert!(description("aaa").is_err());
+
+        fs::remove_dir_all(cfg.bare_repo_dir(&pid("bbb")).unwrap()).unwrap();
+        mirror_changed(&cfg, &pid("aaa"));
+        assert!(description("aaa").is_err());
+        let mut enabled = cfg.clone();
+        enabled.export_gitweb_metadata = true;
+        mirror_changed(&enabled, &pid("aaa"));
+        assert_eq!(description("aaa").unwrap(), "ShareLatex project aaa\n");
+        let list = fs::read_to_string(cfg.gitweb_projects_list()).unwrap();
+        assert_eq!(list, "aaa.git\nstyle.git\n");
+    }
+}
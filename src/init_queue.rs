@@ -0,0 +1,180 @@
+//! First-time mirror creation for git requests. Creating a mirror copies a
+//! whole project, so at most `INIT_CONCURRENCY` run at once; projects
+//! waiting for a slot are prepared in the background while their requests
+//! are asked to retry.
+
+use crate::project_id::ProjectId;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Where a project is in the queue.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum InitStatus {
+    /// Waiting for a slot
+    Queued,
+    /// Its mirror is being created
+    Running,
+}
+
+struct Entry {
+    status: InitStatus,
+    since: Instant,
+}
+
+/// Projects whose mirrors are being created, and the slots to create them.
+pub(crate) struct InitQueue {
+    slots: Arc<Semaphore>,
+    projects: Mutex<BTreeMap<ProjectId, Entry>>,
+}
+
+/// A project's place in the [`InitQueue`]; it leaves the queue when dropped.
+pub(crate) struct InitClaim {
+    queue: Arc<InitQueue>,
+    project_id: ProjectId,
+    permit: Option<OwnedSemaphorePermit>,
+}
+
+/// One project in the queue, for the admin API.
+#[cfg(feature = "admin-ui")]
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct InitProgress {
+    pub(crate) project_id: ProjectId,
+    pub(crate) status: InitStatus,
+    /// Seconds since the project entered its current status
+    pub(crate) seconds: u64,
+}
+
+impl InitQueue {
+    pub(crate) fn new(concurrency: usize) -> Self {
+        Self {
+            slots: Arc::new(Semaphore::new(concurrency.max(1))),
+            projects: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BTreeMap<ProjectId, Entry>> {
+        self.projects.lock().expect("init queue poisoned")
+    }
+
+    /// Queue `project_id`; `None` if it is already queued or running.
+    pub(crate) fn claim(self: &Arc<Self>, project_id: &ProjectId) -> Option<InitClaim> {
+        let mut projects = self.lock();
+        if projects.contains_key(project_id) {
+            return None;
+        }
+        projects.insert(
+            project_id.clone(),
+            Entry {
+                status: InitStatus::Queued,
+                since: Instant::now(),
+            },
+        );
+        Some(InitClaim {
+            queue: self.clone(),
+            project_id: project_id.clone(),
+            permit: None,
+        })
+    }
+
+    /// Where `project_id` is, if it is in the queue.
+    pub(crate) fn status(&self, project_id: &ProjectId) -> Option<InitStatus> {
+        self.lock().get(project_id).map(|entry| entry.status)
+    }
+
+    /// Every project in the queue, running ones first.
+    #[cfg(feature = "admin-ui")]
+    pub(crate) fn progress(&self) -> Vec<InitProgress> {
+        let mut progress: Vec<InitProgress> = self
+            .lock()
+            .iter()
+            .map(|(project_id, entry)| InitProgress {
+                project_id: project_id.clone(),
+                status: entry.status,
+                seconds: entry.since.elapsed().as_secs(),
+            })
+            .collect();
+        progress.sort_by_key(|p| (p.status == InitStatus::Queued, std::cmp::Reverse(p.seconds)));
+        progress
+    }
+
+    fn set_running(&self, project_id: &ProjectId) {
+        if let Some(entry) = self.lock().get_mut(project_id) {
+            entry.status = InitStatus::Running;
+            entry.since = Instant::now();
+        }
+    }
+}
+
+impl InitClaim {
+    /// Take a slot if one is free right now.
+    pub(crate) fn try_start(&mut self) -> bool {
+        match self.queue.slots.clone().try_acquire_owned() {
+            Ok(permit) => {
+                self.permit = Some(permit);
+                self.queue.set_running(&self.project_id);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Wait for a slot.
+    pub(crate) async fn start(&mut self) {
+        let permit = self
+            .queue
+            .slots
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("init slots are never closed");
+        self.permit = Some(permit);
+        self.queue.set_running(&self.project_id);
+    }
+}
+
+impl Drop for InitClaim {
+    fn drop(&mut self) {
+        self.queue.lock().remove(&self.project_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::project_id::pid;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn projects_wait_for_a_free_slot() {
+        let queue = Arc::new(InitQueue::new(1));
+        let mut first = queue.claim(&pid("p1")).unwrap();
+        assert!(queue.claim(&pid("p1")).is_none());
+        assert!(first.try_start());
+        assert_eq!(queue.status(&pid("p1")), Some(InitStatus::Running));
+
+        let mut second = queue.claim(&pid("p2")).unwrap();
+        assert!(!second.try_start());
+        assert_eq!(queue.status(&pid("p2")), Some(InitStatus::Queued));
+
+        let waiting = tokio::spawn(async move {
+            second.start().await;
+            second
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiting.is_finished());
+        drop(first);
+        assert_eq!(queue.status(&pid("p1")), None);
+
+        let second = tokio::time::timeout(Duration::from_secs(5), waiting)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(queue.status(&pid("p2")), Some(InitStatus::Running));
+        drop(second);
+        assert!(queue.claim(&pid("p2")).is_some());
+    }
+}
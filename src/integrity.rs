@@ -0,0 +1,257 @@
+use crate::AppState;
+use crate::config::Config;
+use crate::error::BridgeError;
+use crate::git::GitRunner;
+use crate::metadata::{IntegrityCheck, SyncMetadata, load_metadata, update_metadata};
+use crate::project_id::ProjectId;
+use crate::repo::{ensure_repo_blocking, is_external_repo, list_mirrors};
+use crate::source::ProjectSource;
+use chrono::Utc;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tracing::{debug, error, info, warn};
+
+/// Longest git error output kept in an [`IntegrityCheck`].
+const MAX_ERROR_CHARS: usize = 2000;
+
+/// Counts of one pass over all mirrors.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct IntegrityRun {
+    pub(crate) checked: usize,
+    pub(crate) failed: usize,
+    /// Mirrors skipped because a sync held their lock
+    pub(crate) busy: usize,
+}
+
+/// Run `git fsck` and verify the read-only branch of one mirror. External
+/// repos may use other branches, so only `fsck` applies to them. Blocking.
+pub(crate) fn check_repo(
+    git: &dyn GitRunner,
+    cfg: &Config,
+    project_id: &ProjectId,
+) -> IntegrityCheck {
+    let failed = |error: String| IntegrityCheck {
+        at: Utc::now(),
+        ok: false,
+        error: Some(error),
+        reset: false,
+    };
+    let repo = match cfg.bare_repo_dir(project_id) {
+        Ok(repo) => repo,
+        Err(e) => return failed(e.to_string()),
+    };
+    let checks: [&[&str]; 2] = [
+        &["fsck", "--no-progress"],
+        &["rev-parse", "--verify", &cfg.readonly_branch],
+    ];
+    let checks = if is_external_repo(cfg, project_id) {
+        &checks[..1]
+    } else {
+        &checks[..]
+    };
+    let error = checks.iter().find_map(|args| match git.run(args, &repo) {
+        Ok(out) if out.status.success() => None,
+        Ok(out) => {
+            let stderr = String::from_utf8_lossy(&out.stderr);
+            let stderr: String = stderr.trim().chars().take(MAX_ERROR_CHARS).collect();
+            Some(format!("git {} failed: {stderr}", args.join(" ")))
+        }
+        Err(e) => Some(e.to_string()),
+    });
+    match error {
+        Some(error) => failed(error),
+        None => IntegrityCheck {
+            at: Utc::now(),
+            ok: true,
+            error: None,
+            reset: false,
+        },
+    }
+}
+
+/// Replace a mirror with one built from a fresh snapshot, keeping its sync
+/// metadata. The broken mirror goes to the trash. Callers must hold the project lock. Blocking.
+fn reset_mirror(
+    git: &dyn GitRunner,
+    source: &dyn ProjectSource,
+    cfg: &Config,
+    project_id: &ProjectId,
+) -> Result<(), BridgeError> {
+    let previous = load_metadata(cfg, project_id);
+    if let Some(entry) = crate::trash::remove_mirror(cfg, project_id, false)? {
+        info!(%project_id, %entry, "moved broken mirror to trash");
+    }
+    let outcome = ensure_repo_blocking(git, source, cfg, project_id)?;
+    update_metadata(cfg, project_id, |meta| {
+        *meta = SyncMetadata {
+            source_fingerprint: outcome.source_fingerprint,
+            ..previous
+        }
+    })
+}
+
+/// Check one mirror, reset it if configured, and record the result.
+/// Returns whether the check passed. Callers must hold the project lock. Blocking.
+fn check_and_record(
+    git: &dyn GitRunner,
+    source: &dyn ProjectSource,
+    cfg: &Config,
+    project_id: &ProjectId,
+) -> bool {
+    let mut check = check_repo(git, cfg, project_id);
+    if !check.ok {
+        warn!(%project_id, error = ?check.error, "integrity check failed");
+        if cfg.fsck_auto_reset && !is_external_repo(cfg, project_id) {
+            match reset_mirror(git, source, cfg, project_id) {
+                Ok(()) => {
+                    info!(%project_id, "mirror rebuilt from a fresh snapshot");
+                    check.reset = true;
+                }
+                Err(e) => error!(%project_id, "cannot reset mirror: {e}"),
+            }
+        }
+    }
+    let ok = check.ok;
+    if let Err(e) = update_metadata(cfg, project_id, |meta| meta.integrity = Some(check)) {
+        warn!(%project_id, error = %e, "cannot record integrity check");
+    }
+    ok
+}
+
+/// Check every mirror, at most `FSCK_CONCURRENCY` at a time. Mirrors that are
+/// being synced are skipped rather than waited for.
+pub(crate) async fn run_integrity_checks(
+    state: &AppState,
+    git: Arc<dyn GitRunner>,
+) -> Result<IntegrityRun, BridgeError> {
+    let cfg = state.cfg.clone();
+    let mirrors = tokio::task::spawn_blocking(move || list_mirrors(&cfg))
+        .await
+        .map_err(|e| BridgeError::Other(format!("join error: {e}")))??;
+
+    let semaphore = Arc::new(Semaphore::new(state.cfg.fsck_concurrency));
+    let mut run = IntegrityRun::default();
+    let mut tasks = JoinSet::new();
+    for project_id in mirrors {
+        let permit = semaphore
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("semaphore is never closed");
+        let Some(guard) = state.locks.try_lock(&project_id, "integrity check") else {
+            debug!(%project_id, "skipping integrity check of busy mirror");
+            run.busy += 1;
+            continue;
+        };
+        let git = git.clone();
+        let source = state.source.clone();
+        let cfg = state.cfg.clone();
+        tasks.spawn_blocking(move || {
+            let _permit = permit;
+            let _guard = guard;
+            check_and_record(git.as_ref(), source.as_ref(), &cfg, &project_id)
+        });
+    }
+    while let Some(result) = tasks.join_next().await {
+        match result {
+            Ok(ok) => {
+                run.checked += 1;
+                if !ok {
+                    run.failed += 1;
+                }
+            }
+            Err(e) => error!("integrity check task failed: {e}"),
+        }
+    }
+    Ok(run)
+}
+
+/// Background task checking all mirrors every `interval`.
+pub(crate) async fn run_periodically(
+    state: Arc<AppState>,
+    git: Arc<dyn GitRunner>,
+    interval: Duration,
+) {
+    loop {
+        tokio::time::sleep(interval).await;
+        if state.maintenance.is_enabled() {
+            info!("skipping scheduled integrity checks in maintenance mode");
+            continue;
+        }
+        let _in_flight = state.maintenance.begin();
+        match run_integrity_checks(&state, git.clone()).await {
+            Ok(run) => info!(
+                checked = run.checked,
+                failed = run.failed,
+                busy = run.busy,
+                "integrity checks finished"
+            ),
+            Err(e) => error!("integrity checks failed to run: {e}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git::mock::ScriptedGit;
+    use crate::project_id::pid;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn reports_the_first_failing_check() {
+        let root = TempDir::new().unwrap();
+        let cfg = Config::for_test(root.path());
+
+        let check = check_repo(&ScriptedGit::new(), &cfg, &pid("p1"));
+        assert!(check.ok && check.error.is_none());
+
+        let git = ScriptedGit::new().on(
+            &["fsck", "--no-progress"],
+            2,
+            "error: object file .git/objects/ab/cd is empty\n",
+        );
+        let check = check_repo(&git, &cfg, &pid("p1"));
+        assert!(!check.ok);
+        assert_eq!(
+            check.error.as_deref(),
+            Some("git fsck --no-progress failed: error: object file .git/objects/ab/cd is empty")
+        );
+        assert_eq!(git.calls().len(), 1);
+
+        let git = ScriptedGit::new().on(
+            &["rev-parse", "--verify", "master"],
+            128,
+            "fatal: Needed a single revision",
+        );
+        assert!(!check_repo(&git, &cfg, &pid("p1")).ok);
+    }
+
+    #[tokio::test]
+    async fn busy_mirrors_are_skipped_and_results_recorded() {
+        let root = TempDir::new().unwrap();
+        let cfg = Config::for_test(root.path());
+        fs::create_dir_all(cfg.bare_repo_dir(&pid("idle")).unwrap()).unwrap();
+        fs::create_dir_all(cfg.bare_repo_dir(&pid("busy")).unwrap()).unwrap();
+        let state = AppState::new(cfg.clone());
+        let git = Arc::new(ScriptedGit::new().on(&["fsck", "--no-progress"], 1, "broken"));
+
+        let _held = state.locks.lock("busy", "test").await;
+        let run = run_integrity_checks(&state, git.clone()).await.unwrap();
+        assert_eq!(
+            run,
+            IntegrityRun {
+                checked: 1,
+                failed: 1,
+                busy: 1
+            }
+        );
+
+        let idle = load_metadata(&cfg, &pid("idle")).integrity.unwrap();
+        assert!(!idle.ok && !idle.reset);
+        assert!(load_metadata(&cfg, &pid("busy")).integrity.is_none());
+    }
+}
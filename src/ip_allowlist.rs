@@ -0,0 +1,211 @@
+//! Source address checks for the git endpoint (`GIT_ALLOWED_CIDRS`). The
+//! client address is the connection's peer, or, when the peer is one of
+//! `TRUSTED_PROXIES`, the last address in `X-Forwarded-For` that is not a
+//! trusted proxy itself.
+
+use axum::http::HeaderMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// An address range such as `10.0.0.0/8` or `2001:db8::/32`; a bare address
+/// is a range of one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("invalid CIDR range '{0}'")]
+pub struct InvalidCidr(String);
+
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, canonical(ip)) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                prefix_eq(&net.octets(), &ip.octets(), self.prefix)
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                prefix_eq(&net.octets(), &ip.octets(), self.prefix)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = InvalidCidr;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidCidr(s.to_string());
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr = canonical(addr.parse::<IpAddr>().map_err(|_| invalid())?);
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) if prefix.bytes().all(|b| b.is_ascii_digit()) => {
+                prefix.parse::<u8>().map_err(|_| invalid())?
+            }
+            Some(_) => return Err(invalid()),
+            None => max,
+        };
+        if prefix > max {
+            return Err(invalid());
+        }
+        Ok(Self { addr, prefix })
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+/// IPv4 clients of a dual-stack socket show up as `::ffff:a.b.c.d`; treat
+/// them as the IPv4 address.
+fn canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(IpAddr::V6(v6), IpAddr::V4),
+        v4 => v4,
+    }
+}
+
+fn prefix_eq(a: &[u8], b: &[u8], prefix: u8) -> bool {
+    let bytes = usize::from(prefix / 8);
+    let bits = prefix % 8;
+    if a[..bytes] != b[..bytes] {
+        return false;
+    }
+    bits == 0 || (a[bytes] ^ b[bytes]) >> (8 - bits) == 0
+}
+
+fn any_contains(ranges: &[Cidr], ip: IpAddr) -> bool {
+    ranges.iter().any(|range| range.contains(ip))
+}
+
+/// The address of the client behind `peer`. `X-Forwarded-For` is read right
+/// to left, as each proxy appends the address it got the request from, and
+/// only while the hop so far is a trusted proxy; a malformed entry ends the
+/// walk at the last trusted hop.
+pub(crate) fn client_ip(peer: IpAddr, headers: &HeaderMap, trusted_proxies: &[Cidr]) -> IpAddr {
+    let mut client = canonical(peer);
+    if !any_contains(trusted_proxies, client) {
+        return client;
+    }
+    let forwarded: Vec<&str> = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .collect();
+    for hop in forwarded.into_iter().rev() {
+        let Ok(ip) = hop.parse::<IpAddr>() else {
+            break;
+        };
+        client = canonical(ip);
+        if !any_contains(trusted_proxies, client) {
+            break;
+        }
+    }
+    client
+}
+
+/// Whether `ip` may use the git endpoint; without an allowlist every
+/// address may.
+pub(crate) fn is_allowed(allowed: Option<&[Cidr]>, ip: IpAddr) -> bool {
+    allowed.is_none_or(|allowed| any_contains(allowed, ip))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn cidrs(list: &[&str]) -> Vec<Cidr> {
+        list.iter().map(|c| c.parse().unwrap()).collect()
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn ranges_are_parsed_and_matched() {
+        let ranges = cidrs(&[
+            "10.0.0.0/8",
+            "192.168.1.7",
+            "2001:db8::/32",
+            "172.16.0.0/12",
+        ]);
+        for allowed in [
+            "10.1.2.3",
+            "192.168.1.7",
+            "::ffff:10.9.9.9",
+            "2001:db8:1::1",
+            "172.31.255.255",
+        ] {
+            assert!(is_allowed(Some(&ranges), ip(allowed)), "{allowed}");
+        }
+        for denied in ["11.0.0.1", "192.168.1.8", "2001:db9::1", "172.32.0.0", "::"] {
+            assert!(!is_allowed(Some(&ranges), ip(denied)), "{denied}");
+        }
+        assert!(is_allowed(None, ip("8.8.8.8")));
+        assert!(!is_allowed(Some(&[]), ip("8.8.8.8")));
+        assert!(cidrs(&["0.0.0.0/0"])[0].contains(ip("8.8.8.8")));
+        assert_eq!(cidrs(&["10.0.0.0/8"])[0].to_string(), "10.0.0.0/8");
+
+        for bad in [
+            "10.0.0.0/33",
+            "10.0.0.0/",
+            "10.0.0/8",
+            "::/129",
+            "10.0.0.0/+8",
+            "host",
+        ] {
+            assert!(bad.parse::<Cidr>().is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn forwarded_addresses_count_only_behind_trusted_proxies() {
+        let trusted = cidrs(&["10.0.0.1", "10.0.0.2"]);
+        let mut headers = HeaderMap::new();
+        headers.append(
+            "x-forwarded-for",
+            HeaderValue::from_static("6.6.6.6, 203.0.113.5"),
+        );
+        headers.append("x-forwarded-for", HeaderValue::from_static("10.0.0.2"));
+
+        // a spoofed first entry is ignored, the trusted chain is skipped
+        assert_eq!(
+            client_ip(ip("10.0.0.1"), &headers, &trusted),
+            ip("203.0.113.5")
+        );
+        // an untrusted peer is the client, whatever it claims
+        assert_eq!(
+            client_ip(ip("198.51.100.1"), &headers, &trusted),
+            ip("198.51.100.1")
+        );
+        assert_eq!(client_ip(ip("10.0.0.1"), &headers, &[]), ip("10.0.0.1"));
+        assert_eq!(
+            client_ip(ip("10.0.0.1"), &HeaderMap::new(), &trusted),
+            ip("10.0.0.1")
+        );
+
+        let mut garbled = HeaderMap::new();
+        garbled.insert(
+            "x-forwarded-for",
+            HeaderValue::from_static("203.0.113.5, junk"),
+        );
+        assert_eq!(
+            client_ip(ip("10.0.0.1"), &garbled, &trusted),
+            ip("10.0.0.1")
+        );
+    }
+}
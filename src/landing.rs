@@ -0,0 +1,124 @@
+use crate::config::Config;
+use crate::metadata::SyncMetadata;
+use axum::body::Body;
+use axum::http::{Method, Request, Response, StatusCode, header};
+
+const LANDING_HTML: &str = include_str!("../templates/project_landing.html");
+
+/// A browser opening `/git/<id>.git` itself, as opposed to a git client,
+/// which always asks for a path below it or a `service`.
+pub(crate) fn is_browser_request(req: &Request<Body>, remaining: &str) -> bool {
+    let accepts_html = req
+        .headers()
+        .get(header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.contains("text/html"));
+    let has_service = req
+        .uri()
+        .query()
+        .is_some_and(|q| url::form_urlencoded::parse(q.as_bytes()).any(|(k, _)| k == "service"));
+
+    matches!(*req.method(), Method::GET | Method::HEAD)
+        && remaining.trim_matches('/').is_empty()
+        && accepts_html
+        && !has_service
+}
+
+/// Render the landing page. `meta` is only passed for authorized requests;
+/// anonymous visitors get the same page for every project id, so it does not
+/// tell them whether a project exists.
+pub(crate) fn landing_page(
+    cfg: &Config,
+    project_id: &str,
+    meta: Option<&SyncMetadata>,
+) -> Response<Body> {
+    let last_sync = match meta {
+        None => "shown when opened with a valid token".to_string(),
+        Some(meta) => match (meta.last_success_at, &meta.last_error) {
+            (Some(at), None) => at.format("%Y-%m-%d %H:%M UTC").to_string(),
+            (Some(at), Some(_)) => format!(
+                "{} (the latest attempt failed)",
+                at.format("%Y-%m-%d %H:%M UTC")
+            ),
+            (None, _) => "never".to_string(),
+        },
+    };
+
+    let html = LANDING_HTML
+        .replace("{{CLONE_URL}}", &escape_html(&cfg.clone_url(project_id)))
+        .replace("{{PROJECT_ID}}", &escape_html(project_id))
+        .replace("{{LAST_SYNC}}", &escape_html(&last_sync));
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+        .header(header::CACHE_CONTROL, "no-store")
+        .body(Body::from(html))
+        .expect("landing page response")
+}
+
+fn escape_html(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::to_bytes;
+
+    fn request(uri: &str, accept: &str) -> Request<Body> {
+        Request::builder()
+            .uri(uri)
+            .header(header::ACCEPT, accept)
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[test]
+    fn only_plain_browser_visits_get_the_page() {
+        let html = "text/html,application/xhtml+xml,*/*;q=0.8";
+        assert!(is_browser_request(&request("/git/p.git", html), ""));
+        assert!(is_browser_request(&request("/git/p.git/", html), "/"));
+        assert!(!is_browser_request(
+            &request("/git/p.git/info/refs", html),
+            "info/refs"
+        ));
+        assert!(!is_browser_request(
+            &request("/git/p.git?service=git-upload-pack", html),
+            ""
+        ));
+        assert!(!is_browser_request(&request("/git/p.git", "*/*"), ""));
+    }
+
+    #[tokio::test]
+    async fn page_escapes_the_project_id_and_hides_status_when_anonymous() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let mut cfg = Config::for_test(dir.path());
+        cfg.base_path = "/bridge".to_string();
+
+        let resp = landing_page(&cfg, "<script>", None);
+        let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains("&lt;script&gt;"));
+        assert!(!body.contains("Project <script>"));
+        assert!(body.contains(r#"data-clone-url="/bridge/git/&lt;script&gt;.git""#));
+        assert!(body.contains("shown when opened with a valid token"));
+
+        cfg.public_url = Some("https://tex.example.org/bridge".to_string());
+        let resp = landing_page(&cfg, "p1", None);
+        let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains(r#"data-clone-url="https://tex.example.org/bridge/git/p1.git""#));
+    }
+}
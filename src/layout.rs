@@ -0,0 +1,133 @@
+//! Migration of `GIT_ROOT` to the split layout (`GIT_ROOT_LAYOUT=split`):
+//! mirrors in `repos/`, the bridge's own files in `state/`. Earlier versions
+//! kept both side by side in `GIT_ROOT`, where a mirror could end up next to
+//! (or named like) `tokens.json`.
+
+use crate::config::{Config, GitRootLayout};
+use crate::error::BridgeError;
+use crate::project_id::ProjectId;
+use std::fs;
+use std::path::PathBuf;
+use tracing::{info, warn};
+
+/// Files and directories of the flat layout that belong in `state/`.
+/// `shared-objects` stays where it is: mirrors name it in their alternates.
+const STATE_ENTRIES: &[&str] = &[
+    ".locks",
+    ".tokens.lock",
+    "access.jsonl",
+    ".trash",
+    "activity.jsonl",
+    "admins.json",
+    "audit.jsonl",
+    "deploy-tokens.json",
+    "maintenance.json",
+    "project-settings.json",
+    "projects.list",
+    "token-usage.json",
+    "tokens.json",
+];
+
+/// Where an entry of a flat `git_root` belongs in the split layout; `None`
+/// for anything that is not the bridge's (source directories, temp files,
+/// `shared-objects`).
+fn split_target(cfg: &Config, name: &str, is_dir: bool) -> Option<PathBuf> {
+    if STATE_ENTRIES.contains(&name) {
+        return Some(cfg.state_dir().join(name));
+    }
+    let id = name.strip_suffix(".git")?;
+    (is_dir && ProjectId::new(id).is_ok()).then(|| cfg.repos_dir().join(name))
+}
+
+/// Create `repos/` and `state/` and move the mirrors and files of a flat
+/// `git_root` into them. Entries that already exist at their new place are
+/// left alone and reported. Does nothing in the flat layout. Must run before
+/// anything else uses `git_root`; returns the number of entries moved.
+pub(crate) fn migrate_to_split(cfg: &Config) -> Result<usize, BridgeError> {
+    if cfg.git_root_layout != GitRootLayout::Split {
+        return Ok(0);
+    }
+    fs::create_dir_all(cfg.repos_dir())?;
+    fs::create_dir_all(cfg.state_dir())?;
+
+    let mut moved = 0;
+    for entry in fs::read_dir(&cfg.git_root)? {
+        let entry = entry?;
+        let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+            continue;
+        };
+        let is_dir = entry.file_type()?.is_dir();
+        let Some(target) = split_target(cfg, &name, is_dir) else {
+            continue;
+        };
+        if target.exists() {
+            warn!(
+                from = %entry.path().display(),
+                to = %target.display(),
+                "cannot move to the split GIT_ROOT layout, the target exists; left in place"
+            );
+            continue;
+        }
+        fs::rename(entry.path(), &target)?;
+        moved += 1;
+    }
+    if moved > 0 {
+        info!(
+            git_root = %cfg.git_root.display(),
+            moved, "moved mirrors and state files to the split GIT_ROOT layout"
+        );
+    }
+    Ok(moved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::project_id::pid;
+    use tempfile::TempDir;
+
+    #[test]
+    fn flat_git_roots_are_split_once() {
+        let root = TempDir::new().unwrap();
+        let cfg = Config::for_test(root.path());
+        let git_root = &cfg.git_root;
+        for dir in ["p1.git", "p2.git/objects", ".trash/p3.git-1", "prod/p4.git"] {
+            fs::create_dir_all(git_root.join(dir)).unwrap();
+        }
+        for file in ["tokens.json", "activity.jsonl", "notes.git", "tokens.tmp"] {
+            fs::write(git_root.join(file), file).unwrap();
+        }
+        fs::create_dir_all(git_root.join("shared-objects")).unwrap();
+
+        assert_eq!(migrate_to_split(&cfg).unwrap(), 5);
+        assert!(cfg.bare_repo_dir(&pid("p1")).unwrap().is_dir());
+        assert!(cfg.repos_dir().join("p2.git/objects").is_dir());
+        assert!(cfg.trash_dir().join("p3.git-1").is_dir());
+        assert_eq!(
+            fs::read_to_string(cfg.tokens_file()).unwrap(),
+            "tokens.json"
+        );
+        assert!(cfg.activity_log_file().is_file());
+        // sources, stray files and the shared store stay
+        for kept in ["prod/p4.git", "notes.git", "tokens.tmp", "shared-objects"] {
+            assert!(git_root.join(kept).exists(), "{kept}");
+        }
+
+        // a second start finds nothing to move, a clash is left alone
+        assert_eq!(migrate_to_split(&cfg).unwrap(), 0);
+        fs::write(git_root.join("tokens.json"), "stale").unwrap();
+        assert_eq!(migrate_to_split(&cfg).unwrap(), 0);
+        assert_eq!(
+            fs::read_to_string(cfg.tokens_file()).unwrap(),
+            "tokens.json"
+        );
+
+        let mut flat = cfg.clone();
+        flat.git_root_layout = GitRootLayout::Flat;
+        assert_eq!(migrate_to_split(&flat).unwrap(), 0);
+        assert_eq!(
+            flat.bare_repo_dir(&pid("p1")).unwrap(),
+            git_root.join("p1.git")
+        );
+    }
+}
@@ -0,0 +1,718 @@
+//! Read-only Git Smart HTTP bridge for ShareLatex / Overleaf projects.
+//!
+//! Every ShareLatex project directory is mirrored into a bare git repository
+//! under [`Config::git_root`] and exposed at `/git/<projectId>.git` via
+//! `git http-backend`. Pushes are always refused.
+//!
+//! The binary is a thin wrapper around this crate; the same pieces can be
+//! embedded into another service:
+//!
+//! ```no_run
+//! use std::sync::Arc;
+//! use sharelatex_gitbridge_ro::{AppState, Config, build_router, init_storage};
+//!
+//! # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! let cfg = Config::from_env();
+//! init_storage(&cfg)?;
+//!
+//! let state = Arc::new(AppState::new(cfg));
+//! sharelatex_gitbridge_ro::spawn_background_tasks(&state);
+//! let app = axum::Router::new().nest("/bridge", build_router(state));
+//!
+//! let listener = tokio::net::TcpListener::bind("127.0.0.1:8022").await?;
+//! axum::serve(listener, app).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+mod access_log;
+mod activity;
+#[cfg(feature = "admin-ui")]
+mod admin;
+mod alerts;
+mod archive;
+#[cfg(feature = "admin-ui")]
+mod assets;
+#[cfg(feature = "admin-ui")]
+mod audit;
+pub mod auth;
+mod backup;
+#[cfg(feature = "admin-ui")]
+mod browse;
+pub mod cli;
+mod coalesce;
+pub mod config;
+mod connection;
+mod deploy_tokens;
+#[cfg(feature = "docstore")]
+mod docstore;
+mod eol;
+pub mod error;
+mod git;
+mod git_daemon;
+mod git_http;
+mod gitweb;
+mod init_queue;
+mod integrity;
+pub mod ip_allowlist;
+mod landing;
+mod layout;
+mod locks;
+#[cfg(feature = "admin-ui")]
+mod login_throttle;
+mod maintenance;
+pub mod metadata;
+#[cfg(feature = "oidc")]
+mod oidc;
+pub mod project_id;
+pub mod project_settings;
+#[cfg(feature = "admin-ui")]
+mod projects;
+#[cfg(feature = "admin-ui")]
+mod prune;
+mod queues;
+mod readiness;
+pub mod repo;
+mod request_log;
+#[cfg(feature = "admin-ui")]
+mod restore;
+#[cfg(feature = "admin-ui")]
+mod secrets;
+mod server;
+mod shared_objects;
+mod shutdown;
+pub mod source;
+#[cfg(feature = "admin-ui")]
+mod stats;
+mod sync_events;
+mod timing;
+pub mod token_bootstrap;
+pub mod token_store;
+#[cfg(feature = "admin-ui")]
+mod totp;
+mod trash;
+mod usage;
+mod verify;
+mod warmup;
+mod webhook;
+mod webhook_queue;
+
+pub use crate::auth::{
+    TokenQuota, TokensFile, extract_token, extract_tokens, load_tokens_file, save_tokens_file,
+    token_allowed_for_project, token_hash,
+};
+pub use crate::config::Config;
+#[cfg(feature = "docstore")]
+pub use crate::docstore::DocstoreProjectSource;
+pub use crate::error::BridgeError;
+pub use crate::metadata::{SyncMetadata, load_metadata};
+pub use crate::repo::{SyncOutcome, ensure_repo};
+pub use crate::source::{FsProjectSource, ProjectSource};
+pub use crate::token_store::{
+    Decision, FileTokenStore, ImportMode, ImportSummary, MemoryTokenStore, TokenStore,
+};
+
+#[cfg(feature = "admin-ui")]
+use axum::routing::{delete, put};
+use axum::{
+    Router,
+    routing::{any, get, post},
+};
+use std::collections::BTreeMap;
+use std::fs;
+use std::sync::Arc;
+use std::time::Duration;
+#[cfg(feature = "admin-ui")]
+use std::{collections::HashMap, net::IpAddr, time::Instant};
+#[cfg(feature = "admin-ui")]
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+/// Shared application state
+#[derive(Clone)]
+pub struct AppState {
+    pub cfg: Config,
+    /// Per-project mutexes so we don't race syncs
+    pub(crate) locks: Arc<locks::ProjectLocks>,
+    /// Managed token storage (tokens.json by default)
+    pub tokens: Arc<dyn TokenStore>,
+    /// Where project snapshots are read from (the ShareLatex data dir by default)
+    pub source: Arc<dyn ProjectSource>,
+    /// Bounded log of sync commits across all projects
+    pub(crate) activity: Arc<activity::ActivityLog>,
+    /// Notifications about projects that keep failing to sync
+    pub(crate) alerts: Arc<alerts::Alerter>,
+    /// Outgoing webhook posts not yet accepted by their target
+    pub(crate) webhooks: Arc<webhook_queue::WebhookQueue>,
+    /// Progress and outcome of backups
+    pub(crate) backups: Arc<backup::BackupState>,
+    /// Progress of creating missing mirrors ahead of their first fetch
+    pub(crate) warmup: Arc<warmup::WarmupState>,
+    /// First-time mirror creations for git requests, `INIT_CONCURRENCY` at a time
+    pub(crate) initializations: Arc<init_queue::InitQueue>,
+    /// Daily git traffic per token, for quotas
+    pub(crate) usage: Arc<usage::UsageLog>,
+    /// Authenticated git requests of all sources (access.jsonl)
+    pub(crate) access_log: Arc<access_log::AccessLog>,
+    /// Expiring per-project tokens (deploy-tokens.json)
+    pub(crate) deploy_tokens: Arc<deploy_tokens::DeployTokenStore>,
+    /// When each project's last fetch was written to its metadata
+    pub(crate) fetches: Arc<metadata::FetchTracker>,
+    /// Client aborts and backend failures of git requests
+    pub(crate) transfers: Arc<connection::TransferStats>,
+    /// `info/refs` responses being computed, shared by identical requests
+    pub(crate) advertisements: Arc<coalesce::Coalescer>,
+    /// Outcomes of git request authorization by kind
+    pub(crate) auth_stats: Arc<auth::AuthStats>,
+    /// When each managed token's last use was written to the token store
+    pub(crate) token_uses: Arc<auth::TokenUseTracker>,
+    /// Time spent in each phase of git requests
+    pub(crate) timings: Arc<timing::TimingStats>,
+    /// Maintenance switch (maintenance.json) and the operations still running
+    pub(crate) maintenance: Arc<maintenance::Maintenance>,
+    /// Last outcome of the `/healthz` checks
+    pub(crate) readiness: Arc<readiness::Readiness>,
+    /// Set on SIGTERM or SIGINT; stops connections and new syncs
+    pub(crate) shutdown: Arc<shutdown::Shutdown>,
+    /// States serving the named sources (`SOURCES`), by name; empty in those
+    /// states themselves
+    pub(crate) sources: BTreeMap<String, Arc<AppState>>,
+    /// Active admin sessions with their account and expiry; keyed by hashed token
+    #[cfg(feature = "admin-ui")]
+    pub(crate) admin_sessions: Arc<Mutex<HashMap<String, admin::AdminSession>>>,
+    /// Recent failed admin login attempts by client, for throttling
+    #[cfg(feature = "admin-ui")]
+    pub(crate) admin_login_failures: Arc<Mutex<login_throttle::LoginThrottle>>,
+    /// Current admin password, reloaded from ADMIN_PASSWORD_FILE on change
+    #[cfg(feature = "admin-ui")]
+    pub(crate) admin_password: Arc<secrets::ReloadableSecret>,
+    /// OIDC login to the admin UI, if OIDC_ISSUER_URL is set
+    #[cfg(feature = "oidc")]
+    pub(crate) oidc: Option<Arc<oidc::Oidc>>,
+    /// Admin UI files, possibly overridden from ADMIN_ASSETS_DIR
+    #[cfg(feature = "admin-ui")]
+    pub(crate) admin_assets: Arc<assets::AssetOverrides>,
+    /// Serializes read-modify-write cycles on project-settings.json
+    #[cfg(feature = "admin-ui")]
+    pub(crate) settings_lock: Arc<Mutex<()>>,
+    /// Recently computed per-project storage statistics
+    #[cfg(feature = "admin-ui")]
+    pub(crate) stats_cache: Arc<stats::StatsCache>,
+    /// Log of destructive admin actions
+    #[cfg(feature = "admin-ui")]
+    pub(crate) audit: Arc<audit::AuditLog>,
+}
+
+impl AppState {
+    /// State backed by the `tokens.json` file under `git_root`.
+    pub fn new(cfg: Config) -> Self {
+        let tokens = Arc::new(FileTokenStore::new(cfg.clone()));
+        Self::with_token_store(cfg, tokens)
+    }
+
+    /// State using a custom token store.
+    pub fn with_token_store(cfg: Config, tokens: Arc<dyn TokenStore>) -> Self {
+        let source = source::from_config(&cfg);
+        let locks = Arc::new(locks::ProjectLocks::with_lock_dir(cfg.lock_dir()));
+        let activity = Arc::new(activity::ActivityLog::new(&cfg));
+        let webhooks = Arc::new(webhook_queue::WebhookQueue::new(&cfg));
+        let alerts = Arc::new(alerts::Alerter::new(&cfg, &webhooks));
+        let usage = Arc::new(usage::UsageLog::new(&cfg));
+        let access_log = Arc::new(access_log::AccessLog::new(&cfg));
+        let deploy_tokens = Arc::new(deploy_tokens::DeployTokenStore::new(&cfg));
+        #[cfg(feature = "admin-ui")]
+        let audit = Arc::new(audit::AuditLog::new(&cfg));
+        #[cfg(feature = "admin-ui")]
+        let admin_password = Arc::new(secrets::ReloadableSecret::new(
+            cfg.admin_password.clone(),
+            cfg.admin_password_file.clone(),
+        ));
+        #[cfg(feature = "admin-ui")]
+        let admin_login_failures = Arc::new(Mutex::new(login_throttle::LoginThrottle::new(
+            cfg.admin_login_global_max_failures,
+        )));
+        #[cfg(feature = "admin-ui")]
+        let admin_assets = Arc::new(assets::AssetOverrides::new(cfg.admin_assets_dir.clone()));
+        #[cfg(feature = "oidc")]
+        let oidc = cfg.oidc.clone().map(|oidc| Arc::new(oidc::Oidc::new(oidc)));
+        let initializations = Arc::new(init_queue::InitQueue::new(cfg.init_concurrency));
+        let maintenance = Arc::new(maintenance::Maintenance::load(&cfg));
+        let mut state = Self {
+            cfg,
+            source,
+            locks,
+            tokens,
+            activity,
+            alerts,
+            webhooks,
+            backups: Arc::new(backup::BackupState::default()),
+            warmup: Arc::new(warmup::WarmupState::default()),
+            initializations,
+            usage,
+            access_log,
+            deploy_tokens,
+            fetches: Arc::new(metadata::FetchTracker::default()),
+            transfers: Arc::new(connection::TransferStats::default()),
+            advertisements: Arc::new(coalesce::Coalescer::default()),
+            auth_stats: Arc::new(auth::AuthStats::default()),
+            token_uses: Arc::new(auth::TokenUseTracker::default()),
+            timings: Arc::new(timing::TimingStats::default()),
+            maintenance,
+            readiness: Arc::new(readiness::Readiness::default()),
+            shutdown: Arc::new(shutdown::Shutdown::default()),
+            sources: BTreeMap::new(),
+            #[cfg(feature = "admin-ui")]
+            admin_sessions: Arc::new(Mutex::new(HashMap::new())),
+            #[cfg(feature = "admin-ui")]
+            admin_login_failures,
+            #[cfg(feature = "admin-ui")]
+            admin_password,
+            #[cfg(feature = "oidc")]
+            oidc,
+            #[cfg(feature = "admin-ui")]
+            admin_assets,
+            #[cfg(feature = "admin-ui")]
+            settings_lock: Arc::new(Mutex::new(())),
+            #[cfg(feature = "admin-ui")]
+            stats_cache: Arc::new(stats::StatsCache::default()),
+            #[cfg(feature = "admin-ui")]
+            audit,
+        };
+        state.sources = state
+            .cfg
+            .sources
+            .iter()
+            .map(|source| {
+                let cfg = state.cfg.for_source(source);
+                (source.name.clone(), Arc::new(state.for_source(cfg)))
+            })
+            .collect();
+        state
+    }
+
+    /// State serving a named source with `cfg`: it has its own project
+    /// source, locks, queues and per-project stores, and shares tokens,
+    /// quotas, maintenance and statistics with this one.
+    fn for_source(&self, cfg: Config) -> Self {
+        Self {
+            source: source::from_config(&cfg),
+            locks: Arc::new(locks::ProjectLocks::with_lock_dir(cfg.lock_dir())),
+            activity: Arc::new(activity::ActivityLog::new(&cfg)),
+            warmup: Arc::new(warmup::WarmupState::default()),
+            initializations: Arc::new(init_queue::InitQueue::new(cfg.init_concurrency)),
+            deploy_tokens: Arc::new(deploy_tokens::DeployTokenStore::new(&cfg)),
+            fetches: Arc::new(metadata::FetchTracker::default()),
+            advertisements: Arc::new(coalesce::Coalescer::default()),
+            sources: BTreeMap::new(),
+            #[cfg(feature = "admin-ui")]
+            settings_lock: Arc::new(Mutex::new(())),
+            #[cfg(feature = "admin-ui")]
+            stats_cache: Arc::new(stats::StatsCache::default()),
+            cfg,
+            ..self.clone()
+        }
+    }
+
+    /// The state serving `name`, [`config::DEFAULT_SOURCE`] being this one.
+    #[cfg(feature = "admin-ui")]
+    pub(crate) fn source_state(self: &Arc<Self>, name: &str) -> Option<Arc<AppState>> {
+        if name == config::DEFAULT_SOURCE {
+            return Some(self.clone());
+        }
+        self.sources.get(name).cloned()
+    }
+
+    /// Replace the project source.
+    pub fn with_source(mut self, source: Arc<dyn ProjectSource>) -> Self {
+        self.source = source;
+        self
+    }
+
+    /// Take the lock of a project for `operation`, waiting at most
+    /// `LOCK_WAIT_SECONDS`. A timeout is reported as [`BridgeError::ProjectBusy`]
+    /// and logged with the operation holding the lock.
+    pub(crate) async fn lock_project(
+        &self,
+        project_id: &str,
+        operation: &'static str,
+    ) -> Result<locks::ProjectGuard, BridgeError> {
+        let wait = Duration::from_secs(self.cfg.lock_wait_seconds);
+        let locked = self.locks.lock_timeout(project_id, operation, wait);
+        timing::measure(timing::Phase::LockWait, locked)
+            .await
+            .map_err(|holder| {
+                let held_by = match &holder {
+                    Some(holder) => format!(
+                        "{} running for {}s",
+                        holder.operation,
+                        holder.since.elapsed().as_secs()
+                    ),
+                    None => "an unknown operation".to_string(),
+                };
+                warn!(
+                    %project_id,
+                    waiting = operation,
+                    queued = self.locks.waiting().waiting,
+                    "timed out waiting for project lock held by {held_by}"
+                );
+                BridgeError::ProjectBusy(format!("{project_id} is locked by {held_by}"))
+            })
+    }
+
+    /// How long the admin login from `client` is blocked, if it is.
+    #[cfg(feature = "admin-ui")]
+    pub(crate) async fn login_throttle_status(&self, client: Option<IpAddr>) -> Option<Duration> {
+        let mut failures = self.admin_login_failures.lock().await;
+        failures.status(client, Instant::now())
+    }
+
+    #[cfg(feature = "admin-ui")]
+    pub(crate) async fn note_login_failure(&self, client: Option<IpAddr>) {
+        let mut failures = self.admin_login_failures.lock().await;
+        failures.note_failure(client, Instant::now());
+    }
+
+    #[cfg(feature = "admin-ui")]
+    pub(crate) async fn reset_login_failures(&self, client: Option<IpAddr>) {
+        let mut failures = self.admin_login_failures.lock().await;
+        failures.reset(client);
+    }
+}
+
+/// Build the full HTTP router: health checks, the git endpoint, and (with the
+/// `admin-ui` feature) the admin UI. Requests beyond `MAX_CONCURRENT_REQUESTS`
+/// are answered with 503, except for the health check.
+pub fn build_router(state: Arc<AppState>) -> Router {
+    let router = Router::new()
+        // git smart http endpoint
+        .route("/git/{*tail}", any(server::git_handler))
+        .route("/archive/{*tail}", get(server::archive_handler))
+        // sync trigger for external systems
+        .route("/hooks/sync/{id}", post(webhook::sync_hook));
+
+    #[cfg(feature = "admin-ui")]
+    let router = router
+        // admin UI SPA + APIs
+        .route("/admin", get(admin::admin_app))
+        .route(
+            "/admin/api/login",
+            get(admin::admin_login_options_api).post(admin::admin_login_api),
+        )
+        .route("/admin/api/logout", post(admin::admin_logout_api))
+        .route(
+            "/admin/api/tokens",
+            get(admin::admin_tokens_api).post(admin::admin_create_token_api),
+        )
+        .route(
+            "/admin/api/tokens/export",
+            get(admin::admin_export_tokens_api),
+        )
+        .route(
+            "/admin/api/tokens/import",
+            post(admin::admin_import_tokens_api),
+        )
+        .route(
+            "/admin/api/tokens/{id}",
+            put(admin::admin_update_token_api)
+                .patch(admin::admin_patch_token_api)
+                .delete(admin::admin_delete_token_api),
+        )
+        .route(
+            "/admin/api/tokens/{id}/usage",
+            get(admin::admin_token_usage_api),
+        )
+        .route("/admin/api/config", get(admin::admin_config_api))
+        .route("/admin/api/projects", get(admin::admin_projects_api))
+        .route(
+            "/admin/api/projects/stale",
+            get(admin::admin_stale_projects_api),
+        )
+        .route(
+            "/admin/api/projects/prune",
+            post(admin::admin_prune_projects_api),
+        )
+        .route("/admin/api/projects/{id}", get(admin::admin_project_api))
+        .route(
+            "/admin/api/projects/{id}/alias",
+            put(admin::admin_set_alias_api).delete(admin::admin_delete_alias_api),
+        )
+        .route(
+            "/admin/api/projects/{id}/public",
+            put(admin::admin_set_public_api).delete(admin::admin_delete_public_api),
+        )
+        .route(
+            "/admin/api/projects/{id}/deploy-token",
+            post(admin::admin_create_deploy_token_api).delete(admin::admin_revoke_deploy_token_api),
+        )
+        .route(
+            "/admin/api/deploy-tokens",
+            get(admin::admin_deploy_tokens_api),
+        )
+        .route(
+            "/admin/api/projects/{id}/webhook-secret",
+            put(admin::admin_set_webhook_secret_api).delete(admin::admin_delete_webhook_secret_api),
+        )
+        .route(
+            "/admin/api/projects/{id}/stats",
+            get(admin::admin_project_stats_api),
+        )
+        .route(
+            "/admin/api/projects/{id}/changes",
+            get(admin::admin_project_changes_api),
+        )
+        .route("/admin/api/stats", get(admin::admin_stats_api))
+        .route("/admin/api/stats/top", get(admin::admin_stats_top_api))
+        .route("/admin/api/activity", get(admin::admin_activity_api))
+        .route("/admin/api/audit", get(admin::admin_audit_api))
+        .route("/admin/api/integrity", get(admin::admin_integrity_api))
+        .route("/admin/api/transfers", get(admin::admin_transfers_api))
+        .route("/admin/api/auth-stats", get(admin::admin_auth_stats_api))
+        .route("/admin/api/trash", get(admin::admin_trash_api))
+        .route(
+            "/admin/api/trash/{entry}/restore",
+            post(admin::admin_restore_trash_api),
+        )
+        .route(
+            "/admin/api/webhooks",
+            get(admin::admin_webhooks_api).delete(admin::admin_discard_webhooks_api),
+        )
+        .route(
+            "/admin/api/webhooks/flush",
+            post(admin::admin_flush_webhooks_api),
+        )
+        .route(
+            "/admin/api/webhooks/{id}",
+            delete(admin::admin_discard_webhook_api),
+        )
+        .route(
+            "/admin/api/backup",
+            get(admin::admin_backup_status_api).post(admin::admin_start_backup_api),
+        )
+        .route(
+            "/admin/api/shared-objects",
+            get(admin::admin_shared_objects_api),
+        )
+        .route(
+            "/admin/api/shared-objects/gc",
+            post(admin::admin_gc_shared_objects_api),
+        )
+        .route("/admin/api/warmup", post(admin::admin_start_warmup_api))
+        .route(
+            "/admin/api/warmup/status",
+            get(admin::admin_warmup_status_api),
+        )
+        .route(
+            "/admin/api/warmup/abort",
+            post(admin::admin_abort_warmup_api),
+        )
+        .route(
+            "/admin/api/maintenance",
+            get(admin::admin_maintenance_api).post(admin::admin_set_maintenance_api),
+        )
+        .route(
+            "/admin/api/projects/{id}/restore",
+            post(admin::admin_restore_project_api),
+        )
+        .route(
+            "/admin/api/projects/{id}/sync",
+            post(admin::admin_sync_project_api),
+        )
+        .route(
+            "/admin/api/repos/{id}",
+            delete(admin::admin_delete_repo_api),
+        )
+        .route(
+            "/admin/api/projects/{id}/tree",
+            get(admin::admin_project_tree_api),
+        )
+        .route(
+            "/admin/api/projects/{id}/blob",
+            get(admin::admin_project_blob_api),
+        )
+        .route("/assets/tailwind.js", get(admin::admin_tailwind_asset))
+        .route("/assets/logo.webp", get(admin::admin_logo_asset))
+        .route("/assets/favicon.png", get(admin::admin_favicon_asset))
+        .route("/favicon.ico", get(admin::admin_favicon_asset));
+
+    #[cfg(feature = "oidc")]
+    let router = router
+        .route("/admin/api/login/oidc", get(admin::admin_oidc_login_api))
+        .route(
+            "/admin/api/login/oidc/callback",
+            get(admin::admin_oidc_callback_api),
+        );
+
+    // The health checks stay reachable when the limit is hit; every request,
+    // refused ones too, is logged.
+    connection::limit_requests(router, state.cfg.max_concurrent_requests)
+        .route("/", get(server::health))
+        .route("/healthz", get(server::readiness))
+        .layer(axum::middleware::from_fn(request_log::log_requests))
+        .with_state(state)
+}
+
+/// Create `git_root` and an empty tokens file if needed, move a flat
+/// `git_root` to the split layout, hash the tokens of an existing tokens
+/// file that still holds them in plain, and warn about missing ShareLatex
+/// directories.
+pub fn init_storage(cfg: &Config) -> Result<(), String> {
+    fs::create_dir_all(&cfg.git_root)
+        .map_err(|e| format!("cannot create git_root '{}': {e}", cfg.git_root.display()))?;
+    layout::migrate_to_split(cfg).map_err(|e| {
+        format!(
+            "cannot move git_root '{}' to the split layout: {e}",
+            cfg.git_root.display()
+        )
+    })?;
+
+    let tokens_path = cfg.tokens_file();
+    let _tokens_lock = auth::TokensFileLock::acquire_blocking(cfg, auth::TOKENS_LOCK_WAIT)
+        .map_err(|e| format!("cannot lock tokens file '{}': {e}", tokens_path.display()))?;
+    if !tokens_path.exists() {
+        let default_tokens = TokensFile::default();
+        save_tokens_file(cfg, &default_tokens)
+            .map_err(|e| format!("cannot create tokens file '{}': {e}", tokens_path.display()))?;
+    } else if let Err(e) = auth::hash_plain_tokens(cfg).and_then(|()| load_tokens_file(cfg)) {
+        warn!(path = %tokens_path.display(), "cannot load tokens file: {e}");
+    }
+
+    if !cfg.sharelatex_data_path.exists() {
+        warn!(path = %cfg.sharelatex_data_path.display(), "sharelatex data path does not exist (yet)");
+    } else if !cfg.sharelatex_data_path.is_dir() {
+        warn!(path = %cfg.sharelatex_data_path.display(), "sharelatex data path is not a directory");
+    }
+
+    let projects_root = cfg.projects_root();
+    if !projects_root.exists() {
+        warn!(path = %projects_root.display(), "projects directory does not exist (will cause 404 until created)");
+    }
+
+    for source in &cfg.sources {
+        let source_cfg = cfg.for_source(source);
+        fs::create_dir_all(&source_cfg.git_root).map_err(|e| {
+            format!(
+                "cannot create git_root of source '{}' at '{}': {e}",
+                source.name,
+                source_cfg.git_root.display()
+            )
+        })?;
+        layout::migrate_to_split(&source_cfg).map_err(|e| {
+            format!(
+                "cannot move git_root of source '{}' to the split layout: {e}",
+                source.name
+            )
+        })?;
+        let projects_root = source_cfg.projects_root();
+        if !projects_root.exists() {
+            warn!(source = %source.name, path = %projects_root.display(), "projects directory does not exist (will cause 404 until created)");
+        }
+    }
+
+    Ok(())
+}
+
+/// Create the mirrors of all projects that have none in the background,
+/// `WARMUP_CONCURRENCY` at a time. Returns false when a warm-up is already
+/// running.
+pub fn start_warmup(state: &Arc<AppState>) -> bool {
+    let Some(running) = state.warmup.try_start() else {
+        return false;
+    };
+    tokio::spawn(warmup::run_warmup(
+        state.clone(),
+        state.cfg.warmup_concurrency,
+        running,
+    ));
+    true
+}
+
+/// Start the periodic maintenance tasks. [`serve`] calls this; embedders
+/// using [`build_router`] directly should call it once as well.
+pub fn spawn_background_tasks(state: &Arc<AppState>) {
+    #[cfg(feature = "admin-ui")]
+    if state.cfg.admin_password_file.is_some() {
+        tokio::spawn(watch_admin_password(state.clone()));
+    }
+    // mirror upkeep runs for every source
+    for state in std::iter::once(state).chain(state.sources.values()) {
+        if let Some(hours) = state.cfg.fsck_interval_hours {
+            tokio::spawn(integrity::run_periodically(
+                state.clone(),
+                Arc::new(git::SystemGit),
+                Duration::from_secs(hours * 3600),
+            ));
+        }
+        if state.cfg.export_gitweb_metadata {
+            tokio::spawn(gitweb::run_periodically(state.clone()));
+        }
+        tokio::spawn(trash::run_periodically(state.clone()));
+    }
+    tokio::spawn(deploy_tokens::run_periodically(state.clone()));
+    tokio::spawn(webhook_queue::run_periodically(state.clone()));
+    if let Some(minutes) = state.cfg.backup_interval_minutes {
+        tokio::spawn(backup::run_periodically(
+            state.clone(),
+            Arc::new(git::SystemGit),
+            Duration::from_secs(minutes * 60),
+        ));
+    }
+}
+
+/// Poll ADMIN_PASSWORD_FILE and log out every admin session when it changes.
+#[cfg(feature = "admin-ui")]
+async fn watch_admin_password(state: Arc<AppState>) {
+    const INTERVAL: Duration = Duration::from_secs(10);
+    loop {
+        tokio::time::sleep(INTERVAL).await;
+        if state.admin_password.reload() == secrets::Reload::Changed {
+            state.admin_sessions.lock().await.clear();
+            tracing::info!("admin password changed, all admin sessions invalidated");
+        }
+    }
+}
+
+/// Bind `0.0.0.0:<cfg.port>` and serve [`build_router`] until SIGTERM or
+/// SIGINT, applying the connection limit and the header-read and idle
+/// timeouts. Returns once running syncs and requests have finished, or
+/// after `SHUTDOWN_GRACE_SECONDS` or a second signal; blocking tasks may
+/// still be running then, so callers should exit rather than wait for them.
+pub async fn serve(state: Arc<AppState>) -> std::io::Result<()> {
+    let mut signals = shutdown::Signals::listen()?;
+    spawn_background_tasks(&state);
+    let daemon = match state.cfg.git_daemon_port {
+        Some(port) => {
+            let addr = std::net::SocketAddr::from(([0, 0, 0, 0], port));
+            let listener = tokio::net::TcpListener::bind(addr).await?;
+            Some(tokio::spawn(git_daemon::serve_git_daemon(
+                state.clone(),
+                listener,
+            )))
+        }
+        None => None,
+    };
+    let addr = std::net::SocketAddr::from(([0, 0, 0, 0], state.cfg.port));
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    let server = connection::serve_connections(
+        listener,
+        build_router(state.clone()),
+        &state.cfg,
+        state.transfers.clone(),
+        state.shutdown.clone(),
+    );
+    // dropping the accept loops closes the listeners; connections already
+    // accepted finish on their own
+    let signal = tokio::select! {
+        result = server => return result,
+        signal = signals.next() => signal,
+    };
+    if let Some(daemon) = daemon {
+        daemon.abort();
+    }
+    info!(
+        grace_seconds = state.cfg.shutdown_grace_seconds,
+        "{signal} received, shutting down once running operations finish"
+    );
+    state.shutdown.begin();
+    shutdown::drain(&state, signals.next()).await;
+    Ok(())
+}
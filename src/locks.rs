@@ -0,0 +1,294 @@
+use crate::queues::{QueueDepth, WaitQueue};
+use dashmap::DashMap;
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{Mutex, OwnedMutexGuard};
+use tracing::warn;
+
+/// How often a waiter retries a lock file held by another process.
+const LOCK_FILE_POLL: Duration = Duration::from_millis(50);
+
+/// What currently holds a project lock.
+#[derive(Debug, Clone)]
+pub(crate) struct LockHolder {
+    pub(crate) operation: &'static str,
+    pub(crate) since: Instant,
+}
+
+/// Per-project mutexes serializing syncs and other mirror changes. Each
+/// holder is recorded so a timed-out waiter can tell what it waited for.
+///
+/// With a lock directory, each lock is also taken as an advisory lock on
+/// `<dir>/<id>.lock`, serializing with other processes using the same
+/// `git_root` (`sharelatex-gitbridge-ro sync`).
+#[derive(Default)]
+pub(crate) struct ProjectLocks {
+    locks: DashMap<String, Arc<Mutex<()>>>,
+    holders: Arc<DashMap<String, LockHolder>>,
+    lock_dir: Option<PathBuf>,
+    /// Tasks waiting for any project lock
+    waiters: Arc<WaitQueue>,
+}
+
+/// A held project lock; releases the lock and forgets the holder on drop.
+pub(crate) struct ProjectGuard {
+    project_id: String,
+    holders: Arc<DashMap<String, LockHolder>>,
+    _guard: OwnedMutexGuard<()>,
+    /// Closing the file releases the cross-process lock
+    _file: Option<File>,
+}
+
+/// The lock file of a project is held by another process.
+struct LockedElsewhere;
+
+impl Drop for ProjectGuard {
+    fn drop(&mut self) {
+        self.holders.remove(&self.project_id);
+    }
+}
+
+impl ProjectLocks {
+    /// Locks also held as lock files below `dir`.
+    pub(crate) fn with_lock_dir(dir: PathBuf) -> Self {
+        Self {
+            lock_dir: Some(dir),
+            ..Self::default()
+        }
+    }
+
+    fn mutex(&self, project_id: &str) -> Arc<Mutex<()>> {
+        if let Some(entry) = self.locks.get(project_id) {
+            return entry.clone();
+        }
+        self.locks
+            .entry(project_id.to_string())
+            .or_insert_with(|| Arc::new(Mutex::new(())))
+            .clone()
+    }
+
+    fn guard(
+        &self,
+        project_id: &str,
+        operation: &'static str,
+        guard: OwnedMutexGuard<()>,
+        file: Option<File>,
+    ) -> ProjectGuard {
+        self.holders.insert(
+            project_id.to_string(),
+            LockHolder {
+                operation,
+                since: Instant::now(),
+            },
+        );
+        ProjectGuard {
+            project_id: project_id.to_string(),
+            holders: self.holders.clone(),
+            _guard: guard,
+            _file: file,
+        }
+    }
+
+    /// Take the lock file of a project if it is free. Lock files that cannot
+    /// be opened or locked are logged and skipped, leaving only the
+    /// in-process lock.
+    fn try_lock_file(
+        &self,
+        project_id: &str,
+        operation: &'static str,
+    ) -> Result<Option<File>, LockedElsewhere> {
+        let Some(dir) = &self.lock_dir else {
+            return Ok(None);
+        };
+        let path = dir.join(format!("{project_id}.lock"));
+        let opened = fs::create_dir_all(dir).and_then(|()| {
+            OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(&path)
+        });
+        let mut file = match opened {
+            Ok(file) => file,
+            Err(e) => {
+                warn!(path = %path.display(), "cannot open lock file: {e}");
+                return Ok(None);
+            }
+        };
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => return Err(LockedElsewhere),
+            Err(TryLockError::Error(e)) => {
+                warn!(path = %path.display(), "cannot lock lock file: {e}");
+                return Ok(None);
+            }
+        }
+        // for whoever looks at a lock that stays held
+        let noted = file
+            .set_len(0)
+            .and_then(|()| writeln!(file, "{operation} {}", std::process::id()));
+        if let Err(e) = noted {
+            warn!(path = %path.display(), "cannot write lock file: {e}");
+        }
+        Ok(Some(file))
+    }
+
+    /// Wait for the lock file of a project as long as it takes.
+    async fn lock_file(&self, project_id: &str, operation: &'static str) -> Option<File> {
+        loop {
+            match self.try_lock_file(project_id, operation) {
+                Ok(file) => return file,
+                Err(LockedElsewhere) => tokio::time::sleep(LOCK_FILE_POLL).await,
+            }
+        }
+    }
+
+    /// Wait for the lock of a project as long as it takes.
+    pub(crate) async fn lock(&self, project_id: &str, operation: &'static str) -> ProjectGuard {
+        let waiting = self.waiters.join();
+        let guard = self.mutex(project_id).lock_owned().await;
+        let file = self.lock_file(project_id, operation).await;
+        drop(waiting);
+        self.guard(project_id, operation, guard, file)
+    }
+
+    /// Wait for the lock of a project for at most `wait`; on timeout returns
+    /// the operation holding it, if still known.
+    pub(crate) async fn lock_timeout(
+        &self,
+        project_id: &str,
+        operation: &'static str,
+        wait: Duration,
+    ) -> Result<ProjectGuard, Option<LockHolder>> {
+        let locked = async {
+            let _waiting = self.waiters.join();
+            let guard = self.mutex(project_id).lock_owned().await;
+            let file = self.lock_file(project_id, operation).await;
+            (guard, file)
+        };
+        match tokio::time::timeout(wait, locked).await {
+            Ok((guard, file)) => Ok(self.guard(project_id, operation, guard, file)),
+            Err(_) => Err(self.holder(project_id)),
+        }
+    }
+
+    /// Take the lock of a project only if it is free.
+    pub(crate) fn try_lock(
+        &self,
+        project_id: &str,
+        operation: &'static str,
+    ) -> Option<ProjectGuard> {
+        let guard = self.mutex(project_id).try_lock_owned().ok()?;
+        let file = self.try_lock_file(project_id, operation).ok()?;
+        Some(self.guard(project_id, operation, guard, file))
+    }
+
+    /// Tasks waiting for a project lock, across all projects.
+    pub(crate) fn waiting(&self) -> QueueDepth {
+        self.waiters.depth()
+    }
+
+    /// Project locks currently held.
+    pub(crate) fn held(&self) -> usize {
+        self.holders.len()
+    }
+
+    /// The projects whose lock is held and what holds each, by project.
+    pub(crate) fn holders(&self) -> Vec<(String, &'static str)> {
+        let mut holders: Vec<_> = self
+            .holders
+            .iter()
+            .map(|entry| (entry.key().clone(), entry.value().operation))
+            .collect();
+        holders.sort();
+        holders
+    }
+
+    pub(crate) fn holder(&self, project_id: &str) -> Option<LockHolder> {
+        self.holders.get(project_id).map(|h| h.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn waiters_time_out_and_learn_the_holder() {
+        let locks = ProjectLocks::default();
+        let held = locks.lock("p1", "fetch sync").await;
+        assert!(locks.try_lock("p1", "integrity check").is_none());
+
+        let holder = locks
+            .lock_timeout("p1", "webhook sync", Duration::from_millis(10))
+            .await
+            .err()
+            .flatten()
+            .expect("holder is known");
+        assert_eq!(holder.operation, "fetch sync");
+
+        drop(held);
+        assert!(locks.holder("p1").is_none());
+        let again = locks
+            .lock_timeout("p1", "webhook sync", Duration::from_millis(10))
+            .await;
+        assert!(again.is_ok());
+        assert_eq!(locks.holder("p1").unwrap().operation, "webhook sync");
+    }
+
+    #[tokio::test]
+    async fn waiters_are_counted_until_they_give_up_or_get_the_lock() {
+        let locks = Arc::new(ProjectLocks::default());
+        let held = locks.lock("p1", "fetch sync").await;
+
+        let waiter = {
+            let locks = locks.clone();
+            tokio::spawn(async move { locks.lock("p1", "manual sync").await })
+        };
+        let timed_out = locks.lock_timeout("p1", "webhook sync", Duration::from_millis(50));
+        let (depth, timed_out) = tokio::join!(
+            async {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                locks.waiting()
+            },
+            timed_out
+        );
+        assert_eq!(depth.waiting, 2);
+        assert!(depth.oldest_wait_ms >= 10);
+        assert!(timed_out.is_err());
+        assert_eq!(locks.waiting().waiting, 1);
+
+        drop(held);
+        let _guard = waiter.await.unwrap();
+        assert_eq!(locks.waiting().waiting, 0);
+    }
+
+    #[tokio::test]
+    async fn lock_files_serialize_separate_lock_tables() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let server = ProjectLocks::with_lock_dir(dir.path().join(".locks"));
+        let cli = ProjectLocks::with_lock_dir(dir.path().join(".locks"));
+
+        let held = server.lock("p1", "fetch sync").await;
+        let noted = fs::read_to_string(dir.path().join(".locks/p1.lock")).unwrap();
+        assert!(noted.starts_with("fetch sync "));
+        assert!(cli.try_lock("p1", "cli sync").is_none());
+        assert!(cli.try_lock("p2", "cli sync").is_some());
+        let waited = cli
+            .lock_timeout("p1", "cli sync", Duration::from_millis(120))
+            .await;
+        assert!(waited.is_err());
+        // the timed-out waiter left the in-process lock free
+        assert!(cli.holder("p1").is_none());
+
+        drop(held);
+        let cli_guard = cli
+            .lock_timeout("p1", "cli sync", Duration::from_millis(500))
+            .await;
+        assert!(cli_guard.is_ok());
+        assert!(server.try_lock("p1", "integrity check").is_none());
+    }
+}
@@ -0,0 +1,143 @@
+//! Failed admin logins, counted per client address over a sliding window,
+//! with a ceiling across all addresses (`ADMIN_LOGIN_GLOBAL_MAX_FAILURES`)
+//! for attacks spread over many addresses.
+
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+/// How long a failed login counts.
+pub(crate) const WINDOW: Duration = Duration::from_secs(60);
+
+/// Failures within [`WINDOW`] after which a client has to wait.
+pub(crate) const MAX_FAILURES_PER_CLIENT: usize = 5;
+
+/// Recent failures by client address; `None` collects logins whose address
+/// is unknown.
+pub(crate) struct LoginThrottle {
+    clients: HashMap<Option<IpAddr>, VecDeque<Instant>>,
+    all: VecDeque<Instant>,
+    /// Failures within [`WINDOW`] after which every client has to wait; 0
+    /// for no such limit
+    global_max: usize,
+}
+
+/// Drop the failures older than [`WINDOW`] from the front of `failures`.
+fn expire(failures: &mut VecDeque<Instant>, now: Instant) {
+    while failures
+        .front()
+        .is_some_and(|at| now.duration_since(*at) > WINDOW)
+    {
+        failures.pop_front();
+    }
+}
+
+/// Time until the oldest of `failures` expires, once there are `max`.
+fn wait(failures: &VecDeque<Instant>, max: usize, now: Instant) -> Option<Duration> {
+    if failures.len() < max {
+        return None;
+    }
+    let oldest = failures.front()?;
+    WINDOW.checked_sub(now.duration_since(*oldest))
+}
+
+impl LoginThrottle {
+    pub(crate) fn new(global_max: usize) -> Self {
+        Self {
+            clients: HashMap::new(),
+            all: VecDeque::new(),
+            global_max,
+        }
+    }
+
+    /// Forget expired failures, and the clients left without any, so the
+    /// map only holds addresses that failed within the last [`WINDOW`].
+    fn evict(&mut self, now: Instant) {
+        expire(&mut self.all, now);
+        self.clients.retain(|_, failures| {
+            expire(failures, now);
+            !failures.is_empty()
+        });
+    }
+
+    /// How long `client` has to wait before trying again, if at all.
+    pub(crate) fn status(&mut self, client: Option<IpAddr>, now: Instant) -> Option<Duration> {
+        self.evict(now);
+        let own = self
+            .clients
+            .get(&client)
+            .and_then(|failures| wait(failures, MAX_FAILURES_PER_CLIENT, now));
+        let global = (self.global_max > 0)
+            .then(|| wait(&self.all, self.global_max, now))
+            .flatten();
+        own.max(global)
+    }
+
+    pub(crate) fn note_failure(&mut self, client: Option<IpAddr>, now: Instant) {
+        self.evict(now);
+        self.clients.entry(client).or_default().push_back(now);
+        self.all.push_back(now);
+    }
+
+    /// Forget the failures of `client` after it logged in; they keep
+    /// counting towards the global limit until they expire.
+    pub(crate) fn reset(&mut self, client: Option<IpAddr>) {
+        self.clients.remove(&client);
+    }
+
+    /// Failures of `client` within the window.
+    #[cfg(test)]
+    pub(crate) fn failures(&self, client: Option<IpAddr>) -> usize {
+        self.clients.get(&client).map_or(0, VecDeque::len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> Option<IpAddr> {
+        Some(s.parse().unwrap())
+    }
+
+    #[test]
+    fn clients_are_throttled_on_their_own_and_together() {
+        let start = Instant::now();
+        let mut throttle = LoginThrottle::new(8);
+        let (attacker, admin) = (ip("203.0.113.9"), ip("198.51.100.1"));
+
+        for i in 0..MAX_FAILURES_PER_CLIENT as u64 {
+            assert_eq!(throttle.status(attacker, start), None);
+            throttle.note_failure(attacker, start + Duration::from_secs(i));
+        }
+        let now = start + Duration::from_secs(10);
+        assert_eq!(
+            throttle.status(attacker, now),
+            Some(WINDOW - Duration::from_secs(10))
+        );
+        assert_eq!(throttle.status(admin, now), None);
+        assert_eq!(throttle.status(None, now), None);
+
+        // three more from elsewhere reach the global ceiling of eight
+        for client in [ip("192.0.2.1"), ip("192.0.2.2"), None] {
+            throttle.note_failure(client, now);
+        }
+        assert!(throttle.status(admin, now).is_some());
+
+        // a successful login clears only the client's own failures
+        throttle.reset(attacker);
+        assert_eq!(throttle.failures(attacker), 0);
+        assert!(throttle.status(attacker, now).is_some());
+
+        // expired failures and the clients they belonged to are dropped
+        let later = now + WINDOW + Duration::from_secs(1);
+        assert_eq!(throttle.status(admin, later), None);
+        assert!(throttle.clients.is_empty() && throttle.all.is_empty());
+
+        let mut unlimited = LoginThrottle::new(0);
+        for i in 0..100u8 {
+            unlimited.note_failure(Some(IpAddr::from([10, 0, 0, i])), start);
+        }
+        assert_eq!(unlimited.status(admin, start), None);
+    }
+}
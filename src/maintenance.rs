@@ -0,0 +1,223 @@
+//! Maintenance mode, switched on and off through the admin API. While it is
+//! on, git requests are answered with 503, syncs are refused and the
+//! periodic background tasks skip their runs. It is kept in
+//! `maintenance.json` under `git_root`, so a restart does not end it.
+
+use crate::config::Config;
+#[cfg(feature = "admin-ui")]
+use crate::error::BridgeError;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+#[cfg(feature = "admin-ui")]
+use std::io::Write;
+#[cfg(feature = "admin-ui")]
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::sync::watch;
+use tracing::error;
+
+/// Message used when maintenance is switched on without one.
+pub(crate) const DEFAULT_MESSAGE: &str = "the git bridge is down for maintenance, try again later";
+
+/// `Retry-After` used when maintenance is switched on without one.
+pub(crate) const DEFAULT_RETRY_AFTER_SECONDS: u64 = 300;
+
+/// Why the bridge is in maintenance, as stored in `maintenance.json`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct MaintenanceState {
+    /// Shown to git clients and on the health endpoint
+    pub(crate) message: String,
+    pub(crate) retry_after_seconds: u64,
+    pub(crate) since: DateTime<Utc>,
+}
+
+/// The maintenance switch and the operations still running.
+pub(crate) struct Maintenance {
+    #[cfg(feature = "admin-ui")]
+    path: PathBuf,
+    state: watch::Sender<Option<MaintenanceState>>,
+    /// Git requests, syncs and background runs in progress
+    in_flight: Arc<AtomicUsize>,
+}
+
+/// A running operation, counted until dropped.
+pub(crate) struct InFlight(Arc<AtomicUsize>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Maintenance {
+    /// Read `maintenance.json`; a missing file means maintenance is off. A
+    /// file that cannot be read keeps the bridge in maintenance, with the
+    /// default message.
+    pub(crate) fn load(cfg: &Config) -> Self {
+        let path = cfg.maintenance_file();
+        let state = match fs::read_to_string(&path) {
+            Ok(data) => match serde_json::from_str(&data) {
+                Ok(state) => Some(state),
+                Err(e) => {
+                    error!(path = %path.display(), "cannot parse maintenance file, staying in maintenance: {e}");
+                    Some(Self::state(None, None, Utc::now()))
+                }
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => {
+                error!(path = %path.display(), "cannot read maintenance file, staying in maintenance: {e}");
+                Some(Self::state(None, None, Utc::now()))
+            }
+        };
+        Self {
+            #[cfg(feature = "admin-ui")]
+            path,
+            state: watch::Sender::new(state),
+            in_flight: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    fn state(
+        message: Option<String>,
+        retry_after_seconds: Option<u64>,
+        since: DateTime<Utc>,
+    ) -> MaintenanceState {
+        MaintenanceState {
+            message: message.unwrap_or_else(|| DEFAULT_MESSAGE.to_string()),
+            retry_after_seconds: retry_after_seconds.unwrap_or(DEFAULT_RETRY_AFTER_SECONDS),
+            since,
+        }
+    }
+
+    pub(crate) fn current(&self) -> Option<MaintenanceState> {
+        self.state.borrow().clone()
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.state.borrow().is_some()
+    }
+
+    /// Switch maintenance on, or change its message and `Retry-After` if it
+    /// already is; `since` stays at the time it was first switched on.
+    #[cfg(feature = "admin-ui")]
+    pub(crate) fn enable(
+        &self,
+        message: Option<String>,
+        retry_after_seconds: Option<u64>,
+    ) -> Result<MaintenanceState, BridgeError> {
+        let since = self.current().map_or_else(Utc::now, |state| state.since);
+        let state = Self::state(message, retry_after_seconds, since);
+        let tmp_path = self.path.with_extension("tmp");
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        {
+            let mut f = fs::File::create(&tmp_path)?;
+            f.write_all(serde_json::to_string_pretty(&state)?.as_bytes())?;
+            f.sync_all()?;
+        }
+        fs::rename(&tmp_path, &self.path)?;
+        self.state.send_replace(Some(state.clone()));
+        Ok(state)
+    }
+
+    #[cfg(feature = "admin-ui")]
+    pub(crate) fn disable(&self) -> Result<(), BridgeError> {
+        match fs::remove_file(&self.path) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        self.state.send_replace(None);
+        Ok(())
+    }
+
+    /// Count an operation as running until the guard is dropped. Taken
+    /// before checking [`Self::is_enabled`], so a drain never misses an
+    /// operation that got past the check.
+    pub(crate) fn begin(&self) -> InFlight {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        InFlight(self.in_flight.clone())
+    }
+
+    pub(crate) fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+
+    /// Wait until maintenance is off.
+    pub(crate) async fn wait_until_off(&self) {
+        let mut state = self.state.subscribe();
+        // the sender lives in self, so the channel is never closed here
+        let _ = state.wait_for(Option::is_none).await;
+    }
+}
+
+#[cfg(all(test, feature = "admin-ui"))]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    #[test]
+    fn maintenance_survives_a_restart() {
+        let root = TempDir::new().unwrap();
+        let cfg = Config::for_test(root.path());
+        let maintenance = Maintenance::load(&cfg);
+        assert!(!maintenance.is_enabled());
+
+        let state = maintenance
+            .enable(Some("moving disks".into()), Some(60))
+            .unwrap();
+        let restarted = Maintenance::load(&cfg);
+        assert_eq!(restarted.current(), Some(state.clone()));
+
+        // changing the message keeps the start
+        let changed = restarted.enable(None, None).unwrap();
+        assert_eq!(changed.message, DEFAULT_MESSAGE);
+        assert_eq!(changed.retry_after_seconds, DEFAULT_RETRY_AFTER_SECONDS);
+        assert_eq!(changed.since, state.since);
+
+        restarted.disable().unwrap();
+        assert!(!Maintenance::load(&cfg).is_enabled());
+        restarted.disable().unwrap();
+    }
+
+    #[test]
+    fn an_unreadable_file_keeps_the_bridge_in_maintenance() {
+        let root = TempDir::new().unwrap();
+        let cfg = Config::for_test(root.path());
+        fs::create_dir_all(cfg.state_dir()).unwrap();
+        fs::write(cfg.maintenance_file(), "{not json").unwrap();
+        let maintenance = Maintenance::load(&cfg);
+        assert_eq!(maintenance.current().unwrap().message, DEFAULT_MESSAGE);
+    }
+
+    #[tokio::test]
+    async fn waiters_resume_when_maintenance_ends() {
+        let root = TempDir::new().unwrap();
+        let cfg = Config::for_test(root.path());
+        let maintenance = Arc::new(Maintenance::load(&cfg));
+        maintenance.enable(None, None).unwrap();
+        let first = maintenance.begin();
+        let second = maintenance.begin();
+        assert_eq!(maintenance.in_flight(), 2);
+        drop(first);
+        assert_eq!(maintenance.in_flight(), 1);
+        drop(second);
+        assert_eq!(maintenance.in_flight(), 0);
+
+        let waiting = {
+            let maintenance = maintenance.clone();
+            tokio::spawn(async move { maintenance.wait_until_off().await })
+        };
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiting.is_finished());
+        maintenance.disable().unwrap();
+        tokio::time::timeout(Duration::from_secs(5), waiting)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+}
@@ -0,0 +1,395 @@
+use crate::config::Config;
+use crate::error::BridgeError;
+use crate::git::GitCommandRecord;
+use crate::project_id::ProjectId;
+use crate::repo::{FileChange, SyncOutcome};
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use dashmap::mapref::entry::Entry;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tracing::warn;
+use walkdir::WalkDir;
+
+/// Sync attempts kept in [`SyncMetadata::history`].
+pub const MAX_SYNC_HISTORY: usize = 50;
+
+/// Most recent history records that keep their full change list; older
+/// records keep only the counts.
+pub const MAX_CHANGE_LISTS: usize = 10;
+
+/// Bookkeeping the bridge keeps per project, stored next to the bare repo.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SyncMetadata {
+    /// When the last sync attempt finished (successfully or not)
+    #[serde(default)]
+    pub last_sync_at: Option<DateTime<Utc>>,
+    /// When the last successful sync finished
+    #[serde(default)]
+    pub last_success_at: Option<DateTime<Utc>>,
+    /// Error of the last attempt; `None` when it succeeded
+    #[serde(default)]
+    pub last_error: Option<String>,
+    /// Last git commands of the failed sync behind `last_error`, oldest
+    /// first, with their output
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub last_error_git: Vec<GitCommandRecord>,
+    /// When a git client last fetched or cloned the mirror, updated at most
+    /// once a minute
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_fetch_at: Option<DateTime<Utc>>,
+    /// Most recent sync attempts, oldest first, at most [`MAX_SYNC_HISTORY`]
+    #[serde(default)]
+    pub history: Vec<SyncRecord>,
+    /// Result of the last scheduled integrity check
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub integrity: Option<IntegrityCheck>,
+    /// Source fingerprint the mirror was last synced from; fetches skip the
+    /// sync while the source still has it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_fingerprint: Option<String>,
+}
+
+impl SyncMetadata {
+    /// When a git client last fetched the mirror. Metadata written before
+    /// fetches were tracked falls back to the newest fetch in the history.
+    pub fn last_fetch(&self) -> Option<DateTime<Utc>> {
+        self.last_fetch_at.or_else(|| {
+            self.history
+                .iter()
+                .rev()
+                .find(|r| r.trigger == Some(SyncTrigger::Fetch))
+                .map(|r| r.at)
+        })
+    }
+}
+
+/// Outcome of `git fsck` and verifying the read-only branch on a bare repo.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IntegrityCheck {
+    pub at: DateTime<Utc>,
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// The mirror was rebuilt from a fresh snapshot after failing
+    #[serde(default)]
+    pub reset: bool,
+}
+
+/// What started a sync.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncTrigger {
+    /// A git client fetching or cloning
+    Fetch,
+    /// `/hooks/sync/<id>`
+    Webhook,
+    /// The admin API
+    Manual,
+    /// `sharelatex-gitbridge-ro sync` on the command line
+    Cli,
+    /// A warm-up run creating missing mirrors (`--warm-up` or the admin API)
+    Warmup,
+}
+
+/// One sync attempt.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncRecord {
+    pub at: DateTime<Utc>,
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// On-disk size of the bare repo after the attempt
+    #[serde(default)]
+    pub repo_bytes: Option<u64>,
+    /// Unknown for records written before triggers were tracked
+    #[serde(default)]
+    pub trigger: Option<SyncTrigger>,
+    /// Commit created by the attempt, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commit: Option<String>,
+    #[serde(default)]
+    pub files_changed: usize,
+    /// What the commit changed, file by file; only kept on the
+    /// [`MAX_CHANGE_LISTS`] most recent records
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub changes: Vec<FileChange>,
+}
+
+/// Total size of the files below `path`.
+pub(crate) fn dir_size(path: &Path) -> u64 {
+    WalkDir::new(path)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter_map(|e| e.metadata().ok())
+        .filter(|m| m.is_file())
+        .map(|m| m.len())
+        .sum()
+}
+
+fn metadata_path(cfg: &Config, project_id: &ProjectId) -> Result<PathBuf, BridgeError> {
+    Ok(cfg.bare_repo_dir(project_id)?.join("gitbridge-sync.json"))
+}
+
+/// Metadata of a project; empty when nothing was recorded yet or the file is unreadable.
+pub fn load_metadata(cfg: &Config, project_id: &ProjectId) -> SyncMetadata {
+    let Ok(path) = metadata_path(cfg, project_id) else {
+        return SyncMetadata::default();
+    };
+    match fs::read_to_string(&path) {
+        Ok(data) => serde_json::from_str(&data).unwrap_or_else(|e| {
+            warn!(path = %path.display(), error = %e, "ignoring unreadable sync metadata");
+            SyncMetadata::default()
+        }),
+        Err(_) => SyncMetadata::default(),
+    }
+}
+
+/// Load, modify, and write back the metadata of a project. Callers must hold
+/// the project lock. Does nothing when the bare repo does not exist.
+pub(crate) fn update_metadata(
+    cfg: &Config,
+    project_id: &ProjectId,
+    f: impl FnOnce(&mut SyncMetadata),
+) -> Result<(), BridgeError> {
+    if !cfg.bare_repo_dir(project_id)?.is_dir() {
+        return Ok(());
+    }
+    let mut meta = load_metadata(cfg, project_id);
+    f(&mut meta);
+
+    let path = metadata_path(cfg, project_id)?;
+    let tmp_path = path.with_extension("tmp");
+    {
+        let mut file = fs::File::create(&tmp_path)?;
+        file.write_all(serde_json::to_string_pretty(&meta)?.as_bytes())?;
+    }
+    fs::rename(&tmp_path, &path)?;
+    Ok(())
+}
+
+/// Least time between two writes of a project's `last_fetch_at`.
+pub(crate) const FETCH_RECORD_INTERVAL: Duration = Duration::from_secs(60);
+
+/// When the last fetch of each project was written, so busy projects cause
+/// at most one metadata write per [`FETCH_RECORD_INTERVAL`].
+#[derive(Default)]
+pub(crate) struct FetchTracker {
+    written: DashMap<String, Instant>,
+    /// Fetches that ran the pre-fetch sync
+    synced: AtomicU64,
+    /// Fetches served from the mirror because the source was unchanged
+    unchanged: AtomicU64,
+}
+
+impl FetchTracker {
+    /// Whether a fetch of `project_id` happening now should be written; if
+    /// so, it counts as written.
+    pub(crate) fn due(&self, project_id: &ProjectId) -> bool {
+        let now = Instant::now();
+        match self.written.entry(project_id.to_string()) {
+            Entry::Occupied(mut entry) => {
+                if now.duration_since(*entry.get()) < FETCH_RECORD_INTERVAL {
+                    return false;
+                }
+                entry.insert(now);
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(now);
+            }
+        }
+        true
+    }
+
+    pub(crate) fn synced(&self) {
+        self.synced.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn served_unchanged(&self) {
+        self.unchanged.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Fetches that ran the pre-fetch sync and fetches served without it,
+    /// since startup.
+    #[cfg(feature = "admin-ui")]
+    pub(crate) fn sync_counts(&self) -> (u64, u64) {
+        (
+            self.synced.load(Ordering::Relaxed),
+            self.unchanged.load(Ordering::Relaxed),
+        )
+    }
+}
+
+/// Record that a git client fetched the mirror just now. Callers must hold
+/// the project lock. Blocking.
+pub(crate) fn record_fetch(cfg: &Config, project_id: &ProjectId) -> Result<(), BridgeError> {
+    update_metadata(cfg, project_id, |meta| {
+        meta.last_fetch_at = Some(Utc::now())
+    })
+}
+
+/// Record the outcome of a sync attempt.
+pub(crate) fn record_sync(
+    cfg: &Config,
+    project_id: &ProjectId,
+    trigger: SyncTrigger,
+    result: Result<&SyncOutcome, &BridgeError>,
+) {
+    let now = Utc::now();
+    let repo_bytes = cfg
+        .bare_repo_dir(project_id)
+        .ok()
+        .map(|repo| dir_size(&repo));
+    let res = update_metadata(cfg, project_id, |meta| {
+        meta.last_sync_at = Some(now);
+        match result {
+            Ok(outcome) => {
+                meta.last_success_at = Some(now);
+                meta.last_error = None;
+                meta.last_error_git = Vec::new();
+                meta.source_fingerprint = outcome.source_fingerprint.clone();
+            }
+            Err(e) => {
+                meta.last_error = Some(e.to_string());
+                meta.source_fingerprint = None;
+            }
+        }
+        let outcome = result.ok().cloned().unwrap_or_default();
+        meta.history.push(SyncRecord {
+            at: now,
+            ok: result.is_ok(),
+            error: meta.last_error.clone(),
+            repo_bytes,
+            trigger: Some(trigger),
+            commit: outcome.commit,
+            files_changed: outcome.files_changed,
+            changes: outcome.changes,
+        });
+        let excess = meta.history.len().saturating_sub(MAX_SYNC_HISTORY);
+        meta.history.drain(..excess);
+        for record in meta
+            .history
+            .iter_mut()
+            .rev()
+            .filter(|r| !r.changes.is_empty())
+            .skip(MAX_CHANGE_LISTS)
+        {
+            record.changes = Vec::new();
+        }
+    });
+    if let Err(e) = res {
+        warn!(%project_id, error = %e, "failed to write sync metadata");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::project_id::pid;
+    use tempfile::TempDir;
+
+    #[test]
+    fn history_is_bounded() {
+        let root = TempDir::new().unwrap();
+        let cfg = Config::for_test(root.path());
+        fs::create_dir_all(cfg.bare_repo_dir(&pid("p1")).unwrap()).unwrap();
+
+        for _ in 0..MAX_SYNC_HISTORY + 5 {
+            record_sync(
+                &cfg,
+                &pid("p1"),
+                SyncTrigger::Fetch,
+                Ok(&SyncOutcome::default()),
+            );
+        }
+        record_sync(
+            &cfg,
+            &pid("p1"),
+            SyncTrigger::Webhook,
+            Err(&BridgeError::Other("boom".into())),
+        );
+
+        let meta = load_metadata(&cfg, &pid("p1"));
+        assert_eq!(meta.history.len(), MAX_SYNC_HISTORY);
+        let last = meta.history.last().unwrap();
+        assert!(!last.ok);
+        assert_eq!(last.trigger, Some(SyncTrigger::Webhook));
+        assert_eq!(last.error.as_deref(), Some("internal: boom"));
+        assert!(meta.last_success_at.is_some());
+    }
+
+    #[test]
+    fn only_recent_records_keep_their_change_list() {
+        let root = TempDir::new().unwrap();
+        let cfg = Config::for_test(root.path());
+        fs::create_dir_all(cfg.bare_repo_dir(&pid("p1")).unwrap()).unwrap();
+        let outcome = SyncOutcome {
+            commit: Some("0123abcd".into()),
+            squashed: None,
+            files_changed: 1,
+            changes: vec![FileChange {
+                status: "A".into(),
+                path: "main.tex".into(),
+                old_bytes: None,
+                new_bytes: Some(5),
+                delta_bytes: 5,
+            }],
+            ..SyncOutcome::default()
+        };
+        for _ in 0..MAX_CHANGE_LISTS + 2 {
+            record_sync(&cfg, &pid("p1"), SyncTrigger::Webhook, Ok(&outcome));
+            record_sync(
+                &cfg,
+                &pid("p1"),
+                SyncTrigger::Fetch,
+                Ok(&SyncOutcome::default()),
+            );
+        }
+
+        let history = load_metadata(&cfg, &pid("p1")).history;
+        let with_changes: Vec<_> = history.iter().filter(|r| !r.changes.is_empty()).collect();
+        assert_eq!(with_changes.len(), MAX_CHANGE_LISTS);
+        assert_eq!(with_changes[0].changes, outcome.changes);
+        assert!(history[0].changes.is_empty());
+        assert_eq!(history[0].files_changed, 1);
+    }
+
+    #[test]
+    fn fetches_are_written_at_most_once_per_interval() {
+        let root = TempDir::new().unwrap();
+        let cfg = Config::for_test(root.path());
+        fs::create_dir_all(cfg.bare_repo_dir(&pid("p1")).unwrap()).unwrap();
+        record_sync(
+            &cfg,
+            &pid("p1"),
+            SyncTrigger::Webhook,
+            Ok(&SyncOutcome::default()),
+        );
+        record_sync(
+            &cfg,
+            &pid("p1"),
+            SyncTrigger::Fetch,
+            Ok(&SyncOutcome::default()),
+        );
+        let fetch_sync = load_metadata(&cfg, &pid("p1")).history[1].at;
+        // older metadata only knows fetches from the sync history
+        assert_eq!(
+            load_metadata(&cfg, &pid("p1")).last_fetch(),
+            Some(fetch_sync)
+        );
+
+        let tracker = FetchTracker::default();
+        assert!(tracker.due(&pid("p1")));
+        assert!(!tracker.due(&pid("p1")));
+        assert!(tracker.due(&pid("p2")));
+
+        record_fetch(&cfg, &pid("p1")).unwrap();
+        let meta = load_metadata(&cfg, &pid("p1"));
+        assert!(meta.last_fetch_at.is_some_and(|at| at >= fetch_sync));
+        assert_eq!(meta.last_fetch(), meta.last_fetch_at);
+    }
+}
@@ -0,0 +1,378 @@
+//! OpenID Connect login to the admin UI (`OIDC_ISSUER_URL`): the
+//! authorization code flow with PKCE. The ID token comes straight from the
+//! provider's token endpoint over the back channel, so its issuer, audience,
+//! expiry and nonce are checked but not its signature (OpenID Connect Core
+//! 3.1.3.7).
+
+use crate::config::OidcConfig;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use serde::Deserialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use url::Url;
+use uuid::Uuid;
+
+/// How long a started login may take at the provider.
+pub(crate) const LOGIN_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Started logins kept at most; the oldest are dropped beyond it.
+const MAX_PENDING: usize = 1000;
+
+const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Error)]
+pub(crate) enum OidcError {
+    #[error("OIDC provider unavailable: {0}")]
+    Provider(String),
+    #[error("unknown or expired login, please start again")]
+    UnknownLogin,
+    #[error("login refused by the OIDC provider: {0}")]
+    Refused(String),
+    #[error("invalid ID token: {0}")]
+    InvalidToken(&'static str),
+    #[error("{0} is not allowed to log in")]
+    NotAllowed(String),
+}
+
+/// Endpoints of the provider, from its discovery document.
+#[derive(Clone, Debug, Deserialize)]
+struct Provider {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+}
+
+/// A login sent to the provider and not back yet.
+struct PendingLogin {
+    nonce: String,
+    verifier: String,
+    started: Instant,
+}
+
+/// The claims of an ID token the login looks at.
+#[derive(Debug, Deserialize)]
+struct Claims {
+    iss: String,
+    /// A client id or a list of them
+    aud: Value,
+    exp: i64,
+    #[serde(default)]
+    nonce: Option<String>,
+    sub: String,
+    #[serde(default)]
+    email: Option<String>,
+    #[serde(default)]
+    email_verified: Option<bool>,
+    #[serde(default)]
+    groups: Vec<String>,
+}
+
+/// Client side of the login: discovery, started logins, and the checks of
+/// what comes back.
+pub(crate) struct Oidc {
+    cfg: OidcConfig,
+    http: ureq::Agent,
+    /// Fetched at the first login, so a provider that is down at startup
+    /// does not keep the bridge from starting
+    provider: Mutex<Option<Provider>>,
+    /// Started logins by their `state`
+    pending: Mutex<HashMap<String, PendingLogin>>,
+}
+
+fn same_issuer(a: &str, b: &str) -> bool {
+    a.trim_end_matches('/') == b.trim_end_matches('/')
+}
+
+impl Oidc {
+    pub(crate) fn new(cfg: OidcConfig) -> Self {
+        let http = ureq::Agent::config_builder()
+            .timeout_global(Some(HTTP_TIMEOUT))
+            .build()
+            .into();
+        Self {
+            cfg,
+            http,
+            provider: Mutex::new(None),
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn get_json(&self, url: &str) -> Result<Value, OidcError> {
+        let body = self
+            .http
+            .get(url)
+            .call()
+            .map_err(|e| OidcError::Provider(format!("{url}: {e}")))?
+            .into_body()
+            .read_to_string()
+            .map_err(|e| OidcError::Provider(format!("{url}: {e}")))?;
+        serde_json::from_str(&body).map_err(|e| OidcError::Provider(format!("{url}: {e}")))
+    }
+
+    /// The provider's endpoints, discovered once. Blocking.
+    fn provider(&self) -> Result<Provider, OidcError> {
+        if let Some(provider) = self.provider.lock().expect("oidc lock poisoned").clone() {
+            return Ok(provider);
+        }
+        let url = format!(
+            "{}/.well-known/openid-configuration",
+            self.cfg.issuer_url.trim_end_matches('/')
+        );
+        let provider: Provider = serde_json::from_value(self.get_json(&url)?)
+            .map_err(|e| OidcError::Provider(format!("{url}: {e}")))?;
+        if !same_issuer(&provider.issuer, &self.cfg.issuer_url) {
+            return Err(OidcError::Provider(format!(
+                "{url} names the issuer {}",
+                provider.issuer
+            )));
+        }
+        *self.provider.lock().expect("oidc lock poisoned") = Some(provider.clone());
+        Ok(provider)
+    }
+
+    /// Start a login: its `state` and the provider URL to send the browser
+    /// to. Blocking.
+    pub(crate) fn start(&self, now: Instant) -> Result<(String, String), OidcError> {
+        let provider = self.provider()?;
+        let state = Uuid::new_v4().simple().to_string();
+        let nonce = Uuid::new_v4().simple().to_string();
+        let verifier = format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+        let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()));
+
+        let mut url = Url::parse(&provider.authorization_endpoint)
+            .map_err(|e| OidcError::Provider(format!("authorization_endpoint: {e}")))?;
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &self.cfg.client_id)
+            .append_pair("redirect_uri", &self.cfg.redirect_url)
+            .append_pair("scope", &self.cfg.scopes)
+            .append_pair("state", &state)
+            .append_pair("nonce", &nonce)
+            .append_pair("code_challenge", &challenge)
+            .append_pair("code_challenge_method", "S256");
+
+        let mut pending = self.pending.lock().expect("oidc lock poisoned");
+        pending.retain(|_, login| now.duration_since(login.started) < LOGIN_TIMEOUT);
+        if pending.len() >= MAX_PENDING
+            && let Some(oldest) = pending
+                .iter()
+                .min_by_key(|(_, login)| login.started)
+                .map(|(state, _)| state.clone())
+        {
+            pending.remove(&oldest);
+        }
+        pending.insert(
+            state.clone(),
+            PendingLogin {
+                nonce,
+                verifier,
+                started: now,
+            },
+        );
+        Ok((state, url.into()))
+    }
+
+    /// Finish the login `state` with the `code` the provider sent back: the
+    /// identity of the user, their verified e-mail address or else their
+    /// subject. Blocking.
+    pub(crate) fn finish(
+        &self,
+        state: &str,
+        code: &str,
+        now: Instant,
+        unix_now: i64,
+    ) -> Result<String, OidcError> {
+        let login = self
+            .pending
+            .lock()
+            .expect("oidc lock poisoned")
+            .remove(state)
+            .filter(|login| now.duration_since(login.started) < LOGIN_TIMEOUT)
+            .ok_or(OidcError::UnknownLogin)?;
+        let provider = self.provider()?;
+
+        let body = self
+            .http
+            .post(&provider.token_endpoint)
+            .send_form([
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", &self.cfg.redirect_url),
+                ("client_id", &self.cfg.client_id),
+                ("client_secret", &self.cfg.client_secret),
+                ("code_verifier", &login.verifier),
+            ])
+            .map_err(|e| OidcError::Refused(format!("token endpoint: {e}")))?
+            .into_body()
+            .read_to_string()
+            .map_err(|e| OidcError::Provider(format!("token endpoint: {e}")))?;
+        let tokens: Value = serde_json::from_str(&body)
+            .map_err(|e| OidcError::Provider(format!("token endpoint: {e}")))?;
+        let id_token = tokens["id_token"]
+            .as_str()
+            .ok_or(OidcError::InvalidToken("the token endpoint sent none"))?;
+
+        let claims = self.check_id_token(&provider, id_token, &login.nonce, unix_now)?;
+        self.identity(claims)
+    }
+
+    fn check_id_token(
+        &self,
+        provider: &Provider,
+        id_token: &str,
+        nonce: &str,
+        unix_now: i64,
+    ) -> Result<Claims, OidcError> {
+        let payload = id_token
+            .split('.')
+            .nth(1)
+            .and_then(|part| URL_SAFE_NO_PAD.decode(part.trim_end_matches('=')).ok())
+            .ok_or(OidcError::InvalidToken("not a JWT"))?;
+        let claims: Claims = serde_json::from_slice(&payload)
+            .map_err(|_| OidcError::InvalidToken("missing claims"))?;
+        if !same_issuer(&claims.iss, &provider.issuer) {
+            return Err(OidcError::InvalidToken("issued by someone else"));
+        }
+        let audience_ok = match &claims.aud {
+            Value::String(aud) => *aud == self.cfg.client_id,
+            Value::Array(auds) => auds.iter().any(|aud| *aud == *self.cfg.client_id),
+            _ => false,
+        };
+        if !audience_ok {
+            return Err(OidcError::InvalidToken("issued for another client"));
+        }
+        if claims.exp <= unix_now {
+            return Err(OidcError::InvalidToken("expired"));
+        }
+        if claims.nonce.as_deref() != Some(nonce) {
+            return Err(OidcError::InvalidToken("nonce mismatch"));
+        }
+        Ok(claims)
+    }
+
+    /// The name a user logs in as, if `OIDC_ALLOWED_EMAILS` or
+    /// `OIDC_ALLOWED_GROUPS` lets them in.
+    fn identity(&self, claims: Claims) -> Result<String, OidcError> {
+        let email = claims
+            .email
+            .filter(|_| claims.email_verified != Some(false))
+            .map(|email| email.trim().to_ascii_lowercase());
+        let name = email.clone().unwrap_or_else(|| claims.sub.clone());
+        let by_email = email
+            .as_ref()
+            .is_some_and(|email| self.cfg.allowed_emails.contains(email));
+        let by_group = claims
+            .groups
+            .iter()
+            .any(|group| self.cfg.allowed_groups.contains(group));
+        if by_email || by_group {
+            Ok(name)
+        } else {
+            Err(OidcError::NotAllowed(name))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn oidc() -> Oidc {
+        Oidc::new(OidcConfig {
+            issuer_url: "https://sso.example.org/".into(),
+            client_id: "gitbridge".into(),
+            client_secret: "s3cret".into(),
+            redirect_url: "https://git.example.org/admin/api/login/oidc/callback".into(),
+            scopes: "openid email".into(),
+            allowed_groups: vec!["latex-admins".into()],
+            allowed_emails: vec!["ada@example.org".into()],
+        })
+    }
+
+    fn provider() -> Provider {
+        Provider {
+            issuer: "https://sso.example.org".into(),
+            authorization_endpoint: "https://sso.example.org/auth".into(),
+            token_endpoint: "https://sso.example.org/token".into(),
+        }
+    }
+
+    fn id_token(claims: Value) -> String {
+        let part = |v: Value| URL_SAFE_NO_PAD.encode(v.to_string());
+        format!("{}.{}.sig", part(json!({"alg": "RS256"})), part(claims))
+    }
+
+    #[test]
+    fn logins_start_with_pkce_and_id_tokens_are_checked() {
+        let oidc = oidc();
+        *oidc.provider.lock().unwrap() = Some(provider());
+        let now = Instant::now();
+        let (state, url) = oidc.start(now).unwrap();
+        let url = Url::parse(&url).unwrap();
+        let query: HashMap<_, _> = url.query_pairs().into_owned().collect();
+        assert_eq!(query["state"], state);
+        assert_eq!(query["code_challenge_method"], "S256");
+        let login = oidc.pending.lock().unwrap().remove(&state).unwrap();
+        assert_eq!(
+            query["code_challenge"],
+            URL_SAFE_NO_PAD.encode(Sha256::digest(login.verifier.as_bytes()))
+        );
+        assert_eq!(query["nonce"], login.nonce);
+
+        // an unknown or timed-out state is refused before the provider is asked
+        assert!(matches!(
+            oidc.finish(&state, "code", now, 0),
+            Err(OidcError::UnknownLogin)
+        ));
+        let (state, _) = oidc.start(now).unwrap();
+        let late = now + LOGIN_TIMEOUT;
+        assert!(matches!(
+            oidc.finish(&state, "code", late, 0),
+            Err(OidcError::UnknownLogin)
+        ));
+
+        let claims = json!({
+            "iss": "https://sso.example.org/", "aud": ["gitbridge", "other"], "exp": 2000,
+            "nonce": "n1", "sub": "u-17", "email": "Ada@Example.org",
+        });
+        let check = |claims: &Value, nonce| {
+            oidc.check_id_token(&provider(), &id_token(claims.clone()), nonce, 1000)
+        };
+        let checked = check(&claims, "n1").unwrap();
+        assert_eq!(oidc.identity(checked).unwrap(), "ada@example.org");
+        assert!(matches!(
+            check(&claims, "n2"),
+            Err(OidcError::InvalidToken("nonce mismatch"))
+        ));
+        for (claim, value) in [
+            ("iss", json!("https://evil.example.org")),
+            ("aud", json!("other")),
+            ("exp", json!(1000)),
+        ] {
+            let mut bad = claims.clone();
+            bad[claim] = value;
+            assert!(check(&bad, "n1").is_err(), "{claim}");
+        }
+        assert!(
+            oidc.check_id_token(&provider(), "garbage", "n1", 1000)
+                .is_err()
+        );
+
+        // unverified addresses do not count, groups do
+        let mut unverified = claims.clone();
+        unverified["email_verified"] = json!(false);
+        let err = oidc
+            .identity(check(&unverified, "n1").unwrap())
+            .unwrap_err();
+        assert_eq!(err.to_string(), "u-17 is not allowed to log in");
+        unverified["groups"] = json!(["latex-admins"]);
+        let identity = oidc.identity(check(&unverified, "n1").unwrap()).unwrap();
+        assert_eq!(identity, "u-17");
+    }
+}
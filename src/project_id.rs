@@ -0,0 +1,468 @@
+//! Validated project ids. Every project id taken from a request, the CLI or
+//! a directory listing is parsed into a [`ProjectId`] before it is used, so
+//! mirror and source paths are only ever built from ids that are a single,
+//! harmless path component.
+
+use crate::AppState;
+use axum::body::Body;
+use axum::extract::{FromRef, FromRequestParts, Path};
+use axum::http::request::Parts;
+use axum::http::{StatusCode, header};
+use axum::response::Response;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::ops::Deref;
+use std::str::FromStr;
+use std::sync::Arc;
+use thiserror::Error;
+
+/// Longest accepted id. ShareLatex ids are 24 hex digits; the rest leaves
+/// room for `-<suffix>` directory names.
+pub const MAX_PROJECT_ID_LEN: usize = 128;
+
+/// Hex digits of a ShareLatex project id (a MongoDB ObjectId).
+const SHARELATEX_ID_LEN: usize = 24;
+
+/// Whether `id` (already known to consist of allowed characters) is 24
+/// lowercase hex digits, optionally followed by a non-empty `-<suffix>`.
+fn is_sharelatex_id(id: &str) -> bool {
+    let (hex, suffix) = id.split_at_checked(SHARELATEX_ID_LEN).unwrap_or((id, ""));
+    hex.len() == SHARELATEX_ID_LEN
+        && hex.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+        && (suffix.is_empty() || suffix.len() > 1 && suffix.starts_with('-'))
+}
+
+/// Names of the bridge's own files and directories in `git_root` and the
+/// projects directory, which are never project ids.
+const RESERVED: &[&str] = &[
+    "access.jsonl",
+    "activity.jsonl",
+    "audit.jsonl",
+    "deploy-tokens.json",
+    "maintenance.json",
+    "project-settings.json",
+    "projects.list",
+    "repos",
+    "shared-objects",
+    "state",
+    "token-usage.json",
+    "tokens.json",
+];
+
+/// Whether `id` is, or starts like, one of the [`RESERVED`] names: anything
+/// up to the first `.` naming the same thing (`tokens`, `tokens.json.git`,
+/// `tokens.json.tmp`) could end up next to those files in a flat `git_root`.
+fn is_reserved(id: &str) -> bool {
+    let stem = |name: &str| name.split('.').next().unwrap_or(name).to_ascii_lowercase();
+    let id = stem(id);
+    RESERVED.iter().any(|name| stem(name) == id)
+}
+
+/// Why a string is not a project id.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum InvalidProjectId {
+    #[error("project id is empty")]
+    Empty,
+
+    #[error("project id is longer than {MAX_PROJECT_ID_LEN} characters")]
+    TooLong,
+
+    #[error("project id may only contain ASCII letters, digits, '-', '_' and '.'")]
+    Charset,
+
+    #[error("project id may not start with '.' or contain '..'")]
+    Dots,
+
+    #[error("'{0}' is reserved")]
+    Reserved(String),
+
+    #[error("project id is not a ShareLatex id (24 hex digits, optionally followed by -<suffix>)")]
+    Format,
+}
+
+/// Check `id` against the rules of [`ProjectId::new`]; `sharelatex_only`
+/// additionally requires [`is_sharelatex_id`].
+fn validate(id: &str, sharelatex_only: bool) -> Result<(), InvalidProjectId> {
+    if id.is_empty() {
+        return Err(InvalidProjectId::Empty);
+    }
+    if id.len() > MAX_PROJECT_ID_LEN {
+        return Err(InvalidProjectId::TooLong);
+    }
+    if !id
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c))
+    {
+        return Err(InvalidProjectId::Charset);
+    }
+    if id.starts_with('.') || id.contains("..") {
+        return Err(InvalidProjectId::Dots);
+    }
+    if is_reserved(id) {
+        return Err(InvalidProjectId::Reserved(id.to_string()));
+    }
+    if sharelatex_only && !is_sharelatex_id(id) {
+        return Err(InvalidProjectId::Format);
+    }
+    Ok(())
+}
+
+/// A project id that is safe to use as a single path component (ShareLatex
+/// ids are hex, prefixed directories add `-<suffix>`).
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct ProjectId(String);
+
+impl ProjectId {
+    /// Validate `id`. Whether it also has to be a ShareLatex id
+    /// (`STRICT_PROJECT_IDS`) is up to [`crate::Config::parse_project_id`].
+    pub fn new(id: impl Into<String>) -> Result<Self, InvalidProjectId> {
+        Self::with_format(id, false)
+    }
+
+    /// Validate `id`; with `sharelatex_only`, it also has to be a ShareLatex
+    /// id, optionally followed by `-<suffix>`.
+    pub fn with_format(
+        id: impl Into<String>,
+        sharelatex_only: bool,
+    ) -> Result<Self, InvalidProjectId> {
+        let id = id.into();
+        validate(&id, sharelatex_only)?;
+        Ok(Self(id))
+    }
+
+    /// Whether this is a ShareLatex id, optionally followed by `-<suffix>`.
+    pub fn is_sharelatex(&self) -> bool {
+        is_sharelatex_id(&self.0)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for ProjectId {
+    type Err = InvalidProjectId;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::new(s)
+    }
+}
+
+impl TryFrom<String> for ProjectId {
+    type Error = InvalidProjectId;
+
+    fn try_from(id: String) -> Result<Self, Self::Error> {
+        Self::new(id)
+    }
+}
+
+impl From<ProjectId> for String {
+    fn from(id: ProjectId) -> Self {
+        id.0
+    }
+}
+
+impl Deref for ProjectId {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for ProjectId {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<std::path::Path> for ProjectId {
+    fn as_ref(&self) -> &std::path::Path {
+        self.0.as_ref()
+    }
+}
+
+impl PartialEq<str> for ProjectId {
+    fn eq(&self, other: &str) -> bool {
+        self.0 == other
+    }
+}
+
+impl PartialEq<&str> for ProjectId {
+    fn eq(&self, other: &&str) -> bool {
+        self.0 == *other
+    }
+}
+
+impl fmt::Display for ProjectId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Extracts the `{id}` path parameter; malformed ids, and with
+/// `STRICT_PROJECT_IDS` ids that are no ShareLatex ids, are answered with
+/// `400 {"error": "invalid project id"}` before the handler runs.
+impl<S> FromRequestParts<S> for ProjectId
+where
+    S: Send + Sync,
+    Arc<AppState>: FromRef<S>,
+{
+    type Rejection = Response<Body>;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Path(id) = Path::<String>::from_request_parts(parts, state)
+            .await
+            .map_err(|_| invalid_project_id())?;
+        Arc::<AppState>::from_ref(state)
+            .cfg
+            .parse_project_id(&id)
+            .map_err(|_| invalid_project_id())
+    }
+}
+
+fn invalid_project_id() -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::BAD_REQUEST)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(
+            serde_json::json!({"error": "invalid project id"}).to_string(),
+        ))
+        .expect("invalid project id response")
+}
+
+/// A project id for tests, which only use valid ones.
+#[cfg(test)]
+pub(crate) fn pid(id: &str) -> ProjectId {
+    ProjectId::new(id).expect("valid test project id")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Config;
+    use axum::Router;
+    use axum::http::Request;
+    use axum::routing::get;
+    use std::path::Component;
+    use tower::ServiceExt;
+
+    /// Deterministic xorshift, enough to throw many odd strings at the parser.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+    }
+
+    fn random_id(rng: &mut Rng) -> String {
+        const PIECES: &[&str] = &[
+            "a",
+            "Z",
+            "0",
+            "f",
+            "-",
+            "_",
+            ".",
+            "..",
+            "/",
+            "\\",
+            "\0",
+            " ",
+            "%2e",
+            "\n",
+            "é",
+            "🦀",
+            ":",
+            ".git",
+            "tokens.json",
+            "5f1a2b3c4d5e6f7a8b9c0d1e",
+        ];
+        let len = match rng.below(10) {
+            0 => 0,
+            1 => 60 + rng.below(20),
+            _ => rng.below(8),
+        };
+        (0..len).map(|_| PIECES[rng.below(PIECES.len())]).collect()
+    }
+
+    #[test]
+    fn accepts_sharelatex_ids_and_prefixed_directories() {
+        for id in [
+            "5f1a2b3c4d5e6f7a8b9c0d1e",
+            "5f1a2b3c4d5e6f7a8b9c0d1e-thesis",
+            "a.b_c",
+            "tokens-2024.thesis",
+        ] {
+            assert_eq!(id.parse::<ProjectId>().unwrap().as_str(), id);
+        }
+        let longest = "a".repeat(MAX_PROJECT_ID_LEN);
+        assert!(ProjectId::new(longest).is_ok());
+    }
+
+    #[test]
+    fn rejects_unsafe_and_reserved_ids() {
+        let cases = [
+            ("", InvalidProjectId::Empty),
+            ("..", InvalidProjectId::Dots),
+            (".hidden", InvalidProjectId::Dots),
+            ("a..b", InvalidProjectId::Dots),
+            ("a/b", InvalidProjectId::Charset),
+            ("/etc/passwd", InvalidProjectId::Charset),
+            ("..\\..\\etc", InvalidProjectId::Charset),
+            ("a\\b", InvalidProjectId::Charset),
+            ("a\0b", InvalidProjectId::Charset),
+            ("C:x", InvalidProjectId::Charset),
+            ("a b", InvalidProjectId::Charset),
+            (
+                "tokens.json",
+                InvalidProjectId::Reserved("tokens.json".into()),
+            ),
+            (
+                "Shared-Objects",
+                InvalidProjectId::Reserved("Shared-Objects".into()),
+            ),
+            ("tokens", InvalidProjectId::Reserved("tokens".into())),
+            (
+                "tokens.json.tmp",
+                InvalidProjectId::Reserved("tokens.json.tmp".into()),
+            ),
+            ("Repos", InvalidProjectId::Reserved("Repos".into())),
+            ("state.git", InvalidProjectId::Reserved("state.git".into())),
+        ];
+        for (id, why) in cases {
+            assert_eq!(ProjectId::new(id), Err(why), "{id:?}");
+        }
+        let too_long = "a".repeat(MAX_PROJECT_ID_LEN + 1);
+        assert_eq!(ProjectId::new(too_long), Err(InvalidProjectId::TooLong));
+    }
+
+    #[test]
+    fn strict_ids_are_sharelatex_ids_with_optional_suffix() {
+        for id in [
+            "5f1a2b3c4d5e6f7a8b9c0d1e",
+            "5f1a2b3c4d5e6f7a8b9c0d1e-thesis",
+            "5f1a2b3c4d5e6f7a8b9c0d1e-64a1f0c2e4b0a1b2c3d4e5f6",
+        ] {
+            assert_eq!(validate(id, true), Ok(()), "{id}");
+        }
+        for id in [
+            "p1",
+            "a.b_c",
+            "5f1a2b3c4d5e6f7a8b9c0d1",
+            "5f1a2b3c4d5e6f7a8b9c0d1e0",
+            "5F1A2B3C4D5E6F7A8B9C0D1E",
+            "5f1a2b3c4d5e6f7a8b9c0d1g",
+            "5f1a2b3c4d5e6f7a8b9c0d1e-",
+            "5f1a2b3c4d5e6f7a8b9c0d1e.git",
+            "5f1a2b3c4d5e6f7a8b9c0d1e_x",
+        ] {
+            assert_eq!(validate(id, true), Err(InvalidProjectId::Format), "{id}");
+            assert_eq!(validate(id, false), Ok(()), "{id}");
+        }
+        // the other rules come first
+        assert_eq!(
+            validate("../5f1a2b3c4d5e6f7a8b9c0d1e", true),
+            Err(InvalidProjectId::Charset)
+        );
+        assert_eq!(
+            validate("5f1a2b3c4d5e6f7a8b9c0d1e-a..b", true),
+            Err(InvalidProjectId::Dots)
+        );
+        assert_eq!(
+            validate(&format!("{}-{}", "a".repeat(24), "b".repeat(104)), true),
+            Err(InvalidProjectId::TooLong)
+        );
+    }
+
+    #[test]
+    fn random_strings_parse_only_into_single_path_components() {
+        let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+        let mut accepted = 0;
+        for _ in 0..20_000 {
+            let raw = random_id(&mut rng);
+            let Ok(id) = raw.parse::<ProjectId>() else {
+                continue;
+            };
+            accepted += 1;
+            assert_eq!(id.as_str(), raw);
+            assert!(id.len() <= MAX_PROJECT_ID_LEN);
+            let mut components = std::path::Path::new(id.as_str()).components();
+            assert_eq!(
+                components.next(),
+                Some(Component::Normal(raw.as_ref())),
+                "{raw:?}"
+            );
+            assert_eq!(components.next(), None, "{raw:?}");
+            assert!(!is_reserved(&raw));
+
+            // every way back in gives the same id
+            assert_eq!(id.to_string().parse::<ProjectId>().unwrap(), id);
+            let json = serde_json::to_string(&id).unwrap();
+            assert_eq!(serde_json::from_str::<ProjectId>(&json).unwrap(), id);
+        }
+        // the generator reaches both sides of the parser
+        assert!(accepted > 1_000 && accepted < 19_000, "{accepted}");
+    }
+
+    #[test]
+    fn deserializing_validates() {
+        assert!(serde_json::from_str::<ProjectId>(r#""../etc""#).is_err());
+        assert!(serde_json::from_str::<ProjectId>(r#""p1""#).is_ok());
+    }
+
+    fn id_app(strict: bool) -> (tempfile::TempDir, Router) {
+        let root = tempfile::tempdir().unwrap();
+        let mut cfg = Config::for_test(root.path());
+        cfg.strict_project_ids = strict;
+        let app = Router::new()
+            .route(
+                "/projects/{id}",
+                get(|id: ProjectId| async move { id.to_string() }),
+            )
+            .with_state(Arc::new(AppState::new(cfg)));
+        (root, app)
+    }
+
+    async fn status(app: &Router, uri: &str) -> StatusCode {
+        app.clone()
+            .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+            .status()
+    }
+
+    #[tokio::test]
+    async fn extractor_rejects_bad_ids_before_the_handler() {
+        let (_root, app) = id_app(false);
+        assert_eq!(status(&app, "/projects/p1").await, StatusCode::OK);
+        for uri in [
+            "/projects/%2E%2E",
+            "/projects/tokens.json",
+            "/projects/a%2Fb",
+        ] {
+            assert_eq!(status(&app, uri).await, StatusCode::BAD_REQUEST, "{uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn extractor_applies_strict_project_ids_from_the_config() {
+        let (_root, app) = id_app(true);
+        assert_eq!(status(&app, "/projects/p1").await, StatusCode::BAD_REQUEST);
+        for uri in [
+            "/projects/64a1f0c2e4b0a1b2c3d4e5f6",
+            "/projects/64a1f0c2e4b0a1b2c3d4e5f6-thesis",
+        ] {
+            assert_eq!(status(&app, uri).await, StatusCode::OK, "{uri}");
+        }
+    }
+}
@@ -0,0 +1,185 @@
+use crate::config::Config;
+use crate::error::BridgeError;
+use crate::project_id::ProjectId;
+use crate::repo::PROJECT_CONFIG_FILE;
+use crate::source::ProjectSource;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::Path;
+use tracing::warn;
+
+/// Per-project settings managed through the admin API.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProjectSettings {
+    /// Secret for `/hooks/sync/<id>`, overriding `.gitbridge.toml` and `WEBHOOK_SECRET`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub webhook_secret: Option<String>,
+    /// Human-readable name shown in the admin UI and available to the commit
+    /// message template as `{project_alias}`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alias: Option<String>,
+    /// Fetchable without a token, over HTTP and the `git://` listener
+    /// (`GIT_DAEMON_PORT`)
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub public: bool,
+}
+
+impl ProjectSettings {
+    fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+/// On-disk format of `project-settings.json`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ProjectSettingsFile {
+    #[serde(default)]
+    pub projects: BTreeMap<String, ProjectSettings>,
+}
+
+impl ProjectSettingsFile {
+    pub fn get(&self, project_id: &str) -> ProjectSettings {
+        self.projects.get(project_id).cloned().unwrap_or_default()
+    }
+
+    /// Modify the settings of one project, dropping the entry once it is empty.
+    pub fn update(&mut self, project_id: &str, f: impl FnOnce(&mut ProjectSettings)) {
+        let entry = self.projects.entry(project_id.to_string()).or_default();
+        f(entry);
+        if entry.is_empty() {
+            self.projects.remove(project_id);
+        }
+    }
+}
+
+/// What made a project public.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum PublicSource {
+    /// Set through the admin API (`project-settings.json`)
+    Admin,
+    /// `public = true` in the project's `.gitbridge.toml`
+    ProjectFile,
+}
+
+#[derive(Deserialize)]
+struct ProjectAccessFile {
+    #[serde(default)]
+    public: bool,
+}
+
+/// Whether anyone may fetch `project_id` without a token, and why. Both
+/// sources are read on every call, so changes apply immediately. Blocking.
+pub(crate) fn public_source(
+    settings: &ProjectSettings,
+    source: &dyn ProjectSource,
+    project_id: &ProjectId,
+) -> Option<PublicSource> {
+    if settings.public {
+        return Some(PublicSource::Admin);
+    }
+    let mut content = String::new();
+    source
+        .read(project_id, Path::new(PROJECT_CONFIG_FILE))
+        .and_then(|mut file| Ok(file.read_to_string(&mut content)?))
+        .ok()?;
+    match toml::from_str::<ProjectAccessFile>(&content) {
+        Ok(file) => file.public.then_some(PublicSource::ProjectFile),
+        Err(e) => {
+            warn!(%project_id, error = %e, "ignoring unparsable {PROJECT_CONFIG_FILE}");
+            None
+        }
+    }
+}
+
+/// Read `project-settings.json`; a missing file yields no settings.
+pub fn load_project_settings(cfg: &Config) -> Result<ProjectSettingsFile, BridgeError> {
+    let path = cfg.project_settings_file();
+    match fs::read_to_string(&path) {
+        Ok(data) => Ok(serde_json::from_str(&data)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(ProjectSettingsFile::default()),
+        Err(e) => Err(BridgeError::Other(format!(
+            "cannot read {}: {e}",
+            path.display()
+        ))),
+    }
+}
+
+/// Write `project-settings.json` via a temp file and rename.
+pub fn save_project_settings(cfg: &Config, file: &ProjectSettingsFile) -> Result<(), BridgeError> {
+    let serialized = serde_json::to_string_pretty(file)?;
+    let path = cfg.project_settings_file();
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let tmp_path = path.with_extension("tmp");
+    {
+        let mut f = fs::File::create(&tmp_path)?;
+        f.write_all(serialized.as_bytes())?;
+        f.sync_all()?;
+    }
+    fs::rename(&tmp_path, &path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::project_id::pid;
+    use crate::source::FsProjectSource;
+    use tempfile::TempDir;
+
+    #[test]
+    fn round_trip_drops_empty_entries() {
+        let dir = TempDir::new().unwrap();
+        let cfg = Config::for_test(dir.path());
+        fs::create_dir_all(&cfg.git_root).unwrap();
+
+        let mut file = load_project_settings(&cfg).unwrap();
+        file.update("p1", |s| s.webhook_secret = Some("s3cret".into()));
+        file.update("p2", |s| s.webhook_secret = None);
+        file.update("p3", |s| s.public = true);
+        save_project_settings(&cfg, &file).unwrap();
+
+        let loaded = load_project_settings(&cfg).unwrap();
+        assert_eq!(loaded.get("p1").webhook_secret.as_deref(), Some("s3cret"));
+        assert!(!loaded.projects.contains_key("p2"));
+        assert!(loaded.get("p3").public);
+        assert!(!loaded.get("p1").public);
+    }
+
+    #[test]
+    fn public_flag_comes_from_settings_or_project_file() {
+        let dir = TempDir::new().unwrap();
+        let cfg = Config::for_test(dir.path());
+        let source = FsProjectSource::new(cfg.clone());
+        for (id, toml) in [
+            ("open", "public = true\n"),
+            ("closed", "public = false\n"),
+            ("broken", "public = \"yes\"\n"),
+        ] {
+            let project = cfg.project_source_dir(&pid(id)).unwrap();
+            fs::create_dir_all(&project).unwrap();
+            fs::write(project.join(PROJECT_CONFIG_FILE), toml).unwrap();
+        }
+
+        let none = ProjectSettings::default();
+        let admin = ProjectSettings {
+            public: true,
+            ..ProjectSettings::default()
+        };
+        assert_eq!(
+            public_source(&none, &source, &pid("open")),
+            Some(PublicSource::ProjectFile)
+        );
+        assert_eq!(public_source(&none, &source, &pid("closed")), None);
+        assert_eq!(public_source(&none, &source, &pid("broken")), None);
+        assert_eq!(public_source(&none, &source, &pid("missing")), None);
+        assert_eq!(
+            public_source(&admin, &source, &pid("closed")),
+            Some(PublicSource::Admin)
+        );
+    }
+}
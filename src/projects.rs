@@ -0,0 +1,267 @@
+use crate::config::Config;
+use crate::error::BridgeError;
+use crate::git::{GitRunner, SystemGit};
+use crate::metadata::load_metadata;
+use crate::project_id::ProjectId;
+use crate::project_settings::{ProjectSettingsFile, PublicSource, public_source};
+use crate::repo::{is_external_repo, list_mirrors};
+use crate::source::ProjectSource;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::BTreeSet;
+use tracing::debug;
+
+/// Longest alias accepted through the admin API.
+pub(crate) const MAX_ALIAS_LEN: usize = 100;
+
+/// One row of the admin projects listing.
+#[derive(Debug, Serialize)]
+pub(crate) struct ProjectSummary {
+    pub(crate) id: ProjectId,
+    pub(crate) alias: Option<String>,
+    pub(crate) source_exists: bool,
+    pub(crate) mirror_exists: bool,
+    pub(crate) last_sync_at: Option<DateTime<Utc>>,
+    /// When a git client last fetched the mirror
+    pub(crate) last_fetch_at: Option<DateTime<Utc>>,
+    pub(crate) last_error: Option<String>,
+    /// The last integrity check of the mirror failed
+    pub(crate) integrity_failed: bool,
+    /// A hand-maintained repo served as-is, not an Overleaf project
+    pub(crate) external: bool,
+    /// Fetchable without a token
+    pub(crate) public: bool,
+    /// Whether the admin API or `.gitbridge.toml` made the project public
+    pub(crate) public_source: Option<PublicSource>,
+    /// Commit at the mirror's `HEAD`; only filled in by [`add_details`]
+    pub(crate) last_commit: Option<String>,
+    pub(crate) last_commit_at: Option<DateTime<Utc>>,
+    /// Modification time of the project's source directory; only filled in
+    /// by [`add_details`]
+    pub(crate) source_modified_at: Option<DateTime<Utc>>,
+}
+
+/// Order of the admin projects listing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) enum ProjectSort {
+    #[default]
+    Id,
+    /// Most recently fetched first; never fetched last
+    LastFetch,
+}
+
+impl ProjectSort {
+    pub(crate) fn parse(raw: &str) -> Option<Self> {
+        match raw {
+            "id" => Some(Self::Id),
+            "last_fetch" => Some(Self::LastFetch),
+            _ => None,
+        }
+    }
+}
+
+/// Reorder a listing built by [`collect_projects`] (which sorts by id).
+pub(crate) fn sort_projects(projects: &mut [ProjectSummary], sort: ProjectSort) {
+    if sort == ProjectSort::LastFetch {
+        projects.sort_by_key(|p| std::cmp::Reverse(p.last_fetch_at));
+    }
+}
+
+/// Trimmed alias, or `None` if it is empty, too long, or contains control characters.
+pub(crate) fn normalize_alias(raw: &str) -> Option<String> {
+    let alias = raw.trim();
+    let ok = !alias.is_empty()
+        && alias.chars().count() <= MAX_ALIAS_LEN
+        && !alias.chars().any(char::is_control);
+    ok.then(|| alias.to_string())
+}
+
+/// Every project known to the source, the mirrors, or the settings file,
+/// optionally filtered by a case-insensitive substring of id or alias.
+/// Projects that only have settings left (source and mirror gone) are
+/// included so their alias can still be seen and removed.
+pub(crate) fn collect_projects(
+    cfg: &Config,
+    source: &dyn ProjectSource,
+    settings: &ProjectSettingsFile,
+    query: Option<&str>,
+) -> Result<Vec<ProjectSummary>, BridgeError> {
+    let sources: BTreeSet<ProjectId> = source.list_projects()?.into_iter().collect();
+    let mirrors: BTreeSet<ProjectId> = list_mirrors(cfg)?.into_iter().collect();
+    let ids: BTreeSet<ProjectId> = sources
+        .iter()
+        .chain(mirrors.iter())
+        .cloned()
+        .chain(
+            settings
+                .projects
+                .keys()
+                .filter_map(|id| ProjectId::new(id.as_str()).ok()),
+        )
+        .collect();
+
+    let query = query
+        .map(|q| q.trim().to_lowercase())
+        .filter(|q| !q.is_empty());
+
+    Ok(ids
+        .into_iter()
+        .filter_map(|id| {
+            let project = settings.get(&id);
+            let alias = project.alias.clone();
+            if let Some(q) = &query {
+                let hit = id.to_lowercase().contains(q)
+                    || alias.as_ref().is_some_and(|a| a.to_lowercase().contains(q));
+                if !hit {
+                    return None;
+                }
+            }
+            let mirror_exists = mirrors.contains(&id);
+            let meta = if mirror_exists {
+                load_metadata(cfg, &id)
+            } else {
+                Default::default()
+            };
+            let public_source = public_source(&project, source, &id);
+            Some(ProjectSummary {
+                alias,
+                source_exists: sources.contains(&id),
+                mirror_exists,
+                last_sync_at: meta.last_sync_at,
+                last_fetch_at: meta.last_fetch(),
+                last_error: meta.last_error,
+                integrity_failed: meta.integrity.is_some_and(|check| !check.ok),
+                external: mirror_exists && is_external_repo(cfg, &id),
+                public: public_source.is_some(),
+                public_source,
+                id,
+                last_commit: None,
+                last_commit_at: None,
+                source_modified_at: None,
+            })
+        })
+        .collect())
+}
+
+/// Fill in the last commit and the source's modification time of
+/// `projects`, which cost a git run and a file system lookup per project,
+/// so listings only do it for the page they return. What cannot be read is
+/// left `None`.
+pub(crate) fn add_details(
+    cfg: &Config,
+    source: &dyn ProjectSource,
+    projects: &mut [ProjectSummary],
+) {
+    for project in projects {
+        if project.mirror_exists {
+            let out = cfg
+                .bare_repo_dir(&project.id)
+                .and_then(|repo| SystemGit.run(&["log", "-1", "--format=%H %cI", "HEAD"], &repo));
+            match out {
+                Ok(out) if out.status.success() => {
+                    let out = String::from_utf8_lossy(&out.stdout);
+                    if let Some((commit, at)) = out.trim().split_once(' ') {
+                        project.last_commit = Some(commit.to_string());
+                        project.last_commit_at = DateTime::parse_from_rfc3339(at)
+                            .ok()
+                            .map(|at| at.with_timezone(&Utc));
+                    }
+                }
+                Ok(out) => debug!(
+                    project_id = %project.id,
+                    "no last commit: {}",
+                    String::from_utf8_lossy(&out.stderr).trim()
+                ),
+                Err(e) => debug!(project_id = %project.id, "no last commit: {e}"),
+            }
+        }
+        if project.source_exists {
+            project.source_modified_at = source.modified(&project.id).map(DateTime::from);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::project_id::pid;
+    use crate::source::FsProjectSource;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn listing_merges_sources_mirrors_and_orphaned_aliases() {
+        let root = TempDir::new().unwrap();
+        let cfg = Config::for_test(root.path());
+        let projects = cfg.projects_root();
+        fs::create_dir_all(projects.join("aaa-thesis")).unwrap();
+        fs::create_dir_all(projects.join("bbb")).unwrap();
+        fs::create_dir_all(cfg.bare_repo_dir(&pid("bbb")).unwrap()).unwrap();
+        fs::create_dir_all(cfg.bare_repo_dir(&pid("style")).unwrap()).unwrap();
+        fs::write(
+            cfg.bare_repo_dir(&pid("style"))
+                .unwrap()
+                .join(crate::repo::EXTERNAL_MARKER),
+            "",
+        )
+        .unwrap();
+
+        let mut settings = ProjectSettingsFile::default();
+        settings.update("bbb", |s| s.alias = Some("Lab Report".into()));
+        settings.update("zzz", |s| s.alias = Some("Removed Paper".into()));
+        settings.update("zzz", |s| s.public = true);
+        fs::write(
+            projects
+                .join("aaa-thesis")
+                .join(crate::repo::PROJECT_CONFIG_FILE),
+            "public = true\n",
+        )
+        .unwrap();
+        let source = FsProjectSource::new(cfg.clone());
+
+        let all = collect_projects(&cfg, &source, &settings, None).unwrap();
+        let ids: Vec<&str> = all.iter().map(|p| p.id.as_str()).collect();
+        assert_eq!(ids, ["aaa", "bbb", "style", "zzz"]);
+        assert!(all[1].source_exists && all[1].mirror_exists && !all[1].external);
+        assert!(all[2].external && !all[2].source_exists);
+        assert!(!all[3].source_exists && !all[3].mirror_exists);
+        let public: Vec<_> = all.iter().map(|p| p.public_source).collect();
+        assert_eq!(
+            public,
+            [
+                Some(PublicSource::ProjectFile),
+                None,
+                None,
+                Some(PublicSource::Admin)
+            ]
+        );
+
+        crate::metadata::record_fetch(&cfg, &pid("bbb")).unwrap();
+        let mut by_fetch = collect_projects(&cfg, &source, &settings, None).unwrap();
+        sort_projects(&mut by_fetch, ProjectSort::LastFetch);
+        let ids: Vec<&str> = by_fetch.iter().map(|p| p.id.as_str()).collect();
+        assert_eq!(ids, ["bbb", "aaa", "style", "zzz"]);
+
+        let hits = collect_projects(&cfg, &source, &settings, Some("report")).unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].alias.as_deref(), Some("Lab Report"));
+
+        // details are left out where they cannot be read, e.g. in the
+        // broken mirror of bbb
+        crate::repo::ensure_repo_blocking(&SystemGit, &source, &cfg, &pid("aaa")).unwrap();
+        let mut all = collect_projects(&cfg, &source, &settings, None).unwrap();
+        add_details(&cfg, &source, &mut all);
+        assert_eq!(all[0].last_commit.as_ref().map(String::len), Some(40));
+        assert!(all[0].last_commit_at.is_some() && all[0].source_modified_at.is_some());
+        assert!(all[1].last_commit.is_none() && all[1].source_modified_at.is_some());
+        assert!(all[3].last_commit.is_none() && all[3].source_modified_at.is_none());
+    }
+
+    #[test]
+    fn aliases_are_trimmed_and_bounded() {
+        assert_eq!(normalize_alias("  Thesis ").as_deref(), Some("Thesis"));
+        assert_eq!(normalize_alias("   "), None);
+        assert_eq!(normalize_alias("a\nb"), None);
+        assert_eq!(normalize_alias(&"x".repeat(MAX_ALIAS_LEN + 1)), None);
+    }
+}
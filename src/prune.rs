@@ -0,0 +1,155 @@
+use crate::config::Config;
+use crate::error::BridgeError;
+use crate::git::GitRunner;
+use crate::metadata::{dir_size, load_metadata};
+use crate::project_id::ProjectId;
+use crate::repo::{is_external_repo, list_mirrors};
+use crate::trash::remove_mirror;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::fs;
+use std::path::PathBuf;
+use tracing::info;
+
+/// A mirror no git client fetched since the cutoff.
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub(crate) struct StaleRepo {
+    pub(crate) id: ProjectId,
+    /// `None` when no fetch was ever recorded
+    pub(crate) last_fetch_at: Option<DateTime<Utc>>,
+    pub(crate) repo_bytes: u64,
+}
+
+/// Whether `project_id` was not fetched since `cutoff`. External repos are
+/// never stale. Blocking.
+pub(crate) fn is_stale(cfg: &Config, project_id: &ProjectId, cutoff: DateTime<Utc>) -> bool {
+    !is_external_repo(cfg, project_id)
+        && load_metadata(cfg, project_id)
+            .last_fetch()
+            .is_none_or(|at| at < cutoff)
+}
+
+/// Mirrors not fetched since `cutoff`, least recently fetched first.
+/// Blocking.
+pub(crate) fn find_stale(
+    cfg: &Config,
+    cutoff: DateTime<Utc>,
+) -> Result<Vec<StaleRepo>, BridgeError> {
+    let mut stale: Vec<StaleRepo> = list_mirrors(cfg)?
+        .into_iter()
+        .filter(|id| is_stale(cfg, id, cutoff))
+        .map(|id| StaleRepo {
+            last_fetch_at: load_metadata(cfg, &id).last_fetch(),
+            repo_bytes: cfg.bare_repo_dir(&id).map_or(0, |repo| dir_size(&repo)),
+            id,
+        })
+        .collect();
+    stale.sort_by(|a, b| a.last_fetch_at.cmp(&b.last_fetch_at).then(a.id.cmp(&b.id)));
+    Ok(stale)
+}
+
+/// Where a pruned mirror went.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Pruned {
+    /// Bundle written to `PRUNE_ARCHIVE_DIR`
+    pub(crate) archived: Option<PathBuf>,
+    /// Trash entry holding the mirror; `None` when it was deleted
+    pub(crate) trashed: Option<String>,
+}
+
+/// Remove the mirror of `project_id`, first saving it as a bundle in
+/// `PRUNE_ARCHIVE_DIR` if configured. The mirror goes to the trash unless
+/// `purge` is set. The next fetch recreates it from its snapshot. Callers
+/// must hold the project lock. Blocking.
+pub(crate) fn prune_repo(
+    git: &dyn GitRunner,
+    cfg: &Config,
+    project_id: &ProjectId,
+    purge: bool,
+) -> Result<Pruned, BridgeError> {
+    let mirror = cfg.bare_repo_dir(project_id)?;
+    let archived = match &cfg.prune_archive_dir {
+        Some(dir) => {
+            fs::create_dir_all(dir)?;
+            let stamp = Utc::now().format("%Y%m%dT%H%M%SZ");
+            let bundle = dir.join(format!("{project_id}-{stamp}.bundle"));
+            let bundle_arg = bundle.to_string_lossy();
+            let out = git.run(&["bundle", "create", bundle_arg.as_ref(), "--all"], &mirror)?;
+            if !out.status.success() {
+                return Err(BridgeError::GitFailed(
+                    "git bundle create".to_string(),
+                    String::from_utf8_lossy(&out.stderr).trim().to_string(),
+                ));
+            }
+            Some(bundle)
+        }
+        None => None,
+    };
+    let trashed = remove_mirror(cfg, project_id, purge)?;
+    crate::gitweb::mirror_changed(cfg, project_id);
+    info!(%project_id, archived = ?archived, trashed = ?trashed, "pruned mirror");
+    Ok(Pruned { archived, trashed })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git::mock::ScriptedGit;
+    use crate::metadata::update_metadata;
+    use crate::project_id::pid;
+    use crate::repo::EXTERNAL_MARKER;
+    use chrono::Duration;
+    use tempfile::TempDir;
+
+    #[test]
+    fn only_mirrors_fetched_before_the_cutoff_are_stale() {
+        let root = TempDir::new().unwrap();
+        let cfg = Config::for_test(root.path());
+        let now = Utc::now();
+        for (id, fetched) in [
+            ("old", Some(now - Duration::days(200))),
+            ("recent", Some(now - Duration::days(3))),
+            ("never", None),
+        ] {
+            fs::create_dir_all(cfg.bare_repo_dir(&pid(id)).unwrap()).unwrap();
+            update_metadata(&cfg, &pid(id), |meta| meta.last_fetch_at = fetched).unwrap();
+        }
+        fs::create_dir_all(cfg.bare_repo_dir(&pid("style")).unwrap()).unwrap();
+        fs::write(
+            cfg.bare_repo_dir(&pid("style"))
+                .unwrap()
+                .join(EXTERNAL_MARKER),
+            "",
+        )
+        .unwrap();
+
+        let stale = find_stale(&cfg, now - Duration::days(180)).unwrap();
+        let ids: Vec<_> = stale.iter().map(|r| r.id.as_str()).collect();
+        assert_eq!(ids, ["never", "old"]);
+    }
+
+    #[test]
+    fn pruned_mirrors_are_trashed_and_archived_when_configured() {
+        let root = TempDir::new().unwrap();
+        let mut cfg = Config::for_test(root.path());
+        fs::create_dir_all(cfg.bare_repo_dir(&pid("p1")).unwrap()).unwrap();
+        let pruned = prune_repo(&ScriptedGit::new(), &cfg, &pid("p1"), false).unwrap();
+        assert_eq!(pruned.archived, None);
+        assert!(cfg.trash_dir().join(pruned.trashed.unwrap()).is_dir());
+        assert!(!cfg.bare_repo_dir(&pid("p1")).unwrap().exists());
+
+        cfg.prune_archive_dir = Some(root.path().join("archive"));
+        fs::create_dir_all(cfg.bare_repo_dir(&pid("p1")).unwrap()).unwrap();
+        let git = ScriptedGit::new();
+        let pruned = prune_repo(&git, &cfg, &pid("p1"), true).unwrap();
+        assert!(
+            pruned
+                .archived
+                .unwrap()
+                .starts_with(root.path().join("archive"))
+        );
+        assert_eq!(pruned.trashed, None);
+        assert_eq!(git.calls()[0][..2], ["bundle", "create"]);
+        assert!(!cfg.bare_repo_dir(&pid("p1")).unwrap().exists());
+    }
+}
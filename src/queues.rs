@@ -0,0 +1,104 @@
+//! Gauges of tasks waiting for a shared resource, as a backlog signal for
+//! autoscaling and alerts. Waiters register through an RAII guard, so the
+//! numbers stay right when a waiter times out, fails or is cancelled.
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// Tasks currently waiting in one queue.
+#[derive(Default)]
+pub(crate) struct WaitQueue {
+    waiters: Mutex<Waiters>,
+}
+
+#[derive(Default)]
+struct Waiters {
+    next_id: u64,
+    /// Start of each wait, by waiter id; ids only grow, so the first entry
+    /// is the oldest waiter
+    since: BTreeMap<u64, Instant>,
+}
+
+/// A task waiting in a [`WaitQueue`]; leaves the queue when dropped.
+pub(crate) struct Waiting {
+    queue: Arc<WaitQueue>,
+    id: u64,
+}
+
+impl Drop for Waiting {
+    fn drop(&mut self) {
+        self.queue.lock().since.remove(&self.id);
+    }
+}
+
+/// Depth of a queue at one moment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub(crate) struct QueueDepth {
+    pub(crate) waiting: usize,
+    /// How long the oldest waiter has been waiting; 0 when none is
+    pub(crate) oldest_wait_ms: u64,
+}
+
+impl WaitQueue {
+    fn lock(&self) -> std::sync::MutexGuard<'_, Waiters> {
+        self.waiters.lock().expect("wait queue poisoned")
+    }
+
+    /// Join the queue until the returned guard is dropped.
+    pub(crate) fn join(self: &Arc<Self>) -> Waiting {
+        let mut waiters = self.lock();
+        let id = waiters.next_id;
+        waiters.next_id += 1;
+        waiters.since.insert(id, Instant::now());
+        Waiting {
+            queue: self.clone(),
+            id,
+        }
+    }
+
+    pub(crate) fn depth(&self) -> QueueDepth {
+        let waiters = self.lock();
+        QueueDepth {
+            waiting: waiters.since.len(),
+            oldest_wait_ms: waiters
+                .since
+                .values()
+                .next()
+                .map_or(0, |since| since.elapsed().as_millis() as u64),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn waiters_are_counted_until_dropped() {
+        let queue = Arc::new(WaitQueue::default());
+        assert_eq!(
+            queue.depth(),
+            QueueDepth {
+                waiting: 0,
+                oldest_wait_ms: 0
+            }
+        );
+
+        let first = queue.join();
+        std::thread::sleep(Duration::from_millis(20));
+        let second = queue.join();
+        let depth = queue.depth();
+        assert_eq!(depth.waiting, 2);
+        assert!(depth.oldest_wait_ms >= 20);
+
+        drop(first);
+        let depth = queue.depth();
+        assert_eq!(depth.waiting, 1);
+        assert!(depth.oldest_wait_ms < 20);
+        drop(second);
+        assert_eq!(queue.depth().waiting, 0);
+    }
+}
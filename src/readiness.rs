@@ -0,0 +1,215 @@
+//! The readiness check at `/healthz`. Unlike `/`, which answers as long as
+//! the process runs, it fails when the bridge cannot serve: `git` does not
+//! run, `GIT_ROOT` is not writable, a projects directory is missing or
+//! unreadable (a data volume that failed to mount), or `tokens.json` does
+//! not parse. Results are kept for [`CACHE_FOR`], so a flood of probes
+//! runs the checks, and forks git, once.
+
+use crate::AppState;
+use crate::auth::TokensFile;
+use crate::config::{Config, SourceBackend};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use tracing::warn;
+
+/// How long a report is answered from the cache.
+const CACHE_FOR: Duration = Duration::from_secs(5);
+
+/// The outcome of one check.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub(crate) struct Check {
+    pub(crate) ok: bool,
+    /// What was found, or why the check failed
+    pub(crate) detail: String,
+}
+
+impl Check {
+    fn from_result(result: Result<String, String>) -> Self {
+        match result {
+            Ok(detail) => Self { ok: true, detail },
+            Err(detail) => Self { ok: false, detail },
+        }
+    }
+}
+
+/// Every check by name, `ok` if all passed.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct Report {
+    pub(crate) ok: bool,
+    pub(crate) checks: BTreeMap<String, Check>,
+}
+
+/// The last report and when it was made.
+#[derive(Default)]
+pub(crate) struct Readiness {
+    last: Mutex<Option<(Instant, Report)>>,
+}
+
+impl Readiness {
+    /// The report of the last [`CACHE_FOR`], or a new one. Probes arriving
+    /// while the checks run wait for their result.
+    pub(crate) async fn report(&self, state: &AppState) -> Report {
+        let mut last = self.last.lock().await;
+        if let Some((at, report)) = &*last
+            && at.elapsed() < CACHE_FOR
+        {
+            return report.clone();
+        }
+        let cfg = state.cfg.clone();
+        let sources: Vec<Config> = state.sources.values().map(|s| s.cfg.clone()).collect();
+        let report = tokio::task::spawn_blocking(move || run_checks(&cfg, &sources))
+            .await
+            .unwrap_or_else(|e| Report {
+                ok: false,
+                checks: BTreeMap::from([(
+                    "checks".to_string(),
+                    Check {
+                        ok: false,
+                        detail: format!("checks did not finish: {e}"),
+                    },
+                )]),
+            });
+        *last = Some((Instant::now(), report.clone()));
+        report
+    }
+}
+
+/// Run every check of `cfg` and the configurations of its named sources.
+/// Blocking.
+fn run_checks(cfg: &Config, sources: &[Config]) -> Report {
+    let mut checks = BTreeMap::new();
+    checks.insert("git".to_string(), Check::from_result(check_git()));
+    checks.insert(
+        "git_root".to_string(),
+        Check::from_result(check_git_root(cfg)),
+    );
+    checks.insert("tokens".to_string(), Check::from_result(check_tokens(cfg)));
+    if cfg.source_backend == SourceBackend::Filesystem {
+        checks.insert(
+            "projects_dir".to_string(),
+            Check::from_result(check_projects_dir(cfg)),
+        );
+    }
+    for source in sources {
+        checks.insert(
+            format!("projects_dir:{}", source.source_label()),
+            Check::from_result(check_projects_dir(source)),
+        );
+    }
+    for (name, check) in checks.iter().filter(|(_, check)| !check.ok) {
+        warn!(check = %name, "readiness check failed: {}", check.detail);
+    }
+    Report {
+        ok: checks.values().all(|check| check.ok),
+        checks,
+    }
+}
+
+/// `git --version` runs.
+fn check_git() -> Result<String, String> {
+    let out = Command::new("git")
+        .arg("--version")
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| format!("cannot run git: {e}"))?;
+    if !out.status.success() {
+        return Err(format!("git --version failed with {}", out.status));
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).trim().to_string())
+}
+
+/// A file can be created in `git_root`.
+fn check_git_root(cfg: &Config) -> Result<String, String> {
+    let dir = &cfg.git_root;
+    tempfile::Builder::new()
+        .prefix(".healthz")
+        .tempfile_in(dir)
+        .map_err(|e| format!("cannot write to {}: {e}", dir.display()))?;
+    Ok(format!("{} is writable", dir.display()))
+}
+
+/// The projects directory can be listed.
+fn check_projects_dir(cfg: &Config) -> Result<String, String> {
+    let dir = cfg.projects_root();
+    fs::read_dir(&dir).map_err(|e| format!("cannot read {}: {e}", dir.display()))?;
+    Ok(format!("{} is readable", dir.display()))
+}
+
+/// `tokens.json` parses, or does not exist yet.
+fn check_tokens(cfg: &Config) -> Result<String, String> {
+    let path = cfg.tokens_file();
+    let data = match fs::read_to_string(&path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Ok("no tokens.json yet".to_string());
+        }
+        Err(e) => return Err(format!("cannot read {}: {e}", path.display())),
+    };
+    let tf: TokensFile =
+        serde_json::from_str(&data).map_err(|e| format!("cannot parse {}: {e}", path.display()))?;
+    Ok(format!("{} managed tokens", tf.managed_tokens.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::build_router;
+    use axum::body::{Body, to_bytes};
+    use axum::http::{Request, StatusCode};
+    use std::sync::Arc;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn healthz_reports_failing_checks_and_caches_them() {
+        let root = tempfile::TempDir::new().unwrap();
+        let cfg = Config::for_test(root.path());
+        fs::create_dir_all(&cfg.git_root).unwrap();
+        fs::create_dir_all(cfg.projects_root()).unwrap();
+        let app = build_router(Arc::new(AppState::new(cfg.clone())));
+        let probe = || {
+            let app = app.clone();
+            async move {
+                let req = Request::get("/healthz").body(Body::empty()).unwrap();
+                let resp = app.oneshot(req).await.unwrap();
+                let status = resp.status();
+                let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+                let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+                (status, body)
+            }
+        };
+
+        let (status, report) = probe().await;
+        assert_eq!(status, StatusCode::OK, "{report}");
+        assert_eq!(report["ok"], true);
+        for check in ["git", "git_root", "tokens", "projects_dir"] {
+            assert_eq!(report["checks"][check]["ok"], true, "{check}");
+        }
+        assert!(
+            report["checks"]["git"]["detail"]
+                .as_str()
+                .unwrap()
+                .starts_with("git version")
+        );
+
+        // a broken tokens.json and a missing volume show up once the cached
+        // report has expired
+        fs::create_dir_all(cfg.state_dir()).unwrap();
+        fs::write(cfg.tokens_file(), "{").unwrap();
+        fs::remove_dir(cfg.projects_root()).unwrap();
+        assert_eq!(probe().await.0, StatusCode::OK);
+        let report = run_checks(&cfg, &[]);
+        assert!(!report.ok);
+        assert!(report.checks["git"].ok && report.checks["git_root"].ok);
+        assert!(report.checks["tokens"].detail.contains("cannot parse"));
+        assert!(report.checks["projects_dir"].detail.contains("cannot read"));
+
+        let mut missing = cfg.clone();
+        missing.git_root = root.path().join("unmounted");
+        let check = Check::from_result(check_git_root(&missing));
+        assert!(!check.ok && check.detail.contains("unmounted"), "{check:?}");
+    }
+}
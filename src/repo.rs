@@ -1,11 +1,22 @@
-use crate::config::{Config, GIT_AUTHOR_EMAIL, GIT_AUTHOR_NAME};
+use crate::config::{Config, GIT_AUTHOR_EMAIL, GIT_AUTHOR_NAME, is_valid_git_config_key};
+use crate::eol;
 use crate::error::BridgeError;
-use chrono::Utc;
+use crate::git::{GitRunner, LoggedGit, SystemGit, redact_urls};
+use crate::metadata::{
+    FETCH_RECORD_INTERVAL, SyncTrigger, dir_size, load_metadata, update_metadata,
+};
+use crate::project_id::ProjectId;
+use crate::project_settings::load_project_settings;
+use crate::source::ProjectSource;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
 use std::fs;
-use std::path::Path;
-use std::process::{Command, Stdio};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use tempfile::TempDir;
-use tracing::{debug, info, warn};
+use tracing::{Span, debug, info, warn};
 use walkdir::WalkDir;
 
 const DEFAULT_GITIGNORE: &str = r#"
@@ -24,25 +35,134 @@ output.pdf
 *.fdb_latexmk
 "#;
 
-/// Public async wrapper that also handles locking per project.
-/// We will call this from the HTTP handler.
-pub async fn ensure_repo(cfg: Config, project_id: &str) -> Result<(), BridgeError> {
+/// Whether `project_id` would be accepted as a [`ProjectId`].
+pub fn is_valid_project_id(project_id: &str) -> bool {
+    ProjectId::new(project_id).is_ok()
+}
+
+/// What a successful [`ensure_repo`] changed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SyncOutcome {
+    /// Id of the commit created by this sync; `None` when nothing changed
+    pub commit: Option<String>,
+    /// Previous commit that `commit` replaced under `SQUASH_WINDOW_MINUTES`
+    pub squashed: Option<String>,
+    /// Files touched by that commit
+    pub files_changed: usize,
+    /// What that commit changed, file by file
+    pub changes: Vec<FileChange>,
+    /// [`ProjectSource::fingerprint`] of the source taken before copying it;
+    /// `None` when the source cannot fingerprint itself
+    pub source_fingerprint: Option<String>,
+}
+
+/// One file a sync commit added, modified or deleted.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileChange {
+    /// Status letter of `git diff --name-status`: `A`, `M`, `D` or `T`
+    pub status: String,
+    pub path: String,
+    /// Size before the commit; `None` for added files
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub old_bytes: Option<u64>,
+    /// Size after the commit; `None` for deleted files
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub new_bytes: Option<u64>,
+    /// `new_bytes - old_bytes`, counting a missing side as 0
+    pub delta_bytes: i64,
+}
+
+/// Project control file with per-project bridge settings.
+pub(crate) const PROJECT_CONFIG_FILE: &str = ".gitbridge.toml";
+
+/// File inside a bare repo marking it as maintained outside the bridge.
+pub const EXTERNAL_MARKER: &str = "gitbridge-external";
+
+/// Whether the repo of `project_id` is an external one: served as-is, never
+/// synced, rebuilt, or removed by the bridge.
+pub fn is_external_repo(cfg: &Config, project_id: &ProjectId) -> bool {
+    cfg.bare_repo_dir(project_id)
+        .is_ok_and(|repo| repo.join(EXTERNAL_MARKER).is_file())
+}
+
+/// Ids of all bare mirrors in [`Config::repos_dir`].
+pub(crate) fn list_mirrors(cfg: &Config) -> Result<Vec<ProjectId>, BridgeError> {
+    let entries = match fs::read_dir(cfg.repos_dir()) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(BridgeError::Io(e)),
+    };
+    Ok(entries
+        .flatten()
+        .filter(|entry| entry.file_type().map(|ft| ft.is_dir()).unwrap_or(false))
+        .filter_map(|entry| {
+            let name = entry.file_name().to_str()?.to_string();
+            cfg.parse_project_id(name.strip_suffix(".git")?).ok()
+        })
+        .collect())
+}
+
+/// Create or refresh the bare mirror of `project_id` from its ShareLatex snapshot.
+///
+/// Returns [`BridgeError::ProjectNotFound`] (and trashes a stale mirror) when
+/// the source directory is gone. Callers are responsible for serializing
+/// calls per project.
+pub async fn ensure_repo(
+    cfg: Config,
+    source: Arc<dyn ProjectSource>,
+    project_id: &ProjectId,
+) -> Result<SyncOutcome, BridgeError> {
     // We do heavy filesystem + git work, so run it blocking.
     let cfg_cloned = cfg.clone();
-    let project_id_owned = project_id.to_string();
-    tokio::task::spawn_blocking(move || ensure_repo_blocking(&cfg_cloned, &project_id_owned))
-        .await
-        .map_err(|e| BridgeError::Other(format!("join error: {e}")))?
+    let project_id_owned = project_id.clone();
+    let span = Span::current();
+    tokio::task::spawn_blocking(move || {
+        let _entered = span.enter();
+        ensure_repo_blocking(&SystemGit, source.as_ref(), &cfg_cloned, &project_id_owned)
+    })
+    .await
+    .map_err(|e| BridgeError::Other(format!("join error: {e}")))?
 }
 
-fn ensure_repo_blocking(cfg: &Config, project_id: &str) -> Result<(), BridgeError> {
-    let source_dir = cfg.project_source_dir(project_id);
-    let bare_repo_dir = cfg.bare_repo_dir(project_id);
+/// Sync `project_id`, logging every git command (see [`LoggedGit`]). When the
+/// sync fails, the last commands are kept in the sync metadata. Callers must
+/// hold the project lock. Blocking.
+pub(crate) fn ensure_repo_blocking(
+    git: &dyn GitRunner,
+    source: &dyn ProjectSource,
+    cfg: &Config,
+    project_id: &ProjectId,
+) -> Result<SyncOutcome, BridgeError> {
+    let git = LoggedGit::new(git, cfg.git_command_log);
+    let result = sync_mirror(&git, source, cfg, project_id);
+    if result.is_err() {
+        let commands = git.recent();
+        if let Err(e) = update_metadata(cfg, project_id, |meta| meta.last_error_git = commands) {
+            warn!(%project_id, "cannot record git commands of the failed sync: {e}");
+        }
+    }
+    result
+}
 
-    if !source_dir.is_dir() {
+fn sync_mirror(
+    git: &dyn GitRunner,
+    source: &dyn ProjectSource,
+    cfg: &Config,
+    project_id: &ProjectId,
+) -> Result<SyncOutcome, BridgeError> {
+    let bare_repo_dir = cfg.bare_repo_dir(project_id)?;
+    if is_external_repo(cfg, project_id) {
+        debug!(%project_id, "external repo, nothing to sync");
+        return Ok(SyncOutcome::default());
+    }
+
+    if !source.exists(project_id) {
         if bare_repo_dir.exists() {
-            match fs::remove_dir_all(&bare_repo_dir) {
-                Ok(_) => {
+            match crate::trash::remove_mirror(cfg, project_id, false) {
+                Ok(Some(entry)) => {
+                    info!(%project_id, %entry, "moved stale bare repo to trash because source project is missing")
+                }
+                Ok(None) => {
                     info!(%project_id, "removed stale bare repo because source project is missing")
                 }
                 Err(e) => warn!(%project_id, error = %e, "failed to remove stale bare repo"),
@@ -50,25 +170,52 @@ fn ensure_repo_blocking(cfg: &Config, project_id: &str) -> Result<(), BridgeErro
         }
         return Err(BridgeError::ProjectNotFound(project_id.to_string()));
     }
+    if bare_repo_dir.is_dir() {
+        check_repo_size(cfg, project_id, &bare_repo_dir)?;
+    }
 
-    if !bare_repo_dir.is_dir() {
+    // taken before copying, so a change racing the copy shows up next time
+    let fingerprint = source.fingerprint(project_id).unwrap_or_else(|e| {
+        warn!(%project_id, "cannot fingerprint source: {e}");
+        None
+    });
+
+    let _shared = crate::shared_objects::lock_for_sync(git, cfg)?;
+    let (mut outcome, created) = if !bare_repo_dir.is_dir() {
         info!(%project_id, "bare repo does not exist, creating initial snapshot");
-        initial_create(cfg, project_id, &source_dir, &bare_repo_dir)?;
+        let outcome = initial_create(git, source, cfg, project_id, &bare_repo_dir)?;
+        (outcome, true)
     } else {
         debug!(%project_id, "bare repo exists, syncing");
-        sync_existing(cfg, project_id, &source_dir, &bare_repo_dir)?;
-    }
+        let outcome = sync_existing(git, source, cfg, project_id, &bare_repo_dir)?;
+        (outcome, false)
+    };
 
-    Ok(())
+    let large_change = outcome.changes.iter().any(|change| {
+        change
+            .new_bytes
+            .is_some_and(|bytes| bytes >= cfg.shared_objects_min_bytes)
+    });
+    if cfg.shared_objects
+        && (created || large_change)
+        && let Err(e) =
+            crate::shared_objects::share_large_blobs(git, cfg, project_id, &bare_repo_dir)
+    {
+        // the mirror keeps its own copies; nothing is lost
+        warn!(%project_id, "cannot move large blobs to the shared store: {e}");
+    }
+    outcome.source_fingerprint = snapshot_fingerprint(cfg, fingerprint);
+    Ok(outcome)
 }
 
 /// Create initial bare repo from ShareLatex snapshot
 fn initial_create(
+    git: &dyn GitRunner,
+    source: &dyn ProjectSource,
     cfg: &Config,
-    project_id: &str,
-    source_dir: &Path,
+    project_id: &ProjectId,
     bare_repo_dir: &Path,
-) -> Result<(), BridgeError> {
+) -> Result<SyncOutcome, BridgeError> {
     if let Some(parent) = bare_repo_dir.parent() {
         fs::create_dir_all(parent).map_err(BridgeError::Io)?;
     }
@@ -81,25 +228,38 @@ fn initial_create(
     })?;
     let tmp = tmpdir.path();
 
-    copy_snapshot(source_dir, tmp)?;
+    source.copy_to_dir(project_id, tmp)?;
     ensure_gitignore(tmp)?;
+    normalize_snapshot(cfg, project_id, tmp)?;
 
     // git init
-    run_git(&["init"], tmp)?;
+    run_git(git, &["init"], tmp)?;
+    exclude_control_files(tmp)?;
     // checkout branch we want
-    run_git(&["checkout", "-b", &cfg.readonly_branch], tmp)?;
+    run_git(git, &["checkout", "-b", &cfg.readonly_branch], tmp)?;
 
     // config user
-    run_git(&["config", "user.name", GIT_AUTHOR_NAME], tmp)?;
-    run_git(&["config", "user.email", GIT_AUTHOR_EMAIL], tmp)?;
+    run_git(git, &["config", "user.name", GIT_AUTHOR_NAME], tmp)?;
+    run_git(git, &["config", "user.email", GIT_AUTHOR_EMAIL], tmp)?;
 
     // add & commit
-    run_git(&["add", "-A"], tmp)?;
+    run_git(git, &["add", "-A"], tmp)?;
     let msg = format!("Initial snapshot from ShareLatex project {project_id}");
-    run_git(&["commit", "-m", &msg], tmp)?;
+    let date = cfg
+        .commit_date_from_source
+        .then(|| source_author_date(git, source, project_id, tmp, None))
+        .flatten();
+    if cfg.deterministic_init {
+        let date = date.unwrap_or(DateTime::UNIX_EPOCH);
+        commit_deterministic(git, &msg, date, &cfg.readonly_branch, tmp)?;
+    } else {
+        commit(git, &msg, date, tmp)?;
+    }
+    let outcome = head_commit_outcome(git, tmp)?;
 
     // clone --bare into bare_repo_dir
     run_git(
+        git,
         &[
             "clone",
             "--bare",
@@ -113,6 +273,7 @@ fn initial_create(
 
     // Make sure HEAD in bare repo points to our readonly branch
     run_git(
+        git,
         &[
             "symbolic-ref",
             "HEAD",
@@ -120,17 +281,21 @@ fn initial_create(
         ],
         bare_repo_dir,
     )?;
+    reconcile_git_config(git, source, cfg, project_id, bare_repo_dir);
 
-    Ok(())
+    Ok(outcome)
 }
 
-/// Sync changes from ShareLatex data dir into existing bare repo
-fn sync_existing(
+/// Clone an existing bare repo into a temp worktree on the read-only branch
+/// and stage the current ShareLatex snapshot there. Dropping the returned
+/// dir discards it.
+fn stage_snapshot(
+    git: &dyn GitRunner,
+    source: &dyn ProjectSource,
     cfg: &Config,
-    project_id: &str,
-    source_dir: &Path,
+    project_id: &ProjectId,
     bare_repo_dir: &Path,
-) -> Result<(), BridgeError> {
+) -> Result<TempDir, BridgeError> {
     let tmpdir = TempDir::new_in(&cfg.git_root).map_err(|e| {
         BridgeError::Other(format!(
             "failed to create tempdir in {}: {e}",
@@ -141,6 +306,7 @@ fn sync_existing(
 
     // git clone bare_repo_dir tmp
     run_git(
+        git,
         &[
             "clone",
             bare_repo_dir
@@ -152,102 +318,565 @@ fn sync_existing(
     )?;
 
     // checkout desired branch (create if missing)
-    if let Err(e) = run_git(&["checkout", &cfg.readonly_branch], tmp) {
+    if let Err(e) = run_git(git, &["checkout", &cfg.readonly_branch], tmp) {
         warn!("branch checkout failed: {e}, trying to create");
-        run_git(&["checkout", "-b", &cfg.readonly_branch], tmp)?;
+        run_git(git, &["checkout", "-b", &cfg.readonly_branch], tmp)?;
     }
 
     // mirror ShareLatex project files into tmp working tree
-    sync_worktree_with_source(source_dir, tmp)?;
+    sync_worktree_with_source(source, project_id, tmp)?;
     ensure_gitignore(tmp)?;
+    normalize_snapshot(cfg, project_id, tmp)?;
+    exclude_control_files(tmp)?;
 
     // git add -A
-    run_git(&["add", "-A"], tmp)?;
+    run_git(git, &["add", "-A"], tmp)?;
+    Ok(tmpdir)
+}
+
+/// Files a sync of an existing mirror would add, modify and delete.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub(crate) struct PendingChanges {
+    pub(crate) added: usize,
+    pub(crate) modified: usize,
+    pub(crate) deleted: usize,
+}
+
+impl PendingChanges {
+    pub(crate) fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// What a sync of the existing mirror of `project_id` would commit, without
+/// changing the mirror. Callers should hold the project lock so the mirror
+/// does not move meanwhile. Blocking.
+pub(crate) fn pending_changes(
+    git: &dyn GitRunner,
+    source: &dyn ProjectSource,
+    cfg: &Config,
+    project_id: &ProjectId,
+) -> Result<PendingChanges, BridgeError> {
+    let bare_repo_dir = cfg.bare_repo_dir(project_id)?;
+    let tmpdir = stage_snapshot(git, source, cfg, project_id, &bare_repo_dir)?;
+    let mut changes = PendingChanges::default();
+    for (status, _) in staged_name_status(git, tmpdir.path())? {
+        match status.as_str() {
+            "A" => changes.added += 1,
+            "D" => changes.deleted += 1,
+            _ => changes.modified += 1,
+        }
+    }
+    Ok(changes)
+}
+
+/// Status letter and path of every staged change in the worktree `repo`.
+fn staged_name_status(
+    git: &dyn GitRunner,
+    repo: &Path,
+) -> Result<Vec<(String, String)>, BridgeError> {
+    let args = ["diff", "--staged", "--name-status", "--no-renames", "-z"];
+    let out = git.run(&args, repo)?;
+    if !out.status.success() {
+        return Err(BridgeError::GitFailed(
+            format!("git {:?}", args),
+            String::from_utf8_lossy(&out.stderr).to_string(),
+        ));
+    }
+    // NUL-separated pairs of status letter and path
+    let fields: Vec<&[u8]> = out.stdout.split(|&b| b == 0).collect();
+    Ok(fields
+        .chunks_exact(2)
+        .filter(|pair| !pair[0].is_empty())
+        .map(|pair| {
+            (
+                String::from_utf8_lossy(&pair[0][..1]).to_string(),
+                String::from_utf8_lossy(pair[1]).to_string(),
+            )
+        })
+        .collect())
+}
+
+/// Staged changes of the worktree `repo` with the size of each file before
+/// and after. Sizes before come from `HEAD`; when it cannot be listed, they
+/// are left out rather than failing the sync.
+fn staged_file_changes(git: &dyn GitRunner, repo: &Path) -> Result<Vec<FileChange>, BridgeError> {
+    let staged = staged_name_status(git, repo)?;
+    let mut old_sizes = BTreeMap::new();
+    let out = git.run(&["ls-tree", "-r", "-l", "-z", "HEAD"], repo)?;
+    if out.status.success() {
+        // `<mode> <type> <object> <size>\t<path>`, NUL-terminated
+        for entry in out.stdout.split(|&b| b == 0) {
+            let entry = String::from_utf8_lossy(entry);
+            let Some((meta, path)) = entry.split_once('\t') else {
+                continue;
+            };
+            if let Some(Ok(size)) = meta.split_whitespace().nth(3).map(str::parse::<u64>) {
+                old_sizes.insert(path.to_string(), size);
+            }
+        }
+    } else {
+        debug!("cannot list HEAD, leaving old sizes out of the change list");
+    }
+    Ok(staged
+        .into_iter()
+        .map(|(status, path)| {
+            let old_bytes = (status != "A")
+                .then(|| old_sizes.get(&path).copied())
+                .flatten();
+            let new_bytes = (status != "D")
+                .then(|| fs::symlink_metadata(repo.join(&path)).ok().map(|m| m.len()))
+                .flatten();
+            let delta_bytes = new_bytes.unwrap_or(0) as i64 - old_bytes.unwrap_or(0) as i64;
+            FileChange {
+                status,
+                path,
+                old_bytes,
+                new_bytes,
+                delta_bytes,
+            }
+        })
+        .collect())
+}
+
+/// Refuse to sync a mirror larger than `MAX_REPO_BYTES`. Only a mirror whose
+/// size recorded after its last sync is over the limit is measured again,
+/// so one an admin shrank (by restoring it, or removing it to be created
+/// anew) syncs again right away.
+fn check_repo_size(
+    cfg: &Config,
+    project_id: &ProjectId,
+    bare_repo_dir: &Path,
+) -> Result<(), BridgeError> {
+    if cfg.max_repo_bytes == 0 {
+        return Ok(());
+    }
+    let recorded = load_metadata(cfg, project_id)
+        .history
+        .iter()
+        .rev()
+        .find_map(|record| record.repo_bytes);
+    if recorded.is_none_or(|bytes| bytes <= cfg.max_repo_bytes) {
+        return Ok(());
+    }
+    let bytes = dir_size(bare_repo_dir);
+    if bytes <= cfg.max_repo_bytes {
+        return Ok(());
+    }
+    Err(BridgeError::RepoTooLarge(format!(
+        "{project_id} takes {bytes} bytes, more than MAX_REPO_BYTES={}; syncs are refused until \
+         the mirror is restored from a smaller bundle or removed",
+        cfg.max_repo_bytes
+    )))
+}
+
+/// Sync changes from ShareLatex data dir into existing bare repo
+fn sync_existing(
+    git: &dyn GitRunner,
+    source: &dyn ProjectSource,
+    cfg: &Config,
+    project_id: &ProjectId,
+    bare_repo_dir: &Path,
+) -> Result<SyncOutcome, BridgeError> {
+    let tmpdir = stage_snapshot(git, source, cfg, project_id, bare_repo_dir)?;
+    let tmp = tmpdir.path();
 
     // check if staged diff exists
-    let has_changes = staged_has_changes(tmp)?;
+    let has_changes = staged_has_changes(git, tmp)?;
 
+    let mut outcome = SyncOutcome::default();
     if has_changes {
         // commit & push
-        run_git(&["config", "user.name", GIT_AUTHOR_NAME], tmp)?;
-        run_git(&["config", "user.email", GIT_AUTHOR_EMAIL], tmp)?;
+        run_git(git, &["config", "user.name", GIT_AUTHOR_NAME], tmp)?;
+        run_git(git, &["config", "user.email", GIT_AUTHOR_EMAIL], tmp)?;
 
-        let ts = Utc::now().to_rfc3339();
-        let msg = format!("Sync {ts} from ShareLatex project {project_id}");
+        let changes = staged_file_changes(git, tmp)?;
+        let msg = sync_commit_message(cfg, project_id, &Utc::now().to_rfc3339());
+        let mut squashed = squash_target(git, cfg, project_id, tmp);
+        if let Some(previous) = &squashed
+            && let Err(e) = amend(git, cfg, &msg, previous, tmp)
+        {
+            warn!(%project_id, "cannot amend the previous commit, committing instead: {e}");
+            run_git(git, &["reset", "--soft", previous], tmp)?;
+            squashed = None;
+        }
 
-        run_git(&["commit", "-m", &msg], tmp)?;
-        run_git(&["push", "origin", &cfg.readonly_branch], tmp)?;
-        info!(%project_id, "pushed new commit");
+        if let Some(previous) = &squashed {
+            info!(%project_id, replaced = %previous, "amended the previous commit");
+        } else {
+            let date = if cfg.commit_date_from_source {
+                let written: HashSet<&str> = changes
+                    .iter()
+                    .filter(|change| change.status != "D")
+                    .map(|change| change.path.as_str())
+                    .collect();
+                source_author_date(git, source, project_id, tmp, Some(&written))
+            } else {
+                None
+            };
+            commit(git, &msg, date, tmp)?;
+            run_git(git, &["push", "origin", &cfg.readonly_branch], tmp)?;
+            info!(%project_id, "pushed new commit");
+        }
+        outcome = head_commit_outcome(git, tmp)?;
+        outcome.squashed = squashed;
+        outcome.changes = changes;
     } else {
         debug!(%project_id, "no changes detected, skipping commit");
     }
+    reconcile_git_config(git, source, cfg, project_id, bare_repo_dir);
 
-    Ok(())
+    Ok(outcome)
 }
 
-/// Returns true if there are staged changes
-fn staged_has_changes(repo: &Path) -> Result<bool, BridgeError> {
-    let status = Command::new("git")
-        .arg("diff")
-        .arg("--staged")
-        .arg("--quiet")
-        .current_dir(repo)
-        .status()
-        .map_err(BridgeError::Io)?;
-
-    match status.code() {
-        Some(0) => Ok(false), // no diff
-        Some(1) => Ok(true),  // there is a diff
-        other => Err(BridgeError::Other(format!(
-            "git diff --staged --quiet unexpected exit code {other:?}"
-        ))),
+/// The commit at `HEAD` of the worktree `repo` if the staged changes may be
+/// folded into it (`SQUASH_WINDOW_MINUTES`): a sync commit of the bridge
+/// with a parent, authored within the window, made by a sync other than a
+/// fetch's (whose commit was served right away) and not fetched since. The
+/// last fetch time is written at most once per [`FETCH_RECORD_INTERVAL`],
+/// so a fetch within twice that before the commit counts as one after it.
+/// Amending must also leave a change to the parent.
+fn squash_target(
+    git: &dyn GitRunner,
+    cfg: &Config,
+    project_id: &ProjectId,
+    repo: &Path,
+) -> Option<String> {
+    if cfg.squash_window_minutes == 0 {
+        return None;
+    }
+    let head = git_stdout(git, &["log", "-1", "--format=%H%n%ae%n%at%n%ct%n%P"], repo).ok()?;
+    let mut lines = head.lines();
+    let (commit, email) = (lines.next()?, lines.next()?);
+    let authored = DateTime::from_timestamp(lines.next()?.parse().ok()?, 0)?;
+    let committed = DateTime::from_timestamp(lines.next()?.parse().ok()?, 0)?;
+    let has_parent = lines.next().is_some_and(|parents| !parents.is_empty());
+    let window = chrono::Duration::minutes(cfg.squash_window_minutes as i64);
+    if email != GIT_AUTHOR_EMAIL || !has_parent || Utc::now() - authored >= window {
+        return None;
     }
+
+    let meta = load_metadata(cfg, project_id);
+    let trigger = meta
+        .history
+        .iter()
+        .rev()
+        .find(|record| record.commit.as_deref() == Some(commit))?
+        .trigger?;
+    let slack = chrono::Duration::from_std(FETCH_RECORD_INTERVAL * 2).ok()?;
+    if trigger == SyncTrigger::Fetch
+        || meta
+            .last_fetch_at
+            .is_some_and(|fetched| fetched + slack > committed)
+    {
+        return None;
+    }
+
+    let out = git
+        .run(&["diff", "--cached", "--quiet", "HEAD^"], repo)
+        .ok()?;
+    (out.status.code() == Some(1)).then(|| commit.to_string())
 }
 
-/// Copy entire snapshot from source -> dest (no delete here)
-fn copy_snapshot(src: &Path, dst: &Path) -> Result<(), BridgeError> {
-    copy_recursive(src, dst)
+/// Fold the staged changes of `repo` into its `HEAD`, `previous`, keeping
+/// its author date, and replace `previous` on the read-only branch of the
+/// mirror, unless that moved meanwhile.
+fn amend(
+    git: &dyn GitRunner,
+    cfg: &Config,
+    msg: &str,
+    previous: &str,
+    repo: &Path,
+) -> Result<(), BridgeError> {
+    run_git(git, &["commit", "--amend", "-m", msg], repo)?;
+    let lease = format!("--force-with-lease={}:{previous}", cfg.readonly_branch);
+    run_git(git, &["push", &lease, "origin", &cfg.readonly_branch], repo)
 }
 
-/// Sync snapshot (copy + delete missing in dst) into already-cloned worktree
-fn sync_worktree_with_source(src: &Path, dst: &Path) -> Result<(), BridgeError> {
-    copy_recursive(src, dst)?;
-    delete_removed(src, dst)?;
-    Ok(())
+/// Commit the staged changes of `repo`, with `author_date` as the author
+/// date if given.
+fn commit(
+    git: &dyn GitRunner,
+    msg: &str,
+    author_date: Option<DateTime<Utc>>,
+    repo: &Path,
+) -> Result<(), BridgeError> {
+    match author_date {
+        Some(date) => run_git(
+            git,
+            &["commit", "-m", msg, "--date", &date.to_rfc3339()],
+            repo,
+        ),
+        None => run_git(git, &["commit", "-m", msg], repo),
+    }
 }
 
-/// Copy files recursively from `src` to `dst`
-/// Skips `.git` dirs in `src` just in case.
-fn copy_recursive(src: &Path, dst: &Path) -> Result<(), BridgeError> {
-    for entry in WalkDir::new(src).into_iter().filter_map(|e| e.ok()) {
-        let path = entry.path();
-        let rel = match path.strip_prefix(src) {
-            Ok(r) => r,
-            Err(_) => continue,
+/// Commit the staged changes of `repo` as the root commit of `branch`, with
+/// `date` as both author and committer date, so the commit id only depends
+/// on the snapshot, `msg` and `date` (`DETERMINISTIC_INIT`). The commit
+/// object is written by hand: git takes the committer date only from the
+/// environment.
+fn commit_deterministic(
+    git: &dyn GitRunner,
+    msg: &str,
+    date: DateTime<Utc>,
+    branch: &str,
+    repo: &Path,
+) -> Result<(), BridgeError> {
+    let tree = git_stdout(git, &["write-tree"], repo)?;
+    let ident = format!(
+        "{GIT_AUTHOR_NAME} <{GIT_AUTHOR_EMAIL}> {} +0000",
+        date.timestamp()
+    );
+    let object = format!("tree {tree}\nauthor {ident}\ncommitter {ident}\n\n{msg}\n");
+    fs::write(repo.join(".git/gitbridge-root-commit"), object)?;
+    let commit = git_stdout(
+        git,
+        &[
+            "hash-object",
+            "-t",
+            "commit",
+            "-w",
+            ".git/gitbridge-root-commit",
+        ],
+        repo,
+    )?;
+    run_git(
+        git,
+        &["update-ref", &format!("refs/heads/{branch}"), &commit],
+        repo,
+    )
+}
+
+/// Author date of a commit in the worktree `repo` with
+/// `COMMIT_DATE_FROM_SOURCE`: the newest modification time in the source
+/// among `paths` (all files when `None`), see [`clamp_author_date`]. `None`
+/// when no time is known, e.g. for commits that only delete files.
+fn source_author_date(
+    git: &dyn GitRunner,
+    source: &dyn ProjectSource,
+    project_id: &ProjectId,
+    repo: &Path,
+    paths: Option<&HashSet<&str>>,
+) -> Option<DateTime<Utc>> {
+    let entries = match source.enumerate(project_id) {
+        Ok(entries) => entries,
+        Err(e) => {
+            warn!(%project_id, "cannot read modification times, dating the commit now: {e}");
+            return None;
+        }
+    };
+    let newest = entries
+        .filter(|entry| !entry.is_dir)
+        .filter(|entry| paths.is_none_or(|paths| paths.contains(&*entry.path.to_string_lossy())))
+        .filter_map(|entry| entry.modified)
+        .max()?;
+    let previous = git
+        .run(&["log", "-1", "--format=%at"], repo)
+        .ok()
+        .filter(|out| out.status.success())
+        .and_then(|out| {
+            String::from_utf8_lossy(&out.stdout)
+                .trim()
+                .parse::<i64>()
+                .ok()
+        })
+        .and_then(|secs| DateTime::from_timestamp(secs, 0));
+    Some(clamp_author_date(newest.into(), previous, Utc::now()))
+}
+
+/// `newest`, but never later than `now` (clocks of the source may be off)
+/// and never earlier than the author date of the `previous` commit, so
+/// history stays ordered.
+fn clamp_author_date(
+    newest: DateTime<Utc>,
+    previous: Option<DateTime<Utc>>,
+    now: DateTime<Utc>,
+) -> DateTime<Utc> {
+    let date = newest.min(now);
+    match previous {
+        Some(previous) => date.max(previous.min(now)),
+        None => date,
+    }
+}
+
+/// Settings in a project's `.gitbridge.toml` that apply to its mirror.
+#[derive(Debug, Default, Deserialize)]
+struct ProjectRepoSettings {
+    /// Git config of the mirror, overriding `BARE_REPO_GIT_CONFIG`
+    #[serde(default)]
+    git_config: BTreeMap<String, toml::Value>,
+}
+
+/// Whether a project may set `key` on its mirror. Project files are written
+/// by anyone editing the project, so keys that make git run commands, load
+/// other files, or change what is served stay reserved for the operator.
+fn project_may_set(key: &str) -> bool {
+    let key = key.to_ascii_lowercase();
+    // the one core setting downstream tools commonly need per project
+    if key == "core.ignorecase" {
+        return true;
+    }
+    let section = key.split('.').next().unwrap_or_default();
+    let reserved_sections = [
+        "alias",
+        "core",
+        "credential",
+        "filter",
+        "http",
+        "include",
+        "includeif",
+        "protocol",
+        "remote",
+        "uploadpack",
+        "uploadarchive",
+        "url",
+    ];
+    let reserved_words = ["command", "hook", "program", "proxy", "editor", "pager"];
+    !reserved_sections.contains(&section) && !reserved_words.iter().any(|w| key.contains(w))
+}
+
+/// The git config the mirror of `project_id` should have:
+/// `BARE_REPO_GIT_CONFIG` with the project's `[git_config]` on top. Unusable
+/// project entries are logged and skipped.
+fn desired_git_config(
+    source: &dyn ProjectSource,
+    cfg: &Config,
+    project_id: &ProjectId,
+) -> Vec<(String, String)> {
+    let mut config = cfg.bare_repo_git_config.clone();
+    let mut content = String::new();
+    let read = source
+        .read(project_id, Path::new(PROJECT_CONFIG_FILE))
+        .and_then(|mut file| Ok(file.read_to_string(&mut content)?));
+    if read.is_err() {
+        return config;
+    }
+    let settings = match toml::from_str::<ProjectRepoSettings>(&content) {
+        Ok(settings) => settings,
+        Err(e) => {
+            warn!(%project_id, error = %e, "ignoring unparsable {PROJECT_CONFIG_FILE}");
+            return config;
+        }
+    };
+    for (key, value) in settings.git_config {
+        let value = match value {
+            toml::Value::String(s) => s,
+            toml::Value::Boolean(b) => b.to_string(),
+            toml::Value::Integer(i) => i.to_string(),
+            _ => {
+                warn!(%project_id, key, "skipping git_config entry that is not a single value");
+                continue;
+            }
         };
-        if rel.as_os_str().is_empty() {
+        if !is_valid_git_config_key(&key) || value.contains(['\0', '\n']) {
+            warn!(%project_id, key, "skipping invalid git_config entry");
             continue;
         }
-        if rel.components().any(|c| c.as_os_str() == ".git") {
-            // skip any embedded .git
+        if !project_may_set(&key) {
+            warn!(%project_id, key, "skipping git_config entry reserved for BARE_REPO_GIT_CONFIG");
             continue;
         }
-        let target_path = dst.join(rel);
-        if entry.file_type().is_dir() {
-            fs::create_dir_all(&target_path).map_err(BridgeError::Io)?;
-        } else if entry.file_type().is_file() {
-            if let Some(parent) = target_path.parent() {
-                fs::create_dir_all(parent).map_err(BridgeError::Io)?;
-            }
-            fs::copy(path, &target_path).map_err(BridgeError::Io)?;
+        config.retain(|(k, _)| !k.eq_ignore_ascii_case(&key));
+        config.push((key, value));
+    }
+    config
+}
+
+/// Set every desired git config key the mirror lacks or has a different
+/// value for. Failures are logged and do not fail the sync.
+fn reconcile_git_config(
+    git: &dyn GitRunner,
+    source: &dyn ProjectSource,
+    cfg: &Config,
+    project_id: &ProjectId,
+    bare_repo_dir: &Path,
+) {
+    for (key, value) in desired_git_config(source, cfg, project_id) {
+        let current = git
+            .run(&["config", "--get", &key], bare_repo_dir)
+            .ok()
+            .filter(|out| out.status.success())
+            .map(|out| String::from_utf8_lossy(&out.stdout).trim_end().to_string());
+        if current.as_deref() == Some(value.as_str()) {
+            continue;
+        }
+        match run_git(
+            git,
+            &["config", "--replace-all", &key, &value],
+            bare_repo_dir,
+        ) {
+            Ok(()) => info!(%project_id, key, "set git config of mirror"),
+            Err(e) => warn!(%project_id, key, "cannot set git config of mirror: {e}"),
         }
     }
+}
+
+/// Id and number of touched files of the commit at `HEAD`.
+fn head_commit_outcome(git: &dyn GitRunner, repo: &Path) -> Result<SyncOutcome, BridgeError> {
+    let args = ["rev-parse", "HEAD"];
+    let out = git.run(&args, repo)?;
+    if !out.status.success() {
+        return Err(BridgeError::GitFailed(
+            format!("git {:?}", args),
+            String::from_utf8_lossy(&out.stderr).to_string(),
+        ));
+    }
+    let commit = String::from_utf8_lossy(&out.stdout).trim().to_string();
+
+    let args = [
+        "diff-tree",
+        "--root",
+        "--no-commit-id",
+        "--name-only",
+        "-r",
+        "-z",
+        "HEAD",
+    ];
+    let out = git.run(&args, repo)?;
+    let files_changed = out
+        .stdout
+        .split(|&b| b == 0)
+        .filter(|name| !name.is_empty())
+        .count();
+
+    Ok(SyncOutcome {
+        commit: Some(commit),
+        squashed: None,
+        files_changed,
+        changes: Vec::new(),
+        source_fingerprint: None,
+    })
+}
+
+/// Returns true if there are staged changes
+fn staged_has_changes(git: &dyn GitRunner, repo: &Path) -> Result<bool, BridgeError> {
+    let out = git.run(&["diff", "--staged", "--quiet"], repo)?;
+
+    match out.status.code() {
+        Some(0) => Ok(false), // no diff
+        Some(1) => Ok(true),  // there is a diff
+        other => Err(BridgeError::Other(format!(
+            "git diff --staged --quiet unexpected exit code {other:?}"
+        ))),
+    }
+}
+
+/// Sync snapshot (copy + delete missing in dst) into already-cloned worktree
+fn sync_worktree_with_source(
+    source: &dyn ProjectSource,
+    project_id: &ProjectId,
+    dst: &Path,
+) -> Result<(), BridgeError> {
+    source.copy_to_dir(project_id, dst)?;
+    let present: HashSet<PathBuf> = source.enumerate(project_id)?.map(|e| e.path).collect();
+    delete_removed(&present, dst)?;
     Ok(())
 }
 
-/// Delete files/dirs in `dst` which no longer exist in `src`
+/// Delete files/dirs in `dst` whose relative path is not in `present`
 /// Never touch `dst/.git` directory.
-fn delete_removed(src: &Path, dst: &Path) -> Result<(), BridgeError> {
+fn delete_removed(present: &HashSet<PathBuf>, dst: &Path) -> Result<(), BridgeError> {
     for entry in WalkDir::new(dst)
         .into_iter()
         .filter_map(|e| e.ok())
@@ -268,8 +897,12 @@ fn delete_removed(src: &Path, dst: &Path) -> Result<(), BridgeError> {
             continue;
         }
 
-        let corresponding_src = src.join(rel);
-        if !corresponding_src.exists() {
+        // already gone together with a removed parent directory
+        if !path.exists() {
+            continue;
+        }
+
+        if !present.contains(rel) {
             if entry.file_type().is_dir() {
                 fs::remove_dir_all(path).map_err(BridgeError::Io)?;
             } else {
@@ -291,26 +924,608 @@ fn ensure_gitignore(dst: &Path) -> Result<(), BridgeError> {
     Ok(())
 }
 
-/// Run a git command and ensure success
-fn run_git(args: &[&str], cwd: &Path) -> Result<(), BridgeError> {
-    let mut cmd = std::process::Command::new("git");
-    cmd.args(args)
-        .current_dir(cwd)
-        .stdin(Stdio::null())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped());
-    let out = cmd.output().map_err(|e| {
-        BridgeError::Other(format!(
-            "failed to run git {:?} in {}: {e}",
-            args,
-            cwd.display()
-        ))
-    })?;
+/// With `NORMALIZE_EOL`, turn CRLF into LF in the snapshot in `dst` and add
+/// the `.gitattributes` keeping it that way. Runs on every sync, as the
+/// source has neither.
+fn normalize_snapshot(cfg: &Config, project_id: &ProjectId, dst: &Path) -> Result<(), BridgeError> {
+    if !cfg.normalize_eol {
+        return Ok(());
+    }
+    let normalized = eol::normalize_worktree(dst)?;
+    if normalized > 0 {
+        debug!(%project_id, files = normalized, "normalized line endings");
+    }
+    eol::ensure_gitattributes(dst)
+}
+
+/// Fingerprint to record for a snapshot of a source with `fingerprint`.
+/// It includes `NORMALIZE_EOL`, so switching it makes the next fetch sync
+/// even though the source did not change.
+pub(crate) fn snapshot_fingerprint(cfg: &Config, fingerprint: Option<String>) -> Option<String> {
+    fingerprint.map(|fingerprint| {
+        if cfg.normalize_eol {
+            format!("{fingerprint}+eol=lf")
+        } else {
+            fingerprint
+        }
+    })
+}
+
+/// Render `COMMIT_MESSAGE_TEMPLATE`; `{project_alias}` falls back to the id
+/// when the project has no alias.
+fn sync_commit_message(cfg: &Config, project_id: &ProjectId, timestamp: &str) -> String {
+    let template = &cfg.commit_message_template;
+    let alias = if template.contains("{project_alias}") {
+        match load_project_settings(cfg) {
+            Ok(settings) => settings.get(project_id).alias,
+            Err(e) => {
+                warn!(%project_id, error = %e, "cannot load project settings for commit message");
+                None
+            }
+        }
+    } else {
+        None
+    };
+    template
+        .replace("{project_id}", project_id)
+        .replace("{project_alias}", alias.as_deref().unwrap_or(project_id))
+        .replace("{timestamp}", timestamp)
+}
+
+/// Keep bridge control files that may contain secrets out of the mirror,
+/// independent of the project's own `.gitignore`.
+fn exclude_control_files(worktree: &Path) -> Result<(), BridgeError> {
+    let info_dir = worktree.join(".git").join("info");
+    fs::create_dir_all(&info_dir)?;
+    fs::write(info_dir.join("exclude"), "/.gitbridge.toml\n")?;
+    Ok(())
+}
+
+/// Trimmed standard output of `git <args>` in `cwd`, which has to succeed.
+fn git_stdout(git: &dyn GitRunner, args: &[&str], cwd: &Path) -> Result<String, BridgeError> {
+    let out = git.run(args, cwd)?;
     if !out.status.success() {
         return Err(BridgeError::GitFailed(
             format!("git {:?}", args),
             String::from_utf8_lossy(&out.stderr).to_string(),
         ));
     }
+    Ok(String::from_utf8_lossy(&out.stdout).trim().to_string())
+}
+
+/// Run a git command and ensure success. Some commands explain failures on
+/// stdout (e.g. `git commit`), which is reported when stderr is empty;
+/// credentials in URLs are redacted either way.
+pub(crate) fn run_git(git: &dyn GitRunner, args: &[&str], cwd: &Path) -> Result<(), BridgeError> {
+    let out = git.run(args, cwd)?;
+    if !out.status.success() {
+        let output = if out.stderr.trim_ascii().is_empty() {
+            &out.stdout
+        } else {
+            &out.stderr
+        };
+        return Err(BridgeError::GitFailed(
+            format!("git {:?}", args),
+            redact_urls(&String::from_utf8_lossy(output)),
+        ));
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git::mock::ScriptedGit;
+    use crate::project_id::pid;
+    use crate::source::FsProjectSource;
+
+    #[test]
+    fn commit_message_uses_alias_when_set() {
+        let root = TempDir::new().unwrap();
+        let mut cfg = Config::for_test(root.path());
+        fs::create_dir_all(&cfg.git_root).unwrap();
+        cfg.commit_message_template = "{project_alias} ({project_id}) @ {timestamp}".into();
+        assert_eq!(sync_commit_message(&cfg, &pid("p1"), "T"), "p1 (p1) @ T");
+
+        let mut settings = load_project_settings(&cfg).unwrap();
+        settings.update("p1", |s| s.alias = Some("Thesis".into()));
+        crate::project_settings::save_project_settings(&cfg, &settings).unwrap();
+        assert_eq!(
+            sync_commit_message(&cfg, &pid("p1"), "T"),
+            "Thesis (p1) @ T"
+        );
+    }
+
+    #[test]
+    fn project_ids_must_be_single_path_components() {
+        assert!(is_valid_project_id("5f1a2b3c4d5e6f7a8b9c0d1e"));
+        assert!(is_valid_project_id("5f1a2b3c4d5e6f7a8b9c0d1e-thesis"));
+        assert!(!is_valid_project_id(""));
+        assert!(!is_valid_project_id(".."));
+        assert!(!is_valid_project_id("a/b"));
+        assert!(!is_valid_project_id(".hidden"));
+    }
+
+    #[test]
+    fn external_repos_are_never_synced_or_removed() {
+        let root = TempDir::new().unwrap();
+        let cfg = Config::for_test(root.path());
+        let bare = cfg.bare_repo_dir(&pid("templates")).unwrap();
+        fs::create_dir_all(&bare).unwrap();
+        fs::write(bare.join(EXTERNAL_MARKER), "").unwrap();
+        let source = FsProjectSource::new(cfg.clone());
+        let git = ScriptedGit::new();
+
+        let outcome = ensure_repo_blocking(&git, &source, &cfg, &pid("templates")).unwrap();
+        assert_eq!(outcome, SyncOutcome::default());
+        assert!(bare.is_dir());
+        assert!(git.calls().is_empty());
+    }
+
+    #[test]
+    fn mirrors_of_missing_sources_go_to_the_trash() {
+        let root = TempDir::new().unwrap();
+        let cfg = Config::for_test(root.path());
+        fs::create_dir_all(cfg.bare_repo_dir(&pid("gone")).unwrap()).unwrap();
+        let source = FsProjectSource::new(cfg.clone());
+
+        let err =
+            ensure_repo_blocking(&ScriptedGit::new(), &source, &cfg, &pid("gone")).unwrap_err();
+        assert!(matches!(err, BridgeError::ProjectNotFound(_)));
+        assert!(!cfg.bare_repo_dir(&pid("gone")).unwrap().exists());
+        let trashed = crate::trash::list_trash(&cfg).unwrap();
+        assert_eq!(trashed.len(), 1);
+        assert_eq!(trashed[0].project_id, "gone");
+    }
+
+    fn setup() -> (TempDir, Config, FsProjectSource, PathBuf) {
+        let root = TempDir::new().unwrap();
+        let cfg = Config::for_test(root.path());
+        let source = cfg.project_source_dir(&pid("p1")).unwrap();
+        fs::create_dir_all(&source).unwrap();
+        fs::write(source.join("main.tex"), "hello").unwrap();
+        let bare = cfg.bare_repo_dir(&pid("p1")).unwrap();
+        fs::create_dir_all(&bare).unwrap();
+        let source = FsProjectSource::new(cfg.clone());
+        (root, cfg, source, bare)
+    }
+
+    #[test]
+    fn failed_syncs_keep_their_last_git_commands() {
+        let (_root, cfg, source, _bare) = setup();
+        let git = ScriptedGit::new()
+            .on(&["diff", "--staged", "--quiet"], 1, "")
+            .on(
+                &["push", "origin", "master"],
+                1,
+                "fatal: unable to access 'https://u:pw@mirror.example/p1.git/'",
+            );
+
+        let err = ensure_repo_blocking(&git, &source, &cfg, &pid("p1")).unwrap_err();
+        assert!(
+            err.to_string().contains("https://***@mirror.example"),
+            "{err}"
+        );
+        let meta = crate::metadata::load_metadata(&cfg, &pid("p1"));
+        let push = meta.last_error_git.last().unwrap();
+        assert_eq!(push.command, "git push origin master");
+        assert_eq!(push.exit_code, Some(1));
+        assert_eq!(
+            push.stderr,
+            "fatal: unable to access 'https://***@mirror.example/p1.git/'"
+        );
+        assert!(meta.last_error_git.len() > 1);
+
+        let ok = ensure_repo_blocking(&ScriptedGit::new(), &source, &cfg, &pid("p1")).unwrap();
+        crate::metadata::record_sync(&cfg, &pid("p1"), crate::metadata::SyncTrigger::Cli, Ok(&ok));
+        assert!(
+            crate::metadata::load_metadata(&cfg, &pid("p1"))
+                .last_error_git
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn author_dates_are_clamped() {
+        let at = |secs| DateTime::from_timestamp(secs, 0).unwrap();
+        let now = at(1_000);
+        // in range: kept
+        assert_eq!(clamp_author_date(at(500), Some(at(400)), now), at(500));
+        assert_eq!(clamp_author_date(at(500), None, now), at(500));
+        // in the future: now
+        assert_eq!(clamp_author_date(at(5_000), Some(at(400)), now), now);
+        // before the previous commit: the previous commit
+        assert_eq!(clamp_author_date(at(300), Some(at(400)), now), at(400));
+        // a previous commit from the future does not push the date past now
+        assert_eq!(clamp_author_date(at(300), Some(at(2_000)), now), now);
+    }
+
+    #[test]
+    fn enabling_eol_normalization_commits_once() {
+        let root = TempDir::new().unwrap();
+        let mut cfg = Config::for_test(root.path());
+        let src = cfg.project_source_dir(&pid("p1")).unwrap();
+        fs::create_dir_all(&src).unwrap();
+        fs::write(src.join("main.tex"), "a\r\nb\r\n").unwrap();
+        fs::write(src.join("logo.png"), b"\x89PNG\r\n\x1a\n").unwrap();
+        let source = FsProjectSource::new(cfg.clone());
+        let bare = cfg.bare_repo_dir(&pid("p1")).unwrap();
+        let show = |path: &str| {
+            let spec = format!("master:{path}");
+            SystemGit.run(&["show", &spec], &bare).unwrap().stdout
+        };
+        let commits = || {
+            let out = SystemGit
+                .run(&["rev-list", "--count", "master"], &bare)
+                .unwrap();
+            String::from_utf8(out.stdout).unwrap().trim().to_string()
+        };
+
+        ensure_repo_blocking(&SystemGit, &source, &cfg, &pid("p1")).unwrap();
+        assert_eq!(show("main.tex"), b"a\r\nb\r\n");
+
+        cfg.normalize_eol = true;
+        let outcome = ensure_repo_blocking(&SystemGit, &source, &cfg, &pid("p1")).unwrap();
+        assert!(outcome.commit.is_some());
+        assert!(outcome.source_fingerprint.unwrap().ends_with("+eol=lf"));
+        assert_eq!(commits(), "2");
+        assert_eq!(show("main.tex"), b"a\nb\n");
+        assert_eq!(show(".gitattributes"), eol::GITATTRIBUTES.as_bytes());
+        assert_eq!(show("logo.png"), b"\x89PNG\r\n\x1a\n");
+
+        // the source keeps its CRLF, the mirror stays as it is
+        let again = ensure_repo_blocking(&SystemGit, &source, &cfg, &pid("p1")).unwrap();
+        assert_eq!(again.commit, None);
+        assert_eq!(commits(), "2");
+        assert_eq!(fs::read(src.join("main.tex")).unwrap(), b"a\r\nb\r\n");
+    }
+
+    #[test]
+    fn deterministic_init_gives_identical_sources_the_same_root_commit() {
+        let init = || {
+            let root = TempDir::new().unwrap();
+            let mut cfg = Config::for_test(root.path());
+            cfg.deterministic_init = true;
+            let src = cfg.project_source_dir(&pid("p1")).unwrap();
+            fs::create_dir_all(src.join("figures")).unwrap();
+            fs::write(src.join("main.tex"), "hello").unwrap();
+            fs::write(src.join("figures/plot.png"), [0u8, 1, 2]).unwrap();
+            let source = FsProjectSource::new(cfg.clone());
+            ensure_repo_blocking(&SystemGit, &source, &cfg, &pid("p1")).unwrap();
+            let out = SystemGit
+                .run(
+                    &["log", "-1", "--format=%H %at %ct", "master"],
+                    &cfg.bare_repo_dir(&pid("p1")).unwrap(),
+                )
+                .unwrap();
+            assert!(out.status.success());
+            String::from_utf8(out.stdout).unwrap().trim().to_string()
+        };
+
+        let first = init();
+        std::thread::sleep(std::time::Duration::from_millis(1100));
+        assert_eq!(init(), first);
+        let (_, dates) = first.split_once(' ').unwrap();
+        assert_eq!(dates, "0 0");
+    }
+
+    #[test]
+    fn unfetched_sync_commits_are_amended_within_the_window() {
+        let root = TempDir::new().unwrap();
+        let mut cfg = Config::for_test(root.path());
+        cfg.squash_window_minutes = 30;
+        let src = cfg.project_source_dir(&pid("p1")).unwrap();
+        fs::create_dir_all(&src).unwrap();
+        let source = FsProjectSource::new(cfg.clone());
+        let bare = cfg.bare_repo_dir(&pid("p1")).unwrap();
+        let mut edits = 0;
+        let mut sync = |trigger: SyncTrigger| {
+            edits += 1;
+            fs::write(src.join("main.tex"), format!("edit {edits}")).unwrap();
+            let outcome = ensure_repo_blocking(&SystemGit, &source, &cfg, &pid("p1")).unwrap();
+            crate::metadata::record_sync(&cfg, &pid("p1"), trigger, Ok(&outcome));
+            outcome
+        };
+        let commits = || {
+            let out = SystemGit
+                .run(&["rev-list", "--count", "master"], &bare)
+                .unwrap();
+            String::from_utf8(out.stdout).unwrap().trim().to_string()
+        };
+
+        // the root commit is never amended
+        sync(SyncTrigger::Warmup);
+        let first = sync(SyncTrigger::Webhook);
+        assert_eq!(first.squashed, None);
+        let second = sync(SyncTrigger::Webhook);
+        assert_eq!(second.squashed, first.commit);
+        assert_ne!(second.commit, first.commit);
+        assert_eq!(commits(), "2");
+
+        // a fetch since the commit keeps it
+        crate::metadata::record_fetch(&cfg, &pid("p1")).unwrap();
+        let kept = sync(SyncTrigger::Webhook);
+        assert_eq!(kept.squashed, None);
+        assert_eq!(commits(), "3");
+
+        // so does a commit made by a fetch, which served it
+        update_metadata(&cfg, &pid("p1"), |meta| {
+            meta.last_fetch_at = Some(Utc::now() - chrono::Duration::hours(1))
+        })
+        .unwrap();
+        let fetched = sync(SyncTrigger::Fetch);
+        assert_eq!(fetched.squashed, kept.commit);
+        let after = sync(SyncTrigger::Manual);
+        assert_eq!(after.squashed, None);
+        let amended = sync(SyncTrigger::Manual);
+        assert_eq!(amended.squashed, after.commit);
+        assert_eq!(commits(), "4");
+        let out = SystemGit
+            .run(&["cat-file", "-p", "master:main.tex"], &bare)
+            .unwrap();
+        assert_eq!(String::from_utf8(out.stdout).unwrap(), "edit 7");
+    }
+
+    #[test]
+    fn commits_are_dated_by_the_newest_changed_file() {
+        let root = TempDir::new().unwrap();
+        let mut cfg = Config::for_test(root.path());
+        cfg.commit_date_from_source = true;
+        let src = cfg.project_source_dir(&pid("p1")).unwrap();
+        fs::create_dir_all(&src).unwrap();
+        let write = |name: &str, text: &str, secs_ago: u64| {
+            let path = src.join(name);
+            fs::write(&path, text).unwrap();
+            let mtime = std::time::SystemTime::now() - std::time::Duration::from_secs(secs_ago);
+            fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(mtime)
+                .unwrap();
+        };
+        let dates = |bare: &Path| {
+            let out = SystemGit
+                .run(&["log", "-1", "--format=%at %ct", "master"], bare)
+                .unwrap();
+            let out = String::from_utf8(out.stdout).unwrap();
+            let (author, committer) = out.trim().split_once(' ').unwrap();
+            (
+                author.parse::<i64>().unwrap(),
+                committer.parse::<i64>().unwrap(),
+            )
+        };
+        let source = FsProjectSource::new(cfg.clone());
+        let bare = cfg.bare_repo_dir(&pid("p1")).unwrap();
+        let ago = |secs: i64| Utc::now().timestamp() - secs;
+
+        write("main.tex", "hello", 3 * 86_400);
+        write("refs.bib", "@book", 2 * 86_400);
+        ensure_repo_blocking(&SystemGit, &source, &cfg, &pid("p1")).unwrap();
+        let (author, committer) = dates(&bare);
+        assert!((author - ago(2 * 86_400)).abs() <= 2, "{author}");
+        assert!((committer - ago(0)).abs() <= 5, "{committer}");
+
+        // only the changed file counts, not the newer untouched one
+        write("main.tex", "hello again", 86_400);
+        write("refs.bib", "@book", 60);
+        ensure_repo_blocking(&SystemGit, &source, &cfg, &pid("p1")).unwrap();
+        let (author, _) = dates(&bare);
+        assert!((author - ago(86_400)).abs() <= 2, "{author}");
+
+        // never before the previous commit
+        write("main.tex", "hello from the past", 5 * 86_400);
+        ensure_repo_blocking(&SystemGit, &source, &cfg, &pid("p1")).unwrap();
+        assert_eq!(dates(&bare).0, author);
+
+        // a clock running ahead cannot date a commit into the future
+        write("main.tex", "from the future", 0);
+        let future = std::time::SystemTime::now() + std::time::Duration::from_secs(86_400);
+        fs::File::options()
+            .write(true)
+            .open(src.join("main.tex"))
+            .unwrap()
+            .set_modified(future)
+            .unwrap();
+        ensure_repo_blocking(&SystemGit, &source, &cfg, &pid("p1")).unwrap();
+        let (author, _) = dates(&bare);
+        assert!((author - ago(0)).abs() <= 5, "{author}");
+    }
+
+    #[test]
+    fn sync_creates_branch_when_checkout_fails() {
+        let (_root, cfg, source, bare) = setup();
+        let git = ScriptedGit::new().on(&["checkout", "master"], 1, "pathspec did not match");
+
+        sync_existing(&git, &source, &cfg, &pid("p1"), &bare).unwrap();
+
+        let calls = git.calls();
+        let checkout = calls
+            .iter()
+            .position(|c| c == &["checkout", "master"])
+            .expect("checkout attempted");
+        assert_eq!(calls[checkout + 1], ["checkout", "-b", "master"]);
+        // no staged diff by default, so nothing is pushed
+        assert!(!calls.iter().any(|c| c[0] == "push"));
+    }
+
+    #[test]
+    fn sync_fails_when_branch_cannot_be_created() {
+        let (_root, cfg, source, bare) = setup();
+        let git = ScriptedGit::new().on(&["checkout", "master"], 1, "").on(
+            &["checkout", "-b", "master"],
+            128,
+            "fatal: bad ref",
+        );
+
+        let err = sync_existing(&git, &source, &cfg, &pid("p1"), &bare).unwrap_err();
+        assert!(matches!(err, BridgeError::GitFailed(_, ref stderr) if stderr == "fatal: bad ref"));
+    }
+
+    #[test]
+    fn sync_reports_the_new_commit() {
+        let (_root, cfg, source, bare) = setup();
+        let git = ScriptedGit::new()
+            .on(&["diff", "--staged", "--quiet"], 1, "")
+            .on_stdout(&["rev-parse", "HEAD"], b"0123abcd\n")
+            .on_stdout(
+                &[
+                    "diff-tree",
+                    "--root",
+                    "--no-commit-id",
+                    "--name-only",
+                    "-r",
+                    "-z",
+                    "HEAD",
+                ],
+                b"main.tex\0refs.bib\0",
+            )
+            .on_stdout(
+                &["diff", "--staged", "--name-status", "--no-renames", "-z"],
+                b"M\0main.tex\0D\0refs.bib\0",
+            )
+            .on_stdout(
+                &["ls-tree", "-r", "-l", "-z", "HEAD"],
+                b"100644 blob 1111 3\tmain.tex\x00100644 blob 2222 20\trefs.bib\x00",
+            );
+
+        let outcome = sync_existing(&git, &source, &cfg, &pid("p1"), &bare).unwrap();
+        assert_eq!(
+            outcome,
+            SyncOutcome {
+                commit: Some("0123abcd".into()),
+                squashed: None,
+                files_changed: 2,
+                changes: vec![
+                    FileChange {
+                        status: "M".into(),
+                        path: "main.tex".into(),
+                        old_bytes: Some(3),
+                        new_bytes: Some(5),
+                        delta_bytes: 2,
+                    },
+                    FileChange {
+                        status: "D".into(),
+                        path: "refs.bib".into(),
+                        old_bytes: Some(20),
+                        new_bytes: None,
+                        delta_bytes: -20,
+                    },
+                ],
+                source_fingerprint: None,
+            }
+        );
+        let calls = git.calls();
+        let staged = calls.iter().position(|c| c[0] == "ls-tree").unwrap();
+        let commit = calls.iter().position(|c| c[0] == "commit").unwrap();
+        assert!(staged < commit);
+
+        let unchanged =
+            sync_existing(&ScriptedGit::new(), &source, &cfg, &pid("p1"), &bare).unwrap();
+        assert_eq!(unchanged, SyncOutcome::default());
+    }
+
+    #[test]
+    fn sync_surfaces_push_rejection() {
+        let (_root, cfg, source, bare) = setup();
+        let git = ScriptedGit::new()
+            .on(&["diff", "--staged", "--quiet"], 1, "")
+            .on(&["push", "origin", "master"], 1, "! [rejected]");
+
+        let err = sync_existing(&git, &source, &cfg, &pid("p1"), &bare).unwrap_err();
+        assert!(matches!(err, BridgeError::GitFailed(ref cmd, _) if cmd.contains("push")));
+    }
+
+    #[test]
+    fn pending_changes_count_the_staged_diff() {
+        let (_root, cfg, source, _bare) = setup();
+        let git = ScriptedGit::new().on_stdout(
+            &["diff", "--staged", "--name-status", "--no-renames", "-z"],
+            b"M\0main.tex\0A\0refs.bib\0A\0fig.png\0D\0old.tex\0",
+        );
+        let changes = pending_changes(&git, &source, &cfg, &pid("p1")).unwrap();
+        assert_eq!(
+            changes,
+            PendingChanges {
+                added: 2,
+                modified: 1,
+                deleted: 1
+            }
+        );
+        assert!(
+            !git.calls()
+                .iter()
+                .any(|c| c[0] == "commit" || c[0] == "push")
+        );
+        assert!(
+            pending_changes(&ScriptedGit::new(), &source, &cfg, &pid("p1"))
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn git_config_is_reconciled_with_project_overrides() {
+        let (_root, mut cfg, source, bare) = setup();
+        cfg.bare_repo_git_config = vec![
+            ("core.ignoreCase".into(), "false".into()),
+            ("receive.denyNonFastForwards".into(), "true".into()),
+        ];
+        fs::write(
+            cfg.project_source_dir(&pid("p1"))
+                .unwrap()
+                .join(PROJECT_CONFIG_FILE),
+            "[git_config]\n\"core.ignorecase\" = true\n\"gc.auto\" = 0\n\
+             \"core.hooksPath\" = \"/tmp\"\n\"uploadpack.packObjectsHook\" = \"x\"\n",
+        )
+        .unwrap();
+        let git = ScriptedGit::new().on_stdout(
+            &["config", "--get", "receive.denyNonFastForwards"],
+            b"true\n",
+        );
+
+        reconcile_git_config(&git, &source, &cfg, &pid("p1"), &bare);
+        let set: Vec<_> = git
+            .calls()
+            .into_iter()
+            .filter(|c| c[1] == "--replace-all")
+            .map(|c| format!("{}={}", c[2], c[3]))
+            .collect();
+        assert_eq!(set, ["core.ignorecase=true", "gc.auto=0"]);
+    }
+
+    #[test]
+    fn staged_has_changes_maps_exit_codes() {
+        let dir = TempDir::new().unwrap();
+        assert!(!staged_has_changes(&ScriptedGit::new(), dir.path()).unwrap());
+
+        let dirty = ScriptedGit::new().on(&["diff", "--staged", "--quiet"], 1, "");
+        assert!(staged_has_changes(&dirty, dir.path()).unwrap());
+
+        let broken = ScriptedGit::new().on(&["diff", "--staged", "--quiet"], 128, "not a repo");
+        let err = staged_has_changes(&broken, dir.path()).unwrap_err();
+        assert!(matches!(err, BridgeError::Other(ref msg) if msg.contains("Some(128)")));
+    }
+
+    #[test]
+    fn worktree_sync_mirrors_additions_and_removals() {
+        let (root, cfg, source, _bare) = setup();
+        let src_dir = cfg.project_source_dir(&pid("p1")).unwrap();
+        fs::create_dir_all(src_dir.join("figures")).unwrap();
+        fs::write(src_dir.join("figures/plot.png"), [0u8, 1, 2]).unwrap();
+
+        let work = root.path().join("work");
+        fs::create_dir_all(work.join(".git")).unwrap();
+        fs::create_dir_all(work.join("old/nested")).unwrap();
+        fs::write(work.join("old/nested/gone.tex"), "x").unwrap();
+        fs::write(work.join("stale.tex"), "x").unwrap();
+
+        sync_worktree_with_source(&source, &pid("p1"), &work).unwrap();
+
+        assert_eq!(fs::read(work.join("main.tex")).unwrap(), b"hello");
+        assert_eq!(fs::read(work.join("figures/plot.png")).unwrap(), [0, 1, 2]);
+        assert!(!work.join("old").exists());
+        assert!(!work.join("stale.tex").exists());
+        assert!(work.join(".git").is_dir());
+    }
+}
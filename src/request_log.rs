@@ -0,0 +1,240 @@
+//! One log line per HTTP request, `event = "request"`, written once its
+//! response has been sent or dropped: method, path (with `token` query
+//! values redacted), project, auth outcome, status, duration and response
+//! bytes. Every request gets an id, the client's `X-Request-Id` if it sent a
+//! usable one, which is echoed back and set on the request's `request`
+//! span, so that whatever is logged while handling it (the sync, `git
+//! http-backend`) can be told apart from other requests.
+
+use crate::project_id::ProjectId;
+use axum::body::{Body, Bytes, HttpBody};
+use axum::extract::Request;
+use axum::http::{HeaderMap, HeaderValue, Method, Response, Uri};
+use axum::middleware::Next;
+use hyper::body::{Frame, SizeHint};
+use std::pin::Pin;
+use std::sync::{Arc, OnceLock};
+use std::task::{Context, Poll};
+use std::time::Instant;
+use tracing::{Instrument, Span, info, info_span};
+use url::form_urlencoded;
+use uuid::Uuid;
+
+/// Request header naming a request, echoed in the response.
+pub(crate) const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Longest request id taken from a client; longer ones are replaced.
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// Query parameters whose values never reach the log.
+const SECRET_PARAMS: &[&str] = &["token"];
+
+/// What the handler found out about the request being handled.
+#[derive(Default)]
+struct Noted {
+    project_id: OnceLock<String>,
+    auth: OnceLock<&'static str>,
+}
+
+tokio::task_local! {
+    /// Notes of the request the current task is handling
+    static CURRENT: Arc<Noted>;
+}
+
+/// Note the project and the outcome of the credential check (see
+/// `AuthDecision::as_str`) for the log line of the current request.
+pub(crate) fn note(project_id: &ProjectId, auth: &'static str) {
+    let _ = CURRENT.try_with(|noted| {
+        let _ = noted.project_id.set(project_id.to_string());
+        let _ = noted.auth.set(auth);
+    });
+}
+
+/// The id of a request: the client's if it is at most
+/// [`MAX_REQUEST_ID_LEN`] printable ASCII characters, a new UUID otherwise.
+fn request_id(headers: &HeaderMap) -> String {
+    headers
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| {
+            !id.is_empty()
+                && id.len() <= MAX_REQUEST_ID_LEN
+                && id.bytes().all(|b| b.is_ascii_graphic())
+        })
+        .map_or_else(|| Uuid::new_v4().to_string(), str::to_string)
+}
+
+/// Path and query of `uri`, with the values of [`SECRET_PARAMS`] replaced.
+fn redacted_path(uri: &Uri) -> String {
+    let Some(query) = uri.query() else {
+        return uri.path().to_string();
+    };
+    let params: Vec<String> = query
+        .split('&')
+        .map(|param| {
+            let name = param.split('=').next().unwrap_or_default();
+            let decoded = form_urlencoded::parse(name.as_bytes()).next();
+            if decoded.is_some_and(|(name, _)| SECRET_PARAMS.contains(&name.as_ref())) {
+                format!("{name}=REDACTED")
+            } else {
+                param.to_string()
+            }
+        })
+        .collect();
+    format!("{}?{}", uri.path(), params.join("&"))
+}
+
+/// Middleware giving each request its id and span, and logging it once the
+/// response is sent.
+pub(crate) async fn log_requests(req: Request, next: Next) -> Response<Body> {
+    let id = request_id(req.headers());
+    let span = info_span!("request", request_id = %id);
+    let mut entry = Entry {
+        start: Instant::now(),
+        method: req.method().clone(),
+        path: redacted_path(req.uri()),
+        noted: Arc::new(Noted::default()),
+        span: span.clone(),
+        status: 0,
+        bytes: 0,
+        complete: false,
+    };
+    let mut resp = CURRENT
+        .scope(entry.noted.clone(), next.run(req))
+        .instrument(span)
+        .await;
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        resp.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    entry.status = resp.status().as_u16();
+    resp.map(|inner| {
+        Body::new(LoggedBody {
+            inner,
+            entry: Some(entry),
+        })
+    })
+}
+
+/// The log line of a request, written when dropped.
+struct Entry {
+    start: Instant,
+    method: Method,
+    path: String,
+    noted: Arc<Noted>,
+    span: Span,
+    status: u16,
+    /// Response body bytes sent
+    bytes: u64,
+    /// Whether the whole response body was sent
+    complete: bool,
+}
+
+impl Drop for Entry {
+    fn drop(&mut self) {
+        let _entered = self.span.enter();
+        info!(
+            event = "request",
+            method = %self.method,
+            path = %self.path,
+            project_id = self.noted.project_id.get().map(String::as_str),
+            auth = self.noted.auth.get().copied(),
+            status = self.status,
+            duration_ms = self.start.elapsed().as_millis() as u64,
+            bytes = self.bytes,
+            complete = self.complete,
+            "request"
+        );
+    }
+}
+
+/// A response body counting what is sent, logging its request once sent or
+/// dropped.
+struct LoggedBody {
+    inner: Body,
+    entry: Option<Entry>,
+}
+
+impl HttpBody for LoggedBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, axum::Error>>> {
+        let polled = Pin::new(&mut self.inner).poll_frame(cx);
+        if let Poll::Ready(Some(Ok(frame))) = &polled
+            && let (Some(data), Some(entry)) = (frame.data_ref(), self.entry.as_mut())
+        {
+            entry.bytes += data.len() as u64;
+        }
+        if self.inner.is_end_stream()
+            && let Some(mut entry) = self.entry.take()
+        {
+            entry.complete = true;
+        }
+        polled
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::{AppState, build_router};
+    use axum::body::to_bytes;
+    use tower::ServiceExt;
+
+    #[test]
+    fn tokens_are_redacted_from_logged_paths() {
+        let path = |uri: &str| redacted_path(&uri.parse().unwrap());
+        assert_eq!(path("/git/p1.git/info/refs"), "/git/p1.git/info/refs");
+        assert_eq!(
+            path("/git/p1.git/info/refs?service=git-upload-pack&token=s3cret"),
+            "/git/p1.git/info/refs?service=git-upload-pack&token=REDACTED"
+        );
+        assert_eq!(
+            path("/archive/p1.zip?tok%65n=s3cret&at=x"),
+            "/archive/p1.zip?tok%65n=REDACTED&at=x"
+        );
+        assert_eq!(path("/a?tokens=1&token"), "/a?tokens=1&token=REDACTED");
+    }
+
+    #[tokio::test]
+    async fn request_ids_are_taken_or_made_and_echoed() {
+        let root = tempfile::TempDir::new().unwrap();
+        let app = build_router(Arc::new(AppState::new(Config::for_test(root.path()))));
+        let id_of = |sent: Option<&str>| {
+            let mut req = axum::http::Request::get("/");
+            if let Some(sent) = sent {
+                req = req.header(REQUEST_ID_HEADER, sent);
+            }
+            let req = req.body(Body::empty()).unwrap();
+            let app = app.clone();
+            async move {
+                let resp = app.oneshot(req).await.unwrap();
+                let id = resp.headers()[REQUEST_ID_HEADER]
+                    .to_str()
+                    .unwrap()
+                    .to_string();
+                to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+                id
+            }
+        };
+
+        assert_eq!(id_of(Some("lb-7f3a.42")).await, "lb-7f3a.42");
+        for unusable in [None, Some("two words"), Some(&"x".repeat(200)[..])] {
+            let id = id_of(unusable).await;
+            assert!(Uuid::parse_str(&id).is_ok(), "{unusable:?}: {id}");
+        }
+        assert_ne!(id_of(None).await, id_of(None).await);
+    }
+}
@@ -0,0 +1,210 @@
+use crate::config::Config;
+use crate::error::BridgeError;
+use crate::git::GitRunner;
+use crate::metadata::{load_metadata, update_metadata};
+use crate::project_id::ProjectId;
+use crate::repo::run_git;
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+use tempfile::TempDir;
+use thiserror::Error;
+use tracing::info;
+
+/// Largest bundle accepted by the restore endpoint.
+pub(crate) const MAX_RESTORE_BUNDLE_BYTES: usize = 512 * 1024 * 1024;
+
+/// File name of the uploaded bundle inside the staging directory.
+const UPLOAD_FILE: &str = "upload.bundle";
+
+/// Name of the repository built inside the staging directory.
+const STAGED_REPO: &str = "restored.git";
+
+#[derive(Debug, Error)]
+pub(crate) enum RestoreError {
+    #[error("not a valid, self-contained git bundle: {0}")]
+    InvalidBundle(String),
+    #[error("bundle has no branch refs/heads/{0}; use ?force=true to restore anyway")]
+    MissingBranch(String),
+    #[error(transparent)]
+    Git(#[from] BridgeError),
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub(crate) struct RestoreOutcome {
+    /// Refs found in the bundle
+    pub(crate) heads: Vec<String>,
+    /// Whether the read-only branch was among them
+    pub(crate) branch_present: bool,
+}
+
+/// Ref names listed by `git bundle list-heads`.
+fn parse_bundle_heads(stdout: &[u8]) -> Vec<String> {
+    String::from_utf8_lossy(stdout)
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        .map(|(_, name)| name.trim().to_string())
+        .filter(|name| name.starts_with("refs/"))
+        .collect()
+}
+
+/// Replace the bare mirror of `project_id` with the branches and tags of
+/// `bundle`. The repository is built next to the mirror and swapped in by
+/// renaming; the sync metadata of the old mirror is kept. Callers must hold
+/// the project lock. Blocking.
+pub(crate) fn restore_bundle(
+    git: &dyn GitRunner,
+    cfg: &Config,
+    project_id: &ProjectId,
+    bundle: &[u8],
+    force: bool,
+) -> Result<RestoreOutcome, RestoreError> {
+    fs::create_dir_all(&cfg.git_root).map_err(BridgeError::Io)?;
+    let staging_dir = TempDir::new_in(&cfg.git_root).map_err(|e| {
+        BridgeError::Other(format!(
+            "failed to create tempdir in {}: {e}",
+            cfg.git_root.display()
+        ))
+    })?;
+    let staging = staging_dir.path();
+    fs::write(staging.join(UPLOAD_FILE), bundle).map_err(BridgeError::Io)?;
+
+    let repo = staging.join(STAGED_REPO);
+    fs::create_dir(&repo).map_err(BridgeError::Io)?;
+    run_git(git, &["init", "--bare", "--quiet"], &repo)?;
+
+    let upload = format!("../{UPLOAD_FILE}");
+    let verify = git.run(&["bundle", "verify", "--quiet", &upload], &repo)?;
+    if !verify.status.success() {
+        let stderr = String::from_utf8_lossy(&verify.stderr);
+        return Err(RestoreError::InvalidBundle(stderr.trim().to_string()));
+    }
+
+    let listed = git.run(&["bundle", "list-heads", &upload], &repo)?;
+    if !listed.status.success() {
+        let stderr = String::from_utf8_lossy(&listed.stderr);
+        return Err(RestoreError::InvalidBundle(stderr.trim().to_string()));
+    }
+    let heads = parse_bundle_heads(&listed.stdout);
+    let branch = format!("refs/heads/{}", cfg.readonly_branch);
+    let branch_present = heads.contains(&branch);
+    if !branch_present && !force {
+        return Err(RestoreError::MissingBranch(cfg.readonly_branch.clone()));
+    }
+
+    run_git(
+        git,
+        &[
+            "fetch",
+            "--quiet",
+            &upload,
+            "refs/heads/*:refs/heads/*",
+            "refs/tags/*:refs/tags/*",
+        ],
+        &repo,
+    )?;
+    run_git(git, &["symbolic-ref", "HEAD", &branch], &repo)?;
+
+    let mirror = cfg.bare_repo_dir(project_id)?;
+    let previous = mirror.is_dir().then(|| load_metadata(cfg, project_id));
+    swap_into_place(&repo, &mirror, staging)?;
+    update_metadata(cfg, project_id, |meta| {
+        if let Some(previous) = previous {
+            *meta = previous;
+        }
+        // the bundle is not the source, so the next fetch must sync
+        meta.source_fingerprint = None;
+    })?;
+    crate::gitweb::mirror_changed(cfg, project_id);
+    info!(%project_id, heads = heads.len(), "mirror restored from bundle");
+
+    Ok(RestoreOutcome {
+        heads,
+        branch_present,
+    })
+}
+
+/// Move `repo` to `mirror`, parking an existing mirror in `staging` (which
+/// is removed afterwards) so the mirror is never half-written.
+fn swap_into_place(repo: &Path, mirror: &Path, staging: &Path) -> Result<(), BridgeError> {
+    if mirror.exists() {
+        fs::rename(mirror, staging.join("previous.git"))?;
+    } else if let Some(parent) = mirror.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::rename(repo, mirror)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git::mock::ScriptedGit;
+    use crate::project_id::pid;
+
+    const HEADS: &[u8] = b"1111111111111111111111111111111111111111 refs/heads/master\n\
+        2222222222222222222222222222222222222222 refs/tags/v1\n\
+        1111111111111111111111111111111111111111 HEAD\n";
+
+    #[test]
+    fn bundle_heads_are_parsed() {
+        assert_eq!(
+            parse_bundle_heads(HEADS),
+            ["refs/heads/master", "refs/tags/v1"]
+        );
+    }
+
+    #[test]
+    fn restore_replaces_the_mirror_and_keeps_metadata() {
+        let root = tempfile::TempDir::new().unwrap();
+        let cfg = Config::for_test(root.path());
+        let mirror = cfg.bare_repo_dir(&pid("p1")).unwrap();
+        fs::create_dir_all(&mirror).unwrap();
+        fs::write(mirror.join("stale"), "old").unwrap();
+        update_metadata(&cfg, &pid("p1"), |meta| {
+            meta.last_error = Some("kept".into());
+            meta.source_fingerprint = Some("synced".into());
+        })
+        .unwrap();
+
+        let git =
+            ScriptedGit::new().on_stdout(&["bundle", "list-heads", "../upload.bundle"], HEADS);
+        let outcome = restore_bundle(&git, &cfg, &pid("p1"), b"bundle", false).unwrap();
+        assert!(outcome.branch_present);
+        assert!(!mirror.join("stale").exists());
+        let meta = load_metadata(&cfg, &pid("p1"));
+        assert_eq!(meta.last_error.as_deref(), Some("kept"));
+        assert_eq!(meta.source_fingerprint, None);
+        assert!(git.calls().iter().any(|c| c[0] == "fetch"));
+    }
+
+    #[test]
+    fn bundles_without_the_branch_need_force() {
+        let root = tempfile::TempDir::new().unwrap();
+        let cfg = Config::for_test(root.path());
+        let heads = b"1111111111111111111111111111111111111111 refs/heads/main\n";
+        let git =
+            ScriptedGit::new().on_stdout(&["bundle", "list-heads", "../upload.bundle"], heads);
+
+        let err = restore_bundle(&git, &cfg, &pid("p1"), b"bundle", false).unwrap_err();
+        assert!(matches!(err, RestoreError::MissingBranch(_)));
+        assert!(!cfg.bare_repo_dir(&pid("p1")).unwrap().exists());
+
+        let outcome = restore_bundle(&git, &cfg, &pid("p1"), b"bundle", true).unwrap();
+        assert!(!outcome.branch_present);
+        assert!(cfg.bare_repo_dir(&pid("p1")).unwrap().is_dir());
+    }
+
+    #[test]
+    fn unverifiable_bundles_are_rejected() {
+        let root = tempfile::TempDir::new().unwrap();
+        let cfg = Config::for_test(root.path());
+        let git = ScriptedGit::new().on(
+            &["bundle", "verify", "--quiet", "../upload.bundle"],
+            1,
+            "error: Repository lacks these prerequisite commits",
+        );
+        let err = restore_bundle(&git, &cfg, &pid("p1"), b"bundle", true).unwrap_err();
+        assert!(matches!(err, RestoreError::InvalidBundle(_)));
+    }
+}
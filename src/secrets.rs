@@ -0,0 +1,165 @@
+use argon2::Algorithm;
+use argon2::password_hash::PasswordHash;
+#[cfg(feature = "admin-ui")]
+use argon2::{Argon2, password_hash::PasswordVerifier};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::RwLock;
+use std::time::SystemTime;
+use tracing::{info, warn};
+
+/// Outcome of [`ReloadableSecret::reload`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Reload {
+    Unchanged,
+    Changed,
+    /// The file could not be read; the previous value stays active.
+    Failed,
+}
+
+/// A secret that is either fixed or read from a file, in which case it is
+/// re-read whenever the file's mtime changes.
+pub(crate) struct ReloadableSecret {
+    path: Option<PathBuf>,
+    state: RwLock<(Option<String>, Option<SystemTime>)>,
+}
+
+impl ReloadableSecret {
+    pub(crate) fn new(initial: Option<String>, path: Option<PathBuf>) -> Self {
+        let mtime = path
+            .as_ref()
+            .and_then(|p| fs::metadata(p).and_then(|m| m.modified()).ok());
+        Self {
+            path,
+            state: RwLock::new((initial, mtime)),
+        }
+    }
+
+    pub(crate) fn current(&self) -> Option<String> {
+        self.state.read().expect("secret lock poisoned").0.clone()
+    }
+
+    /// Re-read the backing file if it changed. Missing or empty files (e.g. in
+    /// the middle of a rotation) keep the old value.
+    pub(crate) fn reload(&self) -> Reload {
+        let Some(path) = &self.path else {
+            return Reload::Unchanged;
+        };
+
+        let mtime = match fs::metadata(path).and_then(|m| m.modified()) {
+            Ok(m) => m,
+            Err(e) => {
+                warn!(path = %path.display(), error = %e, "secret file unavailable, keeping previous value");
+                return Reload::Failed;
+            }
+        };
+        if self.state.read().expect("secret lock poisoned").1 == Some(mtime) {
+            return Reload::Unchanged;
+        }
+
+        let value = match fs::read_to_string(path) {
+            Ok(v) if !v.trim().is_empty() => v.trim().to_string(),
+            Ok(_) => {
+                warn!(path = %path.display(), "secret file is empty, keeping previous value");
+                return Reload::Failed;
+            }
+            Err(e) => {
+                warn!(path = %path.display(), error = %e, "cannot read secret file, keeping previous value");
+                return Reload::Failed;
+            }
+        };
+
+        let mut state = self.state.write().expect("secret lock poisoned");
+        state.1 = Some(mtime);
+        if state.0.as_deref() == Some(value.as_str()) {
+            return Reload::Unchanged;
+        }
+        state.0 = Some(value);
+        info!(path = %path.display(), "secret file changed, reloaded");
+        Reload::Changed
+    }
+}
+
+/// Whether `hash` is an argon2 hash in PHC string format, as printed by the
+/// `argon2` tool (`$argon2id$v=19$m=...,t=...,p=...$<salt>$<hash>`).
+pub(crate) fn is_password_hash(hash: &str) -> bool {
+    PasswordHash::new(hash).is_ok_and(|parsed| Algorithm::try_from(parsed.algorithm).is_ok())
+}
+
+/// Check `password` against an argon2 hash, with the parameters stored in
+/// the hash. This takes as long as those parameters ask for, so call it off
+/// the async runtime.
+#[cfg(feature = "admin-ui")]
+pub(crate) fn verify_password_hash(hash: &str, password: &str) -> bool {
+    PasswordHash::new(hash).is_ok_and(|parsed| {
+        Argon2::default()
+            .verify_password(password.as_bytes(), &parsed)
+            .is_ok()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tempfile::TempDir;
+
+    fn touch(path: &std::path::Path, secs_ahead: u64) {
+        fs::File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(SystemTime::now() + Duration::from_secs(secs_ahead))
+            .unwrap();
+    }
+
+    #[test]
+    fn reload_swaps_value_and_survives_missing_file() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("admin_password");
+        fs::write(&path, "old\n").unwrap();
+        let secret = ReloadableSecret::new(Some("old".into()), Some(path.clone()));
+
+        assert_eq!(secret.reload(), Reload::Unchanged);
+
+        fs::write(&path, "new\n").unwrap();
+        touch(&path, 5);
+        assert_eq!(secret.reload(), Reload::Changed);
+        assert_eq!(secret.current().as_deref(), Some("new"));
+
+        fs::remove_file(&path).unwrap();
+        assert_eq!(secret.reload(), Reload::Failed);
+        assert_eq!(secret.current().as_deref(), Some("new"));
+
+        fs::write(&path, "").unwrap();
+        touch(&path, 10);
+        assert_eq!(secret.reload(), Reload::Failed);
+        assert_eq!(secret.current().as_deref(), Some("new"));
+    }
+
+    #[cfg(feature = "admin-ui")]
+    #[test]
+    fn argon2_hashes_are_recognized_and_verified() {
+        use argon2::password_hash::{PasswordHasher, SaltString};
+
+        let salt = SaltString::encode_b64(b"gitbridge-salt").unwrap();
+        let hash = Argon2::default()
+            .hash_password(b"pw", &salt)
+            .unwrap()
+            .to_string();
+        assert!(hash.starts_with("$argon2id$"));
+        assert!(is_password_hash(&hash));
+        assert!(verify_password_hash(&hash, "pw"));
+        assert!(!verify_password_hash(&hash, "pw "));
+        assert!(!verify_password_hash(&hash, ""));
+
+        for bad in [
+            "pw",
+            "$2b$12$R9h/cIPz0gi.URNNX3kh2OPST9/PgBkqquzi.Ss7KIUgO2t0jWMUW",
+            "$pbkdf2-sha256$i=1000$c2FsdA$aGFzaA",
+        ] {
+            assert!(!is_password_hash(bad), "{bad}");
+            assert!(!verify_password_hash(bad, "pw"), "{bad}");
+        }
+    }
+}
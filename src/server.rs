@@ -0,0 +1,2872 @@
+use crate::AppState;
+use crate::access_log::{AccessEntry, AccessKind};
+use crate::activity::ActivityEvent;
+use crate::archive;
+#[cfg(feature = "admin-ui")]
+use crate::audit::AuditEvent;
+use crate::auth::{
+    AuthDecision, AuthSources, authorize, extract_tokens, log_auth_failure, token_hash,
+};
+use crate::coalesce::AdvertisementKey;
+use crate::config::Config;
+use crate::deploy_tokens::DeployTokenCheck;
+use crate::error::BridgeError;
+use crate::git::{GitRunner, SystemGit};
+use crate::git_http::{
+    BackendBody, GitEndpoint, RequestBody, body_encoding, git_error_response, is_dumb_object_file,
+    is_git_client, no_cache_headers, read_head_file, run_git_http_backend,
+};
+use crate::gitweb;
+use crate::ip_allowlist::{client_ip, is_allowed};
+use crate::landing::{is_browser_request, landing_page};
+use crate::locks::LockHolder;
+use crate::metadata::{SyncTrigger, load_metadata, record_fetch, record_sync};
+use crate::project_id::ProjectId;
+use crate::project_settings::{load_project_settings, public_source};
+use crate::repo::{SyncOutcome, ensure_repo, is_external_repo, snapshot_fingerprint};
+use crate::request_log;
+use crate::source::ProjectSource;
+use crate::sync_events;
+use crate::timing::{self, Phase, RequestTimer};
+use crate::token_store::Decision;
+#[cfg(feature = "admin-ui")]
+use crate::webhook::fingerprint;
+use crate::webhook_queue::WebhookKind;
+use async_trait::async_trait;
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, State},
+    http::{HeaderMap, HeaderValue, Method, Request, Response, StatusCode, header},
+    response::IntoResponse,
+};
+use chrono::Utc;
+use serde_json::json;
+use std::io::{ErrorKind, Read};
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Instant;
+use tracing::{Span, debug, error, info, warn};
+use url::form_urlencoded;
+
+/// Request header overriding the pre-fetch sync: `skip` or `force`.
+const SYNC_HEADER: &str = "x-gitbridge-sync";
+
+/// Response header marking a mirror served after its sync failed.
+const STALE_HEADER: &str = "x-gitbridge-stale";
+
+/// Stays `200` in maintenance mode, so orchestrators do not restart the
+/// bridge, but says so in the body.
+pub(crate) async fn health(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    match state.maintenance.current() {
+        Some(maintenance) => (
+            StatusCode::OK,
+            format!(
+                "sharelatex-gitbridge-ro up, in maintenance mode since {}: {}\n",
+                maintenance.since.to_rfc3339(),
+                maintenance.message
+            ),
+        ),
+        None => (StatusCode::OK, "sharelatex-gitbridge-ro up\n".to_string()),
+    }
+}
+
+/// The checks of [`crate::readiness`] as JSON: `200` if all passed, `503`
+/// otherwise.
+pub(crate) async fn readiness(State(state): State<Arc<AppState>>) -> Response<Body> {
+    let report = state.readiness.report(&state).await;
+    let status = if report.ok {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    let mut resp = Response::new(Body::from(json!(report).to_string()));
+    *resp.status_mut() = status;
+    *resp.headers_mut() = no_cache_headers();
+    resp.headers_mut().insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/json"),
+    );
+    resp
+}
+
+/// Core handler for Git Smart HTTP.
+/// Matches /git/<projectId>.git/... for *any* method, but we only really do GET/POST.
+/// Projects of a named source are at /git/<source>/<projectId>.git/...
+///
+/// Every response, including the errors generated here, tells proxies not
+/// to cache it: a cached advertisement serves stale refs. Only objects and
+/// packs fetched over the dumb protocol keep the caching headers of
+/// `git http-backend`, which may let them be cached for good.
+pub(crate) async fn git_handler(
+    State(state): State<Arc<AppState>>,
+    req: Request<Body>,
+) -> Response<Body> {
+    // tail e.g. "1234567890abcdef.git/info/refs"; taken from the URI rather
+    // than the route capture, which axum has already percent-decoded
+    let tail = req.uri().path().strip_prefix("/git/").unwrap_or_default();
+    let (state, tail) = route_source(&state, tail);
+    let tail = tail.to_string();
+    let dumb_object = parse_git_path(&tail).is_ok_and(|path| is_dumb_object_file(&path.endpoint));
+    let timer = RequestTimer::start(state.maintenance.begin(), state.timings.clone(), &req);
+    let mut resp = timer.scope(git_response(state.clone(), &tail, req)).await;
+    if !(dumb_object && resp.status().is_success()) {
+        set_no_cache(resp.headers_mut());
+    }
+    timer.finish(resp, state.cfg.git_timing_header)
+}
+
+/// The state of the source a `/git/` tail addresses and the tail below it:
+/// `<source>/<id>.git/...` for a named source, `<id>.git/...` for the
+/// default one.
+fn route_source<'a>(state: &Arc<AppState>, tail: &'a str) -> (Arc<AppState>, &'a str) {
+    if let Some((name, rest)) = tail.split_once('/')
+        && let Some(source) = state.sources.get(name)
+    {
+        return (source.clone(), rest);
+    }
+    (state.clone(), tail)
+}
+
+/// Whether the client is inside `GIT_ALLOWED_CIDRS`. Requests whose peer
+/// address is unknown (no [`ConnectInfo`]) are refused when the list is set.
+fn source_allowed(cfg: &Config, req: &Request<Body>) -> bool {
+    let Some(cidrs) = cfg.git_allowed_cidrs.as_deref() else {
+        return true;
+    };
+    let client = client_address(cfg, req);
+    let allowed = client.is_some_and(|ip| is_allowed(Some(cidrs), ip));
+    if !allowed {
+        info!(client = ?client, "git request from outside GIT_ALLOWED_CIDRS refused");
+    }
+    allowed
+}
+
+/// The client's address, behind trusted proxies the one they forwarded;
+/// `None` without [`ConnectInfo`].
+fn client_address(cfg: &Config, req: &Request<Body>) -> Option<IpAddr> {
+    req.extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(peer)| client_ip(peer.ip(), req.headers(), &cfg.trusted_proxies))
+}
+
+/// Add [`no_cache_headers`], keeping a stricter `no-store`.
+fn set_no_cache(headers: &mut HeaderMap) {
+    let no_store = headers
+        .get(header::CACHE_CONTROL)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.contains("no-store"));
+    for (name, value) in &no_cache_headers() {
+        if !(no_store && name == header::CACHE_CONTROL) {
+            headers.insert(name, value.clone());
+        }
+    }
+}
+
+/// Answer a git request; it counts as in flight through its [`RequestTimer`],
+/// taken by [`git_handler`].
+async fn git_response(state: Arc<AppState>, tail: &str, req: Request<Body>) -> Response<Body> {
+    if !source_allowed(&state.cfg, &req) {
+        return response_with_status(StatusCode::FORBIDDEN, "forbidden\n");
+    }
+    if let Some(maintenance) = state.maintenance.current() {
+        let mut resp = unavailable_response(req.headers(), &maintenance.message);
+        resp.headers_mut()
+            .insert(header::RETRY_AFTER, maintenance.retry_after_seconds.into());
+        return resp;
+    }
+
+    let GitPath {
+        project_id,
+        endpoint,
+    } = match parse_git_path(tail) {
+        Ok(path) if state.cfg.accepts_project_id(&path.project_id) => path,
+        Ok(_) => return response_400("invalid project id"),
+        Err(msg) => return response_400(msg),
+    };
+    let project_id = &project_id;
+    let remaining = endpoint.as_str();
+
+    // --- Auth ---
+    let tokens = extract_tokens(&req);
+    let decision =
+        match timing::measure(Phase::Auth, authorize(state.as_ref(), &tokens, project_id)).await {
+            Ok(decision) => decision,
+            Err(e) => {
+                error!(%project_id, "cannot check credentials: {e}");
+                return response_500("internal auth error");
+            }
+        };
+    state.auth_stats.record(&decision);
+    request_log::note(project_id, decision.as_str());
+    let authed = decision.is_granted();
+
+    // --- Landing page for browsers ---
+    if is_browser_request(&req, remaining) {
+        let meta = if authed {
+            let cfg = state.cfg.clone();
+            let project_id_owned = project_id.clone();
+            tokio::task::spawn_blocking(move || load_metadata(&cfg, &project_id_owned))
+                .await
+                .ok()
+        } else {
+            None
+        };
+        return landing_page(&state.cfg, project_id, meta.as_ref());
+    }
+
+    if !authed {
+        log_auth_failure(&decision, &tokens, project_id);
+        #[cfg(feature = "admin-ui")]
+        audit_misused_token(&state, project_id, &decision);
+        return unauthorized_response(&state.cfg, project_id, &decision);
+    }
+    // managed tokens are only known by their hash from here on
+    let managed_token = decision.managed_token().map(token_hash);
+    if let Some(token) = &managed_token {
+        note_token_use(&state, token);
+    }
+
+    // --- Endpoint ---
+    // Only git's smart HTTP endpoints, and dumb object files if allowed, are
+    // served; the backend never sees the client's path.
+    let endpoint = GitEndpoint::from_path(remaining);
+    if endpoint.is_none() && !(state.cfg.git_allow_dumb && is_dumb_object_file(remaining)) {
+        return response_with_status(StatusCode::NOT_FOUND, "not found\n");
+    }
+    if endpoint == Some(GitEndpoint::ReceivePack) || is_receive_pack(req.uri().query()) {
+        return git_refusal(
+            req.headers(),
+            req.uri().query(),
+            "push is disabled on this read-only mirror",
+        )
+        .unwrap_or_else(|| {
+            response_with_status(StatusCode::FORBIDDEN, "push disabled (read-only)\n")
+        });
+    }
+    let method_allowed = match endpoint {
+        Some(endpoint) => endpoint.allows(req.method()),
+        None => matches!(*req.method(), Method::GET | Method::HEAD),
+    };
+    if !method_allowed {
+        return response_with_status(StatusCode::METHOD_NOT_ALLOWED, "method not allowed\n");
+    }
+    // a mirror that is a symlink leading out of GIT_ROOT is never served
+    if let Err(e) = state.cfg.bare_repo_dir(project_id) {
+        warn!(%project_id, "not serving the mirror: {e}");
+        return git_refusal(
+            req.headers(),
+            req.uri().query(),
+            &format!("project {project_id} not found"),
+        )
+        .unwrap_or_else(|| response_with_status(StatusCode::NOT_FOUND, "project not found\n"));
+    }
+    if endpoint == Some(GitEndpoint::Status) {
+        return project_status(&state, project_id).await;
+    }
+    let encoding = match body_encoding(req.headers()) {
+        Ok(encoding) => encoding,
+        Err(encoding) => {
+            return response_with_status(
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                &format!("unsupported Content-Encoding {encoding:?}, only gzip is accepted\n"),
+            );
+        }
+    };
+
+    // --- Quota ---
+    // traffic is accounted to managed tokens; project tokens are unlimited
+    if let Some(token) = &managed_token
+        && let Err(resp) = check_quota(&state, token).await
+    {
+        return resp;
+    }
+    log_access(
+        &state,
+        project_id,
+        &decision,
+        managed_token.as_deref(),
+        endpoint,
+        client_address(&state.cfg, &req),
+    );
+
+    // --- Sync repo ---
+    let stale = match sync_before_read(&state, project_id, req.headers(), req.uri().query()).await {
+        Ok(stale) => stale,
+        Err(resp) => return resp,
+    };
+    if matches!(
+        endpoint,
+        Some(GitEndpoint::InfoRefs | GitEndpoint::UploadPack)
+    ) {
+        note_fetch_before_serving(&state, project_id).await;
+    }
+
+    // --- Call git http-backend ---
+    let method = req.method().clone();
+    let query = req.uri().query().map(|s| s.to_string());
+    // hyper sends the interim `100 Continue` itself when the backend first
+    // reads the body; the expectation is handled and must not reach it.
+    let mut headers = req.headers().clone();
+    headers.remove(header::EXPECT);
+    match encoding {
+        Some(encoding) => {
+            headers.insert(header::CONTENT_ENCODING, HeaderValue::from_static(encoding));
+        }
+        None => {
+            headers.remove(header::CONTENT_ENCODING);
+        }
+    }
+    let request_body = Arc::new(RequestBody::stream(req.into_body()));
+
+    let backend_start = Instant::now();
+    let backend_res = match endpoint {
+        Some(GitEndpoint::Head) => read_head_file(&state.cfg, project_id)
+            .map(|(status, headers, body)| (status, headers, BackendBody::from(body))),
+        Some(GitEndpoint::InfoRefs) if method == Method::GET => {
+            let key = AdvertisementKey {
+                project_id: project_id.clone(),
+                query: query.clone().unwrap_or_default(),
+                git_protocol: headers
+                    .get("git-protocol")
+                    .and_then(|v| v.to_str().ok())
+                    .unwrap_or_default()
+                    .to_string(),
+                auth: decision.as_str(),
+            };
+            advertise_refs(&state, key, headers.clone())
+                .await
+                .map(|(status, headers, body)| (status, headers, BackendBody::from(body)))
+        }
+        _ => {
+            stream_to_backend(
+                &state.cfg,
+                project_id,
+                endpoint.map_or(remaining, |e| e.path()),
+                method.clone(),
+                query.clone(),
+                headers.clone(),
+                request_body.clone(),
+            )
+            .await
+        }
+    };
+    timing::record(Phase::Backend, backend_start.elapsed());
+    let backend_res = match backend_res {
+        Ok(r) => r,
+        Err(BridgeError::ClientAborted(msg)) => {
+            state
+                .transfers
+                .client_aborted(&format_args!("{project_id}: {msg}"));
+            return response_500("git backend error");
+        }
+        Err(e) => {
+            state.transfers.backend_failed();
+            error!("git http-backend error: {e}");
+            return response_500("git backend error");
+        }
+    };
+
+    let (status, backend_headers, body) = backend_res;
+    let transfers = state.transfers.clone();
+    let mut body = body.on_failure(move || transfers.backend_failed());
+    if status.is_success()
+        && matches!(
+            endpoint,
+            Some(GitEndpoint::InfoRefs | GitEndpoint::UploadPack)
+        )
+    {
+        note_fetch(&state, project_id);
+    }
+    if let Some(token) = managed_token {
+        match body.len() {
+            Some(sent) => record_usage(&state, token, request_body.len(), sent).await,
+            // streamed: counted once sent
+            None => {
+                let usage_state = state.clone();
+                body = body.on_end(move |sent| {
+                    let received = request_body.len();
+                    tokio::spawn(
+                        async move { record_usage(&usage_state, token, received, sent).await },
+                    );
+                });
+            }
+        }
+    }
+    let mut builder = axum::http::Response::builder().status(status);
+
+    if let Some(headers_mut) = builder.headers_mut() {
+        for (name, value) in backend_headers.iter() {
+            headers_mut.append(name.clone(), value.clone());
+        }
+        if stale {
+            headers_mut.insert(STALE_HEADER, HeaderValue::from_static("true"));
+        }
+    } else {
+        return response_500("failed to build response");
+    }
+
+    builder
+        .body(Body::new(body))
+        .unwrap_or_else(|_| response_500("failed to build response"))
+}
+
+/// The pre-fetch sync of a read request, honouring the sync mode it asked
+/// for. `Ok(true)` if it failed but the existing mirror may be served stale;
+/// the response to send instead if it cannot be served at all.
+async fn sync_before_read(
+    state: &AppState,
+    project_id: &ProjectId,
+    headers: &HeaderMap,
+    query: Option<&str>,
+) -> Result<bool, Response<Body>> {
+    let sync_mode = requested_sync_mode(headers, query);
+    let mirror_exists = match state.cfg.bare_repo_dir(project_id) {
+        Ok(repo) => tokio::fs::try_exists(repo).await.unwrap_or(false),
+        Err(_) => false,
+    };
+    if sync_mode != SyncMode::Auto {
+        // overrides only apply to an existing mirror; a first clone always syncs
+        info!(
+            event = "sync_override",
+            %project_id,
+            sync = sync_mode.as_str(),
+            applied = mirror_exists,
+            "git request asked to {} the pre-fetch sync",
+            sync_mode.as_str()
+        );
+    }
+    let synced = timing::measure(Phase::Sync, async {
+        match sync_mode {
+            _ if !mirror_exists => prepare_mirror(state, project_id).await,
+            SyncMode::Auto => sync_for_fetch(state, project_id).await,
+            SyncMode::Skip => Ok(()),
+            SyncMode::Force => sync_project(state, project_id, SyncTrigger::Fetch)
+                .await
+                .map(drop),
+        }
+    })
+    .await;
+    match synced {
+        Ok(()) => Ok(false),
+        Err(BridgeError::ProjectNotFound(_)) => Err(git_refusal(
+            headers,
+            query,
+            &format!("project {project_id} not found"),
+        )
+        .unwrap_or_else(|| response_with_status(StatusCode::NOT_FOUND, "project not found\n"))),
+        Err(BridgeError::MirrorPreparing(_)) => Err(with_retry_after(
+            unavailable_response(headers, PREPARING_MESSAGE),
+            &state.cfg,
+        )),
+        Err(BridgeError::ProjectBusy(_)) => Err(with_retry_after(
+            response_with_status(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "project is busy, try again later\n",
+            ),
+            &state.cfg,
+        )),
+        // recorded and alerted on by the sync; the mirror stays readable
+        Err(e @ BridgeError::RepoTooLarge(_)) => {
+            warn!(%project_id, "not synced, serving the existing mirror: {e}");
+            Ok(true)
+        }
+        Err(e) if mirror_exists && serves_stale(state, project_id).await => {
+            warn!(%project_id, "sync failed, serving the existing mirror: {e}");
+            Ok(true)
+        }
+        Err(e) => {
+            error!("ensure_repo error: {e}");
+            Err(response_500("repo sync error"))
+        }
+    }
+}
+
+/// Zip of a project's snapshot, `/archive/<id>.zip`, or of the snapshot
+/// current at `?at=<RFC 3339 time>`; `/archive/<source>/<id>.zip` for a
+/// named source. Authorized, synced and timed like a git fetch.
+pub(crate) async fn archive_handler(
+    State(state): State<Arc<AppState>>,
+    req: Request<Body>,
+) -> Response<Body> {
+    let tail = req
+        .uri()
+        .path()
+        .strip_prefix("/archive/")
+        .unwrap_or_default();
+    let (state, tail) = route_source(&state, tail);
+    let tail = tail.to_string();
+    let timer = RequestTimer::start(state.maintenance.begin(), state.timings.clone(), &req);
+    let mut resp = timer
+        .scope(archive_response(state.clone(), &tail, req))
+        .await;
+    set_no_cache(resp.headers_mut());
+    timer.finish(resp, state.cfg.git_timing_header)
+}
+
+async fn archive_response(state: Arc<AppState>, tail: &str, req: Request<Body>) -> Response<Body> {
+    if !source_allowed(&state.cfg, &req) {
+        return response_with_status(StatusCode::FORBIDDEN, "forbidden\n");
+    }
+    if let Some(maintenance) = state.maintenance.current() {
+        let mut resp = unavailable_response(req.headers(), &maintenance.message);
+        resp.headers_mut()
+            .insert(header::RETRY_AFTER, maintenance.retry_after_seconds.into());
+        return resp;
+    }
+    let project_id = match percent_decode(tail).map(|tail| {
+        tail.strip_suffix(".zip")
+            .and_then(|id| state.cfg.parse_project_id(id).ok())
+    }) {
+        Ok(Some(project_id)) => project_id,
+        Ok(None) => return response_400("invalid path, expected /archive/<project id>.zip"),
+        Err(msg) => return response_400(msg),
+    };
+    let project_id = &project_id;
+    let at = match archive::requested_time(req.uri().query()) {
+        Ok(at) => at,
+        Err(msg) => return response_400(&format!("{msg}\n")),
+    };
+
+    // --- Auth ---
+    let tokens = extract_tokens(&req);
+    let decision =
+        match timing::measure(Phase::Auth, authorize(state.as_ref(), &tokens, project_id)).await {
+            Ok(decision) => decision,
+            Err(e) => {
+                error!(%project_id, "cannot check credentials: {e}");
+                return response_500("internal auth error");
+            }
+        };
+    state.auth_stats.record(&decision);
+    request_log::note(project_id, decision.as_str());
+    if !decision.is_granted() {
+        log_auth_failure(&decision, &tokens, project_id);
+        #[cfg(feature = "admin-ui")]
+        audit_misused_token(&state, project_id, &decision);
+        return unauthorized_response(&state.cfg, project_id, &decision);
+    }
+    let managed_token = decision.managed_token().map(token_hash);
+    if let Some(token) = &managed_token {
+        note_token_use(&state, token);
+        if let Err(resp) = check_quota(&state, token).await {
+            return resp;
+        }
+    }
+
+    let stale = match sync_before_read(&state, project_id, req.headers(), req.uri().query()).await {
+        Ok(stale) => stale,
+        Err(resp) => return resp,
+    };
+
+    // --- Archive ---
+    let cfg = state.cfg.clone();
+    let id = project_id.clone();
+    let resolved =
+        tokio::task::spawn_blocking(move || archive::resolve_snapshot(&cfg, &id, at)).await;
+    let commit = match resolved {
+        Ok(Ok(Some(commit))) => commit,
+        Ok(Ok(None)) => {
+            let cfg = state.cfg.clone();
+            let id = project_id.clone();
+            let first =
+                tokio::task::spawn_blocking(move || archive::first_snapshot_time(&cfg, &id))
+                    .await
+                    .ok()
+                    .flatten();
+            let msg = match (at, first) {
+                (Some(at), Some(first)) => format!(
+                    "no snapshot of {project_id} on or before {}; the first one is from {first}\n",
+                    at.to_rfc3339()
+                ),
+                _ => format!("{project_id} has no snapshot yet\n"),
+            };
+            return response_with_status(StatusCode::NOT_FOUND, &msg);
+        }
+        Ok(Err(e)) => {
+            error!(%project_id, "cannot resolve the snapshot to archive: {e}");
+            return response_500("git archive error");
+        }
+        Err(e) => {
+            error!(%project_id, "cannot resolve the snapshot to archive: join error: {e}");
+            return response_500("git archive error");
+        }
+    };
+    let usage_state = state.clone();
+    let body = archive::archive_body(&state.cfg, project_id, &commit, move |sent| {
+        if let Some(token) = managed_token {
+            tokio::spawn(async move { record_usage(&usage_state, token, 0, sent).await });
+        }
+    });
+    let body = match body {
+        Ok(body) => body,
+        Err(e) => {
+            error!(%project_id, "{e}");
+            return response_500("git archive error");
+        }
+    };
+    note_fetch(&state, project_id);
+
+    let mut builder = Response::builder()
+        .header(header::CONTENT_TYPE, "application/zip")
+        .header(
+            header::CONTENT_DISPOSITION,
+            format!(
+                r#"attachment; filename="{project_id}-{}.zip""#,
+                &commit[..commit.len().min(7)]
+            ),
+        )
+        .header(archive::COMMIT_HEADER, &commit);
+    if stale {
+        builder = builder.header(STALE_HEADER, "true");
+    }
+    builder
+        .body(body)
+        .unwrap_or_else(|_| response_500("failed to build response"))
+}
+
+/// Run `git http-backend` on the blocking pool, streaming `body` into it,
+/// until it has written its headers.
+async fn stream_to_backend(
+    cfg: &Config,
+    project_id: &ProjectId,
+    path: &str,
+    method: Method,
+    query: Option<String>,
+    headers: HeaderMap,
+    body: Arc<RequestBody>,
+) -> Result<(StatusCode, HeaderMap, BackendBody), BridgeError> {
+    let cfg = cfg.clone();
+    let project_id = project_id.clone();
+    let path = path.to_string();
+    let span = Span::current();
+    tokio::task::spawn_blocking(move || {
+        let _entered = span.enter();
+        run_git_http_backend(
+            &cfg,
+            &project_id,
+            &path,
+            &method,
+            query.as_deref(),
+            &headers,
+            &body,
+        )
+    })
+    .await
+    .map_err(|e| BridgeError::Other(format!("join error: {e}")))
+    .and_then(|r| r)
+}
+
+/// Run `info/refs` through `git http-backend`, sharing the run with
+/// identical requests in flight.
+async fn advertise_refs(
+    state: &AppState,
+    key: AdvertisementKey,
+    headers: HeaderMap,
+) -> Result<(StatusCode, HeaderMap, Vec<u8>), BridgeError> {
+    let compute = || {
+        let cfg = state.cfg.clone();
+        let key = key.clone();
+        // logs of the shared run go to the request that started it
+        let span = Span::current();
+        async move {
+            let result = tokio::task::spawn_blocking(move || {
+                let _entered = span.enter();
+                run_git_http_backend(
+                    &cfg,
+                    &key.project_id,
+                    GitEndpoint::InfoRefs.path(),
+                    &Method::GET,
+                    Some(key.query.as_str()).filter(|q| !q.is_empty()),
+                    &headers,
+                    &Arc::new(RequestBody::Memory(Vec::new())),
+                )
+                .and_then(|(status, headers, body)| Ok((status, headers, body.into_vec()?)))
+            })
+            .await
+            .map_err(|e| BridgeError::Other(format!("join error: {e}")))
+            .and_then(|r| r);
+            Arc::new(result.map_err(|e| e.to_string()))
+        }
+    };
+    match state
+        .advertisements
+        .advertise(key.clone(), compute)
+        .await
+        .as_ref()
+    {
+        Ok(response) => Ok(response.clone()),
+        Err(msg) => Err(BridgeError::Other(msg.clone())),
+    }
+}
+
+/// Answer `info/gitbridge`: the last sync, the branch and commit at `HEAD`,
+/// and whether a sync is running, from what is on disk. Never syncs and
+/// never counts against a quota.
+async fn project_status(state: &AppState, project_id: &ProjectId) -> Response<Body> {
+    let holder = state.locks.holder(project_id);
+    let preparing = state.initializations.status(project_id);
+    let cfg = state.cfg.clone();
+    let source = state.source.clone();
+    let project_id = project_id.clone();
+    let status = tokio::task::spawn_blocking(move || {
+        let mirrored = cfg
+            .bare_repo_dir(&project_id)
+            .is_ok_and(|repo| repo.is_dir());
+        if !mirrored && !source.exists(&project_id) {
+            return None;
+        }
+        Some(status_json(&SystemGit, &cfg, &project_id, holder))
+    })
+    .await;
+    let mut status = match status {
+        Ok(Some(status)) => status,
+        Ok(None) => return response_with_status(StatusCode::NOT_FOUND, "project not found\n"),
+        Err(e) => {
+            error!("project status failed: {e}");
+            return response_500("status error");
+        }
+    };
+    status["preparing"] = json!(preparing);
+    let mut resp = Response::new(Body::from(status.to_string()));
+    *resp.headers_mut() = no_cache_headers();
+    resp.headers_mut().insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/json"),
+    );
+    resp
+}
+
+/// Status of a project as served by `info/gitbridge`. Only metadata and
+/// refs; token material must never end up here. Blocking.
+fn status_json(
+    git: &dyn GitRunner,
+    cfg: &Config,
+    project_id: &ProjectId,
+    holder: Option<LockHolder>,
+) -> serde_json::Value {
+    let repo = cfg
+        .bare_repo_dir(project_id)
+        .ok()
+        .filter(|repo| repo.is_dir());
+    let stdout = |repo: &Path, args: &[&str]| {
+        git.run(args, repo)
+            .ok()
+            .filter(|out| out.status.success())
+            .map(|out| String::from_utf8_lossy(&out.stdout).trim().to_string())
+            .filter(|s| !s.is_empty())
+    };
+    let (branch, head) = match &repo {
+        Some(repo) => (
+            stdout(repo, &["symbolic-ref", "--short", "HEAD"]),
+            stdout(repo, &["rev-parse", "--verify", "--quiet", "HEAD"]),
+        ),
+        None => (None, None),
+    };
+    let meta = load_metadata(cfg, project_id);
+    json!({
+        "project_id": project_id,
+        "mirrored": repo.is_some(),
+        "external": is_external_repo(cfg, project_id),
+        "branch": branch,
+        "head": head,
+        "last_sync_at": meta.last_sync_at,
+        "last_success_at": meta.last_success_at,
+        "last_error": meta.last_error,
+        "last_sync": meta.history.last(),
+        "sync_running": holder.as_ref().is_some_and(|h| h.operation.ends_with(" sync")),
+        "lock_holder": holder.map(|h| json!({
+            "operation": h.operation,
+            "seconds": h.since.elapsed().as_secs(),
+        })),
+    })
+}
+
+/// Project and endpoint addressed by a `/git/...` request.
+#[derive(Debug, PartialEq, Eq)]
+struct GitPath {
+    project_id: ProjectId,
+    /// Path below `<id>.git/` without leading or trailing slashes; empty for
+    /// the repository root
+    endpoint: String,
+}
+
+/// Decode the still percent-encoded tail of a `/git/` URL once and bring it
+/// into canonical form: empty and `.` segments are dropped, so doubled and
+/// trailing slashes do not matter. Encoded slashes, NULs and `..` segments
+/// are rejected rather than guessed at.
+fn parse_git_path(tail: &str) -> Result<GitPath, &'static str> {
+    let decoded = percent_decode(tail)?;
+    let mut segments = decoded.split('/').filter(|s| !s.is_empty() && *s != ".");
+    let first = segments.next().ok_or("invalid path (no project)")?;
+    let project_id = first
+        .strip_suffix(".git")
+        .ok_or("invalid path (no .git suffix)")?
+        .parse::<ProjectId>()
+        .map_err(|_| "invalid project id")?;
+    let endpoint: Vec<&str> = segments.collect();
+    if endpoint.contains(&"..") {
+        return Err("invalid path (parent segment)");
+    }
+    Ok(GitPath {
+        project_id,
+        endpoint: endpoint.join("/"),
+    })
+}
+
+/// Percent-decode a URL path. `%2F` would turn into a segment separator and
+/// is refused, as are NULs and malformed escapes.
+fn percent_decode(raw: &str) -> Result<String, &'static str> {
+    let bytes = raw.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let byte = match bytes[i] {
+            b'%' => {
+                let hex = bytes.get(i + 1..i + 3).ok_or("invalid percent-encoding")?;
+                let hex = std::str::from_utf8(hex).map_err(|_| "invalid percent-encoding")?;
+                let byte = u8::from_str_radix(hex, 16).map_err(|_| "invalid percent-encoding")?;
+                if byte == b'/' {
+                    return Err("encoded slash in path");
+                }
+                i += 3;
+                byte
+            }
+            byte => {
+                i += 1;
+                byte
+            }
+        };
+        if byte == 0 {
+            return Err("NUL in path");
+        }
+        out.push(byte);
+    }
+    String::from_utf8(out).map_err(|_| "path is not valid UTF-8")
+}
+
+/// Create the missing mirror of a project for a git request through the
+/// initialization queue. When no slot is free, or another request is
+/// already creating it, the mirror is created in the background and
+/// [`BridgeError::MirrorPreparing`] asks the client to retry.
+pub(crate) async fn prepare_mirror(
+    state: &AppState,
+    project_id: &ProjectId,
+) -> Result<(), BridgeError> {
+    // unknown projects must not end up in the queue over and over
+    let source = state.source.clone();
+    let id = project_id.clone();
+    let exists = tokio::task::spawn_blocking(move || source.exists(&id))
+        .await
+        .map_err(|e| BridgeError::Other(format!("join error: {e}")))?;
+    if !exists && !is_external_repo(&state.cfg, project_id) {
+        return Err(BridgeError::ProjectNotFound(project_id.to_string()));
+    }
+
+    let Some(mut claim) = state.initializations.claim(project_id) else {
+        return Err(BridgeError::MirrorPreparing(project_id.to_string()));
+    };
+    if claim.try_start() {
+        sync_project(state, project_id, SyncTrigger::Fetch).await?;
+        state.fetches.synced();
+        return Ok(());
+    }
+    info!(%project_id, "no free slot to create the mirror, preparing it in the background");
+    let state = state.clone();
+    let project_id_owned = project_id.clone();
+    tokio::spawn(async move {
+        claim.start().await;
+        if let Err(e) = sync_project(&state, &project_id_owned, SyncTrigger::Fetch).await {
+            warn!(project_id = %project_id_owned, "preparing the mirror failed: {e}");
+        }
+    });
+    Err(BridgeError::MirrorPreparing(project_id.to_string()))
+}
+
+const PREPARING_MESSAGE: &str = "repository is being prepared, retry shortly";
+
+/// 503 for a project whose mirror is still being created, or in maintenance
+/// mode: plain text, which git shows to the user, or JSON for clients that
+/// ask for it.
+fn unavailable_response(headers: &HeaderMap, message: &str) -> Response<Body> {
+    let wants_json = headers
+        .get(header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|accept| accept.contains("application/json"));
+    let (content_type, body) = if wants_json {
+        ("application/json", json!({"error": message}).to_string())
+    } else {
+        ("text/plain; charset=utf-8", format!("{message}\n"))
+    };
+    Response::builder()
+        .status(StatusCode::SERVICE_UNAVAILABLE)
+        .header(header::CONTENT_TYPE, content_type)
+        .body(Body::from(body))
+        .expect("unavailable response")
+}
+
+/// Sync before serving a fetch, unless `SYNC_SKIP_UNCHANGED` is on and the
+/// source still has the fingerprint the mirror was last synced from.
+pub(crate) async fn sync_for_fetch(
+    state: &AppState,
+    project_id: &ProjectId,
+) -> Result<(), BridgeError> {
+    if state.cfg.sync_skip_unchanged {
+        let cfg = state.cfg.clone();
+        let source = state.source.clone();
+        let id = project_id.clone();
+        let unchanged =
+            tokio::task::spawn_blocking(move || source_unchanged(&cfg, source.as_ref(), &id))
+                .await
+                .map_err(|e| BridgeError::Other(format!("join error: {e}")))?;
+        if unchanged {
+            debug!(%project_id, "source unchanged since the last sync");
+            state.fetches.served_unchanged();
+            return Ok(());
+        }
+    }
+    sync_project(state, project_id, SyncTrigger::Fetch).await?;
+    state.fetches.synced();
+    Ok(())
+}
+
+/// Whether a mirror whose sync just failed may be served as it is: only
+/// with `SERVE_STALE_ON_SYNC_ERROR`, and only if its read-only branch still
+/// points to a commit. The failure itself is already recorded and alerted
+/// on by [`sync_project`].
+pub(crate) async fn serves_stale(state: &AppState, project_id: &ProjectId) -> bool {
+    if !state.cfg.serve_stale_on_sync_error {
+        return false;
+    }
+    let Ok(repo) = state.cfg.bare_repo_dir(project_id) else {
+        return false;
+    };
+    let branch = format!("{}^{{commit}}", state.cfg.readonly_branch);
+    tokio::task::spawn_blocking(move || {
+        SystemGit
+            .run(&["rev-parse", "--verify", "--quiet", &branch], &repo)
+            .is_ok_and(|out| out.status.success())
+    })
+    .await
+    .unwrap_or(false)
+}
+
+/// Whether the mirror exists and was last synced from a source with the
+/// fingerprint the source has now. Blocking.
+fn source_unchanged(cfg: &Config, source: &dyn ProjectSource, project_id: &ProjectId) -> bool {
+    if is_external_repo(cfg, project_id)
+        || !cfg
+            .bare_repo_dir(project_id)
+            .is_ok_and(|repo| repo.is_dir())
+    {
+        return false;
+    }
+    let Some(synced) = load_metadata(cfg, project_id).source_fingerprint else {
+        return false;
+    };
+    match source.fingerprint(project_id) {
+        Ok(current) => snapshot_fingerprint(cfg, current).as_ref() == Some(&synced),
+        Err(e) => {
+            debug!(%project_id, "cannot fingerprint source: {e}");
+            false
+        }
+    }
+}
+
+/// Sync the bare mirror of a project, serialized with other syncs of it, and
+/// record the attempt in the project metadata and the activity log. External
+/// repos are left untouched.
+pub(crate) async fn sync_project(
+    state: &AppState,
+    project_id: &ProjectId,
+    trigger: SyncTrigger,
+) -> Result<SyncOutcome, BridgeError> {
+    if is_external_repo(&state.cfg, project_id) {
+        return Ok(SyncOutcome::default());
+    }
+    let _in_flight = state.maintenance.begin();
+    if state.maintenance.is_enabled() {
+        return Err(BridgeError::ProjectBusy(format!(
+            "{project_id}: the bridge is in maintenance mode"
+        )));
+    }
+    if state.shutdown.is_stopping() {
+        return Err(BridgeError::ProjectBusy(format!(
+            "{project_id}: the bridge is shutting down"
+        )));
+    }
+    let operation = match trigger {
+        SyncTrigger::Fetch => "fetch sync",
+        SyncTrigger::Webhook => "webhook sync",
+        SyncTrigger::Manual => "manual sync",
+        SyncTrigger::Cli => "cli sync",
+        SyncTrigger::Warmup => "warm-up",
+    };
+    let _guard = state.lock_project(project_id, operation).await?;
+    let mirror_existed = state
+        .cfg
+        .bare_repo_dir(project_id)
+        .is_ok_and(|repo| repo.is_dir());
+    let result = ensure_repo(state.cfg.clone(), state.source.clone(), project_id).await;
+
+    if state.alerts.is_enabled() && !matches!(result, Err(BridgeError::ProjectNotFound(_))) {
+        let error = result.as_ref().err().map(ToString::to_string);
+        if let Some(alert) = state.alerts.evaluate(project_id, error.as_deref()) {
+            let alerts = state.alerts.clone();
+            tokio::task::spawn_blocking(move || alerts.dispatch(&alert));
+        }
+    }
+
+    if let Some(url) = &state.cfg.sync_event_webhook_url
+        && let Ok(outcome) = &result
+        && outcome.commit.is_some()
+    {
+        let url = url.clone();
+        let clone_url = state.cfg.public_clone_url(project_id);
+        let event = sync_events::sync_event(
+            project_id,
+            clone_url.as_deref(),
+            trigger,
+            outcome,
+            Utc::now(),
+        );
+        let project_id = project_id.clone();
+        let webhooks = state.webhooks.clone();
+        tokio::task::spawn_blocking(move || {
+            if let Err(e) = webhooks.send(WebhookKind::SyncEvent, &url, event) {
+                warn!(%project_id, "cannot post sync event, queued for a retry: {e}");
+            }
+        });
+    }
+
+    let cfg = state.cfg.clone();
+    let activity = state.activity.clone();
+    let project_id = project_id.clone();
+    tokio::task::spawn_blocking(move || {
+        record_sync(&cfg, &project_id, trigger, result.as_ref());
+        if cfg
+            .bare_repo_dir(&project_id)
+            .is_ok_and(|repo| repo.is_dir())
+            != mirror_existed
+        {
+            gitweb::mirror_changed(&cfg, &project_id);
+        }
+        if let Ok(outcome) = &result
+            && let Some(commit) = &outcome.commit
+        {
+            let event = ActivityEvent {
+                at: Utc::now(),
+                project_id: project_id.to_string(),
+                commit: commit.clone(),
+                files_changed: outcome.files_changed,
+                trigger,
+            };
+            if let Err(e) = activity.append(&event) {
+                warn!(%project_id, error = %e, "cannot append to activity log");
+            }
+        }
+        result
+    })
+    .await
+    .map_err(|e| BridgeError::Other(format!("join error: {e}")))?
+}
+
+/// Add a known token used where it is not valid to the audit log.
+#[cfg(feature = "admin-ui")]
+fn audit_misused_token(state: &AppState, project_id: &ProjectId, decision: &AuthDecision) {
+    let Some(token) = decision.misused_token() else {
+        return;
+    };
+    let event = AuditEvent::new(
+        "auth_denied",
+        project_id,
+        Some(format!(
+            "{}: token {}",
+            decision.as_str(),
+            fingerprint(token)
+        )),
+    );
+    let audit = state.audit.clone();
+    tokio::task::spawn_blocking(move || {
+        if let Err(e) = audit.append(&event) {
+            warn!("cannot write audit log: {e}");
+        }
+    });
+}
+
+#[async_trait]
+impl AuthSources for AppState {
+    async fn managed_token(
+        &self,
+        token: &str,
+        project_id: &ProjectId,
+    ) -> Result<Decision, BridgeError> {
+        let decision = self.tokens.check(token, project_id).await?;
+        if decision != Decision::Allowed {
+            return Ok(decision);
+        }
+        // a token limited to another source is treated like one limited to
+        // other projects
+        match self.tokens.source_scopes().await?.get(&token_hash(token)) {
+            Some(source) if source != self.cfg.source_label() => Ok(Decision::NotAllowedForProject),
+            _ => Ok(decision),
+        }
+    }
+
+    async fn project_token(&self, project_id: &ProjectId) -> Option<String> {
+        read_project_token(self, project_id).await
+    }
+
+    async fn deploy_token(
+        &self,
+        project_id: &ProjectId,
+        token: &str,
+    ) -> Result<DeployTokenCheck, BridgeError> {
+        let store = self.deploy_tokens.clone();
+        let project_id = project_id.clone();
+        let token = token.to_string();
+        tokio::task::spawn_blocking(move || store.check(&project_id, &token))
+            .await
+            .map_err(|e| BridgeError::Other(format!("join error: {e}")))?
+    }
+
+    async fn is_public(&self, project_id: &ProjectId) -> Result<bool, BridgeError> {
+        is_public_project(self, project_id).await
+    }
+}
+
+/// Whether anyone may fetch `project_id` without a token.
+pub(crate) async fn is_public_project(
+    state: &AppState,
+    project_id: &ProjectId,
+) -> Result<bool, BridgeError> {
+    let cfg = state.cfg.clone();
+    let source = state.source.clone();
+    let project_id = project_id.clone();
+    tokio::task::spawn_blocking(move || {
+        let settings = load_project_settings(&cfg)?.get(&project_id);
+        Ok(public_source(&settings, source.as_ref(), &project_id).is_some())
+    })
+    .await
+    .map_err(|e| BridgeError::Other(format!("join error: {e}")))?
+}
+
+/// Refuse the request with 429 once `token` used up its daily quota.
+async fn check_quota(state: &AppState, token: &str) -> Result<(), Response<Body>> {
+    let quota = match state.tokens.quotas().await {
+        Ok(quotas) => quotas.get(token).copied().unwrap_or_default(),
+        Err(e) => {
+            error!("cannot load token quotas: {e}");
+            return Err(response_500("internal auth error"));
+        }
+    };
+    if quota.is_unlimited() {
+        return Ok(());
+    }
+    let usage = state.usage.clone();
+    let token_owned = token.to_string();
+    let today = tokio::task::spawn_blocking(move || usage.today(&token_owned))
+        .await
+        .map_err(|e| BridgeError::Other(format!("join error: {e}")))
+        .and_then(|r| r);
+    match today {
+        Ok(today) => match today.exceeds(&quota) {
+            Some(reason) => {
+                warn!("token over quota: {reason}");
+                let mut resp =
+                    response_with_status(StatusCode::TOO_MANY_REQUESTS, &format!("{reason}\n"));
+                resp.headers_mut()
+                    .insert(header::RETRY_AFTER, seconds_until_utc_midnight().into());
+                Err(resp)
+            }
+            None => Ok(()),
+        },
+        Err(e) => {
+            error!("cannot read token usage: {e}");
+            Err(response_500("internal auth error"))
+        }
+    }
+}
+
+fn seconds_until_utc_midnight() -> u64 {
+    let now = Utc::now();
+    let midnight = (now.date_naive() + chrono::Days::new(1)).and_time(chrono::NaiveTime::MIN);
+    (midnight - now.naive_utc()).num_seconds().max(1) as u64
+}
+
+/// Write the fetch time of a project in the background, at most once per
+/// [`FETCH_RECORD_INTERVAL`](crate::metadata::FETCH_RECORD_INTERVAL).
+pub(crate) fn note_fetch(state: &Arc<AppState>, project_id: &ProjectId) {
+    if !state.fetches.due(project_id) {
+        return;
+    }
+    tokio::spawn(write_fetch(state.clone(), project_id.clone()));
+}
+
+/// Write the fetch time of a project before the mirror is served, with
+/// `SQUASH_WINDOW_MINUTES` only: a sync running between serving and a
+/// later write could otherwise amend the commit just fetched.
+pub(crate) async fn note_fetch_before_serving(state: &Arc<AppState>, project_id: &ProjectId) {
+    if state.cfg.squash_window_minutes > 0 && state.fetches.due(project_id) {
+        write_fetch(state.clone(), project_id.clone()).await;
+    }
+}
+
+async fn write_fetch(state: Arc<AppState>, project_id: ProjectId) {
+    let guard = state.locks.lock(&project_id, "record fetch").await;
+    let cfg = state.cfg.clone();
+    let written = tokio::task::spawn_blocking(move || {
+        let _guard = guard;
+        if is_external_repo(&cfg, &project_id) {
+            return Ok(());
+        }
+        record_fetch(&cfg, &project_id).map_err(|e| format!("{project_id}: {e}"))
+    })
+    .await;
+    match written {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => warn!("cannot record fetch time: {e}"),
+        Err(e) => warn!("cannot record fetch time: join error: {e}"),
+    }
+}
+
+/// Record that a managed token authorized a git request just now, at most
+/// once per [`crate::auth::TOKEN_USE_RECORD_INTERVAL`] and token. Written in the
+/// background, so busy tokens do not slow down their requests.
+fn note_token_use(state: &Arc<AppState>, token: &str) {
+    if !state.token_uses.due(token) {
+        return;
+    }
+    let tokens = state.tokens.clone();
+    let token = token.to_string();
+    tokio::spawn(async move {
+        if let Err(e) = tokens.record_use(&token, Utc::now()).await {
+            warn!("cannot record token use: {e}");
+        }
+    });
+}
+
+/// Record a clone or fetch request let in by a token in the access log;
+/// public access is not logged.
+fn log_access(
+    state: &AppState,
+    project_id: &ProjectId,
+    decision: &AuthDecision,
+    managed_token: Option<&str>,
+    endpoint: Option<GitEndpoint>,
+    client: Option<IpAddr>,
+) {
+    let Some(kind) = AccessKind::of(endpoint) else {
+        return;
+    };
+    let token = match decision {
+        AuthDecision::Managed(_) => managed_token.unwrap_or_default(),
+        AuthDecision::ProjectToken(_) => "project-token",
+        AuthDecision::DeployToken(_) => "deploy-token",
+        _ => return,
+    };
+    state.access_log.record(AccessEntry {
+        at: Utc::now(),
+        project_id: project_id.to_string(),
+        source: state.cfg.source_name.clone(),
+        token: token.to_string(),
+        client,
+        kind,
+    });
+}
+
+/// Add one git request of `token` to its daily usage.
+async fn record_usage(state: &AppState, token: String, bytes_in: u64, bytes_out: u64) {
+    let usage = state.usage.clone();
+    let recorded =
+        tokio::task::spawn_blocking(move || usage.record(&token, bytes_in, bytes_out)).await;
+    match recorded {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => warn!("cannot record token usage: {e}"),
+        Err(e) => warn!("cannot record token usage: join error: {e}"),
+    }
+}
+
+/// Quick helper: check if ?service=git-receive-pack
+fn is_receive_pack(query: Option<&str>) -> bool {
+    requested_service(query).as_deref() == Some("git-receive-pack")
+}
+
+/// The `service` query parameter of an `info/refs` request.
+fn requested_service(query: Option<&str>) -> Option<String> {
+    form_urlencoded::parse(query?.as_bytes())
+        .find(|(k, _)| k == "service")
+        .map(|(_, v)| v.into_owned())
+}
+
+/// Services git asks `info/refs` to advertise.
+const SERVICES: &[&str] = &["git-upload-pack", "git-receive-pack", "git-upload-archive"];
+
+/// The refusal of an `info/refs` request by a git client as an `ERR`
+/// packet, which git prints as `remote error: <message>`; `None` for other
+/// requests and clients, which get plain text.
+fn git_refusal(headers: &HeaderMap, query: Option<&str>, message: &str) -> Option<Response<Body>> {
+    let service = requested_service(query)?;
+    if !is_git_client(headers) || !SERVICES.contains(&service.as_str()) {
+        return None;
+    }
+    let (status, headers, body) = git_error_response(&service, message);
+    let mut resp = Response::new(Body::from(body));
+    *resp.status_mut() = status;
+    *resp.headers_mut() = headers;
+    Some(resp)
+}
+
+/// How a git request asked the pre-fetch sync to be handled, through the
+/// `X-GitBridge-Sync` header or the `sync` query parameter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SyncMode {
+    /// Sync before serving, as usual
+    Auto,
+    /// Serve the existing mirror without syncing
+    Skip,
+    /// Sync before serving even if it would otherwise be skipped
+    Force,
+}
+
+impl SyncMode {
+    fn parse(raw: &str) -> Option<Self> {
+        match raw.trim().to_ascii_lowercase().as_str() {
+            "auto" => Some(Self::Auto),
+            "skip" => Some(Self::Skip),
+            "force" => Some(Self::Force),
+            _ => None,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Auto => "auto",
+            Self::Skip => "skip",
+            Self::Force => "force",
+        }
+    }
+}
+
+/// The sync mode a request asked for; the header wins over the query.
+/// Unknown values are ignored.
+fn requested_sync_mode(headers: &HeaderMap, query: Option<&str>) -> SyncMode {
+    let from_header = headers
+        .get(SYNC_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(SyncMode::parse);
+    let from_query = || {
+        form_urlencoded::parse(query?.as_bytes())
+            .find(|(k, _)| k == "sync")
+            .and_then(|(_, v)| SyncMode::parse(&v))
+    };
+    from_header.or_else(from_query).unwrap_or(SyncMode::Auto)
+}
+
+/// Add a `Retry-After` of `LOCK_WAIT_SECONDS` to a response for a busy project.
+pub(crate) fn with_retry_after(mut response: Response<Body>, cfg: &Config) -> Response<Body> {
+    response
+        .headers_mut()
+        .insert(header::RETRY_AFTER, cfg.lock_wait_seconds.into());
+    response
+}
+
+/// Realm shared by all projects when `AUTH_GLOBAL_REALM` is set.
+const GLOBAL_REALM: &str = "ShareLatex Git Readonly";
+
+/// 401 with WWW-Authenticate header. Credential helpers key saved
+/// credentials on host and realm, so each project gets its own realm unless
+/// one token is meant to be used everywhere.
+fn unauthorized_response(
+    cfg: &Config,
+    project_id: &ProjectId,
+    decision: &AuthDecision,
+) -> Response<Body> {
+    let realm = if cfg.auth_global_realm {
+        GLOBAL_REALM.to_string()
+    } else {
+        format!("gitbridge {project_id}")
+    };
+    axum::http::Response::builder()
+        .status(StatusCode::UNAUTHORIZED)
+        .header("WWW-Authenticate", format!(r#"Basic realm="{realm}""#))
+        .body(Body::from(format!(
+            "Unauthorized: {}\n",
+            decision.message()
+        )))
+        .unwrap()
+}
+
+fn response_500(msg: &str) -> Response<Body> {
+    response_with_status(StatusCode::INTERNAL_SERVER_ERROR, msg)
+}
+
+fn response_400(msg: &str) -> Response<Body> {
+    response_with_status(StatusCode::BAD_REQUEST, msg)
+}
+
+fn response_with_status(code: StatusCode, msg: &str) -> Response<Body> {
+    axum::http::Response::builder()
+        .status(code)
+        .body(Body::from(msg.to_string()))
+        .unwrap()
+}
+
+async fn read_project_token(state: &AppState, project_id: &ProjectId) -> Option<String> {
+    let content = read_project_file(state, project_id, ".gitbridge").await?;
+    let trimmed = content.trim();
+    if trimmed.is_empty() {
+        None
+    } else {
+        Some(trimmed.to_string())
+    }
+}
+
+/// Read a bridge control file (e.g. `.gitbridge`) from the project source.
+/// Missing files and read errors yield `None`; the latter are logged.
+pub(crate) async fn read_project_file(
+    state: &AppState,
+    project_id: &ProjectId,
+    file_name: &'static str,
+) -> Option<String> {
+    let source = state.source.clone();
+    let project_id_owned = project_id.clone();
+    let read = tokio::task::spawn_blocking(move || {
+        let mut content = String::new();
+        source
+            .read(&project_id_owned, Path::new(file_name))?
+            .read_to_string(&mut content)?;
+        Ok::<_, BridgeError>(content)
+    })
+    .await;
+
+    match read {
+        Ok(Ok(content)) => Some(content),
+        Ok(Err(BridgeError::Io(e))) if e.kind() == ErrorKind::NotFound => None,
+        Ok(Err(e)) => {
+            warn!(%project_id, file_name, error = %e, "failed to read project file");
+            None
+        }
+        Err(e) => {
+            warn!(%project_id, file_name, error = %e, "project file read task failed");
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::project_id::pid;
+    use axum::body::to_bytes;
+
+    fn parsed(tail: &str) -> Result<(String, String), &'static str> {
+        parse_git_path(tail).map(|p| (p.project_id.to_string(), p.endpoint))
+    }
+
+    #[test]
+    fn odd_spellings_of_one_endpoint_are_equivalent() {
+        let spellings = [
+            "abc123.git/info/refs",
+            "abc123.git//info/refs",
+            "abc123.git/./info/refs",
+            "abc123.git/info//refs",
+            "abc123.git/info/./refs",
+            "abc123.git/info/refs/",
+            "/abc123.git/info/refs",
+            "abc123%2Egit/info/refs",
+            "abc123%2egit/info/refs",
+            "%61bc123.git/info/refs",
+            "abc123.git/%69nfo/refs",
+            "abc123.git/info/%72%65%66%73",
+        ];
+        for tail in spellings {
+            assert_eq!(
+                parsed(tail),
+                Ok(("abc123".to_string(), "info/refs".to_string())),
+                "{tail}"
+            );
+        }
+    }
+
+    #[test]
+    fn repo_root_may_end_with_a_slash() {
+        for tail in ["abc123.git", "abc123.git/", "abc123.git//", "abc123.git/."] {
+            assert_eq!(
+                parsed(tail),
+                Ok(("abc123".to_string(), String::new())),
+                "{tail}"
+            );
+        }
+    }
+
+    #[test]
+    fn ambiguous_paths_are_rejected() {
+        let rejected = [
+            ("abc123.git/info%2Frefs", "encoded slash in path"),
+            ("abc123.git%2finfo/refs", "encoded slash in path"),
+            ("abc123.git/info/refs%00", "NUL in path"),
+            ("abc123.git/info/%zzrefs", "invalid percent-encoding"),
+            ("abc123.git/info/refs%2", "invalid percent-encoding"),
+            ("abc123.git/%ff", "path is not valid UTF-8"),
+            (
+                "abc123.git/../other.git/info/refs",
+                "invalid path (parent segment)",
+            ),
+            ("abc123/info/refs", "invalid path (no .git suffix)"),
+            ("..git/info/refs", "invalid project id"),
+            ("..%2F..%2Fetc.git/info/refs", "encoded slash in path"),
+            ("%2Fetc.git/info/refs", "encoded slash in path"),
+            ("abc%00.git/info/refs", "NUL in path"),
+            ("..%5C..%5Cetc.git/info/refs", "invalid project id"),
+            ("", "invalid path (no project)"),
+        ];
+        for (tail, msg) in rejected {
+            assert_eq!(parsed(tail), Err(msg), "{tail}");
+        }
+    }
+
+    #[test]
+    fn realm_is_per_project_unless_global() {
+        let root = tempfile::TempDir::new().unwrap();
+        let mut cfg = Config::for_test(root.path());
+        let realm = |cfg: &Config| {
+            unauthorized_response(cfg, &pid("abc123"), &AuthDecision::NoCredentials).headers()["www-authenticate"]
+                .to_str()
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!(realm(&cfg), r#"Basic realm="gitbridge abc123""#);
+        cfg.auth_global_realm = true;
+        assert_eq!(realm(&cfg), r#"Basic realm="ShareLatex Git Readonly""#);
+    }
+
+    #[tokio::test]
+    async fn upload_pack_waits_for_100_continue() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio::time::{Duration, timeout};
+
+        let root = tempfile::TempDir::new().unwrap();
+        let cfg = Config::for_test(root.path());
+        let source = cfg.project_source_dir(&pid("abc123")).unwrap();
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::write(source.join("main.tex"), "hello").unwrap();
+        std::fs::write(source.join(".gitbridge"), "projtok").unwrap();
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = crate::build_router(Arc::new(AppState::new(cfg)));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        // A flush packet: upload-pack without wants answers with nothing.
+        let body = b"0000";
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let head = format!(
+            "POST /git/abc123.git/git-upload-pack HTTP/1.1\r\n\
+             Host: {addr}\r\n\
+             Authorization: Bearer projtok\r\n\
+             Content-Type: application/x-git-upload-pack-request\r\n\
+             Content-Length: {}\r\n\
+             Expect: 100-continue\r\n\
+             Connection: close\r\n\r\n",
+            body.len()
+        );
+        stream.write_all(head.as_bytes()).await.unwrap();
+
+        // Like a strict client, send the body only after the interim response.
+        let mut interim = vec![0; 25];
+        timeout(Duration::from_secs(30), stream.read_exact(&mut interim))
+            .await
+            .expect("no interim response")
+            .unwrap();
+        assert_eq!(interim, b"HTTP/1.1 100 Continue\r\n\r\n");
+
+        stream.write_all(body).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+        assert!(response.contains("application/x-git-upload-pack-result"));
+    }
+
+    #[tokio::test]
+    async fn gzipped_upload_pack_requests_are_served() {
+        use std::io::Write;
+        use tower::ServiceExt;
+
+        let root = tempfile::TempDir::new().unwrap();
+        let cfg = Config::for_test(root.path());
+        let source = cfg.project_source_dir(&pid("abc123")).unwrap();
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::write(source.join("main.tex"), "hello").unwrap();
+        std::fs::write(source.join(".gitbridge"), "projtok").unwrap();
+        let app = crate::build_router(Arc::new(AppState::new(cfg)));
+
+        let req = Request::get("/git/abc123.git/info/refs?service=git-upload-pack")
+            .header(header::AUTHORIZATION, "Bearer projtok")
+            .body(Body::empty())
+            .unwrap();
+        let resp = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let refs = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        // the first ref follows the service announcement and its flush
+        let refs = String::from_utf8_lossy(&refs);
+        let (_, first) = refs.split_once("0000").unwrap();
+        let head = &first[4..44];
+
+        // the request git sends for a clone, compressed like git does
+        let pkt = |line: &str| format!("{:04x}{line}", line.len() + 4);
+        let request = format!("{}0000{}", pkt(&format!("want {head}\n")), pkt("done\n"));
+        let mut gzip = std::process::Command::new("gzip")
+            .arg("-c")
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .spawn()
+            .unwrap();
+        gzip.stdin
+            .take()
+            .unwrap()
+            .write_all(request.as_bytes())
+            .unwrap();
+        let gzipped = gzip.wait_with_output().unwrap().stdout;
+
+        let upload_pack = |encoding: &str, body: Vec<u8>| {
+            Request::post("/git/abc123.git/git-upload-pack")
+                .header(header::AUTHORIZATION, "Bearer projtok")
+                .header(
+                    header::CONTENT_TYPE,
+                    "application/x-git-upload-pack-request",
+                )
+                .header(header::CONTENT_ENCODING, encoding)
+                .header(header::CONTENT_LENGTH, body.len())
+                .body(Body::from(body))
+                .unwrap()
+        };
+        for encoding in ["gzip", "X-Gzip"] {
+            let resp = app
+                .clone()
+                .oneshot(upload_pack(encoding, gzipped.clone()))
+                .await
+                .unwrap();
+            assert_eq!(resp.status(), StatusCode::OK, "{encoding}");
+            let pack = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+            assert!(pack.windows(4).any(|w| w == b"PACK"), "{encoding}");
+        }
+
+        let resp = app.oneshot(upload_pack("br", gzipped)).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("\"br\""));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn mirrors_with_thousands_of_refs_clone_and_fetch() {
+        async fn git(dir: &Path, args: &[&str]) -> String {
+            let out = tokio::process::Command::new("git")
+                .args(args)
+                .current_dir(dir)
+                .env("GIT_TERMINAL_PROMPT", "0")
+                .output()
+                .await
+                .unwrap();
+            assert!(
+                out.status.success(),
+                "git {args:?}: {}",
+                String::from_utf8_lossy(&out.stderr)
+            );
+            String::from_utf8(out.stdout).unwrap()
+        }
+
+        let root = tempfile::TempDir::new().unwrap();
+        let mut cfg = Config::for_test(root.path());
+        cfg.git_hide_refs = vec!["refs/gitbridge/".into()];
+        let source = cfg.project_source_dir(&pid("abc123")).unwrap();
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::write(source.join("main.tex"), "hello").unwrap();
+        std::fs::write(source.join(".gitbridge"), "projtok").unwrap();
+        let bare = cfg.bare_repo_dir(&pid("abc123")).unwrap();
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!(
+            "http://git:projtok@{}/git/abc123.git",
+            listener.local_addr().unwrap()
+        );
+        let app = crate::build_router(Arc::new(AppState::new(cfg)));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        git(root.path(), &["ls-remote", &url]).await;
+        let tip = git(&bare, &["rev-parse", "master"]).await;
+        // the next fetch skips the sync, so master must survive the rewrite
+        let mut packed = format!("{} refs/heads/master\n", tip.trim());
+        for i in 0..3000 {
+            packed.push_str(&format!("{} refs/heads/b{i}\n", tip.trim()));
+        }
+        for i in 0..1000 {
+            packed.push_str(&format!("{} refs/gitbridge/t{i}\n", tip.trim()));
+        }
+        std::fs::write(bare.join("packed-refs"), packed).unwrap();
+
+        for version in ["0", "2"] {
+            let config = format!("protocol.version={version}");
+            let listed = git(root.path(), &["-c", &config, "ls-remote", &url]).await;
+            assert_eq!(listed.lines().count(), 3002, "protocol v{version}");
+            assert!(!listed.contains("refs/gitbridge/"), "protocol v{version}");
+        }
+
+        git(root.path(), &["clone", "-q", &url, "clone"]).await;
+        let clone = root.path().join("clone");
+        let branches = git(&clone, &["branch", "-r"]).await;
+        assert_eq!(branches.lines().count(), 3002);
+
+        std::fs::write(source.join("main.tex"), "hello again").unwrap();
+        git(&clone, &["fetch", "-q", "origin"]).await;
+        let fetched = git(&clone, &["show", "origin/master:main.tex"]).await;
+        assert_eq!(fetched, "hello again");
+    }
+
+    #[tokio::test]
+    async fn syncs_give_up_on_a_held_lock() {
+        let root = tempfile::TempDir::new().unwrap();
+        let mut cfg = Config::for_test(root.path());
+        cfg.lock_wait_seconds = 1;
+        let state = AppState::new(cfg);
+        let _held = state.locks.lock("abc123", "backup").await;
+
+        let err = sync_project(&state, &pid("abc123"), SyncTrigger::Fetch)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&err, BridgeError::ProjectBusy(msg) if msg.contains("backup")),
+            "{err}"
+        );
+        assert!(
+            load_metadata(&state.cfg, &pid("abc123"))
+                .last_error
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn percent_encoding_is_decoded_only_once() {
+        assert_eq!(
+            parsed("abc123.git/info%252Frefs"),
+            Ok(("abc123".to_string(), "info%2Frefs".to_string()))
+        );
+    }
+
+    #[tokio::test]
+    async fn requests_over_the_daily_quota_get_429() {
+        use crate::auth::TokenQuota;
+        use crate::token_store::{MemoryTokenStore, TokenStore};
+        use tower::ServiceExt;
+
+        let root = tempfile::TempDir::new().unwrap();
+        let cfg = Config::for_test(root.path());
+        let source = cfg.project_source_dir(&pid("abc123")).unwrap();
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::write(source.join("main.tex"), "hello").unwrap();
+        let tokens = Arc::new(MemoryTokenStore::with_tokens(
+            [("ci".to_string(), "CI".to_string())].into(),
+        ));
+        let quota = TokenQuota {
+            daily_bytes: None,
+            daily_requests: Some(1),
+        };
+        tokens.set_quota(&token_hash("ci"), quota).await.unwrap();
+        let state = Arc::new(AppState::with_token_store(cfg, tokens));
+        let app = crate::build_router(state.clone());
+
+        let info_refs = || {
+            Request::get("/git/abc123.git/info/refs?service=git-upload-pack")
+                .header(header::AUTHORIZATION, "Bearer ci")
+                .body(Body::empty())
+                .unwrap()
+        };
+        let first = app.clone().oneshot(info_refs()).await.unwrap();
+        assert_eq!(first.status(), StatusCode::OK);
+        let used = state.usage.today(&token_hash("ci")).unwrap();
+        assert_eq!(used.requests, 1);
+        assert!(used.bytes_out > 0);
+
+        let second = app.oneshot(info_refs()).await.unwrap();
+        assert_eq!(second.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(second.headers().contains_key(header::RETRY_AFTER));
+        let body = to_bytes(second.into_body(), usize::MAX).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("request quota of 1"));
+        assert_eq!(state.usage.today(&token_hash("ci")).unwrap().requests, 1);
+    }
+
+    #[cfg(feature = "admin-ui")]
+    #[tokio::test]
+    async fn deploy_tokens_read_only_their_project() {
+        use tower::ServiceExt;
+
+        let root = tempfile::TempDir::new().unwrap();
+        let cfg = Config::for_test(root.path());
+        std::fs::create_dir_all(&cfg.git_root).unwrap();
+        for id in ["abc123", "def456"] {
+            let source = cfg.project_source_dir(&pid(id)).unwrap();
+            std::fs::create_dir_all(&source).unwrap();
+            std::fs::write(source.join("main.tex"), "hello").unwrap();
+        }
+        let state = Arc::new(AppState::new(cfg));
+        let issued = state.deploy_tokens.issue(&pid("abc123"), 30, true).unwrap();
+        let app = crate::build_router(state.clone());
+        let status = |id: &str, token: &str| {
+            let req = Request::get(format!("/git/{id}.git/info/refs?service=git-upload-pack"))
+                .header(header::AUTHORIZATION, format!("Bearer {token}"))
+                .body(Body::empty())
+                .unwrap();
+            let app = app.clone();
+            async move { app.oneshot(req).await.unwrap().status() }
+        };
+
+        assert_eq!(status("abc123", &issued.token).await, StatusCode::OK);
+        assert_eq!(
+            status("def456", &issued.token).await,
+            StatusCode::UNAUTHORIZED
+        );
+        // reissuing keeps the old token for the overlap window
+        let reissued = state.deploy_tokens.issue(&pid("abc123"), 30, true).unwrap();
+        assert_eq!(status("abc123", &issued.token).await, StatusCode::OK);
+        state.deploy_tokens.revoke(&pid("abc123")).unwrap();
+        assert_eq!(
+            status("abc123", &reissued.token).await,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[tokio::test]
+    async fn tokens_are_accepted_in_either_basic_auth_field() {
+        use crate::token_store::MemoryTokenStore;
+        use base64::Engine;
+        use tower::ServiceExt;
+
+        let root = tempfile::TempDir::new().unwrap();
+        let cfg = Config::for_test(root.path());
+        let source = cfg.project_source_dir(&pid("abc123")).unwrap();
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::write(source.join("main.tex"), "hello").unwrap();
+        std::fs::write(source.join(".gitbridge"), "projtok").unwrap();
+        let tokens = Arc::new(MemoryTokenStore::with_tokens(
+            [("ci".to_string(), "CI".to_string())].into(),
+        ));
+        let state = Arc::new(AppState::with_token_store(cfg, tokens));
+        let app = crate::build_router(state.clone());
+        let status = |credentials: &str| {
+            let basic = base64::engine::general_purpose::STANDARD.encode(credentials);
+            let req = Request::get("/git/abc123.git/info/refs?service=git-upload-pack")
+                .header(header::AUTHORIZATION, format!("Basic {basic}"))
+                .body(Body::empty())
+                .unwrap();
+            let app = app.clone();
+            async move { app.oneshot(req).await.unwrap().status() }
+        };
+
+        assert_eq!(status("projtok:").await, StatusCode::OK);
+        assert_eq!(status("git:projtok").await, StatusCode::OK);
+        assert_eq!(status("x-token-auth:ci").await, StatusCode::OK);
+        assert_eq!(status("someone:wrong").await, StatusCode::UNAUTHORIZED);
+        // traffic is accounted to the field that matched
+        assert_eq!(state.usage.today(&token_hash("ci")).unwrap().requests, 1);
+        assert_eq!(
+            state
+                .usage
+                .today(&token_hash("x-token-auth"))
+                .unwrap()
+                .requests,
+            0
+        );
+
+        // the access log names the token that let each request in
+        #[cfg(feature = "admin-ui")]
+        {
+            let logged = state.access_log.query(Some("abc123"), None, 10).unwrap();
+            let tokens: Vec<_> = logged.iter().map(|e| e.token.as_str()).collect();
+            let ci = token_hash("ci");
+            assert_eq!(tokens, [ci.as_str(), "project-token", "project-token"]);
+            assert!(logged.iter().all(|e| e.kind == AccessKind::InfoRefs));
+        }
+    }
+
+    #[tokio::test]
+    async fn clients_outside_the_allowlist_get_403_before_authentication() {
+        use crate::token_store::MemoryTokenStore;
+        use tower::ServiceExt;
+
+        let root = tempfile::TempDir::new().unwrap();
+        let mut cfg = Config::for_test(root.path());
+        cfg.git_allowed_cidrs = Some(vec!["10.0.0.0/8".parse().unwrap()]);
+        cfg.trusted_proxies = vec!["127.0.0.1".parse().unwrap()];
+        let source = cfg.project_source_dir(&pid("abc123")).unwrap();
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::write(source.join("main.tex"), "hello").unwrap();
+        let tokens = Arc::new(MemoryTokenStore::with_tokens(
+            [("ci".to_string(), "CI".to_string())].into(),
+        ));
+        let state = Arc::new(AppState::with_token_store(cfg, tokens));
+        let app = crate::build_router(state.clone());
+        let status = |peer: Option<&str>, forwarded: Option<&str>, token: &str| {
+            let mut req = Request::get("/git/abc123.git/info/refs?service=git-upload-pack")
+                .header(header::AUTHORIZATION, format!("Bearer {token}"));
+            if let Some(forwarded) = forwarded {
+                req = req.header("x-forwarded-for", forwarded);
+            }
+            let mut req = req.body(Body::empty()).unwrap();
+            if let Some(peer) = peer {
+                let peer: SocketAddr = format!("{peer}:40000").parse().unwrap();
+                req.extensions_mut().insert(ConnectInfo(peer));
+            }
+            let app = app.clone();
+            async move { app.oneshot(req).await.unwrap().status() }
+        };
+
+        assert_eq!(status(Some("10.1.2.3"), None, "ci").await, StatusCode::OK);
+        assert_eq!(
+            status(Some("10.1.2.3"), None, "wrong").await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            status(Some("192.0.2.1"), None, "ci").await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            status(Some("192.0.2.1"), None, "wrong").await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(status(None, None, "ci").await, StatusCode::FORBIDDEN);
+
+        // X-Forwarded-For counts only when sent by a trusted proxy
+        assert_eq!(
+            status(Some("127.0.0.1"), Some("10.2.3.4"), "ci").await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(Some("127.0.0.1"), Some("192.0.2.9"), "ci").await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            status(Some("192.0.2.1"), Some("10.2.3.4"), "ci").await,
+            StatusCode::FORBIDDEN
+        );
+        // refused requests are not accounted to the token
+        assert_eq!(state.usage.today(&token_hash("ci")).unwrap().requests, 2);
+    }
+
+    #[tokio::test]
+    async fn named_sources_are_served_below_their_prefix() {
+        use crate::config::{NamedSource, ProjectsLayout};
+        use crate::token_store::{MemoryTokenStore, TokenStore};
+        use tower::ServiceExt;
+
+        let root = tempfile::TempDir::new().unwrap();
+        let mut cfg = Config::for_test(root.path());
+        cfg.sources = vec![NamedSource {
+            name: "prod".into(),
+            sharelatex_data_path: root.path().join("prod"),
+            projects_dir: "projects".into(),
+            projects_layout: ProjectsLayout::Flat,
+        }];
+        let tokens = Arc::new(MemoryTokenStore::with_tokens(
+            [
+                ("ci".to_string(), "CI".to_string()),
+                ("prod-ci".to_string(), "prod CI".to_string()),
+            ]
+            .into(),
+        ));
+        tokens
+            .set_source_scope(&token_hash("prod-ci"), Some("prod".into()))
+            .await
+            .unwrap();
+        let state = Arc::new(AppState::with_token_store(cfg.clone(), tokens));
+        let prod = state.sources["prod"].clone();
+        // the same id in both instances
+        for (cfg, content) in [(&state.cfg, "default"), (&prod.cfg, "production")] {
+            let source = cfg.project_source_dir(&pid("abc123")).unwrap();
+            std::fs::create_dir_all(&source).unwrap();
+            std::fs::write(source.join("main.tex"), content).unwrap();
+        }
+        let app = crate::build_router(state.clone());
+        let get = |path: &str, token: &str| {
+            let req = Request::get(format!("{path}/info/refs?service=git-upload-pack"))
+                .header(header::AUTHORIZATION, format!("Bearer {token}"))
+                .body(Body::empty())
+                .unwrap();
+            let app = app.clone();
+            async move {
+                let resp = app.oneshot(req).await.unwrap();
+                let status = resp.status();
+                let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+                (status, body)
+            }
+        };
+
+        let (status, default_refs) = get("/git/abc123.git", "ci").await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, prod_refs) = get("/git/prod/abc123.git", "ci").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_ne!(default_refs, prod_refs);
+        assert!(cfg.git_root.join("repos/abc123.git").is_dir());
+        assert!(cfg.git_root.join("prod/repos/abc123.git").is_dir());
+        assert_eq!(
+            prod.cfg.bare_repo_dir(&pid("abc123")).unwrap(),
+            cfg.git_root.join("prod/repos/abc123.git")
+        );
+
+        assert_eq!(
+            get("/git/prod/abc123.git", "prod-ci").await.0,
+            StatusCode::OK
+        );
+        assert_eq!(
+            get("/git/abc123.git", "prod-ci").await.0,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            get("/git/staging/abc123.git", "ci").await.0,
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[tokio::test]
+    async fn public_projects_are_readable_without_a_token() {
+        use crate::project_settings::save_project_settings;
+        use tower::ServiceExt;
+
+        let root = tempfile::TempDir::new().unwrap();
+        let cfg = Config::for_test(root.path());
+        std::fs::create_dir_all(&cfg.git_root).unwrap();
+        for id in ["abc123", "def456"] {
+            let source = cfg.project_source_dir(&pid(id)).unwrap();
+            std::fs::create_dir_all(&source).unwrap();
+            std::fs::write(source.join("main.tex"), "hello").unwrap();
+        }
+        let set_public = |public: bool| {
+            let mut settings = load_project_settings(&cfg).unwrap();
+            settings.update("abc123", |s| s.public = public);
+            save_project_settings(&cfg, &settings).unwrap();
+        };
+        set_public(true);
+        let app = crate::build_router(Arc::new(AppState::new(cfg.clone())));
+        let anonymous = |path: String| {
+            app.clone()
+                .oneshot(Request::get(path).body(Body::empty()).unwrap())
+        };
+        let info_refs = |id: &str| format!("/git/{id}.git/info/refs?service=git-upload-pack");
+
+        let public = anonymous(info_refs("abc123")).await.unwrap();
+        assert_eq!(public.status(), StatusCode::OK);
+        let private = anonymous(info_refs("def456")).await.unwrap();
+        assert_eq!(private.status(), StatusCode::UNAUTHORIZED);
+        let push = anonymous("/git/abc123.git/info/refs?service=git-receive-pack".into())
+            .await
+            .unwrap();
+        assert_eq!(push.status(), StatusCode::FORBIDDEN);
+
+        // the flag is read per request
+        set_public(false);
+        let revoked = anonymous(info_refs("abc123")).await.unwrap();
+        assert_eq!(revoked.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn strict_project_ids_come_from_the_config() {
+        use tower::ServiceExt;
+
+        let root = tempfile::TempDir::new().unwrap();
+        let mut cfg = Config::for_test(root.path());
+        cfg.strict_project_ids = true;
+        let app = crate::build_router(Arc::new(AppState::new(cfg)));
+        let anonymous = |path: &str| {
+            app.clone()
+                .oneshot(Request::get(path).body(Body::empty()).unwrap())
+        };
+
+        for path in ["/git/abc123.git/info/refs", "/archive/abc123.zip"] {
+            let resp = anonymous(path).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{path}");
+        }
+        for path in [
+            "/git/64a1f0c2e4b0a1b2c3d4e5f6.git/info/refs",
+            "/archive/64a1f0c2e4b0a1b2c3d4e5f6-thesis.zip",
+        ] {
+            let resp = anonymous(path).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::UNAUTHORIZED, "{path}");
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn simultaneous_advertisements_get_the_same_refs() {
+        use crate::project_settings::save_project_settings;
+        use tower::ServiceExt;
+
+        let root = tempfile::TempDir::new().unwrap();
+        let cfg = Config::for_test(root.path());
+        std::fs::create_dir_all(&cfg.git_root).unwrap();
+        let source = cfg.project_source_dir(&pid("abc123")).unwrap();
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::write(source.join("main.tex"), "hello").unwrap();
+        let mut settings = load_project_settings(&cfg).unwrap();
+        settings.update("abc123", |s| s.public = true);
+        save_project_settings(&cfg, &settings).unwrap();
+        let app = crate::build_router(Arc::new(AppState::new(cfg.clone())));
+
+        let info_refs = |protocol: Option<&'static str>| {
+            let app = app.clone();
+            async move {
+                let mut req = Request::get("/git/abc123.git/info/refs?service=git-upload-pack")
+                    .header(SYNC_HEADER, "skip");
+                if let Some(protocol) = protocol {
+                    req = req.header("git-protocol", protocol);
+                }
+                let resp = app.oneshot(req.body(Body::empty()).unwrap()).await.unwrap();
+                assert_eq!(resp.status(), StatusCode::OK);
+                to_bytes(resp.into_body(), usize::MAX).await.unwrap()
+            }
+        };
+        let first = info_refs(None).await;
+        let classroom: Vec<_> = (0..12).map(|_| tokio::spawn(info_refs(None))).collect();
+        for student in classroom {
+            assert_eq!(student.await.unwrap(), first);
+        }
+        // protocol v2 requests are never answered with a v0 advertisement
+        let (v0, v2) = tokio::join!(info_refs(None), info_refs(Some("version=2")));
+        assert_eq!(v0, first);
+        assert_ne!(v2, first);
+        assert!(String::from_utf8_lossy(&v2).contains("version 2"));
+    }
+
+    #[tokio::test]
+    async fn git_clients_get_refusals_as_err_packets() {
+        use tower::ServiceExt;
+
+        let root = tempfile::TempDir::new().unwrap();
+        let cfg = Config::for_test(root.path());
+        let source = cfg.project_source_dir(&pid("abc123")).unwrap();
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::write(source.join("main.tex"), "hello").unwrap();
+        let tokens = Arc::new(crate::token_store::MemoryTokenStore::with_tokens(
+            [("ci".to_string(), "CI".to_string())].into(),
+        ));
+        let app = crate::build_router(Arc::new(AppState::with_token_store(cfg.clone(), tokens)));
+        let info_refs = |project: &str, service: &str, agent: &str| {
+            let req = Request::get(format!("/git/{project}.git/info/refs?service={service}"))
+                .header(header::AUTHORIZATION, "Bearer ci")
+                .header(header::USER_AGENT, agent)
+                .body(Body::empty())
+                .unwrap();
+            let app = app.clone();
+            async move {
+                let resp = app.oneshot(req).await.unwrap();
+                let status = resp.status();
+                let content_type = resp
+                    .headers()
+                    .get(header::CONTENT_TYPE)
+                    .and_then(|v| v.to_str().ok())
+                    .unwrap_or_default()
+                    .to_string();
+                let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+                (
+                    status,
+                    content_type,
+                    String::from_utf8(body.to_vec()).unwrap(),
+                )
+            }
+        };
+
+        let (status, content_type, body) =
+            info_refs("abc123", "git-receive-pack", "git/2.43.0").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(content_type, "application/x-git-receive-pack-advertisement");
+        assert_eq!(body, "0031ERR push is disabled on this read-only mirror");
+
+        let (status, content_type, body) =
+            info_refs("missing1", "git-upload-pack", "git/2.43.0").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(content_type, "application/x-git-upload-pack-advertisement");
+        assert_eq!(body, "0022ERR project missing1 not found");
+
+        // anything else keeps the plain answers
+        let (status, _, body) = info_refs("abc123", "git-receive-pack", "curl/8.5.0").await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(body, "push disabled (read-only)\n");
+        let (status, _, body) = info_refs("missing1", "git-upload-pack", "curl/8.5.0").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body, "project not found\n");
+
+        // symlinks out of the projects directory or GIT_ROOT lead nowhere
+        let outside = tempfile::TempDir::new().unwrap();
+        std::fs::write(outside.path().join("main.tex"), "secret").unwrap();
+        std::fs::create_dir_all(cfg.repos_dir()).unwrap();
+        let projects = cfg.projects_root();
+        std::os::unix::fs::symlink(outside.path(), projects.join("linked1")).unwrap();
+        std::os::unix::fs::symlink(outside.path(), cfg.repos_dir().join("linked2.git")).unwrap();
+        for project in ["linked1", "linked2"] {
+            let (status, _, body) = info_refs(project, "git-upload-pack", "curl/8.5.0").await;
+            assert_eq!(status, StatusCode::NOT_FOUND, "{project}");
+            assert_eq!(body, "project not found\n", "{project}");
+        }
+        assert!(!cfg.repos_dir().join("linked1.git").exists());
+    }
+
+    #[tokio::test]
+    async fn git_responses_tell_proxies_not_to_cache_them() {
+        use tower::ServiceExt;
+
+        let root = tempfile::TempDir::new().unwrap();
+        let mut cfg = Config::for_test(root.path());
+        cfg.git_allow_dumb = true;
+        let source = cfg.project_source_dir(&pid("abc123")).unwrap();
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::write(source.join("main.tex"), "hello").unwrap();
+        std::fs::write(source.join(".gitbridge"), "projtok").unwrap();
+        let app = crate::build_router(Arc::new(AppState::new(cfg.clone())));
+        let send = |method: Method, path: String, token: Option<&str>, body: &'static str| {
+            let mut req = Request::builder().method(method.clone()).uri(path);
+            if method == Method::POST {
+                req = req.header(
+                    header::CONTENT_TYPE,
+                    "application/x-git-upload-pack-request",
+                );
+            }
+            if let Some(token) = token {
+                req = req.header(header::AUTHORIZATION, format!("Bearer {token}"));
+            }
+            app.clone().oneshot(req.body(Body::from(body)).unwrap())
+        };
+        let git = "/git/abc123.git";
+
+        let cases = [
+            (
+                Method::GET,
+                format!("{git}/info/refs?service=git-upload-pack"),
+                Some("projtok"),
+                StatusCode::OK,
+            ),
+            (
+                Method::POST,
+                format!("{git}/git-upload-pack"),
+                Some("projtok"),
+                StatusCode::OK,
+            ),
+            (
+                Method::GET,
+                format!("{git}/HEAD"),
+                Some("projtok"),
+                StatusCode::OK,
+            ),
+            (
+                Method::GET,
+                format!("{git}/info/gitbridge"),
+                Some("projtok"),
+                StatusCode::OK,
+            ),
+            (
+                Method::GET,
+                format!("{git}/objects/info/packs"),
+                Some("projtok"),
+                StatusCode::OK,
+            ),
+            (
+                Method::GET,
+                format!("{git}/info/refs?service=git-upload-pack"),
+                None,
+                StatusCode::UNAUTHORIZED,
+            ),
+            (
+                Method::GET,
+                format!("{git}/info/refs?service=git-receive-pack"),
+                Some("projtok"),
+                StatusCode::FORBIDDEN,
+            ),
+            (
+                Method::GET,
+                format!("{git}/config"),
+                Some("projtok"),
+                StatusCode::NOT_FOUND,
+            ),
+            (
+                Method::GET,
+                "/git/..%2Fx.git/info/refs".to_string(),
+                Some("projtok"),
+                StatusCode::BAD_REQUEST,
+            ),
+        ];
+        for (method, path, token, status) in cases {
+            let resp = send(method, path.clone(), token, "0000").await.unwrap();
+            assert_eq!(resp.status(), status, "{path}");
+            let headers = resp.headers();
+            assert_eq!(
+                headers[header::CACHE_CONTROL],
+                "no-cache, max-age=0, must-revalidate",
+                "{path}"
+            );
+            assert_eq!(headers[header::PRAGMA], "no-cache", "{path}");
+            assert_eq!(
+                headers[header::EXPIRES],
+                "Fri, 01 Jan 1980 00:00:00 GMT",
+                "{path}"
+            );
+        }
+
+        // objects never change, so the backend may let them be cached
+        let bare = cfg.bare_repo_dir(&pid("abc123")).unwrap();
+        let head = SystemGit.run(&["rev-parse", "HEAD"], &bare).unwrap().stdout;
+        let head = String::from_utf8(head).unwrap();
+        let (dir, file) = head.trim().split_at(2);
+        let object = send(
+            Method::GET,
+            format!("{git}/objects/{dir}/{file}"),
+            Some("projtok"),
+            "",
+        )
+        .await
+        .unwrap();
+        assert_eq!(object.status(), StatusCode::OK);
+        assert!(
+            object.headers()[header::CACHE_CONTROL]
+                .to_str()
+                .unwrap()
+                .contains("max-age=31536000")
+        );
+        let missing = send(
+            Method::GET,
+            format!("{git}/objects/{dir}/{}", "0".repeat(38)),
+            Some("projtok"),
+            "",
+        )
+        .await
+        .unwrap();
+        assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+        assert_eq!(missing.headers()[header::PRAGMA], "no-cache");
+    }
+
+    #[tokio::test]
+    async fn head_is_served_and_dumb_files_only_when_allowed() {
+        use tower::ServiceExt;
+
+        let root = tempfile::TempDir::new().unwrap();
+        let mut cfg = Config::for_test(root.path());
+        let source = cfg.project_source_dir(&pid("abc123")).unwrap();
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::write(source.join("main.tex"), "hello").unwrap();
+        std::fs::write(source.join(".gitbridge"), "projtok").unwrap();
+        let get = |path: &str| {
+            Request::get(format!("/git/abc123.git/{path}"))
+                .header(header::AUTHORIZATION, "Bearer projtok")
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let app = crate::build_router(Arc::new(AppState::new(cfg.clone())));
+        let head = app.clone().oneshot(get("HEAD")).await.unwrap();
+        assert_eq!(head.status(), StatusCode::OK);
+        assert_eq!(head.headers()[header::CONTENT_TYPE], "text/plain");
+        assert!(
+            head.headers()[header::CACHE_CONTROL]
+                .to_str()
+                .unwrap()
+                .contains("no-cache")
+        );
+        let body = to_bytes(head.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"ref: refs/heads/master\n");
+
+        let packs = app.oneshot(get("objects/info/packs")).await.unwrap();
+        assert_eq!(packs.status(), StatusCode::NOT_FOUND);
+
+        cfg.git_allow_dumb = true;
+        let app = crate::build_router(Arc::new(AppState::new(cfg)));
+        let packs = app.oneshot(get("objects/info/packs")).await.unwrap();
+        assert_eq!(packs.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn sync_mode_comes_from_header_or_query() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(requested_sync_mode(&headers, None), SyncMode::Auto);
+        assert_eq!(
+            requested_sync_mode(&headers, Some("service=git-upload-pack&sync=skip")),
+            SyncMode::Skip
+        );
+        assert_eq!(
+            requested_sync_mode(&headers, Some("sync=later")),
+            SyncMode::Auto
+        );
+        headers.insert(SYNC_HEADER, "Force".parse().unwrap());
+        assert_eq!(
+            requested_sync_mode(&headers, Some("sync=skip")),
+            SyncMode::Force
+        );
+    }
+
+    #[tokio::test]
+    async fn skipped_syncs_serve_the_existing_mirror() {
+        use tower::ServiceExt;
+
+        let root = tempfile::TempDir::new().unwrap();
+        let cfg = Config::for_test(root.path());
+        for id in ["abc123", "def456"] {
+            let source = cfg.project_source_dir(&pid(id)).unwrap();
+            std::fs::create_dir_all(&source).unwrap();
+            std::fs::write(source.join("main.tex"), "hello").unwrap();
+            std::fs::write(source.join(".gitbridge"), "projtok").unwrap();
+        }
+        let app = crate::build_router(Arc::new(AppState::new(cfg.clone())));
+        let fetch = |id: &str, query: &str, sync: Option<&str>| {
+            let mut req = Request::get(format!(
+                "/git/{id}.git/info/refs?service=git-upload-pack{query}"
+            ))
+            .header(header::AUTHORIZATION, "Bearer projtok");
+            if let Some(sync) = sync {
+                req = req.header(SYNC_HEADER, sync);
+            }
+            app.clone().oneshot(req.body(Body::empty()).unwrap())
+        };
+        let syncs = |id: &str| load_metadata(&cfg, &pid(id)).history.len();
+
+        // without a mirror the request syncs anyway
+        let first = fetch("abc123", "&sync=skip", None).await.unwrap();
+        assert_eq!(first.status(), StatusCode::OK);
+        assert_eq!(syncs("abc123"), 1);
+
+        let skipped = fetch("abc123", "", Some("skip")).await.unwrap();
+        assert_eq!(skipped.status(), StatusCode::OK);
+        let skipped = fetch("abc123", "&sync=skip", None).await.unwrap();
+        assert_eq!(skipped.status(), StatusCode::OK);
+        assert_eq!(syncs("abc123"), 1);
+
+        fetch("abc123", "&sync=skip", Some("force")).await.unwrap();
+        fetch("def456", "", None).await.unwrap();
+        assert_eq!((syncs("abc123"), syncs("def456")), (2, 1));
+    }
+
+    #[tokio::test]
+    async fn archives_are_served_as_of_a_date() {
+        use tower::ServiceExt;
+
+        let root = tempfile::TempDir::new().unwrap();
+        let cfg = Config::for_test(root.path());
+        let source = cfg.project_source_dir(&pid("abc123")).unwrap();
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::write(source.join("main.tex"), "hello").unwrap();
+        std::fs::write(source.join(".gitbridge"), "projtok").unwrap();
+        let app = crate::build_router(Arc::new(AppState::new(cfg.clone())));
+        let get = |query: &str| {
+            app.clone().oneshot(
+                Request::get(format!("/archive/abc123.zip{query}"))
+                    .header(header::AUTHORIZATION, "Bearer projtok")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+        };
+
+        let latest = get("").await.unwrap();
+        assert_eq!(latest.status(), StatusCode::OK);
+        assert_eq!(latest.headers()[header::CONTENT_TYPE], "application/zip");
+        let zip = to_bytes(latest.into_body(), usize::MAX).await.unwrap();
+        assert!(zip.starts_with(b"PK\x03\x04"));
+        assert!(zip.windows(8).any(|name| name == b"main.tex"));
+
+        // two snapshots, committed ten and five days ago
+        let repo = cfg.bare_repo_dir(&pid("abc123")).unwrap();
+        let git = |args: &[&str], date: &str| {
+            let out = std::process::Command::new("git")
+                .args(args)
+                .current_dir(&repo)
+                .env("GIT_COMMITTER_DATE", date)
+                .env("GIT_AUTHOR_DATE", date)
+                .envs([
+                    ("GIT_AUTHOR_NAME", "t"),
+                    ("GIT_AUTHOR_EMAIL", "t@example.com"),
+                ])
+                .envs([
+                    ("GIT_COMMITTER_NAME", "t"),
+                    ("GIT_COMMITTER_EMAIL", "t@example.com"),
+                ])
+                .output()
+                .unwrap();
+            String::from_utf8(out.stdout).unwrap().trim().to_string()
+        };
+        let days_ago = |days: i64| (Utc::now() - chrono::Duration::days(days)).to_rfc3339();
+        let first = git(
+            &["commit-tree", "master^{tree}", "-m", "one"],
+            &days_ago(10),
+        );
+        let second = git(
+            &["commit-tree", "master^{tree}", "-p", &first, "-m", "two"],
+            &days_ago(5),
+        );
+        git(&["update-ref", "refs/heads/master", &second], "");
+
+        let commit_at = |days: i64| {
+            let query = form_urlencoded::Serializer::new(String::new())
+                .append_pair("at", &days_ago(days))
+                .append_pair("sync", "skip")
+                .finish();
+            let resp = get(&format!("?{query}"));
+            async move {
+                let resp = resp.await.unwrap();
+                let commit = resp.headers().get(archive::COMMIT_HEADER).cloned();
+                (
+                    resp.status(),
+                    commit.map(|c| c.to_str().unwrap().to_string()),
+                )
+            }
+        };
+        assert_eq!(commit_at(7).await, (StatusCode::OK, Some(first.clone())));
+        assert_eq!(commit_at(1).await, (StatusCode::OK, Some(second.clone())));
+        let skip_sync = get("?sync=skip").await.unwrap();
+        assert_eq!(skip_sync.headers()[archive::COMMIT_HEADER], second.as_str());
+
+        let too_early = get("?at=2001-01-01T00:00:00Z&sync=skip").await.unwrap();
+        assert_eq!(too_early.status(), StatusCode::NOT_FOUND);
+        let msg = to_bytes(too_early.into_body(), usize::MAX).await.unwrap();
+        let msg = String::from_utf8_lossy(&msg);
+        assert!(msg.contains("the first one is from"), "{msg}");
+
+        // a UTC offset may come with an unencoded +
+        let offset = get("?at=2099-01-01T00:00:00+01:00&sync=skip")
+            .await
+            .unwrap();
+        assert_eq!(offset.status(), StatusCode::OK);
+        let invalid = get("?at=yesterday").await.unwrap();
+        assert_eq!(invalid.status(), StatusCode::BAD_REQUEST);
+
+        let anonymous = app
+            .clone()
+            .oneshot(
+                Request::get("/archive/abc123.zip")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(anonymous.status(), StatusCode::UNAUTHORIZED);
+        let bad_path = app
+            .clone()
+            .oneshot(
+                Request::get("/archive/abc123.tar")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(bad_path.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[cfg(feature = "admin-ui")]
+    #[tokio::test]
+    async fn timings_are_reported_when_configured() {
+        use tower::ServiceExt;
+
+        let root = tempfile::TempDir::new().unwrap();
+        let mut cfg = Config::for_test(root.path());
+        let source = cfg.project_source_dir(&pid("abc123")).unwrap();
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::write(source.join("main.tex"), "hello").unwrap();
+        std::fs::write(source.join(".gitbridge"), "projtok").unwrap();
+        let fetch = |state: &Arc<AppState>| {
+            let req = Request::get("/git/abc123.git/info/refs?service=git-upload-pack")
+                .header(header::AUTHORIZATION, "Bearer projtok")
+                .body(Body::empty())
+                .unwrap();
+            crate::build_router(state.clone()).oneshot(req)
+        };
+
+        let state = Arc::new(AppState::new(cfg.clone()));
+        let resp = fetch(&state).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(!resp.headers().contains_key(timing::TIMING_HEADER));
+
+        cfg.git_timing_header = true;
+        let state = Arc::new(AppState::new(cfg));
+        let resp = fetch(&state).await.unwrap();
+        let timing = resp.headers()[timing::TIMING_HEADER].to_str().unwrap();
+        for phase in [
+            "auth;dur=",
+            "lock_wait;dur=",
+            "sync;dur=",
+            "backend;dur=",
+            "total;dur=",
+        ] {
+            assert!(timing.contains(phase), "{timing}");
+        }
+        // the request is in flight until its response is read
+        assert_eq!(state.maintenance.in_flight(), 1);
+        to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(state.maintenance.in_flight(), 0);
+    }
+
+    #[tokio::test]
+    async fn failed_syncs_serve_the_existing_mirror_marked_stale() {
+        use crate::token_store::MemoryTokenStore;
+        use std::os::unix::fs::PermissionsExt;
+        use tower::ServiceExt;
+
+        let root = tempfile::TempDir::new().unwrap();
+        let mut cfg = Config::for_test(root.path());
+        let source = cfg.project_source_dir(&pid("abc123")).unwrap();
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::write(source.join("main.tex"), "hello").unwrap();
+        let fetch = |cfg: &Config, id: &str| {
+            let tokens = Arc::new(MemoryTokenStore::with_tokens(
+                [("ci".to_string(), "CI".to_string())].into(),
+            ));
+            let state = AppState::with_token_store(cfg.clone(), tokens);
+            crate::build_router(Arc::new(state)).oneshot(
+                Request::get(format!("/git/{id}.git/info/refs?service=git-upload-pack"))
+                    .header(header::AUTHORIZATION, "Bearer ci")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+        };
+        let first = fetch(&cfg, "abc123").await.unwrap();
+        assert_eq!(first.status(), StatusCode::OK);
+        assert!(first.headers().get(STALE_HEADER).is_none());
+
+        // the mirror refuses the next sync's push
+        let hook = cfg
+            .bare_repo_dir(&pid("abc123"))
+            .unwrap()
+            .join("hooks/pre-receive");
+        std::fs::create_dir_all(hook.parent().unwrap()).unwrap();
+        std::fs::write(&hook, "#!/bin/sh\nexit 1\n").unwrap();
+        std::fs::set_permissions(&hook, std::fs::Permissions::from_mode(0o755)).unwrap();
+        std::fs::write(source.join("main.tex"), "changed").unwrap();
+
+        let failed = fetch(&cfg, "abc123").await.unwrap();
+        assert_eq!(failed.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        cfg.serve_stale_on_sync_error = true;
+        let stale = fetch(&cfg, "abc123").await.unwrap();
+        assert_eq!(stale.status(), StatusCode::OK);
+        assert_eq!(stale.headers()[STALE_HEADER], "true");
+        let metadata = load_metadata(&cfg, &pid("abc123"));
+        assert!(metadata.last_error.is_some());
+        assert!(!metadata.history.last().unwrap().ok);
+
+        // missing projects are still missing
+        let missing = fetch(&cfg, "def456").await.unwrap();
+        assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn mirrors_over_the_size_limit_are_served_but_not_synced() {
+        use crate::token_store::MemoryTokenStore;
+        use tower::ServiceExt;
+
+        let root = tempfile::TempDir::new().unwrap();
+        let mut cfg = Config::for_test(root.path());
+        cfg.max_repo_bytes = 1;
+        let source = cfg.project_source_dir(&pid("abc123")).unwrap();
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::write(source.join("main.tex"), "hello").unwrap();
+        let fetch = |cfg: &Config| {
+            let tokens = Arc::new(MemoryTokenStore::with_tokens(
+                [("ci".to_string(), "CI".to_string())].into(),
+            ));
+            let state = AppState::with_token_store(cfg.clone(), tokens);
+            crate::build_router(Arc::new(state)).oneshot(
+                Request::get("/git/abc123.git/info/refs?service=git-upload-pack")
+                    .header(header::AUTHORIZATION, "Bearer ci")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+        };
+        let snapshot = |cfg: &Config| {
+            let out = SystemGit
+                .run(
+                    &["show", "master:main.tex"],
+                    &cfg.bare_repo_dir(&pid("abc123")).unwrap(),
+                )
+                .unwrap();
+            String::from_utf8(out.stdout).unwrap()
+        };
+
+        // a new mirror is always created, later syncs find it too large
+        let first = fetch(&cfg).await.unwrap();
+        assert_eq!(first.status(), StatusCode::OK);
+        std::fs::write(source.join("main.tex"), "changed").unwrap();
+        let blocked = fetch(&cfg).await.unwrap();
+        assert_eq!(blocked.status(), StatusCode::OK);
+        assert_eq!(blocked.headers()[STALE_HEADER], "true");
+        assert_eq!(snapshot(&cfg), "hello");
+        let metadata = load_metadata(&cfg, &pid("abc123"));
+        assert!(
+            metadata
+                .last_error
+                .unwrap()
+                .contains("more than MAX_REPO_BYTES=1")
+        );
+
+        // a mirror within the limit syncs again
+        cfg.max_repo_bytes = 100 * 1024 * 1024;
+        let synced = fetch(&cfg).await.unwrap();
+        assert!(synced.headers().get(STALE_HEADER).is_none());
+        assert_eq!(snapshot(&cfg), "changed");
+        assert!(load_metadata(&cfg, &pid("abc123")).last_error.is_none());
+    }
+
+    #[tokio::test]
+    async fn first_clones_without_a_free_slot_are_prepared_in_the_background() {
+        use crate::init_queue::InitStatus;
+        use tower::ServiceExt;
+
+        let root = tempfile::TempDir::new().unwrap();
+        let mut cfg = Config::for_test(root.path());
+        cfg.init_concurrency = 1;
+        let source = cfg.project_source_dir(&pid("abc123")).unwrap();
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::write(source.join("main.tex"), "hello").unwrap();
+        std::fs::write(source.join(".gitbridge"), "projtok").unwrap();
+        let state = Arc::new(AppState::new(cfg.clone()));
+        let app = crate::build_router(state.clone());
+        let fetch = |id: &str, accept: &'static str| {
+            let req = Request::get(format!("/git/{id}.git/info/refs?service=git-upload-pack"))
+                .header(header::AUTHORIZATION, "Bearer projtok")
+                .header(header::ACCEPT, accept)
+                .body(Body::empty())
+                .unwrap();
+            app.clone().oneshot(req)
+        };
+
+        let mut busy = state.initializations.claim(&pid("other")).unwrap();
+        assert!(busy.try_start());
+        let queued = fetch("abc123", "*/*").await.unwrap();
+        assert_eq!(queued.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(queued.headers().contains_key(header::RETRY_AFTER));
+        let body = to_bytes(queued.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(body, "repository is being prepared, retry shortly\n");
+        assert_eq!(
+            state.initializations.status(&pid("abc123")),
+            Some(InitStatus::Queued)
+        );
+        let json = fetch("abc123", "application/json").await.unwrap();
+        assert_eq!(json.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body = to_bytes(json.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["error"], "repository is being prepared, retry shortly");
+
+        // unknown projects are not queued
+        let missing = fetch("def456", "*/*").await.unwrap();
+        assert_eq!(missing.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(state.initializations.status(&pid("def456")), None);
+
+        drop(busy);
+        let bare = cfg.bare_repo_dir(&pid("abc123")).unwrap();
+        for _ in 0..500 {
+            if state.initializations.status(&pid("abc123")).is_none() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert!(bare.is_dir());
+        assert_eq!(
+            fetch("abc123", "*/*").await.unwrap().status(),
+            StatusCode::OK
+        );
+        assert_eq!(load_metadata(&cfg, &pid("abc123")).history.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn unchanged_sources_are_served_without_syncing() {
+        use tower::ServiceExt;
+
+        let root = tempfile::TempDir::new().unwrap();
+        let cfg = Config::for_test(root.path());
+        let source = cfg.project_source_dir(&pid("abc123")).unwrap();
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::write(source.join("main.tex"), "hello").unwrap();
+        std::fs::write(source.join(".gitbridge"), "projtok").unwrap();
+        let app = crate::build_router(Arc::new(AppState::new(cfg.clone())));
+        let fetch = |sync: Option<&str>| {
+            let mut req = Request::get("/git/abc123.git/info/refs?service=git-upload-pack")
+                .header(header::AUTHORIZATION, "Bearer projtok");
+            if let Some(sync) = sync {
+                req = req.header(SYNC_HEADER, sync);
+            }
+            app.clone().oneshot(req.body(Body::empty()).unwrap())
+        };
+        let syncs = || load_metadata(&cfg, &pid("abc123")).history.len();
+
+        assert_eq!(fetch(None).await.unwrap().status(), StatusCode::OK);
+        assert!(
+            load_metadata(&cfg, &pid("abc123"))
+                .source_fingerprint
+                .is_some()
+        );
+        assert_eq!(fetch(None).await.unwrap().status(), StatusCode::OK);
+        assert_eq!(syncs(), 1);
+
+        // forcing still syncs
+        fetch(Some("force")).await.unwrap();
+        assert_eq!(syncs(), 2);
+
+        std::fs::write(source.join("main.tex"), "hello again").unwrap();
+        fetch(None).await.unwrap();
+        fetch(None).await.unwrap();
+        assert_eq!(syncs(), 3);
+        let bare = cfg.bare_repo_dir(&pid("abc123")).unwrap();
+        let shown = SystemGit
+            .run(&["show", "master:main.tex"], &bare)
+            .unwrap()
+            .stdout;
+        assert_eq!(shown, b"hello again");
+    }
+
+    #[tokio::test]
+    async fn status_reports_the_mirror_without_syncing() {
+        use tower::ServiceExt;
+
+        let root = tempfile::TempDir::new().unwrap();
+        let cfg = Config::for_test(root.path());
+        let source = cfg.project_source_dir(&pid("abc123")).unwrap();
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::write(source.join("main.tex"), "hello").unwrap();
+        std::fs::write(source.join(".gitbridge"), "projtok").unwrap();
+        let state = Arc::new(AppState::new(cfg.clone()));
+        let app = crate::build_router(state.clone());
+        let get = |path: &str, token: &str| {
+            let req = Request::get(format!("/git/{path}"))
+                .header(header::AUTHORIZATION, format!("Bearer {token}"))
+                .body(Body::empty())
+                .unwrap();
+            app.clone().oneshot(req)
+        };
+        let status = |path: &'static str| {
+            let get = &get;
+            async move {
+                let resp = get(path, "projtok").await.unwrap();
+                assert_eq!(resp.status(), StatusCode::OK);
+                assert_eq!(resp.headers()[header::CONTENT_TYPE], "application/json");
+                let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+                assert!(!String::from_utf8_lossy(&body).contains("projtok"));
+                serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+            }
+        };
+
+        let denied = get("abc123.git/info/gitbridge", "wrong").await.unwrap();
+        assert_eq!(denied.status(), StatusCode::UNAUTHORIZED);
+        let missing = get("def456.git/info/gitbridge", "projtok").await.unwrap();
+        assert_eq!(missing.status(), StatusCode::UNAUTHORIZED);
+
+        let before = status("abc123.git/info/gitbridge").await;
+        assert_eq!(before["mirrored"], false);
+        assert!(before["head"].is_null() && before["last_sync"].is_null());
+        assert!(!cfg.bare_repo_dir(&pid("abc123")).unwrap().exists());
+
+        get("abc123.git/info/refs?service=git-upload-pack", "projtok")
+            .await
+            .unwrap();
+        let after = status("abc123.git/info/gitbridge/").await;
+        assert_eq!(after["branch"], "master");
+        assert_eq!(after["head"].as_str().unwrap().len(), 40);
+        assert_eq!(after["last_sync"]["ok"], true);
+        assert_eq!(after["last_sync"]["trigger"], "fetch");
+        assert_eq!(after["sync_running"], false);
+        assert_eq!(load_metadata(&cfg, &pid("abc123")).history.len(), 1);
+
+        let _held = state.locks.lock("abc123", "webhook sync").await;
+        let busy = status("abc123.git/info/gitbridge").await;
+        assert_eq!(busy["sync_running"], true);
+        assert_eq!(busy["lock_holder"]["operation"], "webhook sync");
+    }
+}
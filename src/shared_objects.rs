@@ -0,0 +1,480 @@
+//! Large blobs shared by all mirrors (`SHARED_OBJECTS`).
+//!
+//! `GIT_ROOT/shared-objects` is a bare repository listed in the
+//! `objects/info/alternates` of every mirror. After a sync brings in files of
+//! at least `SHARED_OBJECTS_MIN_BYTES`, their blobs are copied there as loose
+//! objects and the mirror is repacked without them, so a figure used by
+//! hundreds of projects is stored once. `manifest.json` counts which projects
+//! use each shared blob.
+//!
+//! Syncs hold `gc.lock` shared and [`collect_garbage`] holds it exclusively.
+//! The collection decides from the objects reachable in every mirror and trash
+//! entry rather than from the manifest, and deletes nothing when a repository
+//! cannot be read, so a blob still used anywhere is never removed.
+
+use crate::config::Config;
+use crate::error::BridgeError;
+use crate::git::{GitRunner, git_stdout};
+use crate::project_id::ProjectId;
+use crate::repo::{list_mirrors, run_git};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+const MANIFEST_FILE: &str = "manifest.json";
+
+/// Held shared by syncs and exclusively by garbage collection.
+const GC_LOCK_FILE: &str = "gc.lock";
+
+/// Serializes read-modify-write cycles on the manifest.
+const MANIFEST_LOCK_FILE: &str = "manifest.lock";
+
+/// A blob in the shared store.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) struct SharedBlob {
+    pub(crate) bytes: u64,
+    /// Projects whose mirror uses the blob
+    pub(crate) projects: BTreeSet<String>,
+}
+
+/// Contents of `manifest.json`, keyed by object id.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub(crate) struct Manifest {
+    pub(crate) objects: BTreeMap<String, SharedBlob>,
+}
+
+/// Outcome of a garbage collection of the shared store.
+#[derive(Debug, Clone, Default, Serialize, PartialEq, Eq)]
+pub(crate) struct GcReport {
+    /// Mirrors and trash entries whose objects were listed
+    pub(crate) repos_scanned: usize,
+    /// Shared blobs still used by some repository
+    pub(crate) kept: usize,
+    pub(crate) removed: usize,
+    pub(crate) freed_bytes: u64,
+}
+
+fn objects_dir(cfg: &Config) -> PathBuf {
+    cfg.shared_objects_dir().join("objects")
+}
+
+/// Path of the loose object `oid` below `objects`.
+fn loose_path(objects: &Path, oid: &str) -> PathBuf {
+    objects.join(&oid[..2]).join(&oid[2..])
+}
+
+fn open_lock(cfg: &Config, name: &str) -> Result<File, BridgeError> {
+    Ok(OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(cfg.shared_objects_dir().join(name))?)
+}
+
+/// Create the shared store if needed. Blocking.
+fn init_store(git: &dyn GitRunner, cfg: &Config) -> Result<(), BridgeError> {
+    let dir = cfg.shared_objects_dir();
+    if dir.join("objects").is_dir() {
+        return Ok(());
+    }
+    fs::create_dir_all(&dir)?;
+    run_git(git, &["init", "--bare", "--quiet", "."], &dir)
+}
+
+/// With `SHARED_OBJECTS`, take the shared side of the garbage collection
+/// lock for a sync; released when the file is dropped. Blocking.
+pub(crate) fn lock_for_sync(
+    git: &dyn GitRunner,
+    cfg: &Config,
+) -> Result<Option<File>, BridgeError> {
+    if !cfg.shared_objects {
+        return Ok(None);
+    }
+    init_store(git, cfg)?;
+    let file = open_lock(cfg, GC_LOCK_FILE)?;
+    file.lock_shared()?;
+    Ok(Some(file))
+}
+
+fn load_manifest(cfg: &Config) -> Result<Manifest, BridgeError> {
+    match fs::read(cfg.shared_objects_dir().join(MANIFEST_FILE)) {
+        Ok(raw) => serde_json::from_slice(&raw)
+            .map_err(|e| BridgeError::Other(format!("invalid shared objects manifest: {e}"))),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Manifest::default()),
+        Err(e) => Err(BridgeError::Io(e)),
+    }
+}
+
+fn save_manifest(cfg: &Config, manifest: &Manifest) -> Result<(), BridgeError> {
+    let dir = cfg.shared_objects_dir();
+    let tmp = dir.join(format!("{MANIFEST_FILE}.tmp"));
+    let json = serde_json::to_vec_pretty(manifest)
+        .map_err(|e| BridgeError::Other(format!("cannot serialize manifest: {e}")))?;
+    fs::write(&tmp, json)?;
+    fs::rename(&tmp, dir.join(MANIFEST_FILE))?;
+    Ok(())
+}
+
+/// The shared blobs and who uses them, as last recorded. Blocking.
+#[cfg(feature = "admin-ui")]
+pub(crate) fn manifest(cfg: &Config) -> Result<Manifest, BridgeError> {
+    load_manifest(cfg)
+}
+
+/// Make the shared store an alternate of `bare_repo_dir`.
+fn ensure_alternate(cfg: &Config, bare_repo_dir: &Path) -> Result<(), BridgeError> {
+    let shared = fs::canonicalize(objects_dir(cfg))?;
+    let shared = shared
+        .to_str()
+        .ok_or_else(|| BridgeError::Other("invalid shared objects path".into()))?;
+    let info = bare_repo_dir.join("objects").join("info");
+    let file = info.join("alternates");
+    let current = match fs::read_to_string(&file) {
+        Ok(current) => current,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(BridgeError::Io(e)),
+    };
+    if current.lines().any(|line| line == shared) {
+        return Ok(());
+    }
+    fs::create_dir_all(&info)?;
+    fs::write(&file, format!("{current}{shared}\n"))?;
+    Ok(())
+}
+
+/// Blobs of at least `min_bytes` on `branch`, with their sizes.
+fn large_blobs(
+    git: &dyn GitRunner,
+    bare_repo_dir: &Path,
+    branch: &str,
+    min_bytes: u64,
+) -> Result<BTreeMap<String, u64>, BridgeError> {
+    let refname = format!("refs/heads/{branch}");
+    let out = git_stdout(git, bare_repo_dir, &["ls-tree", "-r", "-l", "-z", &refname])?;
+    let mut blobs = BTreeMap::new();
+    // `<mode> <type> <object> <size>\t<path>`, NUL-terminated
+    for entry in out.split(|&b| b == 0) {
+        let entry = String::from_utf8_lossy(entry);
+        let Some((meta, _path)) = entry.split_once('\t') else {
+            continue;
+        };
+        let fields: Vec<&str> = meta.split_whitespace().collect();
+        if let [_, "blob", oid, size] = fields.as_slice()
+            && let Ok(size) = size.parse::<u64>()
+            && size >= min_bytes
+        {
+            blobs.insert(oid.to_string(), size);
+        }
+    }
+    Ok(blobs)
+}
+
+/// Copy blob `oid` of `bare_repo_dir` into the shared store.
+fn copy_blob(
+    git: &dyn GitRunner,
+    cfg: &Config,
+    bare_repo_dir: &Path,
+    oid: &str,
+) -> Result<(), BridgeError> {
+    let content = git_stdout(git, bare_repo_dir, &["cat-file", "blob", oid])?;
+    let store = cfg.shared_objects_dir();
+    let tmp = tempfile::NamedTempFile::new_in(&store)?;
+    fs::write(tmp.path(), content)?;
+    let path = tmp
+        .path()
+        .to_str()
+        .ok_or_else(|| BridgeError::Other("invalid temp path".into()))?;
+    let written = git_stdout(git, &store, &["hash-object", "-w", "--no-filters", path])?;
+    if String::from_utf8_lossy(&written).trim() != oid {
+        return Err(BridgeError::Other(format!(
+            "blob {oid} changed while being shared"
+        )));
+    }
+    Ok(())
+}
+
+/// Move the blobs of at least `SHARED_OBJECTS_MIN_BYTES` on the read-only
+/// branch of a mirror to the shared store and record them in the manifest.
+/// Callers hold the project lock and the lock from [`lock_for_sync`].
+/// Returns the number of blobs newly added to the store. Blocking.
+pub(crate) fn share_large_blobs(
+    git: &dyn GitRunner,
+    cfg: &Config,
+    project_id: &ProjectId,
+    bare_repo_dir: &Path,
+) -> Result<usize, BridgeError> {
+    ensure_alternate(cfg, bare_repo_dir)?;
+    let blobs = large_blobs(
+        git,
+        bare_repo_dir,
+        &cfg.readonly_branch,
+        cfg.shared_objects_min_bytes,
+    )?;
+    if blobs.is_empty() {
+        return Ok(0);
+    }
+
+    let objects = objects_dir(cfg);
+    let mut added = 0;
+    for oid in blobs.keys() {
+        if !loose_path(&objects, oid).is_file() {
+            copy_blob(git, cfg, bare_repo_dir, oid)?;
+            added += 1;
+        }
+    }
+
+    {
+        let lock = open_lock(cfg, MANIFEST_LOCK_FILE)?;
+        lock.lock()?;
+        let mut manifest = load_manifest(cfg)?;
+        for (oid, bytes) in &blobs {
+            let entry = manifest.objects.entry(oid.clone()).or_default();
+            entry.bytes = *bytes;
+            entry.projects.insert(project_id.to_string());
+        }
+        save_manifest(cfg, &manifest)?;
+    }
+
+    // `-l` leaves out everything the alternates have; loose copies are not
+    // repacked and would stay, so drop them as well
+    run_git(git, &["repack", "-a", "-d", "-l", "-q"], bare_repo_dir)?;
+    let own_objects = bare_repo_dir.join("objects");
+    for oid in blobs.keys() {
+        match fs::remove_file(loose_path(&own_objects, oid)) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => warn!(%project_id, %oid, "cannot remove duplicate loose object: {e}"),
+        }
+    }
+    info!(%project_id, shared = blobs.len(), added, "moved large blobs to the shared store");
+    Ok(added)
+}
+
+/// Loose objects in the shared store with their sizes on disk.
+fn stored_objects(cfg: &Config) -> Result<BTreeMap<String, u64>, BridgeError> {
+    let mut stored = BTreeMap::new();
+    let objects = objects_dir(cfg);
+    for dir in fs::read_dir(&objects)?.flatten() {
+        let name = dir.file_name();
+        let Some(prefix) = name.to_str().filter(|n| n.len() == 2) else {
+            continue;
+        };
+        if !prefix.bytes().all(|b| b.is_ascii_hexdigit()) {
+            continue;
+        }
+        for file in fs::read_dir(dir.path())?.flatten() {
+            let Some(rest) = file.file_name().to_str().map(str::to_string) else {
+                continue;
+            };
+            if rest.bytes().all(|b| b.is_ascii_hexdigit()) {
+                let bytes = file.metadata().map(|m| m.len()).unwrap_or(0);
+                stored.insert(format!("{prefix}{rest}"), bytes);
+            }
+        }
+    }
+    Ok(stored)
+}
+
+/// Mirrors and trash entries using the shared store, by project id.
+fn borrowing_repos(cfg: &Config) -> Result<Vec<(String, PathBuf)>, BridgeError> {
+    let mut repos: Vec<(String, PathBuf)> = list_mirrors(cfg)?
+        .into_iter()
+        .filter_map(|id| {
+            let dir = cfg.bare_repo_dir(&id).ok()?;
+            Some((id.to_string(), dir))
+        })
+        .collect();
+    match fs::read_dir(cfg.trash_dir()) {
+        Ok(entries) => {
+            for entry in entries.flatten() {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                let id = crate::trash::entry_project_id(&name).map_or(name, String::from);
+                repos.push((id, entry.path()));
+            }
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(BridgeError::Io(e)),
+    }
+    Ok(repos
+        .into_iter()
+        .filter(|(_, dir)| dir.join("objects/info/alternates").is_file())
+        .collect())
+}
+
+/// Delete the shared blobs that no mirror or trash entry can reach any
+/// more, and rebuild the manifest from what they do reach. Waits for
+/// running syncs and holds off new ones. Blocking.
+pub(crate) fn collect_garbage(git: &dyn GitRunner, cfg: &Config) -> Result<GcReport, BridgeError> {
+    if !objects_dir(cfg).is_dir() {
+        return Ok(GcReport::default());
+    }
+    let lock = open_lock(cfg, GC_LOCK_FILE)?;
+    lock.lock()?;
+
+    let stored = stored_objects(cfg)?;
+    let repos = borrowing_repos(cfg)?;
+    let mut manifest = Manifest::default();
+    for (project_id, dir) in &repos {
+        // any failure aborts the collection before anything is deleted
+        let out = git_stdout(git, dir, &["rev-list", "--objects", "--all"])?;
+        let reachable: HashSet<&str> = out
+            .split(|&b| b == b'\n')
+            .filter_map(|line| std::str::from_utf8(line).ok())
+            .filter_map(|line| line.split(' ').next())
+            .filter(|oid| !oid.is_empty())
+            .collect();
+        for (oid, bytes) in &stored {
+            if reachable.contains(oid.as_str()) {
+                let entry = manifest.objects.entry(oid.clone()).or_default();
+                entry.bytes = *bytes;
+                entry.projects.insert(project_id.clone());
+            }
+        }
+    }
+
+    let previous = load_manifest(cfg).unwrap_or_default();
+    for (oid, entry) in manifest.objects.iter_mut() {
+        // the manifest has the blob size, the store only the compressed one
+        if let Some(known) = previous.objects.get(oid) {
+            entry.bytes = known.bytes;
+        }
+    }
+
+    let objects = objects_dir(cfg);
+    let mut report = GcReport {
+        repos_scanned: repos.len(),
+        kept: manifest.objects.len(),
+        ..GcReport::default()
+    };
+    for (oid, bytes) in &stored {
+        if manifest.objects.contains_key(oid) {
+            continue;
+        }
+        fs::remove_file(loose_path(&objects, oid))?;
+        report.removed += 1;
+        report.freed_bytes += bytes;
+    }
+    {
+        let lock = open_lock(cfg, MANIFEST_LOCK_FILE)?;
+        lock.lock()?;
+        save_manifest(cfg, &manifest)?;
+    }
+    info!(
+        repos = report.repos_scanned,
+        kept = report.kept,
+        removed = report.removed,
+        freed_bytes = report.freed_bytes,
+        "collected shared object garbage"
+    );
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git::SystemGit;
+    use crate::project_id::pid;
+    use crate::repo::ensure_repo_blocking;
+    use crate::source::FsProjectSource;
+    use tempfile::TempDir;
+
+    fn shared_config(root: &Path) -> Config {
+        Config {
+            shared_objects: true,
+            shared_objects_min_bytes: 1024,
+            ..Config::for_test(root)
+        }
+    }
+
+    fn write_project(cfg: &Config, id: &str, figure: &[u8]) {
+        let dir = cfg.project_source_dir(&pid(id)).unwrap();
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("main.tex"), format!("project {id}")).unwrap();
+        fs::write(dir.join("figure.png"), figure).unwrap();
+    }
+
+    fn sync(cfg: &Config, id: &str) {
+        let source = FsProjectSource::new(cfg.clone());
+        ensure_repo_blocking(&SystemGit, &source, cfg, &pid(id)).unwrap();
+    }
+
+    /// Whether the mirror has `oid` itself rather than through the store.
+    fn has_own_copy(cfg: &Config, id: &str, oid: &str) -> bool {
+        let mirror = cfg.bare_repo_dir(&pid(id)).unwrap();
+        let alternates = mirror.join("objects/info/alternates");
+        let saved = fs::read(&alternates).unwrap();
+        fs::remove_file(&alternates).unwrap();
+        let out = SystemGit.run(&["cat-file", "-e", oid], &mirror).unwrap();
+        fs::write(&alternates, saved).unwrap();
+        out.status.success()
+    }
+
+    #[test]
+    fn identical_large_files_are_stored_once_and_collected_when_unused() {
+        let root = TempDir::new().unwrap();
+        let cfg = shared_config(root.path());
+        let figure: Vec<u8> = (0..4096u32).map(|i| (i * 7 % 251) as u8).collect();
+        write_project(&cfg, "a1", &figure);
+        write_project(&cfg, "b2", &figure);
+        sync(&cfg, "a1");
+        sync(&cfg, "b2");
+
+        let manifest = load_manifest(&cfg).unwrap();
+        assert_eq!(manifest.objects.len(), 1);
+        let (oid, blob) = manifest.objects.iter().next().unwrap();
+        assert_eq!(blob.bytes, 4096);
+        assert_eq!(
+            blob.projects.iter().map(String::as_str).collect::<Vec<_>>(),
+            ["a1", "b2"]
+        );
+        assert!(loose_path(&objects_dir(&cfg), oid).is_file());
+        for id in ["a1", "b2"] {
+            assert!(!has_own_copy(&cfg, id, oid));
+            let fsck = SystemGit
+                .run(
+                    &["fsck", "--no-progress"],
+                    &cfg.bare_repo_dir(&pid(id)).unwrap(),
+                )
+                .unwrap();
+            assert!(fsck.status.success());
+        }
+
+        // a changed figure is shared too; the old one stays in the history
+        write_project(&cfg, "b2", &[b'x'; 2048]);
+        sync(&cfg, "b2");
+        assert_eq!(load_manifest(&cfg).unwrap().objects.len(), 2);
+
+        // a1 in the trash still needs the first figure
+        crate::trash::remove_mirror(&cfg, &pid("a1"), false).unwrap();
+        let report = collect_garbage(&SystemGit, &cfg).unwrap();
+        assert_eq!(
+            (report.repos_scanned, report.kept, report.removed),
+            (2, 2, 0)
+        );
+
+        fs::remove_dir_all(cfg.bare_repo_dir(&pid("b2")).unwrap()).unwrap();
+        let report = collect_garbage(&SystemGit, &cfg).unwrap();
+        assert_eq!((report.kept, report.removed), (1, 1));
+        let manifest = load_manifest(&cfg).unwrap();
+        assert_eq!(manifest.objects[oid].projects.len(), 1);
+        assert!(loose_path(&objects_dir(&cfg), oid).is_file());
+    }
+
+    #[test]
+    fn unreadable_repositories_stop_the_collection() {
+        let root = TempDir::new().unwrap();
+        let cfg = shared_config(root.path());
+        write_project(&cfg, "a1", &[b'y'; 2048]);
+        sync(&cfg, "a1");
+        // a mirror git cannot read must not count as using nothing
+        let broken = cfg.bare_repo_dir(&pid("zz")).unwrap();
+        fs::create_dir_all(broken.join("objects/info")).unwrap();
+        fs::write(broken.join("objects/info/alternates"), "").unwrap();
+        fs::remove_dir_all(cfg.bare_repo_dir(&pid("a1")).unwrap()).unwrap();
+
+        assert!(collect_garbage(&SystemGit, &cfg).is_err());
+        assert_eq!(stored_objects(&cfg).unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,174 @@
+//! Graceful shutdown of `serve`. On SIGTERM or SIGINT the listeners stop
+//! accepting, open connections are closed once their current request has
+//! been answered, and no new syncs start. The process then waits up to
+//! `SHUTDOWN_GRACE_SECONDS` for running syncs, git requests and background
+//! runs, so a sync is not killed halfway through pushing into its mirror. A
+//! second signal ends the wait at once.
+
+use crate::AppState;
+use std::future::Future;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::signal::unix::{Signal, SignalKind, signal};
+use tokio::sync::watch;
+use tracing::{info, warn};
+
+/// How often the wait checks for running operations.
+const DRAIN_POLL: Duration = Duration::from_millis(100);
+
+/// Whether the bridge is shutting down.
+pub(crate) struct Shutdown {
+    stopping: watch::Sender<bool>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self {
+            stopping: watch::Sender::new(false),
+        }
+    }
+}
+
+impl Shutdown {
+    pub(crate) fn begin(&self) {
+        self.stopping.send_replace(true);
+    }
+
+    pub(crate) fn is_stopping(&self) -> bool {
+        *self.stopping.borrow()
+    }
+
+    /// Wait until shutdown begins.
+    pub(crate) async fn stopped(&self) {
+        let mut stopping = self.stopping.subscribe();
+        // the sender lives in self, so the channel is never closed here
+        let _ = stopping.wait_for(|stopping| *stopping).await;
+    }
+}
+
+/// SIGTERM and SIGINT, handled by the bridge instead of ending the process
+/// from the moment they are listened to.
+pub(crate) struct Signals {
+    term: Signal,
+    int: Signal,
+}
+
+impl Signals {
+    pub(crate) fn listen() -> io::Result<Self> {
+        Ok(Self {
+            term: signal(SignalKind::terminate())?,
+            int: signal(SignalKind::interrupt())?,
+        })
+    }
+
+    /// Wait for the next signal and name it.
+    pub(crate) async fn next(&mut self) -> &'static str {
+        tokio::select! {
+            _ = self.term.recv() => "SIGTERM",
+            _ = self.int.recv() => "SIGINT",
+        }
+    }
+}
+
+/// Wait until no sync, git request or background run of `state` and its
+/// sources is left, for at most `SHUTDOWN_GRACE_SECONDS` or until `second`
+/// (the next signal) resolves. Logs the projects still locked if the wait
+/// ends early; returns whether everything finished.
+pub(crate) async fn drain(state: &AppState, second: impl Future<Output = &'static str>) -> bool {
+    let states: Vec<&AppState> = std::iter::once(state)
+        .chain(state.sources.values().map(Arc::as_ref))
+        .collect();
+    let idle =
+        || state.maintenance.in_flight() == 0 && states.iter().all(|state| state.locks.held() == 0);
+    let grace = Duration::from_secs(state.cfg.shutdown_grace_seconds);
+    let waited = tokio::time::timeout(grace, async {
+        while !idle() {
+            tokio::time::sleep(DRAIN_POLL).await;
+        }
+    });
+    let reason = tokio::select! {
+        waited = waited => match waited {
+            Ok(()) => {
+                info!("running operations finished, exiting");
+                return true;
+            }
+            Err(_) => format!("SHUTDOWN_GRACE_SECONDS ({}s) passed", grace.as_secs()),
+        },
+        signal = second => format!("{signal} received again"),
+    };
+
+    let locked: Vec<String> = states
+        .iter()
+        .flat_map(|state| {
+            let source = state.cfg.source_name.as_deref();
+            state
+                .locks
+                .holders()
+                .into_iter()
+                .map(move |(id, operation)| match source {
+                    Some(source) => format!("{source}/{id} ({operation})"),
+                    None => format!("{id} ({operation})"),
+                })
+        })
+        .collect();
+    warn!(
+        in_flight = state.maintenance.in_flight(),
+        "{reason}, exiting with operations still running; projects locked: {}",
+        if locked.is_empty() {
+            "none".to_string()
+        } else {
+            locked.join(", ")
+        }
+    );
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::error::BridgeError;
+    use crate::metadata::SyncTrigger;
+    use crate::project_id::pid;
+    use crate::server::sync_project;
+    use std::time::Instant;
+
+    #[tokio::test]
+    async fn shutdown_waits_for_running_syncs_until_the_deadline() {
+        let root = tempfile::TempDir::new().unwrap();
+        let mut cfg = Config::for_test(root.path());
+        cfg.shutdown_grace_seconds = 5;
+        let state = Arc::new(AppState::new(cfg));
+
+        let held = state.locks.lock("p1", "fetch sync").await;
+        let release = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            drop(held);
+        });
+        let start = Instant::now();
+        assert!(drain(&state, std::future::pending()).await);
+        assert!(start.elapsed() >= Duration::from_millis(300));
+        release.await.unwrap();
+
+        // no new syncs start once shutting down
+        state.shutdown.begin();
+        let refused = sync_project(&state, &pid("p2"), SyncTrigger::Webhook).await;
+        assert!(
+            matches!(&refused, Err(BridgeError::ProjectBusy(msg)) if msg.contains("shutting down")),
+            "{refused:?}"
+        );
+
+        // a lock still held after the deadline, or a second signal, ends the
+        // wait
+        let _held = state.locks.lock("p1", "webhook sync").await;
+        let mut short = state.cfg.clone();
+        short.shutdown_grace_seconds = 0;
+        let short = AppState::new(short);
+        let _held_short = short.locks.lock("p3", "fetch sync").await;
+        assert!(!drain(&short, std::future::pending()).await);
+        let start = Instant::now();
+        assert!(!drain(&state, async { "SIGTERM" }).await);
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+}
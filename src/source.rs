@@ -0,0 +1,461 @@
+use crate::config::{Config, ProjectsLayout, find_project_dir};
+use crate::error::BridgeError;
+use crate::project_id::ProjectId;
+use dashmap::DashMap;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::warn;
+use walkdir::WalkDir;
+
+/// One file or directory of a project snapshot.
+#[derive(Debug, Clone)]
+pub struct SourceEntry {
+    /// Path relative to the project root.
+    pub path: PathBuf,
+    pub is_dir: bool,
+    /// Size in bytes (0 for directories).
+    pub len: u64,
+    pub modified: Option<SystemTime>,
+}
+
+/// Where project contents come from. The sync code only reads snapshots
+/// through this trait; all methods are blocking and are called from
+/// `spawn_blocking` contexts.
+pub trait ProjectSource: Send + Sync {
+    /// Whether the project currently exists in the source.
+    fn exists(&self, project_id: &ProjectId) -> bool;
+
+    /// Ids of all projects the source knows about. Backends that cannot
+    /// enumerate projects return an empty list.
+    fn list_projects(&self) -> Result<Vec<ProjectId>, BridgeError> {
+        Ok(Vec::new())
+    }
+
+    /// All files and directories of the project, excluding any `.git`.
+    fn enumerate(
+        &self,
+        project_id: &ProjectId,
+    ) -> Result<Box<dyn Iterator<Item = SourceEntry> + Send>, BridgeError>;
+
+    /// Open one file of the project by relative path.
+    fn read(
+        &self,
+        project_id: &ProjectId,
+        path: &Path,
+    ) -> Result<Box<dyn Read + Send>, BridgeError>;
+
+    /// Replace the project's `.gitbridge` token file with `token`, or remove
+    /// it when `token` is `None`. Read-only backends keep the default, which
+    /// fails.
+    fn write_project_token(
+        &self,
+        project_id: &ProjectId,
+        token: Option<&str>,
+    ) -> Result<(), BridgeError> {
+        let _ = (project_id, token);
+        Err(BridgeError::Other(
+            "this project source cannot write token files".to_string(),
+        ))
+    }
+
+    /// Cheap digest of the current snapshot that changes whenever its content
+    /// may have changed, compared with the one of the last sync to skip
+    /// syncs of unchanged projects. `None`, the default, when the backend
+    /// cannot tell; such projects are synced on every fetch.
+    fn fingerprint(&self, project_id: &ProjectId) -> Result<Option<String>, BridgeError> {
+        let _ = project_id;
+        Ok(None)
+    }
+
+    /// Modification time of the project's directory, for listings. `None`,
+    /// the default, when the backend has no such thing or it cannot be read.
+    fn modified(&self, project_id: &ProjectId) -> Option<SystemTime> {
+        let _ = project_id;
+        None
+    }
+
+    /// Write the complete snapshot into `dst`, overwriting existing files but
+    /// never deleting anything. Backends with a faster way than
+    /// enumerate + read should override this.
+    fn copy_to_dir(&self, project_id: &ProjectId, dst: &Path) -> Result<(), BridgeError> {
+        for entry in self.enumerate(project_id)? {
+            let target_path = dst.join(&entry.path);
+            if entry.is_dir {
+                fs::create_dir_all(&target_path).map_err(BridgeError::Io)?;
+                continue;
+            }
+            if let Some(parent) = target_path.parent() {
+                fs::create_dir_all(parent).map_err(BridgeError::Io)?;
+            }
+            let mut reader = self.read(project_id, &entry.path)?;
+            let mut file = fs::File::create(&target_path).map_err(BridgeError::Io)?;
+            io::copy(&mut reader, &mut file).map_err(BridgeError::Io)?;
+        }
+        Ok(())
+    }
+}
+
+/// The project source selected by `SOURCE_BACKEND`.
+pub(crate) fn from_config(cfg: &Config) -> Arc<dyn ProjectSource> {
+    match cfg.source_backend {
+        #[cfg(feature = "docstore")]
+        crate::config::SourceBackend::Docstore => {
+            Arc::new(crate::docstore::DocstoreProjectSource::new(cfg.clone()))
+        }
+        _ => Arc::new(FsProjectSource::new(cfg.clone())),
+    }
+}
+
+/// Project directories below `sharelatex_data_path/projects_dir`, either
+/// directly or one per-user directory deeper (see [`ProjectsLayout`]).
+pub struct FsProjectSource {
+    cfg: Config,
+    /// Project directories found in per-user directories, kept while they
+    /// exist
+    nested: DashMap<String, PathBuf>,
+}
+
+impl FsProjectSource {
+    pub fn new(cfg: Config) -> Self {
+        Self {
+            cfg,
+            nested: DashMap::new(),
+        }
+    }
+
+    /// Directory of a project, if it exists.
+    fn project_dir(&self, project_id: &ProjectId) -> Option<PathBuf> {
+        let base = self.cfg.projects_root();
+        match self.cfg.projects_layout {
+            ProjectsLayout::Flat => find_project_dir(&base, project_id),
+            ProjectsLayout::Nested => self.find_nested(&base, project_id),
+            ProjectsLayout::Auto => find_project_dir(&base, project_id)
+                .filter(|dir| !is_user_dir(dir))
+                .or_else(|| self.find_nested(&base, project_id)),
+        }
+    }
+
+    fn existing_dir(&self, project_id: &ProjectId) -> Result<PathBuf, BridgeError> {
+        self.project_dir(project_id)
+            .ok_or_else(|| BridgeError::ProjectNotFound(project_id.to_string()))
+    }
+
+    /// Look for the project in every per-user directory; the first one in
+    /// name order wins.
+    fn find_nested(&self, base: &Path, project_id: &ProjectId) -> Option<PathBuf> {
+        if let Some(dir) = self.nested.get(project_id.as_str()).map(|dir| dir.clone())
+            && dir.is_dir()
+        {
+            return Some(dir);
+        }
+        let matches: Vec<PathBuf> = self
+            .user_dirs(base)
+            .iter()
+            .filter_map(|user| find_project_dir(user, project_id))
+            .collect();
+        let Some(chosen) = matches.first().cloned() else {
+            self.nested.remove(project_id.as_str());
+            return None;
+        };
+        if matches.len() > 1 {
+            warn!(
+                %project_id,
+                count = matches.len(),
+                chosen = %chosen.display(),
+                "project found in several user directories; using first match"
+            );
+        }
+        self.nested.insert(project_id.to_string(), chosen.clone());
+        Some(chosen)
+    }
+
+    /// Per-user directories below `base`, sorted. In the auto layout only
+    /// directories without files of their own count.
+    fn user_dirs(&self, base: &Path) -> Vec<PathBuf> {
+        let mut dirs: Vec<PathBuf> = subdirectories(base)
+            .into_iter()
+            .filter(|dir| self.cfg.projects_layout != ProjectsLayout::Auto || is_user_dir(dir))
+            .collect();
+        dirs.sort();
+        dirs
+    }
+}
+
+impl ProjectSource for FsProjectSource {
+    fn exists(&self, project_id: &ProjectId) -> bool {
+        self.project_dir(project_id).is_some()
+    }
+
+    fn modified(&self, project_id: &ProjectId) -> Option<SystemTime> {
+        fs::metadata(self.project_dir(project_id)?)
+            .ok()?
+            .modified()
+            .ok()
+    }
+
+    /// Directory names, with a `-<suffix>` (see [`Config::project_source_dir`])
+    /// stripped.
+    fn list_projects(&self) -> Result<Vec<ProjectId>, BridgeError> {
+        let base = self.cfg.projects_root();
+        if let Err(e) = fs::read_dir(&base) {
+            return match e.kind() {
+                io::ErrorKind::NotFound => Ok(Vec::new()),
+                _ => Err(BridgeError::Io(e)),
+            };
+        }
+        let mut dirs = match self.cfg.projects_layout {
+            ProjectsLayout::Flat => subdirectories(&base),
+            ProjectsLayout::Nested => Vec::new(),
+            ProjectsLayout::Auto => subdirectories(&base)
+                .into_iter()
+                .filter(|dir| !is_user_dir(dir))
+                .collect(),
+        };
+        if self.cfg.projects_layout != ProjectsLayout::Flat {
+            for user in self.user_dirs(&base) {
+                dirs.extend(subdirectories(&user));
+            }
+        }
+        let mut ids: Vec<ProjectId> = dirs
+            .iter()
+            .filter_map(|dir| {
+                let name = dir.file_name()?.to_str()?;
+                let id = ProjectId::new(name.split_once('-').map_or(name, |(id, _)| id)).ok()?;
+                self.cfg.accepts_project_id(&id).then_some(id)
+            })
+            .collect();
+        ids.sort();
+        ids.dedup();
+        Ok(ids)
+    }
+
+    /// Names, sizes and modification times of all entries; file contents
+    /// are not read.
+    fn fingerprint(&self, project_id: &ProjectId) -> Result<Option<String>, BridgeError> {
+        let mut entries: Vec<SourceEntry> = self.enumerate(project_id)?.collect();
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        let mut hasher = Sha256::new();
+        for entry in entries {
+            let modified = entry
+                .modified
+                .and_then(|at| at.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |at| at.as_nanos());
+            hasher.update(entry.path.as_os_str().as_encoded_bytes());
+            hasher.update(format!("\0{}\0{}\0{modified}\n", entry.is_dir, entry.len));
+        }
+        Ok(Some(hex::encode(hasher.finalize())))
+    }
+
+    fn enumerate(
+        &self,
+        project_id: &ProjectId,
+    ) -> Result<Box<dyn Iterator<Item = SourceEntry> + Send>, BridgeError> {
+        let root = self.existing_dir(project_id)?;
+        let iter = WalkDir::new(&root)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter_map(move |entry| {
+                let rel = entry.path().strip_prefix(&root).ok()?.to_path_buf();
+                if rel.as_os_str().is_empty() || is_in_git_dir(&rel) {
+                    return None;
+                }
+                let file_type = entry.file_type();
+                if !file_type.is_dir() && !file_type.is_file() {
+                    return None;
+                }
+                let meta = entry.metadata().ok();
+                Some(SourceEntry {
+                    path: rel,
+                    is_dir: file_type.is_dir(),
+                    len: meta
+                        .as_ref()
+                        .filter(|m| m.is_file())
+                        .map(|m| m.len())
+                        .unwrap_or(0),
+                    modified: meta.and_then(|m| m.modified().ok()),
+                })
+            });
+        Ok(Box::new(iter))
+    }
+
+    fn read(
+        &self,
+        project_id: &ProjectId,
+        path: &Path,
+    ) -> Result<Box<dyn Read + Send>, BridgeError> {
+        let full = self
+            .project_dir(project_id)
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))?
+            .join(path);
+        let file = fs::File::open(full).map_err(BridgeError::Io)?;
+        Ok(Box::new(file))
+    }
+
+    fn write_project_token(
+        &self,
+        project_id: &ProjectId,
+        token: Option<&str>,
+    ) -> Result<(), BridgeError> {
+        let path = self.existing_dir(project_id)?.join(".gitbridge");
+        match token {
+            Some(token) => {
+                let tmp_path = path.with_extension("tmp");
+                fs::write(&tmp_path, format!("{token}\n"))?;
+                fs::rename(&tmp_path, &path)?;
+            }
+            None => match fs::remove_file(&path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(BridgeError::Io(e)),
+                _ => {}
+            },
+        }
+        Ok(())
+    }
+
+    fn copy_to_dir(&self, project_id: &ProjectId, dst: &Path) -> Result<(), BridgeError> {
+        copy_recursive(&self.existing_dir(project_id)?, dst)
+    }
+}
+
+/// Subdirectories of `dir`; none if it cannot be read.
+fn subdirectories(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .filter(|entry| entry.file_type().map(|ft| ft.is_dir()).unwrap_or(false))
+        .map(|entry| entry.path())
+        .collect()
+}
+
+/// Whether `dir` looks like a per-user directory rather than a project: it
+/// holds no files, only project directories.
+fn is_user_dir(dir: &Path) -> bool {
+    fs::read_dir(dir).is_ok_and(|entries| {
+        !entries
+            .flatten()
+            .any(|entry| entry.file_type().is_ok_and(|ft| ft.is_file()))
+    })
+}
+
+fn is_in_git_dir(rel: &Path) -> bool {
+    rel.components().any(|c| c.as_os_str() == ".git")
+}
+
+/// Copy files recursively from `src` to `dst`
+/// Skips `.git` dirs in `src` just in case.
+fn copy_recursive(src: &Path, dst: &Path) -> Result<(), BridgeError> {
+    for entry in WalkDir::new(src).into_iter().filter_map(|e| e.ok()) {
+        let path = entry.path();
+        let rel = match path.strip_prefix(src) {
+            Ok(r) => r,
+            Err(_) => continue,
+        };
+        if rel.as_os_str().is_empty() {
+            continue;
+        }
+        if is_in_git_dir(rel) {
+            // skip any embedded .git
+            continue;
+        }
+        let target_path = dst.join(rel);
+        if entry.file_type().is_dir() {
+            fs::create_dir_all(&target_path).map_err(BridgeError::Io)?;
+        } else if entry.file_type().is_file() {
+            if let Some(parent) = target_path.parent() {
+                fs::create_dir_all(parent).map_err(BridgeError::Io)?;
+            }
+            fs::copy(path, &target_path).map_err(BridgeError::Io)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::project_id::pid;
+    use tempfile::TempDir;
+
+    fn setup(layout: ProjectsLayout) -> (TempDir, Config) {
+        let root = TempDir::new().unwrap();
+        let mut cfg = Config::for_test(root.path());
+        cfg.projects_layout = layout;
+        (root, cfg)
+    }
+
+    fn write_project(dir: &Path, token: &str) {
+        fs::create_dir_all(dir).unwrap();
+        fs::write(dir.join("main.tex"), "hello").unwrap();
+        fs::write(dir.join(".gitbridge"), token).unwrap();
+    }
+
+    fn token(source: &FsProjectSource, project_id: &ProjectId) -> Option<String> {
+        let mut token = String::new();
+        source
+            .read(project_id, Path::new(".gitbridge"))
+            .ok()?
+            .read_to_string(&mut token)
+            .ok()?;
+        Some(token)
+    }
+
+    #[test]
+    fn flat_layout_resolves_exact_and_prefixed_directories() {
+        let (_root, cfg) = setup(ProjectsLayout::Flat);
+        let base = cfg.projects_root();
+        write_project(&base.join("aaa"), "tok-a");
+        write_project(&base.join("bbb-thesis"), "tok-b");
+        write_project(&base.join("user1").join("ccc"), "tok-c");
+        let source = FsProjectSource::new(cfg);
+
+        assert_eq!(token(&source, &pid("aaa")).as_deref(), Some("tok-a"));
+        assert_eq!(token(&source, &pid("bbb")).as_deref(), Some("tok-b"));
+        assert!(!source.exists(&pid("ccc")));
+        assert_eq!(source.list_projects().unwrap(), ["aaa", "bbb", "user1"]);
+    }
+
+    #[test]
+    fn nested_layout_searches_user_directories() {
+        let (_root, cfg) = setup(ProjectsLayout::Nested);
+        let base = cfg.projects_root();
+        write_project(&base.join("user1").join("aaa"), "tok-a");
+        write_project(&base.join("user2").join("bbb-thesis"), "tok-b");
+        write_project(&base.join("ccc"), "tok-c");
+        let source = FsProjectSource::new(cfg);
+
+        assert_eq!(token(&source, &pid("aaa")).as_deref(), Some("tok-a"));
+        assert_eq!(token(&source, &pid("bbb")).as_deref(), Some("tok-b"));
+        assert!(!source.exists(&pid("ccc")));
+        assert!(!source.exists(&pid("user1")));
+        assert_eq!(source.list_projects().unwrap(), ["aaa", "bbb"]);
+
+        // the resolution is cached until the directory goes away
+        assert!(source.nested.contains_key("aaa"));
+        fs::rename(base.join("user1"), base.join("user3")).unwrap();
+        assert_eq!(token(&source, &pid("aaa")).as_deref(), Some("tok-a"));
+        assert_eq!(
+            source.nested.get("aaa").map(|dir| dir.clone()),
+            Some(base.join("user3").join("aaa"))
+        );
+    }
+
+    #[test]
+    fn auto_layout_prefers_flat_projects_and_skips_user_directories() {
+        let (_root, cfg) = setup(ProjectsLayout::Auto);
+        let base = cfg.projects_root();
+        write_project(&base.join("aaa"), "tok-flat");
+        write_project(&base.join("user1").join("aaa"), "tok-nested");
+        write_project(&base.join("user1").join("bbb-thesis"), "tok-b");
+        let source = FsProjectSource::new(cfg);
+
+        assert_eq!(token(&source, &pid("aaa")).as_deref(), Some("tok-flat"));
+        assert_eq!(token(&source, &pid("bbb")).as_deref(), Some("tok-b"));
+        assert!(!source.exists(&pid("user1")));
+        assert_eq!(source.list_projects().unwrap(), ["aaa", "bbb"]);
+    }
+}
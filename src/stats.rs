@@ -0,0 +1,236 @@
+use crate::config::Config;
+use crate::error::BridgeError;
+use crate::git::GitRunner;
+use crate::metadata::{dir_size, load_metadata};
+use crate::project_id::ProjectId;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How long computed statistics are reused.
+pub(crate) const STATS_TTL: Duration = Duration::from_secs(300);
+
+/// Number of syncs the growth figure covers unless the caller asks otherwise.
+pub(crate) const DEFAULT_GROWTH_WINDOW: usize = 10;
+
+/// Storage figures of one bare mirror.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct ProjectStats {
+    pub(crate) id: String,
+    pub(crate) disk_bytes: u64,
+    pub(crate) object_count: u64,
+    /// Commits on the read-only branch
+    pub(crate) commit_count: u64,
+    /// Sum of all file sizes in the latest snapshot
+    pub(crate) snapshot_bytes: u64,
+    /// Change of `disk_bytes` over the last `growth_window` recorded syncs
+    pub(crate) growth_bytes: Option<i64>,
+    pub(crate) growth_window: usize,
+    /// `MAX_REPO_BYTES`, if set
+    pub(crate) max_bytes: Option<u64>,
+    /// `disk_bytes` is over `max_bytes`, so syncs are refused
+    pub(crate) over_limit: bool,
+}
+
+/// Ordering for [`top`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TopBy {
+    Size,
+    Commits,
+    Growth,
+}
+
+impl TopBy {
+    pub(crate) fn parse(raw: &str) -> Option<Self> {
+        match raw {
+            "size" => Some(Self::Size),
+            "commits" => Some(Self::Commits),
+            "growth" => Some(Self::Growth),
+            _ => None,
+        }
+    }
+}
+
+fn git_stdout(git: &dyn GitRunner, repo: &Path, args: &[&str]) -> Result<String, BridgeError> {
+    let out = git.run(args, repo)?;
+    if !out.status.success() {
+        return Err(BridgeError::GitFailed(
+            format!("git {:?}", args),
+            String::from_utf8_lossy(&out.stderr).to_string(),
+        ));
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).into_owned())
+}
+
+/// Loose plus packed objects from `git count-objects -v`.
+fn object_count(git: &dyn GitRunner, repo: &Path) -> Result<u64, BridgeError> {
+    let out = git_stdout(git, repo, &["count-objects", "-v"])?;
+    Ok(out
+        .lines()
+        .filter_map(|line| line.split_once(": "))
+        .filter(|(key, _)| *key == "count" || *key == "in-pack")
+        .filter_map(|(_, value)| value.trim().parse::<u64>().ok())
+        .sum())
+}
+
+/// Compute the statistics of one project. Blocking.
+pub(crate) fn compute(
+    git: &dyn GitRunner,
+    cfg: &Config,
+    project_id: &ProjectId,
+    growth_window: usize,
+) -> Result<ProjectStats, BridgeError> {
+    let repo = cfg.bare_repo_dir(project_id)?;
+    if !repo.is_dir() {
+        return Err(BridgeError::ProjectNotFound(project_id.to_string()));
+    }
+    let branch = &cfg.readonly_branch;
+
+    let commit_count = git_stdout(git, &repo, &["rev-list", "--count", branch])?
+        .trim()
+        .parse()
+        .unwrap_or(0);
+    let snapshot_bytes = git_stdout(git, &repo, &["ls-tree", "-r", "-l", branch])?
+        .lines()
+        .filter_map(|line| line.split('\t').next()?.split_whitespace().nth(3))
+        .filter_map(|size| size.parse::<u64>().ok())
+        .sum();
+
+    let history = load_metadata(cfg, project_id).history;
+    let sizes: Vec<u64> = history.iter().filter_map(|r| r.repo_bytes).collect();
+    let window = &sizes[sizes.len().saturating_sub(growth_window)..];
+    let growth_bytes = match (window.first(), window.last()) {
+        (Some(first), Some(last)) if window.len() > 1 => Some(*last as i64 - *first as i64),
+        _ => None,
+    };
+
+    let disk_bytes = dir_size(&repo);
+    let max_bytes = (cfg.max_repo_bytes > 0).then_some(cfg.max_repo_bytes);
+    Ok(ProjectStats {
+        id: project_id.to_string(),
+        disk_bytes,
+        object_count: object_count(git, &repo)?,
+        commit_count,
+        snapshot_bytes,
+        growth_bytes,
+        growth_window,
+        max_bytes,
+        over_limit: max_bytes.is_some_and(|max| disk_bytes > max),
+    })
+}
+
+/// Computed statistics keyed by project and growth window, reused for [`STATS_TTL`].
+#[derive(Default)]
+pub(crate) struct StatsCache {
+    entries: Mutex<HashMap<(String, usize), (Instant, ProjectStats)>>,
+}
+
+impl StatsCache {
+    /// Cached statistics, or freshly computed ones. Blocking.
+    pub(crate) fn get_or_compute(
+        &self,
+        git: &dyn GitRunner,
+        cfg: &Config,
+        project_id: &ProjectId,
+        growth_window: usize,
+    ) -> Result<ProjectStats, BridgeError> {
+        let key = (project_id.to_string(), growth_window);
+        if let Some((at, stats)) = self.entries.lock().expect("stats cache poisoned").get(&key)
+            && at.elapsed() < STATS_TTL
+        {
+            return Ok(stats.clone());
+        }
+        let stats = compute(git, cfg, project_id, growth_window)?;
+        self.entries
+            .lock()
+            .expect("stats cache poisoned")
+            .insert(key, (Instant::now(), stats.clone()));
+        Ok(stats)
+    }
+}
+
+/// The `limit` largest mirrors by `by`. Mirrors whose statistics cannot be
+/// computed are skipped. Blocking.
+pub(crate) fn top(
+    git: &dyn GitRunner,
+    cfg: &Config,
+    cache: &StatsCache,
+    mirrors: &[ProjectId],
+    by: TopBy,
+    limit: usize,
+) -> Vec<ProjectStats> {
+    let mut all: Vec<ProjectStats> = mirrors
+        .iter()
+        .filter_map(|id| {
+            cache
+                .get_or_compute(git, cfg, id, DEFAULT_GROWTH_WINDOW)
+                .ok()
+        })
+        .collect();
+    all.sort_by_key(|s| {
+        std::cmp::Reverse(match by {
+            TopBy::Size => s.disk_bytes as i64,
+            TopBy::Commits => s.commit_count as i64,
+            TopBy::Growth => s.growth_bytes.unwrap_or(i64::MIN),
+        })
+    });
+    all.truncate(limit);
+    all
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git::mock::ScriptedGit;
+    use crate::metadata::{SyncTrigger, record_sync};
+    use crate::project_id::pid;
+    use crate::repo::SyncOutcome;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn parses_git_output_and_growth() {
+        let root = TempDir::new().unwrap();
+        let cfg = Config::for_test(root.path());
+        let repo = cfg.bare_repo_dir(&pid("p1")).unwrap();
+        fs::create_dir_all(&repo).unwrap();
+        record_sync(
+            &cfg,
+            &pid("p1"),
+            SyncTrigger::Fetch,
+            Ok(&SyncOutcome::default()),
+        );
+        fs::write(repo.join("pack"), vec![0u8; 1000]).unwrap();
+        record_sync(
+            &cfg,
+            &pid("p1"),
+            SyncTrigger::Fetch,
+            Ok(&SyncOutcome::default()),
+        );
+
+        let git = ScriptedGit::new()
+            .on_stdout(&["rev-list", "--count", "master"], b"7\n")
+            .on_stdout(
+                &["ls-tree", "-r", "-l", "master"],
+                b"100644 blob aaa      12\tmain.tex\n100644 blob bbb     300\tfig/a.png\n",
+            )
+            .on_stdout(
+                &["count-objects", "-v"],
+                b"count: 3\nsize: 1\nin-pack: 20\npacks: 1\nsize-pack: 4\n",
+            );
+
+        let stats = compute(&git, &cfg, &pid("p1"), DEFAULT_GROWTH_WINDOW).unwrap();
+        assert_eq!(stats.commit_count, 7);
+        assert_eq!(stats.snapshot_bytes, 312);
+        assert_eq!(stats.object_count, 23);
+        assert!(stats.growth_bytes.unwrap() >= 1000);
+
+        let cache = StatsCache::default();
+        cache.get_or_compute(&git, &cfg, &pid("p1"), 10).unwrap();
+        cache.get_or_compute(&git, &cfg, &pid("p1"), 10).unwrap();
+        let rev_lists = git.calls().iter().filter(|c| c[0] == "rev-list").count();
+        assert_eq!(rev_lists, 2, "second lookup must come from the cache");
+    }
+}
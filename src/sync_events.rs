@@ -0,0 +1,109 @@
+//! Sync commits are posted to `SYNC_EVENT_WEBHOOK_URL` with the files they
+//! changed, so downstream tooling can react to edits without diffing the
+//! mirror itself.
+
+use crate::metadata::SyncTrigger;
+use crate::repo::SyncOutcome;
+use chrono::{DateTime, Utc};
+use serde_json::{Value, json};
+
+/// Changed files listed in one sync event; the rest are only counted.
+pub(crate) const MAX_WEBHOOK_CHANGES: usize = 50;
+
+/// JSON body announcing the commit of `outcome`. The change list is cut
+/// after [`MAX_WEBHOOK_CHANGES`] files; `more_changes` counts the rest and
+/// `text` ends with "and N more". `clone_url` is only known with `PUBLIC_URL`.
+pub(crate) fn sync_event(
+    project_id: &str,
+    clone_url: Option<&str>,
+    trigger: SyncTrigger,
+    outcome: &SyncOutcome,
+    at: DateTime<Utc>,
+) -> Value {
+    let listed = &outcome.changes[..outcome.changes.len().min(MAX_WEBHOOK_CHANGES)];
+    let more = outcome.changes.len() - listed.len();
+
+    let mut text = format!(
+        "{project_id} synced {}: {} file(s) changed",
+        outcome.commit.as_deref().unwrap_or("-"),
+        outcome.files_changed
+    );
+    for change in listed {
+        text.push_str(&format!(
+            "\n{} {} ({:+} bytes)",
+            change.status, change.path, change.delta_bytes
+        ));
+    }
+    if more > 0 {
+        text.push_str(&format!("\nand {more} more"));
+    }
+
+    json!({
+        "event": "sync",
+        "project_id": project_id,
+        "clone_url": clone_url,
+        "trigger": trigger,
+        "at": at,
+        "commit": outcome.commit,
+        "files_changed": outcome.files_changed,
+        "changes": listed,
+        "more_changes": more,
+        "text": text,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::FileChange;
+
+    fn change(path: &str) -> FileChange {
+        FileChange {
+            status: "M".into(),
+            path: path.into(),
+            old_bytes: Some(10),
+            new_bytes: Some(4),
+            delta_bytes: -6,
+        }
+    }
+
+    #[test]
+    fn long_change_lists_are_cut_with_a_marker() {
+        let outcome = SyncOutcome {
+            commit: Some("0123abcd".into()),
+            files_changed: MAX_WEBHOOK_CHANGES + 3,
+            changes: (0..MAX_WEBHOOK_CHANGES + 3)
+                .map(|i| change(&format!("ch{i}.tex")))
+                .collect(),
+            ..SyncOutcome::default()
+        };
+        let event = sync_event(
+            "p1",
+            Some("https://tex.example.org/git/p1.git"),
+            SyncTrigger::Webhook,
+            &outcome,
+            Utc::now(),
+        );
+        assert_eq!(event["event"], "sync");
+        assert_eq!(event["clone_url"], "https://tex.example.org/git/p1.git");
+        assert_eq!(event["trigger"], "webhook");
+        assert_eq!(
+            event["changes"].as_array().unwrap().len(),
+            MAX_WEBHOOK_CHANGES
+        );
+        assert_eq!(event["changes"][0]["delta_bytes"], -6);
+        assert_eq!(event["more_changes"], 3);
+        let text = event["text"].as_str().unwrap();
+        assert!(text.contains("M ch0.tex (-6 bytes)"));
+        assert!(text.ends_with("\nand 3 more"));
+
+        let short = SyncOutcome {
+            changes: vec![change("main.tex")],
+            ..outcome
+        };
+        let event = sync_event("p1", None, SyncTrigger::Fetch, &short, Utc::now());
+        assert_eq!(event["clone_url"], serde_json::Value::Null);
+        assert_eq!(event["more_changes"], 0);
+        assert!(!event["text"].as_str().unwrap().contains("more"));
+    }
+}
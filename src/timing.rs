@@ -0,0 +1,391 @@
+//! Where the time of a git request goes: authorization, waiting for the
+//! project lock, the pre-fetch sync, `git http-backend`, and sending the
+//! response. A request's [`RequestTimer`] holds its in-flight guard, so both
+//! last until the response body is sent or dropped; the timer then writes
+//! the access log line and adds the request to the per-phase histograms.
+
+use crate::maintenance::InFlight;
+use axum::body::{Body, Bytes, HttpBody};
+use axum::http::{HeaderValue, Request, Response, StatusCode};
+use hyper::body::{Frame, SizeHint};
+#[cfg(feature = "admin-ui")]
+use serde_json::{Value, json};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tracing::field::Empty;
+use tracing::{Instrument, Span, info, info_span};
+
+/// Response header carrying the timings, with `GIT_TIMING_HEADER`.
+pub(crate) const TIMING_HEADER: &str = "x-gitbridge-timing";
+
+/// Upper bounds of the histogram buckets in milliseconds; a last bucket
+/// takes everything slower.
+const BUCKETS_MS: [u64; 12] = [
+    1, 5, 10, 25, 50, 100, 250, 500, 1_000, 2_500, 10_000, 60_000,
+];
+
+/// A part of handling a git request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Phase {
+    /// Checking the credentials
+    Auth,
+    /// Waiting for the project lock to sync
+    LockWait,
+    /// The pre-fetch sync, without its lock wait
+    Sync,
+    /// Running `git http-backend`, or waiting for an identical `info/refs`
+    Backend,
+    /// From handing the response to the connection until its last byte
+    Response,
+}
+
+impl Phase {
+    pub(crate) const ALL: [Phase; 5] = [
+        Phase::Auth,
+        Phase::LockWait,
+        Phase::Sync,
+        Phase::Backend,
+        Phase::Response,
+    ];
+
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            Phase::Auth => "auth",
+            Phase::LockWait => "lock_wait",
+            Phase::Sync => "sync",
+            Phase::Backend => "backend",
+            Phase::Response => "response",
+        }
+    }
+}
+
+/// Time spent in each phase of one request, in microseconds.
+#[derive(Default)]
+struct Phases([AtomicU64; Phase::ALL.len()]);
+
+impl Phases {
+    fn add(&self, phase: Phase, elapsed: Duration) {
+        self.0[phase as usize].fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    fn get(&self, phase: Phase) -> Duration {
+        Duration::from_micros(self.0[phase as usize].load(Ordering::Relaxed))
+    }
+
+    /// Every phase; lock waits happen inside the sync and are taken out of it.
+    fn breakdown(&self) -> [(Phase, Duration); Phase::ALL.len()] {
+        Phase::ALL.map(|phase| {
+            let elapsed = self.get(phase);
+            match phase {
+                Phase::Sync => (phase, elapsed.saturating_sub(self.get(Phase::LockWait))),
+                _ => (phase, elapsed),
+            }
+        })
+    }
+}
+
+tokio::task_local! {
+    /// Phases of the git request the current task is handling
+    static CURRENT: Arc<Phases>;
+}
+
+/// Add `elapsed` to `phase` of the git request being handled, if any;
+/// background work outside a request is not counted anywhere.
+pub(crate) fn record(phase: Phase, elapsed: Duration) {
+    let _ = CURRENT.try_with(|phases| phases.add(phase, elapsed));
+}
+
+/// Run `fut`, counting its time towards `phase` of the current request.
+pub(crate) async fn measure<F: Future>(phase: Phase, fut: F) -> F::Output {
+    let start = Instant::now();
+    let output = fut.await;
+    record(phase, start.elapsed());
+    output
+}
+
+fn millis(elapsed: Duration) -> f64 {
+    (elapsed.as_micros() as f64 / 1000.0 * 10.0).round() / 10.0
+}
+
+/// Counts of durations by bucket, with their sum.
+#[derive(Default)]
+struct Histogram {
+    counts: [AtomicU64; BUCKETS_MS.len() + 1],
+    sum_us: AtomicU64,
+}
+
+impl Histogram {
+    fn observe(&self, elapsed: Duration) {
+        let ms = elapsed.as_millis() as u64;
+        let bucket = BUCKETS_MS
+            .iter()
+            .position(|&bound| ms < bound)
+            .unwrap_or(BUCKETS_MS.len());
+        self.counts[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum_us
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    #[cfg(feature = "admin-ui")]
+    fn snapshot(&self) -> Value {
+        let counts: Vec<u64> = self
+            .counts
+            .iter()
+            .map(|count| count.load(Ordering::Relaxed))
+            .collect();
+        let buckets: Vec<Value> = counts
+            .iter()
+            .enumerate()
+            .map(|(i, count)| json!({"lt_ms": BUCKETS_MS.get(i), "count": count}))
+            .collect();
+        json!({
+            "count": counts.iter().sum::<u64>(),
+            "sum_ms": millis(Duration::from_micros(self.sum_us.load(Ordering::Relaxed))),
+            "buckets": buckets,
+        })
+    }
+}
+
+/// Histograms of every phase and of whole git requests since startup.
+#[derive(Default)]
+pub(crate) struct TimingStats {
+    phases: [Histogram; Phase::ALL.len()],
+    total: Histogram,
+}
+
+impl TimingStats {
+    fn observe(&self, phases: &[(Phase, Duration)], total: Duration) {
+        for &(phase, elapsed) in phases {
+            self.phases[phase as usize].observe(elapsed);
+        }
+        self.total.observe(total);
+    }
+
+    /// Per phase and `total`: the number of requests, the sum of their
+    /// times, and how many took less than each bucket's `lt_ms` (`null` for
+    /// the slowest).
+    #[cfg(feature = "admin-ui")]
+    pub(crate) fn snapshot(&self) -> Value {
+        let mut snapshot = serde_json::Map::new();
+        for phase in Phase::ALL {
+            snapshot.insert(
+                phase.as_str().to_string(),
+                self.phases[phase as usize].snapshot(),
+            );
+        }
+        snapshot.insert("total".to_string(), self.total.snapshot());
+        Value::Object(snapshot)
+    }
+}
+
+/// Times one git request from its arrival until its response is sent.
+pub(crate) struct RequestTimer {
+    start: Instant,
+    phases: Arc<Phases>,
+    stats: Arc<TimingStats>,
+    span: Span,
+    status: Option<StatusCode>,
+    /// When the response was handed to the connection
+    responded: Option<Instant>,
+    /// Whether the whole response body was sent
+    complete: bool,
+    _in_flight: InFlight,
+}
+
+impl RequestTimer {
+    /// Start timing `req`, which counts as in flight until the timer is
+    /// dropped.
+    pub(crate) fn start(in_flight: InFlight, stats: Arc<TimingStats>, req: &Request<Body>) -> Self {
+        let span = info_span!(
+            "git_request",
+            method = %req.method(),
+            path = req.uri().path(),
+            status = Empty,
+            auth_ms = Empty,
+            lock_wait_ms = Empty,
+            sync_ms = Empty,
+            backend_ms = Empty,
+            response_ms = Empty,
+            total_ms = Empty,
+        );
+        Self {
+            start: Instant::now(),
+            phases: Arc::new(Phases::default()),
+            stats,
+            span,
+            status: None,
+            responded: None,
+            complete: false,
+            _in_flight: in_flight,
+        }
+    }
+
+    /// Run `fut`, the handling of the request, in its span and with its
+    /// phases recorded by [`record`] and [`measure`].
+    pub(crate) fn scope<F: Future>(&self, fut: F) -> impl Future<Output = F::Output> {
+        CURRENT
+            .scope(self.phases.clone(), fut)
+            .instrument(self.span.clone())
+    }
+
+    /// The phases so far and the time since the request arrived, in the
+    /// syntax of `Server-Timing`: `auth;dur=1.2, lock_wait;dur=0.0, ...`.
+    fn header_value(&self) -> String {
+        let mut parts: Vec<String> = self
+            .phases
+            .breakdown()
+            .iter()
+            .filter(|(phase, _)| *phase != Phase::Response)
+            .map(|(phase, elapsed)| format!("{};dur={}", phase.as_str(), millis(*elapsed)))
+            .collect();
+        parts.push(format!("total;dur={}", millis(self.start.elapsed())));
+        parts.join(", ")
+    }
+
+    /// Hand `resp` to the connection, adding [`TIMING_HEADER`] if
+    /// `with_header`. The timer goes with the body and stops when the body
+    /// is sent or dropped.
+    pub(crate) fn finish(mut self, mut resp: Response<Body>, with_header: bool) -> Response<Body> {
+        if with_header && let Ok(value) = HeaderValue::from_str(&self.header_value()) {
+            resp.headers_mut().insert(TIMING_HEADER, value);
+        }
+        self.status = Some(resp.status());
+        self.responded = Some(Instant::now());
+        resp.map(|inner| {
+            Body::new(TimedBody {
+                inner,
+                timer: Some(self),
+            })
+        })
+    }
+}
+
+impl Drop for RequestTimer {
+    fn drop(&mut self) {
+        if let Some(responded) = self.responded {
+            self.phases.add(Phase::Response, responded.elapsed());
+        }
+        let total = self.start.elapsed();
+        let phases = self.phases.breakdown();
+        self.stats.observe(&phases, total);
+
+        let ms = |phase: Phase| millis(phases[phase as usize].1);
+        let status = self.status.map(|status| status.as_u16());
+        let span = &self.span;
+        span.record("status", status);
+        for (phase, elapsed) in phases {
+            span.record(format!("{}_ms", phase.as_str()).as_str(), millis(elapsed));
+        }
+        span.record("total_ms", millis(total));
+        let _entered = span.enter();
+        info!(
+            event = "access",
+            status,
+            complete = self.complete,
+            auth_ms = ms(Phase::Auth),
+            lock_wait_ms = ms(Phase::LockWait),
+            sync_ms = ms(Phase::Sync),
+            backend_ms = ms(Phase::Backend),
+            response_ms = ms(Phase::Response),
+            total_ms = millis(total),
+            "git request"
+        );
+    }
+}
+
+/// A response body that stops its request's timer once sent or dropped.
+struct TimedBody {
+    inner: Body,
+    timer: Option<RequestTimer>,
+}
+
+impl HttpBody for TimedBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, axum::Error>>> {
+        let polled = Pin::new(&mut self.inner).poll_frame(cx);
+        if self.inner.is_end_stream()
+            && let Some(mut timer) = self.timer.take()
+        {
+            timer.complete = true;
+        }
+        polled
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+#[cfg(all(test, feature = "admin-ui"))]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::maintenance::Maintenance;
+    use axum::body::to_bytes;
+
+    #[tokio::test]
+    async fn phases_are_recorded_inside_the_scope_only() {
+        let root = tempfile::TempDir::new().unwrap();
+        let maintenance = Maintenance::load(&Config::for_test(root.path()));
+        let stats = Arc::new(TimingStats::default());
+        let req = Request::get("/git/p1.git/info/refs")
+            .body(Body::empty())
+            .unwrap();
+        let timer = RequestTimer::start(maintenance.begin(), stats.clone(), &req);
+
+        record(Phase::Auth, Duration::from_millis(3));
+        timer
+            .scope(async {
+                record(Phase::Auth, Duration::from_millis(2));
+                measure(Phase::Sync, async {
+                    record(Phase::LockWait, Duration::from_millis(40));
+                    tokio::time::sleep(Duration::from_millis(60)).await;
+                })
+                .await;
+            })
+            .await;
+        let phases = timer.phases.breakdown();
+        assert_eq!(phases[Phase::Auth as usize].1, Duration::from_millis(2));
+        assert_eq!(
+            phases[Phase::LockWait as usize].1,
+            Duration::from_millis(40)
+        );
+        let sync = phases[Phase::Sync as usize].1;
+        assert!(sync >= Duration::from_millis(20), "{sync:?}");
+
+        let header = timer.header_value();
+        assert!(
+            header.starts_with("auth;dur=2, lock_wait;dur=40, sync;dur="),
+            "{header}"
+        );
+        assert!(header.contains(", backend;dur=0, total;dur="), "{header}");
+
+        // the request stays in flight until its response is sent
+        let resp = timer.finish(Response::new(Body::from("refs")), true);
+        assert!(resp.headers().contains_key(TIMING_HEADER));
+        assert_eq!(maintenance.in_flight(), 1);
+        assert_eq!(to_bytes(resp.into_body(), 1024).await.unwrap(), "refs");
+        assert_eq!(maintenance.in_flight(), 0);
+
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot["total"]["count"], 1);
+        assert_eq!(snapshot["lock_wait"]["buckets"][4]["lt_ms"], 50);
+        assert_eq!(snapshot["lock_wait"]["buckets"][4]["count"], 1);
+        assert_eq!(snapshot["auth"]["buckets"][1]["count"], 1);
+        assert_eq!(snapshot["auth"]["sum_ms"], 2.0);
+        assert_eq!(snapshot["response"]["count"], 1);
+    }
+}
@@ -0,0 +1,287 @@
+//! Declarative managed tokens (`TOKENS_BOOTSTRAP_FILE`), reconciled into the
+//! token store at startup.
+
+use crate::auth::token_hash;
+use crate::config::{Config, DEFAULT_SOURCE, is_valid_source_name};
+use crate::error::BridgeError;
+use crate::repo::is_valid_project_id;
+use crate::token_store::TokenStore;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+use tracing::info;
+
+/// One token of the bootstrap file.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BootstrapToken {
+    pub token: String,
+    #[serde(default)]
+    pub description: String,
+    /// Projects the token may read; every project when absent
+    #[serde(default)]
+    pub projects: Option<Vec<String>>,
+    /// Source the token may read (see `SOURCES`); every source when absent
+    #[serde(default)]
+    pub source: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BootstrapFile {
+    #[serde(default)]
+    tokens: Vec<BootstrapToken>,
+}
+
+/// What a reconciliation changed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BootstrapSummary {
+    pub created: usize,
+    pub updated: usize,
+    pub unchanged: usize,
+    pub pruned: usize,
+}
+
+/// Whether `token` can be presented as a password or bearer token: not
+/// empty, and no whitespace, control characters, or `:`.
+fn is_valid_token(token: &str) -> bool {
+    !token.is_empty()
+        && !token
+            .chars()
+            .any(|c| c.is_whitespace() || c.is_control() || c == ':')
+}
+
+/// Read and check a bootstrap file: JSON for `*.json`, YAML otherwise.
+/// Project lists come back sorted and without duplicates.
+pub fn load_bootstrap_file(path: &Path) -> Result<Vec<BootstrapToken>, BridgeError> {
+    let data = fs::read_to_string(path)
+        .map_err(|e| BridgeError::Other(format!("cannot read {}: {e}", path.display())))?;
+    let is_json = path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("json"));
+    let parsed: BootstrapFile = if is_json {
+        serde_json::from_str(&data).map_err(|e| e.to_string())
+    } else {
+        serde_yaml_ng::from_str(&data).map_err(|e| e.to_string())
+    }
+    .map_err(|e| BridgeError::Other(format!("cannot parse {}: {e}", path.display())))?;
+
+    let mut seen = HashSet::new();
+    let mut tokens = parsed.tokens;
+    for (i, entry) in tokens.iter_mut().enumerate() {
+        let invalid = |reason: String| {
+            BridgeError::Other(format!("{}: token #{}: {reason}", path.display(), i + 1))
+        };
+        if !is_valid_token(&entry.token) {
+            return Err(invalid(
+                "must not be empty or contain whitespace or ':'".into(),
+            ));
+        }
+        if !seen.insert(entry.token.clone()) {
+            return Err(invalid("listed twice".into()));
+        }
+        entry.description = entry.description.trim().to_string();
+        if let Some(projects) = &mut entry.projects {
+            if let Some(bad) = projects.iter().find(|id| !is_valid_project_id(id)) {
+                return Err(invalid(format!("invalid project id '{bad}'")));
+            }
+            projects.sort();
+            projects.dedup();
+        }
+        if let Some(source) = &entry.source
+            && source != DEFAULT_SOURCE
+            && !is_valid_source_name(source)
+        {
+            return Err(invalid(format!("invalid source name '{source}'")));
+        }
+    }
+    Ok(tokens)
+}
+
+/// Make `store` hold `tokens`: create missing ones and update descriptions
+/// and project and source scopes that differ. Other tokens are left alone unless
+/// `prune` is set, which removes them. Running it again changes nothing.
+pub async fn reconcile_tokens(
+    store: &dyn TokenStore,
+    tokens: &[BootstrapToken],
+    prune: bool,
+) -> Result<BootstrapSummary, BridgeError> {
+    let existing = store.list().await?;
+    let mut scopes = store.scopes().await?;
+    let source_scopes = store.source_scopes().await?;
+    for projects in scopes.values_mut() {
+        projects.sort();
+        projects.dedup();
+    }
+
+    let mut summary = BootstrapSummary::default();
+    for entry in tokens {
+        let id = token_hash(&entry.token);
+        let current = existing.iter().find(|r| r.id == id);
+        let description_differs = current.is_none_or(|r| r.description != entry.description);
+        let scope_differs = scopes.get(&id) != entry.projects.as_ref();
+        let source_differs = source_scopes.get(&id) != entry.source.as_ref();
+        if description_differs {
+            store.insert(&entry.token, &entry.description).await?;
+        }
+        if scope_differs {
+            store.set_scope(&id, entry.projects.clone()).await?;
+        }
+        if source_differs {
+            store.set_source_scope(&id, entry.source.clone()).await?;
+        }
+        match current {
+            None => summary.created += 1,
+            Some(_) if description_differs || scope_differs || source_differs => {
+                summary.updated += 1
+            }
+            Some(_) => summary.unchanged += 1,
+        }
+    }
+
+    if prune {
+        let declared: HashSet<String> = tokens.iter().map(|t| token_hash(&t.token)).collect();
+        for record in &existing {
+            if !declared.contains(&record.id) && store.remove(&record.id).await? {
+                summary.pruned += 1;
+            }
+        }
+    }
+    Ok(summary)
+}
+
+/// Reconcile `TOKENS_BOOTSTRAP_FILE`, if set, into `store` and log what
+/// changed.
+pub async fn bootstrap_tokens(
+    store: &dyn TokenStore,
+    cfg: &Config,
+) -> Result<Option<BootstrapSummary>, BridgeError> {
+    let Some(path) = &cfg.tokens_bootstrap_file else {
+        return Ok(None);
+    };
+    let tokens = {
+        let path = path.clone();
+        tokio::task::spawn_blocking(move || load_bootstrap_file(&path))
+            .await
+            .map_err(|e| BridgeError::Other(format!("join error: {e}")))??
+    };
+    let summary = reconcile_tokens(store, &tokens, cfg.tokens_bootstrap_prune).await?;
+    info!(
+        file = %path.display(),
+        created = summary.created,
+        updated = summary.updated,
+        unchanged = summary.unchanged,
+        pruned = summary.pruned,
+        "bootstrapped managed tokens"
+    );
+    Ok(Some(summary))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::token_store::{Decision, MemoryTokenStore, TokenRecord};
+    use tempfile::TempDir;
+
+    const YAML: &str = "\
+tokens:
+  - token: ci-token
+    description: CI mirror
+  - token: lecture-token
+    description: ' Lecture notes '
+    projects: [bbb222, aaa111, aaa111]
+    source: staging
+";
+
+    #[tokio::test]
+    async fn reconciliation_is_idempotent_and_prunes_only_when_asked() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("tokens.yaml");
+        fs::write(&path, YAML).unwrap();
+        let tokens = load_bootstrap_file(&path).unwrap();
+        assert_eq!(tokens[1].description, "Lecture notes");
+        assert_eq!(
+            tokens[1].projects.as_deref(),
+            Some(&["aaa111".to_string(), "bbb222".to_string()][..])
+        );
+
+        let store = MemoryTokenStore::with_tokens(
+            [
+                ("admin-made".to_string(), "by hand".to_string()),
+                ("ci-token".to_string(), "old description".to_string()),
+            ]
+            .into(),
+        );
+        let first = reconcile_tokens(&store, &tokens, false).await.unwrap();
+        assert_eq!(
+            first,
+            BootstrapSummary {
+                created: 1,
+                updated: 1,
+                ..BootstrapSummary::default()
+            }
+        );
+        assert_eq!(
+            store.check("lecture-token", "aaa111").await.unwrap(),
+            Decision::Allowed
+        );
+        assert_eq!(
+            store.check("lecture-token", "ccc333").await.unwrap(),
+            Decision::NotAllowedForProject
+        );
+        let sources = store.source_scopes().await.unwrap();
+        assert_eq!(sources[&token_hash("lecture-token")], "staging");
+        assert!(!sources.contains_key(&token_hash("ci-token")));
+
+        let again = reconcile_tokens(&store, &tokens, false).await.unwrap();
+        assert_eq!(
+            again,
+            BootstrapSummary {
+                unchanged: 2,
+                ..BootstrapSummary::default()
+            }
+        );
+        assert!(store.list().await.unwrap().contains(&TokenRecord {
+            id: token_hash("admin-made"),
+            description: "by hand".into()
+        }));
+
+        let pruned = reconcile_tokens(&store, &tokens, true).await.unwrap();
+        assert_eq!(pruned.pruned, 1);
+        assert_eq!(store.list().await.unwrap().len(), 2);
+    }
+
+    #[test]
+    fn invalid_files_are_rejected() {
+        let dir = TempDir::new().unwrap();
+        let check = |name: &str, content: &str| {
+            let path = dir.path().join(name);
+            fs::write(&path, content).unwrap();
+            load_bootstrap_file(&path).map_err(|e| e.to_string())
+        };
+        let json = r#"{"tokens": [{"token": "t1", "projects": ["aaa111"]}]}"#;
+        assert_eq!(check("ok.json", json).unwrap()[0].description, "");
+        assert!(
+            check("dup.yml", "tokens:\n  - token: t1\n  - token: t1\n")
+                .unwrap_err()
+                .contains("token #2: listed twice")
+        );
+        assert!(
+            check("colon.yml", "tokens:\n  - token: 'a:b'\n")
+                .unwrap_err()
+                .contains("token #1")
+        );
+        assert!(
+            check("id.yml", "tokens:\n  - token: t1\n    projects: ['../x']\n")
+                .unwrap_err()
+                .contains("invalid project id")
+        );
+        assert!(
+            check("source.yml", "tokens:\n  - token: t1\n    source: Prod\n")
+                .unwrap_err()
+                .contains("invalid source name 'Prod'")
+        );
+        assert!(check("typo.yml", "tokens:\n  - token: t1\n    project: [a]\n").is_err());
+    }
+}
@@ -0,0 +1,861 @@
+use crate::auth::{
+    TOKENS_LOCK_WAIT, TokenQuota, TokensFile, TokensFileLock, load_tokens_file, save_tokens_file,
+    scope_allows, token_hash,
+};
+use crate::config::Config;
+use crate::error::BridgeError;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::sync::{Mutex, MutexGuard};
+
+/// Result of checking a presented token against a [`TokenStore`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    /// The token grants read access to the project.
+    Allowed,
+    /// The store does not know the token.
+    UnknownToken,
+    /// The token exists but is not valid for this project.
+    NotAllowedForProject,
+}
+
+/// A managed token as listed by a [`TokenStore`]. Stores do not keep the
+/// token itself, only its hash.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokenRecord {
+    /// The token's [`token_hash`]
+    pub id: String,
+    pub description: String,
+}
+
+/// How [`TokenStore::import`] combines imported tokens with stored ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportMode {
+    /// Add the imported tokens. A token stored already keeps its
+    /// description, quota and scopes unless `overwrite` is set.
+    Merge { overwrite: bool },
+    /// Replace every stored token by the imported ones.
+    Replace,
+}
+
+/// Tokens changed by a [`TokenStore::import`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct ImportSummary {
+    pub added: usize,
+    /// Stored tokens given the imported description, quota and scopes
+    pub updated: usize,
+    /// Stored tokens left as they were
+    pub kept: usize,
+    pub removed: usize,
+}
+
+/// Storage backend for managed tokens. Tokens are kept as their
+/// [`token_hash`], which identifies them in every method but
+/// [`TokenStore::insert`] and [`TokenStore::check`].
+#[async_trait]
+pub trait TokenStore: Send + Sync {
+    /// All managed tokens, sorted by id.
+    async fn list(&self) -> Result<Vec<TokenRecord>, BridgeError>;
+
+    /// Add a token or replace the description of an existing one.
+    async fn insert(&self, token: &str, description: &str) -> Result<(), BridgeError>;
+
+    /// Replace the description of an existing token. Returns whether it
+    /// existed.
+    async fn set_description(&self, id: &str, description: &str) -> Result<bool, BridgeError> {
+        let _ = (id, description);
+        Err(BridgeError::Other(
+            "this token store does not support changing descriptions".into(),
+        ))
+    }
+
+    /// Remove a token. Returns whether it existed.
+    async fn remove(&self, id: &str) -> Result<bool, BridgeError>;
+
+    /// Decide whether `token` may read `project_id`.
+    async fn check(&self, token: &str, project_id: &str) -> Result<Decision, BridgeError>;
+
+    /// Daily quotas by token id; tokens without an entry are unlimited.
+    async fn quotas(&self) -> Result<HashMap<String, TokenQuota>, BridgeError> {
+        Ok(HashMap::new())
+    }
+
+    /// Replace the quota of an existing token. Returns whether it existed.
+    async fn set_quota(&self, id: &str, quota: TokenQuota) -> Result<bool, BridgeError> {
+        let _ = (id, quota);
+        Err(BridgeError::Other(
+            "this token store does not support quotas".into(),
+        ))
+    }
+
+    /// Project scopes by token id; tokens without an entry may read every
+    /// project.
+    async fn scopes(&self) -> Result<HashMap<String, Vec<String>>, BridgeError> {
+        Ok(HashMap::new())
+    }
+
+    /// Limit an existing token to `projects`, or let it read every project
+    /// again with `None`. Returns whether it existed.
+    async fn set_scope(
+        &self,
+        id: &str,
+        projects: Option<Vec<String>>,
+    ) -> Result<bool, BridgeError> {
+        let _ = (id, projects);
+        Err(BridgeError::Other(
+            "this token store does not support project scopes".into(),
+        ))
+    }
+
+    /// Source scopes by token id; tokens without an entry may read every
+    /// source.
+    async fn source_scopes(&self) -> Result<HashMap<String, String>, BridgeError> {
+        Ok(HashMap::new())
+    }
+
+    /// Limit an existing token to the source `source`, or let it read every
+    /// source again with `None`. Returns whether it existed.
+    async fn set_source_scope(
+        &self,
+        id: &str,
+        source: Option<String>,
+    ) -> Result<bool, BridgeError> {
+        let _ = (id, source);
+        Err(BridgeError::Other(
+            "this token store does not support source scopes".into(),
+        ))
+    }
+
+    /// When each managed token last authorized a git request, by token id;
+    /// tokens without an entry have not been used since their use is
+    /// tracked.
+    async fn last_used(&self) -> Result<HashMap<String, DateTime<Utc>>, BridgeError> {
+        Ok(HashMap::new())
+    }
+
+    /// Note that the token `id` authorized a git request at `at`. Unknown
+    /// tokens are ignored, as are all uses by stores that do not track them.
+    async fn record_use(&self, id: &str, at: DateTime<Utc>) -> Result<(), BridgeError> {
+        let _ = (id, at);
+        Ok(())
+    }
+
+    /// Store the tokens of `imported`, a checked [`TokensFile`] of hashed
+    /// tokens, in one change.
+    async fn import(
+        &self,
+        imported: TokensFile,
+        mode: ImportMode,
+    ) -> Result<ImportSummary, BridgeError> {
+        let _ = (imported, mode);
+        Err(BridgeError::Other(
+            "this token store does not support imports".into(),
+        ))
+    }
+}
+
+fn decide(tf: &TokensFile, token: &str, project_id: &str) -> Decision {
+    match tf.find_token(token) {
+        Some(id) if scope_allows(tf, id, project_id) => Decision::Allowed,
+        Some(_) => Decision::NotAllowedForProject,
+        None => Decision::UnknownToken,
+    }
+}
+
+/// Store the description of an existing token.
+fn apply_description(tf: &mut TokensFile, id: &str, description: &str) -> bool {
+    match tf.managed_tokens.get_mut(id) {
+        Some(current) => {
+            *current = description.to_string();
+            true
+        }
+        None => false,
+    }
+}
+
+/// Store `quota` for an existing token, dropping unlimited entries.
+fn apply_quota(tf: &mut TokensFile, id: &str, quota: TokenQuota) -> bool {
+    if !tf.managed_tokens.contains_key(id) {
+        return false;
+    }
+    if quota.is_unlimited() {
+        tf.quotas.remove(id);
+    } else {
+        tf.quotas.insert(id.to_string(), quota);
+    }
+    true
+}
+
+/// Store the last use of an existing token.
+fn apply_use(tf: &mut TokensFile, id: &str, at: DateTime<Utc>) -> bool {
+    if !tf.managed_tokens.contains_key(id) {
+        return false;
+    }
+    tf.last_used.insert(id.to_string(), at);
+    true
+}
+
+/// Store the scope of an existing token; `None` lifts it.
+fn apply_scope(tf: &mut TokensFile, id: &str, projects: Option<Vec<String>>) -> bool {
+    if !tf.managed_tokens.contains_key(id) {
+        return false;
+    }
+    match projects {
+        Some(projects) => tf.scopes.insert(id.to_string(), projects),
+        None => tf.scopes.remove(id),
+    };
+    true
+}
+
+/// Store the source scope of an existing token; `None` lifts it.
+fn apply_source_scope(tf: &mut TokensFile, id: &str, source: Option<String>) -> bool {
+    if !tf.managed_tokens.contains_key(id) {
+        return false;
+    }
+    match source {
+        Some(source) => tf.source_scopes.insert(id.to_string(), source),
+        None => tf.source_scopes.remove(id),
+    };
+    true
+}
+
+/// Forget a token and everything stored about it.
+fn apply_remove(tf: &mut TokensFile, id: &str) -> bool {
+    tf.quotas.remove(id);
+    tf.scopes.remove(id);
+    tf.source_scopes.remove(id);
+    tf.last_used.remove(id);
+    tf.managed_tokens.remove(id).is_some()
+}
+
+/// Combine `imported` into `tf`. A merged token's last use is the later one
+/// of both.
+fn apply_import(tf: &mut TokensFile, mut imported: TokensFile, mode: ImportMode) -> ImportSummary {
+    let mut summary = ImportSummary::default();
+    let overwrite = match mode {
+        ImportMode::Merge { overwrite } => overwrite,
+        ImportMode::Replace => {
+            let gone: Vec<String> = tf
+                .managed_tokens
+                .keys()
+                .filter(|id| !imported.managed_tokens.contains_key(*id))
+                .cloned()
+                .collect();
+            for id in &gone {
+                apply_remove(tf, id);
+            }
+            summary.removed = gone.len();
+            true
+        }
+    };
+    for (id, description) in imported.managed_tokens.drain() {
+        let stored = tf.managed_tokens.contains_key(&id);
+        if let Some(at) = imported.last_used.remove(&id)
+            && tf.last_used.get(&id).is_none_or(|stored| *stored < at)
+        {
+            tf.last_used.insert(id.clone(), at);
+        }
+        if stored && !overwrite {
+            summary.kept += 1;
+            continue;
+        }
+        if stored {
+            summary.updated += 1;
+        } else {
+            summary.added += 1;
+        }
+        let quota = imported.quotas.remove(&id).unwrap_or_default();
+        let projects = imported.scopes.remove(&id);
+        let source = imported.source_scopes.remove(&id);
+        tf.managed_tokens.insert(id.clone(), description);
+        apply_quota(tf, &id, quota);
+        apply_scope(tf, &id, projects);
+        apply_source_scope(tf, &id, source);
+    }
+    summary
+}
+
+fn records(tf: &TokensFile) -> Vec<TokenRecord> {
+    let mut items: Vec<TokenRecord> = tf
+        .managed_tokens
+        .iter()
+        .map(|(id, desc)| TokenRecord {
+            id: id.clone(),
+            description: desc.clone(),
+        })
+        .collect();
+    items.sort_by(|a, b| a.id.cmp(&b.id));
+    items
+}
+
+/// The `tokens.json` file under `git_root`.
+pub struct FileTokenStore {
+    cfg: Config,
+    /// Serializes read-modify-write cycles of tokens.json
+    lock: Mutex<()>,
+    /// How long a change waits for other processes changing tokens.json
+    lock_wait: Duration,
+}
+
+impl FileTokenStore {
+    pub fn new(cfg: Config) -> Self {
+        Self {
+            cfg,
+            lock: Mutex::new(()),
+            lock_wait: TOKENS_LOCK_WAIT,
+        }
+    }
+
+    /// The locks of a read-modify-write cycle: the in-process one first, so
+    /// tasks of this process queue on it rather than poll the lock file.
+    async fn lock_for_change(&self) -> Result<(MutexGuard<'_, ()>, TokensFileLock), BridgeError> {
+        let guard = self.lock.lock().await;
+        let file = TokensFileLock::acquire(&self.cfg, self.lock_wait).await?;
+        Ok((guard, file))
+    }
+}
+
+#[async_trait]
+impl TokenStore for FileTokenStore {
+    async fn list(&self) -> Result<Vec<TokenRecord>, BridgeError> {
+        let _guard = self.lock.lock().await;
+        Ok(records(&load_tokens_file(&self.cfg)?))
+    }
+
+    async fn insert(&self, token: &str, description: &str) -> Result<(), BridgeError> {
+        let _guard = self.lock_for_change().await?;
+        let mut tf = load_tokens_file(&self.cfg)?;
+        tf.managed_tokens
+            .insert(token_hash(token), description.to_string());
+        save_tokens_file(&self.cfg, &tf)
+    }
+
+    async fn set_description(&self, id: &str, description: &str) -> Result<bool, BridgeError> {
+        let _guard = self.lock_for_change().await?;
+        let mut tf = load_tokens_file(&self.cfg)?;
+        if !apply_description(&mut tf, id, description) {
+            return Ok(false);
+        }
+        save_tokens_file(&self.cfg, &tf)?;
+        Ok(true)
+    }
+
+    async fn remove(&self, id: &str) -> Result<bool, BridgeError> {
+        let _guard = self.lock_for_change().await?;
+        let mut tf = load_tokens_file(&self.cfg)?;
+        let existed = apply_remove(&mut tf, id);
+        save_tokens_file(&self.cfg, &tf)?;
+        Ok(existed)
+    }
+
+    async fn check(&self, token: &str, project_id: &str) -> Result<Decision, BridgeError> {
+        // lock to avoid reading a partially written file
+        let _guard = self.lock.lock().await;
+        let tf = load_tokens_file(&self.cfg)?;
+        Ok(decide(&tf, token, project_id))
+    }
+
+    async fn quotas(&self) -> Result<HashMap<String, TokenQuota>, BridgeError> {
+        let _guard = self.lock.lock().await;
+        Ok(load_tokens_file(&self.cfg)?.quotas)
+    }
+
+    async fn set_quota(&self, id: &str, quota: TokenQuota) -> Result<bool, BridgeError> {
+        let _guard = self.lock_for_change().await?;
+        let mut tf = load_tokens_file(&self.cfg)?;
+        if !apply_quota(&mut tf, id, quota) {
+            return Ok(false);
+        }
+        save_tokens_file(&self.cfg, &tf)?;
+        Ok(true)
+    }
+
+    async fn scopes(&self) -> Result<HashMap<String, Vec<String>>, BridgeError> {
+        let _guard = self.lock.lock().await;
+        Ok(load_tokens_file(&self.cfg)?.scopes)
+    }
+
+    async fn set_scope(
+        &self,
+        id: &str,
+        projects: Option<Vec<String>>,
+    ) -> Result<bool, BridgeError> {
+        let _guard = self.lock_for_change().await?;
+        let mut tf = load_tokens_file(&self.cfg)?;
+        if !apply_scope(&mut tf, id, projects) {
+            return Ok(false);
+        }
+        save_tokens_file(&self.cfg, &tf)?;
+        Ok(true)
+    }
+
+    async fn source_scopes(&self) -> Result<HashMap<String, String>, BridgeError> {
+        let _guard = self.lock.lock().await;
+        Ok(load_tokens_file(&self.cfg)?.source_scopes)
+    }
+
+    async fn set_source_scope(
+        &self,
+        id: &str,
+        source: Option<String>,
+    ) -> Result<bool, BridgeError> {
+        let _guard = self.lock_for_change().await?;
+        let mut tf = load_tokens_file(&self.cfg)?;
+        if !apply_source_scope(&mut tf, id, source) {
+            return Ok(false);
+        }
+        save_tokens_file(&self.cfg, &tf)?;
+        Ok(true)
+    }
+
+    async fn last_used(&self) -> Result<HashMap<String, DateTime<Utc>>, BridgeError> {
+        let _guard = self.lock.lock().await;
+        Ok(load_tokens_file(&self.cfg)?.last_used)
+    }
+
+    async fn record_use(&self, id: &str, at: DateTime<Utc>) -> Result<(), BridgeError> {
+        let _guard = self.lock_for_change().await?;
+        let mut tf = load_tokens_file(&self.cfg)?;
+        if apply_use(&mut tf, id, at) {
+            save_tokens_file(&self.cfg, &tf)?;
+        }
+        Ok(())
+    }
+
+    async fn import(
+        &self,
+        imported: TokensFile,
+        mode: ImportMode,
+    ) -> Result<ImportSummary, BridgeError> {
+        let _guard = self.lock_for_change().await?;
+        let mut tf = load_tokens_file(&self.cfg)?;
+        let summary = apply_import(&mut tf, imported, mode);
+        save_tokens_file(&self.cfg, &tf)?;
+        Ok(summary)
+    }
+}
+
+/// Non-persistent store, handy for tests and embedding.
+#[derive(Default)]
+pub struct MemoryTokenStore {
+    tokens: Mutex<TokensFile>,
+}
+
+impl MemoryTokenStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A store holding `tokens`, given as token and description.
+    pub fn with_tokens(tokens: HashMap<String, String>) -> Self {
+        Self {
+            tokens: Mutex::new(TokensFile {
+                managed_tokens: tokens
+                    .into_iter()
+                    .map(|(token, description)| (token_hash(&token), description))
+                    .collect(),
+                ..TokensFile::default()
+            }),
+        }
+    }
+}
+
+#[async_trait]
+impl TokenStore for MemoryTokenStore {
+    async fn list(&self) -> Result<Vec<TokenRecord>, BridgeError> {
+        Ok(records(&*self.tokens.lock().await))
+    }
+
+    async fn insert(&self, token: &str, description: &str) -> Result<(), BridgeError> {
+        self.tokens
+            .lock()
+            .await
+            .managed_tokens
+            .insert(token_hash(token), description.to_string());
+        Ok(())
+    }
+
+    async fn set_description(&self, id: &str, description: &str) -> Result<bool, BridgeError> {
+        Ok(apply_description(
+            &mut *self.tokens.lock().await,
+            id,
+            description,
+        ))
+    }
+
+    async fn remove(&self, id: &str) -> Result<bool, BridgeError> {
+        Ok(apply_remove(&mut *self.tokens.lock().await, id))
+    }
+
+    async fn check(&self, token: &str, project_id: &str) -> Result<Decision, BridgeError> {
+        Ok(decide(&*self.tokens.lock().await, token, project_id))
+    }
+
+    async fn quotas(&self) -> Result<HashMap<String, TokenQuota>, BridgeError> {
+        Ok(self.tokens.lock().await.quotas.clone())
+    }
+
+    async fn set_quota(&self, id: &str, quota: TokenQuota) -> Result<bool, BridgeError> {
+        Ok(apply_quota(&mut *self.tokens.lock().await, id, quota))
+    }
+
+    async fn scopes(&self) -> Result<HashMap<String, Vec<String>>, BridgeError> {
+        Ok(self.tokens.lock().await.scopes.clone())
+    }
+
+    async fn set_scope(
+        &self,
+        id: &str,
+        projects: Option<Vec<String>>,
+    ) -> Result<bool, BridgeError> {
+        Ok(apply_scope(&mut *self.tokens.lock().await, id, projects))
+    }
+
+    async fn source_scopes(&self) -> Result<HashMap<String, String>, BridgeError> {
+        Ok(self.tokens.lock().await.source_scopes.clone())
+    }
+
+    async fn set_source_scope(
+        &self,
+        id: &str,
+        source: Option<String>,
+    ) -> Result<bool, BridgeError> {
+        Ok(apply_source_scope(
+            &mut *self.tokens.lock().await,
+            id,
+            source,
+        ))
+    }
+
+    async fn last_used(&self) -> Result<HashMap<String, DateTime<Utc>>, BridgeError> {
+        Ok(self.tokens.lock().await.last_used.clone())
+    }
+
+    async fn record_use(&self, id: &str, at: DateTime<Utc>) -> Result<(), BridgeError> {
+        apply_use(&mut *self.tokens.lock().await, id, at);
+        Ok(())
+    }
+
+    async fn import(
+        &self,
+        imported: TokensFile,
+        mode: ImportMode,
+    ) -> Result<ImportSummary, BridgeError> {
+        Ok(apply_import(&mut *self.tokens.lock().await, imported, mode))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 3)]
+    async fn stores_of_separate_processes_do_not_lose_updates() {
+        let root = TempDir::new().unwrap();
+        let cfg = Config::for_test(root.path());
+        std::fs::create_dir_all(&cfg.git_root).unwrap();
+        // each store takes the lock file through its own open file, like
+        // another process would, and has its own in-process mutex
+        let writers: Vec<_> = ["a", "b"]
+            .into_iter()
+            .map(|name| {
+                let store = FileTokenStore::new(cfg.clone());
+                tokio::spawn(async move {
+                    for i in 0..40 {
+                        store.insert(&format!("{name}{i}"), name).await.unwrap();
+                    }
+                })
+            })
+            .collect();
+        let path = cfg.tokens_file();
+        let reader = std::thread::spawn(move || {
+            let mut reads = 0;
+            while reads < 200 {
+                if let Ok(data) = std::fs::read_to_string(&path) {
+                    serde_json::from_str::<TokensFile>(&data).unwrap();
+                    reads += 1;
+                }
+            }
+        });
+        for writer in writers {
+            writer.await.unwrap();
+        }
+        reader.join().unwrap();
+        assert_eq!(load_tokens_file(&cfg).unwrap().managed_tokens.len(), 80);
+
+        // a lock held elsewhere makes changes fail after the wait, reads
+        // keep working
+        let held = TokensFileLock::acquire(&cfg, Duration::ZERO).await.unwrap();
+        let store = FileTokenStore {
+            lock_wait: Duration::from_millis(100),
+            ..FileTokenStore::new(cfg.clone())
+        };
+        let err = store.insert("c", "c").await.unwrap_err();
+        assert!(err.to_string().contains("another process"), "{err}");
+        assert_eq!(store.list().await.unwrap().len(), 80);
+        drop(held);
+        store.insert("c", "c").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn imports_merge_or_replace_the_stored_tokens() {
+        let root = TempDir::new().unwrap();
+        let cfg = Config::for_test(root.path());
+        std::fs::create_dir_all(&cfg.git_root).unwrap();
+        let store = FileTokenStore::new(cfg.clone());
+        store.insert("a-token", "staging a").await.unwrap();
+        store.insert("b-token", "staging b").await.unwrap();
+        let (a, b, c) = (
+            token_hash("a-token"),
+            token_hash("b-token"),
+            token_hash("c-token"),
+        );
+        let earlier = Utc::now() - chrono::Duration::hours(1);
+        store.record_use(&a, earlier).await.unwrap();
+
+        let mut imported = TokensFile::default();
+        imported.managed_tokens.insert(a.clone(), "prod a".into());
+        imported.managed_tokens.insert(c.clone(), "prod c".into());
+        imported.scopes.insert(a.clone(), vec!["p1".into()]);
+        imported.last_used.insert(a.clone(), Utc::now());
+        let quota = TokenQuota {
+            daily_bytes: None,
+            daily_requests: Some(10),
+        };
+        imported.quotas.insert(c.clone(), quota);
+
+        let merged = |overwrite| ImportMode::Merge { overwrite };
+        let summary = store.import(imported.clone(), merged(false)).await.unwrap();
+        assert_eq!((summary.added, summary.kept, summary.updated), (1, 1, 0));
+        let tf = load_tokens_file(&cfg).unwrap();
+        assert_eq!(tf.managed_tokens[&a], "staging a");
+        assert!(tf.scopes.is_empty());
+        assert_eq!(tf.quotas[&c], quota);
+        // the later use wins even where the description is kept
+        assert_eq!(tf.last_used[&a], imported.last_used[&a]);
+
+        let summary = store.import(imported.clone(), merged(true)).await.unwrap();
+        assert_eq!((summary.added, summary.kept, summary.updated), (0, 0, 2));
+        let tf = load_tokens_file(&cfg).unwrap();
+        assert_eq!(tf.managed_tokens[&a], "prod a");
+        assert_eq!(tf.scopes[&a], ["p1"]);
+        assert_eq!(tf.managed_tokens.len(), 3);
+
+        let summary = store.import(imported, ImportMode::Replace).await.unwrap();
+        assert_eq!((summary.updated, summary.removed), (2, 1));
+        let tf = load_tokens_file(&cfg).unwrap();
+        assert!(!tf.managed_tokens.contains_key(&b));
+        assert_eq!(tf.managed_tokens.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn file_store_round_trip() {
+        let root = TempDir::new().unwrap();
+        let cfg = Config::for_test(root.path());
+        std::fs::create_dir_all(&cfg.git_root).unwrap();
+        let store = FileTokenStore::new(cfg.clone());
+
+        store.insert("b-token", "second").await.unwrap();
+        store.insert("a-token", "first").await.unwrap();
+        let mut expected = vec![
+            TokenRecord {
+                id: token_hash("a-token"),
+                description: "first".into(),
+            },
+            TokenRecord {
+                id: token_hash("b-token"),
+                description: "second".into(),
+            },
+        ];
+        expected.sort_by(|a, b| a.id.cmp(&b.id));
+        assert_eq!(store.list().await.unwrap(), expected);
+        assert_eq!(
+            store.check("a-token", "p1").await.unwrap(),
+            Decision::Allowed
+        );
+
+        let a = token_hash("a-token");
+        assert!(store.set_description(&a, "renamed").await.unwrap());
+        assert!(!store.set_description("a-token", "renamed").await.unwrap());
+        assert!(store.remove(&a).await.unwrap());
+        assert!(!store.remove(&a).await.unwrap());
+        assert_eq!(
+            store.check("a-token", "p1").await.unwrap(),
+            Decision::UnknownToken
+        );
+
+        // persisted in the regular tokens.json format, without the tokens
+        let tf = load_tokens_file(&cfg).unwrap();
+        assert_eq!(tf.managed_tokens.len(), 1);
+        assert_eq!(tf.managed_tokens[&token_hash("b-token")], "second");
+        let raw = std::fs::read_to_string(cfg.tokens_file()).unwrap();
+        assert!(!raw.contains("b-token"), "{raw}");
+    }
+
+    #[tokio::test]
+    async fn plain_tokens_are_hashed_at_startup() {
+        let root = TempDir::new().unwrap();
+        let cfg = Config::for_test(root.path());
+        std::fs::create_dir_all(cfg.state_dir()).unwrap();
+        std::fs::write(
+            cfg.tokens_file(),
+            r#"{
+                "managed_tokens": {"ci": "CI mirror", "thesis": "Thesis"},
+                "quotas": {"ci": {"daily_requests": 5}},
+                "scopes": {"thesis": ["p1"]},
+                "last_used": {"ci": "2026-03-01T12:00:00Z"}
+            }"#,
+        )
+        .unwrap();
+        std::fs::write(
+            cfg.token_usage_file(),
+            r#"{"2026-03-01": {"ci": {"requests": 2, "bytes_in": 1, "bytes_out": 3}}}"#,
+        )
+        .unwrap();
+
+        // reading does not migrate, it refuses until init_storage has
+        let store = FileTokenStore::new(cfg.clone());
+        assert!(load_tokens_file(&cfg).is_err());
+        assert!(store.check("ci", "p2").await.is_err());
+        assert!(
+            std::fs::read_to_string(cfg.tokens_file())
+                .unwrap()
+                .contains("\"ci\"")
+        );
+
+        crate::init_storage(&cfg).unwrap();
+        assert_eq!(store.check("ci", "p2").await.unwrap(), Decision::Allowed);
+        assert_eq!(
+            store.check("thesis", "p2").await.unwrap(),
+            Decision::NotAllowedForProject
+        );
+        let (ci, thesis) = (token_hash("ci"), token_hash("thesis"));
+        assert_eq!(store.quotas().await.unwrap()[&ci].daily_requests, Some(5));
+        assert_eq!(store.scopes().await.unwrap()[&thesis], ["p1"]);
+        assert!(store.last_used().await.unwrap().contains_key(&ci));
+        let descriptions: Vec<String> = store
+            .list()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|r| r.description)
+            .collect();
+        assert!(descriptions.contains(&"CI mirror".to_string()));
+
+        for file in [cfg.tokens_file(), cfg.token_usage_file()] {
+            let raw = std::fs::read_to_string(file).unwrap();
+            assert!(raw.contains(&ci), "{raw}");
+            assert!(!raw.contains("\"ci\""), "{raw}");
+        }
+        // the migrated file is loaded as it is
+        assert!(load_tokens_file(&cfg).unwrap().hashed);
+        assert_eq!(store.check("ci", "p2").await.unwrap(), Decision::Allowed);
+    }
+
+    #[tokio::test]
+    async fn quotas_follow_their_token() {
+        let root = TempDir::new().unwrap();
+        let cfg = Config::for_test(root.path());
+        std::fs::create_dir_all(&cfg.git_root).unwrap();
+        let store = FileTokenStore::new(cfg.clone());
+        let quota = TokenQuota {
+            daily_bytes: Some(1000),
+            daily_requests: None,
+        };
+
+        let ci = token_hash("ci");
+        assert!(!store.set_quota(&ci, quota).await.unwrap());
+        store.insert("ci", "CI mirror").await.unwrap();
+        assert!(store.set_quota(&ci, quota).await.unwrap());
+        assert_eq!(store.quotas().await.unwrap()[&ci], quota);
+
+        // unlimited quotas are not stored
+        store.set_quota(&ci, TokenQuota::default()).await.unwrap();
+        assert!(store.quotas().await.unwrap().is_empty());
+
+        store.set_quota(&ci, quota).await.unwrap();
+        store.remove(&ci).await.unwrap();
+        assert!(load_tokens_file(&cfg).unwrap().quotas.is_empty());
+    }
+
+    #[tokio::test]
+    async fn scopes_limit_tokens_to_their_projects() {
+        let root = TempDir::new().unwrap();
+        let cfg = Config::for_test(root.path());
+        std::fs::create_dir_all(&cfg.git_root).unwrap();
+        let store = FileTokenStore::new(cfg.clone());
+
+        let ci = token_hash("ci");
+        assert!(!store.set_scope(&ci, Some(vec!["p1".into()])).await.unwrap());
+        store.insert("ci", "CI").await.unwrap();
+        assert!(store.set_scope(&ci, Some(vec!["p1".into()])).await.unwrap());
+        assert_eq!(store.check("ci", "p1").await.unwrap(), Decision::Allowed);
+        assert_eq!(
+            store.check("ci", "p2").await.unwrap(),
+            Decision::NotAllowedForProject
+        );
+
+        assert!(store.set_scope(&ci, None).await.unwrap());
+        assert_eq!(store.check("ci", "p2").await.unwrap(), Decision::Allowed);
+
+        assert!(
+            !store
+                .set_source_scope("unknown", Some("prod".into()))
+                .await
+                .unwrap()
+        );
+        assert!(
+            store
+                .set_source_scope(&ci, Some("prod".into()))
+                .await
+                .unwrap()
+        );
+        assert_eq!(store.source_scopes().await.unwrap()[&ci], "prod");
+
+        store.set_scope(&ci, Some(vec!["p1".into()])).await.unwrap();
+        store.remove(&ci).await.unwrap();
+        let tf = load_tokens_file(&cfg).unwrap();
+        assert!(tf.scopes.is_empty());
+        assert!(tf.source_scopes.is_empty());
+    }
+
+    #[tokio::test]
+    async fn last_use_survives_a_restart_and_goes_with_the_token() {
+        let root = TempDir::new().unwrap();
+        let cfg = Config::for_test(root.path());
+        std::fs::create_dir_all(&cfg.git_root).unwrap();
+        let store = FileTokenStore::new(cfg.clone());
+        store.insert("ci", "CI").await.unwrap();
+        let ci = token_hash("ci");
+        let at = "2026-03-01T12:00:00Z".parse::<DateTime<Utc>>().unwrap();
+
+        // concurrent uses each rewrite the file under the store lock
+        let store = std::sync::Arc::new(store);
+        let uses: Vec<_> = (0..8)
+            .map(|i| {
+                let (store, ci) = (store.clone(), ci.clone());
+                tokio::spawn(async move {
+                    let at = at + chrono::Duration::seconds(i);
+                    store.record_use(&ci, at).await.unwrap();
+                    store.record_use(&token_hash("unknown"), at).await.unwrap();
+                })
+            })
+            .collect();
+        for used in uses {
+            used.await.unwrap();
+        }
+
+        let restarted = FileTokenStore::new(cfg.clone());
+        let last_used = restarted.last_used().await.unwrap();
+        assert_eq!(last_used.len(), 1);
+        assert!(last_used[&ci] >= at);
+        assert_eq!(restarted.list().await.unwrap().len(), 1);
+
+        restarted.remove(&ci).await.unwrap();
+        assert!(load_tokens_file(&cfg).unwrap().last_used.is_empty());
+    }
+}
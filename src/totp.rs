@@ -0,0 +1,117 @@
+//! Time-based one-time passwords (RFC 6238) for the admin login
+//! (`ADMIN_TOTP_SECRET`): 6 digits, 30 second steps, HMAC-SHA1, as used by
+//! the common authenticator apps.
+
+use crate::auth::constant_time_eq;
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+
+const STEP_SECONDS: u64 = 30;
+const DIGITS: u32 = 6;
+/// Steps before and after the current one that are still accepted, for
+/// clocks that are a little off.
+const SKEW_STEPS: u64 = 1;
+/// Shortest accepted secret; authenticator apps hand out at least 80 bits.
+const MIN_SECRET_BYTES: usize = 10;
+
+/// Decode a base32 secret (RFC 4648 alphabet), ignoring case, spaces and
+/// `=` padding; `None` if it is not base32 or too short.
+pub(crate) fn decode_secret(secret: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let (mut buffer, mut bits) = (0u32, 0u32);
+    for c in secret.chars().filter(|c| !c.is_whitespace() && *c != '=') {
+        let value = match c.to_ascii_uppercase() {
+            c @ 'A'..='Z' => c as u32 - 'A' as u32,
+            c @ '2'..='7' => c as u32 - '2' as u32 + 26,
+            _ => return None,
+        };
+        buffer = (buffer << 5) | value;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    (bytes.len() >= MIN_SECRET_BYTES).then_some(bytes)
+}
+
+/// The code for time step `step` (RFC 4226 dynamic truncation).
+fn code_at(key: &[u8], step: u64) -> String {
+    let mut mac = Hmac::<Sha1>::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(&step.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+    let offset = usize::from(digest[digest.len() - 1] & 0x0f);
+    let value = u32::from_be_bytes([
+        digest[offset] & 0x7f,
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]);
+    format!(
+        "{:0width$}",
+        value % 10u32.pow(DIGITS),
+        width = DIGITS as usize
+    )
+}
+
+/// Whether `code` is valid for `secret` at `unix_seconds`, give or take
+/// [`SKEW_STEPS`]. Spaces in the code are ignored.
+pub(crate) fn verify(secret: &str, code: &str, unix_seconds: u64) -> bool {
+    let Some(key) = decode_secret(secret) else {
+        return false;
+    };
+    let code: String = code.chars().filter(|c| !c.is_whitespace()).collect();
+    if code.len() != DIGITS as usize || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return false;
+    }
+    let current = unix_seconds / STEP_SECONDS;
+    // check every step so the time taken does not tell which one matched
+    (current.saturating_sub(SKEW_STEPS)..=current + SKEW_STEPS).fold(false, |matched, step| {
+        constant_time_eq(&code_at(&key, step), &code) | matched
+    })
+}
+
+/// The code for `secret` at `unix_seconds`, for tests that log in.
+#[cfg(test)]
+pub(crate) fn code(secret: &str, unix_seconds: u64) -> String {
+    let key = decode_secret(secret).expect("valid test secret");
+    code_at(&key, unix_seconds / STEP_SECONDS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The RFC 6238 SHA-1 key, "12345678901234567890", in base32.
+    const RFC_SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+    #[test]
+    fn codes_match_the_rfc_vectors_within_one_step() {
+        assert_eq!(
+            decode_secret("gezd gnbv gy3t qojq gezd gnbv gy3t qojq").unwrap(),
+            b"12345678901234567890"
+        );
+        for bad in ["GEZDGNBV1", "GEZDGNBV", ""] {
+            assert_eq!(decode_secret(bad), None, "{bad}");
+        }
+
+        // the last six digits of the RFC's eight-digit codes
+        assert!(verify(RFC_SECRET, "287082", 59));
+        assert!(verify(RFC_SECRET, "081804", 1_111_111_109));
+        assert!(verify(RFC_SECRET, "050471", 1_111_111_111));
+        assert!(verify(RFC_SECRET, "005924", 1_234_567_890));
+
+        // one step early or late is fine, two are not
+        assert!(verify(RFC_SECRET, "005924", 1_234_567_890 + 30));
+        assert!(verify(RFC_SECRET, "005924", 1_234_567_890 - 30));
+        assert!(!verify(RFC_SECRET, "005924", 1_234_567_890 + 60));
+        assert!(!verify(RFC_SECRET, "005924", 1_234_567_890 - 60));
+
+        assert!(verify(RFC_SECRET, "005 924", 1_234_567_890));
+        for bad in ["", "5924", "0059240", "00592a", "005925"] {
+            assert!(!verify(RFC_SECRET, bad, 1_234_567_890), "{bad}");
+        }
+        assert!(!verify("not base32!", "005924", 1_234_567_890));
+    }
+}
@@ -0,0 +1,239 @@
+//! Removed mirrors are moved to `GIT_ROOT/state/.trash` and deleted only after
+//! `TRASH_RETENTION_DAYS`, so a vanished source or a mistaken prune can be
+//! undone.
+
+use crate::AppState;
+use crate::config::Config;
+use crate::error::BridgeError;
+use crate::metadata::dir_size;
+use crate::project_id::ProjectId;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::Serialize;
+use std::fs;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+/// Time between purges of expired trash entries.
+const PURGE_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Removal time in trash entry names, in UTC with milliseconds.
+const STAMP_FORMAT: &str = "%Y%m%dT%H%M%S%3fZ";
+
+/// Why a trash entry cannot be restored.
+#[cfg(feature = "admin-ui")]
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum TrashError {
+    #[error("no trash entry '{0}'")]
+    NotFound(String),
+    #[error("project {0} has a mirror again; prune or remove it first")]
+    MirrorExists(String),
+    #[error(transparent)]
+    Io(#[from] BridgeError),
+}
+
+/// A removed mirror waiting in the trash.
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub(crate) struct TrashEntry {
+    /// Directory name below `.trash`, `<id>.git-<timestamp>`
+    pub(crate) entry: String,
+    pub(crate) project_id: ProjectId,
+    pub(crate) removed_at: DateTime<Utc>,
+    pub(crate) expires_at: DateTime<Utc>,
+    pub(crate) repo_bytes: u64,
+}
+
+fn entry_name(project_id: &ProjectId, at: DateTime<Utc>) -> String {
+    format!("{project_id}.git-{}", at.format(STAMP_FORMAT))
+}
+
+/// Project id and removal time of a trash entry name.
+fn parse_entry_name(name: &str) -> Option<(ProjectId, DateTime<Utc>)> {
+    let (project_id, stamp) = name.rsplit_once(".git-")?;
+    let at = NaiveDateTime::parse_from_str(stamp, STAMP_FORMAT).ok()?;
+    Some((ProjectId::new(project_id).ok()?, at.and_utc()))
+}
+
+fn expires_at(cfg: &Config, removed_at: DateTime<Utc>) -> DateTime<Utc> {
+    let days = i64::try_from(cfg.trash_retention_days).unwrap_or(i64::MAX / 86_400_000);
+    removed_at + chrono::Duration::days(days)
+}
+
+/// Remove the mirror of `project_id`. It is moved to the trash unless
+/// `purge` is set or `TRASH_RETENTION_DAYS` is 0, in which case it is
+/// deleted right away. Returns the trash entry. Callers must hold the
+/// project lock. Blocking.
+pub(crate) fn remove_mirror(
+    cfg: &Config,
+    project_id: &ProjectId,
+    purge: bool,
+) -> Result<Option<String>, BridgeError> {
+    let mirror = cfg.bare_repo_dir(project_id)?;
+    if purge || cfg.trash_retention_days == 0 {
+        fs::remove_dir_all(&mirror)?;
+        return Ok(None);
+    }
+    let trash = cfg.trash_dir();
+    fs::create_dir_all(&trash)?;
+    let entry = entry_name(project_id, Utc::now());
+    fs::rename(&mirror, trash.join(&entry))?;
+    Ok(Some(entry))
+}
+
+/// Every entry in the trash, most recently removed first. Blocking.
+pub(crate) fn list_trash(cfg: &Config) -> Result<Vec<TrashEntry>, BridgeError> {
+    let dir = match fs::read_dir(cfg.trash_dir()) {
+        Ok(dir) => dir,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(BridgeError::Io(e)),
+    };
+    let mut entries: Vec<TrashEntry> = dir
+        .flatten()
+        .filter_map(|item| {
+            let name = item.file_name().to_str()?.to_string();
+            let (project_id, removed_at) = parse_entry_name(&name)?;
+            Some(TrashEntry {
+                project_id,
+                removed_at,
+                expires_at: expires_at(cfg, removed_at),
+                repo_bytes: dir_size(&item.path()),
+                entry: name,
+            })
+        })
+        .collect();
+    entries.sort_by_key(|e| std::cmp::Reverse(e.removed_at));
+    Ok(entries)
+}
+
+/// Delete the trash entries that expired by `now`. Returns their names.
+/// Blocking.
+pub(crate) fn purge_expired(cfg: &Config, now: DateTime<Utc>) -> Result<Vec<String>, BridgeError> {
+    let mut purged = Vec::new();
+    for entry in list_trash(cfg)? {
+        if entry.expires_at > now {
+            continue;
+        }
+        match fs::remove_dir_all(cfg.trash_dir().join(&entry.entry)) {
+            Ok(()) => {
+                info!(project_id = %entry.project_id, entry = %entry.entry, "purged mirror from trash");
+                purged.push(entry.entry);
+            }
+            Err(e) => warn!(entry = %entry.entry, "cannot purge trash entry: {e}"),
+        }
+    }
+    Ok(purged)
+}
+
+/// Move a trash entry back into place as the mirror of its project.
+/// Returns the project id. Callers must hold the project lock. Blocking.
+#[cfg(feature = "admin-ui")]
+pub(crate) fn restore_from_trash(cfg: &Config, entry: &str) -> Result<ProjectId, TrashError> {
+    let Some((project_id, _)) = parse_entry_name(entry) else {
+        return Err(TrashError::NotFound(entry.to_string()));
+    };
+    let trashed = cfg.trash_dir().join(entry);
+    if !trashed.is_dir() {
+        return Err(TrashError::NotFound(entry.to_string()));
+    }
+    let mirror = cfg.bare_repo_dir(&project_id)?;
+    if mirror.exists() {
+        return Err(TrashError::MirrorExists(project_id.to_string()));
+    }
+    fs::rename(&trashed, &mirror).map_err(BridgeError::Io)?;
+    crate::gitweb::mirror_changed(cfg, &project_id);
+    info!(%project_id, %entry, "mirror restored from trash");
+    Ok(project_id)
+}
+
+/// Project id of a trash entry name, if it is one.
+pub(crate) fn entry_project_id(entry: &str) -> Option<ProjectId> {
+    parse_entry_name(entry).map(|(project_id, _)| project_id)
+}
+
+/// Background task purging expired trash entries at startup and every
+/// [`PURGE_INTERVAL`].
+pub(crate) async fn run_periodically(state: Arc<AppState>) {
+    loop {
+        if !state.maintenance.is_enabled() {
+            let _in_flight = state.maintenance.begin();
+            let cfg = state.cfg.clone();
+            match tokio::task::spawn_blocking(move || purge_expired(&cfg, Utc::now())).await {
+                Ok(Ok(_)) => {}
+                Ok(Err(e)) => error!("trash purge failed: {e}"),
+                Err(e) => error!("trash purge failed: join error: {e}"),
+            }
+        }
+        tokio::time::sleep(PURGE_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::project_id::pid;
+    use tempfile::TempDir;
+
+    #[test]
+    fn removed_mirrors_wait_in_the_trash_until_they_expire() {
+        let root = TempDir::new().unwrap();
+        let mut cfg = Config::for_test(root.path());
+        fs::create_dir_all(cfg.bare_repo_dir(&pid("p1")).unwrap()).unwrap();
+        fs::write(
+            cfg.bare_repo_dir(&pid("p1")).unwrap().join("HEAD"),
+            "ref: refs/heads/master\n",
+        )
+        .unwrap();
+
+        let entry = remove_mirror(&cfg, &pid("p1"), false).unwrap().unwrap();
+        assert!(!cfg.bare_repo_dir(&pid("p1")).unwrap().exists());
+        assert!(entry.starts_with("p1.git-"));
+        let listed = list_trash(&cfg).unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].project_id, "p1");
+        assert_eq!(
+            listed[0].expires_at - listed[0].removed_at,
+            chrono::Duration::days(14)
+        );
+        assert!(crate::repo::list_mirrors(&cfg).unwrap().is_empty());
+
+        assert!(purge_expired(&cfg, Utc::now()).unwrap().is_empty());
+        let later = Utc::now() + chrono::Duration::days(15);
+        assert_eq!(purge_expired(&cfg, later).unwrap(), [entry]);
+        assert!(list_trash(&cfg).unwrap().is_empty());
+
+        fs::create_dir_all(cfg.bare_repo_dir(&pid("p2")).unwrap()).unwrap();
+        assert_eq!(remove_mirror(&cfg, &pid("p2"), true).unwrap(), None);
+        cfg.trash_retention_days = 0;
+        fs::create_dir_all(cfg.bare_repo_dir(&pid("p3")).unwrap()).unwrap();
+        assert_eq!(remove_mirror(&cfg, &pid("p3"), false).unwrap(), None);
+        assert!(list_trash(&cfg).unwrap().is_empty());
+    }
+
+    #[cfg(feature = "admin-ui")]
+    #[test]
+    fn trash_entries_are_restored_only_over_a_missing_mirror() {
+        let root = TempDir::new().unwrap();
+        let cfg = Config::for_test(root.path());
+        fs::create_dir_all(cfg.bare_repo_dir(&pid("p1")).unwrap()).unwrap();
+        let entry = remove_mirror(&cfg, &pid("p1"), false).unwrap().unwrap();
+        assert_eq!(entry_project_id(&entry).as_deref(), Some("p1"));
+
+        fs::create_dir_all(cfg.bare_repo_dir(&pid("p1")).unwrap()).unwrap();
+        assert!(matches!(
+            restore_from_trash(&cfg, &entry),
+            Err(TrashError::MirrorExists(_))
+        ));
+        fs::remove_dir(cfg.bare_repo_dir(&pid("p1")).unwrap()).unwrap();
+
+        assert_eq!(restore_from_trash(&cfg, &entry).unwrap(), "p1");
+        assert!(cfg.bare_repo_dir(&pid("p1")).unwrap().is_dir());
+        assert!(matches!(
+            restore_from_trash(&cfg, &entry),
+            Err(TrashError::NotFound(_))
+        ));
+        assert!(matches!(
+            restore_from_trash(&cfg, "../p1.git-20260101T000000000Z"),
+            Err(TrashError::NotFound(_))
+        ));
+    }
+}
@@ -0,0 +1,243 @@
+use crate::auth::{TokenQuota, token_hash};
+use crate::config::Config;
+use crate::error::BridgeError;
+use chrono::{Days, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Days of traffic kept in `token-usage.json`, today included.
+pub(crate) const USAGE_RETENTION_DAYS: u64 = 31;
+
+/// Git traffic of one token on one UTC day.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct DailyUsage {
+    pub(crate) requests: u64,
+    /// Request body bytes
+    pub(crate) bytes_in: u64,
+    /// Response body bytes
+    pub(crate) bytes_out: u64,
+}
+
+impl DailyUsage {
+    pub(crate) fn bytes(&self) -> u64 {
+        self.bytes_in.saturating_add(self.bytes_out)
+    }
+
+    /// Why `quota` does not allow another request, if it does not.
+    pub(crate) fn exceeds(&self, quota: &TokenQuota) -> Option<String> {
+        if let Some(limit) = quota.daily_requests
+            && self.requests >= limit
+        {
+            return Some(format!(
+                "daily request quota of {limit} requests exceeded for this token; it resets at 00:00 UTC"
+            ));
+        }
+        if let Some(limit) = quota.daily_bytes
+            && self.bytes() >= limit
+        {
+            return Some(format!(
+                "daily transfer quota of {limit} bytes exceeded for this token ({} bytes used); it resets at 00:00 UTC",
+                self.bytes()
+            ));
+        }
+        None
+    }
+}
+
+/// On-disk format of `token-usage.json`: totals by day, then by
+/// [`token_hash`](crate::auth::token_hash).
+type UsageDays = BTreeMap<NaiveDate, HashMap<String, DailyUsage>>;
+
+/// Daily git traffic per token, persisted under `git_root` after every
+/// request so quotas survive restarts.
+pub(crate) struct UsageLog {
+    path: PathBuf,
+    /// Contents of the file once read; held while updating it
+    days: Mutex<Option<UsageDays>>,
+}
+
+impl UsageLog {
+    pub(crate) fn new(cfg: &Config) -> Self {
+        Self {
+            path: cfg.token_usage_file(),
+            days: Mutex::new(None),
+        }
+    }
+
+    fn read(&self) -> Result<UsageDays, BridgeError> {
+        match fs::read_to_string(&self.path) {
+            Ok(data) => Ok(serde_json::from_str(&data)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(UsageDays::new()),
+            Err(e) => Err(BridgeError::Io(e)),
+        }
+    }
+
+    fn with_days<R>(&self, f: impl FnOnce(&mut UsageDays) -> R) -> Result<R, BridgeError> {
+        let mut days = self.days.lock().expect("usage lock poisoned");
+        if days.is_none() {
+            *days = Some(self.read()?);
+        }
+        Ok(f(days.as_mut().expect("loaded above")))
+    }
+
+    /// Traffic of `token` so far today. Blocking.
+    pub(crate) fn today(&self, token: &str) -> Result<DailyUsage, BridgeError> {
+        let today = Utc::now().date_naive();
+        self.with_days(|days| {
+            days.get(&today)
+                .and_then(|tokens| tokens.get(token))
+                .copied()
+                .unwrap_or_default()
+        })
+    }
+
+    /// Count one request of `token`, forget days past the retention and
+    /// write the file. Blocking.
+    pub(crate) fn record(
+        &self,
+        token: &str,
+        bytes_in: u64,
+        bytes_out: u64,
+    ) -> Result<(), BridgeError> {
+        self.record_on(Utc::now().date_naive(), token, bytes_in, bytes_out)
+    }
+
+    fn record_on(
+        &self,
+        day: NaiveDate,
+        token: &str,
+        bytes_in: u64,
+        bytes_out: u64,
+    ) -> Result<(), BridgeError> {
+        let data = self.with_days(|days| {
+            let usage = days
+                .entry(day)
+                .or_default()
+                .entry(token.to_string())
+                .or_default();
+            usage.requests += 1;
+            usage.bytes_in = usage.bytes_in.saturating_add(bytes_in);
+            usage.bytes_out = usage.bytes_out.saturating_add(bytes_out);
+            if let Some(oldest) = day.checked_sub_days(Days::new(USAGE_RETENTION_DAYS - 1)) {
+                days.retain(|d, _| *d >= oldest);
+            }
+            serde_json::to_string(days)
+        })??;
+        write_days(&self.path, &data)
+    }
+
+    /// Daily traffic of `token`, newest day first. Blocking.
+    #[cfg(feature = "admin-ui")]
+    pub(crate) fn history(&self, token: &str) -> Result<Vec<(NaiveDate, DailyUsage)>, BridgeError> {
+        self.with_days(|days| {
+            days.iter()
+                .rev()
+                .filter_map(|(day, tokens)| tokens.get(token).map(|usage| (*day, *usage)))
+                .collect()
+        })
+    }
+}
+
+fn write_days(path: &Path, data: &str) -> Result<(), BridgeError> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, data)?;
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+/// Replace the tokens in a `token-usage.json` written before tokens were
+/// hashed by their hashes. Only called by [`crate::auth::hash_plain_tokens`], as
+/// nothing in the file tells plain tokens from hashes. Blocking.
+pub(crate) fn hash_usage_tokens(cfg: &Config) -> Result<(), BridgeError> {
+    let path = cfg.token_usage_file();
+    let mut days: UsageDays = match fs::read_to_string(&path) {
+        Ok(data) => serde_json::from_str(&data)?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(BridgeError::Io(e)),
+    };
+    for tokens in days.values_mut() {
+        *tokens = tokens
+            .drain()
+            .map(|(token, usage)| (token_hash(&token), usage))
+            .collect();
+    }
+    write_days(&path, &serde_json::to_string(&days)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn day(d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 3, d).unwrap()
+    }
+
+    #[test]
+    fn usage_is_persisted_and_old_days_dropped() {
+        let root = TempDir::new().unwrap();
+        let cfg = Config::for_test(root.path());
+        fs::create_dir_all(&cfg.git_root).unwrap();
+        let log = UsageLog::new(&cfg);
+
+        log.record_on(day(1), "ci", 10, 100).unwrap();
+        log.record_on(day(2), "ci", 10, 100).unwrap();
+        log.record_on(day(2), "ci", 5, 50).unwrap();
+        log.record_on(day(2), "other", 1, 1).unwrap();
+
+        let reloaded = UsageLog::new(&cfg).read().unwrap();
+        assert_eq!(
+            reloaded[&day(2)]["ci"],
+            DailyUsage {
+                requests: 2,
+                bytes_in: 15,
+                bytes_out: 150
+            }
+        );
+        assert!(reloaded.contains_key(&day(1)));
+
+        // day 1 falls out of the 31-day window
+        log.record_on(day(31) + Days::new(1), "ci", 0, 0).unwrap();
+        assert!(!UsageLog::new(&cfg).read().unwrap().contains_key(&day(1)));
+    }
+
+    #[test]
+    fn quotas_are_checked_against_todays_usage() {
+        let usage = DailyUsage {
+            requests: 3,
+            bytes_in: 400,
+            bytes_out: 600,
+        };
+        assert!(usage.exceeds(&TokenQuota::default()).is_none());
+        assert!(
+            usage
+                .exceeds(&TokenQuota {
+                    daily_bytes: Some(1001),
+                    daily_requests: Some(4),
+                })
+                .is_none()
+        );
+
+        let reason = usage
+            .exceeds(&TokenQuota {
+                daily_bytes: None,
+                daily_requests: Some(3),
+            })
+            .unwrap();
+        assert!(reason.contains("request quota of 3"), "{reason}");
+
+        let reason = usage
+            .exceeds(&TokenQuota {
+                daily_bytes: Some(1000),
+                daily_requests: None,
+            })
+            .unwrap();
+        assert!(reason.contains("1000 bytes"), "{reason}");
+    }
+}
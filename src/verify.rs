@@ -0,0 +1,280 @@
+use crate::AppState;
+use crate::config::Config;
+use crate::error::BridgeError;
+use crate::git::GitRunner;
+use crate::integrity::check_repo;
+use crate::project_id::ProjectId;
+use crate::repo::{PendingChanges, is_external_repo, list_mirrors, pending_changes};
+use crate::source::ProjectSource;
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+/// How a mirror compares to its source.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub(crate) enum Verdict {
+    /// The tip of the read-only branch matches the source
+    InSync,
+    /// A sync would commit these changes
+    WouldChange {
+        #[serde(flatten)]
+        changes: PendingChanges,
+    },
+    /// The source has no mirror yet; the next fetch creates it
+    NotMirrored,
+    /// The mirror has no source; the next sync removes it
+    SourceMissing,
+    /// `git fsck` or the read-only branch failed
+    RepoCorrupt { error: String },
+    /// A repo maintained outside the bridge that passed `git fsck`
+    External,
+    /// The project stayed locked for `LOCK_WAIT_SECONDS`
+    Busy,
+    /// Anything else that kept the comparison from finishing
+    Error { error: String },
+}
+
+impl Verdict {
+    /// One line for people reading a report.
+    pub(crate) fn describe(&self) -> String {
+        match self {
+            Self::InSync => "in sync".to_string(),
+            Self::WouldChange { changes } => format!(
+                "would change ({} added, {} modified, {} deleted)",
+                changes.added, changes.modified, changes.deleted
+            ),
+            Self::NotMirrored => "not mirrored yet".to_string(),
+            Self::SourceMissing => "source missing".to_string(),
+            Self::RepoCorrupt { error } => format!("repo corrupt: {error}"),
+            Self::External => "external repo, fsck ok".to_string(),
+            Self::Busy => "busy, not verified".to_string(),
+            Self::Error { error } => format!("error: {error}"),
+        }
+    }
+
+    /// Whether the project needs attention.
+    pub(crate) fn is_problem(&self) -> bool {
+        !matches!(self, Self::InSync | Self::NotMirrored | Self::External)
+    }
+}
+
+/// The verdict on one project.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct ProjectReport {
+    pub(crate) project_id: ProjectId,
+    #[serde(flatten)]
+    pub(crate) verdict: Verdict,
+}
+
+/// Compare one project without changing anything. Callers should hold the
+/// project lock. Blocking.
+pub(crate) fn verify_project(
+    git: &dyn GitRunner,
+    source: &dyn ProjectSource,
+    cfg: &Config,
+    project_id: &ProjectId,
+) -> Verdict {
+    let mirrored = cfg
+        .bare_repo_dir(project_id)
+        .is_ok_and(|repo| repo.is_dir());
+    if mirrored && is_external_repo(cfg, project_id) {
+        let check = check_repo(git, cfg, project_id);
+        return match check.error {
+            Some(error) => Verdict::RepoCorrupt { error },
+            None => Verdict::External,
+        };
+    }
+    if !source.exists(project_id) {
+        return Verdict::SourceMissing;
+    }
+    if !mirrored {
+        return Verdict::NotMirrored;
+    }
+    if let Some(error) = check_repo(git, cfg, project_id).error {
+        return Verdict::RepoCorrupt { error };
+    }
+    match pending_changes(git, source, cfg, project_id) {
+        Ok(changes) if changes.is_empty() => Verdict::InSync,
+        Ok(changes) => Verdict::WouldChange { changes },
+        Err(e) => Verdict::Error {
+            error: e.to_string(),
+        },
+    }
+}
+
+/// Every project with a source or a mirror, sorted. Blocking.
+pub(crate) fn all_projects(
+    source: &dyn ProjectSource,
+    cfg: &Config,
+) -> Result<Vec<ProjectId>, BridgeError> {
+    let mut ids: BTreeSet<ProjectId> = source.list_projects()?.into_iter().collect();
+    ids.extend(list_mirrors(cfg)?);
+    Ok(ids.into_iter().collect())
+}
+
+/// Verify `project_ids`, at most `jobs` at a time, calling `progress` with
+/// each report as it completes. Returns the reports sorted by project id.
+pub(crate) async fn verify_projects(
+    state: &Arc<AppState>,
+    git: Arc<dyn GitRunner>,
+    project_ids: Vec<ProjectId>,
+    jobs: usize,
+    mut progress: impl FnMut(&ProjectReport),
+) -> Vec<ProjectReport> {
+    let semaphore = Arc::new(Semaphore::new(jobs.max(1)));
+    let mut tasks = JoinSet::new();
+    for project_id in project_ids {
+        let semaphore = semaphore.clone();
+        let state = state.clone();
+        let git = git.clone();
+        tasks.spawn(async move {
+            let _permit = semaphore
+                .acquire_owned()
+                .await
+                .expect("semaphore is never closed");
+            let verdict = match state.lock_project(&project_id, "verify").await {
+                Ok(guard) => {
+                    let source = state.source.clone();
+                    let cfg = state.cfg.clone();
+                    let id = project_id.clone();
+                    tokio::task::spawn_blocking(move || {
+                        let _guard = guard;
+                        verify_project(git.as_ref(), source.as_ref(), &cfg, &id)
+                    })
+                    .await
+                    .unwrap_or_else(|e| Verdict::Error {
+                        error: format!("join error: {e}"),
+                    })
+                }
+                Err(BridgeError::ProjectBusy(_)) => Verdict::Busy,
+                Err(e) => Verdict::Error {
+                    error: e.to_string(),
+                },
+            };
+            ProjectReport {
+                project_id,
+                verdict,
+            }
+        });
+    }
+
+    let mut reports = Vec::new();
+    while let Some(result) = tasks.join_next().await {
+        match result {
+            Ok(report) => {
+                progress(&report);
+                reports.push(report);
+            }
+            Err(e) => tracing::error!("verify task failed: {e}"),
+        }
+    }
+    reports.sort_by(|a, b| a.project_id.cmp(&b.project_id));
+    reports
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::git::mock::ScriptedGit;
+    use crate::project_id::pid;
+    use crate::repo::EXTERNAL_MARKER;
+    use crate::source::FsProjectSource;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn every_project_gets_a_verdict() {
+        let root = TempDir::new().unwrap();
+        let cfg = Config::for_test(root.path());
+        for id in ["clean", "dirty", "broken", "new"] {
+            let dir = cfg.project_source_dir(&pid(id)).unwrap();
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join("main.tex"), id).unwrap();
+        }
+        for id in ["clean", "dirty", "broken", "gone", "style"] {
+            fs::create_dir_all(cfg.bare_repo_dir(&pid(id)).unwrap()).unwrap();
+        }
+        fs::write(
+            cfg.bare_repo_dir(&pid("style"))
+                .unwrap()
+                .join(EXTERNAL_MARKER),
+            "",
+        )
+        .unwrap();
+
+        let state = Arc::new(AppState::new(cfg.clone()));
+        let ids = all_projects(&FsProjectSource::new(cfg.clone()), &cfg).unwrap();
+        assert_eq!(ids, ["broken", "clean", "dirty", "gone", "new", "style"]);
+
+        // the scripted git answers the same for every mirror, so verify them
+        // one at a time with the answers each one needs
+        let verdict = |git: ScriptedGit, id: &'static str| {
+            let state = state.clone();
+            async move {
+                verify_projects(&state, Arc::new(git), vec![pid(id)], 2, |_| {})
+                    .await
+                    .remove(0)
+                    .verdict
+            }
+        };
+        assert_eq!(verdict(ScriptedGit::new(), "clean").await, Verdict::InSync);
+        let dirty = ScriptedGit::new().on_stdout(
+            &["diff", "--staged", "--name-status", "--no-renames", "-z"],
+            b"M\0main.tex\0",
+        );
+        assert_eq!(
+            verdict(dirty, "dirty").await,
+            Verdict::WouldChange {
+                changes: PendingChanges {
+                    modified: 1,
+                    ..PendingChanges::default()
+                }
+            }
+        );
+        let corrupt = ScriptedGit::new().on(&["fsck", "--no-progress"], 2, "missing blob");
+        assert!(matches!(
+            verdict(corrupt, "broken").await,
+            Verdict::RepoCorrupt { error } if error.contains("missing blob")
+        ));
+        assert_eq!(
+            verdict(ScriptedGit::new(), "gone").await,
+            Verdict::SourceMissing
+        );
+        assert_eq!(
+            verdict(ScriptedGit::new(), "new").await,
+            Verdict::NotMirrored
+        );
+        assert_eq!(
+            verdict(ScriptedGit::new(), "style").await,
+            Verdict::External
+        );
+
+        let reports = verify_projects(&state, Arc::new(ScriptedGit::new()), ids, 3, |_| {}).await;
+        let problems: Vec<_> = reports
+            .iter()
+            .filter(|r| r.verdict.is_problem())
+            .map(|r| r.project_id.as_str())
+            .collect();
+        assert_eq!(problems, ["gone"]);
+        let json = serde_json::to_value(&reports[2]).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({"project_id": "dirty", "status": "in_sync"})
+        );
+        let json = serde_json::to_value(Verdict::WouldChange {
+            changes: PendingChanges {
+                added: 1,
+                modified: 2,
+                deleted: 3,
+            },
+        })
+        .unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({"status": "would_change", "added": 1, "modified": 2, "deleted": 3})
+        );
+    }
+}
@@ -0,0 +1,340 @@
+//! Warm-up: create the missing mirrors of all projects ahead of their first
+//! fetch, so a new or migrated bridge does not build them on demand.
+
+use crate::AppState;
+use crate::error::BridgeError;
+use crate::metadata::SyncTrigger;
+use crate::project_id::ProjectId;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tracing::{info, warn};
+
+/// Failures kept in the progress report; the rest are only counted.
+const MAX_REPORTED_FAILURES: usize = 100;
+
+/// A project whose mirror could not be created.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct WarmupFailure {
+    pub(crate) project_id: ProjectId,
+    pub(crate) error: String,
+}
+
+/// Progress of the current or last warm-up.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct WarmupProgress {
+    pub(crate) started_at: DateTime<Utc>,
+    pub(crate) finished_at: Option<DateTime<Utc>>,
+    /// Projects without a mirror when the run started
+    pub(crate) total: usize,
+    /// Of `total`, projects attempted so far
+    pub(crate) done: usize,
+    pub(crate) created: usize,
+    /// Projects that already had a mirror, or got one from a fetch meanwhile
+    pub(crate) skipped: usize,
+    pub(crate) failed: usize,
+    /// The run was stopped before every project was attempted
+    pub(crate) aborted: bool,
+    /// The first [`MAX_REPORTED_FAILURES`] failures
+    pub(crate) failures: Vec<WarmupFailure>,
+    /// Why the projects could not be listed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) error: Option<String>,
+}
+
+impl WarmupProgress {
+    fn new(started_at: DateTime<Utc>) -> Self {
+        Self {
+            started_at,
+            finished_at: None,
+            total: 0,
+            done: 0,
+            created: 0,
+            skipped: 0,
+            failed: 0,
+            aborted: false,
+            failures: Vec::new(),
+            error: None,
+        }
+    }
+
+    fn fail(&mut self, project_id: ProjectId, error: String) {
+        self.failed += 1;
+        if self.failures.len() < MAX_REPORTED_FAILURES {
+            self.failures.push(WarmupFailure { project_id, error });
+        }
+    }
+}
+
+/// Whether a warm-up is running, whether it was asked to stop, and its
+/// progress.
+#[derive(Default)]
+pub(crate) struct WarmupState {
+    running: AtomicBool,
+    abort: AtomicBool,
+    progress: Mutex<Option<WarmupProgress>>,
+}
+
+/// Marks a warm-up as running until dropped.
+pub(crate) struct RunningWarmup(Arc<WarmupState>);
+
+impl Drop for RunningWarmup {
+    fn drop(&mut self) {
+        self.0.running.store(false, Ordering::SeqCst);
+    }
+}
+
+impl WarmupState {
+    /// Claim the warm-up slot; `None` while another warm-up is running.
+    pub(crate) fn try_start(self: &Arc<Self>) -> Option<RunningWarmup> {
+        if self.running.swap(true, Ordering::SeqCst) {
+            return None;
+        }
+        self.abort.store(false, Ordering::SeqCst);
+        Some(RunningWarmup(self.clone()))
+    }
+
+    /// Ask the running warm-up to stop starting projects. Projects already
+    /// being created are finished. Returns false when none is running.
+    #[cfg(feature = "admin-ui")]
+    pub(crate) fn abort(&self) -> bool {
+        if !self.running.load(Ordering::SeqCst) {
+            return false;
+        }
+        self.abort.store(true, Ordering::SeqCst);
+        true
+    }
+
+    #[cfg(feature = "admin-ui")]
+    pub(crate) fn is_running(&self) -> bool {
+        self.running.load(Ordering::SeqCst)
+    }
+
+    #[cfg(feature = "admin-ui")]
+    pub(crate) fn is_aborting(&self) -> bool {
+        self.is_running() && self.abort.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn progress(&self) -> Option<WarmupProgress> {
+        self.progress
+            .lock()
+            .expect("warm-up state poisoned")
+            .clone()
+    }
+
+    fn update(&self, f: impl FnOnce(&mut WarmupProgress)) {
+        if let Some(progress) = self
+            .progress
+            .lock()
+            .expect("warm-up state poisoned")
+            .as_mut()
+        {
+            f(progress);
+        }
+    }
+}
+
+enum Attempt {
+    Created,
+    Skipped,
+    Aborted,
+    Failed(String),
+}
+
+/// Create the mirrors of all projects that have none, at most `jobs` at a
+/// time, through the same path as a sync. Projects that already have a
+/// mirror are skipped, so an aborted or failed run can simply be started
+/// again. Returns the final progress.
+pub(crate) async fn run_warmup(
+    state: Arc<AppState>,
+    jobs: usize,
+    running: RunningWarmup,
+) -> WarmupProgress {
+    let warmup = running.0.clone();
+    *warmup.progress.lock().expect("warm-up state poisoned") =
+        Some(WarmupProgress::new(Utc::now()));
+
+    let source = state.source.clone();
+    let cfg = state.cfg.clone();
+    let listed = tokio::task::spawn_blocking(move || {
+        let ids = source.list_projects()?;
+        let (existing, missing): (Vec<ProjectId>, Vec<ProjectId>) = ids
+            .into_iter()
+            .partition(|id| cfg.bare_repo_dir(id).is_ok_and(|repo| repo.is_dir()));
+        Ok::<_, BridgeError>((existing.len(), missing))
+    })
+    .await
+    .map_err(|e| BridgeError::Other(format!("join error: {e}")))
+    .and_then(|r| r);
+
+    let missing = match listed {
+        Ok((existing, missing)) => {
+            warmup.update(|p| {
+                p.total = missing.len();
+                p.skipped = existing;
+            });
+            info!(
+                total = missing.len(),
+                skipped = existing,
+                jobs,
+                "warm-up started"
+            );
+            missing
+        }
+        Err(e) => {
+            warn!("warm-up cannot list projects: {e}");
+            warmup.update(|p| p.error = Some(format!("cannot list projects: {e}")));
+            Vec::new()
+        }
+    };
+
+    let total = missing.len();
+    let log_every = (total / 20).max(1);
+    let semaphore = Arc::new(Semaphore::new(jobs.max(1)));
+    let mut tasks = JoinSet::new();
+    for project_id in missing {
+        let semaphore = semaphore.clone();
+        let state = state.clone();
+        let warmup = warmup.clone();
+        tasks.spawn(async move {
+            let _permit = semaphore
+                .acquire_owned()
+                .await
+                .expect("semaphore is never closed");
+            // paused, not failed, by maintenance mode
+            state.maintenance.wait_until_off().await;
+            let attempt = if warmup.abort.load(Ordering::SeqCst) {
+                Attempt::Aborted
+            } else if state
+                .cfg
+                .bare_repo_dir(&project_id)
+                .is_ok_and(|repo| repo.is_dir())
+            {
+                Attempt::Skipped
+            } else {
+                match crate::server::sync_project(&state, &project_id, SyncTrigger::Warmup).await {
+                    Ok(_) => Attempt::Created,
+                    Err(e) => Attempt::Failed(e.to_string()),
+                }
+            };
+            (project_id, attempt)
+        });
+    }
+
+    while let Some(result) = tasks.join_next().await {
+        let (project_id, attempt) = match result {
+            Ok(r) => r,
+            Err(e) => {
+                warn!("warm-up task failed: {e}");
+                continue;
+            }
+        };
+        if matches!(attempt, Attempt::Aborted) {
+            continue;
+        }
+        let mut logged = None;
+        warmup.update(|p| {
+            p.done += 1;
+            match attempt {
+                Attempt::Created => p.created += 1,
+                Attempt::Skipped => p.skipped += 1,
+                Attempt::Aborted => {}
+                Attempt::Failed(error) => {
+                    warn!(%project_id, "warm-up failed: {error}");
+                    p.fail(project_id, error);
+                }
+            }
+            if p.done % log_every == 0 || p.done == total {
+                logged = Some((p.done, p.failed));
+            }
+        });
+        if let Some((done, failed)) = logged {
+            info!(done, total, failed, "warm-up progress");
+        }
+    }
+
+    let aborted = warmup.abort.load(Ordering::SeqCst);
+    warmup.update(|p| {
+        p.aborted = aborted;
+        p.finished_at = Some(Utc::now());
+    });
+    let progress = warmup.progress().expect("progress set at start");
+    info!(
+        created = progress.created,
+        skipped = progress.skipped,
+        failed = progress.failed,
+        aborted,
+        "warm-up finished"
+    );
+    drop(running);
+    progress
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::project_id::pid;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn project(cfg: &Config, id: &str) {
+        let dir = cfg.project_source_dir(&pid(id)).unwrap();
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("main.tex"), id).unwrap();
+    }
+
+    #[tokio::test]
+    async fn missing_mirrors_are_created_and_existing_ones_skipped() {
+        let root = TempDir::new().unwrap();
+        let cfg = Config::for_test(root.path());
+        for id in ["a1", "b2", "c3"] {
+            project(&cfg, id);
+        }
+        let state = Arc::new(AppState::new(cfg.clone()));
+        crate::server::sync_project(&state, &pid("b2"), SyncTrigger::Cli)
+            .await
+            .unwrap();
+
+        let running = state.warmup.try_start().unwrap();
+        assert!(state.warmup.try_start().is_none());
+        let progress = run_warmup(state.clone(), 2, running).await;
+        assert_eq!((progress.total, progress.done, progress.created), (2, 2, 2));
+        assert_eq!((progress.skipped, progress.failed), (1, 0));
+        assert!(!progress.aborted && progress.finished_at.is_some());
+        for id in ["a1", "c3"] {
+            assert!(cfg.bare_repo_dir(&pid(id)).unwrap().is_dir());
+        }
+
+        // a second run has nothing left to do
+        let running = state.warmup.try_start().unwrap();
+        let progress = run_warmup(state.clone(), 2, running).await;
+        assert_eq!((progress.total, progress.skipped), (0, 3));
+    }
+
+    #[tokio::test]
+    async fn an_aborted_run_starts_no_more_projects() {
+        let root = TempDir::new().unwrap();
+        let cfg = Config::for_test(root.path());
+        for id in ["a1", "b2"] {
+            project(&cfg, id);
+        }
+        let state = Arc::new(AppState::new(cfg.clone()));
+
+        let running = state.warmup.try_start().unwrap();
+        state.warmup.abort.store(true, Ordering::SeqCst);
+        let progress = run_warmup(state.clone(), 1, running).await;
+        assert_eq!((progress.total, progress.done), (2, 0));
+        assert!(progress.aborted);
+        assert!(!cfg.bare_repo_dir(&pid("a1")).unwrap().exists());
+
+        // starting again clears the abort request and resumes
+        let running = state.warmup.try_start().unwrap();
+        let progress = run_warmup(state.clone(), 1, running).await;
+        assert_eq!(progress.created, 2);
+        assert!(!progress.aborted);
+    }
+}
@@ -0,0 +1,229 @@
+use crate::AppState;
+use crate::auth::constant_time_eq;
+use crate::error::BridgeError;
+use crate::metadata::SyncTrigger;
+use crate::project_id::ProjectId;
+use crate::project_settings::load_project_settings;
+use crate::repo::PROJECT_CONFIG_FILE;
+use crate::server::{read_project_file, sync_project, with_retry_after};
+use axum::{
+    body::Body,
+    extract::State,
+    http::{HeaderMap, Response, StatusCode, header},
+};
+use hex::encode as hex_encode;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use tracing::{error, info, warn};
+
+/// Header carrying the secret on `/hooks/sync/<id>` requests.
+pub(crate) const SECRET_HEADER: &str = "x-gitbridge-secret";
+
+/// Where the secret checked for a project comes from, in order of precedence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum SecretSource {
+    /// Set through the admin API (`project-settings.json`)
+    Admin,
+    /// `webhook_secret` in the project's `.gitbridge.toml`
+    ProjectFile,
+    /// `WEBHOOK_SECRET`
+    Global,
+}
+
+pub(crate) struct WebhookSecret {
+    pub(crate) value: String,
+    pub(crate) source: SecretSource,
+}
+
+#[derive(Deserialize)]
+struct ProjectConfigFile {
+    webhook_secret: Option<String>,
+}
+
+/// Short, non-reversible identifier of a secret for display.
+pub(crate) fn fingerprint(secret: &str) -> String {
+    let digest = Sha256::digest(secret.as_bytes());
+    format!("sha256:{}", &hex_encode(digest)[..12])
+}
+
+/// The secret `/hooks/sync/<project_id>` accepts. Sources are read on every
+/// call, so rotating a secret takes effect without a restart.
+pub(crate) async fn resolve_webhook_secret(
+    state: &AppState,
+    project_id: &ProjectId,
+) -> Result<Option<WebhookSecret>, BridgeError> {
+    let cfg = state.cfg.clone();
+    let settings = tokio::task::spawn_blocking(move || load_project_settings(&cfg))
+        .await
+        .map_err(|e| BridgeError::Other(format!("join error: {e}")))??;
+    if let Some(value) = settings.get(project_id).webhook_secret {
+        return Ok(Some(WebhookSecret {
+            value,
+            source: SecretSource::Admin,
+        }));
+    }
+
+    if let Some(content) = read_project_file(state, project_id, PROJECT_CONFIG_FILE).await {
+        match toml::from_str::<ProjectConfigFile>(&content) {
+            Ok(ProjectConfigFile {
+                webhook_secret: Some(value),
+            }) if !value.trim().is_empty() => {
+                return Ok(Some(WebhookSecret {
+                    value: value.trim().to_string(),
+                    source: SecretSource::ProjectFile,
+                }));
+            }
+            Ok(_) => {}
+            Err(e) => warn!(%project_id, error = %e, "ignoring unparsable {PROJECT_CONFIG_FILE}"),
+        }
+    }
+
+    Ok(state.cfg.webhook_secret.clone().map(|value| WebhookSecret {
+        value,
+        source: SecretSource::Global,
+    }))
+}
+
+fn json_response(status: StatusCode, value: serde_json::Value) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(value.to_string()))
+        .expect("json response")
+}
+
+/// `POST /hooks/sync/<id>`: sync a project now, authenticated by the
+/// project's webhook secret (or the global one when it has none).
+pub(crate) async fn sync_hook(
+    State(state): State<Arc<AppState>>,
+    project_id: ProjectId,
+    headers: HeaderMap,
+) -> Response<Body> {
+    let expected = match resolve_webhook_secret(&state, &project_id).await {
+        Ok(Some(secret)) => secret,
+        Ok(None) => {
+            return json_response(
+                StatusCode::NOT_FOUND,
+                json!({"error": "webhook not configured"}),
+            );
+        }
+        Err(e) => {
+            error!(%project_id, "cannot resolve webhook secret: {e}");
+            return json_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"error": "internal error"}),
+            );
+        }
+    };
+
+    let provided = headers
+        .get(SECRET_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("");
+    if !constant_time_eq(provided, &expected.value) {
+        warn!(%project_id, "webhook rejected: wrong secret");
+        return json_response(StatusCode::UNAUTHORIZED, json!({"error": "unauthorized"}));
+    }
+
+    match sync_project(&state, &project_id, SyncTrigger::Webhook).await {
+        Ok(_) => {
+            info!(
+                %project_id,
+                source = ?expected.source,
+                secret = %fingerprint(&expected.value),
+                "sync triggered by webhook"
+            );
+            json_response(
+                StatusCode::OK,
+                json!({
+                    "synced": true,
+                    "clone_url": state.cfg.public_clone_url(&project_id),
+                }),
+            )
+        }
+        Err(BridgeError::ProjectNotFound(_)) => {
+            json_response(StatusCode::NOT_FOUND, json!({"error": "project not found"}))
+        }
+        Err(BridgeError::ProjectBusy(_)) => with_retry_after(
+            json_response(
+                StatusCode::SERVICE_UNAVAILABLE,
+                json!({"error": "project is busy"}),
+            ),
+            &state.cfg,
+        ),
+        Err(e @ BridgeError::RepoTooLarge(_)) => {
+            warn!(%project_id, "webhook sync refused: {e}");
+            json_response(StatusCode::CONFLICT, json!({"error": e.to_string()}))
+        }
+        Err(e) => {
+            error!(%project_id, "webhook sync failed: {e}");
+            json_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"error": "sync failed"}),
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::project_id::pid;
+    use crate::project_settings::save_project_settings;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn project_secrets_take_precedence_over_the_global_one() {
+        let dir = TempDir::new().unwrap();
+        let mut cfg = Config::for_test(dir.path());
+        cfg.webhook_secret = Some("global".into());
+        let project = cfg.project_source_dir(&pid("p1")).unwrap();
+        fs::create_dir_all(&project).unwrap();
+        fs::create_dir_all(&cfg.git_root).unwrap();
+        let state = AppState::new(cfg.clone());
+
+        let secret = resolve_webhook_secret(&state, &pid("p1"))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(secret.source, SecretSource::Global);
+
+        fs::write(
+            project.join(".gitbridge.toml"),
+            "webhook_secret = \"from-file\"\n",
+        )
+        .unwrap();
+        let secret = resolve_webhook_secret(&state, &pid("p1"))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            (secret.value.as_str(), secret.source),
+            ("from-file", SecretSource::ProjectFile)
+        );
+
+        let mut settings = load_project_settings(&cfg).unwrap();
+        settings.update("p1", |s| s.webhook_secret = Some("from-admin".into()));
+        save_project_settings(&cfg, &settings).unwrap();
+        let secret = resolve_webhook_secret(&state, &pid("p1"))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            (secret.value.as_str(), secret.source),
+            ("from-admin", SecretSource::Admin)
+        );
+    }
+
+    #[test]
+    fn fingerprints_do_not_contain_the_secret() {
+        let fp = fingerprint("hunter2");
+        assert!(fp.starts_with("sha256:") && fp.len() == 19);
+        assert!(!fp.contains("hunter2"));
+    }
+}
@@ -0,0 +1,430 @@
+//! Outgoing webhook posts (alerts, sync events, deploy token rotations) go
+//! through a queue persisted in `GIT_ROOT/state/webhook-queue.json`: a post
+//! is written there before it is sent and removed once the target accepted
+//! it, so posts to a target that is down are retried with exponential
+//! backoff, across restarts, for `WEBHOOK_QUEUE_MAX_AGE_HOURS`.
+//!
+//! Delivery is at least once. Every payload carries an `idempotency_key`,
+//! also sent as the `Idempotency-Key` header, that stays the same across
+//! retries so receivers can drop duplicates.
+
+use crate::AppState;
+use crate::config::Config;
+use crate::error::BridgeError;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+/// Timeout for one post.
+const POST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Time between looks for posts due for a retry.
+const RETRY_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Wait after the first failed attempt; doubled after every further one.
+const FIRST_BACKOFF_SECONDS: i64 = 30;
+
+/// Longest wait between two attempts.
+const MAX_BACKOFF_SECONDS: i64 = 3600;
+
+/// What a queued post announces.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum WebhookKind {
+    /// `ALERT_WEBHOOK_URL`
+    Alert,
+    /// `SYNC_EVENT_WEBHOOK_URL`
+    SyncEvent,
+    /// `DEPLOY_TOKEN_WEBHOOK_URL`
+    DeployToken,
+}
+
+/// A post that has not been accepted yet.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Delivery {
+    /// Also the payload's `idempotency_key`
+    pub(crate) id: String,
+    pub(crate) kind: WebhookKind,
+    pub(crate) url: String,
+    pub(crate) payload: Value,
+    pub(crate) created_at: DateTime<Utc>,
+    /// Failed attempts so far
+    pub(crate) attempts: u32,
+    pub(crate) next_attempt_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) last_error: Option<String>,
+}
+
+impl Delivery {
+    /// What the admin API shows of a post: its target host and event, but
+    /// not the URL or payload, which may hold secrets or tokens.
+    #[cfg(feature = "admin-ui")]
+    pub(crate) fn summary(&self) -> Value {
+        let host = url::Url::parse(&self.url)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_string));
+        serde_json::json!({
+            "id": self.id,
+            "kind": self.kind,
+            "target": host,
+            "event": self.payload.get("event"),
+            "project_id": self.payload.get("project_id"),
+            "created_at": self.created_at,
+            "attempts": self.attempts,
+            "next_attempt_at": self.next_attempt_at,
+            "last_error": self.last_error,
+        })
+    }
+}
+
+/// Wait before the next attempt after `attempts` failed ones.
+fn backoff(attempts: u32) -> chrono::Duration {
+    let doublings = attempts.saturating_sub(1).min(16);
+    let seconds = (FIRST_BACKOFF_SECONDS << doublings).min(MAX_BACKOFF_SECONDS);
+    chrono::Duration::seconds(seconds)
+}
+
+/// Sends one post. Blocking.
+pub(crate) trait WebhookPoster: Send + Sync {
+    fn post(&self, url: &str, payload: &Value, idempotency_key: &str) -> Result<(), BridgeError>;
+}
+
+struct HttpPoster {
+    agent: ureq::Agent,
+}
+
+impl WebhookPoster for HttpPoster {
+    fn post(&self, url: &str, payload: &Value, idempotency_key: &str) -> Result<(), BridgeError> {
+        self.agent
+            .post(url)
+            .header("content-type", "application/json")
+            .header("idempotency-key", idempotency_key)
+            .send(payload.to_string())
+            .map_err(|e| BridgeError::Other(format!("webhook: {e}")))?;
+        Ok(())
+    }
+}
+
+/// The persisted queue of undelivered posts.
+pub(crate) struct WebhookQueue {
+    path: PathBuf,
+    max_age: chrono::Duration,
+    max_entries: usize,
+    poster: Box<dyn WebhookPoster>,
+    /// Contents of the file once read; held while updating it
+    deliveries: Mutex<Option<Vec<Delivery>>>,
+    /// Posts being sent right now, so a retry does not send them twice
+    sending: Mutex<HashSet<String>>,
+}
+
+impl WebhookQueue {
+    pub(crate) fn new(cfg: &Config) -> Self {
+        let agent = ureq::Agent::config_builder()
+            .timeout_global(Some(POST_TIMEOUT))
+            .build()
+            .into();
+        Self::with_poster(cfg, Box::new(HttpPoster { agent }))
+    }
+
+    pub(crate) fn with_poster(cfg: &Config, poster: Box<dyn WebhookPoster>) -> Self {
+        let hours = i64::try_from(cfg.webhook_queue_max_age_hours).unwrap_or(i64::MAX / 3600);
+        Self {
+            path: cfg.webhook_queue_file(),
+            max_age: chrono::Duration::hours(hours),
+            max_entries: cfg.webhook_queue_max_entries,
+            poster,
+            deliveries: Mutex::new(None),
+            sending: Mutex::new(HashSet::new()),
+        }
+    }
+
+    fn read(&self) -> Result<Vec<Delivery>, BridgeError> {
+        match fs::read_to_string(&self.path) {
+            Ok(data) => Ok(serde_json::from_str(&data)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(BridgeError::Io(e)),
+        }
+    }
+
+    fn write(&self, deliveries: &[Delivery]) -> Result<(), BridgeError> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let tmp_path = self.path.with_extension("tmp");
+        fs::write(&tmp_path, serde_json::to_string_pretty(deliveries)?)?;
+        fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+
+    /// Run `f` on the queue; it is written back if `f` returns `true`.
+    fn with_deliveries<R>(
+        &self,
+        f: impl FnOnce(&mut Vec<Delivery>) -> (bool, R),
+    ) -> Result<R, BridgeError> {
+        let mut cached = self.deliveries.lock().expect("webhook queue poisoned");
+        if cached.is_none() {
+            *cached = Some(self.read()?);
+        }
+        let deliveries = cached.as_mut().expect("loaded above");
+        let (changed, result) = f(deliveries);
+        if changed {
+            self.write(deliveries)?;
+        }
+        Ok(result)
+    }
+
+    /// Queue a post of `payload` to `url` and try to send it right away.
+    /// An error means it was not accepted; it stays queued for a retry
+    /// unless it could not even be queued. Blocking.
+    pub(crate) fn send(
+        &self,
+        kind: WebhookKind,
+        url: &str,
+        mut payload: Value,
+    ) -> Result<(), BridgeError> {
+        let id = Uuid::new_v4().to_string();
+        payload["idempotency_key"] = Value::String(id.clone());
+        let now = Utc::now();
+        let delivery = Delivery {
+            id: id.clone(),
+            kind,
+            url: url.to_string(),
+            payload: payload.clone(),
+            created_at: now,
+            attempts: 0,
+            next_attempt_at: now,
+            last_error: None,
+        };
+        let queued = self.with_deliveries(|deliveries| {
+            deliveries.push(delivery);
+            let dropped = deliveries.len().saturating_sub(self.max_entries);
+            for old in deliveries.drain(..dropped) {
+                warn!(id = %old.id, kind = ?old.kind, "webhook queue full, dropped the oldest post");
+            }
+            (true, ())
+        });
+        if let Err(e) = queued {
+            // still worth a try, just without a retry
+            error!("cannot queue webhook post: {e}");
+            return self.poster.post(url, &payload, &id);
+        }
+        self.attempt(&id, now)
+    }
+
+    /// Post the queued delivery `id` unless it is being sent already, and
+    /// remove it once accepted or reschedule it. Blocking.
+    fn attempt(&self, id: &str, now: DateTime<Utc>) -> Result<(), BridgeError> {
+        if !self
+            .sending
+            .lock()
+            .expect("webhook queue poisoned")
+            .insert(id.to_string())
+        {
+            return Ok(());
+        }
+        let result = self.attempt_claimed(id, now);
+        self.sending
+            .lock()
+            .expect("webhook queue poisoned")
+            .remove(id);
+        result
+    }
+
+    fn attempt_claimed(&self, id: &str, now: DateTime<Utc>) -> Result<(), BridgeError> {
+        let delivery = self.with_deliveries(|deliveries| {
+            (false, deliveries.iter().find(|d| d.id == id).cloned())
+        })?;
+        let Some(delivery) = delivery else {
+            return Ok(());
+        };
+        let posted = self.poster.post(&delivery.url, &delivery.payload, id);
+        self.with_deliveries(|deliveries| {
+            let Some(index) = deliveries.iter().position(|d| d.id == id) else {
+                return (false, ());
+            };
+            match &posted {
+                Ok(()) => {
+                    deliveries.remove(index);
+                }
+                Err(e) => {
+                    let queued = &mut deliveries[index];
+                    queued.attempts += 1;
+                    queued.next_attempt_at = now + backoff(queued.attempts);
+                    queued.last_error = Some(e.to_string());
+                }
+            }
+            (true, ())
+        })?;
+        if delivery.attempts > 0 && posted.is_ok() {
+            info!(%id, kind = ?delivery.kind, attempts = delivery.attempts + 1, "webhook post delivered on retry");
+        }
+        posted
+    }
+
+    /// Drop posts older than `WEBHOOK_QUEUE_MAX_AGE_HOURS`.
+    fn expire(&self, now: DateTime<Utc>) -> Result<(), BridgeError> {
+        self.with_deliveries(|deliveries| {
+            let before = deliveries.len();
+            deliveries.retain(|d| {
+                let keep = now - d.created_at <= self.max_age;
+                if !keep {
+                    warn!(id = %d.id, kind = ?d.kind, attempts = d.attempts, "webhook post given up after too many failures");
+                }
+                keep
+            });
+            (deliveries.len() != before, ())
+        })
+    }
+
+    /// Try every post that is due, or every post with `all`. Returns the
+    /// number delivered. Blocking.
+    pub(crate) fn deliver(&self, all: bool, now: DateTime<Utc>) -> Result<usize, BridgeError> {
+        self.expire(now)?;
+        let due: Vec<String> = self.with_deliveries(|deliveries| {
+            let due = deliveries
+                .iter()
+                .filter(|d| all || d.next_attempt_at <= now)
+                .map(|d| d.id.clone())
+                .collect();
+            (false, due)
+        })?;
+        Ok(due
+            .iter()
+            .filter(|id| self.attempt(id, now).is_ok())
+            .count())
+    }
+
+    /// The posts waiting for a retry, oldest first.
+    #[cfg(feature = "admin-ui")]
+    pub(crate) fn pending(&self) -> Result<Vec<Delivery>, BridgeError> {
+        self.with_deliveries(|deliveries| (false, deliveries.clone()))
+    }
+
+    /// Remove the post `id`, or all posts without one. Returns the removed
+    /// posts.
+    #[cfg(feature = "admin-ui")]
+    pub(crate) fn discard(&self, id: Option<&str>) -> Result<Vec<Delivery>, BridgeError> {
+        self.with_deliveries(|deliveries| {
+            let (removed, kept) = std::mem::take(deliveries)
+                .into_iter()
+                .partition::<Vec<_>, _>(|d| id.is_none_or(|id| d.id == id));
+            *deliveries = kept;
+            (!removed.is_empty(), removed)
+        })
+    }
+}
+
+/// Background task retrying queued posts every [`RETRY_INTERVAL`].
+pub(crate) async fn run_periodically(state: Arc<AppState>) {
+    loop {
+        tokio::time::sleep(RETRY_INTERVAL).await;
+        if state.maintenance.is_enabled() {
+            continue;
+        }
+        let _in_flight = state.maintenance.begin();
+        let webhooks = state.webhooks.clone();
+        let result = tokio::task::spawn_blocking(move || webhooks.deliver(false, Utc::now())).await;
+        match result {
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => error!("webhook retries failed: {e}"),
+            Err(e) => error!("webhook retries failed: join error: {e}"),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "admin-ui"))]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use tempfile::TempDir;
+
+    /// Records what it is sent; fails while `down` is set.
+    #[derive(Default)]
+    struct Target {
+        down: AtomicBool,
+        received: Mutex<Vec<(Value, String)>>,
+    }
+
+    impl WebhookPoster for Arc<Target> {
+        fn post(&self, _url: &str, payload: &Value, key: &str) -> Result<(), BridgeError> {
+            if self.down.load(Ordering::SeqCst) {
+                return Err(BridgeError::Other("connection refused".into()));
+            }
+            self.received
+                .lock()
+                .unwrap()
+                .push((payload.clone(), key.to_string()));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn failed_posts_are_retried_with_backoff_until_they_expire() {
+        let root = TempDir::new().unwrap();
+        let mut cfg = Config::for_test(root.path());
+        cfg.webhook_queue_max_entries = 3;
+        let target = Arc::new(Target::default());
+        let queue = WebhookQueue::with_poster(&cfg, Box::new(target.clone()));
+
+        queue
+            .send(WebhookKind::Alert, "http://hooks", json!({"n": 0}))
+            .unwrap();
+        assert!(queue.pending().unwrap().is_empty());
+
+        target.down.store(true, Ordering::SeqCst);
+        for n in 1..=4 {
+            let sent = queue.send(WebhookKind::SyncEvent, "http://hooks", json!({"n": n}));
+            assert!(sent.is_err());
+        }
+        // the oldest is dropped beyond the limit; the queue survives a restart
+        let queue = WebhookQueue::with_poster(&cfg, Box::new(target.clone()));
+        let pending = queue.pending().unwrap();
+        assert_eq!(pending.len(), 3);
+        assert_eq!(pending[0].payload["n"], 2);
+        assert_eq!(pending[0].attempts, 1);
+        assert_eq!(
+            pending[0].last_error.as_deref(),
+            Some("internal: connection refused")
+        );
+
+        // nothing is due before the backoff, failures double it
+        assert_eq!(queue.deliver(false, pending[0].created_at).unwrap(), 0);
+        let later = pending[0].next_attempt_at;
+        assert_eq!(queue.deliver(false, later).unwrap(), 0);
+        let pending = queue.pending().unwrap();
+        assert_eq!(pending[0].attempts, 2);
+        assert_eq!(
+            pending[0].next_attempt_at,
+            later + chrono::Duration::seconds(2 * FIRST_BACKOFF_SECONDS)
+        );
+
+        target.down.store(false, Ordering::SeqCst);
+        let removed = queue.discard(Some(&pending[2].id)).unwrap();
+        assert_eq!(removed[0].payload["n"], 4);
+        assert_eq!(queue.deliver(true, later).unwrap(), 2);
+        assert!(queue.pending().unwrap().is_empty());
+        let received = target.received.lock().unwrap();
+        let ns: Vec<_> = received.iter().map(|(p, _)| p["n"].clone()).collect();
+        assert_eq!(ns, [json!(0), json!(2), json!(3)]);
+        // the key is in the payload and the same one every attempt
+        for (payload, key) in received.iter() {
+            assert_eq!(payload["idempotency_key"], key.as_str());
+        }
+        assert_eq!(received[1].1, pending[0].id);
+        drop(received);
+
+        target.down.store(true, Ordering::SeqCst);
+        let _ = queue.send(WebhookKind::DeployToken, "http://hooks", json!({}));
+        let expired = Utc::now() + chrono::Duration::hours(25);
+        assert_eq!(queue.deliver(false, expired).unwrap(), 0);
+        assert!(queue.pending().unwrap().is_empty());
+    }
+}
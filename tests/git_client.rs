@@ -0,0 +1,344 @@
+//! End-to-end tests driving a real `git` client against a bridge on an
+//! ephemeral port. They are skipped when no `git` binary is installed.
+
+use axum::body::Body;
+use axum::http::{Request, StatusCode, header};
+use sharelatex_gitbridge_ro::project_id::ProjectId;
+use sharelatex_gitbridge_ro::{AppState, Config, MemoryTokenStore, build_router, init_storage};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Output;
+use std::sync::Arc;
+use tempfile::TempDir;
+use tower::ServiceExt;
+
+const PROJECT: &str = "5f1a2b3c4d5e6f7a8b9c0d1e";
+const MANAGED_TOKEN: &str = "ci-token";
+const PROJECT_TOKEN: &str = "projtok";
+
+/// A running bridge with one project, `PROJECT`, in its source tree.
+struct Bridge {
+    root: TempDir,
+    state: Arc<AppState>,
+    /// `http://host:port/git`
+    base: String,
+}
+
+impl Bridge {
+    async fn start() -> Option<Self> {
+        if std::process::Command::new("git")
+            .arg("--version")
+            .output()
+            .is_err()
+        {
+            eprintln!("git is not installed, skipping");
+            return None;
+        }
+
+        let root = TempDir::new().unwrap();
+        let mut cfg = Config::from_env();
+        cfg.sharelatex_data_path = root.path().join("sharelatex");
+        cfg.user_files_dir = root.path().join("user_files");
+        cfg.git_root = root.path().join("git");
+        init_storage(&cfg).unwrap();
+
+        let tokens = MemoryTokenStore::with_tokens(HashMap::from([(
+            MANAGED_TOKEN.to_string(),
+            "CI".to_string(),
+        )]));
+        let state = Arc::new(AppState::with_token_store(cfg, Arc::new(tokens)));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}/git", listener.local_addr().unwrap());
+        let app = build_router(state.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let bridge = Self { root, state, base };
+        bridge.write("main.tex", "\\documentclass{article}\n");
+        bridge.write("refs.bib", "@book{knuth}\n");
+        bridge.write(".gitbridge", PROJECT_TOKEN);
+        Some(bridge)
+    }
+
+    fn source(&self) -> PathBuf {
+        self.state
+            .cfg
+            .project_source_dir(&PROJECT.parse::<ProjectId>().unwrap())
+            .unwrap()
+    }
+
+    fn write(&self, path: &str, content: &str) {
+        let path = self.source().join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    /// Clone URL of `project` with `user:password` credentials.
+    fn url(&self, credentials: &str, project: &str) -> String {
+        let (scheme, host) = self.base.split_once("://").unwrap();
+        format!("{scheme}://{credentials}@{host}/{project}.git")
+    }
+
+    /// Clone `PROJECT` into `name` below the temp dir.
+    async fn clone(&self, credentials: &str, name: &str) -> PathBuf {
+        let url = self.url(credentials, PROJECT);
+        let out = git(self.root.path(), &["clone", "-q", &url, name]).await;
+        assert_success(&out, "clone");
+        self.root.path().join(name)
+    }
+
+    /// Answer a single `GET` on the router, without a git client.
+    async fn get(&self, uri: &str, authorization: Option<&str>) -> StatusCode {
+        let mut req = Request::get(uri);
+        if let Some(value) = authorization {
+            req = req.header(header::AUTHORIZATION, value);
+        }
+        build_router(self.state.clone())
+            .oneshot(req.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+            .status()
+    }
+}
+
+async fn git(dir: &Path, args: &[&str]) -> Output {
+    tokio::process::Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .env("GIT_CONFIG_NOSYSTEM", "1")
+        .env("GIT_AUTHOR_NAME", "Test")
+        .env("GIT_AUTHOR_EMAIL", "test@example.org")
+        .env("GIT_COMMITTER_NAME", "Test")
+        .env("GIT_COMMITTER_EMAIL", "test@example.org")
+        .output()
+        .await
+        .unwrap()
+}
+
+fn assert_success(out: &Output, what: &str) {
+    assert!(
+        out.status.success(),
+        "git {what} failed: {}",
+        String::from_utf8_lossy(&out.stderr)
+    );
+}
+
+async fn stdout(dir: &Path, args: &[&str]) -> String {
+    let out = git(dir, args).await;
+    assert_success(&out, &args.join(" "));
+    String::from_utf8(out.stdout).unwrap()
+}
+
+#[tokio::test]
+async fn clone_with_a_managed_token_and_fetch_one_new_commit() {
+    let Some(bridge) = Bridge::start().await else {
+        return;
+    };
+    let clone = bridge.clone(&format!("git:{MANAGED_TOKEN}"), "clone").await;
+    assert_eq!(
+        fs::read_to_string(clone.join("main.tex")).unwrap(),
+        "\\documentclass{article}\n"
+    );
+    let before = stdout(&clone, &["rev-parse", "HEAD"]).await;
+
+    bridge.write("main.tex", "\\documentclass{article}\n\\begin{document}\n");
+    assert_success(&git(&clone, &["fetch", "-q", "origin"]).await, "fetch");
+    let new = stdout(
+        &clone,
+        &["rev-list", &format!("{}..origin/master", before.trim())],
+    )
+    .await;
+    assert_eq!(new.lines().count(), 1, "{new}");
+    assert_eq!(
+        stdout(&clone, &["show", "origin/master:main.tex"]).await,
+        "\\documentclass{article}\n\\begin{document}\n"
+    );
+
+    // nothing changed, nothing new
+    assert_success(&git(&clone, &["fetch", "-q", "origin"]).await, "fetch");
+    let again = stdout(
+        &clone,
+        &["rev-list", &format!("{}..origin/master", before.trim())],
+    )
+    .await;
+    assert_eq!(again, new);
+}
+
+#[tokio::test]
+async fn deleted_files_disappear_from_the_mirror() {
+    let Some(bridge) = Bridge::start().await else {
+        return;
+    };
+    let clone = bridge.clone(&format!("git:{MANAGED_TOKEN}"), "clone").await;
+    assert!(clone.join("refs.bib").exists());
+
+    fs::remove_file(bridge.source().join("refs.bib")).unwrap();
+    assert_success(&git(&clone, &["pull", "-q", "--ff-only"]).await, "pull");
+    assert!(!clone.join("refs.bib").exists());
+    let files = stdout(&clone, &["ls-files"]).await;
+    assert!(files.lines().any(|f| f == "main.tex"), "{files}");
+    assert!(!files.lines().any(|f| f == "refs.bib"), "{files}");
+}
+
+#[tokio::test]
+async fn pushes_are_rejected() {
+    let Some(bridge) = Bridge::start().await else {
+        return;
+    };
+    let clone = bridge.clone(&format!("git:{MANAGED_TOKEN}"), "clone").await;
+    fs::write(clone.join("main.tex"), "changed locally\n").unwrap();
+    assert_success(
+        &git(&clone, &["commit", "-q", "-am", "local change"]).await,
+        "commit",
+    );
+
+    let push = git(&clone, &["push", "-q", "origin", "HEAD:master"]).await;
+    assert!(!push.status.success());
+    let stderr = String::from_utf8_lossy(&push.stderr);
+    assert!(
+        stderr.contains("remote error: push is disabled on this read-only mirror"),
+        "{stderr}"
+    );
+    let source = fs::read_to_string(bridge.source().join("main.tex")).unwrap();
+    assert_eq!(source, "\\documentclass{article}\n");
+    assert_eq!(
+        bridge
+            .get(
+                &format!("/git/{PROJECT}.git/info/refs?service=git-receive-pack"),
+                Some(&format!("Bearer {MANAGED_TOKEN}")),
+            )
+            .await,
+        StatusCode::FORBIDDEN
+    );
+}
+
+#[tokio::test]
+async fn wrong_tokens_get_401_and_missing_projects_404() {
+    let Some(bridge) = Bridge::start().await else {
+        return;
+    };
+    let info_refs = format!("/git/{PROJECT}.git/info/refs?service=git-upload-pack");
+    assert_eq!(
+        bridge.get(&info_refs, Some("Bearer wrong")).await,
+        StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(bridge.get(&info_refs, None).await, StatusCode::UNAUTHORIZED);
+    let url = bridge.url("git:wrong", PROJECT);
+    let out = git(bridge.root.path(), &["ls-remote", &url]).await;
+    assert!(!out.status.success());
+
+    let missing = "0123456789abcdef01234567";
+    assert_eq!(
+        bridge
+            .get(
+                &format!("/git/{missing}.git/info/refs?service=git-upload-pack"),
+                Some(&format!("Bearer {MANAGED_TOKEN}")),
+            )
+            .await,
+        StatusCode::NOT_FOUND
+    );
+    let url = bridge.url(&format!("git:{MANAGED_TOKEN}"), missing);
+    let out = git(bridge.root.path(), &["ls-remote", &url]).await;
+    assert!(!out.status.success());
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(
+        stderr.contains(&format!("remote error: project {missing} not found")),
+        "{stderr}"
+    );
+}
+
+#[tokio::test]
+async fn project_tokens_from_gitbridge_files_clone_only_their_project() {
+    let Some(bridge) = Bridge::start().await else {
+        return;
+    };
+    let clone = bridge.clone(&format!("git:{PROJECT_TOKEN}"), "clone").await;
+    assert!(clone.join("main.tex").exists());
+
+    let other = "0123456789abcdef01234567";
+    let source = bridge
+        .state
+        .cfg
+        .project_source_dir(&other.parse::<ProjectId>().unwrap())
+        .unwrap();
+    fs::create_dir_all(&source).unwrap();
+    fs::write(source.join("main.tex"), "other\n").unwrap();
+    let url = bridge.url(&format!("git:{PROJECT_TOKEN}"), other);
+    let out = git(bridge.root.path(), &["clone", "-q", &url, "other"]).await;
+    assert!(!out.status.success());
+    assert_eq!(
+        bridge
+            .get(
+                &format!("/git/{other}.git/info/refs?service=git-upload-pack"),
+                Some(&format!("Bearer {PROJECT_TOKEN}")),
+            )
+            .await,
+        StatusCode::UNAUTHORIZED
+    );
+}
+
+#[tokio::test]
+async fn protocol_v2_is_negotiated_and_still_read_only() {
+    let Some(bridge) = Bridge::start().await else {
+        return;
+    };
+    let info_refs = |service: &str, protocol: Option<&str>| {
+        let mut req = Request::get(format!("/git/{PROJECT}.git/info/refs?service={service}"))
+            .header(header::AUTHORIZATION, format!("Bearer {MANAGED_TOKEN}"));
+        if let Some(protocol) = protocol {
+            req = req.header("git-protocol", protocol);
+        }
+        let app = build_router(bridge.state.clone());
+        async move {
+            let resp = app.oneshot(req.body(Body::empty()).unwrap()).await.unwrap();
+            let status = resp.status();
+            let body = axum::body::to_bytes(resp.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            (status, String::from_utf8_lossy(&body).into_owned())
+        }
+    };
+
+    let (status, v2) = info_refs("git-upload-pack", Some("version=2")).await;
+    assert_eq!(status, StatusCode::OK);
+    // capabilities instead of refs
+    assert!(v2.contains("version 2\n"), "{v2}");
+    assert!(v2.contains("ls-refs"), "{v2}");
+    assert!(v2.contains("fetch"), "{v2}");
+    assert!(!v2.contains("refs/heads/"), "{v2}");
+    let (_, v0) = info_refs("git-upload-pack", None).await;
+    assert!(!v0.contains("version 2"));
+    assert!(v0.contains("refs/heads/"));
+    let (status, refused) = info_refs("git-receive-pack", Some("version=2")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(refused, "0031ERR push is disabled on this read-only mirror");
+
+    // a v2 client clones and fetches, and still cannot push
+    let url = bridge.url(&format!("git:{MANAGED_TOKEN}"), PROJECT);
+    let out = git(
+        bridge.root.path(),
+        &["-c", "protocol.version=2", "clone", "-q", &url, "clone"],
+    )
+    .await;
+    assert_success(&out, "clone over protocol v2");
+    let clone = bridge.root.path().join("clone");
+    bridge.write("main.tex", "v2\n");
+    stdout(&clone, &["-c", "protocol.version=2", "pull", "-q"]).await;
+    assert_eq!(fs::read_to_string(clone.join("main.tex")).unwrap(), "v2\n");
+    fs::write(clone.join("refs.bib"), "@book{local}\n").unwrap();
+    stdout(&clone, &["commit", "-q", "-am", "local change"]).await;
+    let push = git(
+        &clone,
+        &[
+            "-c",
+            "protocol.version=2",
+            "push",
+            "-q",
+            "origin",
+            "HEAD:master",
+        ],
+    )
+    .await;
+    assert!(!push.status.success());
+}